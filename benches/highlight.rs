@@ -0,0 +1,44 @@
+// Benchmark of highlighting a large Rust file from scratch. Run with:
+//
+//   cargo bench --features bench --bench highlight
+//
+// The source of the editor itself is repeated to make a file of about 100k lines. Highlighting
+// the whole file happens on opening it and jumping to its end, or after an edit at its head.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use kiro_editor::{GapBuffer, Highlighting, Language, Row};
+
+const SOURCE: &str = include_str!("../src/editor.rs");
+
+fn highlight(c: &mut Criterion) {
+    let lines = SOURCE.lines().count();
+    let repeat = 100_000 / lines + 1;
+    let rows: GapBuffer<Row> = SOURCE
+        .lines()
+        .cycle()
+        .take(lines * repeat)
+        .map(|l| Row::new(l, 8))
+        .collect();
+    let mut hl = Highlighting::new(Language::Rust, &rows);
+
+    let mut group = c.benchmark_group("highlight");
+    group.sample_size(10);
+    group.bench_function(format!("rust {} lines", rows.len()), |b| {
+        b.iter(|| {
+            hl.lines_changed(0);
+            hl.update(&rows, rows.len());
+        })
+    });
+
+    // Only the rows in the view are highlighted after editing near the top of the file
+    group.bench_function("rust 24 lines", |b| {
+        b.iter(|| {
+            hl.lines_changed(0);
+            hl.update(&rows, 24);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, highlight);
+criterion_main!(benches);
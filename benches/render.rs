@@ -0,0 +1,66 @@
+// Benchmark of redrawing the whole screen. Run with:
+//
+//   cargo bench --features bench --bench render
+//
+// A large Rust file is opened in the headless editor and scrolled by pages. Each page down renders
+// all rows in the screen with their highlights. Time to open the file is measured separately and
+// subtracted.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use kiro_editor::{Clipboard, Editor, KeySeq, Script};
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+
+const SOURCE: &str = include_str!("../src/editor.rs");
+const PAGES: usize = 200;
+
+fn run(script: Script, path: &str) -> Duration {
+    let start = Instant::now();
+    let size = Some((200, 60));
+    let mut editor = Editor::open(
+        script.into_iter(),
+        io::sink(),
+        size,
+        Clipboard::default(),
+        &[path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    start.elapsed()
+}
+
+fn render(c: &mut Criterion) {
+    let path = env::temp_dir().join(format!("kiro-bench-render-{}.rs", process::id()));
+    fs::write(
+        &path,
+        SOURCE.repeat(PAGES * 60 / SOURCE.lines().count() + 1),
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    group.bench_function("page down", |b| {
+        b.iter_custom(|iters| {
+            // Go back to the top before reaching the end of file where page down does nothing
+            let mut script = Script::new();
+            for i in 0..iters as usize {
+                script = if i % PAGES == PAGES - 1 {
+                    script.alt('<')
+                } else {
+                    script.key(KeySeq::PageDownKey)
+                };
+            }
+            let total = run(script, path);
+            total.saturating_sub(run(Script::new(), path))
+        })
+    });
+    group.finish();
+    fs::remove_file(path).unwrap();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+// Benchmark of editing one character and rendering pathological long lines. Run with:
+//
+//   cargo bench --features bench --bench row
+//
+// Each edit is measured with patching indices of the row incrementally and with building a row
+// from the whole line, which is how a row was updated on each key input before. Rendering computes
+// display characters of the whole line as drawing the screen does when the line is visible.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use kiro_editor::Row;
+
+const LINE_LEN: usize = 100_000;
+const AT: usize = LINE_LEN / 2;
+
+type Edit = fn(&mut Row);
+
+fn row(c: &mut Criterion) {
+    let ascii = "a".repeat(LINE_LEN);
+    let utf8 = "良".repeat(LINE_LEN);
+    let tabs = "\tb".repeat(LINE_LEN / 2);
+    let cases: [(&str, Edit); 4] = [
+        ("insert_char", |row| row.insert_char(AT, 'a')),
+        ("insert_char + rebuild", |row| {
+            row.insert_char(AT, 'a');
+            *row = Row::new(row.buffer(), 8);
+        }),
+        ("delete_char", |row| row.delete_char(AT)),
+        ("delete_char + rebuild", |row| {
+            row.delete_char(AT);
+            *row = Row::new(row.buffer(), 8);
+        }),
+    ];
+
+    for (name, line) in [("ascii", &ascii), ("utf-8", &utf8), ("tabs", &tabs)] {
+        let mut group = c.benchmark_group(name);
+        for (case, edit) in &cases {
+            // Each iteration edits a fresh row. Building it is not measured
+            group.bench_function(*case, |b| {
+                b.iter_batched_ref(|| Row::new(line, 8), edit, BatchSize::LargeInput)
+            });
+        }
+
+        let row = Row::new(line, 8);
+        group.bench_function("render_chars", |b| {
+            b.iter(|| assert!(row.render_chars().count() >= LINE_LEN))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, row);
+criterion_main!(benches);
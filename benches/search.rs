@@ -0,0 +1,40 @@
+// Benchmark of incremental text search in a buffer of 100k lines. Run with:
+//
+//   cargo bench --features bench --bench search
+//
+// Each key input of incremental search matches the query against all lines of the buffer to
+// highlight every match, so the whole buffer is searched once per query.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use kiro_editor::Matcher;
+
+const SOURCE: &str = include_str!("../src/editor.rs");
+const LINES: usize = 100_000;
+
+fn search(c: &mut Criterion) {
+    let lines: Vec<_> = SOURCE.lines().cycle().take(LINES).collect();
+    let queries = [
+        ("text", "self.buf", false),
+        ("text", "not found anywhere", false),
+        ("regex", r"fn \w+\(", true),
+        ("regex", r"[0-9]+ms", true),
+    ];
+
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for (kind, query, regex) in &queries {
+        let matcher = Matcher::new(query, *regex).unwrap();
+        group.bench_function(format!("{} {:?}", kind, query), |b| {
+            b.iter(|| {
+                lines
+                    .iter()
+                    .map(|l| matcher.find_all(l).len())
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
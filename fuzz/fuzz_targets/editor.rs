@@ -0,0 +1,30 @@
+// Drive the editor with arbitrary key inputs through the headless frontend. Editing, prompts and
+// rendering must not panic with any keys. Commands which run external programs or write files
+// are unbound so that inputs cannot run arbitrary commands
+#![no_main]
+
+use kiro_editor::{Command, Editor, Keymap};
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+const UNSAFE_COMMANDS: &[Command] = &[
+    Command::Save,
+    Command::Shell,
+    Command::FilterSelection,
+    Command::Compile,
+    Command::FormatBuffer,
+    Command::Blame,
+    Command::ShowCommit,
+    Command::Suspend,
+    Command::Palette,
+];
+
+fuzz_target!(|data: &[u8]| {
+    let mut keymap = Keymap::default();
+    for cmd in UNSAFE_COMMANDS {
+        keymap.bind(*cmd, vec![]);
+    }
+    let mut editor = Editor::with_io(data, io::sink()).unwrap();
+    editor.set_keymap(keymap);
+    let _ = editor.edit();
+});
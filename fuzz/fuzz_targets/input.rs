@@ -0,0 +1,14 @@
+// Decode arbitrary bytes as key inputs sent from a terminal. Malformed escape sequences and broken
+// UTF-8 sequences must be decoded into some keys or `Unidentified` without panicking
+#![no_main]
+
+use kiro_editor::ReaderInputs;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for seq in ReaderInputs::new(data) {
+        // Reading bytes from memory never fails
+        let seq = seq.unwrap();
+        let _ = seq.to_string();
+    }
+});
@@ -0,0 +1,46 @@
+// Edit a row with arbitrary operations. The first line of the input is the initial text. Each
+// following line is one operation: its first character selects the operation, the second one is
+// the character index and the rest is the text to insert. Indices may be out of the row and text
+// may contain combining characters, wide characters and control characters
+#![no_main]
+
+use kiro_editor::Row;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.split('\n');
+    let mut row = Row::new(lines.next().unwrap_or(""), 8);
+    for line in lines {
+        let mut chars = line.chars();
+        let (op, at) = match (chars.next(), chars.next()) {
+            (Some(op), Some(at)) => (op as usize, at as usize % (row.len() + 2)),
+            _ => continue,
+        };
+        let text = chars.as_str();
+        match op % 8 {
+            0 => row.insert_char(at, text.chars().next().unwrap_or('a')),
+            1 => row.insert_str(at, text),
+            2 => row.delete_char(at),
+            3 => row.append(text),
+            4 => row.truncate(at),
+            5 => row.remove(at, at + text.len()),
+            6 => row.set_tab_width(at % 16 + 1),
+            _ => {
+                let _ = row.char_at_checked(at);
+            }
+        }
+
+        // Queries used by the cursor and the screen accept every position in the row
+        let len = row.len();
+        assert_eq!(row.buffer().chars().count(), len);
+        for cx in 0..=len {
+            let rx = row.rx_from_cx(cx);
+            let back = row.cx_from_rx(rx);
+            assert!(back <= len && row.rx_from_cx(back) <= rx);
+            assert!(row.next_boundary(cx) <= len);
+            assert!(row.prev_boundary(cx) <= cx);
+        }
+        assert_eq!(row.render_chars().filter(|&(cx, ..)| cx >= len).count(), 0);
+    }
+});
@@ -1,4 +1,6 @@
+use crate::theme::{Rgb, Theme};
 use std::env;
+#[cfg(feature = "terminal")]
 use term::terminfo::TermInfo;
 
 #[derive(Clone, Copy)]
@@ -19,22 +21,31 @@ impl ColorSupport {
                     None
                 }
             })
-            .or_else(|| {
-                TermInfo::from_env().ok().and_then(|info| {
-                    info.numbers.get("colors").map(|colors| {
-                        if *colors == 256 {
-                            ColorSupport::Extended256
-                        } else {
-                            ColorSupport::Only16
-                        }
-                    })
-                })
-            })
+            .or_else(Self::from_terminfo)
             .unwrap_or(ColorSupport::Only16)
     }
+
+    #[cfg(feature = "terminal")]
+    fn from_terminfo() -> Option<ColorSupport> {
+        TermInfo::from_env().ok().and_then(|info| {
+            info.numbers.get("colors").map(|colors| {
+                if *colors == 256 {
+                    ColorSupport::Extended256
+                } else {
+                    ColorSupport::Only16
+                }
+            })
+        })
+    }
+
+    // terminfo database is only available on terminals
+    #[cfg(not(feature = "terminal"))]
+    fn from_terminfo() -> Option<ColorSupport> {
+        None
+    }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum AnsiColor {
     Reset,
     Red,
@@ -45,82 +56,218 @@ pub enum AnsiColor {
     Purple,
     Cyan,
     CyanUnderline,
+    RedUnderline,
+    Underline,
     RedBG,
     Invert,
 }
 
+const ALL_COLORS: [AnsiColor; 13] = [
+    AnsiColor::Reset,
+    AnsiColor::Red,
+    AnsiColor::Green,
+    AnsiColor::Gray,
+    AnsiColor::Yellow,
+    AnsiColor::Blue,
+    AnsiColor::Purple,
+    AnsiColor::Cyan,
+    AnsiColor::CyanUnderline,
+    AnsiColor::RedUnderline,
+    AnsiColor::Underline,
+    AnsiColor::RedBG,
+    AnsiColor::Invert,
+];
+
+// Foreground or background color in the given color support
+// 'm' sets attributes to text printed after: https://vt100.net/docs/vt100-ug/chapter3.html#SGR
+// Color table: https://en.wikipedia.org/wiki/ANSI_escape_code#Colors
+//
+// 256 colors sequences are '\x1b[38;5;<n>m' (for fg) or '\x1b[48;5;<n>m (for bg)
+// https://www.xfree86.org/current/ctlseqs.html
+//
+// 24bit colors sequences are '\x1b[38;2;<r>;<g>;<b>m' (for fg) or '\x1b[48;2;<r>;<g>;<b>m' (for fg)
+// https://en.wikipedia.org/wiki/ANSI_escape_code#Colors
+fn rgb_sequence(rgb: Rgb, support: ColorSupport, bg: bool) -> String {
+    let target = if bg { 48 } else { 38 };
+    match support {
+        ColorSupport::TrueColor => format!("\x1b[{};2;{};{};{}m", target, rgb.0, rgb.1, rgb.2),
+        _ => format!("\x1b[{};5;{}m", target, rgb.to_256()),
+    }
+}
+
 impl AnsiColor {
-    pub fn sequence(&self, support: ColorSupport) -> &'static [u8] {
-        // From color palette of gruvbox: https://github.com/morhetz/gruvbox#palette
-        //
-        // 'm' sets attributes to text printed after: https://vt100.net/docs/vt100-ug/chapter3.html#SGR
-        // Color table: https://en.wikipedia.org/wiki/ANSI_escape_code#Colors
-        //
-        // 256 colors sequences are '\x1b[38;5;<n>m' (for fg) or '\x1b[48;5;<n>m (for bg)
-        // https://www.xfree86.org/current/ctlseqs.html
-        //
-        // 24bit colors sequences are '\x1b[38;2;<r>;<g>;<b>m' (for fg) or '\x1b[48;2;<r>;<g>;<b>m' (for fg)
-        // https://en.wikipedia.org/wiki/ANSI_escape_code#Colors
-
-        macro_rules! rgb_color {
-            (fg, $r:expr, $g:expr, $b:expr) => {
-                concat!("\x1b[38;2;", $r, ';', $g, ';', $b, "m")
-            };
-            (bg, $r:expr, $g:expr, $b:expr) => {
-                concat!("\x1b[48;2;", $r, ';', $g, ';', $b, "m")
-            };
+    fn theme_color(self, theme: &Theme) -> Option<Rgb> {
+        use AnsiColor::*;
+        match self {
+            Reset => Some(theme.foreground),
+            Red => Some(theme.statement),
+            Green => Some(theme.string),
+            Gray => Some(theme.comment),
+            Yellow => Some(theme.type_),
+            Blue => Some(theme.keyword),
+            Purple => Some(theme.number),
+            Cyan => Some(theme.decorator),
+            CyanUnderline => Some(theme.matched),
+            RedUnderline => Some(theme.error),
+            Underline => Some(theme.foreground),
+            RedBG => Some(theme.error),
+            Invert => None,
         }
+    }
 
+    // Colors of 16 colors terminal are decided by terminal's palette so theme is not used
+    fn sequence_16(self) -> &'static str {
         use AnsiColor::*;
-        match support {
-            ColorSupport::TrueColor => match self {
-                Reset => concat!(
-                    "\x1b[39;0m",
-                    rgb_color!(fg, 0xfb, 0xf1, 0xc7),
-                    rgb_color!(bg, 0x28, 0x28, 0x28),
-                )
-                .as_bytes(),
-                Red => rgb_color!(fg, 0xfb, 0x49, 0x34).as_bytes(),
-                Green => rgb_color!(fg, 0xb8, 0xbb, 0x26).as_bytes(),
-                Gray => rgb_color!(fg, 0xa8, 0x99, 0x84).as_bytes(),
-                Yellow => rgb_color!(fg, 0xfa, 0xbd, 0x2f).as_bytes(),
-                Blue => rgb_color!(fg, 0x83, 0xa5, 0x98).as_bytes(),
-                Purple => rgb_color!(fg, 0xd3, 0x86, 0x9b).as_bytes(),
-                Cyan => rgb_color!(fg, 0x8e, 0xc0, 0x7c).as_bytes(),
-                CyanUnderline => concat!("\x1b[4m", rgb_color!(fg, 0x8e, 0xc0, 0x7c)).as_bytes(),
-                RedBG => rgb_color!(bg, 0xcc, 0x24, 0x1d).as_bytes(),
-                Invert => b"\x1b[7m",
-            },
-            ColorSupport::Extended256 => match self {
-                Reset => b"\x1b[39;0m\x1b[38;5;223m\x1b[48;5;235m",
-                Red => b"\x1b[38;5;167m",
-                Green => b"\x1b[38;5;142m",
-                Gray => b"\x1b[38;5;246m",
-                Yellow => b"\x1b[38;5;214m",
-                Blue => b"\x1b[38;5;109m",
-                Purple => b"\x1b[38;5;175m",
-                Cyan => b"\x1b[38;5;108m",
-                CyanUnderline => b"\x1b[4m\x1b[38;5;208m",
-                RedBG => b"\x1b[48;5;124m",
-                Invert => b"\x1b[7m",
-            },
-            ColorSupport::Only16 => match self {
-                Reset => b"\x1b[39;0m",
-                Red => b"\x1b[91m",
-                Green => b"\x1b[32m",
-                Gray => b"\x1b[90m",
-                Yellow => b"\x1b[33m",
-                Blue => b"\x1b[94m",
-                Purple => b"\x1b[95m",
-                Cyan => b"\x1b[96m",
-                CyanUnderline => b"\x1b[96;4m",
-                RedBG => b"\x1b[41m",
-                Invert => b"\x1b[7m",
-            },
+        match self {
+            Reset => "\x1b[39;0m",
+            Red => "\x1b[91m",
+            Green => "\x1b[32m",
+            Gray => "\x1b[90m",
+            Yellow => "\x1b[33m",
+            Blue => "\x1b[94m",
+            Purple => "\x1b[95m",
+            Cyan => "\x1b[96m",
+            CyanUnderline => "\x1b[96;4m",
+            RedUnderline => "\x1b[91;4m",
+            Underline => "\x1b[39;4m",
+            RedBG => "\x1b[41m",
+            Invert => "\x1b[7m",
+        }
+    }
+
+    fn sequence(self, support: ColorSupport, theme: &Theme) -> String {
+        use AnsiColor::*;
+        if let ColorSupport::Only16 = support {
+            return self.sequence_16().to_string();
+        }
+        match (self, self.theme_color(theme)) {
+            (Reset, Some(fg)) => format!(
+                "\x1b[39;0m{}{}",
+                rgb_sequence(fg, support, false),
+                rgb_sequence(theme.background, support, true),
+            ),
+            (CyanUnderline | RedUnderline | Underline, Some(fg)) => {
+                format!("\x1b[4m{}", rgb_sequence(fg, support, false))
+            }
+            (RedBG, Some(bg)) => rgb_sequence(bg, support, true),
+            (_, Some(fg)) => rgb_sequence(fg, support, false),
+            (_, None) => self.sequence_16().to_string(),
         }
     }
 
-    pub fn is_underlined(&self) -> bool {
-        *self == AnsiColor::CyanUnderline
+    // Colors which decide the background by themselves. Backgrounds such as the cursor line are not
+    // layered under them
+    pub fn has_background(&self) -> bool {
+        matches!(self, AnsiColor::Invert | AnsiColor::RedBG)
+    }
+
+    // Attributes such as underline, reverse video and background color are not cleared by setting
+    // other colors
+    pub fn has_attribute(&self) -> bool {
+        matches!(
+            self,
+            AnsiColor::CyanUnderline
+                | AnsiColor::RedUnderline
+                | AnsiColor::Underline
+                | AnsiColor::Invert
+                | AnsiColor::RedBG
+        )
+    }
+}
+
+// Background layered under the foreground colors of highlights
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Background {
+    Default,
+    CursorLine,
+    IndentGuide,
+    Ruler,
+    Scrollbar,
+    Thumb, // Part of scrollbar showing the screen
+    DiffAdded,
+    DiffRemoved,
+    DiffChanged,
+    ConflictMarker,
+    ConflictOurs,
+    ConflictTheirs,
+}
+
+const ALL_BACKGROUNDS: [Background; 12] = [
+    Background::Default,
+    Background::CursorLine,
+    Background::IndentGuide,
+    Background::Ruler,
+    Background::Scrollbar,
+    Background::Thumb,
+    Background::DiffAdded,
+    Background::DiffRemoved,
+    Background::DiffChanged,
+    Background::ConflictMarker,
+    Background::ConflictOurs,
+    Background::ConflictTheirs,
+];
+
+impl Background {
+    // Backgrounds are subtle colors between the background and the foreground of the theme. Lines of
+    // diff and merge conflicts are tinted with the colors of the theme instead
+    fn sequence(self, support: ColorSupport, theme: &Theme) -> String {
+        let (color, percent) = match (self, support) {
+            (Background::Default, ColorSupport::Only16) => return "\x1b[49m".to_string(),
+            (Background::CursorLine, ColorSupport::Only16) => return "\x1b[40m".to_string(),
+            (Background::IndentGuide | Background::Ruler, ColorSupport::Only16) => {
+                return "\x1b[100m".to_string()
+            }
+            (Background::Scrollbar, ColorSupport::Only16) => return "\x1b[40m".to_string(),
+            (Background::Thumb, ColorSupport::Only16) => return "\x1b[47m".to_string(),
+            (Background::DiffAdded, ColorSupport::Only16) => return "\x1b[42m".to_string(),
+            (Background::DiffRemoved, ColorSupport::Only16) => return "\x1b[41m".to_string(),
+            (Background::DiffChanged, ColorSupport::Only16) => return "\x1b[44m".to_string(),
+            (Background::ConflictMarker, ColorSupport::Only16) => return "\x1b[100m".to_string(),
+            (Background::ConflictOurs, ColorSupport::Only16) => return "\x1b[46m".to_string(),
+            (Background::ConflictTheirs, ColorSupport::Only16) => return "\x1b[45m".to_string(),
+            (Background::Default, _) => (theme.foreground, 0),
+            (Background::CursorLine, _) => (theme.foreground, 8),
+            (Background::IndentGuide, _) => (theme.foreground, 12),
+            (Background::Ruler, _) => (theme.foreground, 16),
+            (Background::Scrollbar, _) => (theme.foreground, 8),
+            (Background::Thumb, _) => (theme.foreground, 32),
+            (Background::DiffAdded, _) => (theme.string, 24),
+            (Background::DiffRemoved, _) => (theme.statement, 24),
+            (Background::DiffChanged, _) => (theme.keyword, 24),
+            (Background::ConflictMarker, _) => (theme.foreground, 24),
+            (Background::ConflictOurs, _) => (theme.decorator, 24),
+            (Background::ConflictTheirs, _) => (theme.number, 24),
+        };
+        let rgb = theme.background.mix(color, percent);
+        rgb_sequence(rgb, support, true)
+    }
+}
+
+// Escape sequences of all colors calculated from color support of terminal and theme
+pub struct Palette {
+    sequences: Vec<String>,
+    backgrounds: Vec<String>,
+}
+
+impl Palette {
+    pub fn new(support: ColorSupport, theme: &Theme) -> Palette {
+        Palette {
+            sequences: ALL_COLORS
+                .iter()
+                .map(|c| c.sequence(support, theme))
+                .collect(),
+            backgrounds: ALL_BACKGROUNDS
+                .iter()
+                .map(|b| b.sequence(support, theme))
+                .collect(),
+        }
+    }
+
+    pub fn sequence(&self, color: AnsiColor) -> &[u8] {
+        self.sequences[color as usize].as_bytes()
+    }
+
+    pub fn background(&self, bg: Background) -> &[u8] {
+        self.backgrounds[bg as usize].as_bytes()
     }
 }
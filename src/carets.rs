@@ -0,0 +1,92 @@
+// Extra cursors for editing multiple places at once. Commands are run at each cursor from the last
+// one in the buffer so that an edit only moves cursors after it. Those cursors are shifted by the
+// change of the edited line:
+//
+//   Edit in a line   # Cursors after the edit in the same line move by the change of the length
+//   Split a line     # Cursors after the edit move to the new line
+//   Join two lines   # Cursors in the joined line move to the end of the previous line
+//
+// Other edits such as deleting multiple lines are not run with extra cursors.
+
+use crate::gap_buffer::GapBuffer;
+use crate::row::Row;
+
+// Where an edit happened at a cursor. `before` and `after` are positions of the cursor before and
+// after the edit
+pub struct Edit {
+    pub before: (usize, usize),
+    pub after: (usize, usize),
+    pub lines_delta: isize,
+    pub len_delta: isize, // Change of the length of the line at `before`
+}
+
+impl Edit {
+    pub fn shift(&self, pos: (usize, usize)) -> (usize, usize) {
+        let ((bx, by), (ax, ay)) = (self.before, self.after);
+        let (x, y) = pos;
+        match self.lines_delta {
+            0 if y == by && x >= bx => ((x as isize + self.len_delta).max(0) as usize, y),
+            1 if y == by && x >= bx => (x - bx + ax, y + 1),
+            1 if y > by => (x, y + 1),
+            -1 if y == ay + 1 => (x + ax, ay),
+            -1 if y > ay + 1 => (x, y - 1),
+            _ => pos,
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+// Range of the word at the position or just before it
+pub fn word_at(line: &str, x: usize) -> Option<(usize, usize)> {
+    let chars: Vec<_> = line.chars().collect();
+    let x = if chars.get(x).copied().is_some_and(is_word) {
+        x
+    } else if x > 0 && chars.get(x - 1).copied().is_some_and(is_word) {
+        x - 1
+    } else {
+        return None;
+    };
+    let start = chars[..x]
+        .iter()
+        .rposition(|c| !is_word(*c))
+        .map_or(0, |i| i + 1);
+    let end = chars[x..]
+        .iter()
+        .position(|c| !is_word(*c))
+        .map_or(chars.len(), |i| x + i);
+    Some((start, end))
+}
+
+// Start position of the next occurrence of the whole word after the position. Search wraps around
+// at the end of buffer
+pub fn next_word(
+    rows: &GapBuffer<Row>,
+    word: &str,
+    from: (usize, usize),
+) -> Option<(usize, usize)> {
+    let len = rows.len();
+    if len == 0 {
+        return None;
+    }
+    let word: Vec<_> = word.chars().collect();
+    let (fx, fy) = from;
+    for i in 0..=len {
+        let y = (fy + i) % len;
+        let chars: Vec<_> = rows[y].buffer().chars().collect();
+        for x in 0..=chars.len().saturating_sub(word.len()) {
+            let wraps = i == len; // Only before the start position in the start line
+            if (i == 0 && x <= fx) || (wraps && x >= fx) {
+                continue;
+            }
+            let boundary_before = x == 0 || !is_word(chars[x - 1]);
+            let boundary_after = chars.get(x + word.len()).is_none_or(|c| !is_word(*c));
+            if boundary_before && boundary_after && chars[x..].starts_with(&word) {
+                return Some((x, y));
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,160 @@
+// Command line arguments of `kiro` command. In addition to options, arguments starting with '+'
+// move the cursor in the first file after opening files like Vim:
+//
+//   +120      # Line 120
+//   +         # The last line
+//   +/pattern # The first match of the regular expression
+
+use crate::keymap::Keymap;
+use getopts::Options;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Jump {
+    Line(usize), // 1-based line number
+    LastLine,
+    Pattern(String),
+}
+
+impl Jump {
+    fn parse(arg: &str) -> Option<Jump> {
+        let arg = arg.strip_prefix('+')?;
+        if arg.is_empty() {
+            Some(Jump::LastLine)
+        } else if let Some(pat) = arg.strip_prefix('/') {
+            Some(Jump::Pattern(pat.to_string()))
+        } else {
+            arg.parse().ok().map(Jump::Line)
+        }
+    }
+}
+
+#[derive(Default, PartialEq, Debug)]
+pub struct Args {
+    pub files: Vec<String>,
+    pub jump: Option<Jump>,
+    pub readonly: bool,
+    pub view: bool,
+    pub diff: bool, // Compare two files side by side
+    pub listen: bool,
+    pub remote: bool,
+    pub wait: bool, // Wait until the files opened remotely are saved
+    pub socket: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Cli {
+    Edit(Args),
+    Help(String),
+    Version,
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("R", "readonly", "Open files as read-only buffers");
+    opts.optflag(
+        "",
+        "view",
+        "View files with pager keys like less. Implies -R",
+    );
+    opts.optflag(
+        "",
+        "diff",
+        "Compare two files side by side and highlight changed lines",
+    );
+    opts.optflag(
+        "",
+        "listen",
+        "Accept requests from other processes on the socket",
+    );
+    opts.optflag(
+        "",
+        "remote",
+        "Open files in the editor listening on the socket instead of starting new one",
+    );
+    opts.optflag(
+        "",
+        "remote-wait",
+        "Same as --remote but wait until the files are saved",
+    );
+    opts.optopt(
+        "",
+        "socket",
+        "Path of the socket for --listen and --remote. $KIRO_SOCKET or kiro-$USER.sock in\n$XDG_RUNTIME_DIR by default",
+        "PATH",
+    );
+    opts.optflag("v", "version", "Print version");
+    opts.optflag("h", "help", "Print this help");
+    opts
+}
+
+fn help(program: &str, opts: &Options) -> String {
+    let description = format!(
+        "{prog}: A tiny UTF-8 terminal text editor
+
+Kiro is a tiny UTF-8 text editor on terminals for Unix-like systems and Windows.
+Specify file paths to edit (or a directory to browse) as a command argument or
+run without argument to start to write a new text. '-' reads text from stdin.
+Help can show up with key mapping Ctrl-?.
+
+Usage:
+    {prog} [options] [+N|+|+/pattern] [FILES...]
+    {prog} --remote[-wait] [FILE[:LINE]...]
+    {prog} --diff OLD NEW
+
+Arguments:
+    +N          Move cursor to line N of the first file
+    +           Move cursor to the last line of the first file
+    +/pattern   Move cursor to the first match of the regular expression
+
+Mappings:
+    {maps}",
+        prog = program,
+        maps = Keymap::default().help().replace('\n', "\n    "),
+    );
+    opts.usage(&description)
+}
+
+pub fn parse_args<I>(program: &str, argv: I) -> Result<Cli, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let opts = options();
+    let matches = opts
+        .parse(argv)
+        .map_err(|e| format!("{}. Please see --help for more details", e))?;
+
+    if matches.opt_present("v") {
+        return Ok(Cli::Version);
+    }
+    if matches.opt_present("h") {
+        return Ok(Cli::Help(help(program, &opts)));
+    }
+
+    let mut args = Args {
+        readonly: matches.opt_present("R"),
+        view: matches.opt_present("view"),
+        diff: matches.opt_present("diff"),
+        listen: matches.opt_present("listen"),
+        remote: matches.opt_present("remote") || matches.opt_present("remote-wait"),
+        wait: matches.opt_present("remote-wait"),
+        socket: matches.opt_str("socket"),
+        ..Default::default()
+    };
+    for arg in matches.free {
+        if !arg.starts_with('+') {
+            args.files.push(arg);
+            continue;
+        }
+        match Jump::parse(&arg) {
+            Some(jump) => args.jump = Some(jump),
+            None => return Err(format!("Invalid argument '{}'. Please see --help", arg)),
+        }
+    }
+    if args.remote && args.files.is_empty() {
+        return Err("No file to open remotely. Please see --help".to_string());
+    }
+    if args.diff && args.files.len() != 2 {
+        return Err("--diff needs two files to compare. Please see --help".to_string());
+    }
+    Ok(Cli::Edit(args))
+}
@@ -0,0 +1,164 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+// Backend of the clipboard shared with other applications. It is passed to `Editor` on creating it
+pub trait ClipboardBackend {
+    fn copy(&self, text: &str) -> io::Result<()>;
+    fn paste(&self) -> io::Result<String>;
+}
+
+// Backend which does not touch any clipboard. Copied text is kept only in the editor. This is for
+// headless editor, fuzzing and tests
+pub struct NoClipboard;
+
+impl ClipboardBackend for NoClipboard {
+    fn copy(&self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn paste(&self) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "No clipboard"))
+    }
+}
+
+// External commands to access system clipboard. They are used as fallback since not all terminals
+// support OSC 52 and reading clipboard via OSC 52 is usually disabled for security.
+struct ClipboardCommand {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+const PBCOPY: ClipboardCommand = ClipboardCommand {
+    copy: &["pbcopy"],
+    paste: &["pbpaste"],
+};
+
+const WL_COPY: ClipboardCommand = ClipboardCommand {
+    copy: &["wl-copy"],
+    paste: &["wl-paste", "--no-newline"],
+};
+
+const XCLIP: ClipboardCommand = ClipboardCommand {
+    copy: &["xclip", "-selection", "clipboard"],
+    paste: &["xclip", "-selection", "clipboard", "-o"],
+};
+
+impl ClipboardCommand {
+    fn from_env() -> Option<ClipboardCommand> {
+        if cfg!(target_os = "macos") {
+            Some(PBCOPY)
+        } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(WL_COPY)
+        } else if env::var_os("DISPLAY").is_some() {
+            Some(XCLIP)
+        } else {
+            None
+        }
+    }
+}
+
+impl ClipboardBackend for ClipboardCommand {
+    fn copy(&self, text: &str) -> io::Result<()> {
+        let mut child = Command::new(self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        } // Close stdin here. Otherwise the command waits for more input forever
+        child.wait()?;
+        Ok(())
+    }
+
+    fn paste(&self) -> io::Result<String> {
+        let mut child = Command::new(self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut text = String::new();
+        if let Some(stdout) = child.stdout.as_mut() {
+            stdout.read_to_string(&mut text)?;
+        }
+        if child.wait()?.success() {
+            Ok(text)
+        } else {
+            Err(io::Error::other("Clipboard command failed"))
+        }
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// OSC 52 sets text to clipboard of terminal. It works even on remote machine via SSH
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+pub fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+pub struct Clipboard {
+    // Text copied last time. This is used when system clipboard is not available
+    text: String,
+    backend: Box<dyn ClipboardBackend>,
+}
+
+// Default clipboard does not touch system clipboard
+impl Default for Clipboard {
+    fn default() -> Self {
+        Clipboard::new(Box::new(NoClipboard))
+    }
+}
+
+impl Clipboard {
+    pub fn new(backend: Box<dyn ClipboardBackend>) -> Self {
+        Clipboard {
+            text: String::new(),
+            backend,
+        }
+    }
+
+    // Clipboard accessed with the command for the platform such as pbcopy or xclip
+    pub fn system() -> Self {
+        match ClipboardCommand::from_env() {
+            Some(cmd) => Clipboard::new(Box::new(cmd)),
+            None => Clipboard::default(),
+        }
+    }
+
+    pub fn copy<S: Into<String>>(&mut self, text: S) {
+        self.text = text.into();
+        // Text is still kept in this struct even if the backend failed
+        let _ = self.backend.copy(&self.text);
+    }
+
+    pub fn paste(&mut self) -> &str {
+        if let Ok(text) = self.backend.paste() {
+            // Clipboard may be updated by other applications
+            self.text = text;
+        }
+        self.text.as_str()
+    }
+}
@@ -0,0 +1,49 @@
+// Monotonic clock for timers such as expiring status messages. `std::time::Instant` panics on
+// wasm32-unknown-unknown since the target has no clock. There the current time is imported from
+// JavaScript as `kiro_now` function in `env` module, which returns milliseconds like `Date.now()`.
+// Wall clock for timestamps such as the message log is also taken from the function there.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use js::{unix_time, Instant};
+
+// Seconds since the Unix epoch
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(target_arch = "wasm32")]
+mod js {
+    use std::time::Duration;
+
+    extern "C" {
+        fn kiro_now() -> f64;
+    }
+
+    pub fn unix_time() -> u64 {
+        (unsafe { kiro_now() } / 1000.0) as u64
+    }
+
+    #[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+    pub struct Instant(f64); // Milliseconds
+
+    impl Instant {
+        pub fn now() -> Instant {
+            Instant(unsafe { kiro_now() })
+        }
+
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            Duration::from_secs_f64((self.0 - earlier.0).max(0.0) / 1000.0)
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Instant::now().duration_since(*self)
+        }
+    }
+}
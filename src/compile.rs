@@ -0,0 +1,104 @@
+// Build command integration. Output of the build command is shown in a read-only buffer and
+// diagnostics in it are parsed so that the cursor can jump to the locations. A location is a word
+// in the output formatted as below:
+//
+//   src/main.rs:12:5           # file:line:col like rustc's '--> src/main.rs:12:5'
+//   foo.c:3:10: error: ...     # Trailing ':' is ignored like GCC or Go
+//   Makefile:8                 # Column is optional
+//   File "foo.py", line 3, in f  # Python's traceback
+//
+// The file part must contain '.' or path separator so that times such as '12:30:00' are not
+// considered as locations. The same parser finds a location in text pasted from a terminal.
+
+use crate::language::Language;
+
+// Name of the buffer which shows output of the build command
+pub const OUTPUT_BUFFER: &str = "*compile*";
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize, // 1-based
+    pub col: usize,  // 1-based
+    pub message: String,
+    pub output_line: usize, // Index of the line in the output
+}
+
+pub fn default_command(lang: Language) -> Option<&'static str> {
+    match lang {
+        Language::Rust => Some("cargo build"),
+        Language::Go => Some("go build"),
+        Language::C | Language::Cpp => Some("make"),
+        _ => None,
+    }
+}
+
+fn parse_location(word: &str) -> Option<(String, usize, usize)> {
+    // Locations may be quoted or in parentheses such as '(src/main.rs:3:1)'
+    let word = word.trim_matches(|c| "()[]<>'\"`,".contains(c));
+    let word = word.trim_end_matches(':');
+    let mut parts = word.rsplitn(3, ':');
+    let last = parts.next()?.parse::<usize>().ok()?;
+    let mid = parts.next()?;
+    let (file, line, col) = match (mid.parse::<usize>(), parts.next()) {
+        (Ok(line), Some(file)) => (file, line, last),
+        _ => {
+            // No column. Windows drive letter such as 'C:\foo.c:3' is a part of the file
+            let file = word.rsplit_once(':')?.0;
+            (file, last, 1)
+        }
+    };
+    let is_path = file.contains('.') || file.contains('/') || file.contains('\\');
+    if !is_path || line == 0 || col == 0 {
+        return None;
+    }
+    Some((file.to_string(), line, col))
+}
+
+// Python shows a location in traceback as 'File "foo.py", line 3, in f'
+fn parse_python_location(line: &str) -> Option<(String, usize, usize)> {
+    let rest = line.strip_prefix("File \"")?;
+    let (file, rest) = rest.split_once("\", line ")?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    match rest[..digits].parse() {
+        Ok(line) if line > 0 && !file.is_empty() => Some((file.to_string(), line, 1)),
+        _ => None,
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, usize, usize)> {
+    parse_python_location(line).or_else(|| line.split_whitespace().find_map(parse_location))
+}
+
+// The first location in the text such as an error message copied from terminal
+pub fn find_location(text: &str) -> Option<(String, usize, usize)> {
+    text.lines().find_map(|line| parse_line(line.trim_start()))
+}
+
+pub fn parse(output: &str) -> Vec<Diagnostic> {
+    let lines: Vec<_> = output.lines().collect();
+    let mut diags = vec![];
+    for (idx, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        let (file, line, col) = match parse_line(trimmed) {
+            Some(loc) => loc,
+            None => continue,
+        };
+        // rustc shows the message on the line before '--> file:line:col'
+        let message = if trimmed.starts_with("-->") && idx > 0 {
+            lines[idx - 1].trim()
+        } else {
+            trimmed
+        };
+        diags.push(Diagnostic {
+            file,
+            line,
+            col,
+            message: message.to_string(),
+            output_line: idx,
+        });
+    }
+    diags
+}
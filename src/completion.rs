@@ -0,0 +1,148 @@
+// Completion of the word at cursor while typing. Candidates are words which start with the word
+// before the cursor. They are collected from lines of open buffers, nearer lines to the cursor
+// first. Candidates are shown in a popup under the cursor:
+//
+//   Up/Down   # Select candidate
+//   Tab       # Insert the selected candidate
+//   ESC       # Dismiss the popup
+//
+// Other keys close the popup and are handled as usual.
+//
+// `complete` command completes the word before the cursor inline instead. Repeating it cycles the
+// candidates. When the text before the cursor looks like a path such as `./src/ma`, it is completed
+// with entries in the directory.
+
+use std::collections::BTreeMap;
+
+// Completion starts when the word before cursor is at least this length
+pub const MIN_PREFIX: usize = 2;
+pub const MAX_CANDIDATES: usize = 8;
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Start index (in characters) and text of the word before character index `cx`. The word may be
+// empty
+pub fn word_before(line: &str, cx: usize) -> (usize, String) {
+    let before: Vec<_> = line.chars().take(cx).collect();
+    let start = before
+        .iter()
+        .rposition(|c| !is_word_char(*c))
+        .map_or(0, |i| i + 1);
+    (start, before[start..].iter().collect())
+}
+
+// Word before character index `cx` which is long enough to be completed
+pub fn prefix_at(line: &str, cx: usize) -> Option<(usize, String)> {
+    let (start, prefix) = word_before(line, cx);
+    if prefix.chars().count() < MIN_PREFIX || prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((start, prefix))
+}
+
+// Start index (in characters) and text of the path before character index `cx`. The path must
+// start with '/', './' or '../'
+pub fn path_at(line: &str, cx: usize) -> Option<(usize, String)> {
+    let before: Vec<_> = line.chars().take(cx).collect();
+    let start = before
+        .iter()
+        .rposition(|c| c.is_whitespace() || "\"'`()[]{}<>=,;:".contains(*c))
+        .map_or(0, |i| i + 1);
+    let path: String = before[start..].iter().collect();
+    if path.starts_with('/') || path.starts_with("./") || path.starts_with("../") {
+        Some((start, path))
+    } else {
+        None
+    }
+}
+
+// Words which start with `prefix` in the lines. Duplicates and the prefix itself are excluded
+pub fn word_candidates<'a, I>(prefix: &str, lines: I) -> Vec<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut words: Vec<String> = vec![];
+    for line in lines {
+        for word in line.split(|c| !is_word_char(c)) {
+            if word.len() > prefix.len()
+                && word.starts_with(prefix)
+                && !words.iter().any(|w| w == word)
+            {
+                words.push(word.to_string());
+                if words.len() == MAX_CANDIDATES {
+                    return words;
+                }
+            }
+        }
+    }
+    words
+}
+
+pub struct Completion {
+    pub start: usize, // Character index where the completed word starts
+    pub prefix: String,
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+impl Completion {
+    pub fn select(&mut self, forward: bool) {
+        let len = self.candidates.len();
+        self.selected = if forward {
+            (self.selected + 1) % len
+        } else {
+            (self.selected + len - 1) % len
+        };
+    }
+
+    // Text to be inserted at cursor to complete the selected candidate
+    pub fn rest(&self) -> &str {
+        &self.candidates[self.selected][self.prefix.len()..]
+    }
+}
+
+// Words in lines of a buffer with lines where they appear. This is collected once and cached until
+// the buffer is modified
+#[derive(Default)]
+pub struct Words(BTreeMap<String, Vec<usize>>);
+
+impl Words {
+    pub fn collect<'a, I: Iterator<Item = &'a str>>(lines: I) -> Self {
+        let mut words: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (y, line) in lines.enumerate() {
+            for word in line.split(|c| !is_word_char(c)) {
+                if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) {
+                    continue;
+                }
+                let ys = words.entry(word.to_string()).or_default();
+                if ys.last() != Some(&y) {
+                    ys.push(y);
+                }
+            }
+        }
+        Words(words)
+    }
+
+    // Words which start with `prefix` excluding the prefix itself. Words appearing nearer to line
+    // `near` come first. Otherwise they are sorted alphabetically
+    pub fn starting_with(&self, prefix: &str, near: Option<usize>) -> Vec<&str> {
+        let mut found: Vec<_> = self
+            .0
+            .range(prefix.to_string()..)
+            .take_while(|(w, _)| w.starts_with(prefix))
+            .filter(|(w, _)| w.len() > prefix.len())
+            .collect();
+        if let Some(y) = near {
+            // Lines are sorted so the nearest one is around the insertion point of `y`
+            found.sort_by_key(|(_, ys)| {
+                let i = ys.partition_point(|l| *l < y);
+                let after = ys.get(i).map(|l| l - y);
+                let before = i.checked_sub(1).map(|i| y - ys[i]);
+                after.into_iter().chain(before).min()
+            });
+        }
+        found.into_iter().map(|(w, _)| w.as_str()).collect()
+    }
+}
@@ -0,0 +1,358 @@
+use crate::completion::is_word_char;
+use crate::error::{self, Context, Error};
+use crate::grapheme::AmbiguousWidth;
+use crate::input::InputSeq;
+use crate::keymap::{self, Command, Keymap};
+use crate::status_bar::StatusLayout;
+use crate::theme::Theme;
+use crate::toml::{self, Table, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Directory for user configurations. ~/.config/kiro by default
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .map(|d| d.join("kiro"))
+}
+
+// Directory for states kept across sessions such as marks. ~/.local/share/kiro/state by default
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".local").join("share")))
+        .map(|d| d.join("kiro").join("state"))
+}
+
+// Configurations in ~/.config/kiro/config.toml
+//
+//   [keymap]
+//   save = "Ctrl-W"                   # Command name = key
+//   delete-word = ["Alt-D", "Ctrl-U"] # Multiple keys can be bound to one command
+//   case.upcase = "Alt-U"             # Command of plugin is bound with the plugin name
+//
+//   [plugins]
+//   case = "kiro-case-plugin"         # Name = command line of plugin process
+//
+//   [theme]
+//   name = "solarized"                # Builtin theme
+//   keyword = "#268bd2"               # Override color of highlight
+//
+//   [status]
+//   left = ["filename", "modified"]   # Segments of status bar in order
+//   right = ["position"]
+//
+//   [editor]
+//   backup = true                     # Keep previous contents in 'file~' on saving
+//   swap_interval = 10                # Seconds between writes of swap files. 0 disables them
+//   vi = true                         # Modal editing like Vi
+//   compile = "make -j4"              # Build command run by compile command
+//   completion = false                # Disable completion popup while typing
+//   spell = true                      # Spell checking of text, comments and strings
+//   dictionary = "/path/to/en_GB"     # Hunspell dictionary (.dic and .aff) or word list
+//   format = { rust = "rustfmt" }     # Formatter commands of languages
+//   format_on_save = true             # Format buffer with the formatter before saving
+//   abbrev = { teh = "the" }          # Expanded when a non-word character is typed after it
+//   mmap = true                       # Open files with memory map instead of reading them
+//   auto_revert = true                # Reload unmodified buffers when their files are changed
+//   scrolloff = 3                     # Lines kept visible above and below the cursor
+//   sidescroll = 8                    # Columns scrolled horizontally at once. 0 is half screen
+//   invisibles = true                 # Show tabs, trailing spaces and non-breaking spaces
+//   rainbow_brackets = true           # Color brackets by their nesting depths
+//   cursor_line = true                # Highlight the line at the cursor with subtle background
+//   ruler = [80, 100]                 # Columns of guides. One integer is also accepted
+//   indent_guides = true              # Show guides at indentation levels of code
+//   scrollbar = true                  # Show position of screen, changes and matches at right
+//   ambiguous_width = "narrow"        # Width of East Asian ambiguous characters. "auto" detects it
+#[derive(Default, Clone)]
+pub struct Config {
+    pub keymap: Keymap,
+    pub theme: Theme,
+    pub status: StatusLayout,
+    pub editor: Options,
+    pub plugins: BTreeMap<String, String>, // Plugin name to command line of its process
+}
+
+// General options of editor in [editor] table
+#[derive(Clone)]
+pub struct Options {
+    pub backup: bool,
+    pub swap_interval: u64,
+    pub vi: bool,
+    pub compile: Option<String>, // None uses default command of the language
+    pub completion: bool,
+    pub spell: bool,
+    pub dictionary: Option<String>, // None searches dictionary of the locale
+    pub format: BTreeMap<String, String>, // Language name to formatter command
+    pub format_on_save: bool,
+    pub abbrev: BTreeMap<String, String>, // Abbreviation to its expansion
+    pub mmap: bool,
+    pub auto_revert: bool,
+    pub scrolloff: usize,
+    pub sidescroll: usize,
+    pub invisibles: bool,
+    pub rainbow_brackets: bool,
+    pub cursor_line: bool,
+    pub indent_guides: bool,
+    pub scrollbar: bool,
+    pub ruler: Vec<usize>, // Widths of lines such as 80. Guides are drawn at the next columns
+    pub ambiguous_width: AmbiguousWidth,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            backup: false,
+            swap_interval: 10,
+            vi: false,
+            compile: None,
+            completion: true,
+            spell: false,
+            dictionary: None,
+            format: BTreeMap::new(),
+            format_on_save: false,
+            abbrev: BTreeMap::new(),
+            mmap: false,
+            auto_revert: false,
+            scrolloff: 0,
+            sidescroll: 0,
+            invisibles: false,
+            rainbow_brackets: false,
+            cursor_line: false,
+            indent_guides: false,
+            scrollbar: false,
+            ruler: vec![],
+            ambiguous_width: AmbiguousWidth::Auto,
+        }
+    }
+}
+
+impl Options {
+    fn from_table(table: &Table) -> Result<Options, String> {
+        let mut options = Options::default();
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("backup", Value::Boolean(b)) => options.backup = *b,
+                ("vi", Value::Boolean(b)) => options.vi = *b,
+                ("completion", Value::Boolean(b)) => options.completion = *b,
+                ("spell", Value::Boolean(b)) => options.spell = *b,
+                ("format_on_save", Value::Boolean(b)) => options.format_on_save = *b,
+                ("mmap", Value::Boolean(b)) => options.mmap = *b,
+                ("auto_revert", Value::Boolean(b)) => options.auto_revert = *b,
+                ("invisibles", Value::Boolean(b)) => options.invisibles = *b,
+                ("rainbow_brackets", Value::Boolean(b)) => options.rainbow_brackets = *b,
+                ("cursor_line", Value::Boolean(b)) => options.cursor_line = *b,
+                ("indent_guides", Value::Boolean(b)) => options.indent_guides = *b,
+                ("scrollbar", Value::Boolean(b)) => options.scrollbar = *b,
+                ("backup", v)
+                | ("vi", v)
+                | ("completion", v)
+                | ("spell", v)
+                | ("format_on_save", v)
+                | ("mmap", v)
+                | ("auto_revert", v)
+                | ("invisibles", v)
+                | ("rainbow_brackets", v)
+                | ("cursor_line", v)
+                | ("indent_guides", v)
+                | ("scrollbar", v) => {
+                    return Err(format!(
+                        "'{}' must be boolean but got {}",
+                        key,
+                        v.type_name()
+                    ))
+                }
+                ("swap_interval", Value::Integer(i)) if *i >= 0 => {
+                    options.swap_interval = *i as u64
+                }
+                ("scrolloff", Value::Integer(i)) if *i >= 0 => options.scrolloff = *i as usize,
+                ("sidescroll", Value::Integer(i)) if *i >= 0 => options.sidescroll = *i as usize,
+                ("swap_interval", v) | ("scrolloff", v) | ("sidescroll", v) => {
+                    return Err(format!(
+                        "'{}' must be non-negative integer but got {}",
+                        key,
+                        v.type_name()
+                    ))
+                }
+                ("ambiguous_width", Value::String(s)) => {
+                    options.ambiguous_width = AmbiguousWidth::from_name(s).ok_or_else(|| {
+                        format!(
+                            "'{}' must be \"auto\", \"narrow\" or \"wide\" but got '{}'",
+                            key, s
+                        )
+                    })?
+                }
+                ("compile", Value::String(s)) => options.compile = Some(s.clone()),
+                ("dictionary", Value::String(s)) => options.dictionary = Some(s.clone()),
+                ("compile", v) | ("dictionary", v) | ("ambiguous_width", v) => {
+                    return Err(format!(
+                        "'{}' must be string but got {}",
+                        key,
+                        v.type_name()
+                    ))
+                }
+                ("format", Value::Table(t)) => {
+                    for (lang, cmd) in t {
+                        let cmd = cmd
+                            .as_str()
+                            .ok_or_else(|| format!("Formatter of '{}' must be string", lang))?;
+                        options
+                            .format
+                            .insert(lang.to_ascii_lowercase(), cmd.to_string());
+                    }
+                }
+                ("abbrev", Value::Table(t)) => {
+                    for (word, text) in t {
+                        let text = text.as_str().ok_or_else(|| {
+                            format!("Expansion of abbreviation '{}' must be string", word)
+                        })?;
+                        if word.is_empty() || !word.chars().all(is_word_char) {
+                            return Err(format!(
+                                "Abbreviation '{}' must consist of word characters",
+                                word
+                            ));
+                        }
+                        if text.contains('\n') {
+                            return Err(format!(
+                                "Expansion of abbreviation '{}' must be one line",
+                                word
+                            ));
+                        }
+                        options.abbrev.insert(word.clone(), text.to_string());
+                    }
+                }
+                ("ruler", Value::Integer(i)) if *i > 0 => options.ruler = vec![*i as usize],
+                ("ruler", Value::Array(elems)) => {
+                    options.ruler = elems
+                        .iter()
+                        .map(|v| match v {
+                            Value::Integer(i) if *i > 0 => Ok(*i as usize),
+                            v => Err(format!(
+                                "Columns of 'ruler' must be positive integers but got {}",
+                                v.type_name()
+                            )),
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                ("ruler", v) => {
+                    return Err(format!(
+                        "'{}' must be positive integer or array of them but got {}",
+                        key,
+                        v.type_name()
+                    ))
+                }
+                ("format", v) | ("abbrev", v) => {
+                    return Err(format!("'{}' must be table but got {}", key, v.type_name()))
+                }
+                (key, _) => return Err(format!("Unknown key '{}' in [editor]", key)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_keys(name: &str, value: &Value) -> Result<Vec<InputSeq>, String> {
+    let notations = match value {
+        Value::String(s) => vec![s.as_str()],
+        Value::Array(elems) => elems
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .ok_or_else(|| format!("Keys of '{}' must be strings", name))
+            })
+            .collect::<Result<_, _>>()?,
+        v => {
+            return Err(format!(
+                "Key of '{}' must be string or array but got {}",
+                name,
+                v.type_name(),
+            ))
+        }
+    };
+    notations
+        .iter()
+        .map(|n| keymap::parse_key(n).ok_or_else(|| format!("Invalid key '{}'", n)))
+        .collect()
+}
+
+fn parse_keymap(table: &Table) -> Result<Keymap, String> {
+    let mut keymap = Keymap::default();
+    for (name, value) in table {
+        match (Command::from_name(name), value) {
+            (Some(cmd), value) => keymap.bind(cmd, parse_keys(name, value)?),
+            // Table of plugin such as `[keymap.plugin]` binds keys to its commands. The commands
+            // are checked after plugins are loaded
+            (None, Value::Table(commands)) => {
+                for (cmd, value) in commands {
+                    keymap.bind_plugin(cmd, parse_keys(cmd, value)?);
+                }
+            }
+            (None, _) => return Err(format!("Unknown command '{}' in [keymap]", name)),
+        }
+    }
+    Ok(keymap)
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Config, String> {
+        let table = toml::parse(source).map_err(|e| e.to_string())?;
+        let mut config = Config::default();
+        for (key, value) in &table {
+            match (key.as_str(), value) {
+                ("keymap", Value::Table(t)) => config.keymap = parse_keymap(t)?,
+                ("theme", Value::Table(t)) => config.theme = Theme::from_table(t)?,
+                ("status", Value::Table(t)) => config.status = StatusLayout::from_table(t)?,
+                ("editor", Value::Table(t)) => config.editor = Options::from_table(t)?,
+                ("plugins", Value::Table(t)) => {
+                    for (name, cmdline) in t {
+                        let cmdline = cmdline.as_str().ok_or_else(|| {
+                            format!("Command of plugin '{}' must be string", name)
+                        })?;
+                        config.plugins.insert(name.clone(), cmdline.to_string());
+                    }
+                }
+                ("keymap", v) | ("theme", v) | ("status", v) | ("editor", v) | ("plugins", v) => {
+                    return Err(format!("'{}' must be table but got {}", key, v.type_name()))
+                }
+                (key, _) => return Err(format!("Unknown key '{}'", key)),
+            }
+        }
+        Ok(config)
+    }
+
+    fn load(path: &Path) -> error::Result<Config> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()), // Config file is optional
+            Err(err) => return Err(err).context("reading", path),
+        };
+        Config::parse(&source).map_err(|message| Error::Config {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+}
+
+static USER_CONFIG: OnceLock<error::Result<Config>> = OnceLock::new();
+
+// Load ~/.config/kiro/config.toml. This should be called once before creating an editor. Default
+// configuration is used when the file does not exist or is broken
+pub fn load_user_config() {
+    USER_CONFIG.get_or_init(|| match config_dir() {
+        Some(dir) => Config::load(&dir.join("config.toml")),
+        None => Ok(Config::default()),
+    });
+}
+
+pub fn user_config() -> Option<&'static Config> {
+    USER_CONFIG.get().and_then(|c| c.as_ref().ok())
+}
+
+pub fn user_config_error() -> Option<&'static Error> {
+    USER_CONFIG.get().and_then(|c| c.as_ref().err())
+}
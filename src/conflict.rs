@@ -0,0 +1,110 @@
+// Merge conflicts which git leaves in a file. Each conflict is surrounded by markers, and the common
+// ancestor is also shown when `merge.conflictStyle` is `diff3`:
+//
+//   <<<<<<< HEAD
+//   lines of ours
+//   ||||||| base
+//   lines of the common ancestor
+//   =======
+//   lines of theirs
+//   >>>>>>> branch
+
+use std::ops::Range;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Conflict {
+    pub start: usize,        // Line of '<<<<<<<'
+    pub base: Option<usize>, // Line of '|||||||'
+    pub middle: usize,       // Line of '======='
+    pub end: usize,          // Line of '>>>>>>>'
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Part {
+    Marker,
+    Ours,
+    Base,
+    Theirs,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+impl Conflict {
+    pub fn ours(&self) -> Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.middle)
+    }
+
+    pub fn theirs(&self) -> Range<usize> {
+        self.middle + 1..self.end
+    }
+
+    pub fn contains(&self, y: usize) -> bool {
+        self.start <= y && y <= self.end
+    }
+
+    pub fn part_at(&self, y: usize) -> Option<Part> {
+        if !self.contains(y) {
+            None
+        } else if self.ours().contains(&y) {
+            Some(Part::Ours)
+        } else if self.theirs().contains(&y) {
+            Some(Part::Theirs)
+        } else if self.base.is_some_and(|b| b < y && y < self.middle) {
+            Some(Part::Base)
+        } else {
+            Some(Part::Marker)
+        }
+    }
+
+    // Lines kept by the resolution. Markers and the common ancestor are removed
+    pub fn kept(&self, resolution: Resolution) -> Vec<Range<usize>> {
+        match resolution {
+            Resolution::Ours => vec![self.ours()],
+            Resolution::Theirs => vec![self.theirs()],
+            Resolution::Both => vec![self.ours(), self.theirs()],
+        }
+    }
+}
+
+// A marker is 7 marker characters followed by nothing or a space and a label
+fn is_marker(line: &str, c: u8) -> bool {
+    let bytes = line.as_bytes();
+    bytes.len() >= 7
+        && bytes[..7].iter().all(|b| *b == c)
+        && matches!(bytes.get(7), None | Some(b' '))
+}
+
+// Conflicts in the lines. Markers out of order such as '=======' without '<<<<<<<' are ignored
+pub fn find<'a, I: Iterator<Item = &'a str>>(lines: I) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    let (mut start, mut base, mut middle) = (None, None, None);
+    for (y, line) in lines.enumerate() {
+        if is_marker(line, b'<') {
+            start = Some(y);
+            base = None;
+            middle = None;
+        } else if start.is_some() && middle.is_none() && base.is_none() && is_marker(line, b'|') {
+            base = Some(y);
+        } else if start.is_some() && middle.is_none() && is_marker(line, b'=') {
+            middle = Some(y);
+        } else if is_marker(line, b'>') {
+            if let (Some(start), Some(middle)) = (start, middle) {
+                conflicts.push(Conflict {
+                    start,
+                    base,
+                    middle,
+                    end: y,
+                });
+            }
+            start = None;
+            base = None;
+            middle = None;
+        }
+    }
+    conflicts
+}
@@ -0,0 +1,193 @@
+// Line-based diff by Myers' O(ND) algorithm. It is used for comparing a buffer with the file in git
+// HEAD, for applying output of formatters as minimal edits and for diff mode comparing two buffers
+// side by side. Sides of diff mode are 0 for the old text on the left and 1 for the new text.
+
+use std::cmp;
+use std::ops::Range;
+
+// Edit distance at which comparing lines gives up. Lines between the common prefix and suffix are
+// replaced entirely in the case
+const MAX_EDITS: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Equal,
+    Insert,
+    Delete,
+}
+
+// Shortest edit script from `old` to `new`. None when the edit distance exceeds MAX_EDITS
+fn edit_script(old: &[&str], new: &[&str]) -> Option<Vec<Op>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // Furthest x of each diagonal k in -d..=d for each d. They are used to trace back the path
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    let mut found = None;
+    for d in 0..=max.min(MAX_EDITS) as isize {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1] // Move down (insert)
+            } else {
+                v[i - 1] + 1 // Move right (delete)
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                found = Some(d);
+                break;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        if found.is_some() {
+            break;
+        }
+    }
+    let found = found?;
+
+    let mut ops = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (1..=found).rev() {
+        let prev = &trace[d as usize - 1]; // Indexed by k + d - 1
+        let at = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.extend((0..x).map(|_| Op::Equal)); // Common prefix
+    ops.reverse();
+    Some(ops)
+}
+
+// Edit operations from `old` to `new` in order. Common prefix and suffix are skipped before
+// running the algorithm since edits are usually local. When the edit distance is too large, all
+// lines between them are deleted and inserted
+pub fn line_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut ops = vec![Op::Equal; prefix];
+    match edit_script(old_mid, new_mid) {
+        Some(script) => ops.extend(script),
+        None => {
+            ops.extend(old_mid.iter().map(|_| Op::Delete));
+            ops.extend(new_mid.iter().map(|_| Op::Insert));
+        }
+    }
+    ops.extend((0..suffix).map(|_| Op::Equal));
+    ops
+}
+
+// Lines `lines[0]` of the old text are replaced with lines `lines[1]` of the new text. One of them is
+// empty when lines are only inserted or deleted
+#[derive(Clone, PartialEq, Debug)]
+pub struct Hunk {
+    pub lines: [Range<usize>; 2],
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+// Runs of lines which are not equal between the texts
+pub fn hunks(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    let (mut o, mut n) = (0, 0);
+    let mut start = None;
+    for op in line_ops(old, new) {
+        if op == Op::Equal {
+            if let Some((so, sn)) = start.take() {
+                hunks.push(Hunk {
+                    lines: [so..o, sn..n],
+                });
+            }
+        } else if start.is_none() {
+            start = Some((o, n));
+        }
+        match op {
+            Op::Equal => {
+                o += 1;
+                n += 1;
+            }
+            Op::Delete => o += 1,
+            Op::Insert => n += 1,
+        }
+    }
+    if let Some((so, sn)) = start {
+        hunks.push(Hunk {
+            lines: [so..o, sn..n],
+        });
+    }
+    hunks
+}
+
+// The hunk changing the line on the side. A hunk which only has lines on the other side is found at
+// the line following the missing lines
+pub fn hunk_at(hunks: &[Hunk], side: usize, y: usize) -> Option<&Hunk> {
+    let i = hunks.partition_point(|h| {
+        let lines = &h.lines[side];
+        if lines.is_empty() {
+            lines.start < y
+        } else {
+            lines.end <= y
+        }
+    });
+    hunks.get(i).filter(|h| h.lines[side].start <= y)
+}
+
+pub fn change_at(hunks: &[Hunk], side: usize, y: usize) -> Option<Change> {
+    let hunk = hunk_at(hunks, side, y).filter(|h| h.lines[side].contains(&y))?;
+    Some(match (hunk.lines[1 - side].is_empty(), side) {
+        (false, _) => Change::Changed,
+        (true, 0) => Change::Removed,
+        (true, _) => Change::Added,
+    })
+}
+
+// The line on the other side corresponding to the line on the side. Lines in a hunk correspond to
+// the lines at the same offsets in the hunk as far as the other side has
+pub fn corresponding_line(hunks: &[Hunk], side: usize, y: usize) -> usize {
+    let i = hunks.partition_point(|h| h.lines[side].start <= y);
+    let hunk = match i.checked_sub(1) {
+        Some(i) => &hunks[i],
+        None => return y,
+    };
+    let (this, other) = (&hunk.lines[side], &hunk.lines[1 - side]);
+    if this.contains(&y) {
+        other.start + cmp::min(y - this.start, other.len().saturating_sub(1))
+    } else {
+        other.end + (y - this.end)
+    }
+}
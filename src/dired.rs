@@ -0,0 +1,50 @@
+// Listing of directory entries. A directory is opened as read-only text buffer whose lines are its
+// entries. Names of directories end with '/' and are listed before files:
+//
+//   ../
+//   src/
+//   Cargo.toml
+//   README.md
+//
+// Entering a line opens the file or descends into the directory.
+
+use crate::error::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PARENT: &str = "../";
+
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let mut dirs = vec![];
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).context("listing", dir)? {
+        let entry = entry.context("listing", dir)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Follow symbolic links to know the target is a directory
+        if entry.path().is_dir() {
+            dirs.push(format!("{}/", name));
+        } else {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+
+    let mut lines = Vec::with_capacity(dirs.len() + files.len() + 1);
+    if dir.parent().is_some() {
+        lines.push(PARENT.to_string());
+    }
+    lines.extend(dirs);
+    lines.extend(files);
+    Ok(lines)
+}
+
+// Path of the entry at the line of listing. The directory must be canonicalized so that parent
+// directory can be resolved
+pub fn entry_path(dir: &Path, line: &str) -> PathBuf {
+    if line == PARENT {
+        dir.parent().unwrap_or(dir).to_path_buf()
+    } else {
+        dir.join(line.trim_end_matches('/'))
+    }
+}
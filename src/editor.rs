@@ -1,34 +1,129 @@
-use crate::highlight::Highlighting;
-use crate::input::{InputSeq, KeySeq};
+use crate::cli::Jump;
+use crate::clipboard::Clipboard;
+use crate::clock::Instant;
+use crate::compile::{self, Diagnostic};
+use crate::completion::{self, Completion};
+use crate::config::{self, Options};
+use crate::conflict::Resolution;
+use crate::diff::{self, Hunk};
+use crate::encoding::Encoding;
+use crate::error::{Error, Result};
+use crate::event::{Event, Events, TaskResult};
+use crate::finder;
+use crate::format;
+use crate::headless::{self, ReaderInputs};
+use crate::highlight::{self, BracketPair, Highlight, Highlighting};
+use crate::history::{History, HistoryKind};
+use crate::input::{InputSeq, KeySeq, ScrollDir};
+use crate::jump;
+use crate::keymap::{self, Command, Keymap};
+use crate::killring::KillRing;
 use crate::language::Language;
-use crate::screen::Screen;
+use crate::marks;
+use crate::pager;
+use crate::picker::Picker;
+use crate::plugin::{self, Action, Plugin, Plugins};
+use crate::prompt::{self, PromptInput};
+use crate::remote::{self, Call, Request, Server};
+use crate::row::Row;
+use crate::screen::{DiffPane, Screen};
+use crate::script::InitScript;
+use crate::search::Matcher;
+use crate::session::{Position, Session};
+use crate::shell;
+use crate::spell::{self, Dictionary};
 use crate::status_bar::StatusBar;
+use crate::stream::{self, Chunk};
 use crate::text_buffer::{CursorDir, Lines, TextBuffer};
-use std::io::{self, Write};
-use std::path::Path;
+use crate::theme::{Theme, BUILTIN_THEMES};
+use crate::undo::EditCommand;
+use crate::vcs::{self, Blame};
+use crate::vi::{self, Mode, Operator, Vi};
+use std::cmp;
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str;
+use std::time::Duration;
 
 #[derive(Clone, Copy)]
 enum FindDir {
     Back,
     Forward,
 }
+
+impl FindDir {
+    fn next_line(self, y: usize, len: usize) -> usize {
+        // Wrapping text search at top/bottom of text buffer
+        match self {
+            FindDir::Forward if y == len - 1 => 0,
+            FindDir::Forward => y + 1,
+            FindDir::Back if y == 0 => len - 1,
+            FindDir::Back => y - 1,
+        }
+    }
+}
+
+const FIND_KEYS: &str = "(^N or DOWN to forward, ^P or UP to back, ^G or ESC to cancel)";
+
 struct FindState {
-    last_match: Option<usize>,
+    last_match: Option<(usize, usize)>, // Start of the match
     dir: FindDir,
+    regex: bool, // Query is a regular expression
+    // All matches of the query in the buffer as (y, start, end). They are collected once per query
+    // and reused while moving between them
+    matches: Option<Vec<(usize, usize, usize)>>,
 }
 
 impl FindState {
-    fn new() -> FindState {
+    fn new(regex: bool) -> FindState {
         FindState {
             last_match: None,
             dir: FindDir::Forward,
+            regex,
+            matches: None,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        if self.regex {
+            "Regex search"
+        } else {
+            "Search"
+        }
+    }
+
+    // 1-based index of the current match among all matches
+    fn current_index(&self) -> usize {
+        match (self.last_match, &self.matches) {
+            (Some((x, y)), Some(matches)) => {
+                matches.partition_point(|&(my, mx, _)| (my, mx) < (y, x)) + 1
+            }
+            _ => 0,
         }
     }
 }
 
+// Word replaced with its candidates in turn by repeating spell-suggest or complete command
+struct Suggestion {
+    y: usize,
+    start: usize,
+    candidates: Vec<String>, // The first one is the original text
+    idx: usize,              // Index of the candidate at cursor
+}
+
+// Two buffers compared side by side. The buffer on the other side than the current buffer is drawn
+// with its own highlighting
+struct DiffMode {
+    bufs: [usize; 2], // Buffers of the old text on the left and the new text on the right
+    hunks: Vec<Hunk>,
+    other: usize, // Buffer highlighted by `other_hl`
+    other_hl: Highlighting,
+}
+
 pub struct Editor<I: Iterator<Item = io::Result<InputSeq>>, W: Write> {
-    input: I,           // Escape sequences stream represented as Iterator
+    events: Events<I>,  // Key inputs and results of background tasks
     quitting: bool,     // After first Ctrl-Q
     finding: FindState, // Text search state
     hl: Highlighting,
@@ -36,6 +131,38 @@ pub struct Editor<I: Iterator<Item = io::Result<InputSeq>>, W: Write> {
     bufs: Vec<TextBuffer>,
     buf_idx: usize,
     status_bar: StatusBar,
+    clipboard: Clipboard,
+    selecting_by_shift: bool, // Selection was started by Shift + cursor key
+    keymap: Keymap,
+    killring: KillRing,
+    last_cmd: Option<Command>, // Command run by the previous key input
+    yanked_at: Option<(usize, usize)>, // Start position of text pasted by the last yank
+    rectangle: Vec<String>,    // Lines of rectangle copied last
+    current_edit: Option<EditCommand>, // Edit operation by the current key input
+    last_edit: Option<EditCommand>, // Edit operation to be repeated
+    inserting: bool,           // The previous key input typed text
+    options: Options,
+    vi: Option<Vi>,             // Modal editing is enabled
+    view: bool,                 // Viewer mode with pager keys
+    swapped_at: Instant,        // When swap files were written last time
+    state_dir: Option<PathBuf>, // Directory to persist marks. None disables persistence
+    history: History,           // Inputs of prompts such as search queries
+    // Suspends the process and returns after resumed. Set by frontend supporting job control
+    suspender: Option<Box<dyn FnMut() -> io::Result<()>>>,
+    compile_cmd: Option<String>,        // Build command run last time
+    compiling: bool,                    // Build command is running in background
+    diagnostics: Vec<Diagnostic>,       // Locations in output of the last build
+    diag_idx: Option<usize>,            // Diagnostic jumped last
+    completion: Option<Completion>,     // Candidates shown in popup while typing
+    dictionary: Option<Rc<Dictionary>>, // Loaded when spell checking is enabled first time
+    spell_check: bool,
+    suggestion: Option<Suggestion>, // Suggestions of the word replaced by the last spell-suggest
+    completed: Option<Suggestion>,  // Candidates of the text replaced by the last complete
+    literal: bool,                  // Next typed character does not expand abbreviation
+    plugins: Plugins,
+    server: Option<Server>, // Socket accepting requests from other processes
+    waiting: Vec<(Vec<PathBuf>, Call)>, // Clients waiting until the files are saved
+    diff: Option<DiffMode>,
 }
 
 impl<I, W> Editor<I, W>
@@ -44,48 +171,269 @@ where
     W: Write,
 {
     pub fn new(
-        mut input: I,
+        input: I,
         output: W,
         window_size: Option<(usize, usize)>,
-    ) -> io::Result<Editor<I, W>> {
-        let screen = Screen::new(window_size, &mut input, output)?;
-        Ok(Editor {
-            input,
-            quitting: false,
-            finding: FindState::new(),
-            hl: Highlighting::default(),
-            screen,
-            bufs: vec![TextBuffer::new()],
-            buf_idx: 0,
-            status_bar: StatusBar::default(),
-        })
+        clipboard: Clipboard,
+    ) -> Result<Editor<I, W>> {
+        Self::open::<&str>(input, output, window_size, clipboard, &[])
     }
 
     pub fn open<P: AsRef<Path>>(
         mut input: I,
         output: W,
         window_size: Option<(usize, usize)>,
+        clipboard: Clipboard,
         paths: &[P],
-    ) -> io::Result<Editor<I, W>> {
-        if paths.is_empty() {
-            return Self::new(input, output, window_size);
+    ) -> Result<Editor<I, W>> {
+        let mut screen = Screen::new(window_size, &mut input, output)?;
+        let options = config::user_config()
+            .map(|c| c.editor.clone())
+            .unwrap_or_default();
+        screen.set_scrolloff(options.scrolloff);
+        screen.set_sidescroll(options.sidescroll);
+        screen.set_invisibles(options.invisibles);
+        screen.set_cursor_line(options.cursor_line);
+        screen.set_indent_guides(options.indent_guides);
+        screen.set_scrollbar(options.scrollbar);
+        screen.set_rulers(&options.ruler);
+        screen.set_ambiguous_width(options.ambiguous_width);
+        let (bufs, hl) = if paths.is_empty() {
+            (vec![TextBuffer::new()], Highlighting::default())
+        } else {
+            // '-' reads text from stdin into an unnamed buffer like `cat foo | kiro -`
+            let bufs: Vec<_> = paths
+                .iter()
+                .map(|p| match p.as_ref().to_str() {
+                    Some("-") => TextBuffer::from_reader(io::stdin()),
+                    _ if options.mmap => TextBuffer::open_mapped(p),
+                    _ => TextBuffer::open(p),
+                })
+                .collect::<Result<_>>()?;
+            let hl = Highlighting::new(bufs[0].lang(), bufs[0].rows());
+            (bufs, hl)
+        };
+        if let Some(config) = config::user_config() {
+            screen.set_theme(&config.theme);
         }
-        let screen = Screen::new(window_size, &mut input, output)?;
-        let bufs: Vec<_> = paths
-            .iter()
-            .map(TextBuffer::open)
-            .collect::<io::Result<_>>()?;
-        let hl = Highlighting::new(bufs[0].lang(), bufs[0].rows());
-        Ok(Editor {
-            input,
+        if let Some(err) = config::user_config_error().or_else(highlight::user_syntax_error) {
+            screen.set_error_message(err.to_string());
+        }
+        let mut editor = Editor {
+            events: Events::new(input),
             quitting: false,
-            finding: FindState::new(),
+            finding: FindState::new(false),
             hl,
             screen,
             bufs,
             buf_idx: 0,
-            status_bar: StatusBar::default(),
-        })
+            status_bar: StatusBar {
+                layout: config::user_config()
+                    .map(|c| c.status.clone())
+                    .unwrap_or_default(),
+                ..Default::default()
+            },
+            clipboard,
+            selecting_by_shift: false,
+            keymap: config::user_config()
+                .map(|c| c.keymap.clone())
+                .unwrap_or_default(),
+            killring: KillRing::default(),
+            last_cmd: None,
+            yanked_at: None,
+            rectangle: vec![],
+            current_edit: None,
+            last_edit: None,
+            inserting: false,
+            vi: if options.vi {
+                Some(Vi::default())
+            } else {
+                None
+            },
+            view: false,
+            options,
+            swapped_at: Instant::now(),
+            state_dir: None,
+            history: History::default(),
+            suspender: None,
+            compile_cmd: None,
+            compiling: false,
+            diagnostics: vec![],
+            diag_idx: None,
+            completion: None,
+            dictionary: None,
+            spell_check: false,
+            suggestion: None,
+            completed: None,
+            literal: false,
+            plugins: Plugins::default(),
+            server: None,
+            waiting: vec![],
+            diff: None,
+        };
+        editor.report_conflicts();
+        if editor.options.spell {
+            editor.enable_spell_check();
+        }
+        let plugins = config::user_config().map(|c| c.plugins.iter());
+        for (name, cmdline) in plugins.into_iter().flatten() {
+            let loaded =
+                plugin::Process::spawn(name, cmdline).and_then(|p| editor.add_plugin(Box::new(p)));
+            if let Err(err) = loaded {
+                editor.screen.set_error_message(err.to_string());
+            }
+        }
+        for idx in 0..editor.bufs.len() {
+            editor.load_rest(idx);
+            editor.watch_file(idx);
+        }
+        Ok(editor)
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // Register commands of the plugin and bind their default keys. Keys configured by user and
+    // keys of other commands are not overridden
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) -> Result<()> {
+        for cmd in self.plugins.add(plugin)? {
+            let keys = cmd.keys.iter().filter_map(|k| keymap::parse_key(k));
+            self.keymap.bind_plugin_default(&cmd.name, keys.collect());
+        }
+        Ok(())
+    }
+
+    // Run the init script and register the commands it defined. Errors are shown in the status bar.
+    // Nothing happens when the script does not exist
+    pub fn load_script(&mut self, path: &Path) {
+        let loaded = match InitScript::load(path) {
+            Ok(Some(script)) => self.add_plugin(Box::new(script)),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = loaded {
+            self.screen.set_error_message(err.to_string());
+        }
+    }
+
+    // Accept requests from other processes such as `kiro --remote` on the Unix domain socket
+    pub fn listen(&mut self, path: &Path) -> Result<()> {
+        let (server, calls) = remote::listen(path)?;
+        self.events.listen(calls);
+        self.server = Some(server);
+        Ok(())
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        if options.vi != self.vi.is_some() {
+            self.vi = if options.vi {
+                Some(Vi::default())
+            } else {
+                None
+            };
+        }
+        let spell = options.spell;
+        self.screen.set_scrolloff(options.scrolloff);
+        self.screen.set_sidescroll(options.sidescroll);
+        self.screen.set_invisibles(options.invisibles);
+        self.screen.set_cursor_line(options.cursor_line);
+        self.screen.set_indent_guides(options.indent_guides);
+        self.screen.set_scrollbar(options.scrollbar);
+        self.screen.set_rulers(&options.ruler);
+        self.screen.set_ambiguous_width(options.ambiguous_width);
+        self.options = options;
+        if spell {
+            self.enable_spell_check();
+        }
+    }
+
+    // Cursor positions saved in the previous sessions are restored. Call this before `jump` so that
+    // the jump takes precedence
+    pub fn set_state_dir(&mut self, dir: Option<PathBuf>) {
+        if let Some(dir) = &dir {
+            self.history = History::load(&dir.join("history"));
+        }
+        self.state_dir = dir;
+        let current = self.buf_idx;
+        for idx in 0..self.bufs.len() {
+            self.restore_position(idx, idx == current);
+        }
+    }
+
+    pub fn set_suspender(&mut self, suspender: Box<dyn FnMut() -> io::Result<()>>) {
+        self.suspender = Some(suspender);
+    }
+
+    // Make all opened buffers read-only. Edits are rejected with message in status bar
+    pub fn set_readonly(&mut self) {
+        for buf in self.bufs.iter_mut() {
+            buf.set_readonly(true);
+        }
+    }
+
+    // Viewer mode where keys behave like a pager. Buffers opened in the mode are also read-only
+    pub fn set_view_mode(&mut self) {
+        self.view = true;
+        self.set_readonly();
+        self.screen
+            .set_info_message("SPACE/b to scroll, / to search, q to quit");
+    }
+
+    // Diff mode comparing the first buffer with the second buffer. They are shown side by side and
+    // the other buffer scrolls along with the current buffer
+    pub fn start_diff(&mut self) {
+        if self.bufs.len() < 2 {
+            self.screen.set_error_message("Diff mode needs two buffers");
+            return;
+        }
+        self.diff = Some(DiffMode {
+            bufs: [0, 1],
+            hunks: vec![],
+            other: 1,
+            other_hl: Highlighting::new(self.bufs[1].lang(), self.bufs[1].rows()),
+        });
+        self.update_diff();
+        let len = self.diff.as_ref().map_or(0, |d| d.hunks.len());
+        self.screen.set_info_message(match len {
+            0 => "No difference".to_string(),
+            1 => "1 hunk".to_string(),
+            n => format!("{} hunks", n),
+        });
+    }
+
+    // Move the cursor in the current buffer before starting editor. Pattern is searched from the
+    // top of the buffer
+    pub fn jump(&mut self, jump: &Jump) {
+        let last = self.buf().rows().len().saturating_sub(1);
+        let (x, y) =
+            match jump {
+                Jump::Line(n) => (0, n.saturating_sub(1).min(last)),
+                Jump::LastLine => (0, last),
+                Jump::Pattern(pat) => {
+                    let matcher = match Matcher::new(pat, true) {
+                        Ok(m) => m,
+                        Err(err) => {
+                            self.screen
+                                .set_error_message(format!("Invalid pattern '{}': {}", pat, err));
+                            return;
+                        }
+                    };
+                    let found =
+                        self.buf().rows().iter().enumerate().find_map(|(y, row)| {
+                            matcher.find_at(row.buffer(), 0).map(|(x, _)| (x, y))
+                        });
+                    match found {
+                        Some(pos) => pos,
+                        None => {
+                            self.screen
+                                .set_error_message(format!("Pattern '{}' not found", pat));
+                            return;
+                        }
+                    }
+                }
+            };
+        self.jump_to(x, y);
     }
 
     fn buf(&self) -> &TextBuffer {
@@ -101,6 +449,16 @@ where
         let buf_pos = (self.buf_idx + 1, self.bufs.len());
         let lang = self.bufs[self.buf_idx].lang();
         let line_pos = (self.buf().cy(), self.buf().rows().len());
+        let col = self.buf().cx();
+        let offset = self.buf().binary_offset();
+        let loading = self.buf().loaded_percent();
+        let follow = self.buf().follow_offset().map(|_| {
+            let buf = self.buf();
+            buf.cy() + 1 >= buf.rows().len()
+        });
+        let encoding = self.buf().encoding().name();
+        let line_ending = self.buf().line_ending();
+        let indent = (self.buf().expand_tab(), self.buf().tab_width());
 
         self.status_bar.redraw = false;
         self.status_bar.set_modified(modified);
@@ -109,374 +467,2938 @@ where
         self.status_bar.set_buf_pos(buf_pos);
         self.status_bar.set_lang(lang);
         self.status_bar.set_line_pos(line_pos);
+        self.status_bar.set_col(col);
+        self.status_bar.set_offset(offset);
+        self.status_bar.set_loading(loading);
+        self.status_bar.set_follow(follow);
+        self.status_bar.set_encoding(encoding);
+        self.status_bar.set_line_ending(line_ending);
+        self.status_bar.set_indent(indent);
     }
 
-    fn refresh_screen(&mut self) -> io::Result<()> {
-        self.refresh_status_bar();
-        self.screen
-            .refresh(&self.bufs[self.buf_idx], &mut self.hl, &self.status_bar)?;
-        Ok(())
+    fn refresh_selection(&mut self) {
+        let buf = &self.bufs[self.buf_idx];
+        let dirty = if let Some((left, right, top, bottom)) = buf.rect_selection() {
+            self.hl.set_rect_selection((left, top), (right, bottom))
+        } else if let Some((start, end)) = buf.selection() {
+            self.hl.set_selection(start, end)
+        } else {
+            self.hl.clear_selection()
+        };
+        if let Some(line) = dirty {
+            self.screen.set_dirty_start(line);
+        }
     }
 
-    fn reset_screen(&mut self) -> io::Result<()> {
-        self.screen.set_dirty_start(0);
-        self.screen.rowoff = 0;
-        self.screen.coloff = 0;
-        self.refresh_screen()
+    // Bracket at the cursor or just before the cursor, and its pair
+    fn bracket_at_cursor(&mut self, bottom: usize) -> Option<((usize, usize), BracketPair)> {
+        let buf = &self.bufs[self.buf_idx];
+        let (cx, cy) = (buf.cx(), buf.cy());
+        self.hl.update(buf.rows(), bottom);
+        [Some(cx), cx.checked_sub(1)]
+            .iter()
+            .flatten()
+            .find_map(|&x| {
+                let pair = self.hl.find_bracket_pair(buf.rows(), x, cy)?;
+                Some(((x, cy), pair))
+            })
     }
 
-    fn open_buffer(&mut self) -> io::Result<()> {
-        if let Some(input) = self.prompt(
-            "Open: {} (Empty name for new text buffer, ^G or ESC to cancel)",
-            |_, _, _, _| Ok(()),
-        )? {
-            let buf = if input.is_empty() {
-                TextBuffer::new()
-            } else {
-                TextBuffer::open(input)?
-            };
-            self.hl = Highlighting::new(buf.lang(), buf.rows());
-            self.bufs.push(buf);
-            self.buf_idx = self.bufs.len() - 1;
-            self.reset_screen()
-        } else {
-            Ok(()) // Canceled
+    fn refresh_brackets(&mut self) {
+        // Highlights until the line at cursor are necessary even if the screen will scroll down
+        let bottom = cmp::max(self.screen.bottom_line(self.buf()), self.buf().cy() + 1);
+        let brackets = match self.bracket_at_cursor(bottom) {
+            Some(((x, y), BracketPair::Matched(px, py))) => {
+                vec![(x, y, Highlight::Bracket), (px, py, Highlight::Bracket)]
+            }
+            Some(((x, y), BracketPair::Unmatched)) => vec![(x, y, Highlight::UnmatchedBracket)],
+            None => vec![],
+        };
+        if let Some(line) = self.hl.set_brackets(brackets) {
+            self.screen.set_dirty_start(line);
         }
     }
 
-    fn switch_buffer(&mut self, idx: usize) -> io::Result<()> {
-        let len = self.bufs.len();
-        if len == 1 {
-            self.screen.set_info_message("No other buffer is opened");
-            return Ok(());
+    fn refresh_carets(&mut self) {
+        let buf = &self.bufs[self.buf_idx];
+        let mut carets = buf.carets().to_vec();
+        // Empty rectangle is shown as a column of cursors where text is inserted
+        if let Some((left, right, top, bottom)) = buf.rect_selection() {
+            if left == right {
+                let rows = cmp::min(bottom + 1, buf.rows().len());
+                let cx = |y: usize| buf.rows()[y].cx_from_rx(left);
+                carets.extend((top..rows).filter(|y| *y != buf.cy()).map(|y| (cx(y), y)));
+            }
+        }
+        if let Some(line) = self.hl.set_carets(carets) {
+            self.screen.set_dirty_start(line);
         }
-
-        debug_assert!(idx < len);
-        self.buf_idx = idx;
-        let buf = self.buf();
-
-        // XXX: Should we put Highlighting instance in TextBuffer rather than Editor?
-        // Then we don't need to recreate Highlighting instance for each buffer switch.
-        self.hl = Highlighting::new(buf.lang(), buf.rows());
-        self.reset_screen()
     }
 
-    fn next_buffer(&mut self) -> io::Result<()> {
-        self.switch_buffer(if self.buf_idx == self.bufs.len() - 1 {
-            0
+    // Highlights by plugins are updated with the whole buffer when lines were modified
+    fn refresh_plugin_highlights(&mut self) {
+        if !self.hl.needs_update {
+            return;
+        }
+        let buf = &self.bufs[self.buf_idx];
+        let spans = if self.plugins.highlights(buf.lang()) {
+            let lines: Vec<_> = buf.lines().collect();
+            match self.plugins.highlight(buf.lang(), &lines) {
+                Ok(spans) => spans.iter().map(|s| (s.y, s.start, s.end, s.hl)).collect(),
+                Err(err) => {
+                    self.screen.set_error_message(err.to_string());
+                    vec![]
+                }
+            }
         } else {
-            self.buf_idx + 1
-        })
+            vec![]
+        };
+        if let Some(line) = self.hl.set_spans(&spans) {
+            self.screen.set_dirty_start(line);
+        }
     }
 
-    fn previous_buffer(&mut self) -> io::Result<()> {
-        self.switch_buffer(if self.buf_idx == 0 {
-            self.bufs.len() - 1
-        } else {
-            self.buf_idx - 1
-        })
+    fn refresh_screen(&mut self) -> Result<()> {
+        // Jumps such as search may move the cursor onto a line hidden by a fold
+        if self.buf_mut().reveal_cursor() {
+            self.sync_buffer_dirty();
+        }
+        self.refresh_plugin_highlights(); // Before highlights are updated for brackets
+        self.refresh_status_bar();
+        self.refresh_selection();
+        self.refresh_brackets();
+        self.refresh_carets();
+        let dict = self.dictionary.as_ref().filter(|_| self.spell_check);
+        self.hl.set_dictionary(dict);
+        self.hl.set_rainbow(self.options.rainbow_brackets);
+        let side = self.diff_side();
+        let diff = match (&mut self.diff, side) {
+            (Some(diff), Some(side)) => {
+                let other = diff.bufs[1 - side];
+                if diff.other != other {
+                    let buf = &self.bufs[other];
+                    diff.other_hl = Highlighting::new(buf.lang(), buf.rows());
+                    diff.other = other;
+                }
+                Some(DiffPane {
+                    buf: &self.bufs[other],
+                    hl: &mut diff.other_hl,
+                    hunks: &diff.hunks,
+                    side,
+                })
+            }
+            _ => None,
+        };
+        self.screen.refresh(
+            &self.bufs[self.buf_idx],
+            &mut self.hl,
+            diff,
+            &self.status_bar,
+        )?;
+        Ok(())
     }
 
-    fn save(&mut self) -> io::Result<()> {
-        let mut create = false;
-        if !self.buf().has_file() {
-            if let Some(input) =
-                self.prompt("Save as: {} (^G or ESC to cancel)", |_, _, _, _| Ok(()))?
-            {
-                if input.is_empty() {}
-                let prev_lang = self.buf().lang();
-                self.buf_mut().set_file(input);
-                self.hl.lang_changed(self.buf().lang());
-                if prev_lang != self.buf().lang() {
-                    // Render entire screen since highglight updated
-                    self.screen.set_dirty_start(self.screen.rowoff);
-                }
-                create = true;
+    // Terminal is restored to the state before starting the editor while suspended
+    fn suspend(&mut self) -> Result<()> {
+        let suspend = match &mut self.suspender {
+            Some(suspend) => suspend,
+            None => {
+                self.screen
+                    .set_error_message("Suspend is not supported on this terminal");
+                return Ok(());
             }
+        };
+        self.screen.suspend()?;
+        let result = suspend();
+        self.screen.resume()?;
+        self.status_bar.redraw = true;
+        Ok(result?)
+    }
+
+    // Insert output of shell command at cursor. When `filter` is true, selected text is passed to
+    // stdin of the command and replaced with the output like `:'<,'>!sort` in Vim
+    fn shell_command(&mut self, filter: bool) -> Result<()> {
+        if filter && !self.buf().has_selection() {
+            self.screen.set_error_message("No text is selected");
+            return Ok(());
         }
+        let prompt = if filter {
+            "Filter with command: {} (^G or ESC to cancel)"
+        } else {
+            "Shell command: {} (^G or ESC to cancel)"
+        };
+        let cmdline = match self.prompt(prompt, |_, _, _, _| Ok(()))? {
+            Some(cmdline) if !cmdline.trim().is_empty() => cmdline,
+            _ => return Ok(()),
+        };
 
-        match self.buf_mut().save() {
-            Ok(msg) => self.screen.set_info_message(msg),
-            Err(msg) => {
-                self.screen.set_error_message(msg);
-                if create {
-                    self.buf_mut().set_unnamed();
-                }
+        let input = if filter {
+            Some(self.buf().selected_text())
+        } else {
+            None
+        };
+        let output = match shell::run(&cmdline, input.as_deref()) {
+            Ok(output) => output,
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                return Ok(());
             }
+        };
+        // Most commands end output with newline. It is kept only when the input also ends with it
+        let mut output = output.as_str();
+        if !input.as_ref().is_some_and(|i| i.ends_with('\n')) {
+            output = output.strip_suffix('\n').unwrap_or(output);
         }
-
+        if filter {
+            self.buf_mut().delete_selection();
+        }
+        self.buf_mut().insert_text(output);
         Ok(())
     }
 
-    fn on_incremental_find(&mut self, query: &str, seq: InputSeq, end: bool) -> io::Result<()> {
-        use KeySeq::*;
+    // Formatter command in config or the default formatter of the language
+    fn formatter(&self) -> Option<String> {
+        let lang = self.buf().lang();
+        self.options
+            .format
+            .get(lang.name())
+            .cloned()
+            .or_else(|| format::default_command(lang).map(String::from))
+    }
 
-        if self.finding.last_match.is_some() {
-            if let Some(matched_line) = self.hl.clear_previous_match() {
-                self.hl.needs_update = true;
-                self.screen.set_dirty_start(matched_line);
+    // Pipe the buffer through the formatter and replace only the changed lines so that the cursor
+    // and undo history are kept
+    fn format_buffer(&mut self) {
+        let cmdline = match self.formatter() {
+            Some(cmdline) => cmdline,
+            None => {
+                let msg = format!("No formatter for {}", self.buf().lang().name());
+                self.screen.set_error_message(msg);
+                return;
             }
+        };
+        let mut text = String::new();
+        for line in self.buf().lines() {
+            text.push_str(line);
+            text.push('\n');
         }
-
-        if end {
-            return Ok(());
+        let output = match shell::run(&cmdline, Some(&text)) {
+            Ok(output) => output,
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                return;
+            }
+        };
+        if self.buf_mut().replace_lines(&format::output_lines(&output)) {
+            self.screen
+                .set_info_message(format!("Formatted with '{}'", cmdline));
+        } else {
+            self.screen.set_info_message("Already formatted");
         }
+    }
 
-        match (seq.key, seq.ctrl) {
-            (RightKey, ..) | (DownKey, ..) | (Key(b'f'), true) | (Key(b'n'), true) => {
-                self.finding.dir = FindDir::Forward
+    // Show text in a read-only buffer. The buffer with the same name is reused
+    fn show_output_buffer(&mut self, name: &str, text: &str) -> Result<()> {
+        let buf = TextBuffer::scratch(name, text);
+        let idx = self
+            .bufs
+            .iter()
+            .position(|b| !b.has_file() && b.filename() == name);
+        match idx {
+            Some(idx) if idx == self.buf_idx => {
+                self.bufs[idx] = buf;
+                self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+                self.reset_screen()
             }
-            (LeftKey, ..) | (UpKey, ..) | (Key(b'b'), true) | (Key(b'p'), true) => {
-                self.finding.dir = FindDir::Back
+            Some(idx) => {
+                self.bufs[idx] = buf;
+                self.switch_buffer(idx)
             }
-            _ => self.finding = FindState::new(),
+            None => self.push_buffer(buf),
         }
+    }
 
-        fn next_line(y: usize, dir: FindDir, len: usize) -> usize {
-            // Wrapping text search at top/bottom of text buffer
-            match dir {
-                FindDir::Forward if y == len - 1 => 0,
-                FindDir::Forward => y + 1,
-                FindDir::Back if y == 0 => len - 1,
-                FindDir::Back => y - 1,
-            }
+    // Messages shown in message bar are listed from the oldest one
+    fn show_messages(&mut self) -> Result<()> {
+        let log = self.screen.message_log();
+        if log.is_empty() {
+            self.screen.set_info_message("No message");
+            return Ok(());
         }
+        let text = log.text();
+        self.show_output_buffer("*messages*", &text)?;
+        let bottom = self.buf().rows().len().saturating_sub(1);
+        self.buf_mut().set_cursor(0, bottom);
+        Ok(())
+    }
 
-        let row_len = self.buf().rows().len();
-        let dir = self.finding.dir;
-        let mut y = self
-            .finding
-            .last_match
-            .map(|y| next_line(y, dir, row_len)) // Start from next line on moving to next match
-            .unwrap_or_else(|| self.buf().cy());
-
-        // TODO: Use more efficient string search algorithm such as Aho-Corasick
-        for _ in 0..row_len {
-            let row = &self.buf().rows()[y];
-            if let Some(byte_idx) = row.buffer().find(query) {
-                let idx = row.char_idx_of(byte_idx);
-                self.buf_mut().set_cursor(idx, y);
-
-                let row = &self.buf().rows()[y]; // Immutable borrow again since self.buf().set_cursor() yields mutable borrow
-                let rx = row.rx_from_cx(self.buf().cx());
-                // Cause do_scroll() to scroll upwards to the matching line at next screen redraw
-                self.screen.rowoff = row_len;
-                self.finding.last_match = Some(y);
-                // Set match highlight on the found line
-                self.hl.set_match(y, rx, rx + query.chars().count());
-                // XXX: It updates entire highlights
-                self.hl.needs_update = true;
-                self.screen.set_dirty_start(y);
-                break;
-            }
-            y = next_line(y, dir, row_len);
+    // Run build command in background and show its output when it finishes. Empty input runs the
+    // previous command, the command in config or the default command of the language in this order
+    fn compile(&mut self) -> Result<()> {
+        if self.compiling {
+            self.screen
+                .set_error_message("Build command is already running");
+            return Ok(());
         }
+        let default = self
+            .compile_cmd
+            .clone()
+            .or_else(|| self.options.compile.clone())
+            .or_else(|| compile::default_command(self.buf().lang()).map(String::from));
+        let prompt = match &default {
+            Some(cmd) => format!("Compile: {{}} (Empty for '{}', ^G or ESC to cancel)", cmd),
+            None => "Compile: {} (^G or ESC to cancel)".to_string(),
+        };
+        let cmdline = match (self.prompt(prompt, |_, _, _, _| Ok(()))?, default) {
+            (Some(input), _) if !input.trim().is_empty() => input,
+            (Some(_), Some(cmd)) => cmd,
+            _ => return Ok(()),
+        };
 
+        self.screen
+            .set_info_message(format!("Running '{}'...", cmdline));
+        self.compiling = true;
+        self.compile_cmd = Some(cmdline.clone());
+        self.events.spawn(move || {
+            let result = shell::run_with_status(&cmdline);
+            TaskResult::Compile(cmdline, result)
+        });
         Ok(())
     }
 
-    fn find(&mut self) -> io::Result<()> {
-        let (cx, cy, coloff, rowoff) = (
-            self.buf().cx(),
-            self.buf().cy(),
-            self.screen.coloff,
-            self.screen.rowoff,
+    fn on_compile_done(&mut self, cmdline: &str, result: Result<(String, bool)>) -> Result<()> {
+        self.compiling = false;
+        let (output, ok) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                return Ok(());
+            }
+        };
+        self.diagnostics = compile::parse(&output);
+        self.diag_idx = None;
+        self.show_output_buffer(compile::OUTPUT_BUFFER, &output)?;
+
+        let msg = format!(
+            "'{}' {}. {} locations found",
+            cmdline,
+            if ok { "succeeded" } else { "failed" },
+            self.diagnostics.len(),
         );
-        let s = "Search: {} (^F or RIGHT to forward, ^B or LEFT to back, ^G or ESC to cancel)";
-        let input = self.prompt(s, Self::on_incremental_find)?;
-        if input.as_ref().map(String::is_empty).unwrap_or(true) {
-            // Canceled. Restore cursor position
-            self.buf_mut().set_cursor(cx, cy);
-            self.screen.coloff = coloff;
-            self.screen.rowoff = rowoff;
-            self.screen.set_dirty_start(self.screen.rowoff); // Redraw all lines
-        } else if self.finding.last_match.is_some() {
-            self.screen.set_info_message("Found");
+        if ok {
+            self.screen.set_info_message(msg);
         } else {
-            self.screen.set_error_message("Not Found");
+            self.screen.set_error_message(msg);
         }
-
-        self.finding = FindState::new(); // Clear text search state for next time
         Ok(())
     }
 
-    fn show_help(&mut self) -> io::Result<()> {
-        self.screen.draw_help()?;
-
-        // Consume any key
-        while let Some(seq) = self.input.next() {
-            if self.screen.maybe_resize(&mut self.input)? {
-                // XXX: Status bar is not redrawn
-                self.screen.draw_help()?;
-            }
-            if seq?.key != KeySeq::Unidentified {
-                break;
+    fn jump_to_error(&mut self, forward: bool) -> Result<()> {
+        // Errors of the running build are not known until it finishes
+        while self.compiling {
+            match self.events.wait_task() {
+                Some(result) => self.on_task_done(result)?,
+                None => break,
             }
         }
+        let len = self.diagnostics.len();
+        if len == 0 {
+            self.screen.set_info_message("No error location of build");
+            return Ok(());
+        }
+        let idx = match (self.diag_idx, forward) {
+            (None, true) => 0,
+            (None, false) => len - 1,
+            (Some(i), true) if i + 1 < len => i + 1,
+            (Some(i), false) if i > 0 => i - 1,
+            _ => {
+                self.screen.set_info_message("No more errors");
+                return Ok(());
+            }
+        };
+        self.diag_idx = Some(idx);
 
-        // Redraw screen
-        self.screen.set_dirty_start(self.screen.rowoff);
+        let diag = self.diagnostics[idx].clone();
+        if self.open_location(&diag.file, diag.line, diag.col)? {
+            self.screen
+                .set_info_message(format!("[{}/{}] {}", idx + 1, len, diag.message));
+        }
         Ok(())
     }
 
-    fn prompt<S, F>(&mut self, prompt: S, mut incremental_callback: F) -> io::Result<Option<String>>
-    where
-        S: AsRef<str>,
-        F: FnMut(&mut Self, &str, InputSeq, bool) -> io::Result<()>,
-    {
-        let mut buf = String::new();
-        let mut canceled = false;
-        let prompt = prompt.as_ref();
-        self.screen.set_info_message(prompt.replacen("{}", "", 1));
-        self.refresh_screen()?;
-
-        while let Some(seq) = self.input.next() {
-            use KeySeq::*;
-
-            if self.screen.maybe_resize(&mut self.input)? {
-                self.refresh_screen()?;
-            }
+    // Open the file with the cursor at 1-based line and column. Returns false when it does not exist
+    fn open_location(&mut self, file: &str, line: usize, col: usize) -> Result<bool> {
+        if !Path::new(file).is_file() {
+            self.screen
+                .set_error_message(format!("File '{}' not found", file));
+            return Ok(false);
+        }
+        self.open_path(file)?;
+        let rows = self.buf().rows();
+        let y = cmp::min(line.saturating_sub(1), rows.len().saturating_sub(1));
+        let x = rows
+            .get(y)
+            .map_or(0, |r| cmp::min(col.saturating_sub(1), r.len()));
+        self.jump_to(x, y);
+        Ok(true)
+    }
 
-            let seq = seq?;
-            let mut finished = false;
+    fn fold(&mut self, cmd: Command) {
+        let buf = self.buf_mut();
+        let (done, msg) = match cmd {
+            Command::Fold => (buf.close_fold(), "No block to fold"),
+            Command::Unfold => (buf.open_fold(), "No fold at cursor"),
+            Command::ToggleFold => (buf.toggle_fold(), "No block to fold"),
+            Command::FoldAll => (buf.close_all_folds() > 0, "No block to fold"),
+            Command::UnfoldAll => (buf.open_all_folds(), "No fold in buffer"),
+            _ => unreachable!(),
+        };
+        if !done {
+            self.screen.set_info_message(msg);
+        }
+    }
 
-            match (&seq.key, seq.ctrl) {
-                (Unidentified, ..) => continue,
-                (Key(b'h'), true) | (Key(0x7f), ..) | (DeleteKey, ..) if !buf.is_empty() => {
-                    buf.pop();
-                }
-                (Key(b'g'), true) | (Key(b'q'), true) | (Key(0x1b), ..) => {
-                    finished = true;
-                    canceled = true;
-                }
-                (Key(b'\r'), ..) | (Key(b'm'), true) => {
-                    finished = true;
+    // Open a location such as 'src/main.rs:12:5' in an error message. The location in the clipboard
+    // or in the current line is opened when the input is empty
+    fn quick_open(&mut self) -> Result<()> {
+        let default = compile::find_location(self.clipboard.paste()).or_else(|| {
+            let buf = self.buf();
+            buf.rows()
+                .get(buf.cy())
+                .and_then(|row| compile::find_location(row.buffer()))
+        });
+        let prompt = match &default {
+            Some((file, line, col)) => format!(
+                "Open location: {{}} (Empty for '{}:{}:{}', ^G or ESC to cancel)",
+                file, line, col,
+            ),
+            None => "Open location: {} (^G or ESC to cancel)".to_string(),
+        };
+        let location = match (self.prompt(prompt, |_, _, _, _| Ok(()))?, default) {
+            (Some(input), _) if !input.trim().is_empty() => match compile::find_location(&input) {
+                Some(loc) => loc,
+                None => {
+                    let msg = format!("No location such as 'file:line' in '{}'", input);
+                    self.screen.set_error_message(msg);
+                    return Ok(());
                 }
-                (Key(b), false) => buf.push(*b as char),
-                (Utf8Key(c), false) => buf.push(*c),
-                _ => {}
-            }
-
-            incremental_callback(self, buf.as_str(), seq, finished)?;
-            if finished {
-                break;
-            }
-            self.screen.set_info_message(prompt.replacen("{}", &buf, 1));
-            self.refresh_screen()?;
+            },
+            (Some(_), Some(loc)) => loc,
+            _ => return Ok(()),
+        };
+        let (file, line, col) = location;
+        if self.open_location(&file, line, col)? {
+            self.screen
+                .set_info_message(format!("Opened {}:{}:{}", file, line, col));
         }
-
-        self.screen
-            .set_info_message(if canceled { "Canceled" } else { "" });
-        self.refresh_screen()?;
-
-        Ok(if canceled { None } else { Some(buf) })
+        Ok(())
     }
 
-    fn handle_quit(&mut self) -> io::Result<bool> {
-        let modified = self.bufs.iter().any(|b| b.modified());
-        if !modified || self.quitting {
-            Ok(true)
-        } else {
-            self.quitting = true;
-            self.screen.set_error_message(
-                "At least one file has unsaved changes! Press ^Q again to quit or ^S to save",
-            );
-            Ok(false)
+    // Blame of the current line. Unsaved edits are passed to git so that the line is not shifted
+    fn blame_current_line(&self) -> Result<Blame> {
+        let buf = self.buf();
+        if !buf.has_file() || buf.is_directory() {
+            return Err(Error::other("Buffer has no file"));
+        }
+        let mut contents = String::new();
+        for line in buf.lines() {
+            contents.push_str(line);
+            contents.push('\n');
         }
+        vcs::blame(Path::new(buf.filename()), buf.cy() + 1, &contents)
     }
 
-    fn handle_not_mapped(&mut self, seq: InputSeq) {
-        self.screen
+    // Show blame of the current line in message bar. When `show_commit` is true, the commit which
+    // last changed the line is opened in a read-only buffer instead
+    fn blame(&mut self, show_commit: bool) -> Result<()> {
+        let path = PathBuf::from(self.buf().filename());
+        let blame = match self.blame_current_line() {
+            Ok(blame) => blame,
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                return Ok(());
+            }
+        };
+        if !blame.is_committed() {
+            self.screen.set_info_message("Not committed yet");
+            return Ok(());
+        }
+        if !show_commit {
+            self.screen.set_info_message(format!(
+                "{} {} {} {}",
+                blame.short_commit(),
+                blame.author,
+                blame.date,
+                blame.summary,
+            ));
+            return Ok(());
+        }
+        match vcs::show_commit(&path, &blame.commit) {
+            Ok(text) => {
+                let name = format!("*commit {}*", blame.short_commit());
+                self.show_output_buffer(&name, &text)?;
+                self.screen.set_info_message(blame.summary);
+            }
+            Err(err) => self.screen.set_error_message(err.to_string()),
+        }
+        Ok(())
+    }
+
+    // Dictionary is loaded on enabling spell checking first time. Returns false when not found
+    fn enable_spell_check(&mut self) -> bool {
+        if self.dictionary.is_none() {
+            let loaded = match &self.options.dictionary {
+                Some(path) => Dictionary::load(path),
+                None => Dictionary::find(),
+            };
+            match loaded {
+                Ok(dict) => self.dictionary = Some(Rc::new(dict)),
+                Err(err) => {
+                    self.screen.set_error_message(err.to_string());
+                    return false;
+                }
+            }
+        }
+        self.spell_check = true;
+        true
+    }
+
+    fn toggle_spell_check(&mut self) {
+        if self.spell_check {
+            self.spell_check = false;
+            self.screen.set_info_message("Spell checking disabled");
+        } else if self.enable_spell_check() {
+            self.screen.set_info_message("Spell checking enabled");
+        }
+    }
+
+    fn toggle_invisibles(&mut self) {
+        let enabled = !self.screen.invisibles();
+        self.screen.set_invisibles(enabled);
+        self.screen.set_info_message(if enabled {
+            "Invisible characters shown"
+        } else {
+            "Invisible characters hidden"
+        });
+    }
+
+    fn toggle_indent_guides(&mut self) {
+        let enabled = !self.screen.indent_guides();
+        self.screen.set_indent_guides(enabled);
+        self.screen.set_info_message(if enabled {
+            "Indent guides shown"
+        } else {
+            "Indent guides hidden"
+        });
+    }
+
+    fn toggle_rainbow_brackets(&mut self) {
+        let enabled = !self.options.rainbow_brackets;
+        self.options.rainbow_brackets = enabled;
+        self.screen.set_info_message(if enabled {
+            "Rainbow brackets enabled"
+        } else {
+            "Rainbow brackets disabled"
+        });
+    }
+
+    // Replace the misspelled word at cursor with the first suggestion. Repeating this command
+    // replaces it with the next suggestion and finally with the original word
+    fn spell_suggest(&mut self, prev_cmd: Option<Command>) {
+        let dict = match self.dictionary.as_ref().filter(|_| self.spell_check) {
+            Some(dict) => dict.clone(),
+            None => {
+                self.screen
+                    .set_error_message("Spell checking is not enabled");
+                return;
+            }
+        };
+        let (cx, cy) = (self.buf().cx(), self.buf().cy());
+        let repeated = self.suggestion.take().filter(|s| {
+            let end = s.start + s.candidates[s.idx].chars().count();
+            prev_cmd == Some(Command::SpellSuggest) && s.y == cy && end == cx
+        });
+        let mut suggestion = match repeated {
+            Some(s) => s,
+            None => {
+                let line = self.buf().rows().get(cy).map_or("", |r| r.buffer());
+                let (start, end) = match spell::word_at(line, cx) {
+                    Some(range) => range,
+                    None => {
+                        self.screen.set_info_message("No word at cursor");
+                        return;
+                    }
+                };
+                let word: String = line.chars().skip(start).take(end - start).collect();
+                if dict.check(&word) {
+                    self.screen
+                        .set_info_message(format!("'{}' is spelled correctly", word));
+                    return;
+                }
+                let mut candidates = dict.suggest(&word);
+                if candidates.is_empty() {
+                    self.screen
+                        .set_info_message(format!("No suggestion for '{}'", word));
+                    return;
+                }
+                candidates.insert(0, word);
+                Suggestion {
+                    y: cy,
+                    start,
+                    candidates,
+                    idx: 0,
+                }
+            }
+        };
+
+        self.replace_with_next(&mut suggestion, "suggestion");
+        self.suggestion = Some(suggestion);
+    }
+
+    // Replace the text at cursor with the next candidate
+    fn replace_with_next(&mut self, suggestion: &mut Suggestion, what: &str) {
+        let end = suggestion.start + suggestion.candidates[suggestion.idx].chars().count();
+        suggestion.idx = (suggestion.idx + 1) % suggestion.candidates.len();
+        let next = suggestion.candidates[suggestion.idx].clone();
+        self.buf_mut()
+            .replace_range(suggestion.start, suggestion.y, end, &next);
+        if suggestion.idx == 0 {
+            self.screen.set_info_message(format!("Back to '{}'", next));
+        } else {
+            self.screen.set_info_message(format!(
+                "[{}/{}] {} (Repeat for next {})",
+                suggestion.idx,
+                suggestion.candidates.len() - 1,
+                next,
+                what,
+            ));
+        }
+    }
+
+    // Complete the word or the path before the cursor with the first candidate. Repeating this
+    // command replaces it with the next candidate and finally with the original text
+    fn complete(&mut self, prev_cmd: Option<Command>) {
+        let (cx, cy) = (self.buf().cx(), self.buf().cy());
+        let repeated = self.completed.take().filter(|s| {
+            let end = s.start + s.candidates[s.idx].chars().count();
+            prev_cmd == Some(Command::Complete) && s.y == cy && end == cx
+        });
+        let mut completed = match repeated {
+            Some(s) => s,
+            None => {
+                let line = self
+                    .buf()
+                    .rows()
+                    .get(cy)
+                    .map_or(String::new(), |r| r.buffer().to_string());
+                let (start, text, mut candidates) =
+                    if let Some((start, path)) = completion::path_at(&line, cx) {
+                        let mut found = prompt::path_candidates(&path);
+                        found.retain(|p| *p != path);
+                        (start, path, found)
+                    } else if let Some((start, prefix)) = completion::prefix_at(&line, cx) {
+                        let found = self.word_candidates(&prefix);
+                        (start, prefix, found)
+                    } else {
+                        self.screen.set_info_message("No word to complete");
+                        return;
+                    };
+                if candidates.is_empty() {
+                    self.screen
+                        .set_info_message(format!("No completion for '{}'", text));
+                    return;
+                }
+                candidates.insert(0, text);
+                Suggestion {
+                    y: cy,
+                    start,
+                    candidates,
+                    idx: 0,
+                }
+            }
+        };
+
+        self.replace_with_next(&mut completed, "candidate");
+        self.completed = Some(completed);
+    }
+
+    // Words starting with the prefix in the current buffer nearer to the cursor first, then words
+    // in other buffers
+    fn word_candidates(&mut self, prefix: &str) -> Vec<String> {
+        let cy = self.buf().cy();
+        let mut words: Vec<String> = self
+            .buf_mut()
+            .words()
+            .starting_with(prefix, Some(cy))
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let current = self.buf_idx;
+        for (i, buf) in self.bufs.iter_mut().enumerate() {
+            if i == current {
+                continue;
+            }
+            for word in buf.words().starting_with(prefix, None) {
+                if !words.iter().any(|w| w == word) {
+                    words.push(word.to_string());
+                }
+            }
+        }
+        words
+    }
+
+    fn reset_screen(&mut self) -> Result<()> {
+        self.screen.set_dirty_start(0);
+        self.screen.rowoff = 0;
+        self.screen.coloff = 0;
+        self.refresh_screen()
+    }
+
+    fn open_buffer(&mut self) -> Result<()> {
+        if let Some(input) = self.prompt_with_completion(
+            "Open: {} (Empty name for new text buffer, TAB to complete, ^G or ESC to cancel)",
+            |_, _, _, _| Ok(()),
+            prompt::path_candidates,
+        )? {
+            if input.is_empty() {
+                self.push_buffer(TextBuffer::new())
+            } else {
+                self.open_path(input)
+            }
+        } else {
+            Ok(()) // Canceled
+        }
+    }
+
+    fn open_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        if let Some(idx) = self.bufs.iter().position(|b| b.is_file(&path)) {
+            // Do not open the same file twice
+            return self.switch_buffer(idx);
+        }
+        let buf = self.open_file(path.as_ref())?;
+        self.push_buffer(buf)?;
+        self.load_rest(self.buf_idx);
+        self.watch_file(self.buf_idx);
+        self.load_marks(self.buf_idx);
+        self.restore_position(self.buf_idx, true);
+        self.recover_swap()
+    }
+
+    fn open_file(&self, path: &Path) -> Result<TextBuffer> {
+        if self.options.mmap {
+            TextBuffer::open_mapped(path)
+        } else {
+            TextBuffer::open(path)
+        }
+    }
+
+    // Fuzzy find a file under the current directory
+    fn find_file(&mut self) -> Result<()> {
+        let files = finder::walk(".");
+        if files.is_empty() {
+            self.screen.set_info_message("No file found");
+            return Ok(());
+        }
+        self.pick_file("Find file", files)
+    }
+
+    // Pick one of files edited in previous sessions. Files under the current directory are shown
+    // with relative paths
+    fn recent_files(&mut self) -> Result<()> {
+        let session = match self.session_file() {
+            Some(state) => Session::load(&state),
+            None => Session::default(),
+        };
+        let cwd = env::current_dir().unwrap_or_default();
+        let files: Vec<_> = session
+            .recent_files()
+            .into_iter()
+            .filter(|f| Path::new(f).is_file() && !self.bufs.iter().any(|b| b.is_file(f)))
+            .map(|f| match Path::new(&f).strip_prefix(&cwd) {
+                Ok(rel) => rel.to_string_lossy().into_owned(),
+                Err(_) => f,
+            })
+            .collect();
+        if files.is_empty() {
+            self.screen.set_info_message("No recent file");
+            return Ok(());
+        }
+        self.pick_file("Recent file", files)
+    }
+
+    fn pick_file(&mut self, label: &str, files: Vec<String>) -> Result<()> {
+        match self.pick(label, files)? {
+            Some((_, Some(path))) => self.open_path(path),
+            Some((input, None)) => {
+                self.screen
+                    .set_error_message(format!("No file matches '{}'", input));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Items matching the input by fuzzy matching are listed over text area and UP/DOWN keys select
+    // one of them. PAGE-UP/PAGE-DOWN move the selection by a page. Returns the input and the
+    // selected item, which is None when no item matches the input. None is returned when canceled
+    fn pick(
+        &mut self,
+        label: &str,
+        items: Vec<String>,
+    ) -> Result<Option<(String, Option<String>)>> {
+        const PICK_KEYS: &str = "(UP/DOWN to select, ^G or ESC to cancel)";
+        let show = |this: &mut Self, picker: &mut Picker, input: &str| {
+            let (lines, selected) = picker.page(this.screen.rows());
+            this.screen.set_overlay(lines, selected);
+            this.screen.set_prompt_message(format!(
+                "{}: {} [{}/{}] {}",
+                label,
+                input,
+                picker.position(),
+                picker.len(),
+                PICK_KEYS,
+            ));
+        };
+
+        let mut picker = Picker::new(items);
+        let (lines, selected) = picker.page(self.screen.rows());
+        self.screen.set_overlay(lines, selected);
+        let prompt = format!(
+            "{}: {{}} [{}/{}] {}",
+            label,
+            picker.position(),
+            picker.len(),
+            PICK_KEYS
+        );
+        let input = self.prompt(prompt, |this, input, seq, end| {
+            use KeySeq::*;
+            if end {
+                return Ok(());
+            }
+            let page = this.screen.rows() as isize;
+            match (seq.key, seq.ctrl, seq.alt) {
+                (UpKey, false, false) | (Key(b'p'), true, false) => picker.move_selection(1),
+                (DownKey, false, false) | (Key(b'n'), true, false) => picker.move_selection(-1),
+                (PageUpKey, ..) | (Key(b'v'), false, true) => picker.move_selection(page),
+                (PageDownKey, ..) | (Key(b'v'), true, false) => picker.move_selection(-page),
+                _ => picker.filter(input),
+            }
+            show(this, &mut picker, input);
+            Ok(())
+        })?;
+        self.screen.clear_overlay();
+
+        Ok(input.map(|input| {
+            picker.filter(&input);
+            let selected = picker.selected().map(|i| picker.item(i).to_string());
+            (input, selected)
+        }))
+    }
+
+    fn push_buffer(&mut self, mut buf: TextBuffer) -> Result<()> {
+        if self.view {
+            buf.set_readonly(true);
+        }
+        self.record_jump();
+        self.hl = Highlighting::new(buf.lang(), buf.rows());
+        self.bufs.push(buf);
+        self.buf_idx = self.bufs.len() - 1;
+        self.report_conflicts();
+        self.reset_screen()
+    }
+
+    // Open the entry at the cursor in directory listing. Directory is opened in the same buffer
+    // so that moving around directories does not leave many buffers
+    fn open_dir_entry(&mut self) -> Result<()> {
+        let path = match self.buf().dir_entry() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if !path.is_dir() {
+            return self.open_path(path);
+        }
+        let mut buf = match TextBuffer::open(&path) {
+            Ok(buf) => buf,
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                return Ok(());
+            }
+        };
+        // Put cursor on the directory where we came from when going up
+        let prev = Path::new(self.buf().filename());
+        if prev.parent() == Some(&path) {
+            let name = format!(
+                "{}/",
+                prev.file_name().unwrap_or_default().to_string_lossy()
+            );
+            if let Some(y) = buf.lines().position(|l| l == name) {
+                buf.set_cursor(0, y);
+            }
+        }
+        self.hl = Highlighting::new(buf.lang(), buf.rows());
+        self.bufs[self.buf_idx] = buf;
+        self.reset_screen()
+    }
+
+    fn switch_buffer(&mut self, idx: usize) -> Result<()> {
+        let len = self.bufs.len();
+        if len == 1 {
+            self.screen.set_info_message("No other buffer is opened");
+            return Ok(());
+        }
+
+        debug_assert!(idx < len);
+        self.record_jump();
+        // The cursor and the scroll move to the corresponding lines when switching sides of diff
+        let synced = match (&self.diff, self.diff_side()) {
+            (Some(diff), Some(side)) if diff.bufs[1 - side] == idx => {
+                let y = diff::corresponding_line(&diff.hunks, side, self.buf().cy());
+                let rowoff = diff::corresponding_line(&diff.hunks, side, self.screen.rowoff);
+                Some((y, rowoff))
+            }
+            _ => None,
+        };
+        self.buf_idx = idx;
+        if let Some((y, rowoff)) = synced {
+            let y = cmp::min(y, self.buf().rows().len());
+            self.buf_mut().set_cursor(0, y);
+            self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+            self.screen.rowoff = cmp::min(rowoff, y);
+            self.screen.set_dirty_start(0);
+            return self.refresh_screen();
+        }
+        let buf = self.buf();
+
+        // XXX: Should we put Highlighting instance in TextBuffer rather than Editor?
+        // Then we don't need to recreate Highlighting instance for each buffer switch.
+        self.hl = Highlighting::new(buf.lang(), buf.rows());
+        self.reset_screen()
+    }
+
+    // Find buffer by its 1-based index or its file name. Part of file name is also accepted when
+    // it identifies exactly one buffer.
+    fn find_buffer(&self, name: &str) -> Option<usize> {
+        if let Ok(n) = name.parse::<usize>() {
+            if 0 < n && n <= self.bufs.len() {
+                return Some(n - 1);
+            }
+        }
+        if let Some(idx) = self.bufs.iter().position(|b| b.filename() == name) {
+            return Some(idx);
+        }
+        let mut candidates = self
+            .bufs
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.filename().contains(name))
+            .map(|(i, _)| i);
+        match (candidates.next(), candidates.next()) {
+            (Some(idx), None) => Some(idx),
+            _ => None,
+        }
+    }
+
+    fn select_buffer(&mut self) -> Result<()> {
+        let names = self
+            .bufs
+            .iter()
+            .enumerate()
+            .map(|(i, b)| format!("{}:{}", i + 1, b.filename()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let prompt = format!("Buffer: {{}} ({}) ^G or ESC to cancel", names);
+        if let Some(input) = self.prompt(prompt, |_, _, _, _| Ok(()))? {
+            if input.is_empty() {
+                return Ok(());
+            }
+            match self.find_buffer(&input) {
+                Some(idx) if idx == self.buf_idx => {}
+                Some(idx) => self.switch_buffer(idx)?,
+                None => self
+                    .screen
+                    .set_error_message(format!("No buffer matches '{}'", input)),
+            }
+        }
+        Ok(())
+    }
+
+    fn next_buffer(&mut self) -> Result<()> {
+        self.switch_buffer(if self.buf_idx == self.bufs.len() - 1 {
+            0
+        } else {
+            self.buf_idx + 1
+        })
+    }
+
+    fn previous_buffer(&mut self) -> Result<()> {
+        self.switch_buffer(if self.buf_idx == 0 {
+            self.bufs.len() - 1
+        } else {
+            self.buf_idx - 1
+        })
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let mut create = false;
+        if !self.buf().has_file() {
+            if let Some(input) =
+                self.prompt("Save as: {} (^G or ESC to cancel)", |_, _, _, _| Ok(()))?
+            {
+                let prev_lang = self.buf().lang();
+                self.buf_mut().set_file(input);
+                self.hl.lang_changed(self.buf().lang());
+                if prev_lang != self.buf().lang() {
+                    // Render entire screen since highglight updated
+                    self.screen.set_dirty_start(self.screen.rowoff);
+                }
+                create = true;
+            }
+        }
+
+        // Buffer is saved even if formatting failed so that the edits are not lost
+        if self.options.format_on_save && !self.buf().readonly() && self.formatter().is_some() {
+            self.format_buffer();
+        }
+
+        let backup = self.options.backup;
+        match self.buf_mut().save(backup) {
+            Ok(msg) => {
+                self.screen.set_info_message(msg);
+                self.reply_saved();
+                self.watch_file(self.buf_idx);
+                self.buf_mut().reload_vcs();
+                self.screen.set_dirty_start(self.screen.rowoff);
+            }
+            Err(err) => {
+                self.screen.set_error_message(err.to_string());
+                if create {
+                    self.buf_mut().set_unnamed();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_incremental_find(&mut self, query: &str, seq: InputSeq, end: bool) -> Result<()> {
+        use KeySeq::*;
+
+        if self.finding.last_match.is_some() {
+            if let Some(matched_line) = self.hl.clear_previous_match() {
+                self.hl.needs_update = true;
+                self.screen.set_dirty_start(matched_line);
+            }
+        }
+
+        if end {
+            self.set_other_matches(&[]);
+            return Ok(());
+        }
+
+        // Alt-Up and Alt-Down recall history, which changes the query
+        match (seq.key, seq.ctrl, seq.alt) {
+            (DownKey, _, false) | (Key(b'n'), true, _) => self.finding.dir = FindDir::Forward,
+            (UpKey, _, false) | (Key(b'p'), true, _) => self.finding.dir = FindDir::Back,
+            _ => self.finding = FindState::new(self.finding.regex),
+        }
+
+        if query.is_empty() {
+            // Nothing to match. The cursor stays where it is
+            self.set_other_matches(&[]);
+            return Ok(());
+        }
+
+        let matcher = match Matcher::new(query, self.finding.regex) {
+            Ok(m) => m,
+            Err(_) => {
+                // Pattern is being typed. Wait for valid input
+                self.set_other_matches(&[]);
+                return Ok(());
+            }
+        };
+
+        let matches = self.finding.matches.take().unwrap_or_else(|| {
+            let rows = self.bufs[self.buf_idx].rows().iter().enumerate();
+            rows.flat_map(|(y, row)| {
+                let found = matcher.find_all(row.buffer());
+                found.into_iter().map(move |(start, end)| (y, start, end))
+            })
+            .collect()
+        });
+        self.set_other_matches(&matches);
+        self.finding.matches = Some(matches);
+
+        let row_len = self.buf().rows().len();
+        let dir = self.finding.dir;
+        // Moving to next match starts from the previous match. Other matches in the same line are
+        // visited before going to the next line
+        let (mut y, mut from) = match self.finding.last_match {
+            Some((x, y)) => (y, Some(x)),
+            // The cursor at the line after the last line wraps around to the first line
+            None if self.buf().cy() >= row_len => (0, None),
+            None => (self.buf().cy(), None),
+        };
+
+        // The start line is visited twice so that matches before the previous match are found
+        // after wrapping around the buffer. Nothing is visited in an empty buffer
+        let visits = if row_len == 0 { 0 } else { row_len + 1 };
+        for _ in 0..visits {
+            let line = self.buf().rows()[y].buffer();
+            let found = match (dir, from.take()) {
+                (FindDir::Forward, Some(x)) => matcher.find_at(line, x + 1),
+                (FindDir::Forward, None) => matcher.find_at(line, 0),
+                (FindDir::Back, Some(x)) => matcher
+                    .find_all(line)
+                    .into_iter()
+                    .rev()
+                    .find(|&(s, _)| s < x),
+                (FindDir::Back, None) => matcher.find_all(line).pop(),
+            };
+            if let Some((start, end)) = found {
+                self.jump_to(start, y);
+
+                self.finding.last_match = Some((start, y));
+                // Set match highlight on the found line
+                self.hl.set_match(y, start, end);
+                // XXX: It updates entire highlights
+                self.hl.needs_update = true;
+                self.screen.set_dirty_start(y);
+                break;
+            }
+            y = dir.next_line(y, row_len);
+        }
+
+        if !query.is_empty() {
+            let total = self.finding.matches.as_ref().map_or(0, Vec::len);
+            self.screen.set_prompt_message(format!(
+                "{}: {} [{}/{}] {}",
+                self.finding.label(),
+                query,
+                self.finding.current_index(),
+                total,
+                FIND_KEYS,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn set_other_matches(&mut self, matches: &[(usize, usize, usize)]) {
+        if let Some(line) = self.hl.set_other_matches(matches) {
+            self.screen.set_dirty_start(line);
+        }
+    }
+
+    fn find(&mut self, regex: bool) -> Result<()> {
+        self.finding = FindState::new(regex);
+        let (cx, cy, coloff, rowoff) = (
+            self.buf().cx(),
+            self.buf().cy(),
+            self.screen.coloff,
+            self.screen.rowoff,
+        );
+        let s = format!("{}: {{}} {}", self.finding.label(), FIND_KEYS);
+        let input = self.prompt_with_history(s, HistoryKind::Search, Self::on_incremental_find)?;
+        if input.as_ref().map(String::is_empty).unwrap_or(true) {
+            // Canceled. Restore cursor position
+            self.buf_mut().set_cursor(cx, cy);
+            self.screen.coloff = coloff;
+            self.screen.rowoff = rowoff;
+            self.screen.set_dirty_start(self.screen.rowoff); // Redraw all lines
+        } else if self.finding.last_match.is_some() {
+            self.buf_mut().push_jump(cx, cy);
+            self.screen.set_info_message("Found");
+        } else {
+            self.screen.set_error_message("Not Found");
+        }
+
+        self.finding = FindState::new(false); // Clear text search state for next time
+        Ok(())
+    }
+
+    fn read_replace_choice(&mut self) -> Result<Option<u8>> {
+        while let Some(seq) = self.next_input() {
+            use KeySeq::*;
+
+            let seq = seq?;
+            if self.screen.maybe_resize(&seq) {
+                self.refresh_screen()?;
+                continue;
+            }
+
+            match seq {
+                InputSeq {
+                    key: Key(b),
+                    ctrl: false,
+                    alt: false,
+                    shift: false,
+                } if b"ynaq".contains(&b) => return Ok(Some(b)),
+                InputSeq {
+                    key: Key(b'g'),
+                    ctrl: true,
+                    ..
+                }
+                | InputSeq { key: Key(0x1b), .. } => return Ok(Some(b'q')),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn replace(&mut self) -> Result<()> {
+        let prompt = "Replace: {} (^G or ESC to cancel)";
+        let query =
+            match self.prompt_with_history(prompt, HistoryKind::Search, |_, _, _, _| Ok(()))? {
+                Some(q) if !q.is_empty() => q,
+                Some(_) => {
+                    self.screen
+                        .set_error_message("Pattern to replace must not be empty");
+                    return Ok(());
+                }
+                None => return Ok(()),
+            };
+        let with_prompt = format!("Replace {} with: {{}} (^G or ESC to cancel)", query);
+        let kind = HistoryKind::Replace;
+        let replacement = match self.prompt_with_history(with_prompt, kind, |_, _, _, _| Ok(()))? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        let matcher = Matcher::Text(query);
+        let row_len = self.buf().rows().len();
+        if row_len == 0 {
+            self.screen.set_error_message("Not Found");
+            return Ok(());
+        }
+
+        let (start_x, start_y) = (self.buf().cx(), self.buf().cy().min(row_len - 1));
+        let (mut x, mut y) = (start_x, start_y);
+        // Matches before this column on the start line are checked after wrapping around buffer
+        let mut stop_x = start_x;
+        // Number of lines gone through. The start line is visited twice; after the cursor at first
+        // and before the cursor at last
+        let mut visited = 0;
+        let mut replace_all = false;
+        let mut count = 0;
+        let repl_len = replacement.chars().count();
+        let dir = FindDir::Forward;
+
+        while visited <= row_len {
+            let found = matcher
+                .find_at(self.buf().rows()[y].buffer(), x)
+                .filter(|&(s, _)| visited < row_len || s < stop_x);
+            let (start, end) = if let Some(m) = found {
+                m
+            } else {
+                visited += 1;
+                y = dir.next_line(y, row_len);
+                x = 0;
+                continue;
+            };
+
+            let choice = if replace_all {
+                b'a'
+            } else {
+                self.buf_mut().set_cursor(start, y);
+                self.hl.set_match(y, start, end);
+                self.hl.needs_update = true;
+                self.screen.set_dirty_start(y);
+                self.screen.cursor_moved = true;
+                self.screen
+                    .set_prompt_message("Replace this match? (y)es, (n)o, (a)ll, (q)uit");
+                self.refresh_screen()?;
+                match self.read_replace_choice()? {
+                    Some(c) => c,
+                    None => break,
+                }
+            };
+
+            match choice {
+                b'y' | b'a' => {
+                    self.buf_mut().replace_range(start, y, end, &replacement);
+                    self.sync_buffer_dirty();
+                    count += 1;
+                    if visited == row_len {
+                        // Matches before the start position moved by replacement
+                        stop_x = (stop_x + repl_len).saturating_sub(end - start);
+                    }
+                    x = start + repl_len;
+                    replace_all |= choice == b'a';
+                }
+                b'n' => x = end,
+                _ => break,
+            }
+        }
+
+        if let Some(matched_line) = self.hl.clear_previous_match() {
+            self.hl.needs_update = true;
+            self.screen.set_dirty_start(matched_line);
+        }
+        self.screen.cursor_moved = true;
+        self.screen
+            .set_info_message(format!("Replaced {} occurrence(s)", count));
+        Ok(())
+    }
+
+    // Cursor keys scroll the help when it is longer than the screen. Any other key closes it
+    fn show_help(&mut self) -> Result<()> {
+        let mut help = self.keymap.help();
+        let palette = self
+            .keymap
+            .keys(Command::Palette)
+            .next()
+            .map(keymap::key_name);
+        for (name, desc) in self.plugins.commands() {
+            let keys: Vec<_> = self
+                .keymap
+                .plugin_keys(name)
+                .map(keymap::key_name)
+                .collect();
+            let keys = match &palette {
+                _ if !keys.is_empty() => keys.join(" or "),
+                Some(key) => format!("{} {}", key, name),
+                None => name.to_string(),
+            };
+            help.push_str(&format!("\n{:<29} : {}", keys, desc));
+        }
+        let mut offset = self.screen.draw_help(&help, 0)?;
+        let scrollable = help.lines().count() > self.screen.rows();
+
+        while let Some(seq) = self.next_input() {
+            let seq = seq?;
+            if self.screen.maybe_resize(&seq) {
+                // Redraw status bar and message bar for new window size then overwrite text area
+                self.refresh_screen()?;
+                offset = self.screen.draw_help(&help, offset)?;
+                continue;
+            }
+            let page = self.screen.rows().saturating_sub(1);
+            let next = match (seq.key, seq.ctrl, seq.alt) {
+                (KeySeq::Unidentified, ..) => continue,
+                _ if !scrollable => break,
+                (KeySeq::UpKey, false, false) | (KeySeq::Key(b'p'), true, false) => {
+                    offset.saturating_sub(1)
+                }
+                (KeySeq::DownKey, false, false) | (KeySeq::Key(b'n'), true, false) => offset + 1,
+                (KeySeq::PageUpKey, false, false) | (KeySeq::Key(b'v'), false, true) => {
+                    offset.saturating_sub(page)
+                }
+                (KeySeq::PageDownKey, false, false) | (KeySeq::Key(b'v'), true, false) => {
+                    offset + page
+                }
+                _ => break,
+            };
+            offset = self.screen.draw_help(&help, next)?;
+        }
+
+        // Redraw screen
+        self.screen.set_dirty_start(self.screen.rowoff);
+        Ok(())
+    }
+
+    fn prompt<S, F>(&mut self, prompt: S, incremental_callback: F) -> Result<Option<String>>
+    where
+        S: AsRef<str>,
+        F: FnMut(&mut Self, &str, InputSeq, bool) -> Result<()>,
+    {
+        self.read_prompt(prompt, incremental_callback, None, None)
+    }
+
+    // Alt-Up and Alt-Down recall inputs entered previously into the prompts of the same kind
+    fn prompt_with_history<S, F>(
+        &mut self,
+        prompt: S,
+        kind: HistoryKind,
+        incremental_callback: F,
+    ) -> Result<Option<String>>
+    where
+        S: AsRef<str>,
+        F: FnMut(&mut Self, &str, InputSeq, bool) -> Result<()>,
+    {
+        self.read_prompt(prompt, incremental_callback, None, Some(kind))
+    }
+
+    fn prompt_with_completion<S, F>(
+        &mut self,
+        prompt: S,
+        incremental_callback: F,
+        complete: fn(&str) -> Vec<String>,
+    ) -> Result<Option<String>>
+    where
+        S: AsRef<str>,
+        F: FnMut(&mut Self, &str, InputSeq, bool) -> Result<()>,
+    {
+        self.read_prompt(prompt, incremental_callback, Some(complete), None)
+    }
+
+    // Tab key completes the input with candidates returned from `complete`. When the completion is
+    // ambiguous, the candidates are listed in message bar. The input can be edited at the cursor
+    // with keys such as LEFT, RIGHT, ^A, ^E, ^W and ^K. Keys which only move the cursor are not
+    // passed to the callback
+    fn read_prompt<S, F>(
+        &mut self,
+        prompt: S,
+        mut incremental_callback: F,
+        complete: Option<fn(&str) -> Vec<String>>,
+        history: Option<HistoryKind>,
+    ) -> Result<Option<String>>
+    where
+        S: AsRef<str>,
+        F: FnMut(&mut Self, &str, InputSeq, bool) -> Result<()>,
+    {
+        let mut input = PromptInput::default();
+        let mut canceled = false;
+        let prompt = prompt.as_ref();
+        let prefix = prompt.split("{}").next().unwrap_or("").len();
+        let mut recall = history.map(|kind| self.history.recall(kind));
+        self.screen.set_prompt_message(prompt.replacen("{}", "", 1));
+        self.screen.set_prompt_cursor(Some(prefix));
+        self.refresh_screen()?;
+
+        while let Some(seq) = self.next_input() {
+            use KeySeq::*;
+
+            let seq = seq?;
+            if self.screen.maybe_resize(&seq) {
+                self.refresh_screen()?;
+                continue;
+            }
+
+            let mut finished = false;
+            let mut candidates = None;
+
+            match (&seq.key, seq.ctrl) {
+                (Unidentified, ..) => continue,
+                (Key(b'i'), true) if complete.is_some() => {
+                    let found = complete.unwrap()(input.text());
+                    input.set(&prompt::completion(input.text(), &found));
+                    if found.len() > 1 {
+                        candidates = Some(found);
+                    }
+                }
+                (Key(b'g'), true) | (Key(b'q'), true) | (Key(0x1b), ..) => {
+                    finished = true;
+                    canceled = true;
+                }
+                (Key(b'\r'), ..) | (Key(b'm'), true) => {
+                    finished = true;
+                }
+                (UpKey, false) if seq.alt => {
+                    if let Some(entry) = recall.as_mut().and_then(|r| r.older(input.text())) {
+                        input.set(entry);
+                    }
+                }
+                (DownKey, false) if seq.alt => {
+                    if let Some(entry) = recall.as_mut().and_then(|r| r.newer()) {
+                        input.set(entry);
+                    }
+                }
+                _ => {
+                    if input.edit(&seq) == Some(false) {
+                        // Message shown by the callback is kept since the input is not changed
+                        self.screen.set_prompt_cursor(Some(prefix + input.cursor()));
+                        self.refresh_screen()?;
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(candidates) = candidates {
+                // Show only the last components of paths since directory part is common
+                let label = prompt.split(": {}").next().unwrap_or("");
+                let names: Vec<_> = candidates
+                    .iter()
+                    .map(|c| {
+                        let dir = c.trim_end_matches('/').rfind('/');
+                        &c[dir.map(|i| i + 1).unwrap_or(0)..]
+                    })
+                    .collect();
+                self.show_candidates(label, input.text(), &names);
+            } else if !finished {
+                self.screen
+                    .set_prompt_message(prompt.replacen("{}", input.text(), 1));
+            }
+            self.screen.set_prompt_cursor(Some(prefix + input.cursor()));
+            // Callback can overwrite the message to show more information such as candidates
+            incremental_callback(self, input.text(), seq, finished)?;
+            if finished {
+                break;
+            }
+            self.refresh_screen()?;
+        }
+
+        self.screen.set_prompt_cursor(None);
+        self.screen
+            .set_info_message(if canceled { "Canceled" } else { "" });
+        if let (Some(kind), false) = (history, canceled) {
+            self.push_history(kind, input.text());
+        }
+        self.refresh_screen()?;
+
+        Ok(if canceled {
+            None
+        } else {
+            Some(input.text().to_string())
+        })
+    }
+
+    fn push_history(&mut self, kind: HistoryKind, input: &str) {
+        self.history.push(kind, input);
+        if let Some(dir) = &self.state_dir {
+            if let Err(err) = self.history.save(&dir.join("history")) {
+                self.screen.set_error_message(err.to_string());
+            }
+        }
+    }
+
+    // Ask to recover the current buffer from the swap file left by a crashed editor
+    fn recover_swap(&mut self) -> Result<()> {
+        if !self.buf().has_swap() {
+            return Ok(());
+        }
+        self.screen.set_error_message(format!(
+            "Swap file of {} was found. Recover unsaved changes? r: recover, d: discard",
+            self.buf().filename(),
+        ));
+        self.refresh_screen()?;
+
+        while let Some(seq) = self.next_input() {
+            use KeySeq::*;
+
+            let seq = seq?;
+            if self.screen.maybe_resize(&seq) {
+                self.refresh_screen()?;
+                continue;
+            }
+
+            if seq.ctrl || seq.alt {
+                continue;
+            }
+            match seq.key {
+                Key(b'r') => {
+                    self.buf_mut().recover_swap()?;
+                    self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+                    self.screen.set_info_message("Recovered from swap file");
+                    break;
+                }
+                Key(b'd') => {
+                    self.buf_mut().remove_swap();
+                    self.screen.set_info_message("Discarded swap file");
+                    break;
+                }
+                _ => {}
+            }
+        }
+        self.reset_screen()
+    }
+
+    // Periodically called while waiting for key inputs to write modified buffers to swap files
+    fn write_swap_files(&mut self) {
+        let interval = self.options.swap_interval;
+        if interval == 0 || self.swapped_at.elapsed() < Duration::from_secs(interval) {
+            return;
+        }
+        self.swapped_at = Instant::now();
+        for buf in self.bufs.iter_mut() {
+            if let Err(err) = buf.write_swap() {
+                self.screen.set_error_message(err.to_string());
+            }
+        }
+    }
+
+    // Text piped to stdin is unsaved but it is only viewed in viewer mode
+    fn handle_quit(&mut self) -> Result<bool> {
+        let modified = self.bufs.iter().any(|b| b.modified());
+        if !modified || self.quitting || self.view {
+            Ok(true)
+        } else {
+            self.quitting = true;
+            self.screen.set_error_message(
+                "At least one file has unsaved changes! Press ^Q again to quit or ^S to save",
+            );
+            Ok(false)
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.buf_mut().undo() {
+            Some(Some(cmd)) => self
+                .screen
+                .set_info_message(format!("Undo {}", cmd.describe())),
+            Some(None) => {}
+            None => self.screen.set_info_message("No older change"),
+        }
+    }
+
+    fn redo(&mut self) {
+        match self.buf_mut().redo() {
+            Some(Some(cmd)) => self
+                .screen
+                .set_info_message(format!("Redo {}", cmd.describe())),
+            Some(None) => {}
+            None => self.screen.set_info_message("Already at newest change"),
+        }
+    }
+
+    // Successive typing is remembered as one insertion. Other key inputs finish the insertion
+    fn remember_edit(&mut self, edit: Option<EditCommand>) {
+        let typing = matches!(edit, Some(EditCommand::Insert(_)));
+        if let Some(edit) = edit {
+            let inserting = self.inserting;
+            let merged = inserting && self.last_edit.as_mut().is_some_and(|l| l.merge(&edit));
+            if !merged {
+                self.last_edit = Some(edit);
+            }
+        }
+        self.inserting = typing;
+    }
+
+    fn repeat_edit(&mut self) -> Result<()> {
+        match self.last_edit.clone() {
+            Some(EditCommand::Insert(text)) => {
+                if !self.check_writable() {
+                    return Ok(());
+                }
+                for c in text.chars() {
+                    if c == '\n' {
+                        self.for_each_caret(TextBuffer::insert_line_indented);
+                    } else {
+                        self.insert_char(c);
+                    }
+                }
+                self.current_edit = Some(EditCommand::Insert(text));
+            }
+            Some(EditCommand::Run(cmd)) => self.run_command(cmd)?,
+            None => self.screen.set_info_message("No edit to repeat"),
+        }
+        Ok(())
+    }
+
+    // Move cursor to the text position at (row, col) on screen. Both are 1-based. Clicking the
+    // scrollbar jumps to the relative position in the buffer
+    fn click_at(&mut self, row: usize, col: usize) {
+        let len = self.buf().rows().len();
+        if let Some(y) = self.screen.scrollbar_line(row, col, len) {
+            self.buf_mut().clear_carets();
+            self.buf_mut().set_cursor(0, y);
+            self.center_cursor();
+            return;
+        }
+        let col = col.saturating_sub(self.screen.text_left() + self.screen.gutter_width());
+        if row == 0 || row > self.screen.rows() || col == 0 {
+            return; // Status bar or message bar was clicked
+        }
+        let y = self.buf().folds().down(self.screen.rowoff, row - 1, len);
+        let x = self
+            .buf()
+            .rows()
+            .get(y)
+            .map(|r| r.cx_from_rx(self.screen.coloff + col - 1))
+            .unwrap_or(0);
+        self.buf_mut().clear_carets();
+        self.buf_mut().set_cursor(x, y);
+    }
+
+    // Unlike `EndOfLine`, the cursor moves within the screen without scrolling a long line
+    fn end_of_screen_line(&mut self) {
+        let cy = self.buf().cy();
+        if let Some(row) = self.buf().rows().get(cy) {
+            let cx = self.screen.end_of_screen_line(row);
+            self.buf_mut().set_cursor(cx, cy);
+        }
+    }
+
+    fn scroll_screen(&mut self, dir: ScrollDir) {
+        const SCROLL_LINES: usize = 3;
+        let (len, num_rows) = (self.buf().rows().len(), self.screen.rows());
+        let folds = self.bufs[self.buf_idx].folds();
+        let rowoff = match dir {
+            ScrollDir::Up => folds.up(self.screen.rowoff, SCROLL_LINES),
+            ScrollDir::Down => {
+                let last = folds.visible_line(len.saturating_sub(1));
+                folds.down(self.screen.rowoff, SCROLL_LINES, last)
+            }
+        };
+        if rowoff == self.screen.rowoff {
+            return;
+        }
+        self.screen.rowoff = rowoff;
+        self.screen.set_dirty_start(rowoff);
+
+        // Keep cursor in screen out of the scroll margin. Otherwise the screen is scrolled back to
+        // the cursor
+        let margin = self.screen.scroll_margin();
+        let cy = self.buf().cy();
+        let folds = self.bufs[self.buf_idx].folds();
+        let y = cmp::max(
+            cmp::min(cy, folds.down(rowoff, num_rows - 1 - margin, len)),
+            folds.down(rowoff, margin, len),
+        );
+        if y != cy {
+            let x = cmp::min(
+                self.buf().cx(),
+                self.buf().rows().get(y).map(Row::len).unwrap_or(0),
+            );
+            self.buf_mut().set_cursor(x, y);
+        }
+    }
+
+    fn copy_to_clipboard(&mut self, text: String) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.screen.set_clipboard(&text)?;
+        self.clipboard.copy(text);
+        Ok(())
+    }
+
+    fn toggle_mark(&mut self) {
+        self.selecting_by_shift = false;
+        if self.buf().has_selection() {
+            self.buf_mut().clear_anchor();
+            self.screen.set_info_message("Mark deactivated");
+        } else {
+            self.buf_mut().set_anchor();
+            self.screen.set_info_message("Mark set");
+        }
+    }
+
+    fn toggle_rect_mark(&mut self) {
+        self.selecting_by_shift = false;
+        if self.buf().has_selection() {
+            self.buf_mut().clear_anchor();
+            self.screen.set_info_message("Mark deactivated");
+        } else {
+            self.buf_mut().set_rect_anchor();
+            self.screen.set_info_message("Mark set for rectangle");
+        }
+    }
+
+    // Rectangle is copied to clipboard as lines. Pasting the same text inserts it as rectangle
+    fn copy_rect(&mut self, cut: bool) -> Result<()> {
+        let lines = if cut {
+            self.buf_mut().delete_rect()
+        } else {
+            let lines = self.buf().rect_text();
+            self.buf_mut().clear_anchor();
+            lines
+        };
+        self.copy_to_clipboard(lines.join("\n"))?;
+        self.rectangle = lines;
+        self.screen.set_info_message(if cut {
+            "Cut rectangle"
+        } else {
+            "Copied rectangle"
+        });
+        Ok(())
+    }
+
+    fn toggle_comment(&mut self) {
+        if !self.buf_mut().toggle_comment() {
+            let lang = self.buf().lang().name();
+            self.screen
+                .set_error_message(format!("{} has no comment syntax", lang));
+        }
+    }
+
+    fn copy_selection(&mut self) -> Result<()> {
+        let text = self.buf().selected_text();
+        self.buf_mut().clear_anchor();
+        self.copy_to_clipboard(text)?;
+        self.screen.set_info_message("Copied selected text");
+        Ok(())
+    }
+
+    fn copy_line(&mut self) -> Result<()> {
+        let line = if let Some(row) = self.buf().rows().get(self.buf().cy()) {
+            format!("{}\n", row.buffer())
+        } else {
+            return Ok(());
+        };
+        self.copy_to_clipboard(line)?;
+        self.screen.set_info_message("Copied current line");
+        Ok(())
+    }
+
+    fn paste(&mut self) {
+        let text = self.clipboard.paste().to_string();
+        if text.is_empty() {
+            self.screen.set_info_message("Clipboard is empty");
+        } else if !self.rectangle.is_empty() && text == self.rectangle.join("\n") {
+            let lines = self.rectangle.clone();
+            self.buf_mut().insert_rect(&lines);
+        } else {
+            self.buf_mut().insert_text(&text);
+        }
+    }
+
+    // Run the command of plugin with the current buffer and apply the actions it returned
+    fn run_plugin_command(&mut self, name: &str) -> Result<()> {
+        self.last_cmd = None;
+        self.buf_mut().clear_carets();
+        let buf = &self.bufs[self.buf_idx];
+        let req = plugin::Request {
+            command: name,
+            filename: buf.filename(),
+            lang: buf.lang(),
+            cursor: (buf.cx(), buf.cy()),
+            selection: Some(buf)
+                .filter(|b| b.has_selection())
+                .map(TextBuffer::selected_text),
+            lines: buf.lines().collect(),
+        };
+        let actions = match self.plugins.run(&req) {
+            Some(actions) => actions?,
+            None => return Err(Error::other(format!("Unknown command '{}'", name))),
+        };
+
+        for action in actions {
+            match action {
+                Action::Insert(_) | Action::ReplaceLine(..) if !self.check_writable() => {
+                    return Ok(())
+                }
+                Action::Insert(text) => {
+                    if self.buf().has_selection() {
+                        self.buf_mut().delete_selection();
+                    }
+                    self.buf_mut().insert_text(&text);
+                }
+                Action::ReplaceLine(y, text) => {
+                    let rows = self.buf().rows();
+                    if y >= rows.len() || text.contains('\n') {
+                        let msg =
+                            format!("Plugin command '{}' cannot replace line {}", name, y + 1);
+                        return Err(Error::other(msg));
+                    }
+                    let len = rows[y].len();
+                    self.buf_mut().replace_range(0, y, len, &text);
+                }
+                // Plugins may return positions out of the buffer
+                Action::MoveCursor(x, y) => {
+                    let rows = self.buf().rows();
+                    let y = y.min(rows.len());
+                    let x = rows.get(y).map(|r| x.min(r.len())).unwrap_or(0);
+                    self.jump_to(x, y);
+                }
+                Action::Message(msg) => self.screen.set_info_message(msg),
+                Action::Error(msg) => self.screen.set_error_message(msg),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_not_mapped(&mut self, seq: InputSeq) {
+        self.screen
             .set_error_message(format!("Key '{}' not mapped", seq));
     }
 
-    fn process_keypress(&mut self, s: InputSeq) -> io::Result<bool> {
-        use KeySeq::*;
+    fn close_completion(&mut self) {
+        self.completion = None;
+        self.screen.clear_popup();
+    }
+
+    // Handle the key while completion popup is shown. Returns true when the key was consumed.
+    // Other keys close the popup
+    fn handle_completion_key(&mut self, s: &InputSeq) -> bool {
+        let completion = match &mut self.completion {
+            Some(completion) => completion,
+            None => return false,
+        };
+        match (&s.key, s.ctrl, s.alt) {
+            (KeySeq::UpKey, false, false) => completion.select(false),
+            (KeySeq::DownKey, false, false) => completion.select(true),
+            (KeySeq::Key(b'i'), true, false) => {
+                // Tab inserts the rest of the selected candidate
+                let rest = completion.rest().to_string();
+                self.close_completion();
+                self.current_edit = Some(EditCommand::Insert(rest.clone()));
+                self.buf_mut().insert_str(rest);
+                return true;
+            }
+            (KeySeq::Key(0x1b), false, false) => {
+                self.close_completion();
+                return self.vi.is_none(); // ESC also goes back to normal mode of Vi
+            }
+            _ => {
+                self.close_completion();
+                return false;
+            }
+        }
+        let (candidates, selected, start) = (
+            completion.candidates.clone(),
+            completion.selected,
+            completion.start,
+        );
+        self.screen.set_popup(candidates, selected, start);
+        true
+    }
+
+    // Lines of the current buffer nearer to the cursor first, then lines of other buffers
+    fn completion_lines(&self) -> impl Iterator<Item = &str> {
+        let rows = self.buf().rows();
+        let (cy, len) = (self.buf().cy(), rows.len());
+        let near = (0..=len).flat_map(move |d| {
+            let above = cy.checked_sub(d);
+            let below = Some(cy + d).filter(|_| d > 0);
+            above.into_iter().chain(below).filter(move |y| *y < len)
+        });
+        let others = self
+            .bufs
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| *i != self.buf_idx)
+            .flat_map(|(_, b)| b.lines());
+        near.map(move |y| rows[y].buffer()).chain(others)
+    }
+
+    // Completion popup follows typing a word. It is not shown when typing at multiple places
+    fn update_completion(&mut self, typed: char) {
+        let buf = self.buf();
+        let inserting = self.vi.as_ref().is_none_or(|vi| vi.mode() == Mode::Insert);
+        if !self.options.completion
+            || !completion::is_word_char(typed)
+            || !inserting
+            || buf.readonly()
+            || !buf.carets().is_empty()
+            || buf.rect_selection().is_some()
+        {
+            return;
+        }
+
+        let line = match buf.rows().get(buf.cy()) {
+            Some(row) => row.buffer(),
+            None => return,
+        };
+        let (start, prefix) = match completion::prefix_at(line, buf.cx()) {
+            Some(found) => found,
+            None => return,
+        };
+        let candidates = completion::word_candidates(&prefix, self.completion_lines());
+        if candidates.is_empty() {
+            return;
+        }
+        self.screen.set_popup(candidates.clone(), 0, start);
+        self.completion = Some(Completion {
+            start,
+            prefix,
+            candidates,
+            selected: 0,
+        });
+    }
+
+    // Propagate lines modified in text buffer to highlight and screen
+    fn sync_buffer_dirty(&mut self) {
+        if let Some(line) = self.buf().dirty_start {
+            self.hl.lines_changed(line);
+            self.screen.set_dirty_start(line);
+            self.update_diff();
+            // Marks of changes in gutter may be updated before the modified line
+            if let Some(line) = self.buf_mut().update_vcs() {
+                self.screen.set_dirty_start(line);
+            }
+            if let Some(line) = self.buf_mut().update_conflicts() {
+                self.screen.set_dirty_start(line);
+            }
+        }
+    }
+
+    fn show_candidates(&mut self, label: &str, input: &str, candidates: &[&str]) {
+        let candidates = if candidates.is_empty() {
+            "no match".to_string()
+        } else {
+            candidates.join(" | ")
+        };
+        self.screen
+            .set_prompt_message(format!("{}: {} ({})", label, input, candidates));
+    }
+
+    // Prompt command name and returns the name of command which matches best. Commands are
+    // filtered by fuzzy matching while typing. Commands of plugins follow builtin commands
+    fn command_palette(&mut self) -> Result<Option<String>> {
+        let names = Command::ALL
+            .iter()
+            .filter(|c| **c != Command::Palette)
+            .map(|c| c.name().to_string())
+            .chain(self.plugins.commands().map(|(name, _)| name.to_string()))
+            .collect();
+        match self.pick("Command", names)? {
+            Some((_, Some(name))) => Ok(Some(name)),
+            Some((input, None)) => {
+                self.screen
+                    .set_error_message(format!("No command matches '{}'", input));
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_language(&mut self) -> Result<()> {
+        let input = match self.prompt("Language: {} (^G or ESC to cancel)", |_, _, _, _| Ok(()))? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        let lang = if input == Language::Plain.name() {
+            Language::Plain
+        } else if let Some(lang) = Language::from_name(&input) {
+            lang
+        } else {
+            self.screen
+                .set_error_message(format!("Unknown language '{}'", input));
+            return Ok(());
+        };
+        self.buf_mut().set_lang(lang);
+        self.hl.lang_changed(lang);
+        // Render entire screen since highglight updated
+        self.screen.set_dirty_start(self.screen.rowoff);
+        self.screen
+            .set_info_message(format!("Language was changed to {}", lang.name()));
+        Ok(())
+    }
+
+    // Killed text is pushed to kill ring. Text killed by successive kill commands is concatenated
+    fn kill(&mut self, text: String, backward: bool, prev_cmd: Option<Command>) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if prev_cmd.map(Command::kills_text).unwrap_or(false) {
+            self.killring.append(&text, backward);
+        } else {
+            self.killring.push(text);
+        }
+        let latest = self.killring.latest().unwrap_or_default().to_string();
+        self.copy_to_clipboard(latest)
+    }
+
+    fn yank(&mut self) {
+        let text = if let Some(text) = self.killring.yank() {
+            text.to_string()
+        } else {
+            self.screen.set_info_message("Kill ring is empty");
+            return;
+        };
+        self.yanked_at = Some((self.buf().cx(), self.buf().cy()));
+        self.buf_mut().insert_text(&text);
+    }
+
+    // Replace text pasted by previous yank with earlier killed text
+    fn yank_pop(&mut self, prev_cmd: Option<Command>) {
+        let start = match (prev_cmd, self.yanked_at) {
+            (Some(Command::Yank), Some(start)) | (Some(Command::YankPop), Some(start)) => start,
+            _ => {
+                self.last_cmd = None;
+                self.screen
+                    .set_error_message("Previous command was not a yank");
+                return;
+            }
+        };
+        if let Some(text) = self.killring.yank_pop() {
+            let text = text.to_string();
+            self.buf_mut().delete_to(start.0, start.1);
+            self.buf_mut().insert_text(&text);
+        }
+    }
+
+    fn set_theme(&mut self) -> Result<()> {
+        let names = BUILTIN_THEMES.iter().map(|t| t.name.to_string()).collect();
+        match self.pick("Theme", names)? {
+            Some((_, Some(name))) => {
+                if let Some(theme) = Theme::builtin(&name) {
+                    self.screen.set_theme(&theme);
+                }
+            }
+            Some((input, None)) => self
+                .screen
+                .set_error_message(format!("Unknown theme '{}'", input)),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn set_tab_width(&mut self) -> Result<()> {
+        let prompt = format!(
+            "Tab width: {{}} (current: {}) ^G or ESC to cancel",
+            self.buf().tab_width()
+        );
+        let input = match self.prompt(prompt, |_, _, _, _| Ok(()))? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        match input.parse() {
+            Ok(width) if 0 < width && width <= 16 => {
+                self.buf_mut().set_tab_width(width);
+                self.screen
+                    .set_info_message(format!("Tab width was changed to {}", width));
+            }
+            _ => self
+                .screen
+                .set_error_message(format!("Tab width must be 1~16 but got '{}'", input)),
+        }
+        Ok(())
+    }
+
+    // "tab" or number of spaces as 'indent' in syntax files
+    fn set_indent(&mut self) -> Result<()> {
+        let input = match self.prompt(
+            "Indent: {} (\"tab\" or number of spaces) ^G or ESC to cancel",
+            |_, _, _, _| Ok(()),
+        )? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        match input.as_str() {
+            "tab" => self.buf_mut().set_indent(None),
+            _ => match input.parse() {
+                Ok(width) if 0 < width && width <= 16 => self.buf_mut().set_indent(Some(width)),
+                _ => {
+                    self.screen.set_error_message(format!(
+                        "Indent must be \"tab\" or 1~16 but got '{}'",
+                        input
+                    ));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // Encoding used on saving the current buffer
+    fn set_encoding(&mut self) -> Result<()> {
+        let names = Encoding::ALL
+            .iter()
+            .map(|e| e.name())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let prompt = format!("Encoding: {{}} ({}) ^G or ESC to cancel", names);
+        let input = match self.prompt(prompt, |_, _, _, _| Ok(()))? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        if let Some(encoding) = Encoding::from_name(&input) {
+            self.buf_mut().set_encoding(encoding);
+            self.screen.set_info_message(format!(
+                "Encoding was changed to {}. It is applied on saving",
+                encoding.name()
+            ));
+        } else {
+            self.screen
+                .set_error_message(format!("Unknown encoding '{}'", input));
+        }
+        Ok(())
+    }
+
+    // Offset is decimal or hexadecimal with '0x' prefix
+    fn go_to_offset(&mut self) -> Result<()> {
+        if self.buf().binary_offset().is_none() {
+            self.screen
+                .set_error_message("Byte offset is only available in hex view of binary file");
+            return Ok(());
+        }
+        let input = match self.prompt("Offset: {} (^G or ESC to cancel)", |_, _, _, _| Ok(()))? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        let parsed = match input.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => input.parse(),
+        };
+        match parsed {
+            Ok(offset) => {
+                self.record_jump();
+                self.buf_mut().move_cursor_to_offset(offset);
+            }
+            Err(_) => self
+                .screen
+                .set_error_message(format!("Invalid offset '{}'", input)),
+        }
+        Ok(())
+    }
+
+    // Move the cursor to the position and show the line at center of screen
+    // Side of the current buffer in diff mode. None when the current buffer is not compared
+    fn diff_side(&self) -> Option<usize> {
+        self.diff
+            .as_ref()?
+            .bufs
+            .iter()
+            .position(|b| *b == self.buf_idx)
+    }
+
+    fn update_diff(&mut self) {
+        if let Some(diff) = &mut self.diff {
+            let old: Vec<_> = self.bufs[diff.bufs[0]].lines().collect();
+            let new: Vec<_> = self.bufs[diff.bufs[1]].lines().collect();
+            diff.hunks = diff::hunks(&old, &new);
+        }
+    }
+
+    fn jump_to_hunk(&mut self, forward: bool) {
+        let (diff, side) = match (&self.diff, self.diff_side()) {
+            (Some(diff), Some(side)) => (diff, side),
+            _ => {
+                self.screen.set_error_message("Not in diff mode");
+                return;
+            }
+        };
+        let starts: Vec<_> = diff.hunks.iter().map(|h| h.lines[side].start).collect();
+        match self.jump_to_next_line(&starts, forward) {
+            Some(i) => self
+                .screen
+                .set_info_message(format!("Hunk {}/{}", i + 1, starts.len())),
+            None => self.screen.set_info_message("No more hunks"),
+        }
+    }
+
+    // Jump to the nearest line after or before the cursor line in the ascending lines. Returns the
+    // index of the line
+    fn jump_to_next_line(&mut self, lines: &[usize], forward: bool) -> Option<usize> {
+        let cy = self.buf().cy();
+        let i = if forward {
+            lines.iter().position(|y| *y > cy)?
+        } else {
+            lines.iter().rposition(|y| *y < cy)?
+        };
+        self.record_jump();
+        self.jump_to(0, lines[i]);
+        Some(i)
+    }
+
+    fn jump_to_conflict(&mut self, forward: bool) {
+        let starts: Vec<_> = self.buf().conflicts().iter().map(|c| c.start).collect();
+        if starts.is_empty() {
+            self.screen.set_info_message("No merge conflict");
+            return;
+        }
+        match self.jump_to_next_line(&starts, forward) {
+            Some(i) => self
+                .screen
+                .set_info_message(format!("Conflict {}/{}", i + 1, starts.len())),
+            None => self.screen.set_info_message("No more conflicts"),
+        }
+    }
+
+    fn resolve_conflict(&mut self, resolution: Resolution) {
+        if !self.buf_mut().resolve_conflict(resolution) {
+            self.screen.set_error_message("No merge conflict at cursor");
+            return;
+        }
+        match self.buf().conflicts().len() {
+            0 => self.screen.set_info_message("All conflicts resolved"),
+            1 => self.screen.set_info_message("1 conflict left"),
+            n => self
+                .screen
+                .set_info_message(format!("{} conflicts left", n)),
+        }
+    }
+
+    // Files left with merge conflicts are noticed on opening
+    fn report_conflicts(&mut self) {
+        match self.buf().conflicts().len() {
+            0 => {}
+            1 => self.screen.set_info_message("1 merge conflict"),
+            n => self
+                .screen
+                .set_info_message(format!("{} merge conflicts", n)),
+        }
+    }
+
+    // Replace the lines of the hunk at the cursor on the other side with the lines on this side
+    fn diff_put(&mut self) {
+        let (diff, side) = match (&self.diff, self.diff_side()) {
+            (Some(diff), Some(side)) => (diff, side),
+            _ => {
+                self.screen.set_error_message("Not in diff mode");
+                return;
+            }
+        };
+        let hunk = match diff::hunk_at(&diff.hunks, side, self.buf().cy()) {
+            Some(hunk) => hunk,
+            None => {
+                self.screen.set_info_message("No change at cursor");
+                return;
+            }
+        };
+        let other = diff.bufs[1 - side];
+        if self.bufs[other].readonly() {
+            self.screen
+                .set_error_message("Buffer on the other side is read-only");
+            return;
+        }
+        let (src, dest) = (&hunk.lines[side], &hunk.lines[1 - side]);
+        let lines: Vec<String> = {
+            let this = self.buf().lines();
+            let that: Vec<_> = self.bufs[other].lines().collect();
+            that[..dest.start]
+                .iter()
+                .copied()
+                .chain(this.skip(src.start).take(src.len()))
+                .chain(that[dest.end..].iter().copied())
+                .map(String::from)
+                .collect()
+        };
+        let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+
+        let buf = &mut self.bufs[other];
+        buf.replace_lines(&lines);
+        buf.finish_edit(None);
+        buf.update_vcs();
+        if let Some(diff) = &mut self.diff {
+            diff.other_hl = Highlighting::new(buf.lang(), buf.rows());
+        }
+        let msg = format!("Copied changes to {}", buf.filename());
+        self.update_diff();
+        self.screen.set_info_message(msg);
+    }
+
+    fn jump_to(&mut self, x: usize, y: usize) {
+        self.buf_mut().set_cursor(x, y);
+        self.center_cursor();
+    }
+
+    // Folds hiding the cursor line are opened before scrolling to it
+    fn center_cursor(&mut self) {
+        if self.buf_mut().reveal_cursor() {
+            self.sync_buffer_dirty();
+        }
+        let buf = &self.bufs[self.buf_idx];
+        self.screen.center_on(buf.cy(), buf.folds());
+    }
+
+    fn marks_file(&self, idx: usize) -> Option<(PathBuf, PathBuf)> {
+        let buf = &self.bufs[idx];
+        if !buf.has_file() || buf.is_directory() {
+            return None;
+        }
+        let state = self.state_dir.as_ref()?.join("marks");
+        Some((state, PathBuf::from(buf.filename())))
+    }
+
+    // Marks loaded from state file are ignored when the same names are already used in other
+    // buffers since a name identifies one position across buffers
+    fn load_marks(&mut self, idx: usize) {
+        if let Some((state, file)) = self.marks_file(idx) {
+            let mut loaded = marks::load(&state, &file);
+            for name in self.bufs.iter().flat_map(|b| b.marks().names()) {
+                loaded.remove(name);
+            }
+            self.bufs[idx].set_marks(loaded);
+        }
+    }
+
+    fn session_file(&self) -> Option<PathBuf> {
+        Some(self.state_dir.as_ref()?.join("session"))
+    }
+
+    // Position in the state file may be out of the buffer when the file was modified by others
+    fn restore_position(&mut self, idx: usize, current: bool) {
+        let (state, file) = match (self.session_file(), self.marks_file(idx)) {
+            (Some(state), Some((_, file))) => (state, file),
+            _ => return,
+        };
+        let pos = match Session::load(&state).position(&file) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let buf = &mut self.bufs[idx];
+        let y = cmp::min(pos.y, buf.rows().len().saturating_sub(1));
+        let x = cmp::min(pos.x, buf.rows().get(y).map_or(0, Row::len));
+        buf.set_cursor(x, y);
+        if current {
+            self.screen.rowoff = cmp::min(pos.rowoff, y);
+        }
+    }
+
+    // Positions are merged into the state file so that files edited by other processes are kept.
+    // The current buffer becomes the most recently used file
+    fn save_session(&mut self) {
+        let state = match self.session_file() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut session = Session::load(&state);
+        let order = (0..self.bufs.len()).filter(|i| *i != self.buf_idx);
+        for idx in order.chain(Some(self.buf_idx)) {
+            let file = match self.marks_file(idx) {
+                Some((_, file)) => file,
+                None => continue,
+            };
+            let buf = &self.bufs[idx];
+            let rowoff = if idx == self.buf_idx {
+                self.screen.rowoff
+            } else {
+                buf.cy().saturating_sub(self.screen.rows() / 2)
+            };
+            let (x, y) = (buf.cx(), buf.cy());
+            session.update(&file, Position { x, y, rowoff });
+        }
+        if let Err(err) = session.save(&state) {
+            self.screen.set_error_message(err.to_string());
+        }
+    }
+
+    fn save_marks(&mut self, idx: usize) {
+        if let Some((state, file)) = self.marks_file(idx) {
+            if let Err(err) = marks::save(&state, &file, self.bufs[idx].marks()) {
+                self.screen.set_error_message(err.to_string());
+            }
+        }
+    }
+
+    // Read a letter for name of mark
+    fn read_mark_name(&mut self, message: &str) -> Result<Option<char>> {
+        self.screen.set_info_message(message);
+        self.refresh_screen()?;
+        while let Some(seq) = self.next_input() {
+            let seq = seq?;
+            if self.screen.maybe_resize(&seq) {
+                self.refresh_screen()?;
+                continue;
+            }
+            match seq {
+                InputSeq {
+                    key: KeySeq::Key(b),
+                    ctrl: false,
+                    alt: false,
+                    ..
+                } if b.is_ascii_alphabetic() => return Ok(Some(b as char)),
+                InputSeq {
+                    key: KeySeq::Unidentified,
+                    ..
+                } => {}
+                _ => break,
+            }
+        }
+        self.screen.set_info_message("Canceled");
+        Ok(None)
+    }
+
+    fn set_mark(&mut self) -> Result<()> {
+        let name = match self.read_mark_name("Set mark: press a letter (other keys to cancel)")? {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        for idx in 0..self.bufs.len() {
+            if idx != self.buf_idx && self.bufs[idx].marks().get(name).is_some() {
+                self.bufs[idx].remove_mark(name);
+                self.save_marks(idx);
+            }
+        }
+        self.buf_mut().set_mark(name);
+        self.screen
+            .set_info_message(format!("Mark '{}' was set", name));
+        self.save_marks(self.buf_idx);
+        Ok(())
+    }
+
+    fn go_to_mark(&mut self) -> Result<()> {
+        let name = match self.read_mark_name("Go to mark: press a letter (other keys to cancel)")? {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let idx = match self.bufs.iter().position(|b| b.marks().get(name).is_some()) {
+            Some(idx) => idx,
+            None => {
+                self.screen
+                    .set_error_message(format!("Mark '{}' is not set", name));
+                return Ok(());
+            }
+        };
+        if idx != self.buf_idx {
+            self.switch_buffer(idx)?;
+        }
+        self.record_jump();
+        self.buf_mut().move_cursor_to_mark(name);
+        self.center_cursor();
+        self.screen.set_info_message("");
+        Ok(())
+    }
+
+    fn jump_to_bracket(&mut self) {
+        // Calculate highlights of entire buffer since the pair may be far from screen
+        let len = self.buf().rows().len();
+        match self.bracket_at_cursor(len) {
+            Some((_, BracketPair::Matched(x, y))) => {
+                self.record_jump();
+                self.buf_mut().set_cursor(x, y);
+            }
+            Some((_, BracketPair::Unmatched)) => {
+                self.screen.set_error_message("No matching bracket")
+            }
+            None => self.screen.set_info_message("No bracket at cursor"),
+        }
+    }
+
+    fn record_jump(&mut self) {
+        let (x, y) = (self.buf().cx(), self.buf().cy());
+        self.buf_mut().push_jump(x, y);
+    }
+
+    fn jump_back(&mut self) {
+        if self.buf_mut().jump_back() {
+            self.center_cursor();
+        } else {
+            self.screen.set_info_message("No older jump");
+        }
+    }
+
+    fn jump_forward(&mut self) {
+        if self.buf_mut().jump_forward() {
+            self.center_cursor();
+        } else {
+            self.screen.set_info_message("Already at newest jump");
+        }
+    }
+
+    fn go_to_line(&mut self) -> Result<()> {
+        let prompt = "Go to line: {} (N, +N, -N or N%, ^G or ESC to cancel)";
+        let input = match self.prompt(prompt, |_, _, _, _| Ok(()))? {
+            Some(input) if !input.is_empty() => input,
+            _ => return Ok(()),
+        };
+        match jump::parse_line(&input, self.buf().cy(), self.buf().rows().len()) {
+            Some(y) => {
+                self.record_jump();
+                self.jump_to(0, y);
+            }
+            None => self
+                .screen
+                .set_error_message(format!("Invalid line '{}'", input)),
+        }
+        Ok(())
+    }
+
+    fn check_writable(&mut self) -> bool {
+        if let Some(percent) = self.buf().loaded_percent() {
+            self.screen.set_error_message(format!(
+                "Buffer is read-only until the file is loaded ({}%)",
+                percent
+            ));
+            return false;
+        }
+        // Lines are copied from the map on the first edit. The map must not be changed until then
+        if self.buf().mapping_changed() {
+            if let Err(err) = self.check_mapped_files() {
+                self.screen.set_error_message(err.to_string());
+            }
+            return false;
+        }
+        if self.buf().lossy() {
+            self.screen
+                .set_error_message("Buffer is read-only since the file is not valid UTF-8");
+            return false;
+        }
+        if self.buf().readonly() {
+            self.screen.set_error_message("Buffer is read-only");
+            return false;
+        }
+        true
+    }
+
+    fn toggle_expand_tab(&mut self) {
+        let expand = !self.buf().expand_tab();
+        self.buf_mut().set_expand_tab(expand);
+        self.screen.set_info_message(if expand {
+            "Tab key inserts spaces"
+        } else {
+            "Tab key inserts a tab character"
+        });
+    }
+
+    fn for_each_caret<F: FnMut(&mut TextBuffer)>(&mut self, op: F) {
+        self.buf_mut().for_each_caret(op);
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.current_edit = Some(EditCommand::Insert(c.to_string()));
+        if self.buf().rect_selection().is_some() {
+            self.buf_mut().insert_rect_str(c.encode_utf8(&mut [0; 4]));
+        } else {
+            self.for_each_caret(|b| b.insert_char(c));
+        }
+    }
+
+    // Insert the character typed by key input. A non-word character expands the abbreviation
+    // before it
+    fn type_char(&mut self, c: char) {
+        self.insert_char(c);
+        if !completion::is_word_char(c) {
+            let cx = self.buf().cx();
+            self.expand_abbrev(cx - 1);
+        }
+    }
+
+    // Expand the abbreviation which ends at character index `end` in the cursor line. The
+    // expansion is one undo unit separated from typing so that undo restores the abbreviation
+    fn expand_abbrev(&mut self, end: usize) {
+        let buf = self.buf();
+        if self.literal
+            || self.options.abbrev.is_empty()
+            || !buf.carets().is_empty()
+            || buf.rect_selection().is_some()
+        {
+            return;
+        }
+        let line = match buf.rows().get(buf.cy()) {
+            Some(row) => row.buffer(),
+            None => return,
+        };
+        let (start, word) = completion::word_before(line, end);
+        let text = match self.options.abbrev.get(&word) {
+            Some(text) => text.clone(),
+            None => return,
+        };
+        let typed = self.current_edit.clone();
+        self.buf_mut().finish_edit(typed);
+        self.buf_mut().replace_before_cursor(start, end, &text);
+        self.buf_mut().finish_edit(None);
+    }
+
+    fn add_cursor(&mut self) {
+        match self.buf_mut().add_caret_at_next_word() {
+            Some((word, true)) => {
+                let n = self.buf().carets().len() + 1;
+                self.screen
+                    .set_info_message(format!("{} cursors at '{}'", n, word));
+            }
+            Some((word, false)) => self
+                .screen
+                .set_info_message(format!("No more occurrence of '{}'", word)),
+            None => self.screen.set_info_message("No word at cursor"),
+        }
+    }
+
+    fn operate_selection(&mut self, op: Operator) -> Result<()> {
+        match op {
+            Operator::Yank => {
+                let start = self.buf().selection().map(|(start, _)| start);
+                self.run_command(Command::Copy)?;
+                if let Some((x, y)) = start {
+                    self.buf_mut().set_cursor(x, y);
+                }
+            }
+            Operator::Delete | Operator::Change => self.run_command(Command::DeleteWord)?,
+        }
+        Ok(())
+    }
+
+    // Run commands translated from the key input in Vi modes
+    fn run_vi_action(&mut self, action: vi::Action, seq: InputSeq) -> Result<()> {
+        use vi::Action::*;
+        match action {
+            Pass => unreachable!(), // Handled by the modeless key mappings
+            Run(cmds) => {
+                for cmd in cmds {
+                    self.run_command(cmd)?;
+                }
+            }
+            Operate { op, before, motion } => {
+                for cmd in before {
+                    self.run_command(cmd)?;
+                }
+                self.buf_mut().set_anchor();
+                for cmd in motion {
+                    self.run_command(cmd)?;
+                }
+                self.operate_selection(op)?;
+            }
+            OperateSelection(op) => {
+                // Selection in visual mode includes the character at the cursor
+                let (x, y) = (self.buf().cx(), self.buf().cy());
+                if self.buf().selection().is_some_and(|(_, end)| end == (x, y)) {
+                    self.buf_mut().move_cursor_one(CursorDir::Right);
+                }
+                self.operate_selection(op)?;
+            }
+            StartSelection => self.buf_mut().set_anchor(),
+            EndSelection => self.buf_mut().clear_anchor(),
+            LeaveInsert if self.buf().cx() > 0 => self.run_command(Command::CursorLeft)?,
+            LeaveInsert => {}
+            Consumed => {}
+            NotMapped => self.handle_not_mapped(seq),
+        }
+        Ok(())
+    }
+
+    fn run_command(&mut self, cmd: Command) -> Result<()> {
+        use Command::*;
 
         let rowoff = self.screen.rowoff;
         let rows = self.screen.rows();
+        let prev_cmd = self.last_cmd.replace(cmd);
+        if cmd == NewLine && self.buf().is_directory() {
+            return self.open_dir_entry();
+        }
+        if cmd.edits_text() && !self.check_writable() {
+            return Ok(());
+        }
+        if !cmd.runs_at_carets() {
+            self.buf_mut().clear_carets();
+        }
+        if cmd.edits_text() && cmd != Undo && cmd != Redo {
+            self.current_edit = Some(if cmd == NewLine {
+                EditCommand::Insert("\n".to_string())
+            } else {
+                EditCommand::Run(cmd)
+            });
+        }
+        match cmd {
+            Quit | Palette => unreachable!(), // Handled by caller since it may exit editor
+            Save => self.save()?,
+            Open => self.open_buffer()?,
+            NextBuffer => self.next_buffer()?,
+            PreviousBuffer => self.previous_buffer()?,
+            SelectBuffer => self.select_buffer()?,
+            CursorUp => self.for_each_caret(|b| b.move_cursor_one(CursorDir::Up)),
+            CursorDown => self.for_each_caret(|b| b.move_cursor_one(CursorDir::Down)),
+            CursorRight => self.for_each_caret(|b| b.move_cursor_one(CursorDir::Right)),
+            CursorLeft => self.for_each_caret(|b| b.move_cursor_one(CursorDir::Left)),
+            HeadOfLine => self.for_each_caret(|b| b.move_cursor_to_buffer_edge(CursorDir::Left)),
+            EndOfLine => self.for_each_caret(|b| b.move_cursor_to_buffer_edge(CursorDir::Right)),
+            NextPage => self
+                .buf_mut()
+                .move_cursor_page(CursorDir::Down, rowoff, rows),
+            PreviousPage => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
+            NextWord => self.for_each_caret(|b| b.move_cursor_by_word(CursorDir::Right)),
+            PreviousWord => self.for_each_caret(|b| b.move_cursor_by_word(CursorDir::Left)),
+            EndOfWord => self.for_each_caret(TextBuffer::move_cursor_to_word_end),
+            NextParagraph => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
+            PreviousParagraph => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
+            TopOfFile => {
+                self.record_jump();
+                self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Up);
+            }
+            BottomOfFile => {
+                self.record_jump();
+                self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down);
+            }
+            DeleteChar if self.buf().rect_selection().is_some() => {
+                self.buf_mut().delete_rect_char()
+            }
+            DeleteChar => self.for_each_caret(TextBuffer::delete_char),
+            DeleteNextChar => self.for_each_caret(TextBuffer::delete_right_char),
+            DeleteWord if self.buf().rect_selection().is_some() => self.copy_rect(true)?,
+            DeleteWord if self.buf().has_selection() => {
+                let killed = self.buf_mut().delete_selection();
+                self.kill(killed, false, prev_cmd)?;
+            }
+            DeleteWord => {
+                let killed = self.buf_mut().delete_word();
+                self.kill(killed, true, prev_cmd)?;
+            }
+            DeleteToHead => {
+                let killed = self.buf_mut().delete_until_head_of_line();
+                self.kill(killed, true, prev_cmd)?;
+            }
+            DeleteToEnd => {
+                let killed = self.buf_mut().delete_until_end_of_line();
+                self.kill(killed, false, prev_cmd)?;
+            }
+            ToggleMark => self.toggle_mark(),
+            RectangleMark => self.toggle_rect_mark(),
+            Copy if self.buf().rect_selection().is_some() => self.copy_rect(false)?,
+            Copy if self.buf().has_selection() => self.copy_selection()?,
+            Copy => self.copy_line()?,
+            Paste => self.paste(),
+            Yank => self.yank(),
+            YankPop => self.yank_pop(prev_cmd),
+            Undo => self.undo(),
+            Redo => self.redo(),
+            Search => self.find(false)?,
+            RegexSearch => self.find(true)?,
+            Replace => self.replace()?,
+            Indent if self.buf().has_selection() => self.buf_mut().indent_lines(false),
+            Indent => self.buf_mut().insert_tab(),
+            Dedent => self.buf_mut().indent_lines(true),
+            ToggleComment => self.toggle_comment(),
+            AddCursor => self.add_cursor(),
+            RepeatEdit => self.repeat_edit()?,
+            DuplicateLine => self.buf_mut().duplicate_lines(),
+            MoveLineUp => self.buf_mut().move_lines(CursorDir::Up),
+            MoveLineDown => self.buf_mut().move_lines(CursorDir::Down),
+            NewLine => {
+                let cx = self.buf().cx();
+                self.expand_abbrev(cx);
+                self.for_each_caret(TextBuffer::insert_line_indented);
+            }
+            Refresh => self.screen.force_redraw(),
+            Suspend => self.suspend()?,
+            Shell => self.shell_command(false)?,
+            FilterSelection => self.shell_command(true)?,
+            Compile => self.compile()?,
+            NextError => self.jump_to_error(true)?,
+            PreviousError => self.jump_to_error(false)?,
+            NextHunk => self.jump_to_hunk(true),
+            PreviousHunk => self.jump_to_hunk(false),
+            DiffPut => self.diff_put(),
+            NextConflict => self.jump_to_conflict(true),
+            PreviousConflict => self.jump_to_conflict(false),
+            KeepOurs => self.resolve_conflict(Resolution::Ours),
+            KeepTheirs => self.resolve_conflict(Resolution::Theirs),
+            KeepBoth => self.resolve_conflict(Resolution::Both),
+            Blame => self.blame(false)?,
+            ShowCommit => self.blame(true)?,
+            ToggleSpellCheck => self.toggle_spell_check(),
+            SpellSuggest => self.spell_suggest(prev_cmd),
+            Complete => self.complete(prev_cmd),
+            LiteralInsert => {
+                self.literal = true;
+                self.screen
+                    .set_info_message("Next character is inserted literally");
+            }
+            FormatBuffer => self.format_buffer(),
+            RecentFiles => self.recent_files()?,
+            QuickOpen => self.quick_open()?,
+            Fold | Unfold | ToggleFold | FoldAll | UnfoldAll => self.fold(cmd),
+            ShowMessages => self.show_messages()?,
+            Follow => self.toggle_follow()?,
+            CenterLine => self.center_cursor(),
+            LineToTop => {
+                let buf = &self.bufs[self.buf_idx];
+                self.screen.top_on(buf.cy(), buf.folds());
+            }
+            LineToBottom => {
+                let buf = &self.bufs[self.buf_idx];
+                self.screen.bottom_on(buf.cy(), buf.folds());
+            }
+            EndOfScreenLine => self.end_of_screen_line(),
+            ToggleInvisibles => self.toggle_invisibles(),
+            ToggleRainbowBrackets => self.toggle_rainbow_brackets(),
+            ToggleIndentGuides => self.toggle_indent_guides(),
+            Help => self.show_help()?,
+            SetLanguage => self.set_language()?,
+            SetTheme => self.set_theme()?,
+            SetTabWidth => self.set_tab_width()?,
+            ToggleExpandTab => self.toggle_expand_tab(),
+            SetIndent => self.set_indent()?,
+            SetEncoding => self.set_encoding()?,
+            GoToOffset => self.go_to_offset()?,
+            FindFile => self.find_file()?,
+            GoToLine => self.go_to_line()?,
+            JumpBack => self.jump_back(),
+            JumpForward => self.jump_forward(),
+            SetBookmark => self.set_mark()?,
+            GoToBookmark => self.go_to_mark()?,
+            MatchingBracket => self.jump_to_bracket(),
+        }
+        Ok(())
+    }
+
+    fn process_keypress(&mut self, s: InputSeq) -> Result<bool> {
+        use KeySeq::*;
+
         let (prev_cx, prev_cy) = (self.buf().cx(), self.buf().cy());
         self.buf_mut().dirty_start = None;
 
-        match &s {
-            InputSeq {
-                key: Unidentified, ..
-            } => return Ok(false),
-            InputSeq { key, alt: true, .. } => match key {
-                Key(b'v') => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
-                Key(b'f') => self.buf_mut().move_cursor_by_word(CursorDir::Right),
-                Key(b'b') => self.buf_mut().move_cursor_by_word(CursorDir::Left),
-                Key(b'n') => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
-                Key(b'p') => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
-                Key(b'x') => self.previous_buffer()?,
-                Key(b'<') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Up),
-                Key(b'>') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down),
-                LeftKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                RightKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                _ => self.handle_not_mapped(s),
-            },
-            InputSeq {
-                key, ctrl: true, ..
-            } => match key {
-                Key(b'p') => self.buf_mut().move_cursor_one(CursorDir::Up),
-                Key(b'b') => self.buf_mut().move_cursor_one(CursorDir::Left),
-                Key(b'n') => self.buf_mut().move_cursor_one(CursorDir::Down),
-                Key(b'f') => self.buf_mut().move_cursor_one(CursorDir::Right),
-                Key(b'v') => self
-                    .buf_mut()
-                    .move_cursor_page(CursorDir::Down, rowoff, rows),
-                Key(b'a') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                Key(b'e') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                Key(b'd') => self.buf_mut().delete_right_char(),
-                Key(b'g') => self.find()?,
-                Key(b'h') => self.buf_mut().delete_char(),
-                Key(b'k') => self.buf_mut().delete_until_end_of_line(),
-                Key(b'j') => self.buf_mut().delete_until_head_of_line(),
-                Key(b'w') => self.buf_mut().delete_word(),
-                Key(b'l') => self.screen.set_dirty_start(self.screen.rowoff), // Clear
-                Key(b's') => self.save()?,
-                Key(b'i') => self.buf_mut().insert_tab(),
-                Key(b'm') => self.buf_mut().insert_line(),
-                Key(b'o') => self.open_buffer()?,
-                Key(b'?') => self.show_help()?,
-                Key(b'x') => self.next_buffer()?,
-                Key(b']') => self
-                    .buf_mut()
-                    .move_cursor_page(CursorDir::Down, rowoff, rows),
-                LeftKey => self.buf_mut().move_cursor_by_word(CursorDir::Left),
-                RightKey => self.buf_mut().move_cursor_by_word(CursorDir::Right),
-                DownKey => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
-                UpKey => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
-                Key(b'q') => return self.handle_quit(),
-                _ => self.handle_not_mapped(s),
-            },
-            InputSeq { key, .. } => match key {
-                Key(0x1b) => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows), // Clash with Ctrl-[
-                Key(0x08) => self.buf_mut().delete_char(), // Backspace
-                Key(0x7f) => self.buf_mut().delete_char(), // Delete key is mapped to \x1b[3~
-                Key(b'\r') => self.buf_mut().insert_line(),
-                Key(b) if !b.is_ascii_control() => self.buf_mut().insert_char(*b as char),
-                Utf8Key(c) => self.buf_mut().insert_char(*c),
-                UpKey => self.buf_mut().move_cursor_one(CursorDir::Up),
-                LeftKey => self.buf_mut().move_cursor_one(CursorDir::Left),
-                DownKey => self.buf_mut().move_cursor_one(CursorDir::Down),
-                RightKey => self.buf_mut().move_cursor_one(CursorDir::Right),
-                PageUpKey => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
-                PageDownKey => self
-                    .buf_mut()
-                    .move_cursor_page(CursorDir::Down, rowoff, rows),
-                HomeKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                EndKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                DeleteKey => self.buf_mut().delete_right_char(),
-                Cursor(_, _) => unreachable!(),
-                _ => self.handle_not_mapped(s),
-            },
+        if s.key == Unidentified {
+            return Ok(false);
+        }
+        let prev_mode = self.vi.as_ref().map(Vi::mode);
+        let literal = self.literal;
+        let completed = self.handle_completion_key(&s);
+        let typed = match (&s.key, s.ctrl, s.alt) {
+            (Key(b), false, false) => Some(*b as char),
+            (Utf8Key(c), false, false) => Some(*c),
+            _ => None,
+        };
+        let action = match &mut self.vi {
+            Some(vi) if !completed && !self.view => vi.translate(&s),
+            _ => vi::Action::Pass,
+        };
+        let cmd = if action == vi::Action::Pass && !completed {
+            let paged = Some(&s).filter(|_| self.view).and_then(pager::translate);
+            paged.or_else(|| self.keymap.lookup(&s))
+        } else {
+            None
+        };
+
+        // Shift + cursor keys extend selected region
+        let moving = cmd.map(Command::moves_cursor).unwrap_or(false);
+        if moving && s.shift {
+            if !self.buf().has_selection() {
+                self.buf_mut().set_anchor();
+                self.selecting_by_shift = true;
+            }
+        } else if moving && self.selecting_by_shift {
+            self.buf_mut().clear_anchor();
         }
 
-        if let Some(line) = self.buf().dirty_start {
-            self.hl.needs_update = true;
-            self.screen.set_dirty_start(line);
+        match cmd {
+            _ if action != vi::Action::Pass => {
+                self.last_cmd = None;
+                self.run_vi_action(action, s)?;
+            }
+            Some(Command::Quit) => return self.handle_quit(),
+            Some(Command::Palette) => {
+                if let Some(name) = self.command_palette()? {
+                    match Command::from_name(&name) {
+                        Some(Command::Quit) => return self.handle_quit(),
+                        Some(cmd) => self.run_command(cmd)?,
+                        None => self.run_plugin_command(&name)?,
+                    }
+                }
+            }
+            Some(cmd) => self.run_command(cmd)?,
+            None if completed => {}
+            // Keys not bound to builtin commands may be bound to commands of plugins
+            None if action == vi::Action::Pass && self.keymap.lookup_plugin(&s).is_some() => {
+                let name = self.keymap.lookup_plugin(&s).unwrap().to_string();
+                self.run_plugin_command(&name)?;
+            }
+            None => {
+                self.last_cmd = None;
+                match &s {
+                    InputSeq {
+                        key,
+                        ctrl: false,
+                        alt: false,
+                        ..
+                    } => match key {
+                        Key(b) if !b.is_ascii_control() => {
+                            if self.check_writable() {
+                                self.type_char(*b as char);
+                            }
+                        }
+                        Utf8Key(c) => {
+                            if self.check_writable() {
+                                self.type_char(*c);
+                            }
+                        }
+                        Cursor(_, _) => unreachable!(),
+                        MouseClick(row, col, 0) => self.click_at(*row, *col),
+                        MouseClick(..) => {} // Other buttons are not used
+                        MouseScroll(dir) => self.scroll_screen(*dir),
+                        _ => self.handle_not_mapped(s),
+                    },
+                    _ => self.handle_not_mapped(s),
+                }
+            }
+        }
+
+        // Edits by one key input are undone at once
+        let edit = self.current_edit.take();
+        self.buf_mut().finish_edit(edit.clone());
+        if cmd == Some(Command::RepeatEdit) {
+            self.inserting = false; // Repeated text is not merged with next typing
+        } else {
+            self.remember_edit(edit);
+        }
+        if !self.buf().has_selection() {
+            self.selecting_by_shift = false;
         }
+        let mode = self.vi.as_ref().map(Vi::mode);
+        if mode != prev_mode {
+            self.screen.set_info_message(match mode {
+                Some(Mode::Normal) | None => "-- NORMAL --",
+                Some(Mode::Insert) => "-- INSERT --",
+                Some(Mode::Visual) => "-- VISUAL --",
+            });
+        }
+
+        if literal {
+            self.literal = false; // Only the key after literal-insert is affected
+        }
+        if let Some(c) = typed.filter(|_| !completed) {
+            self.update_completion(c);
+        }
+        self.sync_buffer_dirty();
         if self.buf().cx() != prev_cx || self.buf().cy() != prev_cy {
             self.screen.cursor_moved = true;
         }
@@ -484,21 +3406,470 @@ where
         Ok(false)
     }
 
-    pub fn edit(&mut self) -> io::Result<()> {
+    // Frontends which cannot block on reading input (e.g. wasm) call `start`, `handle_input` for
+    // each input and `finish` instead of `edit`
+    pub fn start(&mut self) -> Result<()> {
+        // Plugins were loaded before starting. Keys configured for commands which no plugin
+        // provides are reported
+        let unknown = self
+            .keymap
+            .plugin_commands()
+            .find(|name| !self.plugins.has_command(name));
+        if let Some(name) = unknown {
+            let msg = format!("Unknown command '{}' in [keymap]", name);
+            self.screen.set_error_message(msg);
+        }
         self.refresh_screen()?; // First paint
 
-        while let Some(seq) = self.input.next() {
-            if self.screen.maybe_resize(&mut self.input)? {
-                self.refresh_screen()?;
+        for idx in 0..self.bufs.len() {
+            self.load_marks(idx);
+            if self.bufs[idx].has_swap() {
+                self.buf_idx = idx;
+                self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+                self.recover_swap()?;
             }
+        }
+        if self.buf_idx != 0 {
+            self.buf_idx = 0;
+            self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+            self.reset_screen()?;
+        }
+        Ok(())
+    }
 
-            if self.process_keypress(seq?)? {
-                break;
+    // Read the next chunk of the large file in background. Chunks are read one by one so that
+    // each of them is appended in order
+    fn load_rest(&mut self, idx: usize) {
+        let buf = &self.bufs[idx];
+        let (offset, path) = match (buf.stream_offset(), buf.file_path()) {
+            (Some(offset), Some(path)) => (offset, path.to_path_buf()),
+            _ => return,
+        };
+        let tab_width = buf.tab_width();
+        let map = buf.mapping().cloned();
+        self.events.spawn(move || {
+            let chunk = match map {
+                Some(map) => stream::map_chunk(&map, offset, stream::CHUNK_SIZE, tab_width),
+                None => stream::read_chunk(&path, offset, stream::CHUNK_SIZE, tab_width),
+            };
+            TaskResult::Load(path, chunk)
+        });
+    }
+
+    // Rows of a buffer opened with memory map must not refer to the map after the file was
+    // modified by other process. Such a buffer has no unsaved changes since its lines are copied on
+    // the first edit, so it is loaded again
+    fn check_mapped_files(&mut self) -> Result<()> {
+        for idx in 0..self.bufs.len() {
+            if !self.bufs[idx].mapping_changed() {
+                continue;
             }
+            let name = self.bufs[idx].filename().to_string();
+            self.reload_buffer(idx)?;
+            self.screen
+                .set_info_message(format!("{} was changed on disk. Reloaded", name));
+        }
+        Ok(())
+    }
 
-            self.refresh_screen()?;
+    // Follow lines appended to the file like `tail -f`. New lines are shown while the cursor is at
+    // the bottom. Moving the cursor up pauses it and moving back to the bottom resumes it
+    fn toggle_follow(&mut self) -> Result<()> {
+        let name = self.buf().filename().to_string();
+        if self.buf().follow_offset().is_some() {
+            self.buf_mut().set_follow(None);
+            self.screen
+                .set_info_message(format!("Stopped following {}", name));
+            return Ok(());
+        }
+        if !self.buf().has_file() || self.buf().is_directory() {
+            self.screen
+                .set_error_message("Buffer has no file to follow");
+            return Ok(());
+        }
+        if self.buf().stream_offset().is_some() {
+            self.screen.set_error_message("File is still being loaded");
+            return Ok(());
+        }
+        if self.buf().modified() {
+            self.screen
+                .set_error_message("Could not follow: buffer has unsaved changes");
+            return Ok(());
+        }
+        self.start_follow(self.buf_idx);
+        self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down);
+        self.screen
+            .set_info_message(format!("Following {}. Move cursor up to pause", name));
+        Ok(())
+    }
+
+    fn start_follow(&mut self, idx: usize) {
+        let buf = &mut self.bufs[idx];
+        let len = match buf.file_path().and_then(|p| p.metadata().ok()) {
+            Some(meta) => meta.len(),
+            None => return,
+        };
+        // Growing file must not be reloaded as a changed mapped file
+        buf.copy_mapped();
+        buf.set_follow(Some(len));
+    }
+
+    // Read lines appended to the followed files. A truncated file such as a rotated log is loaded
+    // again
+    fn follow_files(&mut self) -> Result<()> {
+        for idx in 0..self.bufs.len() {
+            let buf = &self.bufs[idx];
+            let (offset, path) = match (buf.follow_offset(), buf.file_path()) {
+                (Some(offset), Some(path)) => (offset, path.to_path_buf()),
+                _ => continue,
+            };
+            let len = match path.metadata() {
+                Ok(meta) => meta.len(),
+                Err(_) => continue, // The file may be being replaced
+            };
+            if len < offset {
+                self.reload_buffer(idx)?;
+                self.start_follow(idx);
+                self.bufs[idx].move_cursor_to_buffer_edge(CursorDir::Down);
+                self.screen.set_info_message(format!(
+                    "{} was truncated. Reloaded",
+                    self.bufs[idx].filename()
+                ));
+                continue;
+            }
+            if len == offset {
+                continue;
+            }
+            let chunk = match stream::read_appended(&path, offset, buf.tab_width()) {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    self.screen.set_error_message(err.to_string());
+                    self.bufs[idx].set_follow(None);
+                    continue;
+                }
+            };
+            let y = self.bufs[idx].rows().len();
+            if self.bufs[idx].append_followed(chunk) > 0 {
+                self.on_lines_appended(idx, y);
+            }
+        }
+        Ok(())
+    }
+
+    // Changes of the file by other processes are notified as events. Watching again after saving
+    // the file ignores the change by the save
+    fn watch_file(&mut self, idx: usize) {
+        let buf = &self.bufs[idx];
+        if buf.is_directory() {
+            return;
+        }
+        if let Some(path) = buf.file_path() {
+            self.events.watch(path);
+        }
+    }
+
+    // Handle a request from a client connected to the socket. Failures are replied to the client.
+    // Returns true when the editor quits
+    fn on_remote_call(&mut self, call: Call) -> Result<bool> {
+        let response = match call.request.clone() {
+            Request::Open { files, wait } => {
+                let cwd = env::current_dir().unwrap_or_default();
+                let mut opened = vec![];
+                let mut result = Ok(None);
+                for (path, line) in files {
+                    // Paths are absolute. Files under the current directory are opened with
+                    // relative paths as they are opened from the command line
+                    let path = Path::new(&path);
+                    let path = path.strip_prefix(&cwd).unwrap_or(path).to_path_buf();
+                    if let Err(err) = self.open_path(&path) {
+                        result = Err(err.to_string());
+                        break;
+                    }
+                    if let Some(line) = line {
+                        self.jump(&Jump::Line(line));
+                    }
+                    opened.push(path);
+                }
+                if wait && result.is_ok() {
+                    self.waiting.push((opened, call));
+                    return Ok(false);
+                }
+                result
+            }
+            Request::Run(name) => match Command::from_name(&name) {
+                Some(Command::Quit) => {
+                    let quit = self.handle_quit()?;
+                    call.reply(Ok(None));
+                    return Ok(quit);
+                }
+                Some(Command::Palette) => {
+                    Err(Error::other("Command palette cannot be run remotely"))
+                }
+                Some(cmd) => self.run_command(cmd).map(|()| None),
+                None if self.plugins.has_command(&name) => {
+                    self.run_plugin_command(&name).map(|()| None)
+                }
+                None => Err(Error::other(format!("Unknown command '{}'", name))),
+            }
+            .map_err(|err| err.to_string()),
+            Request::Text(name) => {
+                let idx = match &name {
+                    Some(name) => self.find_buffer(name),
+                    None => Some(self.buf_idx),
+                };
+                match idx {
+                    Some(idx) => Ok(Some(self.bufs[idx].lines().collect::<Vec<_>>().join("\n"))),
+                    None => Err(format!("No buffer '{}'", name.unwrap_or_default())),
+                }
+            }
+        };
+        call.reply(response);
+        Ok(false)
+    }
+
+    // Reply to clients waiting for the current buffer when all of their files were saved
+    fn reply_saved(&mut self) {
+        let buf = &self.bufs[self.buf_idx];
+        for (files, _) in self.waiting.iter_mut() {
+            files.retain(|f| !buf.is_file(f));
+        }
+        let (done, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(files, _)| files.is_empty());
+        self.waiting = waiting;
+        for (_, call) in done {
+            call.reply(Ok(None));
+        }
+    }
+
+    // A buffer without unsaved changes is reverted to the changed file with `auto_revert` option.
+    // Followed files are not reverted since appending to them is expected
+    fn on_file_changed(&mut self, path: &Path) -> Result<()> {
+        let idx = match self.bufs.iter().position(|b| b.is_file(path)) {
+            Some(idx) => idx,
+            None => {
+                self.events.unwatch(path);
+                return Ok(());
+            }
+        };
+        let buf = &self.bufs[idx];
+        if buf.follow_offset().is_some() {
+            return Ok(());
+        }
+        let name = buf.filename().to_string();
+        if !path.exists() {
+            self.screen
+                .set_error_message(format!("{} was removed on disk", name));
+        } else if buf.modified() {
+            self.screen.set_error_message(format!(
+                "{} was changed on disk but the buffer has unsaved changes",
+                name
+            ));
+        } else if self.options.auto_revert {
+            self.reload_buffer(idx)?;
+            self.screen
+                .set_info_message(format!("Reverted {} changed on disk", name));
+        } else {
+            self.screen
+                .set_info_message(format!("{} was changed on disk", name));
+        }
+        Ok(())
+    }
+
+    // Key input read directly while waiting for an answer. Mapped files may be changed while
+    // waiting for the input
+    fn next_input(&mut self) -> Option<Result<InputSeq>> {
+        let seq = match self.events.next_input()? {
+            // Report of the probe is not a key input to prompts
+            Ok(seq) if self.screen.maybe_cursor_report(&seq) => {
+                Ok(InputSeq::new(KeySeq::Unidentified))
+            }
+            seq => seq.map_err(Error::Terminal),
+        };
+        if let Err(err) = self.check_mapped_files() {
+            return Some(Err(err));
+        }
+        Some(seq)
+    }
+
+    // Read the file of the buffer again. The cursor position is kept as much as possible
+    fn reload_buffer(&mut self, idx: usize) -> Result<()> {
+        let path = match self.bufs[idx].file_path() {
+            Some(path) => path.to_path_buf(),
+            None => return Ok(()),
+        };
+        let mut buf = self.open_file(&path)?;
+        let old = &self.bufs[idx];
+        let y = old.cy().min(buf.rows().len().saturating_sub(1));
+        let x = buf
+            .rows()
+            .get(y)
+            .map(|r| old.cx().min(r.len()))
+            .unwrap_or(0);
+        buf.set_cursor(x, y);
+        buf.set_readonly(old.readonly_by_user());
+        self.bufs[idx] = buf;
+        self.load_rest(idx);
+        self.watch_file(idx);
+        if idx == self.buf_idx {
+            self.hl = Highlighting::new(self.buf().lang(), self.buf().rows());
+            self.screen.set_dirty_start(self.screen.rowoff);
+        }
+        Ok(())
+    }
+
+    // Lines were appended at `y` out of key inputs. Highlights are updated from the top of buffer,
+    // so lines appended below the screen do not need to update them
+    fn on_lines_appended(&mut self, idx: usize, y: usize) {
+        if idx == self.buf_idx && y < self.screen.bottom_line(self.buf()) {
+            self.sync_buffer_dirty();
+        }
+    }
+
+    fn on_chunk_loaded(&mut self, path: &Path, chunk: Result<Chunk>) {
+        // The buffer may be closed while loading
+        let idx = match self.bufs.iter().position(|b| b.is_file(path)) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                self.screen.set_error_message(format!(
+                    "Could not load {}: {}",
+                    path.display(),
+                    err
+                ));
+                return;
+            }
+        };
+        let y = self.bufs[idx].rows().len();
+        self.bufs[idx].append_chunk(chunk);
+        self.on_lines_appended(idx, y);
+        if self.bufs[idx].stream_offset().is_some() {
+            self.load_rest(idx);
+        } else {
+            let msg = format!(
+                "Loaded {} lines of {}",
+                self.bufs[idx].rows().len(),
+                self.bufs[idx].filename()
+            );
+            self.screen.set_info_message(msg);
+        }
+    }
+
+    fn on_task_done(&mut self, result: TaskResult) -> Result<()> {
+        match result {
+            TaskResult::Compile(cmdline, result) => self.on_compile_done(&cmdline, result),
+            TaskResult::Load(path, chunk) => {
+                self.on_chunk_loaded(&path, chunk);
+                Ok(())
+            }
+        }
+    }
+
+    // Returns true when the editor quits
+    pub fn handle_event(&mut self, event: Event) -> Result<bool> {
+        // Mapped files are checked before lines are read
+        self.check_mapped_files()?;
+        // Input times out every 100ms even if no key is pressed. It drives the timer
+        self.write_swap_files();
+        self.follow_files()?;
+
+        let handled = match event {
+            Event::Input(seq) if self.screen.maybe_resize(&seq) => Ok(false),
+            Event::Input(seq) if self.screen.maybe_cursor_report(&seq) => Ok(false),
+            Event::Input(seq) => self.process_keypress(seq),
+            Event::Tick => Ok(false),
+            Event::Task(result) => self.on_task_done(result).map(|()| false),
+            Event::FileChanged(path) => self.on_file_changed(&path).map(|()| false),
+            Event::Remote(call) => self.on_remote_call(call),
+        };
+        match handled {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            // Errors such as a file which cannot be opened are shown and the editor keeps running
+            Err(err) if !err.is_fatal() => self.screen.set_error_message(err.to_string()),
+            Err(err) => return Err(err),
+        }
+
+        self.refresh_screen()?;
+        Ok(false)
+    }
+
+    // For frontends which push key inputs instead of the editor pulling them. Tasks which finished
+    // before the input are handled first. Returns true when the editor quits
+    pub fn handle_input(&mut self, seq: InputSeq) -> Result<bool> {
+        while let Some(result) = self.events.finished_task() {
+            if self.handle_event(Event::Task(result))? {
+                return Ok(true);
+            }
+        }
+        let event = if seq.key == KeySeq::Unidentified {
+            match self.events.changed_file() {
+                Some(path) => Event::FileChanged(path),
+                None => Event::Tick,
+            }
+        } else {
+            Event::Input(seq)
+        };
+        self.handle_event(event)
+    }
+
+    // Save modified buffers to '*.recovered' files after a panic was caught so that the work is not
+    // lost. Returns messages to be printed since the screen is no longer available
+    pub fn recover(&mut self) -> Vec<String> {
+        // The file itself is not overwritten since the contents may be broken by the panic
+        let mut unnamed = 0;
+        let mut messages = vec![];
+        for buf in self.bufs.iter().filter(|b| b.modified()) {
+            let path = match buf.file_path() {
+                Some(path) => {
+                    let mut path = path.as_os_str().to_os_string();
+                    path.push(".recovered");
+                    PathBuf::from(path)
+                }
+                None => {
+                    unnamed += 1;
+                    PathBuf::from(format!("kiro-unnamed-{}.recovered", unnamed))
+                }
+            };
+            messages.push(match buf.write_copy(&path) {
+                Ok(()) => format!(
+                    "Unsaved {} was written to {}",
+                    buf.filename(),
+                    path.display()
+                ),
+                Err(err) => format!("Could not recover {}: {}", buf.filename(), err),
+            });
+        }
+        messages
+    }
+
+    pub fn finish(&mut self) {
+        // Clients waiting for files are released since nothing will be saved anymore
+        for (_, call) in self.waiting.drain(..) {
+            call.reply(Ok(None));
+        }
+        // Unsaved changes were discarded intentionally on quit
+        for buf in self.bufs.iter_mut() {
+            buf.remove_swap();
         }
+        // Positions of marks may be moved by edits
+        for idx in 0..self.bufs.len() {
+            self.save_marks(idx);
+        }
+        self.save_session();
+    }
 
+    pub fn edit(&mut self) -> Result<()> {
+        self.start()?;
+        while let Some(event) = self.events.next_event() {
+            if self.handle_event(event?)? {
+                break;
+            }
+        }
+        self.finish();
         Ok(())
     }
 
@@ -514,3 +3885,17 @@ where
         self.buf().lang()
     }
 }
+
+impl<R: Read, W: Write> Editor<ReaderInputs<R>, W> {
+    // Editor without terminal. Key inputs are decoded from bytes read from the reader, and the
+    // screen in the size of `headless::WINDOW_SIZE` is rendered to the writer. System clipboard is
+    // not used
+    pub fn with_io(reader: R, writer: W) -> Result<Self> {
+        Self::new(
+            ReaderInputs::new(reader),
+            writer,
+            Some(headless::WINDOW_SIZE),
+            Clipboard::default(),
+        )
+    }
+}
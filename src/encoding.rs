@@ -0,0 +1,119 @@
+// Text encodings of files. Text buffer is always kept as UTF-8 so file contents are decoded on
+// loading and encoded again in the same encoding on saving.
+
+use crate::sjis;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    ShiftJis,
+    Latin1,
+}
+
+const BOM_UTF8: &[u8] = &[0xef, 0xbb, 0xbf];
+const BOM_UTF16LE: &[u8] = &[0xff, 0xfe];
+const BOM_UTF16BE: &[u8] = &[0xfe, 0xff];
+
+impl Encoding {
+    pub const ALL: &'static [Encoding] = &[
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::ShiftJis,
+        Encoding::Latin1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::ShiftJis => "shift_jis",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        let name = name.to_ascii_lowercase();
+        Encoding::ALL.iter().find(|e| e.name() == name).copied()
+    }
+
+    pub fn is_utf16(self) -> bool {
+        self == Encoding::Utf16Le || self == Encoding::Utf16Be
+    }
+
+    // Byte order mark put at head of file
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => BOM_UTF8,
+            Encoding::Utf16Le => BOM_UTF16LE,
+            Encoding::Utf16Be => BOM_UTF16BE,
+            Encoding::Utf8 | Encoding::ShiftJis | Encoding::Latin1 => &[],
+        }
+    }
+
+    // UTF-16 is detected only by BOM. Bytes which are not valid UTF-8 are Shift_JIS when they look
+    // like Japanese text and otherwise considered Latin-1 since any byte sequence is valid in it
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        if bytes.starts_with(BOM_UTF8) {
+            Encoding::Utf8Bom
+        } else if bytes.starts_with(BOM_UTF16LE) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(BOM_UTF16BE) {
+            Encoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Encoding::Utf8
+        } else if sjis::is_likely(bytes) {
+            Encoding::ShiftJis
+        } else {
+            Encoding::Latin1
+        }
+    }
+
+    // Invalid sequences are replaced with U+FFFD
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks(2).map(|c| match (self, c) {
+                    (Encoding::Utf16Le, [lo, hi]) => u16::from_le_bytes([*lo, *hi]),
+                    (_, [hi, lo]) => u16::from_be_bytes([*hi, *lo]),
+                    _ => 0xfffd, // Odd length
+                });
+                char::decode_utf16(units)
+                    .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            Encoding::ShiftJis => sjis::decode(bytes),
+            Encoding::Latin1 => bytes.iter().map(|b| char::from(*b)).collect(),
+        }
+    }
+
+    // BOM is not included. Returns the first character which cannot be represented in the
+    // encoding on error
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, char> {
+        let mut bytes = Vec::with_capacity(text.len());
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Encoding::ShiftJis => return sjis::encode(text),
+            Encoding::Latin1 => {
+                for c in text.chars() {
+                    if c as u32 > 0xff {
+                        return Err(c);
+                    }
+                    bytes.push(c as u8);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
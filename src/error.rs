@@ -0,0 +1,124 @@
+// Errors of the editor. Each variant keeps what was being done when it failed so that the message
+// shown in the status bar tells the cause such as "Permission denied saving /etc/hosts" instead of
+// a bare OS error.
+//
+// I/O errors of files are wrapped with `Context::context`. I/O errors converted by `?` without
+// context are considered as failures of the terminal, after which the editor cannot continue.
+// Other errors are shown in the status bar and the editor keeps running.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    // Reading or writing a file failed. `action` describes the operation such as "saving"
+    Io {
+        source: io::Error,
+        action: &'static str,
+        path: PathBuf,
+    },
+    // Bytes which must be UTF-8 such as output of a command are not. It has what the bytes are
+    Utf8(String),
+    // A character of the buffer cannot be represented in the encoding of the file
+    Encode {
+        c: char,
+        encoding: &'static str,
+    },
+    // Config file or syntax file is broken
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    // External command could not run or exited with failure
+    Subprocess {
+        command: String,
+        message: String,
+    },
+    // Reading keys from or writing to the terminal failed
+    Terminal(io::Error),
+    // Request which cannot be done such as saving a read-only buffer
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn other<S: Into<String>>(msg: S) -> Self {
+        Error::Other(msg.into())
+    }
+
+    pub fn subprocess<S: Into<String>, M: fmt::Display>(command: S, message: M) -> Self {
+        Error::Subprocess {
+            command: command.into(),
+            message: message.to_string(),
+        }
+    }
+
+    // The editor cannot continue after this error
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::Terminal(_))
+    }
+}
+
+// Message of the OS error without its code such as "Permission denied" for "Permission denied (os
+// error 13)"
+fn describe(err: &io::Error) -> String {
+    let msg = err.to_string();
+    match msg.rfind(" (os error ") {
+        Some(idx) if msg.ends_with(')') => msg[..idx].to_string(),
+        _ => msg,
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io {
+                source,
+                action,
+                path,
+            } => write!(f, "{} {} {}", describe(source), action, path.display()),
+            Error::Utf8(what) => write!(f, "{} is not UTF-8", what),
+            Error::Encode { c, encoding } => write!(f, "{:?} cannot be encoded in {}", c, encoding),
+            Error::Config { path, message } => {
+                write!(f, "Could not load {}: {}", path.display(), message)
+            }
+            Error::Subprocess { command, message } => {
+                write!(f, "'{}' failed: {}", command, message)
+            }
+            Error::Terminal(err) => write!(f, "Terminal I/O failed: {}", describe(err)),
+            Error::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::Terminal(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Terminal(err)
+    }
+}
+
+// Attach the operation and the file to an I/O error
+pub trait Context<T> {
+    fn context<P: AsRef<Path>>(self, action: &'static str, path: P) -> Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context<P: AsRef<Path>>(self, action: &'static str, path: P) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            source,
+            action,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+}
@@ -0,0 +1,155 @@
+// Event loop of the editor. Key inputs, timer ticks and results of background tasks are multiplexed
+// into one stream of `Event` which the editor consumes in its main loop.
+//
+// Slow work such as running build commands or loading large files is done in background threads
+// so that the screen does not stall while it runs. Each task sends its result to a channel when
+// finished. Reading key inputs times out every 100ms, so finished tasks are received within the
+// interval even if no key is pressed. The timeout is also notified as `Event::Tick` which drives timers.
+// Requests from other processes are received in the same way while the editor listens on a socket.
+
+use crate::error::{Error, Result};
+use crate::input::{InputSeq, KeySeq};
+use crate::remote::Call;
+use crate::stream::Chunk;
+use crate::watcher::Watcher;
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+
+// Results of background tasks. Each task has its own variant
+pub enum TaskResult {
+    // Command line, and its output with whether it succeeded or error message
+    Compile(String, Result<(String, bool)>),
+    // Path of the large file and its lines read from the offset
+    Load(PathBuf, Result<Chunk>),
+}
+
+pub enum Event {
+    Input(InputSeq),
+    Tick, // No key was pressed in the interval
+    Task(TaskResult),
+    FileChanged(PathBuf), // Watched file was modified, truncated or removed by other process
+    Remote(Call),         // Request from other process through the socket
+}
+
+pub struct Events<I: Iterator<Item = io::Result<InputSeq>>> {
+    input: I,
+    sender: Sender<TaskResult>,
+    receiver: Receiver<TaskResult>,
+    running: usize,
+    watcher: Watcher,
+    changed: VecDeque<PathBuf>, // Changed files not notified yet
+    calls: Option<Receiver<Call>>,
+}
+
+impl<I> Events<I>
+where
+    I: Iterator<Item = io::Result<InputSeq>>,
+{
+    pub fn new(input: I) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            input,
+            sender,
+            receiver,
+            running: 0,
+            watcher: Watcher::default(),
+            changed: VecDeque::new(),
+            calls: None,
+        }
+    }
+
+    // Receive requests of clients connected to the socket
+    pub fn listen(&mut self, calls: Receiver<Call>) {
+        self.calls = Some(calls);
+    }
+
+    // Request which has already been received
+    pub fn remote_call(&mut self) -> Option<Call> {
+        self.calls.as_ref()?.try_recv().ok()
+    }
+
+    // Run the task in background. Its result is delivered as `Event::Task`
+    pub fn spawn<F>(&mut self, task: F)
+    where
+        F: FnOnce() -> TaskResult + Send + 'static,
+    {
+        self.running += 1;
+        let sender = self.sender.clone();
+        // Threads are not available in browser. The task is run in place and its result is
+        // delivered as other tasks
+        #[cfg(target_arch = "wasm32")]
+        let _ = sender.send(task());
+        #[cfg(not(target_arch = "wasm32"))]
+        thread::spawn(move || {
+            // Sending fails when the editor already quit. The result is no longer needed
+            let _ = sender.send(task());
+        });
+    }
+
+    // Result of a task which has already finished
+    pub fn finished_task(&mut self) -> Option<TaskResult> {
+        let result = self.receiver.try_recv().ok()?;
+        self.running -= 1;
+        Some(result)
+    }
+
+    // Block until one of the running tasks finishes. None when no task is running
+    pub fn wait_task(&mut self) -> Option<TaskResult> {
+        if self.running == 0 {
+            return None;
+        }
+        let result = self.receiver.recv().ok()?;
+        self.running -= 1;
+        Some(result)
+    }
+
+    // Notify changes of the file by other processes. Calling this again forgets its changes until
+    // now such as after saving the file
+    pub fn watch(&mut self, path: &Path) {
+        self.changed.retain(|p| p != path);
+        self.watcher.watch(path);
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.changed.retain(|p| p != path);
+        self.watcher.unwatch(path);
+    }
+
+    // Watched file changed since the previous call. Files are polled when no change is left
+    pub fn changed_file(&mut self) -> Option<PathBuf> {
+        if self.changed.is_empty() {
+            self.changed.extend(self.watcher.poll());
+        }
+        self.changed.pop_front()
+    }
+
+    // Read a key input directly. It is used while waiting for an answer such as in prompts. Results
+    // of tasks are kept until the main loop receives them
+    pub fn next_input(&mut self) -> Option<io::Result<InputSeq>> {
+        self.input.next()
+    }
+
+    // Next event of the main loop. Finished tasks are received prior to key inputs. When the input
+    // stream ends, it waits for running tasks so that their results are not lost
+    pub fn next_event(&mut self) -> Option<Result<Event>> {
+        if let Some(result) = self.finished_task() {
+            return Some(Ok(Event::Task(result)));
+        }
+        if let Some(call) = self.remote_call() {
+            return Some(Ok(Event::Remote(call)));
+        }
+        match self.input.next() {
+            Some(Ok(seq)) if seq.key == KeySeq::Unidentified => match self.changed_file() {
+                Some(path) => Some(Ok(Event::FileChanged(path))),
+                None => Some(Ok(Event::Tick)),
+            },
+            Some(Ok(seq)) => Some(Ok(Event::Input(seq))),
+            Some(Err(err)) => Some(Err(Error::Terminal(err))),
+            None => self.wait_task().map(|r| Ok(Event::Task(r))),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Fuzzy file finder. Files under the current directory are collected recursively and filtered by
+// typed text with fuzzy matching in the picker. Files and directories ignored by .gitignore are
+// skipped.
+//
+// Supported syntax of .gitignore is a subset of Git's:
+//
+//   # comment
+//   *.o          # '*' and '?' match any characters except for '/'
+//   /target      # Leading '/' or '/' in the middle anchors the pattern to the directory
+//   build/       # Trailing '/' only matches directories
+//   docs/**/*.md # '**' matches any number of directories
+//   !keep.o      # Negate the pattern
+
+use std::fs;
+use std::path::Path;
+
+// Upper limit of collected files not to hang up in huge directory trees such as $HOME
+const MAX_FILES: usize = 100_000;
+
+fn glob_match(pat: &[char], text: &[char]) -> bool {
+    match pat {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "**/" also matches zero directories
+            let rest = rest.strip_prefix(&['/']).unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            let end = text.iter().position(|c| *c == '/').unwrap_or(text.len());
+            (0..=end).any(|i| glob_match(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => matches!(text, [c, ..] if *c != '/') && glob_match(rest, &text[1..]),
+        ['\\', c, rest @ ..] | [c, rest @ ..] => {
+            matches!(text, [t, ..] if t == c) && glob_match(rest, &text[1..])
+        }
+    }
+}
+
+struct Rule {
+    pattern: Vec<char>,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool, // Matched with the path from the directory instead of the file name
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            pattern: line.chars().collect(),
+            negate,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let target = if self.anchored {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        glob_match(&self.pattern, &target.chars().collect::<Vec<_>>())
+    }
+}
+
+// Rules in .gitignore of one directory
+struct Gitignore {
+    base: String, // Directory containing the .gitignore file relative to the root. Empty for root
+    rules: Vec<Rule>,
+}
+
+impl Gitignore {
+    fn parse(base: &str, source: &str) -> Gitignore {
+        Gitignore {
+            base: base.to_string(),
+            rules: source.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    // Returns None when no rule matches. Later rules take precedence
+    fn ignores(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let path = if self.base.is_empty() {
+            path
+        } else {
+            path.strip_prefix(&self.base)?.strip_prefix('/')?
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.matches(path, is_dir))
+            .map(|r| !r.negate)
+    }
+}
+
+// Rules of deeper directories take precedence
+fn is_ignored(ignores: &[Gitignore], path: &str, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|i| i.ignores(path, is_dir))
+        .unwrap_or(false)
+}
+
+fn walk_dir(root: &Path, rel: &str, ignores: &mut Vec<Gitignore>, files: &mut Vec<String>) {
+    let dir = root.join(rel);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return, // Unreadable directory is skipped
+    };
+    let has_gitignore = match fs::read_to_string(dir.join(".gitignore")) {
+        Ok(source) => {
+            ignores.push(Gitignore::parse(rel, &source));
+            true
+        }
+        Err(_) => false,
+    };
+
+    // Symbolic links to directories are not followed to avoid cycles
+    let mut entries: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|e| {
+            let is_dir = e.file_type().ok()?.is_dir();
+            Some((e.file_name().into_string().ok()?, is_dir))
+        })
+        .collect();
+    entries.sort();
+
+    for (name, is_dir) in entries {
+        if files.len() >= MAX_FILES {
+            break;
+        }
+        if name == ".git" {
+            continue;
+        }
+        let path = if rel.is_empty() {
+            name
+        } else {
+            format!("{}/{}", rel, name)
+        };
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk_dir(root, &path, ignores, files);
+        } else {
+            files.push(path);
+        }
+    }
+
+    if has_gitignore {
+        ignores.pop();
+    }
+}
+
+// Paths of files under the directory relative to it
+pub fn walk<P: AsRef<Path>>(root: P) -> Vec<String> {
+    let mut files = vec![];
+    walk_dir(root.as_ref(), "", &mut vec![], &mut files);
+    files
+}
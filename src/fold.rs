@@ -0,0 +1,293 @@
+// Code folding. A closed fold hides the lines after its first line until its last line, and the
+// first line is shown with a marker instead. Folds follow their lines when lines are inserted or
+// deleted above them, and a fold is opened when a line hidden by it is edited.
+//
+// Foldable regions are found from the text. A line ending with an unclosed bracket starts a region
+// until the line of the matching bracket. Otherwise a region continues while following lines are
+// indented deeper than the first line. Brackets in double-quoted strings are skipped, but comments
+// are not distinguished since highlights may not be calculated yet for the whole region.
+
+use crate::gap_buffer::GapBuffer;
+use crate::row::Row;
+use crate::undo::EditDiff;
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct Folds(Vec<(usize, usize)>); // Closed folds as (first, last) sorted by the first lines
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // At most one fold starts at the same line. Folds starting at different lines may be nested
+    pub fn close(&mut self, first: usize, last: usize) {
+        debug_assert!(first < last);
+        match self.0.binary_search_by_key(&first, |(f, _)| *f) {
+            Ok(i) => self.0[i].1 = last,
+            Err(i) => self.0.insert(i, (first, last)),
+        }
+    }
+
+    // Returns false when no fold starts at the line
+    pub fn open(&mut self, first: usize) -> bool {
+        let len = self.0.len();
+        self.0.retain(|(f, _)| *f != first);
+        self.0.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    // Last line hidden by the fold starting at the line
+    pub fn closed_at(&self, first: usize) -> Option<usize> {
+        self.0
+            .binary_search_by_key(&first, |(f, _)| *f)
+            .ok()
+            .map(|i| self.0[i].1)
+    }
+
+    fn hiding(&self, y: usize) -> impl Iterator<Item = &(usize, usize)> + '_ {
+        self.0
+            .iter()
+            .take_while(move |(f, _)| *f < y)
+            .filter(move |(_, l)| y <= *l)
+    }
+
+    pub fn is_hidden(&self, y: usize) -> bool {
+        self.hiding(y).next().is_some()
+    }
+
+    // Open all folds hiding the line. Returns true when some fold was opened
+    pub fn reveal(&mut self, y: usize) -> bool {
+        let len = self.0.len();
+        self.0.retain(|&(f, l)| !(f < y && y <= l));
+        self.0.len() != len
+    }
+
+    // The visible line which shows the line. A hidden line is shown by the first line of the
+    // outermost fold hiding it
+    pub fn visible_line(&self, mut y: usize) -> usize {
+        while let Some(first) = self.hiding(y).map(|(f, _)| *f).min() {
+            y = first;
+        }
+        y
+    }
+
+    // Next visible line after the line. The result may be the line past the end of buffer
+    pub fn next_line(&self, y: usize) -> usize {
+        let mut next = y + 1;
+        while let Some(last) = self.hiding(next).map(|(_, l)| *l).max() {
+            next = last + 1;
+        }
+        next
+    }
+
+    pub fn prev_line(&self, y: usize) -> usize {
+        self.visible_line(y.saturating_sub(1))
+    }
+
+    // Move the visible line down by n visible lines but not past the line `end`
+    pub fn down(&self, mut y: usize, n: usize, end: usize) -> usize {
+        if self.0.is_empty() {
+            return std::cmp::min(y + n, end);
+        }
+        for _ in 0..n {
+            if y >= end {
+                break;
+            }
+            y = std::cmp::min(self.next_line(y), end);
+        }
+        y
+    }
+
+    pub fn up(&self, mut y: usize, n: usize) -> usize {
+        if self.0.is_empty() {
+            return y.saturating_sub(n);
+        }
+        for _ in 0..n {
+            if y == 0 {
+                break;
+            }
+            y = self.prev_line(y);
+        }
+        y
+    }
+
+    // Number of visible lines in the range `start..end`
+    pub fn count(&self, start: usize, end: usize) -> usize {
+        if end <= start {
+            return 0;
+        }
+        // Hidden ranges of nested folds are merged
+        let mut hidden = 0;
+        let mut covered = start; // Lines before this line are already counted
+        for &(f, l) in &self.0 {
+            let (lo, hi) = (std::cmp::max(f + 1, covered), std::cmp::min(l + 1, end));
+            if lo < hi {
+                hidden += hi - lo;
+                covered = hi;
+            }
+        }
+        end - start - hidden
+    }
+
+    // Fold containing an edited line is opened. Folds below inserted or deleted lines are moved
+    pub fn on_diff(&mut self, diff: &EditDiff) {
+        match diff {
+            EditDiff::InsertLine(at, _) => {
+                let at = *at;
+                self.0.retain(|&(f, l)| !(f < at && at <= l));
+                for (f, l) in self.0.iter_mut().filter(|(f, _)| *f >= at) {
+                    *f += 1;
+                    *l += 1;
+                }
+            }
+            EditDiff::DeleteLine(at, _) => {
+                let at = *at;
+                self.0.retain(|&(f, l)| !(f <= at && at <= l));
+                for (f, l) in self.0.iter_mut().filter(|(f, _)| *f > at) {
+                    *f -= 1;
+                    *l -= 1;
+                }
+            }
+            diff => {
+                self.reveal(diff.line());
+            }
+        }
+    }
+}
+
+fn is_blank(row: &Row) -> bool {
+    row.buffer().trim().is_empty()
+}
+
+// Indentation in display width so that tabs and spaces are compared
+fn indent(row: &Row) -> usize {
+    let spaces = row
+        .buffer()
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count();
+    row.rx_from_cx(spaces)
+}
+
+const BRACKETS: &[(char, char)] = &[('{', '}'), ('[', ']'), ('(', ')')];
+
+// Brackets in the line except for ones in double-quoted strings
+fn brackets(line: &str) -> impl Iterator<Item = char> + '_ {
+    let mut in_str = false;
+    let mut escaped = false;
+    line.chars().filter(move |&c| {
+        if in_str {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+            return false;
+        }
+        if c == '"' {
+            in_str = true;
+            return false;
+        }
+        BRACKETS.iter().any(|&(o, e)| c == o || c == e)
+    })
+}
+
+// The last line of the region starting with the bracket left open at the end of the line
+fn bracket_region(rows: &GapBuffer<Row>, y: usize) -> Option<usize> {
+    let mut stack = vec![];
+    for c in brackets(rows[y].buffer()) {
+        if BRACKETS.iter().any(|&(o, _)| o == c) {
+            stack.push(c);
+        } else if let Some(&open) = stack.last() {
+            if BRACKETS.iter().any(|&(o, e)| o == open && e == c) {
+                stack.pop();
+            }
+        }
+    }
+    let open = *stack.last()?;
+    let close = BRACKETS.iter().find(|(o, _)| *o == open)?.1;
+    let mut depth = 0;
+    for (ey, row) in rows.iter().enumerate().skip(y + 1) {
+        for c in brackets(row.buffer()) {
+            if c == open {
+                depth += 1;
+            } else if c == close && depth > 0 {
+                depth -= 1;
+            } else if c == close {
+                // The line of the closing bracket is also hidden unless it continues like '} else {'
+                let rest = row.buffer().trim().trim_start_matches(close);
+                let last = if rest.chars().all(|c| ";,)]".contains(c)) {
+                    ey
+                } else {
+                    ey - 1
+                };
+                return Some(last).filter(|l| *l > y);
+            }
+        }
+    }
+    None
+}
+
+fn indent_region(rows: &GapBuffer<Row>, y: usize) -> Option<usize> {
+    let base = indent(&rows[y]);
+    let mut last = y;
+    for (i, row) in rows.iter().enumerate().skip(y + 1) {
+        if is_blank(row) {
+            continue;
+        }
+        if indent(row) <= base {
+            break;
+        }
+        last = i;
+    }
+    Some(last).filter(|l| *l > y)
+}
+
+// The last line of the foldable region starting at the line
+pub fn region_at(rows: &GapBuffer<Row>, y: usize) -> Option<usize> {
+    let row = rows.get(y)?;
+    if is_blank(row) {
+        return None;
+    }
+    bracket_region(rows, y).or_else(|| indent_region(rows, y))
+}
+
+// The innermost foldable region containing the line. A region containing the line starts at a line
+// whose indentation is not deeper than the lines between them
+pub fn enclosing_region(rows: &GapBuffer<Row>, y: usize) -> Option<(usize, usize)> {
+    if let Some(last) = region_at(rows, y) {
+        return Some((y, last));
+    }
+    let mut min_indent = rows
+        .get(y)
+        .filter(|r| !is_blank(r))
+        .map_or(usize::MAX, indent);
+    for first in (0..y).rev() {
+        let row = &rows[first];
+        if is_blank(row) {
+            continue;
+        }
+        let indent = indent(row);
+        if indent > min_indent {
+            continue;
+        }
+        min_indent = indent;
+        match region_at(rows, first) {
+            Some(last) if last >= y => return Some((first, last)),
+            _ if indent == 0 => return None, // Top level line outside regions
+            _ => {}
+        }
+    }
+    None
+}
+
+// All foldable regions including nested ones
+pub fn all_regions(rows: &GapBuffer<Row>) -> Vec<(usize, usize)> {
+    (0..rows.len())
+        .filter_map(|y| region_at(rows, y).map(|last| (y, last)))
+        .collect()
+}
@@ -0,0 +1,36 @@
+// Formatting buffers with external formatters. Text of the buffer is passed to stdin of the
+// formatter and its stdout is the formatted text. Formatters can be set per language in config:
+//
+//   [editor]
+//   format = { rust = "rustfmt --edition 2024", python = "ruff format -" }
+//   format_on_save = true
+//
+// The output is compared with the buffer line by line and only the changed lines are replaced.
+// Cursor position and marks on unchanged lines are kept, and formatting is undone at once.
+
+use crate::language::Language;
+
+pub fn default_command(lang: Language) -> Option<&'static str> {
+    match lang {
+        Language::Rust => Some("rustfmt --edition 2021"),
+        Language::Go => Some("gofmt"),
+        Language::C | Language::Cpp => Some("clang-format"),
+        Language::Python => Some("black -q -"),
+        Language::JavaScript => Some("prettier --parser babel"),
+        Language::Json => Some("prettier --parser json"),
+        Language::Yaml => Some("prettier --parser yaml"),
+        Language::Markdown => Some("prettier --parser markdown"),
+        _ => None,
+    }
+}
+
+// Lines of the formatter output. Newline at end of output and CR of CRLF are not a part of lines
+pub fn output_lines(output: &str) -> Vec<&str> {
+    let text = output.strip_suffix('\n').unwrap_or(output);
+    if text.is_empty() {
+        return vec![];
+    }
+    text.split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+        .collect()
+}
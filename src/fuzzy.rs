@@ -0,0 +1,59 @@
+// Fuzzy matching used for filtering candidates by typed text. Query matches a candidate when all
+// characters of the query appear in the candidate in the same order (case insensitive). e.g. "nb"
+// matches "next-buffer".
+
+fn is_boundary(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => c == '-' || c == '_' || c == ' ' || c == '/' || c == '.',
+    }
+}
+
+// Returns None when the query does not match. Larger score is better match. Consecutive matches
+// and matches at head of words get bonus so that "nb" prefers "next-buffer" to "unbind".
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut chars = candidate.chars().enumerate();
+    let mut prev_match = None;
+    let mut prev_char = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        loop {
+            let (i, c) = chars.next()?;
+            let is_match = c.to_lowercase().next() == Some(q);
+            if is_match {
+                score += 1;
+                if prev_match.map(|p| p + 1 == i).unwrap_or(false) {
+                    score += 5;
+                } else if is_boundary(prev_char) {
+                    score += 3;
+                } else {
+                    score -= 1; // Penalty for gap
+                }
+                prev_match = Some(i);
+            }
+            prev_char = Some(c);
+            if is_match {
+                break;
+            }
+        }
+    }
+
+    Some(score)
+}
+
+// Filter candidates by the query and returns indices of matched ones from best match. Candidates
+// which have the same score are sorted by length. Original order is kept when they also have the
+// same length
+pub fn filter<'a, I>(query: &str, candidates: I) -> Vec<usize>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut matched: Vec<_> = candidates
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, c)| score(query, c).map(|s| (s, c.chars().count(), i)))
+        .collect();
+    matched.sort_by_key(|(s, len, _)| (-s, *len));
+    matched.into_iter().map(|(_, _, i)| i).collect()
+}
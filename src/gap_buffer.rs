@@ -0,0 +1,125 @@
+use std::iter::{self, FromIterator};
+use std::ops::{Index, IndexMut};
+use std::slice;
+
+// Sequence of elements which can be inserted/removed efficiently around the position edited last
+// time. Text buffer edits lines around the cursor in most cases so inserting/removing a line does
+// not need to shift all lines after it as Vec does. It is important when editing a large file.
+//
+// Elements are stored in two stacks. `front` contains elements before the gap and `back` contains
+// elements after the gap in reverse order. Moving the gap costs O(distance).
+pub struct GapBuffer<T> {
+    front: Vec<T>,
+    back: Vec<T>,
+}
+
+pub type Iter<'a, T> = iter::Chain<slice::Iter<'a, T>, iter::Rev<slice::Iter<'a, T>>>;
+pub type IterMut<'a, T> = iter::Chain<slice::IterMut<'a, T>, iter::Rev<slice::IterMut<'a, T>>>;
+
+impl<T> Default for GapBuffer<T> {
+    fn default() -> Self {
+        GapBuffer {
+            front: vec![],
+            back: vec![],
+        }
+    }
+}
+
+impl<T> GapBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.front.len() + self.back.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.front.is_empty() && self.back.is_empty()
+    }
+
+    fn move_gap(&mut self, at: usize) {
+        while self.front.len() > at {
+            self.back.push(self.front.pop().unwrap());
+        }
+        while self.front.len() < at {
+            self.front.push(self.back.pop().unwrap());
+        }
+    }
+
+    pub fn insert(&mut self, at: usize, elem: T) {
+        assert!(
+            at <= self.len(),
+            "insert index {} out of {}",
+            at,
+            self.len()
+        );
+        self.move_gap(at);
+        self.front.push(elem);
+    }
+
+    pub fn remove(&mut self, at: usize) -> T {
+        assert!(at < self.len(), "remove index {} out of {}", at, self.len());
+        self.move_gap(at);
+        self.back.pop().unwrap()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        let front = self.front.len();
+        if idx < front {
+            Some(&self.front[idx])
+        } else {
+            let back = self.back.len();
+            if idx - front < back {
+                Some(&self.back[back - 1 - (idx - front)])
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        let front = self.front.len();
+        if idx < front {
+            Some(&mut self.front[idx])
+        } else {
+            let back = self.back.len();
+            if idx - front < back {
+                Some(&mut self.back[back - 1 - (idx - front)])
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.front.iter().chain(self.back.iter().rev())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.front.iter_mut().chain(self.back.iter_mut().rev())
+    }
+}
+
+impl<T> Index<usize> for GapBuffer<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        let len = self.len();
+        self.get(idx)
+            .unwrap_or_else(|| panic!("index {} out of {}", idx, len))
+    }
+}
+
+impl<T> IndexMut<usize> for GapBuffer<T> {
+    fn index_mut(&mut self, idx: usize) -> &mut T {
+        let len = self.len();
+        self.get_mut(idx)
+            .unwrap_or_else(|| panic!("index {} out of {}", idx, len))
+    }
+}
+
+impl<T> FromIterator<T> for GapBuffer<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        GapBuffer {
+            front: iter.into_iter().collect(),
+            back: vec![],
+        }
+    }
+}
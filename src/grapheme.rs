@@ -0,0 +1,146 @@
+// Segmentation of text into grapheme clusters, which are units of characters perceived as one
+// character such as 'é' written as 'e' and U+0301 (combining acute accent) or '👪' written as
+// '👨', '👩' and '👦' joined with U+200D (zero width joiner). The editor draws, moves the cursor
+// and deletes characters by clusters so that a cursor never stops at the middle of them.
+//
+// No crate for Unicode segmentation is available, so this implements a subset of the rules of
+// UAX #29 which covers the common cases:
+//
+//   - Zero-width characters such as combining marks and variation selectors extend the cluster
+//   - Emoji modifiers (skin tones U+1F3FB..U+1F3FF) extend the cluster
+//   - A non-ASCII character following U+200D (zero width joiner) is joined to the cluster
+//   - A pair of regional indicators is a cluster since it is rendered as a flag
+//   - Control characters such as tab are always a cluster by themselves
+//
+// Rules specific to scripts such as Indic conjuncts are not implemented.
+//
+// East Asian ambiguous width characters such as '○' and '→' are rendered in double width by some
+// terminals and in single width by others. Widths of them follow `ambiguous_width` option, which
+// is detected by asking the terminal the cursor position after printing one of them by default.
+
+use std::cell::Cell;
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AmbiguousWidth {
+    Auto,
+    Narrow,
+    Wide,
+}
+
+impl AmbiguousWidth {
+    pub fn from_name(name: &str) -> Option<AmbiguousWidth> {
+        match name {
+            "auto" => Some(AmbiguousWidth::Auto),
+            "narrow" => Some(AmbiguousWidth::Narrow),
+            "wide" => Some(AmbiguousWidth::Wide),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    // The width depends on the terminal, so it is shared by all rows. It is per thread since only
+    // the main thread computes widths, and tests running in parallel do not affect each other
+    static AMBIGUOUS_WIDE: Cell<bool> = const { Cell::new(true) };
+}
+
+pub fn set_ambiguous_wide(wide: bool) {
+    AMBIGUOUS_WIDE.with(|w| w.set(wide));
+}
+
+pub fn ambiguous_wide() -> bool {
+    AMBIGUOUS_WIDE.with(Cell::get)
+}
+
+// Display width of the character. None for control characters
+pub fn char_width(c: char) -> Option<usize> {
+    if ambiguous_wide() {
+        c.width_cjk()
+    } else {
+        c.width()
+    }
+}
+
+pub fn str_width(s: &str) -> usize {
+    graphemes(s).map(width).sum()
+}
+
+const ZWJ: char = '\u{200d}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+fn is_emoji_modifier(c: char) -> bool {
+    ('\u{1f3fb}'..='\u{1f3ff}').contains(&c)
+}
+
+fn is_extend(c: char) -> bool {
+    c.width() == Some(0) || is_emoji_modifier(c)
+}
+
+// Whether the character is a part of the cluster preceding it
+pub fn continues(cluster: &str, c: char) -> bool {
+    let last = match cluster.chars().next_back() {
+        Some(last) => last,
+        None => return false,
+    };
+    if last.is_control() || c.is_control() {
+        return false;
+    }
+    // Joiner between ASCII characters is not a part of emoji sequence
+    is_extend(c)
+        || last == ZWJ && !c.is_ascii()
+        || is_regional_indicator(c) && is_regional_indicator(last) && cluster.len() == 4
+}
+
+// Display width of the cluster. Characters after the first one do not take cells except that a
+// flag and an emoji with the presentation selector are rendered in double width. Control
+// characters have no width
+pub fn width(cluster: &str) -> usize {
+    let mut chars = cluster.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return 0,
+    };
+    let width = match char_width(first) {
+        Some(w) => w,
+        None => return 0,
+    };
+    let wide = is_regional_indicator(first) && chars.next().is_some()
+        || cluster.contains(EMOJI_PRESENTATION);
+    if wide {
+        width.max(2)
+    } else {
+        width
+    }
+}
+
+pub struct Graphemes<'a> {
+    text: &'a str,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut chars = self.text.char_indices();
+        let (_, first) = chars.next()?;
+        let mut end = first.len_utf8();
+        for (idx, c) in chars {
+            if !continues(&self.text[..idx], c) {
+                break;
+            }
+            end = idx + c.len_utf8();
+        }
+        let (cluster, rest) = self.text.split_at(end);
+        self.text = rest;
+        Some(cluster)
+    }
+}
+
+pub fn graphemes(text: &str) -> Graphemes<'_> {
+    Graphemes { text }
+}
@@ -0,0 +1,423 @@
+// Cell grid of terminal screen for double-buffered rendering. Screen draws a frame with escape
+// sequences as before, but they are not written to the terminal directly. They are interpreted into
+// cells on a copy of the previous frame, then only cells which differ from the previous frame are
+// written. It minimizes output on slow connections such as SSH where a full redraw flickers.
+//
+// Only sequences written by Screen are interpreted. Others are ignored:
+//
+//   ESC [ row ; col H   # Move cursor
+//   ESC [ K             # Erase to end of line with current background color
+//   ESC [ ... m         # Colors and attributes (SGR)
+
+use crate::grapheme;
+use std::io::{self, Write};
+
+// Unchanged cells between changed ones are written again when the gap is at most this width since
+// it is shorter than a sequence to move the cursor
+const MAX_GAP: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum Color {
+    #[default]
+    Default,
+    Code(u8),    // SGR parameter of 16 colors such as 91
+    Indexed(u8), // 256 colors
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    // `base` is 38 for foreground and 48 for background
+    fn write_param(self, base: u8, out: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Color::Default => Ok(()),
+            Color::Code(c) => write!(out, ";{}", c),
+            Color::Indexed(i) => write!(out, ";{};5;{}", base, i),
+            Color::Rgb(r, g, b) => write!(out, ";{};2;{};{};{}", base, r, g, b),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    underline: bool,
+    invert: bool,
+}
+
+impl Style {
+    // Apply parameters of SGR sequence
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(p) = params.next() {
+            match p {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                4 => self.underline = true,
+                7 => self.invert = true,
+                22 => self.bold = false,
+                24 => self.underline = false,
+                27 => self.invert = false,
+                30..=37 | 90..=97 => self.fg = Color::Code(p as u8),
+                40..=47 | 100..=107 => self.bg = Color::Code(p as u8),
+                39 => self.fg = Color::Default,
+                49 => self.bg = Color::Default,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(|i| Color::Indexed(i as u8)),
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => {
+                                Some(Color::Rgb(r as u8, g as u8, b as u8))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        if p == 38 {
+                            self.fg = color;
+                        } else {
+                            self.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Erased cells only have background color
+    fn erased(self) -> Style {
+        Style {
+            bg: self.bg,
+            ..Style::default()
+        }
+    }
+
+    // The sequence resets previous attributes so that the result does not depend on them
+    fn write_sequence(self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.extend_from_slice(b"\x1b[0");
+        self.fg.write_param(38, out)?;
+        self.bg.write_param(48, out)?;
+        for (enabled, param) in [
+            (self.bold, b";1"),
+            (self.underline, b";4"),
+            (self.invert, b";7"),
+        ] {
+            if enabled {
+                out.extend_from_slice(param);
+            }
+        }
+        out.push(b'm');
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Cell {
+    text: String, // Empty for the right half of a double-width character
+    style: Style,
+}
+
+impl Cell {
+    fn blank(style: Style) -> Cell {
+        Cell {
+            text: " ".to_string(),
+            style,
+        }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn width(&self, next: Option<&Cell>) -> usize {
+        if next.is_some_and(Cell::is_continuation) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Grid {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    // State of interpreting sequences. 0-based
+    x: usize,
+    y: usize,
+    style: Style,
+}
+
+impl Grid {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            cells: vec![Cell::blank(Style::default()); cols * rows],
+            x: 0,
+            y: 0,
+            style: Style::default(),
+        }
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+
+    // Characters on the row without styles. Trailing spaces are trimmed
+    pub fn text(&self, y: usize) -> String {
+        let text: String = self.row(y).iter().map(|c| c.text.as_str()).collect();
+        text.trim_end().to_string()
+    }
+
+    pub fn background(&self, x: usize, y: usize) -> Color {
+        self.row(y)[x].style.bg
+    }
+
+    fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.cols..(y + 1) * self.cols]
+    }
+
+    fn cell_mut(&mut self, x: usize) -> &mut Cell {
+        &mut self.cells[self.y * self.cols + x]
+    }
+
+    // Cell which the character before the cursor was put in. It is the left half when the
+    // character is double-width
+    fn prev_cell(&self) -> Option<usize> {
+        if self.x == 0 || self.x > self.cols {
+            return None;
+        }
+        let x = self.x - 1;
+        let cell = &self.cells[self.y * self.cols + x];
+        if cell.is_continuation() && x > 0 {
+            Some(x - 1)
+        } else {
+            Some(x)
+        }
+    }
+
+    // Characters beyond the right edge are dropped since Screen never relies on line wrapping
+    fn put(&mut self, c: char) {
+        if self.y >= self.rows {
+            return;
+        }
+        if let Some(x) = self.prev_cell() {
+            if grapheme::continues(&self.cell_mut(x).text, c) {
+                // A part of grapheme cluster such as combining character joins the previous cell
+                self.cell_mut(x).text.push(c);
+                let widened = grapheme::width(&self.cell_mut(x).text) == 2 && x + 1 == self.x;
+                if widened && self.x < self.cols {
+                    let style = self.style;
+                    let end = self.x + 1;
+                    if end < self.cols && self.cell_mut(end).is_continuation() {
+                        *self.cell_mut(end) = Cell::blank(style);
+                    }
+                    *self.cell_mut(self.x) = Cell {
+                        text: String::new(),
+                        style,
+                    };
+                    self.x += 1;
+                }
+                return;
+            }
+        }
+        let width = match grapheme::char_width(c) {
+            Some(0) => return, // Combining character at the start of line
+            Some(w) => w,
+            None => 1,
+        };
+        if self.x + width > self.cols {
+            self.x = self.cols;
+            return;
+        }
+        let x = self.x;
+        let style = self.style;
+        // Overwriting a half of double-width character breaks the other half
+        if self.cell_mut(x).is_continuation() && x > 0 {
+            *self.cell_mut(x - 1) = Cell::blank(style);
+        }
+        let end = x + width;
+        if end < self.cols && self.cell_mut(end).is_continuation() {
+            *self.cell_mut(end) = Cell::blank(style);
+        }
+        *self.cell_mut(x) = Cell {
+            text: c.to_string(),
+            style,
+        };
+        if width == 2 {
+            *self.cell_mut(x + 1) = Cell {
+                text: String::new(),
+                style,
+            };
+        }
+        self.x = end;
+    }
+
+    fn erase_line(&mut self) {
+        if self.y >= self.rows {
+            return;
+        }
+        let blank = Cell::blank(self.style.erased());
+        if self.x > 0 && self.x < self.cols && self.cell_mut(self.x).is_continuation() {
+            *self.cell_mut(self.x - 1) = blank.clone();
+        }
+        for x in self.x..self.cols {
+            *self.cell_mut(x) = blank.clone();
+        }
+    }
+
+    fn csi(&mut self, params: &str, cmd: char) {
+        let nums: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        match cmd {
+            'H' if !params.starts_with('?') => {
+                let at = |i: usize| nums.get(i).copied().unwrap_or(1).max(1) as usize - 1;
+                self.y = at(0);
+                self.x = at(1);
+            }
+            'K' => self.erase_line(),
+            'm' => self.style.apply(&nums),
+            _ => {}
+        }
+    }
+
+    // Interpret the output of Screen and update cells
+    pub fn apply(&mut self, bytes: &[u8]) {
+        let text = String::from_utf8_lossy(bytes);
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                self.put(c);
+                continue;
+            }
+            if chars.next() != Some('[') {
+                continue;
+            }
+            let mut params = String::new();
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    self.csi(&params, c);
+                    break;
+                }
+                params.push(c);
+            }
+        }
+    }
+
+    // Rest of the row from x when it can be drawn by erasing the line
+    fn erasable_rest(&self, y: usize, x: usize) -> Option<Style> {
+        let rest = &self.row(y)[x..];
+        let style = rest.first()?.style;
+        let erasable = style == style.erased()
+            && rest.len() > MAX_GAP
+            && rest.iter().all(|c| c.text == " " && c.style == style);
+        if erasable {
+            Some(style)
+        } else {
+            None
+        }
+    }
+
+    // Scroll lines of the top `height` rows by `n` lines with a scroll region. It is much shorter
+    // than writing the moved lines. Positive `n` moves the lines up as the view moves down. Lines
+    // scrolled in are filled with the background color of the current style
+    // https://vt100.net/docs/vt100-ug/chapter3.html#DECSTBM
+    pub fn scroll(&mut self, height: usize, n: isize, out: &mut Vec<u8>) -> io::Result<()> {
+        let height = height.min(self.rows);
+        let count = n.unsigned_abs();
+        if count == 0 || count >= height {
+            return Ok(());
+        }
+        self.style.write_sequence(out)?;
+        write!(out, "\x1b[1;{}r", height)?;
+        let blank = Cell::blank(self.style.erased());
+        let cols = self.cols;
+        let region = &mut self.cells[..height * cols];
+        if n > 0 {
+            // Index (ESC D) at the bottom margin scrolls up
+            write!(out, "\x1b[{};1H", height)?;
+            for _ in 0..count {
+                out.extend_from_slice(b"\x1bD");
+            }
+            region.rotate_left(count * cols);
+            region[(height - count) * cols..].fill(blank);
+        } else {
+            // Reverse index (ESC M) at the top margin scrolls down
+            out.extend_from_slice(b"\x1b[H");
+            for _ in 0..count {
+                out.extend_from_slice(b"\x1bM");
+            }
+            region.rotate_right(count * cols);
+            region[..count * cols].fill(blank);
+        }
+        out.extend_from_slice(b"\x1b[r"); // Reset the scroll region
+        Ok(())
+    }
+
+    // Write sequences to update the terminal showing `prev` to show this grid. When `prev` is None,
+    // contents of the terminal are unknown and all cells are written. Cursor and colors are left at
+    // arbitrary position and state
+    pub fn write_diff(&self, prev: Option<&Grid>, out: &mut Vec<u8>) -> io::Result<()> {
+        let prev = prev.filter(|p| p.cols == self.cols && p.rows == self.rows);
+        let mut pen: Option<(usize, usize)> = None; // Cursor position on terminal
+        let mut style = None; // Style of terminal
+        for y in 0..self.rows {
+            let row = self.row(y);
+            let old = prev.map(|p| p.row(y));
+            let mut x = 0;
+            while x < self.cols {
+                if old.is_some_and(|o| o[x] == row[x]) {
+                    x += 1;
+                    continue;
+                }
+                if row[x].is_continuation() && x > 0 {
+                    x -= 1; // Write from the left half
+                }
+
+                if let Some(s) = self.erasable_rest(y, x) {
+                    if pen != Some((x, y)) {
+                        write!(out, "\x1b[{};{}H", y + 1, x + 1)?;
+                    }
+                    if style != Some(s) {
+                        s.write_sequence(out)?;
+                        style = Some(s);
+                    }
+                    out.extend_from_slice(b"\x1b[K");
+                    pen = Some((x, y));
+                    break;
+                }
+
+                let mut from = x;
+                match pen {
+                    Some((px, py)) if py == y && px <= x && x - px <= MAX_GAP => from = px,
+                    _ => write!(out, "\x1b[{};{}H", y + 1, x + 1)?,
+                }
+                // Unchanged cells in the short gap are written again instead of moving cursor
+                let mut cx = from;
+                while cx <= x {
+                    let cell = &row[cx];
+                    if style != Some(cell.style) {
+                        cell.style.write_sequence(out)?;
+                        style = Some(cell.style);
+                    }
+                    out.extend_from_slice(cell.text.as_bytes());
+                    cx += cell.width(row.get(cx + 1));
+                }
+                pen = Some((cx, y));
+                x = cx;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Grids are equal when they show the same contents. State of interpreting is not compared
+impl PartialEq for Grid {
+    fn eq(&self, other: &Self) -> bool {
+        self.cols == other.cols && self.rows == other.rows && self.cells == other.cells
+    }
+}
@@ -0,0 +1,127 @@
+// Headless frontend which drives the editor without a terminal for integration tests and scripts
+// editing files non-interactively. Key inputs are built with `Script`, or decoded from bytes of
+// any reader as a terminal sends them by `Editor::with_io`. Escape sequences rendered by the
+// editor are written to any writer and text on the screen is available as
+// `Screen::rendered_lines`.
+//
+// Inputs end when the script or the reader runs out. Then prompts are canceled and the editor
+// stops as when the terminal is closed.
+
+use crate::input::{InputSeq, InputSequences, KeySeq};
+use std::io::{self, Read};
+use std::vec;
+
+// Size of the screen since there is no terminal to ask
+pub const WINDOW_SIZE: (usize, usize) = (80, 24);
+
+// Builder of key inputs. ASCII characters are keys without modifiers as in configuration such as
+// '\r' for ENTER and '\t' for TAB
+//
+//   let script = Script::new().keys("hello").ctrl('s').key(KeySeq::UpKey);
+#[derive(Default, Clone, Debug)]
+pub struct Script {
+    seqs: Vec<InputSeq>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn seq(mut self, seq: InputSeq) -> Self {
+        self.seqs.push(seq);
+        self
+    }
+
+    pub fn key(self, key: KeySeq) -> Self {
+        self.seq(InputSeq::new(key))
+    }
+
+    pub fn keys(mut self, text: &str) -> Self {
+        self.seqs.extend(text.chars().map(|c| {
+            InputSeq::new(if c.is_ascii() {
+                KeySeq::Key(c as u8)
+            } else {
+                KeySeq::Utf8Key(c)
+            })
+        }));
+        self
+    }
+
+    pub fn ctrl(self, c: char) -> Self {
+        self.seq(InputSeq::ctrl(KeySeq::Key(c as u8)))
+    }
+
+    pub fn alt(self, c: char) -> Self {
+        let mut seq = InputSeq::new(KeySeq::Key(c as u8));
+        seq.alt = true;
+        self.seq(seq)
+    }
+
+    // Notify the new size of the screen as a terminal is resized
+    pub fn resize(self, cols: usize, rows: usize) -> Self {
+        self.key(KeySeq::Resize(cols, rows))
+    }
+}
+
+pub struct ScriptInputs(vec::IntoIter<InputSeq>);
+
+impl Iterator for ScriptInputs {
+    type Item = io::Result<InputSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(Ok)
+    }
+}
+
+impl IntoIterator for Script {
+    type Item = io::Result<InputSeq>;
+    type IntoIter = ScriptInputs;
+
+    fn into_iter(self) -> ScriptInputs {
+        ScriptInputs(self.seqs.into_iter())
+    }
+}
+
+// Reading 0 bytes means the end of input since the reader does not time out unlike terminals
+struct UntilEof<R: Read> {
+    reader: R,
+    eof: bool,
+}
+
+impl<R: Read> Read for UntilEof<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.eof = true;
+        }
+        Ok(read)
+    }
+}
+
+// Key inputs decoded from the reader until it reaches EOF
+pub struct ReaderInputs<R: Read> {
+    seqs: InputSequences<UntilEof<R>>,
+}
+
+impl<R: Read> ReaderInputs<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            seqs: InputSequences::new(UntilEof { reader, eof: false }),
+        }
+    }
+}
+
+impl<R: Read> Iterator for ReaderInputs<R> {
+    type Item = io::Result<InputSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.seqs.reader().eof {
+            return None;
+        }
+        match self.seqs.next()? {
+            Ok(seq) if seq.key == KeySeq::Unidentified && self.seqs.reader().eof => None,
+            result => Some(result),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Hex dump of binary files. A binary file is opened as read-only text buffer whose lines are dump
+// of its bytes like `hexdump -C`:
+//
+//   00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|
+//
+// Positions in the dump are converted from/to byte offsets for navigation.
+
+pub const BYTES_PER_LINE: usize = 16;
+const HEX_START: usize = 10; // After offset and two spaces
+const ASCII_START: usize = HEX_START + BYTES_PER_LINE * 3 + 3; // After separators and ' |'
+
+// Only the head of file is checked so that large files can be opened quickly
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8000).any(|b| *b == 0)
+}
+
+fn dump_line(offset: usize, chunk: &[u8]) -> String {
+    let mut line = format!("{:08x}  ", offset);
+    for i in 0..BYTES_PER_LINE {
+        if i == BYTES_PER_LINE / 2 {
+            line.push(' ');
+        }
+        match chunk.get(i) {
+            Some(b) => line.push_str(&format!("{:02x} ", b)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str(" |");
+    for b in chunk {
+        line.push(if b.is_ascii_graphic() || *b == b' ' {
+            *b as char
+        } else {
+            '.'
+        });
+    }
+    line.push('|');
+    line
+}
+
+pub fn dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(i, chunk)| dump_line(i * BYTES_PER_LINE, chunk))
+        .collect()
+}
+
+// Byte offset at the position in dump. Both hex and ASCII columns point the byte
+pub fn offset_at(x: usize, y: usize) -> usize {
+    let i = if x >= ASCII_START {
+        x - ASCII_START
+    } else if x >= HEX_START {
+        let x = x - HEX_START;
+        // Skip the separator between the first and second halves
+        let x = if x > BYTES_PER_LINE / 2 * 3 { x - 1 } else { x };
+        x / 3
+    } else {
+        0
+    };
+    y * BYTES_PER_LINE + i.min(BYTES_PER_LINE - 1)
+}
+
+// Position of the hex digits of the byte at the offset
+pub fn position_of(offset: usize) -> (usize, usize) {
+    let i = offset % BYTES_PER_LINE;
+    let sep = if i >= BYTES_PER_LINE / 2 { 1 } else { 0 };
+    (HEX_START + i * 3 + sep, offset / BYTES_PER_LINE)
+}
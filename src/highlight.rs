@@ -1,10 +1,23 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs;
 use std::iter;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::OnceLock;
 
 use crate::ansi_color::AnsiColor;
-use crate::language::Language;
+use crate::config::config_dir;
+use crate::error::{self, Context, Error};
+use crate::gap_buffer::GapBuffer;
+use crate::language::{Indent, Language};
 use crate::row::Row;
+use crate::spell::Dictionary;
+use crate::token::TokenStream;
+use crate::toml::{self, Table, Value};
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Highlight {
     Normal,
     Number,
@@ -14,9 +27,28 @@ pub enum Highlight {
     Type,
     Char,
     Statement,
+    Decorator,
     Match,
+    OtherMatch, // Matches of text search other than the current one
+    Selection,
+    TrailingSpace,
+    Bracket,
+    UnmatchedBracket,
+    Cursor,
+    Misspelled,
+    Rainbow(usize), // Bracket nested at the depth
 }
 
+// Colors of rainbow brackets rotated by their depths
+const RAINBOW: &[AnsiColor] = &[
+    AnsiColor::Yellow,
+    AnsiColor::Purple,
+    AnsiColor::Cyan,
+    AnsiColor::Green,
+    AnsiColor::Blue,
+    AnsiColor::Red,
+];
+
 impl Highlight {
     pub fn color(self) -> AnsiColor {
         use AnsiColor::*;
@@ -30,7 +62,32 @@ impl Highlight {
             Type => Yellow,
             Char => Green,
             Statement => Red,
+            Decorator => Cyan,
             Match => CyanUnderline,
+            OtherMatch => Underline,
+            Selection => Invert,
+            TrailingSpace => RedBG,
+            Bracket => CyanUnderline,
+            UnmatchedBracket => RedBG,
+            Cursor => Invert,
+            Misspelled => RedUnderline,
+            Rainbow(depth) => RAINBOW[depth % RAINBOW.len()],
+        }
+    }
+
+    // Highlights of syntax by the names of their colors in themes
+    pub fn from_name(name: &str) -> Option<Highlight> {
+        use Highlight::*;
+        match name {
+            "number" => Some(Number),
+            "string" => Some(String),
+            "comment" => Some(Comment),
+            "keyword" => Some(Keyword),
+            "type" => Some(Type),
+            "char" => Some(Char),
+            "statement" => Some(Statement),
+            "decorator" => Some(Decorator),
+            _ => None,
         }
     }
 }
@@ -44,6 +101,15 @@ struct SyntaxHighlight {
     character: bool,
     line_comment: Option<&'static str>,
     block_comment: Option<(&'static str, &'static str)>,
+    block_strings: &'static [&'static str], // Delimiters of string literals which can contain newlines
+    decorator: bool,                        // '@' at head of line starts decorator
+    multi_line_quotes: bool, // String literals by `string_quotes` can contain newlines
+    key_separator: Option<char>, // Keys of config files such as 'key = value'
+    table_header: bool,      // '[table]' line in TOML
+    auto_indent: bool,       // Copy indentation of current line to new line
+    indent_after: &'static [&'static str], // New line after them is indented one more level
+    trailing_spaces: bool,   // Highlight whitespaces at end of line
+    strip_trailing_spaces: bool, // Remove whitespaces at end of line on saving
     keywords: &'static [&'static str],
     control_statements: &'static [&'static str],
     builtin_types: &'static [&'static str],
@@ -58,6 +124,15 @@ const PLAIN_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: false,
     line_comment: None,
     block_comment: None,
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &[],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[],
     control_statements: &[],
     builtin_types: &[],
@@ -72,6 +147,15 @@ const C_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: true,
     line_comment: Some("//"),
     block_comment: Some(("/*", "*/")),
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &["{", "(", "["],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[
         "auto", "const", "enum", "extern", "inline", "register", "restrict", "sizeof", "static",
         "struct", "typedef", "union", "volatile",
@@ -94,6 +178,15 @@ const RUST_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: true,
     line_comment: Some("//"),
     block_comment: Some(("/*", "*/")),
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &["{", "(", "["],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[
         "as", "const", "crate", "dyn", "enum", "extern", "false", "fn", "impl", "let", "mod",
         "move", "mut", "pub", "ref", "Self", "self", "static", "struct", "super", "trait", "true",
@@ -117,6 +210,15 @@ const JAVASCRIPT_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: false,
     line_comment: Some("//"),
     block_comment: Some(("/*", "*/")),
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &["{", "(", "["],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[
         "class",
         "const",
@@ -189,6 +291,15 @@ const GO_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: true,
     line_comment: Some("//"),
     block_comment: Some(("/*", "*/")),
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &["{", "(", "["],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[
         "chan",
         "const",
@@ -251,6 +362,15 @@ const CPP_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     character: true,
     line_comment: Some("//"),
     block_comment: Some(("/*", "*/")),
+    block_strings: &[],
+    decorator: false,
+    multi_line_quotes: true,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &["{", "(", "["],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
     keywords: &[
         "alignas",
         "alignof",
@@ -338,8 +458,143 @@ const CPP_SYNTAX: SyntaxHighlight = SyntaxHighlight {
     ],
 };
 
+const PYTHON_SYNTAX: SyntaxHighlight = SyntaxHighlight {
+    lang: Language::Python,
+    number: true,
+    hex_number: true,
+    bin_number: true,
+    string_quotes: &['"', '\''],
+    character: false,
+    line_comment: Some("#"),
+    block_comment: None,
+    block_strings: &["\"\"\"", "'''"],
+    decorator: true,
+    multi_line_quotes: false,
+    key_separator: None,
+    table_header: false,
+    auto_indent: true,
+    indent_after: &[":", "(", "[", "{"],
+    trailing_spaces: true,
+    strip_trailing_spaces: false,
+    keywords: &[
+        "and", "as", "assert", "async", "await", "class", "def", "del", "False", "from", "global",
+        "import", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "True", "with", "yield",
+    ],
+    control_statements: &[
+        "break", "case", "continue", "elif", "else", "except", "finally", "for", "if", "in",
+        "match", "raise", "return", "try", "while",
+    ],
+    builtin_types: &[
+        // Builtin types
+        "bool",
+        "bytearray",
+        "bytes",
+        "complex",
+        "dict",
+        "float",
+        "frozenset",
+        "int",
+        "list",
+        "object",
+        "set",
+        "str",
+        "tuple",
+        "type",
+        // Builtin functions
+        "abs",
+        "all",
+        "any",
+        "callable",
+        "chr",
+        "dir",
+        "enumerate",
+        "filter",
+        "format",
+        "getattr",
+        "hasattr",
+        "hash",
+        "id",
+        "input",
+        "isinstance",
+        "issubclass",
+        "iter",
+        "len",
+        "map",
+        "max",
+        "min",
+        "next",
+        "open",
+        "ord",
+        "print",
+        "range",
+        "repr",
+        "reversed",
+        "round",
+        "setattr",
+        "sorted",
+        "sum",
+        "super",
+        "zip",
+    ],
+};
+
+const TOML_SYNTAX: SyntaxHighlight = SyntaxHighlight {
+    lang: Language::Toml,
+    number: true,
+    hex_number: true,
+    bin_number: true,
+    string_quotes: &['"', '\''],
+    line_comment: Some("#"),
+    block_strings: &["\"\"\"", "'''"],
+    multi_line_quotes: false,
+    key_separator: Some('='),
+    table_header: true,
+    keywords: &["true", "false", "inf", "nan"],
+    ..PLAIN_SYNTAX
+};
+
+const YAML_SYNTAX: SyntaxHighlight = SyntaxHighlight {
+    lang: Language::Yaml,
+    number: true,
+    hex_number: true,
+    string_quotes: &['"', '\''],
+    line_comment: Some("#"),
+    multi_line_quotes: false,
+    key_separator: Some(':'),
+    keywords: &[
+        "true", "false", "True", "False", "yes", "no", "on", "off", "null", "Null",
+    ],
+    indent_after: &[":"],
+    ..PLAIN_SYNTAX
+};
+
+const JSON_SYNTAX: SyntaxHighlight = SyntaxHighlight {
+    lang: Language::Json,
+    number: true,
+    string_quotes: &['"'],
+    multi_line_quotes: false,
+    key_separator: Some(':'),
+    keywords: &["true", "false", "null"],
+    indent_after: &["{", "["],
+    ..PLAIN_SYNTAX
+};
+
+// Markdown is highlighted line by line in highlight_markdown_line() instead of tokenizing with this
+const MARKDOWN_SYNTAX: SyntaxHighlight = SyntaxHighlight {
+    lang: Language::Markdown,
+    trailing_spaces: false, // Trailing spaces are hard line break
+    ..PLAIN_SYNTAX
+};
+
 impl SyntaxHighlight {
     fn for_lang(lang: Language) -> &'static SyntaxHighlight {
+        if let Some(file) = user_syntaxes().iter().find(|f| f.syntax.lang == lang) {
+            return file.syntax;
+        }
+        Self::builtin(lang)
+    }
+
+    fn builtin(lang: Language) -> &'static SyntaxHighlight {
         use Language::*;
         match lang {
             Plain => &PLAIN_SYNTAX,
@@ -348,13 +603,360 @@ impl SyntaxHighlight {
             JavaScript => &JAVASCRIPT_SYNTAX,
             Go => &GO_SYNTAX,
             Cpp => &CPP_SYNTAX,
+            Python => &PYTHON_SYNTAX,
+            Markdown => &MARKDOWN_SYNTAX,
+            Toml => &TOML_SYNTAX,
+            Yaml => &YAML_SYNTAX,
+            Json => &JSON_SYNTAX,
+            Custom(_) => &PLAIN_SYNTAX,
+        }
+    }
+}
+
+// Syntax definition loaded from a TOML file in ~/.config/kiro/syntax/. Syntax files are loaded
+// once at startup and live until the editor exits so their contents are leaked as 'static.
+struct SyntaxFile {
+    syntax: &'static SyntaxHighlight,
+    file_exts: &'static [&'static str],
+    indent: Option<Indent>,
+}
+
+#[derive(Default)]
+struct UserSyntaxes {
+    files: Vec<SyntaxFile>,
+    error: Option<Error>,
+}
+
+static USER_SYNTAXES: OnceLock<UserSyntaxes> = OnceLock::new();
+
+fn user_syntaxes() -> &'static [SyntaxFile] {
+    USER_SYNTAXES
+        .get()
+        .map(|u| u.files.as_slice())
+        .unwrap_or(&[])
+}
+
+fn leak_str(s: &str) -> &'static str {
+    Box::leak(s.to_string().into_boxed_str())
+}
+
+fn leak_slice<T>(v: Vec<T>) -> &'static [T] {
+    Box::leak(v.into_boxed_slice())
+}
+
+fn get_bool(table: &Table, key: &str) -> Result<Option<bool>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(v) => v
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| format!("'{}' must be boolean but got {}", key, v.type_name())),
+    }
+}
+
+fn get_str(table: &Table, key: &str) -> Result<Option<&'static str>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(v) => v
+            .as_str()
+            .map(|s| Some(leak_str(s)))
+            .ok_or_else(|| format!("'{}' must be string but got {}", key, v.type_name())),
+    }
+}
+
+fn get_strs(table: &Table, key: &str) -> Result<Option<Vec<&'static str>>, String> {
+    let elems = match table.get(key) {
+        None => return Ok(None),
+        Some(Value::Array(elems)) => elems,
+        Some(v) => return Err(format!("'{}' must be array but got {}", key, v.type_name())),
+    };
+    elems
+        .iter()
+        .map(|v| {
+            v.as_str()
+                .map(leak_str)
+                .ok_or_else(|| format!("Elements of '{}' must be string", key))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn get_char(table: &Table, key: &str) -> Result<Option<char>, String> {
+    match get_str(table, key)? {
+        None => Ok(None),
+        Some(s) if s.chars().count() == 1 => Ok(s.chars().next()),
+        Some(_) => Err(format!("'{}' must be one character", key)),
+    }
+}
+
+const SYNTAX_FILE_KEYS: &[&str] = &[
+    "name",
+    "extensions",
+    "indent",
+    "number",
+    "hex_number",
+    "bin_number",
+    "character",
+    "string_quotes",
+    "line_comment",
+    "block_comment",
+    "block_strings",
+    "decorator",
+    "multi_line_quotes",
+    "key_separator",
+    "table_header",
+    "auto_indent",
+    "indent_after",
+    "trailing_spaces",
+    "strip_trailing_spaces",
+    "keywords",
+    "control_statements",
+    "builtin_types",
+];
+
+// Fields which are not specified in the file are inherited from builtin language which has the
+// same name. New language inherits plain text syntax.
+fn load_syntax_file(path: &Path) -> error::Result<SyntaxFile> {
+    let source = fs::read_to_string(path).context("reading", path)?;
+    parse_syntax_file(path, &source).map_err(|message| Error::Config {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+fn parse_syntax_file(path: &Path, source: &str) -> Result<SyntaxFile, String> {
+    let table = toml::parse(source).map_err(|e| e.to_string())?;
+    if let Some(key) = table
+        .keys()
+        .find(|k| !SYNTAX_FILE_KEYS.contains(&k.as_str()))
+    {
+        return Err(format!("Unknown key '{}'", key));
+    }
+
+    let name = match get_str(&table, "name")? {
+        Some(name) => name,
+        None => match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => leak_str(stem),
+            None => return Err("'name' is required".to_string()),
+        },
+    };
+    let lang = Language::builtin(name).unwrap_or(Language::Custom(name));
+    let mut syntax = SyntaxHighlight {
+        lang,
+        ..*SyntaxHighlight::builtin(lang)
+    };
+
+    macro_rules! set_field {
+        ($field:ident, $getter:ident) => {
+            if let Some(v) = $getter(&table, stringify!($field))? {
+                syntax.$field = v;
+            }
+        };
+        ($field:ident, $getter:ident, $map:expr) => {
+            if let Some(v) = $getter(&table, stringify!($field))? {
+                syntax.$field = $map(v)?;
+            }
+        };
+    }
+
+    set_field!(number, get_bool);
+    set_field!(hex_number, get_bool);
+    set_field!(bin_number, get_bool);
+    set_field!(character, get_bool);
+    set_field!(decorator, get_bool);
+    set_field!(multi_line_quotes, get_bool);
+    set_field!(table_header, get_bool);
+    set_field!(auto_indent, get_bool);
+    set_field!(indent_after, get_strs, |v| Ok::<_, String>(leak_slice(v)));
+    set_field!(trailing_spaces, get_bool);
+    set_field!(strip_trailing_spaces, get_bool);
+    set_field!(key_separator, get_char, |c| Ok::<_, String>(Some(c)));
+    set_field!(line_comment, get_str, |s| Ok::<_, String>(Some(s)));
+    set_field!(
+        block_comment,
+        get_strs,
+        |v: Vec<&'static str>| match v.as_slice() {
+            [start, end] => Ok(Some((*start, *end))),
+            _ => Err("'block_comment' must be an array of start and end delimiters".to_string()),
+        }
+    );
+    set_field!(string_quotes, get_strs, |v: Vec<&'static str>| {
+        v.iter()
+            .map(|s| match s.chars().collect::<Vec<_>>().as_slice() {
+                [c] => Ok(*c),
+                _ => Err("Elements of 'string_quotes' must be one character".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(leak_slice)
+    });
+    set_field!(block_strings, get_strs, |v| Ok::<_, String>(leak_slice(v)));
+    set_field!(keywords, get_strs, |v| Ok::<_, String>(leak_slice(v)));
+    set_field!(control_statements, get_strs, |v| Ok::<_, String>(
+        leak_slice(v)
+    ));
+    set_field!(builtin_types, get_strs, |v| Ok::<_, String>(leak_slice(v)));
+
+    let file_exts = leak_slice(get_strs(&table, "extensions")?.unwrap_or_default());
+    // Indent is "tab" or number of spaces
+    let indent = match table.get("indent") {
+        None => None,
+        Some(Value::String(s)) if s == "tab" => Some(Indent::AsIs),
+        Some(Value::Integer(n)) if 0 < *n && *n <= 16 => {
+            Some(Indent::Fixed(leak_str(&" ".repeat(*n as usize))))
+        }
+        Some(_) => return Err("'indent' must be \"tab\" or number of spaces".to_string()),
+    };
+
+    Ok(SyntaxFile {
+        syntax: Box::leak(Box::new(syntax)),
+        file_exts,
+        indent,
+    })
+}
+
+fn load_syntax_dir(dir: &Path) -> UserSyntaxes {
+    let mut loaded = UserSyntaxes::default();
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|e| e == "toml").unwrap_or(false))
+            .collect::<Vec<_>>(),
+        Err(_) => return loaded, // Syntax directory is optional
+    };
+    paths.sort();
+    for path in paths {
+        match load_syntax_file(&path) {
+            Ok(file) => loaded.files.push(file),
+            Err(err) if loaded.error.is_none() => loaded.error = Some(err),
+            Err(_) => {}
         }
     }
+    loaded
+}
+
+// Load syntax files in ~/.config/kiro/syntax/. This should be called once before opening files
+pub fn load_user_syntaxes() {
+    USER_SYNTAXES.get_or_init(|| {
+        config_dir()
+            .map(|d| load_syntax_dir(&d.join("syntax")))
+            .unwrap_or_default()
+    });
 }
 
+// Error which happened while loading syntax files. Only the first error is kept
+pub fn user_syntax_error() -> Option<&'static Error> {
+    USER_SYNTAXES.get().and_then(|u| u.error.as_ref())
+}
+
+pub fn detect_user_syntax(ext: &str) -> Option<Language> {
+    user_syntaxes()
+        .iter()
+        .find(|f| f.file_exts.contains(&ext) || f.syntax.lang.name() == ext)
+        .map(|f| f.syntax.lang)
+}
+
+pub fn user_indent(lang: Language) -> Option<Indent> {
+    user_syntaxes()
+        .iter()
+        .find(|f| f.syntax.lang == lang)
+        .and_then(|f| f.indent)
+}
+
+// Whether new line inherits indentation of the current line
+pub fn auto_indent(lang: Language) -> bool {
+    SyntaxHighlight::for_lang(lang).auto_indent
+}
+
+// New line after a line ending with one of them is indented one more level
+pub fn indent_after(lang: Language) -> &'static [&'static str] {
+    SyntaxHighlight::for_lang(lang).indent_after
+}
+
+// Delimiters of line comment and block comment of the language
+pub fn comment_delimiters(
+    lang: Language,
+) -> (Option<&'static str>, Option<(&'static str, &'static str)>) {
+    let syntax = SyntaxHighlight::for_lang(lang);
+    (syntax.line_comment, syntax.block_comment)
+}
+
+// Whether whitespaces at end of lines are removed on saving a file
+pub fn strip_trailing_spaces(lang: Language) -> bool {
+    SyntaxHighlight::for_lang(lang).strip_trailing_spaces
+}
+
+// Find key of line in config file such as 'key = value' or '"key": value'. Returned range is
+// character indices of the key.
+fn find_key(line: &str, sep: char) -> Option<(usize, usize)> {
+    let chars: Vec<char> = line.chars().collect();
+    let skip_spaces = |i: usize| i + chars[i..].iter().take_while(|c| c.is_whitespace()).count();
+    let mut start = skip_spaces(0);
+    if sep == ':' && chars.get(start) == Some(&'-') && chars.get(start + 1) == Some(&' ') {
+        start = skip_spaces(start + 1); // Item of list in YAML such as '- key: value'
+    }
+
+    let end = match *chars.get(start)? {
+        q @ '"' | q @ '\'' => start + 1 + chars[start + 1..].iter().position(|c| *c == q)? + 1,
+        _ => {
+            let len = chars[start..]
+                .iter()
+                .position(|c| *c == sep || "#\"'{}[],".contains(*c))?;
+            // Trailing whitespaces between key and separator are not a part of key
+            start
+                + chars[start..start + len]
+                    .iter()
+                    .rposition(|c| !c.is_whitespace())?
+                + 1
+        }
+    };
+
+    let sep_idx = skip_spaces(end);
+    if chars.get(sep_idx) != Some(&sep) {
+        return None;
+    }
+    // Colon in such as URL 'http://...' is not a separator in YAML
+    if sep == ':'
+        && !chars
+            .get(sep_idx + 1)
+            .map(|c| c.is_whitespace())
+            .unwrap_or(true)
+    {
+        return None;
+    }
+    Some((start, end))
+}
+
+// Brackets colored by their nesting depths. Brackets in strings, characters and comments are skipped
+// by the tokens of the language. The depth at the end of the line is carried to the next line
+fn rainbow_brackets(text: &str, tokens: &TokenStream, depth: &mut usize) -> TokenStream {
+    let mut brackets = TokenStream::new();
+    for (x, c) in text.chars().enumerate() {
+        let opener = BRACKETS.iter().any(|(o, _)| *o == c);
+        if !opener && !BRACKETS.iter().any(|(_, e)| *e == c) {
+            continue;
+        }
+        if matches!(
+            tokens.kind_at(x),
+            Some(Highlight::String | Highlight::Comment | Highlight::Char)
+        ) {
+            continue;
+        }
+        if opener {
+            brackets.push(x, x + 1, Highlight::Rainbow(*depth));
+            *depth += 1;
+        } else if *depth > 0 {
+            *depth -= 1;
+            brackets.push(x, x + 1, Highlight::Rainbow(*depth));
+        }
+    }
+    brackets
+}
+
+#[derive(PartialEq)]
 struct Region {
     start: (usize, usize),
     end: (usize, usize),
+    rect: bool, // Columns between start and end in all lines are contained
 }
 
 impl Region {
@@ -362,6 +964,8 @@ impl Region {
         let ((sx, sy), (ex, ey)) = (self.start, self.end);
         if y < sy || ey < y {
             false
+        } else if self.rect {
+            sx <= x && x < ex
         } else if sy < y && y < ey {
             true
         } else {
@@ -370,13 +974,35 @@ impl Region {
     }
 }
 
+const BRACKETS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+// Result of searching the bracket paired with a bracket
+#[derive(PartialEq, Debug)]
+pub enum BracketPair {
+    Matched(usize, usize), // (x, y) in the buffer
+    Unmatched,
+}
+
+type Misspelled = Vec<(usize, usize)>; // Ranges of misspelled words in a line
+
 pub struct Highlighting {
     pub needs_update: bool,
-    // One item per render text byte
-    pub lines: Vec<Vec<Highlight>>, // TODO: One item per one character
+    // One item per character of rows. Screen applies the item to all display columns of the
+    // character such as spaces of an expanded tab
+    pub lines: Vec<Vec<Highlight>>,
+    tokens: Vec<TokenStream>, // Tokens produced by the language for each line
+    rainbow: bool,            // Color brackets by their depths
     previous_bottom_of_screen: usize,
     matched: Option<Region>,
+    others: BTreeMap<usize, Vec<(usize, usize)>>, // Ranges of all matches of text search per line
+    spans: BTreeMap<usize, TokenStream>,          // Highlights by plugins per line
+    selection: Option<Region>,
+    brackets: Vec<(usize, usize, Highlight)>, // Bracket at cursor and its pair
+    carets: Vec<(usize, usize)>,              // Extra cursors
     syntax: &'static SyntaxHighlight,
+    // Dictionary of spell checking and misspelled words of each line. Lines after
+    // the modified line are checked again
+    spell: Option<(Rc<Dictionary>, Vec<Option<Misspelled>>)>,
 }
 
 impl Default for Highlighting {
@@ -384,28 +1010,40 @@ impl Default for Highlighting {
         Highlighting {
             needs_update: false,
             lines: vec![],
+            tokens: vec![],
+            rainbow: false,
             previous_bottom_of_screen: 0,
             matched: None,
+            others: BTreeMap::new(),
+            spans: BTreeMap::new(),
+            selection: None,
+            brackets: vec![],
+            carets: vec![],
             syntax: &PLAIN_SYNTAX,
+            spell: None,
         }
     }
 }
 
 impl Highlighting {
-    pub fn new(lang: Language, rows: &[Row]) -> Highlighting {
+    pub fn new(lang: Language, rows: &GapBuffer<Row>) -> Highlighting {
         Highlighting {
             needs_update: true,
             lines: rows
                 .iter()
-                .map(|r| {
-                    iter::repeat(Highlight::Normal)
-                        .take(r.render_text().chars().count()) // TODO: One item per one character
-                        .collect()
-                })
+                .map(|r| iter::repeat_n(Highlight::Normal, r.len()).collect())
                 .collect(),
+            tokens: vec![],
+            rainbow: false,
             previous_bottom_of_screen: 0,
             matched: None,
+            others: BTreeMap::new(),
+            spans: BTreeMap::new(),
+            selection: None,
+            brackets: vec![],
+            carets: vec![],
             syntax: SyntaxHighlight::for_lang(lang),
+            spell: None,
         }
     }
 
@@ -414,14 +1052,104 @@ impl Highlighting {
             return;
         }
         self.syntax = SyntaxHighlight::for_lang(new_lang);
+        self.lines_changed(0);
+    }
+
+    // Lines at and after `y` were modified
+    pub fn lines_changed(&mut self, y: usize) {
         self.needs_update = true;
+        if let Some((_, checked)) = &mut self.spell {
+            checked.truncate(y);
+        }
+    }
+
+    // Enable spell checking with the dictionary or disable it with None
+    pub fn set_dictionary(&mut self, dict: Option<&Rc<Dictionary>>) {
+        let unchanged = match (&self.spell, dict) {
+            (Some((prev, _)), Some(dict)) => Rc::ptr_eq(prev, dict),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            self.spell = dict.map(|d| (d.clone(), vec![]));
+            self.needs_update = true;
+        }
+    }
+
+    // Words in text are checked on plain text and Markdown. Only comments and strings are checked
+    // in other languages
+    fn apply_spans(&mut self, bottom_of_screen: usize) {
+        for (&y, spans) in self.spans.range(..bottom_of_screen) {
+            spans.paint(&mut self.lines[y]);
+        }
+    }
+
+    pub fn set_rainbow(&mut self, enabled: bool) {
+        if self.rainbow != enabled {
+            self.rainbow = enabled;
+            self.needs_update = true;
+        }
     }
 
-    fn replace(&mut self, y: usize, start: usize, end: usize, hl: Highlight) {
-        self.lines[y].splice(start..end, iter::repeat(hl).take(end - start));
+    fn apply_spell(&mut self, rows: &GapBuffer<Row>, bottom_of_screen: usize) {
+        let Highlighting {
+            spell,
+            lines,
+            syntax,
+            ..
+        } = self;
+        let (dict, checked) = match spell {
+            Some(spell) => spell,
+            None => return,
+        };
+        let target = |hl: Highlight| match syntax.lang {
+            Language::Plain => true,
+            Language::Markdown => {
+                matches!(hl, Highlight::Normal | Highlight::Keyword | Highlight::Type)
+            }
+            _ => matches!(hl, Highlight::Comment | Highlight::String),
+        };
+        checked.resize(rows.len(), None);
+        for (y, row) in rows.iter().enumerate().take(bottom_of_screen) {
+            let misspelled = checked[y].get_or_insert_with(|| {
+                let line = &lines[y];
+                dict.misspelled(row.buffer(), |x| line.get(x).is_some_and(|h| target(*h)))
+            });
+            for &(start, end) in misspelled.iter() {
+                for hl in &mut lines[y][start..end] {
+                    *hl = Highlight::Misspelled;
+                }
+            }
+        }
     }
 
-    fn apply_match(&mut self) {
+    fn apply_trailing_spaces(&mut self, rows: &GapBuffer<Row>, bottom_of_screen: usize) {
+        if !self.syntax.trailing_spaces {
+            return;
+        }
+        for (y, row) in rows.iter().enumerate().take(bottom_of_screen) {
+            let trailing = row
+                .buffer()
+                .chars()
+                .rev()
+                .take_while(|c| *c == ' ' || *c == '\t');
+            let start = self.lines[y].len() - trailing.count();
+            for hl in self.lines[y][start..].iter_mut() {
+                *hl = Highlight::TrailingSpace;
+            }
+        }
+    }
+
+    fn apply_match(&mut self, bottom_of_screen: usize) {
+        for (&y, ranges) in self.others.range(..bottom_of_screen) {
+            let line = &mut self.lines[y];
+            for &(start, end) in ranges {
+                let end = cmp::min(end, line.len());
+                if start < end {
+                    line[start..end].fill(Highlight::OtherMatch);
+                }
+            }
+        }
         if let Some(m) = &self.matched {
             for y in m.start.1..=m.end.1 {
                 for (x, hl) in self.lines[y].iter_mut().enumerate() {
@@ -433,12 +1161,174 @@ impl Highlighting {
         }
     }
 
-    pub fn update(&mut self, rows: &[Row], bottom_of_screen: usize) {
+    fn apply_brackets(&mut self) {
+        for (x, y, hl) in self.brackets.iter() {
+            if let Some(h) = self.lines.get_mut(*y).and_then(|l| l.get_mut(*x)) {
+                *h = *hl;
+            }
+        }
+    }
+
+    fn apply_carets(&mut self) {
+        for (x, y) in self.carets.iter() {
+            if let Some(h) = self.lines.get_mut(*y).and_then(|l| l.get_mut(*x)) {
+                *h = Highlight::Cursor;
+            }
+        }
+    }
+
+    fn apply_selection(&mut self, rows: &GapBuffer<Row>) {
+        if let Some(r) = &self.selection {
+            // Region may end at the line after the last line
+            for y in r.start.1..cmp::min(r.end.1 + 1, self.lines.len()) {
+                // Columns of rectangle are display columns. Convert them into characters of the line
+                let rect = match rows.get(y) {
+                    Some(row) if r.rect => {
+                        Some((row.cx_from_rx(r.start.0), row.cx_from_rx(r.end.0)))
+                    }
+                    _ => None,
+                };
+                for (x, hl) in self.lines[y].iter_mut().enumerate() {
+                    let selected = match rect {
+                        Some((left, right)) => left <= x && x < right,
+                        None => r.contains((x, y)),
+                    };
+                    if selected {
+                        *hl = Highlight::Selection;
+                    }
+                }
+            }
+        }
+    }
+
+    // Highlight keywords of the syntax. This is used for code blocks in other languages
+    fn highlight_keywords(tokens: &mut TokenStream, text: &str, syntax: &SyntaxHighlight) {
+        let is_word = |c: char| c == '_' || c.is_alphanumeric();
+        let mut chars = text.chars().enumerate().peekable();
+        while let Some((x, c)) = chars.next() {
+            if !is_word(c) {
+                continue;
+            }
+            let mut word = c.to_string();
+            while let Some((_, c)) = chars.next_if(|(_, c)| is_word(*c)) {
+                word.push(c);
+            }
+            let w = word.as_str();
+            let hl = if syntax.keywords.contains(&w) {
+                Highlight::Keyword
+            } else if syntax.control_statements.contains(&w) {
+                Highlight::Statement
+            } else if syntax.builtin_types.contains(&w) {
+                Highlight::Type
+            } else {
+                continue;
+            };
+            tokens.push(x, x + word.chars().count(), hl);
+        }
+    }
+
+    // Headings, code, emphasis markers and link targets are highlighted in Markdown. `fence` is
+    // the state of fenced code block: its delimiter and syntax of the code
+    fn highlight_markdown_line(
+        tokens: &mut TokenStream,
+        text: &str,
+        fence: &mut Option<(String, &'static SyntaxHighlight)>,
+    ) {
+        let len = text.chars().count();
+        tokens.push(0, len, Highlight::Normal);
+        let trimmed = text.trim_start();
+        let indent = text.chars().count() - trimmed.chars().count();
+
+        if let Some((delim, syntax)) = fence {
+            if trimmed.trim_end() == delim.as_str() {
+                *fence = None;
+                tokens.push(0, len, Highlight::Comment);
+            } else if syntax.lang == Language::Plain {
+                tokens.push(0, len, Highlight::String);
+            } else {
+                Self::highlight_keywords(tokens, text, syntax);
+            }
+            return;
+        }
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let delim_len = trimmed
+                .chars()
+                .take_while(|c| *c == trimmed.chars().next().unwrap())
+                .count();
+            let (delim, tag) = trimmed.split_at(delim_len);
+            let tag = tag.split_whitespace().next().unwrap_or("");
+            let lang = Language::from_name(tag).unwrap_or(Language::Plain);
+            *fence = Some((delim.to_string(), SyntaxHighlight::for_lang(lang)));
+            tokens.push(0, len, Highlight::Comment);
+            return;
+        }
+
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if 0 < hashes
+            && hashes <= 6
+            && (trimmed.len() == hashes || trimmed[hashes..].starts_with(' '))
+        {
+            tokens.push(0, len, Highlight::Keyword);
+            return;
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        let mut x = 0;
+        while x < chars.len() {
+            match chars[x] {
+                '`' => {
+                    // Inline code is closed by the same number of backquotes
+                    let n = chars[x..].iter().take_while(|c| **c == '`').count();
+                    let close = (x + n..chars.len()).find(|&i| {
+                        chars[i..].iter().take_while(|c| **c == '`').count() == n
+                            && (i == 0 || chars[i - 1] != '`')
+                    });
+                    if let Some(close) = close {
+                        tokens.push(x, close + n, Highlight::String);
+                        x = close + n;
+                        continue;
+                    }
+                    x += n;
+                    continue;
+                }
+                '*' | '_' => {
+                    let c = chars[x];
+                    let n = chars[x..].iter().take_while(|d| **d == c).count();
+                    let prev = if x > 0 { chars[x - 1] } else { ' ' };
+                    let next = chars.get(x + n).copied().unwrap_or(' ');
+                    let is_bullet = c == '*' && x == indent && n == 1 && next == ' ';
+                    // '_' in the middle of word such as snake_case is not an emphasis
+                    let in_word = c == '_' && prev.is_alphanumeric() && next.is_alphanumeric();
+                    let surrounded_by_spaces = prev == ' ' && next == ' ';
+                    if !is_bullet && !in_word && !surrounded_by_spaces {
+                        tokens.push(x, x + n, Highlight::Type);
+                    }
+                    x += n;
+                    continue;
+                }
+                ']' if chars.get(x + 1) == Some(&'(') => {
+                    if let Some(close) = chars[x + 1..].iter().position(|c| *c == ')') {
+                        let end = x + 1 + close + 1;
+                        tokens.push(x + 1, end, Highlight::Statement);
+                        x = end;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            x += 1;
+        }
+    }
+
+    pub fn update(&mut self, rows: &GapBuffer<Row>, bottom_of_screen: usize) {
         if !self.needs_update && bottom_of_screen <= self.previous_bottom_of_screen {
             return;
         }
 
         self.lines.resize_with(rows.len(), Default::default);
+        self.tokens.resize_with(rows.len(), Default::default);
+        let syntax = self.syntax;
 
         fn is_sep(c: char) -> bool {
             c.is_ascii_whitespace() || (c.is_ascii_punctuation() && c != '_') || c == '\0'
@@ -470,43 +1360,122 @@ impl Highlighting {
 
         let mut prev_quote = None;
         let mut in_block_comment = false;
-        for (y, ref row) in rows.iter().enumerate().take(bottom_of_screen) {
-            self.lines[y].resize(row.render_text().chars().count(), Highlight::Normal); // TODO: One item per one character
+        let mut in_block_string = None;
+        let mut fence = None;
+        for (y, row) in rows.iter().enumerate().take(bottom_of_screen) {
+            let tokens = &mut self.tokens[y];
+            tokens.clear();
+
+            if syntax.lang == Language::Plain {
+                // On 'plain' syntax, skip highlighting since nothing is highlighted
+                continue;
+            }
 
-            if self.syntax.lang == Language::Plain {
-                // On 'plain' syntax, skip highlighting since nothing is highlighted.
+            if syntax.lang == Language::Markdown {
+                Self::highlight_markdown_line(tokens, row.buffer(), &mut fence);
                 continue;
             }
 
+            if !syntax.multi_line_quotes {
+                prev_quote = None;
+            }
+
+            // Keys and table headers are highlighted after highlighting entire line
+            let key = if in_block_string.is_some() || prev_quote.is_some() {
+                None
+            } else if syntax.table_header && row.buffer().trim_start().starts_with('[') {
+                let text = row.buffer();
+                let start = text.chars().count() - text.trim_start().chars().count();
+                text.rfind(']')
+                    .map(|idx| (start, text[..=idx].chars().count(), Highlight::Statement))
+            } else {
+                syntax
+                    .key_separator
+                    .and_then(|sep| find_key(row.buffer(), sep))
+                    .map(|(start, end)| (start, end, Highlight::Type))
+            };
+
             let mut prev_hl = Highlight::Normal;
             let mut prev_char = '\0';
             let mut num = Num::Digit;
-            let mut iter = row.render_text().char_indices().enumerate();
+            let mut iter = row.buffer().char_indices().enumerate();
 
             while let Some((x, (idx, c))) = iter.next() {
                 let mut hl = Highlight::Normal;
 
-                if let Some((comment_start, comment_end)) = self.syntax.block_comment {
+                if !syntax.block_strings.is_empty() && prev_quote.is_none() {
+                    let text = &row.buffer()[idx..];
+                    let delim = match in_block_string {
+                        Some(delim) if prev_char != '\\' && text.starts_with(delim) => {
+                            in_block_string = None;
+                            Some(delim)
+                        }
+                        Some(_) => None,
+                        None => {
+                            in_block_string = self
+                                .syntax
+                                .block_strings
+                                .iter()
+                                .find(|d| text.starts_with(*d))
+                                .copied();
+                            in_block_string
+                        }
+                    };
+
+                    // Eat delimiter of multi-line string at once
+                    if let Some(delim) = delim {
+                        let len = delim.len();
+                        tokens.push(x, x + len, Highlight::String);
+                        prev_hl = Highlight::String;
+                        prev_char = delim.chars().last().unwrap();
+                        iter.nth(len - 2);
+                        continue;
+                    }
+
+                    if in_block_string.is_some() {
+                        tokens.push(x, x + 1, Highlight::String);
+                        prev_hl = Highlight::String;
+                        // Escaped backslash does not escape the next character
+                        prev_char = if prev_char == '\\' { '\0' } else { c };
+                        continue;
+                    }
+                }
+
+                if syntax.decorator && c == '@' && row.buffer()[..idx].trim().is_empty() {
+                    let len = row.buffer()[idx + 1..]
+                        .chars()
+                        .take_while(|c| *c == '_' || *c == '.' || c.is_alphanumeric())
+                        .count()
+                        + 1;
+                    tokens.push(x, x + len, Highlight::Decorator);
+                    prev_hl = Highlight::Decorator;
+                    prev_char = row.buffer()[idx..].chars().nth(len - 1).unwrap();
+                    if len > 1 {
+                        iter.nth(len - 2);
+                    }
+                    continue;
+                }
+
+                if let Some((comment_start, comment_end)) = syntax.block_comment {
                     if hl == Highlight::Normal && prev_quote.is_none() {
-                        let comment_delim = if in_block_comment
-                            && row.render_text()[idx..].starts_with(comment_end)
-                        {
-                            in_block_comment = false;
-                            Some(comment_end)
-                        } else if !in_block_comment
-                            && row.render_text()[idx..].starts_with(comment_start)
-                        {
-                            in_block_comment = true;
-                            Some(comment_start)
-                        } else {
-                            None
-                        };
+                        let comment_delim =
+                            if in_block_comment && row.buffer()[idx..].starts_with(comment_end) {
+                                in_block_comment = false;
+                                Some(comment_end)
+                            } else if !in_block_comment
+                                && row.buffer()[idx..].starts_with(comment_start)
+                            {
+                                in_block_comment = true;
+                                Some(comment_start)
+                            } else {
+                                None
+                            };
 
                         // Eat delimiter of block comment at once
                         if let Some(comment_delim) = comment_delim {
                             // Consume whole '/*' here. Otherwise such as '/*/' is wrongly accepted
                             let len = comment_delim.len();
-                            self.replace(y, x, x + len, Highlight::Comment);
+                            tokens.push(x, x + len, Highlight::Comment);
                             prev_hl = Highlight::Comment;
                             prev_char = comment_delim.chars().last().unwrap();
                             iter.nth(len - 2);
@@ -519,16 +1488,15 @@ impl Highlighting {
                     }
                 }
 
-                if let Some(comment_leader) = self.syntax.line_comment {
-                    if prev_quote.is_none() && row.render_text()[idx..].starts_with(comment_leader)
-                    {
-                        self.replace(y, x, self.lines[y].len(), Highlight::Comment);
+                if let Some(comment_leader) = syntax.line_comment {
+                    if prev_quote.is_none() && row.buffer()[idx..].starts_with(comment_leader) {
+                        tokens.push(x, row.len(), Highlight::Comment);
                         break;
                     }
                 }
 
-                if hl == Highlight::Normal && self.syntax.character {
-                    let mut i = row.render_text()[idx..].chars();
+                if hl == Highlight::Normal && syntax.character {
+                    let mut i = row.buffer()[idx..].chars();
                     let len = match (i.next(), i.next(), i.next(), i.next()) {
                         (Some('\''), Some('\\'), _, Some('\'')) => Some(4),
                         (Some('\''), _, Some('\''), _) => Some(3),
@@ -536,7 +1504,7 @@ impl Highlighting {
                     };
 
                     if let Some(len) = len {
-                        self.replace(y, x, x + len, Highlight::Char);
+                        tokens.push(x, x + len, Highlight::Char);
                         prev_hl = Highlight::Char;
                         prev_char = '\'';
                         iter.nth(len - 2);
@@ -544,14 +1512,14 @@ impl Highlighting {
                     }
                 }
 
-                if hl == Highlight::Normal && !self.syntax.string_quotes.is_empty() {
+                if hl == Highlight::Normal && !syntax.string_quotes.is_empty() {
                     if let Some(q) = prev_quote {
                         // In string literal. XXX: "\\" is not highlighted correctly
                         if prev_char != '\\' && q == c {
                             prev_quote = None;
                         }
                         hl = Highlight::String;
-                    } else if self.syntax.string_quotes.contains(&c) {
+                    } else if syntax.string_quotes.contains(&c) {
                         prev_quote = Some(c);
                         hl = Highlight::String;
                     }
@@ -561,20 +1529,20 @@ impl Highlighting {
 
                 // Highlight identifiers
                 if hl == Highlight::Normal && is_bound {
-                    let line = &row.render_text()[idx..];
+                    let line = &row.buffer()[idx..];
                     if let Some((keyword, highlight)) = self
                         .syntax
                         .keywords
                         .iter()
                         .zip(iter::repeat(Highlight::Keyword))
                         .chain(
-                            self.syntax
+                            syntax
                                 .control_statements
                                 .iter()
                                 .zip(iter::repeat(Highlight::Statement)),
                         )
                         .chain(
-                            self.syntax
+                            syntax
                                 .builtin_types
                                 .iter()
                                 .zip(iter::repeat(Highlight::Type)),
@@ -582,7 +1550,7 @@ impl Highlighting {
                         .find(|(k, _)| starts_with_word(line, k))
                     {
                         let len = keyword.len();
-                        self.replace(y, x, x + len, highlight);
+                        tokens.push(x, x + len, highlight);
 
                         prev_hl = highlight;
                         prev_char = line.chars().nth(len - 1).unwrap();
@@ -594,13 +1562,12 @@ impl Highlighting {
                     }
                 }
 
-                if hl == Highlight::Normal && self.syntax.hex_number {
-                    let line = row.render_text()[idx..].as_bytes();
+                if hl == Highlight::Normal && syntax.hex_number {
+                    let line = &row.buffer().as_bytes()[idx..];
                     if is_bound {
                         if line.starts_with(b"0x") && line.len() > 2 && line[2].is_ascii_hexdigit()
                         {
-                            self.lines[y][x] = Highlight::Number;
-                            self.lines[y][x + 1] = Highlight::Number;
+                            tokens.push(x, x + 2, Highlight::Number);
                             num = Num::Hex;
                             prev_hl = Highlight::Number;
                             prev_char = 'x';
@@ -615,12 +1582,11 @@ impl Highlighting {
                     }
                 }
 
-                if hl == Highlight::Normal && self.syntax.bin_number {
-                    let line = row.render_text()[idx..].as_bytes();
+                if hl == Highlight::Normal && syntax.bin_number {
+                    let line = &row.buffer().as_bytes()[idx..];
                     if is_bound {
                         if line.starts_with(b"0b") && line.len() > 2 && b"01".contains(&line[2]) {
-                            self.lines[y][x] = Highlight::Number;
-                            self.lines[y][x + 1] = Highlight::Number;
+                            tokens.push(x, x + 2, Highlight::Number);
                             num = Num::Bin;
                             prev_hl = Highlight::Number;
                             prev_char = 'b';
@@ -633,7 +1599,7 @@ impl Highlighting {
                 }
 
                 if hl == Highlight::Normal
-                    && self.syntax.number
+                    && syntax.number
                     && (c.is_ascii_digit() && (prev_hl == Highlight::Number || is_bound)
                         || c == '.' && prev_hl == Highlight::Number)
                 {
@@ -641,18 +1607,169 @@ impl Highlighting {
                     num = Num::Digit;
                 }
 
-                self.lines[y][x] = hl;
+                tokens.push(x, x + 1, hl);
                 prev_hl = hl;
                 prev_char = c;
             }
+
+            if let Some((start, end, hl)) = key {
+                tokens.push(start, end, hl);
+            }
         }
 
-        self.apply_match();
+        // Compose layers over the tokens. Highlights applied previously such as misspelled words or
+        // matches are cleared here
+        let rainbow = self.rainbow && syntax.lang != Language::Plain;
+        let mut depth = 0;
+        for (y, row) in rows.iter().enumerate().take(bottom_of_screen) {
+            let line = &mut self.lines[y];
+            line.clear();
+            line.resize(row.len(), Highlight::Normal);
+            self.tokens[y].paint(line);
+            if rainbow {
+                rainbow_brackets(row.buffer(), &self.tokens[y], &mut depth).paint(line);
+            }
+        }
+
+        self.apply_spans(cmp::min(bottom_of_screen, self.lines.len()));
+        self.apply_spell(rows, bottom_of_screen);
+        self.apply_trailing_spaces(rows, bottom_of_screen);
+        self.apply_match(cmp::min(bottom_of_screen, self.lines.len()));
+        self.apply_brackets();
+        self.apply_selection(rows);
+        self.apply_carets();
 
         self.needs_update = false;
         self.previous_bottom_of_screen = bottom_of_screen;
     }
 
+    // Search the bracket paired with the bracket at (x, y) considering nesting.
+    // Brackets in strings and comments are skipped. Highlights must be updated before calling this.
+    // Returns None when the character is not a bracket or when the pair is not found in the lines
+    // whose highlights are already calculated
+    pub fn find_bracket_pair(
+        &self,
+        rows: &GapBuffer<Row>,
+        x: usize,
+        y: usize,
+    ) -> Option<BracketPair> {
+        let in_literal = |x: usize, y: usize| {
+            matches!(
+                self.lines.get(y).and_then(|l| l.get(x)),
+                Some(Highlight::String | Highlight::Comment | Highlight::Char)
+            )
+        };
+        let c = rows.get(y)?.buffer().chars().nth(x)?;
+        if in_literal(x, y) {
+            return None;
+        }
+        let (open, close, forward) = BRACKETS.iter().find_map(|&(open, close)| {
+            if c == open {
+                Some((open, close, true))
+            } else if c == close {
+                Some((open, close, false))
+            } else {
+                None
+            }
+        })?;
+        let (start, end) = if forward {
+            (open, close)
+        } else {
+            (close, open)
+        };
+
+        let mut depth = 0;
+        let mut check = |bx: usize, by: usize, b: char| {
+            if in_literal(bx, by) {
+                return false;
+            }
+            if b == start {
+                depth += 1;
+            } else if b == end {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        };
+
+        if forward {
+            let bottom = cmp::min(self.previous_bottom_of_screen, rows.len());
+            for by in y..bottom {
+                let skip = if by == y { x + 1 } else { 0 };
+                for (bx, b) in rows[by].buffer().chars().enumerate().skip(skip) {
+                    if check(bx, by, b) {
+                        return Some(BracketPair::Matched(bx, by));
+                    }
+                }
+            }
+            if bottom < rows.len() {
+                return None; // Highlights after the bottom of screen are not known yet
+            }
+        } else {
+            for by in (0..=y).rev() {
+                let chars: Vec<_> = rows[by].buffer().chars().collect();
+                let end = if by == y { x } else { chars.len() };
+                for bx in (0..end).rev() {
+                    if check(bx, by, chars[bx]) {
+                        return Some(BracketPair::Matched(bx, by));
+                    }
+                }
+            }
+        }
+        Some(BracketPair::Unmatched)
+    }
+
+    // Brackets are given as (x, y, highlight). Returns the first line where highlight needs to be
+    // updated
+    pub fn set_brackets(&mut self, brackets: Vec<(usize, usize, Highlight)>) -> Option<usize> {
+        if self.brackets == brackets {
+            return None;
+        }
+        let line = self
+            .brackets
+            .iter()
+            .chain(brackets.iter())
+            .map(|(_, y, _)| *y)
+            .min();
+        self.brackets = brackets;
+        self.needs_update = true;
+        line
+    }
+
+    // Returns the first line where highlight needs to be updated
+    pub fn set_carets(&mut self, carets: Vec<(usize, usize)>) -> Option<usize> {
+        if self.carets == carets {
+            return None;
+        }
+        let line = self
+            .carets
+            .iter()
+            .chain(carets.iter())
+            .map(|(_, y)| *y)
+            .min();
+        self.carets = carets;
+        self.needs_update = true;
+        line
+    }
+
+    // Tokens produced by the language before layers are composed. Lines below the screen may not
+    // be tokenized yet
+    pub fn tokens(&self, y: usize) -> Option<&TokenStream> {
+        self.tokens.get(y)
+    }
+
+    // Tokens of the line after all layers are composed
+    pub fn composed_tokens(&self, y: usize) -> Option<TokenStream> {
+        self.lines.get(y).map(|l| TokenStream::from_highlights(l))
+    }
+
+    // Extra cursor at end of line has no character to highlight. Screen draws it separately
+    pub fn has_caret_at(&self, x: usize, y: usize) -> bool {
+        self.carets.contains(&(x, y))
+    }
+
     pub fn set_match(&mut self, y: usize, start: usize, end: usize) {
         if start >= end {
             return;
@@ -660,7 +1777,67 @@ impl Highlighting {
         self.clear_previous_match();
         let start = (start, y);
         let end = (end, y);
-        self.matched = Some(Region { start, end }); // XXX: Currently only one-line match is supported
+        self.matched = Some(Region {
+            start,
+            end,
+            rect: false,
+        }); // XXX: Currently only one-line match is supported
+    }
+
+    // Some line in the range has a match of text search
+    pub fn has_match_in(&self, lines: Range<usize>) -> bool {
+        self.others.range(lines).next().is_some()
+    }
+
+    // Set ranges of all matches as (y, start, end) sorted by position. Returns the first line where
+    // highlight needs to be updated
+    pub fn set_other_matches(&mut self, matches: &[(usize, usize, usize)]) -> Option<usize> {
+        let mut others = BTreeMap::new();
+        for &(y, start, end) in matches {
+            others.entry(y).or_insert_with(Vec::new).push((start, end));
+        }
+        if others == self.others {
+            return None;
+        }
+        let prev = self.others.keys().next().copied();
+        let line = [prev, others.keys().next().copied()]
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+        self.others = others;
+        self.needs_update = true;
+        line
+    }
+
+    // Set highlights by plugins as (y, start, end, highlight) on top of syntax highlighting.
+    // Returns the first line where highlight needs to be updated
+    pub fn set_spans(&mut self, spans: &[(usize, usize, usize, Highlight)]) -> Option<usize> {
+        let mut lines = BTreeMap::new();
+        for &(y, start, end, hl) in spans {
+            lines
+                .entry(y)
+                .or_insert_with(TokenStream::new)
+                .push(start, end, hl);
+        }
+        if lines == self.spans {
+            return None;
+        }
+        let same = self
+            .spans
+            .iter()
+            .zip(lines.iter())
+            .take_while(|(prev, next)| prev == next)
+            .count();
+        let line = [self.spans.keys().nth(same), lines.keys().nth(same)]
+            .iter()
+            .flatten()
+            .min()
+            .copied()
+            .copied();
+        self.spans = lines;
+        self.needs_update = true;
+        line
     }
 
     pub fn clear_previous_match(&mut self) -> Option<usize> {
@@ -671,4 +1848,47 @@ impl Highlighting {
             None
         }
     }
+
+    // Returns the first line where highlight needs to be updated
+    pub fn set_selection(&mut self, start: (usize, usize), end: (usize, usize)) -> Option<usize> {
+        self.update_selection(Region {
+            start,
+            end,
+            rect: false,
+        })
+    }
+
+    // Rectangle is given as (left, top) and (right, bottom). Right is exclusive
+    pub fn set_rect_selection(
+        &mut self,
+        start: (usize, usize),
+        end: (usize, usize),
+    ) -> Option<usize> {
+        self.update_selection(Region {
+            start,
+            end,
+            rect: true,
+        })
+    }
+
+    fn update_selection(&mut self, selection: Region) -> Option<usize> {
+        let start = selection.start;
+        if self.selection.as_ref() == Some(&selection) {
+            return None;
+        }
+        let line = match &self.selection {
+            Some(prev) => cmp::min(prev.start.1, start.1),
+            None => start.1,
+        };
+        self.selection = Some(selection);
+        self.needs_update = true;
+        Some(line)
+    }
+
+    // Returns the first line where highlight needs to be updated
+    pub fn clear_selection(&mut self) -> Option<usize> {
+        let line = self.selection.take()?.start.1;
+        self.needs_update = true;
+        Some(line)
+    }
 }
@@ -0,0 +1,157 @@
+// History of inputs in prompts such as search queries. It is persisted to a state file so that
+// the inputs can be recalled in later sessions. Each line of the file is a kind and an input
+// separated by a tab. Tabs, newlines and backslashes in inputs are escaped.
+//
+//   search	fn main
+//   replace	foo\tbar
+
+use crate::error::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+// Number of kept inputs for each kind. Older ones are dropped
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HistoryKind {
+    Search,
+    Replace,
+}
+
+impl HistoryKind {
+    const ALL: [HistoryKind; 2] = [HistoryKind::Search, HistoryKind::Replace];
+
+    fn name(self) -> &'static str {
+        match self {
+            HistoryKind::Search => "search",
+            HistoryKind::Replace => "replace",
+        }
+    }
+}
+
+fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(line: &str) -> Option<String> {
+    let mut input = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            input.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => input.push('\\'),
+            't' => input.push('\t'),
+            'n' => input.push('\n'),
+            _ => return None,
+        }
+    }
+    Some(input)
+}
+
+#[derive(Default)]
+pub struct History {
+    entries: Vec<(HistoryKind, String)>, // Older first
+}
+
+impl History {
+    // Broken lines in the state file are ignored. Missing state file is the same as empty history
+    pub fn load(state: &Path) -> History {
+        let source = fs::read_to_string(state).unwrap_or_default();
+        let mut history = History::default();
+        for line in source.lines() {
+            let parsed = line.split_once('\t').and_then(|(name, input)| {
+                let kind = HistoryKind::ALL.iter().find(|k| k.name() == name)?;
+                Some((*kind, unescape(input)?))
+            });
+            if let Some((kind, input)) = parsed {
+                history.push(kind, &input);
+            }
+        }
+        history
+    }
+
+    pub fn save(&self, state: &Path) -> Result<()> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(kind, input)| format!("{}\t{}\n", kind.name(), escape(input)))
+            .collect();
+        if let Some(dir) = state.parent() {
+            fs::create_dir_all(dir).context("creating", dir)?;
+        }
+        fs::write(state, content).context("saving", state)
+    }
+
+    // The same input entered previously moves to the newest. Empty input is not recorded
+    pub fn push(&mut self, kind: HistoryKind, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        self.entries.retain(|(k, i)| *k != kind || i != input);
+        self.entries.push((kind, input.to_string()));
+        let count = self.entries.iter().filter(|(k, _)| *k == kind).count();
+        if count > MAX_ENTRIES {
+            let oldest = self.entries.iter().position(|(k, _)| *k == kind).unwrap();
+            self.entries.remove(oldest);
+        }
+    }
+
+    pub fn recall(&self, kind: HistoryKind) -> Recall {
+        Recall {
+            entries: self
+                .entries
+                .iter()
+                .filter(|(k, _)| *k == kind)
+                .map(|(_, i)| i.clone())
+                .collect(),
+            idx: None,
+            typed: String::new(),
+        }
+    }
+}
+
+// State of browsing history in one prompt. Going newer from the newest entry restores the input
+// typed before browsing
+pub struct Recall {
+    entries: Vec<String>,
+    idx: Option<usize>, // None while editing the typed input
+    typed: String,
+}
+
+impl Recall {
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let idx = match self.idx {
+            Some(0) => return None,
+            Some(i) => i - 1,
+            None => {
+                self.typed = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+        };
+        self.idx = Some(idx);
+        Some(&self.entries[idx])
+    }
+
+    pub fn newer(&mut self) -> Option<&str> {
+        let idx = self.idx?;
+        if idx + 1 < self.entries.len() {
+            self.idx = Some(idx + 1);
+            Some(&self.entries[idx + 1])
+        } else {
+            self.idx = None;
+            Some(&self.typed)
+        }
+    }
+}
@@ -1,72 +1,14 @@
 use std::fmt;
 use std::io::{self, Read};
-use std::ops::{Deref, DerefMut};
-use std::os::unix::io::AsRawFd;
 use std::str;
 
-pub struct StdinRawMode {
-    stdin: io::Stdin,
-    orig: termios::Termios,
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScrollDir {
+    Up,
+    Down,
 }
 
-// TODO: Separate editor into frontend and backend. In frontend, it handles actual screen and user input.
-// It interacts with backend by responding to request from frontend. Frontend focues on core editor
-// logic. This is useful when adding a new frontend (e.g. wasm).
-
-impl StdinRawMode {
-    pub fn new() -> io::Result<StdinRawMode> {
-        use termios::*;
-
-        let stdin = io::stdin();
-        let fd = stdin.as_raw_fd();
-        let mut termios = Termios::from_fd(fd)?;
-        let orig = termios;
-
-        // Set terminal raw mode. Disable echo back, canonical mode, signals (SIGINT, SIGTSTP) and Ctrl+V.
-        termios.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
-        // Disable control flow mode (Ctrl+Q/Ctrl+S) and CR-to-NL translation
-        termios.c_iflag &= !(IXON | ICRNL | BRKINT | INPCK | ISTRIP);
-        // Disable output processing such as \n to \r\n translation
-        termios.c_oflag &= !OPOST;
-        // Ensure character size is 8bits
-        termios.c_cflag |= CS8;
-        // Do not wait for next byte with blocking since reading 0 byte is permitted
-        termios.c_cc[VMIN] = 0;
-        // Set read timeout to 1/10 second it enables 100ms timeout on read()
-        termios.c_cc[VTIME] = 1;
-        // Apply terminal configurations
-        tcsetattr(fd, TCSAFLUSH, &termios)?;
-
-        Ok(StdinRawMode { stdin, orig })
-    }
-
-    pub fn input_keys(self) -> InputSequences {
-        InputSequences { stdin: self }
-    }
-}
-
-impl Drop for StdinRawMode {
-    fn drop(&mut self) {
-        // Restore original terminal mode
-        termios::tcsetattr(self.stdin.as_raw_fd(), termios::TCSAFLUSH, &self.orig).unwrap();
-    }
-}
-
-impl Deref for StdinRawMode {
-    type Target = io::Stdin;
-
-    fn deref(&self) -> &Self::Target {
-        &self.stdin
-    }
-}
-
-impl DerefMut for StdinRawMode {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stdin
-    }
-}
-
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum KeySeq {
     Unidentified,
     Utf8Key(char),
@@ -80,7 +22,10 @@ pub enum KeySeq {
     HomeKey,
     EndKey,
     DeleteKey,
-    Cursor(usize, usize), // Pseudo key (x, y)
+    Cursor(usize, usize),         // Pseudo key (x, y)
+    MouseClick(usize, usize, u8), // (row, col, button) 1-based row and col. Left button is 0
+    MouseScroll(ScrollDir),
+    Resize(usize, usize), // Pseudo key (cols, rows) sent by frontend when window size changed
 }
 
 impl fmt::Display for KeySeq {
@@ -102,15 +47,20 @@ impl fmt::Display for KeySeq {
             EndKey => write!(f, "END"),
             DeleteKey => write!(f, "DELETE"),
             Cursor(r, c) => write!(f, "CURSOR({},{})", r, c),
+            MouseClick(r, c, b) => write!(f, "CLICK{}({},{})", b, r, c),
+            MouseScroll(ScrollDir::Up) => write!(f, "SCROLLUP"),
+            MouseScroll(ScrollDir::Down) => write!(f, "SCROLLDOWN"),
+            Resize(c, r) => write!(f, "RESIZE({},{})", c, r),
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct InputSeq {
     pub key: KeySeq,
     pub ctrl: bool,
     pub alt: bool,
+    pub shift: bool, // Only set for special keys such as arrow keys
 }
 
 impl InputSeq {
@@ -119,6 +69,7 @@ impl InputSeq {
             key,
             ctrl: false,
             alt: false,
+            shift: false,
         }
     }
 
@@ -127,6 +78,24 @@ impl InputSeq {
             key,
             ctrl: true,
             alt: false,
+            shift: false,
+        }
+    }
+
+    // xterm sends modifier keys as parameter of escape sequence. e.g. S-<UP> => \x1b[1;2A
+    // The parameter is 1 + (1 for shift) + (2 for alt) + (4 for ctrl)
+    // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-PC-Style-Function-Keys
+    fn with_modifiers(key: KeySeq, param: Option<&[u8]>) -> Self {
+        let mods = param
+            .and_then(|b| str::from_utf8(b).ok())
+            .and_then(|s| s.parse::<u8>().ok())
+            .map(|m| m.saturating_sub(1))
+            .unwrap_or(0);
+        Self {
+            key,
+            ctrl: mods & 4 != 0,
+            alt: mods & 2 != 0,
+            shift: mods & 1 != 0,
         }
     }
 }
@@ -139,18 +108,38 @@ impl fmt::Display for InputSeq {
         if self.alt {
             write!(f, "M-")?;
         }
+        if self.shift {
+            write!(f, "S-")?;
+        }
         write!(f, "{}", self.key)
     }
 }
 
-pub struct InputSequences {
-    stdin: StdinRawMode,
+// Number of read timeouts waiting for continuation bytes of UTF-8 sequence. About 1 second
+const UTF8_TIMEOUTS: usize = 10;
+
+// Decodes bytes sent from terminal into key inputs. Reading the reader must time out and return 0
+// bytes when nothing arrives so that a lone ESC key can be distinguished from escape sequences.
+pub struct InputSequences<R: Read> {
+    reader: R,
 }
 
-impl InputSequences {
+impl<R: Read> InputSequences<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    pub fn reader(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
     fn read_byte(&mut self) -> io::Result<Option<u8>> {
         let mut one_byte: [u8; 1] = [0];
-        Ok(if self.stdin.read(&mut one_byte)? == 0 {
+        Ok(if self.reader.read(&mut one_byte)? == 0 {
             None
         } else {
             Some(one_byte[0])
@@ -165,14 +154,23 @@ impl InputSequences {
         // TODO?: Should we consider sequences not starting with '['?
         match self.read_byte()? {
             Some(b'[') => { /* fall thought */ }
-            Some(b) if b.is_ascii_control() => return Ok(InputSeq::new(Key(0x1b))), // Ignore control characters after ESC
+            Some(b'O') => return self.decode_ss3(),
+            Some(0x1b) => {
+                // ESC ESC is Alt-ESC. Decoding the second ESC as escape sequence would wait for
+                // following bytes
+                let mut seq = InputSeq::new(Key(0x1b));
+                seq.alt = true;
+                return Ok(seq);
+            }
             Some(b) => {
-                // Alt key is sent as ESC prefix (e.g. Alt-A => \x1b\x61
+                // Alt key is sent as ESC prefix (e.g. Alt-A => \x1b\x61, Ctrl-Alt-A => \x1b\x01)
                 // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Alt-and-Meta-Keys
                 let mut seq = self.decode(b)?;
                 seq.alt = true;
                 return Ok(seq);
             }
+            // Lone ESC key. Since no byte followed within the read timeout (VTIME), it is not
+            // a header of escape sequence
             None => return Ok(InputSeq::new(Key(0x1b))),
         };
 
@@ -183,8 +181,9 @@ impl InputSequences {
             if let Some(b) = self.read_byte()? {
                 match b {
                     // Control command chars from http://ascii-table.com/ansi-escape-sequences-vt-100.php
-                    b'A' | b'B' | b'C' | b'D' | b'F' | b'H' | b'K' | b'J' | b'R' | b'c' | b'f'
-                    | b'g' | b'h' | b'l' | b'm' | b'n' | b'q' | b't' | b'y' | b'~' => break b,
+                    b'A' | b'B' | b'C' | b'D' | b'F' | b'H' | b'K' | b'J' | b'M' | b'R' | b'Z'
+                    | b'c' | b'f' | b'g' | b'h' | b'l' | b'm' | b'n' | b'q' | b't' | b'y'
+                    | b'~' => break b,
                     _ => buf.push(b),
                 }
             } else {
@@ -197,6 +196,10 @@ impl InputSequences {
             str::from_utf8(b).ok().and_then(|s| s.parse().ok())
         }
 
+        if buf.first() == Some(&b'<') && (cmd == b'M' || cmd == b'm') {
+            return Ok(InputSeq::new(Self::decode_sgr_mouse(&buf[1..], cmd)));
+        }
+
         let mut args = buf.split(|b| *b == b';');
         match cmd {
             b'R' => {
@@ -217,20 +220,19 @@ impl InputSequences {
                     b'D' => LeftKey,
                     _ => unreachable!(),
                 };
-                let ctrl = args.next() == Some(b"1") && args.next() == Some(b"5");
-                let alt = false;
-                Ok(InputSeq { key, ctrl, alt })
+                Ok(InputSeq::with_modifiers(key, args.nth(1)))
             }
             b'~' => {
-                // e.g. \x1b[5~
-                match args.next() {
-                    Some(b"5") => Ok(InputSeq::new(PageUpKey)),
-                    Some(b"6") => Ok(InputSeq::new(PageDownKey)),
-                    Some(b"1") | Some(b"7") => Ok(InputSeq::new(HomeKey)),
-                    Some(b"4") | Some(b"8") => Ok(InputSeq::new(EndKey)),
-                    Some(b"3") => Ok(InputSeq::new(DeleteKey)),
-                    _ => Ok(InputSeq::new(Unidentified)),
-                }
+                // e.g. \x1b[5~, C-<DELETE> => \x1b[3;5~
+                let key = match args.next() {
+                    Some(b"5") => PageUpKey,
+                    Some(b"6") => PageDownKey,
+                    Some(b"1") | Some(b"7") => HomeKey,
+                    Some(b"4") | Some(b"8") => EndKey,
+                    Some(b"3") => DeleteKey,
+                    _ => return Ok(InputSeq::new(Unidentified)),
+                };
+                Ok(InputSeq::with_modifiers(key, args.next()))
             }
             b'H' | b'F' => {
                 // C-HOME => \x1b[1;5H
@@ -239,34 +241,91 @@ impl InputSequences {
                     b'F' => EndKey,
                     _ => unreachable!(),
                 };
-                let ctrl = args.next() == Some(b"1") && args.next() == Some(b"5");
-                let alt = false;
-                Ok(InputSeq { key, ctrl, alt })
+                Ok(InputSeq::with_modifiers(key, args.nth(1)))
             }
-            _ => unreachable!(),
+            b'Z' => {
+                // Shift-Tab => \x1b[Z. Tab is sent as Ctrl-I
+                let mut seq = InputSeq::ctrl(Key(b'i'));
+                seq.shift = true;
+                Ok(seq)
+            }
+            _ => Ok(InputSeq::new(Unidentified)),
         }
     }
 
-    fn decode_utf8(&mut self, b: u8) -> io::Result<InputSeq> {
+    // Some terminals send cursor keys, HOME and END as SS3 sequences (\x1bO) in application cursor
+    // mode. e.g. <HOME> => \x1bOH. It is Alt-Shift-O when no byte follows within the timeout
+    fn decode_ss3(&mut self) -> io::Result<InputSeq> {
         use KeySeq::*;
-
-        let mut buf = Vec::with_capacity(4);
-        buf.push(b);
-        loop {
-            if let Some(b) = self.read_byte()? {
-                buf.push(b);
-            } else {
-                return Ok(InputSeq::new(Unidentified));
+        let key = match self.read_byte()? {
+            Some(b'A') => UpKey,
+            Some(b'B') => DownKey,
+            Some(b'C') => RightKey,
+            Some(b'D') => LeftKey,
+            Some(b'H') => HomeKey,
+            Some(b'F') => EndKey,
+            Some(_) => Unidentified, // e.g. F1~F4 keys
+            None => {
+                let mut seq = InputSeq::new(Key(b'O'));
+                seq.alt = true;
+                return Ok(seq);
             }
+        };
+        Ok(InputSeq::new(key))
+    }
 
-            if let Ok(s) = str::from_utf8(&buf) {
-                return Ok(InputSeq::new(Utf8Key(s.chars().next().unwrap())));
-            }
+    // SGR extended mouse mode (1006) reports mouse events as \x1b[<{button};{col};{row}M on press
+    // and \x1b[<{button};{col};{row}m on release.
+    // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+    fn decode_sgr_mouse(args: &[u8], cmd: u8) -> KeySeq {
+        let mut args = args
+            .split(|b| *b == b';')
+            .map(|b| str::from_utf8(b).ok().and_then(|s| s.parse::<usize>().ok()));
+        let (button, col, row) = match (args.next(), args.next(), args.next()) {
+            (Some(Some(b)), Some(Some(c)), Some(Some(r))) => (b, c, r),
+            _ => return KeySeq::Unidentified,
+        };
 
-            if buf.len() == 4 {
-                return Ok(InputSeq::new(Unidentified));
+        // Lower 2 bits are button number. Bits for modifier keys (4, 8, 16) are ignored. 32 is for
+        // motion and 64 is for wheel
+        let modifiers = 4 | 8 | 16;
+        match button & !modifiers {
+            64 => KeySeq::MouseScroll(ScrollDir::Up),
+            65 => KeySeq::MouseScroll(ScrollDir::Down),
+            b @ 0..=2 if cmd == b'M' => KeySeq::MouseClick(row, col, b as u8),
+            _ => KeySeq::Unidentified, // Release and motion events are not handled
+        }
+    }
+
+    fn decode_utf8(&mut self, b: u8) -> io::Result<InputSeq> {
+        use KeySeq::*;
+
+        // Length of UTF-8 sequence is known from its first byte. Invalid first bytes such as
+        // continuation bytes are ignored without consuming following bytes
+        let len = match b {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return Ok(InputSeq::new(Unidentified)),
+        };
+
+        // Input methods commit composed text at once, but its bytes may arrive in separate reads on
+        // slow connections. Rest of the character is waited for a while instead of dropping it
+        let mut buf = Vec::with_capacity(len);
+        buf.push(b);
+        let mut timeouts = 0;
+        while buf.len() < len {
+            match self.read_byte()? {
+                Some(b) if b & 0b1100_0000 == 0b1000_0000 => buf.push(b),
+                None if timeouts < UTF8_TIMEOUTS => timeouts += 1,
+                _ => return Ok(InputSeq::new(Unidentified)),
             }
         }
+
+        Ok(InputSeq::new(match str::from_utf8(&buf) {
+            Ok(s) => Utf8Key(s.chars().next().unwrap()),
+            Err(_) => Unidentified, // e.g. Overlong encoding or surrogate
+        }))
     }
 
     fn decode(&mut self, b: u8) -> io::Result<InputSeq> {
@@ -296,10 +355,10 @@ impl InputSequences {
     }
 }
 
-impl Iterator for InputSequences {
+impl<R: Read> Iterator for InputSequences<R> {
     type Item = io::Result<InputSeq>;
 
-    // Read next byte from stdin with timeout 100ms. If nothing was read, it returns InputSeq::Unidentified.
+    // Read next byte from the reader with timeout 100ms. If nothing was read, it returns InputSeq::Unidentified.
     // This method never returns None so for loop never ends
     fn next(&mut self) -> Option<Self::Item> {
         Some(self.read_seq())
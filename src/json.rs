@@ -0,0 +1,240 @@
+// Small JSON parser and writer for messages of the remote protocol. Objects keep the order of
+// their keys. Numbers are f64 as in JavaScript.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(src: &str) -> Result<Json, String> {
+        let mut chars = src.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after value", c)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+type Input<'a> = Peekable<Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Input<'_>) {
+    while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect_word(chars: &mut Input<'_>, word: &str, value: Json) -> Result<Json, String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("Invalid literal. '{}' was expected", word));
+        }
+    }
+    Ok(value)
+}
+
+fn parse_value(chars: &mut Input<'_>) -> Result<Json, String> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        None => Err("Unexpected end of input".to_string()),
+        Some('n') => expect_word(chars, "null", Json::Null),
+        Some('t') => expect_word(chars, "true", Json::Bool(true)),
+        Some('f') => expect_word(chars, "false", Json::Bool(false)),
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('[') => {
+            chars.next();
+            let mut elems = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(elems));
+            }
+            loop {
+                elems.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(elems)),
+                    _ => return Err("',' or ']' was expected in array".to_string()),
+                }
+            }
+        }
+        Some('{') => {
+            chars.next();
+            let mut entries = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(entries));
+            }
+            loop {
+                skip_whitespace(chars);
+                if chars.peek() != Some(&'"') {
+                    return Err("String key was expected in object".to_string());
+                }
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                if chars.next() != Some(':') {
+                    return Err(format!("':' was expected after key \"{}\"", key));
+                }
+                entries.push((key, parse_value(chars)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(entries)),
+                    _ => return Err("',' or '}' was expected in object".to_string()),
+                }
+            }
+        }
+        Some(c) if *c == '-' || c.is_ascii_digit() => {
+            let mut num = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || "+-.eE".contains(c) {
+                    num.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            num.parse()
+                .map(Json::Number)
+                .map_err(|_| format!("Invalid number '{}'", num))
+        }
+        Some(c) => Err(format!("Unexpected '{}'", c)),
+    }
+}
+
+fn parse_hex4(chars: &mut Input<'_>) -> Result<u32, String> {
+    let hex: String = chars.take(4).collect();
+    match u32::from_str_radix(&hex, 16) {
+        Ok(u) if hex.len() == 4 => Ok(u),
+        _ => Err(format!("Invalid unicode escape '\\u{}'", hex)),
+    }
+}
+
+fn parse_string(chars: &mut Input<'_>) -> Result<String, String> {
+    chars.next(); // Opening quote
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("Unterminated string".to_string()),
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let c = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let mut u = parse_hex4(chars)?;
+                        // Characters out of BMP are encoded as surrogate pairs
+                        if (0xd800..0xdc00).contains(&u) {
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err("Lone surrogate in unicode escape".to_string());
+                            }
+                            let low = parse_hex4(chars)?;
+                            u = 0x10000 + ((u - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        char::from_u32(u).ok_or("Invalid unicode escape")?
+                    }
+                    _ => return Err("Invalid escape sequence in string".to_string()),
+                };
+                s.push(c);
+            }
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+// Serialized in one line so that a message is delimited by newline
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => f.write_str("null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(elems) => {
+                f.write_str("[")?;
+                for (i, e) in elems.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                f.write_str("]")
+            }
+            Json::Object(entries) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+// Destinations of jump commands such as go-to-line
+
+// Parse input of go-to-line prompt and returns 0-based line index. Accepted formats are:
+//
+//   120  # 1-based line number
+//   +20  # 20 lines below the current line
+//   -5   # 5 lines above the current line
+//   50%  # Line at the percentage of the buffer
+//
+// Line out of the buffer is clamped to the last line
+pub fn parse_line(input: &str, cy: usize, len: usize) -> Option<usize> {
+    let input = input.trim();
+    let last = len.saturating_sub(1);
+    let y = if let Some(percent) = input.strip_suffix('%') {
+        let percent: usize = percent.parse().ok()?;
+        // Same as Vim. 50% of 100 lines is the 50th line
+        (len * percent.min(100)).div_ceil(100).saturating_sub(1)
+    } else if let Some(n) = input.strip_prefix('+') {
+        cy.saturating_add(n.parse().ok()?)
+    } else if let Some(n) = input.strip_prefix('-') {
+        cy.saturating_sub(n.parse().ok()?)
+    } else {
+        input.parse::<usize>().ok()?.saturating_sub(1)
+    };
+    Some(y.min(last))
+}
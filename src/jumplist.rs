@@ -0,0 +1,50 @@
+// History of cursor positions before large jumps such as text search and go-to-line. Going back
+// and forward in the history works like a web browser's one. When a new jump happens after going
+// back, newer positions are dropped.
+
+const MAX_JUMPS: usize = 100;
+
+#[derive(Default)]
+pub struct JumpList {
+    positions: Vec<(usize, usize)>,
+    // Current position in the history. It is equal to the length when not going back
+    idx: usize,
+}
+
+impl JumpList {
+    pub fn push(&mut self, pos: (usize, usize)) {
+        self.positions.truncate(self.idx);
+        if self.positions.last() != Some(&pos) {
+            self.positions.push(pos);
+        }
+        if self.positions.len() > MAX_JUMPS {
+            self.positions.remove(0);
+        }
+        self.idx = self.positions.len();
+    }
+
+    // `current` is the current cursor position. It is remembered on first going back so that going
+    // forward can return to it
+    pub fn back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        if self.idx == 0 {
+            return None;
+        }
+        if self.idx == self.positions.len() {
+            self.push(current);
+            if self.positions.len() < 2 {
+                return None; // Only the current position is in the history
+            }
+            self.idx = self.positions.len() - 1;
+        }
+        self.idx -= 1;
+        Some(self.positions[self.idx])
+    }
+
+    pub fn forward(&mut self) -> Option<(usize, usize)> {
+        if self.idx + 1 >= self.positions.len() {
+            return None;
+        }
+        self.idx += 1;
+        Some(self.positions[self.idx])
+    }
+}
@@ -0,0 +1,832 @@
+use crate::input::{InputSeq, KeySeq};
+
+// Editor commands which can be bound to keys. Name of each command is used in config file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Quit,
+    Save,
+    Open,
+    NextBuffer,
+    PreviousBuffer,
+    SelectBuffer,
+    CursorUp,
+    CursorDown,
+    CursorRight,
+    CursorLeft,
+    HeadOfLine,
+    EndOfLine,
+    NextPage,
+    PreviousPage,
+    NextWord,
+    PreviousWord,
+    EndOfWord,
+    NextParagraph,
+    PreviousParagraph,
+    TopOfFile,
+    BottomOfFile,
+    DeleteChar,
+    DeleteNextChar,
+    DeleteWord,
+    DeleteToHead,
+    DeleteToEnd,
+    ToggleMark,
+    RectangleMark,
+    Copy,
+    Paste,
+    Yank,
+    YankPop,
+    Undo,
+    Redo,
+    RepeatEdit,
+    Search,
+    RegexSearch,
+    Replace,
+    Indent,
+    Dedent,
+    ToggleComment,
+    DuplicateLine,
+    MoveLineUp,
+    MoveLineDown,
+    AddCursor,
+    NewLine,
+    Refresh,
+    Suspend,
+    Shell,
+    FilterSelection,
+    Compile,
+    NextError,
+    PreviousError,
+    Blame,
+    ShowCommit,
+    NextHunk,
+    PreviousHunk,
+    DiffPut,
+    NextConflict,
+    PreviousConflict,
+    KeepOurs,
+    KeepTheirs,
+    KeepBoth,
+    ToggleSpellCheck,
+    SpellSuggest,
+    Complete,
+    LiteralInsert,
+    FormatBuffer,
+    RecentFiles,
+    QuickOpen,
+    Fold,
+    Unfold,
+    ToggleFold,
+    FoldAll,
+    UnfoldAll,
+    ShowMessages,
+    Follow,
+    CenterLine,
+    LineToTop,
+    LineToBottom,
+    EndOfScreenLine,
+    ToggleInvisibles,
+    ToggleRainbowBrackets,
+    ToggleIndentGuides,
+    Help,
+    Palette,
+    SetLanguage,
+    SetTheme,
+    SetTabWidth,
+    ToggleExpandTab,
+    SetIndent,
+    SetEncoding,
+    GoToOffset,
+    FindFile,
+    GoToLine,
+    JumpBack,
+    JumpForward,
+    SetBookmark,
+    GoToBookmark,
+    MatchingBracket,
+}
+
+impl Command {
+    // Commands in the order shown in help
+    pub const ALL: &'static [Command] = {
+        use Command::*;
+        &[
+            Quit,
+            Save,
+            Open,
+            NextBuffer,
+            PreviousBuffer,
+            SelectBuffer,
+            CursorUp,
+            CursorDown,
+            CursorRight,
+            CursorLeft,
+            HeadOfLine,
+            EndOfLine,
+            NextPage,
+            PreviousPage,
+            NextWord,
+            PreviousWord,
+            EndOfWord,
+            NextParagraph,
+            PreviousParagraph,
+            TopOfFile,
+            BottomOfFile,
+            DeleteChar,
+            DeleteNextChar,
+            DeleteWord,
+            DeleteToHead,
+            DeleteToEnd,
+            ToggleMark,
+            RectangleMark,
+            Copy,
+            Paste,
+            Yank,
+            YankPop,
+            Undo,
+            Redo,
+            RepeatEdit,
+            Search,
+            RegexSearch,
+            Replace,
+            Indent,
+            Dedent,
+            ToggleComment,
+            DuplicateLine,
+            MoveLineUp,
+            MoveLineDown,
+            AddCursor,
+            NewLine,
+            Refresh,
+            Suspend,
+            Shell,
+            FilterSelection,
+            Compile,
+            NextError,
+            PreviousError,
+            Blame,
+            ShowCommit,
+            NextHunk,
+            PreviousHunk,
+            DiffPut,
+            NextConflict,
+            PreviousConflict,
+            KeepOurs,
+            KeepTheirs,
+            KeepBoth,
+            ToggleSpellCheck,
+            SpellSuggest,
+            Complete,
+            LiteralInsert,
+            FormatBuffer,
+            RecentFiles,
+            QuickOpen,
+            Fold,
+            Unfold,
+            ToggleFold,
+            FoldAll,
+            UnfoldAll,
+            ShowMessages,
+            Follow,
+            CenterLine,
+            LineToTop,
+            LineToBottom,
+            EndOfScreenLine,
+            ToggleInvisibles,
+            ToggleRainbowBrackets,
+            ToggleIndentGuides,
+            Help,
+            Palette,
+            SetLanguage,
+            SetTheme,
+            SetTabWidth,
+            ToggleExpandTab,
+            SetIndent,
+            SetEncoding,
+            GoToOffset,
+            FindFile,
+            GoToLine,
+            JumpBack,
+            JumpForward,
+            SetBookmark,
+            GoToBookmark,
+            MatchingBracket,
+        ]
+    };
+
+    pub fn name(self) -> &'static str {
+        use Command::*;
+        match self {
+            Quit => "quit",
+            Save => "save",
+            Open => "open",
+            NextBuffer => "next-buffer",
+            PreviousBuffer => "previous-buffer",
+            SelectBuffer => "select-buffer",
+            CursorUp => "cursor-up",
+            CursorDown => "cursor-down",
+            CursorRight => "cursor-right",
+            CursorLeft => "cursor-left",
+            HeadOfLine => "head-of-line",
+            EndOfLine => "end-of-line",
+            NextPage => "next-page",
+            PreviousPage => "previous-page",
+            NextWord => "next-word",
+            PreviousWord => "previous-word",
+            EndOfWord => "end-of-word",
+            NextParagraph => "next-paragraph",
+            PreviousParagraph => "previous-paragraph",
+            TopOfFile => "top-of-file",
+            BottomOfFile => "bottom-of-file",
+            DeleteChar => "delete-char",
+            DeleteNextChar => "delete-next-char",
+            DeleteWord => "delete-word",
+            DeleteToHead => "delete-to-head",
+            DeleteToEnd => "delete-to-end",
+            ToggleMark => "toggle-mark",
+            RectangleMark => "rectangle-mark",
+            Copy => "copy",
+            Paste => "paste",
+            Yank => "yank",
+            YankPop => "yank-pop",
+            Undo => "undo",
+            Redo => "redo",
+            RepeatEdit => "repeat-edit",
+            Search => "search",
+            RegexSearch => "regex-search",
+            Replace => "replace",
+            Indent => "indent",
+            Dedent => "dedent",
+            ToggleComment => "toggle-comment",
+            DuplicateLine => "duplicate-line",
+            MoveLineUp => "move-line-up",
+            MoveLineDown => "move-line-down",
+            AddCursor => "add-cursor",
+            NewLine => "new-line",
+            Refresh => "refresh",
+            Suspend => "suspend",
+            Shell => "shell-command",
+            FilterSelection => "filter-selection",
+            Compile => "compile",
+            NextError => "next-error",
+            PreviousError => "previous-error",
+            Blame => "blame",
+            ShowCommit => "show-commit",
+            NextHunk => "next-hunk",
+            PreviousHunk => "previous-hunk",
+            DiffPut => "diff-put",
+            NextConflict => "next-conflict",
+            PreviousConflict => "previous-conflict",
+            KeepOurs => "keep-ours",
+            KeepTheirs => "keep-theirs",
+            KeepBoth => "keep-both",
+            ToggleSpellCheck => "toggle-spell-check",
+            SpellSuggest => "spell-suggest",
+            Complete => "complete",
+            LiteralInsert => "literal-insert",
+            FormatBuffer => "format-buffer",
+            RecentFiles => "recent-files",
+            QuickOpen => "quick-open",
+            Fold => "fold",
+            Unfold => "unfold",
+            ToggleFold => "toggle-fold",
+            FoldAll => "fold-all",
+            UnfoldAll => "unfold-all",
+            ShowMessages => "show-messages",
+            Follow => "follow",
+            CenterLine => "center-line",
+            LineToTop => "line-to-top",
+            LineToBottom => "line-to-bottom",
+            EndOfScreenLine => "end-of-screen-line",
+            ToggleInvisibles => "toggle-invisibles",
+            ToggleRainbowBrackets => "toggle-rainbow-brackets",
+            ToggleIndentGuides => "toggle-indent-guides",
+            Help => "help",
+            Palette => "command-palette",
+            SetLanguage => "set-language",
+            SetTheme => "set-theme",
+            SetTabWidth => "set-tab-width",
+            ToggleExpandTab => "toggle-expand-tab",
+            SetIndent => "set-indent",
+            SetEncoding => "set-encoding",
+            GoToOffset => "go-to-offset",
+            FindFile => "find-file",
+            GoToLine => "go-to-line",
+            JumpBack => "jump-back",
+            JumpForward => "jump-forward",
+            SetBookmark => "set-bookmark",
+            GoToBookmark => "go-to-bookmark",
+            MatchingBracket => "matching-bracket",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Command> {
+        Command::ALL.iter().copied().find(|c| c.name() == name)
+    }
+
+    pub fn description(self) -> &'static str {
+        use Command::*;
+        match self {
+            Quit => "Quit",
+            Save => "Save to file",
+            Open => "Open text buffer",
+            NextBuffer => "Next text buffer",
+            PreviousBuffer => "Previous text buffer",
+            SelectBuffer => "Switch text buffer by name",
+            CursorUp => "Move cursor up",
+            CursorDown => "Move cursor down",
+            CursorRight => "Move cursor right",
+            CursorLeft => "Move cursor left",
+            HeadOfLine => "Move cursor to head of line",
+            EndOfLine => "Move cursor to end of line",
+            NextPage => "Next page",
+            PreviousPage => "Previous page",
+            NextWord => "Move cursor to next word",
+            PreviousWord => "Move cursor to previous word",
+            EndOfWord => "Move cursor to end of word",
+            NextParagraph => "Move cursor to next paragraph",
+            PreviousParagraph => "Move cursor to previous paragraph",
+            TopOfFile => "Move cursor to top of file",
+            BottomOfFile => "Move cursor to bottom of file",
+            DeleteChar => "Delete character",
+            DeleteNextChar => "Delete next character",
+            DeleteWord => "Delete a word or cut selected text",
+            DeleteToHead => "Delete until head of line",
+            DeleteToEnd => "Delete until end of line",
+            ToggleMark => "Set or unset mark to select text",
+            RectangleMark => "Set or unset mark to select rectangle",
+            Copy => "Copy selected text or current line",
+            Paste => "Paste text from clipboard",
+            Yank => "Paste last killed text",
+            YankPop => "Replace pasted text with earlier killed text",
+            Undo => "Undo last change",
+            Redo => "Redo last undone change",
+            RepeatEdit => "Repeat last edit at cursor",
+            Search => "Search text",
+            RegexSearch => "Search text with regular expression",
+            Replace => "Replace text interactively",
+            Indent => "Insert tab or indent selected lines",
+            Dedent => "Dedent current or selected lines",
+            ToggleComment => "Comment out or uncomment lines",
+            DuplicateLine => "Duplicate current line or selected lines",
+            MoveLineUp => "Move current line or selected lines up",
+            MoveLineDown => "Move current line or selected lines down",
+            AddCursor => "Add cursor at next occurrence of word",
+            NewLine => "New line",
+            Refresh => "Refresh screen",
+            Suspend => "Suspend editor and go back to shell",
+            Shell => "Insert output of shell command at cursor",
+            FilterSelection => "Replace selected text with output of shell command",
+            Compile => "Run build command and list errors",
+            NextError => "Jump to next error of build",
+            PreviousError => "Jump to previous error of build",
+            Blame => "Show git blame of current line",
+            ShowCommit => "Open commit of current line in read-only buffer",
+            NextHunk => "Jump to next changed lines in diff mode",
+            PreviousHunk => "Jump to previous changed lines in diff mode",
+            DiffPut => "Copy changed lines at cursor to the other side in diff mode",
+            NextConflict => "Jump to next merge conflict",
+            PreviousConflict => "Jump to previous merge conflict",
+            KeepOurs => "Resolve merge conflict at cursor with our lines",
+            KeepTheirs => "Resolve merge conflict at cursor with their lines",
+            KeepBoth => "Resolve merge conflict at cursor with both lines",
+            ToggleSpellCheck => "Toggle spell checking of text and comments",
+            SpellSuggest => "Replace misspelled word at cursor with suggestions in turn",
+            Complete => "Complete word or path before cursor with candidates in turn",
+            LiteralInsert => "Insert next character without expanding abbreviation",
+            FormatBuffer => "Format buffer with the formatter of the language",
+            RecentFiles => "Open a file edited in previous sessions",
+            QuickOpen => "Open location such as 'file:line' in clipboard or current line",
+            Fold => "Fold the block at cursor into one line",
+            Unfold => "Unfold the folded line at cursor",
+            ToggleFold => "Fold or unfold the block at cursor",
+            FoldAll => "Fold all blocks in buffer",
+            UnfoldAll => "Unfold all folded lines in buffer",
+            ShowMessages => "Show log of messages in a read-only buffer",
+            Follow => "Toggle following lines appended to the file like 'tail -f'",
+            CenterLine => "Scroll the screen so that the cursor line is at the center",
+            LineToTop => "Scroll the screen so that the cursor line is at the top",
+            LineToBottom => "Scroll the screen so that the cursor line is at the bottom",
+            EndOfScreenLine => "Move cursor to the last character on the screen line",
+            ToggleInvisibles => "Toggle showing tabs, trailing spaces and non-breaking spaces",
+            ToggleRainbowBrackets => "Toggle coloring brackets by their nesting depths",
+            ToggleIndentGuides => "Toggle showing guides at indentation levels of code",
+            Help => "Show this help",
+            Palette => "Run command by name",
+            SetLanguage => "Change language of syntax highlighting",
+            SetTheme => "Change color theme",
+            SetTabWidth => "Change width of tab stop",
+            ToggleExpandTab => "Toggle inserting spaces by tab key",
+            SetIndent => "Change indentation of current buffer",
+            SetEncoding => "Change encoding to save current buffer",
+            GoToOffset => "Move cursor to byte offset in hex view",
+            FindFile => "Find file under current directory by fuzzy matching",
+            GoToLine => "Go to line number, relative line or percentage",
+            JumpBack => "Go back to position before jump",
+            JumpForward => "Go forward to position after jump back",
+            SetBookmark => "Set named bookmark at cursor",
+            GoToBookmark => "Go to named bookmark",
+            MatchingBracket => "Jump to matching bracket",
+        }
+    }
+
+    // Text deleted by these commands is pushed to kill ring
+    pub fn kills_text(self) -> bool {
+        use Command::*;
+        matches!(self, DeleteWord | DeleteToHead | DeleteToEnd)
+    }
+
+    // These commands are not allowed in read-only buffers
+    pub fn edits_text(self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            DeleteChar
+                | DeleteNextChar
+                | DeleteWord
+                | DeleteToHead
+                | DeleteToEnd
+                | Paste
+                | Yank
+                | YankPop
+                | Undo
+                | Redo
+                | Replace
+                | Indent
+                | Dedent
+                | ToggleComment
+                | DuplicateLine
+                | MoveLineUp
+                | MoveLineDown
+                | NewLine
+                | Shell
+                | FilterSelection
+                | SpellSuggest
+                | Complete
+                | FormatBuffer
+                | KeepOurs
+                | KeepTheirs
+                | KeepBoth
+        )
+    }
+
+    // These commands are run at extra cursors as well. Other commands remove extra cursors
+    pub fn runs_at_carets(self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            CursorUp
+                | CursorDown
+                | CursorRight
+                | CursorLeft
+                | HeadOfLine
+                | EndOfLine
+                | NextWord
+                | PreviousWord
+                | EndOfWord
+                | DeleteChar
+                | DeleteNextChar
+                | NewLine
+                | AddCursor
+                | Save
+                | Refresh
+        )
+    }
+
+    // Shift + keys bound to these commands extend selection
+    pub fn moves_cursor(self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            CursorUp
+                | CursorDown
+                | CursorRight
+                | CursorLeft
+                | HeadOfLine
+                | EndOfLine
+                | NextPage
+                | PreviousPage
+                | NextWord
+                | PreviousWord
+                | EndOfWord
+                | NextParagraph
+                | PreviousParagraph
+                | TopOfFile
+                | BottomOfFile
+        )
+    }
+}
+
+// Parse key notation such as "Ctrl-S", "Alt-<", "Shift-TAB" or "PAGEDOWN". Modifiers can also be
+// written in short form like "C-s" or "M-x". Letters with Ctrl or Alt are case insensitive.
+pub fn parse_key(notation: &str) -> Option<InputSeq> {
+    use KeySeq::*;
+
+    let mut seq = InputSeq::new(Unidentified);
+    let mut rest = notation;
+    loop {
+        let (name, key) = match rest.split_once('-') {
+            Some((name, key)) if !name.is_empty() => (name, key),
+            _ => break,
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "ctrl" | "c" => seq.ctrl = true,
+            "alt" | "meta" | "m" => seq.alt = true,
+            "shift" | "s" => seq.shift = true,
+            _ => break,
+        }
+        rest = key;
+    }
+
+    if rest == "[" && seq.ctrl {
+        // Ctrl-[ is sent as ESC
+        seq.ctrl = false;
+        seq.key = Key(0x1b);
+        return Some(seq);
+    }
+
+    let mut chars = rest.chars();
+    seq.key = match (chars.next(), chars.next()) {
+        (None, _) => return None,
+        (Some(c), None) if c.is_ascii() && (seq.ctrl || seq.alt) => {
+            Key(c.to_ascii_lowercase() as u8)
+        }
+        (Some(c), None) if c.is_ascii() => Key(c as u8),
+        (Some(c), None) => Utf8Key(c),
+        _ => match rest.replace(' ', "").to_ascii_uppercase().as_str() {
+            "UP" => UpKey,
+            "DOWN" => DownKey,
+            "LEFT" => LeftKey,
+            "RIGHT" => RightKey,
+            "PAGEUP" => PageUpKey,
+            "PAGEDOWN" => PageDownKey,
+            "HOME" => HomeKey,
+            "END" => EndKey,
+            "DELETE" | "DEL" => DeleteKey,
+            "BACKSPACE" => Key(0x7f),
+            "SPACE" => Key(b' '),
+            "ENTER" | "RETURN" => Key(b'\r'),
+            "ESC" | "ESCAPE" => Key(0x1b),
+            "TAB" => {
+                // Tab is sent as Ctrl-I
+                seq.ctrl = true;
+                Key(b'i')
+            }
+            _ => return None,
+        },
+    };
+    Some(seq)
+}
+
+// Notation of key shown in help. parse_key() can parse the result
+pub fn key_name(seq: &InputSeq) -> String {
+    use KeySeq::*;
+
+    let key = match &seq.key {
+        Key(b'i') if seq.ctrl && !seq.alt => {
+            let shift = if seq.shift { "Shift-" } else { "" };
+            return format!("{}TAB", shift);
+        }
+        Key(0x1b) if seq.alt => "ESC".to_string(),
+        Key(0x1b) => "Ctrl-[".to_string(), // ESC is the same byte as Ctrl-[
+        Key(0x7f) => "BACKSPACE".to_string(),
+        Key(b'\r') => "ENTER".to_string(),
+        Key(b' ') => "SPACE".to_string(),
+        Key(b) if seq.ctrl || seq.alt => (*b as char).to_ascii_uppercase().to_string(),
+        Key(b) => (*b as char).to_string(),
+        key => key.to_string(),
+    };
+
+    let mut name = String::new();
+    if seq.ctrl {
+        name.push_str("Ctrl-");
+    }
+    if seq.alt {
+        name.push_str("Alt-");
+    }
+    if seq.shift {
+        name.push_str("Shift-");
+    }
+    name.push_str(&key);
+    name
+}
+
+#[derive(Clone)]
+pub struct Keymap {
+    // Bindings are kept in order so that help can show keys in the order of definitions
+    bindings: Vec<(InputSeq, Command)>,
+    // Keys of commands provided by plugins. Keys bound to builtin commands take precedence
+    plugins: Vec<(InputSeq, String)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use Command::*;
+        const DEFAULT_BINDINGS: &[(&str, Command)] = &[
+            ("Ctrl-Q", Quit),
+            ("Ctrl-S", Save),
+            ("Ctrl-O", Open),
+            ("Ctrl-T", FindFile),
+            ("Ctrl-X", NextBuffer),
+            ("Alt-X", PreviousBuffer),
+            ("Alt-O", SelectBuffer),
+            ("Ctrl-P", CursorUp),
+            ("UP", CursorUp),
+            ("Ctrl-N", CursorDown),
+            ("DOWN", CursorDown),
+            ("Ctrl-F", CursorRight),
+            ("RIGHT", CursorRight),
+            ("Ctrl-B", CursorLeft),
+            ("LEFT", CursorLeft),
+            ("Ctrl-A", HeadOfLine),
+            ("Alt-LEFT", HeadOfLine),
+            ("HOME", HeadOfLine),
+            ("Ctrl-E", EndOfLine),
+            ("Alt-RIGHT", EndOfLine),
+            ("END", EndOfLine),
+            ("Alt-END", EndOfScreenLine),
+            ("Ctrl-]", NextPage),
+            ("PAGEDOWN", NextPage),
+            ("Ctrl-[", PreviousPage),
+            ("Alt-V", PreviousPage),
+            ("PAGEUP", PreviousPage),
+            ("Alt-F", NextWord),
+            ("Ctrl-RIGHT", NextWord),
+            ("Alt-B", PreviousWord),
+            ("Ctrl-LEFT", PreviousWord),
+            ("Alt-E", EndOfWord),
+            ("Alt-N", NextParagraph),
+            ("Ctrl-DOWN", NextParagraph),
+            ("Alt-P", PreviousParagraph),
+            ("Ctrl-UP", PreviousParagraph),
+            ("Alt-<", TopOfFile),
+            ("Alt->", BottomOfFile),
+            ("Alt-G", GoToLine),
+            ("Alt-,", JumpBack),
+            ("Alt-.", JumpForward),
+            ("Alt-M", SetBookmark),
+            ("Alt-J", GoToBookmark),
+            ("Alt-]", MatchingBracket),
+            ("Alt-Q", ToggleFold),
+            ("Alt-{", FoldAll),
+            ("Alt-}", UnfoldAll),
+            ("Ctrl-H", DeleteChar),
+            ("BACKSPACE", DeleteChar),
+            ("Ctrl-D", DeleteNextChar),
+            ("DELETE", DeleteNextChar),
+            ("Ctrl-W", DeleteWord),
+            ("Ctrl-J", DeleteToHead),
+            ("Ctrl-K", DeleteToEnd),
+            ("Ctrl-SPACE", ToggleMark),
+            ("Alt-SPACE", RectangleMark),
+            ("Ctrl-C", Copy),
+            ("Ctrl-V", Paste),
+            ("Ctrl-Y", Yank),
+            ("Alt-Y", YankPop),
+            ("Ctrl-Z", Undo),
+            ("Ctrl-R", Redo),
+            ("Alt-Z", RepeatEdit),
+            ("Ctrl-G", Search),
+            ("Alt-R", RegexSearch),
+            ("Alt-%", Replace),
+            ("TAB", Indent),
+            ("Shift-TAB", Dedent),
+            ("Alt-;", ToggleComment),
+            ("Alt-D", DuplicateLine),
+            ("Alt-UP", MoveLineUp),
+            ("Alt-DOWN", MoveLineDown),
+            ("Alt-A", AddCursor),
+            ("Ctrl-M", NewLine),
+            ("ENTER", NewLine),
+            ("Ctrl-L", Refresh),
+            ("Alt-S", Suspend),
+            ("Alt-!", Shell),
+            ("Alt-|", FilterSelection),
+            ("Alt-K", Compile),
+            ("Alt-L", NextError),
+            ("Alt-H", PreviousError),
+            ("Alt-I", Blame),
+            ("Alt-)", NextHunk),
+            ("Alt-(", PreviousHunk),
+            ("Alt-=", DiffPut),
+            ("Alt-W", SpellSuggest),
+            ("Alt-/", Complete),
+            ("Alt-'", LiteralInsert),
+            ("Alt-T", FormatBuffer),
+            ("Ctrl-?", Help),
+            ("Alt-C", Palette),
+        ];
+
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(key, cmd)| (parse_key(key).unwrap(), *cmd))
+            .collect();
+        Keymap {
+            bindings,
+            plugins: vec![],
+        }
+    }
+}
+
+impl Keymap {
+    pub fn lookup(&self, seq: &InputSeq) -> Option<Command> {
+        let find = |seq: &InputSeq| {
+            self.bindings
+                .iter()
+                .find(|(s, _)| s == seq)
+                .map(|(_, cmd)| *cmd)
+        };
+        find(seq).or_else(|| {
+            // Shift + key runs the same command as the key (e.g. Shift-UP selects text upward)
+            if seq.shift {
+                find(&InputSeq {
+                    shift: false,
+                    ..seq.clone()
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn keys(&self, cmd: Command) -> impl Iterator<Item = &InputSeq> {
+        self.bindings
+            .iter()
+            .filter(move |(_, c)| *c == cmd)
+            .map(|(s, _)| s)
+    }
+
+    // Replace keys bound to the command. The keys are unbound from other commands
+    pub fn bind(&mut self, cmd: Command, keys: Vec<InputSeq>) {
+        self.bindings
+            .retain(|(s, c)| *c != cmd && !keys.contains(s));
+        self.plugins.retain(|(s, _)| !keys.contains(s));
+        self.bindings.extend(keys.into_iter().map(|s| (s, cmd)));
+    }
+
+    // Replace keys bound to the command of plugin. The keys are unbound from other commands
+    pub fn bind_plugin(&mut self, name: &str, keys: Vec<InputSeq>) {
+        self.bindings.retain(|(s, _)| !keys.contains(s));
+        self.plugins.retain(|(s, n)| n != name && !keys.contains(s));
+        self.plugins
+            .extend(keys.into_iter().map(|s| (s, name.to_string())));
+    }
+
+    // Bind default keys of the command of plugin. Keys already bound to other commands are not
+    // bound, and nothing is bound when keys of the command were configured
+    pub fn bind_plugin_default(&mut self, name: &str, keys: Vec<InputSeq>) {
+        if self.plugins.iter().any(|(_, n)| n == name) {
+            return;
+        }
+        for key in keys {
+            if self.lookup(&key).is_none() && self.lookup_plugin(&key).is_none() {
+                self.plugins.push((key, name.to_string()));
+            }
+        }
+    }
+
+    pub fn lookup_plugin(&self, seq: &InputSeq) -> Option<&str> {
+        self.plugins
+            .iter()
+            .find(|(s, _)| s == seq)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn plugin_keys<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a InputSeq> {
+        self.plugins
+            .iter()
+            .filter(move |(_, n)| n == name)
+            .map(|(s, _)| s)
+    }
+
+    // Names of plugin commands which have keys, in the order of bindings
+    pub fn plugin_commands(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(|(_, name)| name.as_str())
+    }
+
+    // Help text of active key bindings. Each line is "{keys} : {description}". Commands without
+    // keys follow them with the key to run them by name
+    pub fn help(&self) -> String {
+        let mut lines = vec![];
+        let mut unbound = vec![];
+        for cmd in Command::ALL {
+            let keys: Vec<_> = self.keys(*cmd).map(key_name).collect();
+            if !keys.is_empty() {
+                lines.push(format!("{:<29} : {}", keys.join(" or "), cmd.description()));
+            } else {
+                unbound.push(*cmd);
+            }
+            if *cmd == Command::ToggleMark {
+                lines.push(format!("{:<29} : {}", "Shift + cursor keys", "Select text"));
+            }
+        }
+        let palette = self.keys(Command::Palette).next().map(key_name);
+        for cmd in unbound {
+            let keys = match &palette {
+                Some(key) => format!("{} {}", key, cmd.name()),
+                None => cmd.name().to_string(),
+            };
+            lines.push(format!("{:<29} : {}", keys, cmd.description()));
+        }
+        lines.join("\n")
+    }
+}
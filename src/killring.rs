@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+
+// Max number of killed texts kept in the ring. Older texts are forgotten
+const MAX_ENTRIES: usize = 60;
+
+// Emacs-style kill ring. Texts deleted by kill commands are pushed to the ring and can be pasted
+// ('yanked') later. Yank-pop rotates the ring to paste earlier kills. This is shared by all text
+// buffers so that text killed in one buffer can be yanked in another.
+#[derive(Default)]
+pub struct KillRing {
+    // The latest kill is at front
+    entries: VecDeque<String>,
+    // Index of the entry yanked last time
+    yank_idx: usize,
+}
+
+impl KillRing {
+    pub fn push(&mut self, text: String) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(text);
+        self.yank_idx = 0;
+    }
+
+    // Successive kills are concatenated into one entry. Text killed backward (e.g. Ctrl-J) is
+    // prepended to the latest entry
+    pub fn append(&mut self, text: &str, backward: bool) {
+        match self.entries.front_mut() {
+            Some(latest) if backward => latest.insert_str(0, text),
+            Some(latest) => latest.push_str(text),
+            None => self.entries.push_front(text.to_string()),
+        }
+        self.yank_idx = 0;
+    }
+
+    pub fn latest(&self) -> Option<&str> {
+        self.entries.front().map(String::as_str)
+    }
+
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_idx = 0;
+        self.latest()
+    }
+
+    // Returns the entry before the one yanked last time. It wraps around at the oldest entry
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yank_idx = (self.yank_idx + 1) % self.entries.len();
+        self.entries.get(self.yank_idx).map(String::as_str)
+    }
+}
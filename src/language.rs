@@ -1,26 +1,43 @@
+use crate::highlight;
 use std::ffi::OsStr;
 use std::path::Path;
 
+#[derive(Clone, Copy)]
 pub enum Indent {
     AsIs,
     Fixed(&'static str),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Language {
+    #[default]
     Plain,
     C,
     Rust,
     JavaScript,
     Go,
     Cpp,
+    Python,
+    Markdown,
+    Toml,
+    Yaml,
+    Json,
+    Custom(&'static str), // Defined by user's syntax file
 }
 
-impl Default for Language {
-    fn default() -> Language {
-        Language::Plain
-    }
-}
+// Languages which can be detected from file name or file content
+const DETECTABLE: &[Language] = &[
+    Language::C,
+    Language::Rust,
+    Language::JavaScript,
+    Language::Go,
+    Language::Cpp,
+    Language::Python,
+    Language::Markdown,
+    Language::Toml,
+    Language::Yaml,
+    Language::Json,
+];
 
 impl Language {
     pub fn name(self) -> &'static str {
@@ -32,6 +49,12 @@ impl Language {
             JavaScript => "javascript",
             Go => "go",
             Cpp => "c++",
+            Python => "python",
+            Markdown => "markdown",
+            Toml => "toml",
+            Yaml => "yaml",
+            Json => "json",
+            Custom(name) => name,
         }
     }
 
@@ -44,27 +67,82 @@ impl Language {
             JavaScript => &["js"],
             Go => &["go"],
             Cpp => &["cpp", "hpp", "cxx", "hxx", "cc", "hh"],
+            Python => &["py"],
+            Markdown => &["md", "markdown"],
+            Toml => &["toml"],
+            Yaml => &["yaml", "yml"],
+            Json => &["json"],
+            Custom(_) => &[], // Extensions of syntax file are checked while detection
+        }
+    }
+
+    // Interpreter names in shebang line such as '#!/usr/bin/env python'
+    fn interpreters(self) -> &'static [&'static str] {
+        use Language::*;
+        match self {
+            Python => &["python"],
+            _ => &[],
         }
     }
 
     pub fn indent(self) -> Indent {
         use Language::*;
+        if let Some(indent) = highlight::user_indent(self) {
+            return indent;
+        }
         match self {
-            Plain | Go => Indent::AsIs,
-            C | Rust | Cpp => Indent::Fixed("    "),
-            JavaScript => Indent::Fixed("  "),
+            Plain | Go | Markdown => Indent::AsIs,
+            C | Rust | Cpp | Python | Toml => Indent::Fixed("    "),
+            JavaScript | Yaml | Json => Indent::Fixed("  "),
+            Custom(_) => Indent::AsIs,
         }
     }
 
     pub fn detect<P: AsRef<Path>>(path: P) -> Language {
-        use Language::*;
         if let Some(ext) = path.as_ref().extension().and_then(OsStr::to_str) {
-            for lang in &[C, Rust, JavaScript, Go, Cpp] {
+            // Syntax files take priority over builtin languages
+            if let Some(lang) = highlight::detect_user_syntax(ext) {
+                return lang;
+            }
+            for lang in DETECTABLE {
                 if lang.file_exts().contains(&ext) {
                     return *lang;
                 }
             }
         }
-        Plain
+        Language::Plain
+    }
+
+    // Find language by its name or file extension. e.g. Language tag of fenced code block in Markdown
+    pub fn from_name(name: &str) -> Option<Language> {
+        let name = name.to_ascii_lowercase();
+        if let Some(lang) = highlight::detect_user_syntax(&name) {
+            return Some(lang);
+        }
+        DETECTABLE
+            .iter()
+            .find(|lang| lang.name() == name || lang.file_exts().contains(&name.as_str()))
+            .copied()
+    }
+
+    // Builtin language which has exactly the name
+    pub fn builtin(name: &str) -> Option<Language> {
+        DETECTABLE.iter().find(|lang| lang.name() == name).copied()
+    }
+
+    // Detect language from shebang at the first line of file. Both '#!/usr/bin/python3' and
+    // '#!/usr/bin/env python3' are accepted.
+    pub fn detect_shebang(line: &str) -> Option<Language> {
+        let mut args = line.strip_prefix("#!")?.split_whitespace();
+        let mut prog = args.next()?.rsplit('/').next()?;
+        if prog == "env" {
+            prog = args.find(|a| !a.starts_with('-'))?;
+        }
+        // Strip version such as 'python3.8'
+        let prog = prog.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        DETECTABLE
+            .iter()
+            .find(|lang| lang.interpreters().contains(&prog))
+            .copied()
     }
 }
@@ -9,21 +9,102 @@
 #![allow(clippy::cognitive_complexity)]
 
 mod ansi_color;
+mod carets;
+mod cli;
+mod clipboard;
+mod clock;
+mod compile;
+mod completion;
+mod config;
+mod conflict;
+mod diff;
+mod dired;
 mod editor;
+mod encoding;
+mod error;
+mod event;
+mod finder;
+mod fold;
+mod format;
+mod fuzzy;
+mod gap_buffer;
+mod grapheme;
+mod grid;
+mod headless;
+mod hexview;
 mod highlight;
+mod history;
 mod input;
+mod json;
+mod jump;
+mod jumplist;
+mod keymap;
+mod killring;
 mod language;
+mod lua;
+mod marks;
+mod message_log;
+mod mmap;
+mod pager;
+mod picker;
+mod plugin;
+mod prompt;
+mod regex;
+mod remote;
 mod row;
 mod screen;
+mod script;
+mod search;
+mod session;
+mod shell;
+#[cfg(all(feature = "terminal", unix))]
 mod signal;
+mod sjis;
+mod spell;
 mod status_bar;
+mod stream;
+#[cfg(all(feature = "terminal", unix))]
+mod term_unix;
+#[cfg(all(feature = "terminal", windows))]
+mod term_windows;
+#[cfg(feature = "terminal")]
+mod terminal;
 mod text_buffer;
+mod theme;
+mod token;
+mod toml;
+mod undo;
+mod vcs;
+mod vi;
+mod watcher;
 
 #[cfg(test)]
 mod ui_test;
 
+pub use cli::{parse_args, Args, Cli, Jump};
+pub use clipboard::{Clipboard, ClipboardBackend, NoClipboard};
+pub use config::{config_dir, load_user_config, state_dir};
 pub use editor::Editor;
-pub use input::StdinRawMode;
+pub use error::{Error, Result};
+pub use event::{Event, TaskResult};
+#[cfg(feature = "bench")]
+pub use gap_buffer::GapBuffer;
+pub use headless::{ReaderInputs, Script, ScriptInputs};
+#[cfg(feature = "bench")]
+pub use highlight::Highlighting;
+pub use highlight::{load_user_syntaxes, Highlight};
+pub use input::{InputSeq, InputSequences, KeySeq, ScrollDir};
+#[cfg(feature = "fuzz")]
+pub use keymap::Command;
+pub use keymap::Keymap;
 pub use language::Language;
-pub use screen::{Screen, HELP, VERSION};
+pub use plugin::{Action, Plugin, PluginCommand, Request, Span};
+pub use remote::{default_socket, open_files, split_location};
+#[cfg(any(feature = "bench", feature = "fuzz"))]
+pub use row::Row;
+pub use screen::{Screen, VERSION};
+#[cfg(feature = "bench")]
+pub use search::Matcher;
+#[cfg(feature = "terminal")]
+pub use terminal::{install_panic_hook, window_size, StdinRawMode, Term, TerminalInput};
 pub use text_buffer::Lines;
@@ -0,0 +1,2033 @@
+// Small interpreter for subset of Lua used by init scripts.
+//
+// Supported syntax:
+//   nil, booleans, numbers, strings ("...", '...', [[...]]), tables, functions and closures,
+//   local/global variables, multiple assignment and return values, if/elseif/else, while,
+//   repeat/until, numeric and generic for, break, method calls 'obj:f()' and comments
+//
+// Metatables, coroutines, varargs '...', goto and integer division are not supported. Standard
+// library is limited to print, type, tostring, tonumber, pairs, ipairs, next, error, pcall, and a
+// part of string and table libraries. Indices of strings are characters instead of bytes so that
+// they never point the middle of UTF-8 sequences.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::rc::Rc;
+
+// Statements run by one call of `exec` or `call` at most. Scripts never block the editor forever
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub line: usize, // 1-based. 0 when unknown
+    pub message: String,
+}
+
+impl Error {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Error {
+            line: 0,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} at line {}", self.message, self.line)
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub type Builtin = dyn Fn(&mut Interp, Vec<Value>) -> Result<Vec<Value>>;
+
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Table(Rc<RefCell<Table>>),
+    Function(Rc<Closure>),
+    Builtin(Rc<Builtin>),
+}
+
+impl Value {
+    pub fn string<S: AsRef<str>>(s: S) -> Value {
+        Value::Str(s.as_ref().into())
+    }
+
+    pub fn builtin<F>(f: F) -> Value
+    where
+        F: Fn(&mut Interp, Vec<Value>) -> Result<Vec<Value>> + 'static,
+    {
+        Value::Builtin(Rc::new(f))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) | Value::Builtin(_) => "function",
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // Numbers and strings which look like numbers are converted as Lua does for arithmetic
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => parse_number(s.trim()),
+            _ => None,
+        }
+    }
+
+    fn ptr(&self) -> Option<usize> {
+        match self {
+            Value::Table(t) => Some(Rc::as_ptr(t) as *const u8 as usize),
+            Value::Function(f) => Some(Rc::as_ptr(f) as *const u8 as usize),
+            Value::Builtin(f) => Some(Rc::as_ptr(f) as *const u8 as usize),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(l), Value::Bool(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::Str(l), Value::Str(r)) => l == r,
+            (l, r) => l.ptr().is_some() && l.ptr() == r.ptr(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Table(_) => write!(f, "table: {:#x}", self.ptr().unwrap()),
+            Value::Function(_) | Value::Builtin(_) => {
+                write!(f, "function: {:#x}", self.ptr().unwrap())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", s),
+            v => write!(f, "{}", v),
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return i64::from_str_radix(hex, 16).ok().map(|i| i as f64);
+    }
+    // Rust accepts "inf" and "nan" which are not numbers in Lua
+    if s.is_empty()
+        || s.chars()
+            .any(|c| c.is_ascii_alphabetic() && c != 'e' && c != 'E')
+    {
+        return None;
+    }
+    s.parse().ok()
+}
+
+// Keys of hash part of tables. Numbers with integer values are normalized so that 1 and 1.0 are the
+// same key
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(Rc<str>),
+    Ptr(usize),
+}
+
+impl Key {
+    fn new(v: &Value) -> Result<Key> {
+        Ok(match v {
+            Value::Nil => return Err(Error::new("Table index is nil")),
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Number(n) if n.is_nan() => return Err(Error::new("Table index is NaN")),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 9e15 => Key::Int(*n as i64),
+            Value::Number(n) => Key::Float(n.to_bits()),
+            Value::Str(s) => Key::Str(s.clone()),
+            v => Key::Ptr(v.ptr().unwrap()),
+        })
+    }
+}
+
+// Table with array part for keys 1..n and hash part for other keys. Keys are iterated in the order
+// of the array part and then in the order of keys
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    hash: BTreeMap<Key, (Value, Value)>,
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        if let Value::Number(n) = key {
+            let i = *n as usize;
+            if i as f64 == *n && i >= 1 && i <= self.array.len() {
+                return self.array[i - 1].clone();
+            }
+        }
+        match Key::new(key) {
+            Ok(k) => self
+                .hash
+                .get(&k)
+                .map(|(_, v)| v.clone())
+                .unwrap_or(Value::Nil),
+            Err(_) => Value::Nil,
+        }
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<()> {
+        if let Value::Number(n) = key {
+            let i = n as usize;
+            if i as f64 == n && i >= 1 && i <= self.array.len() + 1 {
+                if i <= self.array.len() {
+                    self.array[i - 1] = value;
+                } else if !matches!(value, Value::Nil) {
+                    self.array.push(value);
+                    self.hash.remove(&Key::Int(i as i64));
+                    // Following keys in the hash part are moved to the array part
+                    while let Some((_, v)) =
+                        self.hash.remove(&Key::Int(self.array.len() as i64 + 1))
+                    {
+                        self.array.push(v);
+                    }
+                }
+                while matches!(self.array.last(), Some(Value::Nil)) {
+                    self.array.pop();
+                }
+                return Ok(());
+            }
+        }
+        let k = Key::new(&key)?;
+        if matches!(value, Value::Nil) {
+            self.hash.remove(&k);
+        } else {
+            self.hash.insert(k, (key, value));
+        }
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::string(key), value).unwrap();
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.array.push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    // Key and value following the key. Iteration starts with nil key
+    fn next(&self, key: &Value) -> Result<Option<(Value, Value)>> {
+        let start = match key {
+            Value::Nil => 0,
+            Value::Number(n)
+                if n.fract() == 0.0 && *n >= 1.0 && *n as usize <= self.array.len() =>
+            {
+                *n as usize
+            }
+            _ => {
+                // The key may have been removed while iterating
+                let k = Key::new(key)?;
+                let mut rest = self.hash.range((Bound::Excluded(k), Bound::Unbounded));
+                return Ok(rest.next().map(|(_, (k, v))| (k.clone(), v.clone())));
+            }
+        };
+        for i in start..self.array.len() {
+            if !matches!(self.array[i], Value::Nil) {
+                return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+            }
+        }
+        Ok(self
+            .hash
+            .values()
+            .next()
+            .map(|(k, v)| (k.clone(), v.clone())))
+    }
+}
+
+pub fn table(t: Table) -> Value {
+    Value::Table(Rc::new(RefCell::new(t)))
+}
+
+// Lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(Rc<str>),
+    Number(f64),
+    Str(Rc<str>),
+    Keyword(&'static str),
+    Symbol(&'static str),
+    Eof,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// Longer symbols must come first
+const SYMBOLS: &[&str] = &[
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let mut line = 1;
+    let err = |line, msg: &str| Error {
+        line,
+        message: msg.to_string(),
+    };
+
+    // Long bracket such as [[...]] or [==[...]==]. Returns the content and the index after it
+    let long_bracket = |i: usize, line: &mut usize| -> Option<Result<(String, usize)>> {
+        let mut j = i + 1;
+        while chars.get(j) == Some(&'=') {
+            j += 1;
+        }
+        if chars.get(j) != Some(&'[') {
+            return None;
+        }
+        let level = j - i - 1;
+        let mut close: Vec<char> = vec![']'];
+        close.extend(std::iter::repeat_n('=', level));
+        close.push(']');
+        let mut k = j + 1;
+        // A newline just after the opening bracket is skipped
+        if chars.get(k) == Some(&'\n') {
+            *line += 1;
+            k += 1;
+        }
+        let start = k;
+        while k < chars.len() {
+            if chars[k..].starts_with(&close) {
+                return Some(Ok((chars[start..k].iter().collect(), k + close.len())));
+            }
+            if chars[k] == '\n' {
+                *line += 1;
+            }
+            k += 1;
+        }
+        Some(Err(err(*line, "Unfinished long string")))
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '-' && chars.get(i + 1) == Some(&'-') {
+            if chars.get(i + 2) == Some(&'[') {
+                if let Some(comment) = long_bracket(i + 2, &mut line) {
+                    i = comment?.1;
+                    continue;
+                }
+            }
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '[' {
+            if let Some(s) = long_bracket(i, &mut line) {
+                let (s, end) = s?;
+                tokens.push((Token::Str(s.into()), line));
+                i = end;
+                continue;
+            }
+        }
+        if c.is_ascii_digit() || c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+            let start = i;
+            while i < chars.len() {
+                let c = chars[i];
+                let exp = (c == '+' || c == '-') && matches!(chars[i - 1], 'e' | 'E');
+                if c.is_ascii_alphanumeric() || c == '.' || exp {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            match parse_number(&text) {
+                Some(n) => tokens.push((Token::Number(n), line)),
+                None => return Err(err(line, &format!("Malformed number '{}'", text))),
+            }
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let token = match KEYWORDS.iter().find(|k| **k == word) {
+                Some(k) => Token::Keyword(k),
+                None => Token::Name(word.into()),
+            };
+            tokens.push((token, line));
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            let quote = c;
+            let mut s = String::new();
+            i += 1;
+            loop {
+                let c = match chars.get(i) {
+                    Some('\n') | None => return Err(err(line, "Unfinished string")),
+                    Some(c) => *c,
+                };
+                i += 1;
+                if c == quote {
+                    break;
+                }
+                if c == '\\' {
+                    let e = chars.get(i).copied();
+                    i += 1;
+                    match e {
+                        Some('n') => s.push('\n'),
+                        Some('t') => s.push('\t'),
+                        Some('r') => s.push('\r'),
+                        Some('0') => s.push('\0'),
+                        Some(c @ ('\\' | '"' | '\'')) => s.push(c),
+                        Some('\n') => {
+                            line += 1;
+                            s.push('\n');
+                        }
+                        _ => return Err(err(line, "Invalid escape sequence")),
+                    }
+                } else {
+                    s.push(c);
+                }
+            }
+            tokens.push((Token::Str(s.into()), line));
+            continue;
+        }
+        let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+        match SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            Some(s) => {
+                tokens.push((Token::Symbol(s), line));
+                i += s.len();
+            }
+            None => return Err(err(line, &format!("Unexpected character '{}'", c))),
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(n) => write!(f, "'{}'", n),
+            Token::Number(n) => write!(f, "'{}'", Value::Number(*n)),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Keyword(k) | Token::Symbol(k) => write!(f, "'{}'", k),
+            Token::Eof => write!(f, "end of script"),
+        }
+    }
+}
+
+// Syntax tree
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn from_token(t: &Token) -> Option<BinOp> {
+        use BinOp::*;
+        Some(match t {
+            Token::Symbol("+") => Add,
+            Token::Symbol("-") => Sub,
+            Token::Symbol("*") => Mul,
+            Token::Symbol("/") => Div,
+            Token::Symbol("%") => Mod,
+            Token::Symbol("^") => Pow,
+            Token::Symbol("..") => Concat,
+            Token::Symbol("==") => Eq,
+            Token::Symbol("~=") => Ne,
+            Token::Symbol("<") => Lt,
+            Token::Symbol("<=") => Le,
+            Token::Symbol(">") => Gt,
+            Token::Symbol(">=") => Ge,
+            Token::Keyword("and") => And,
+            Token::Keyword("or") => Or,
+            _ => return None,
+        })
+    }
+
+    // Left and right priorities. The same as the reference implementation
+    fn priority(self) -> (u8, u8) {
+        use BinOp::*;
+        match self {
+            Or => (1, 1),
+            And => (2, 2),
+            Eq | Ne | Lt | Le | Gt | Ge => (3, 3),
+            Concat => (9, 8),
+            Add | Sub => (10, 10),
+            Mul | Div | Mod => (11, 11),
+            Pow => (14, 13),
+        }
+    }
+}
+
+const UNARY_PRIORITY: u8 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<str>),
+    Name(Rc<str>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, Rc<str>, Vec<Expr>),
+    Function(Rc<FuncDef>),
+    Table(Vec<(Option<Expr>, Expr)>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..))
+    }
+}
+
+#[derive(Debug)]
+enum StmtKind {
+    Local(Vec<Rc<str>>, Vec<Expr>),
+    LocalFunction(Rc<str>, Rc<FuncDef>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    NumFor(Rc<str>, Expr, Expr, Option<Expr>, Block),
+    GenFor(Vec<Rc<str>>, Vec<Expr>, Block),
+    Do(Block),
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+struct Stmt {
+    kind: StmtKind,
+    line: usize,
+}
+
+type Block = Vec<Stmt>;
+
+#[derive(Debug)]
+pub struct FuncDef {
+    params: Vec<Rc<str>>,
+    body: Block,
+}
+
+// Parser
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let t = self.tokens[self.pos].0.clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        t
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T> {
+        Err(Error {
+            line: self.line(),
+            message: format!("Expected {} but got {}", expected, self.peek()),
+        })
+    }
+
+    fn accept(&mut self, t: Token) -> bool {
+        if *self.peek() == t {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_symbol(&mut self, s: &'static str) -> bool {
+        self.accept(Token::Symbol(s))
+    }
+
+    fn accept_keyword(&mut self, k: &'static str) -> bool {
+        self.accept(Token::Keyword(k))
+    }
+
+    fn expect_symbol(&mut self, s: &'static str) -> Result<()> {
+        if self.accept_symbol(s) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", s))
+        }
+    }
+
+    fn expect_keyword(&mut self, k: &'static str) -> Result<()> {
+        if self.accept_keyword(k) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", k))
+        }
+    }
+
+    fn name(&mut self) -> Result<Rc<str>> {
+        match self.peek().clone() {
+            Token::Name(n) => {
+                self.next();
+                Ok(n)
+            }
+            _ => self.error("name"),
+        }
+    }
+
+    fn chunk(&mut self) -> Result<Block> {
+        let block = self.block()?;
+        if *self.peek() != Token::Eof {
+            return self.error("end of script");
+        }
+        Ok(block)
+    }
+
+    fn block(&mut self) -> Result<Block> {
+        let mut block = vec![];
+        loop {
+            match self.peek() {
+                Token::Eof | Token::Keyword("end" | "else" | "elseif" | "until") => {
+                    return Ok(block)
+                }
+                Token::Keyword("return") => {
+                    let line = self.line();
+                    self.next();
+                    let exprs = match self.peek() {
+                        Token::Eof
+                        | Token::Keyword("end" | "else" | "elseif" | "until")
+                        | Token::Symbol(";") => vec![],
+                        _ => self.exprlist()?,
+                    };
+                    self.accept_symbol(";");
+                    block.push(Stmt {
+                        kind: StmtKind::Return(exprs),
+                        line,
+                    });
+                    return match self.peek() {
+                        Token::Eof | Token::Keyword("end" | "else" | "elseif" | "until") => {
+                            Ok(block)
+                        }
+                        _ => self.error("end of block after 'return'"),
+                    };
+                }
+                Token::Symbol(";") => {
+                    self.next();
+                }
+                _ => block.push(self.statement()?),
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        let line = self.line();
+        let kind = match self.next() {
+            Token::Keyword("if") => {
+                let mut arms = vec![];
+                let mut otherwise = None;
+                loop {
+                    let cond = self.expr()?;
+                    self.expect_keyword("then")?;
+                    arms.push((cond, self.block()?));
+                    if self.accept_keyword("elseif") {
+                        continue;
+                    }
+                    if self.accept_keyword("else") {
+                        otherwise = Some(self.block()?);
+                    }
+                    self.expect_keyword("end")?;
+                    break;
+                }
+                StmtKind::If(arms, otherwise)
+            }
+            Token::Keyword("while") => {
+                let cond = self.expr()?;
+                self.expect_keyword("do")?;
+                let body = self.block()?;
+                self.expect_keyword("end")?;
+                StmtKind::While(cond, body)
+            }
+            Token::Keyword("repeat") => {
+                let body = self.block()?;
+                self.expect_keyword("until")?;
+                StmtKind::Repeat(body, self.expr()?)
+            }
+            Token::Keyword("do") => {
+                let body = self.block()?;
+                self.expect_keyword("end")?;
+                StmtKind::Do(body)
+            }
+            Token::Keyword("for") => {
+                let first = self.name()?;
+                if self.accept_symbol("=") {
+                    let start = self.expr()?;
+                    self.expect_symbol(",")?;
+                    let stop = self.expr()?;
+                    let step = if self.accept_symbol(",") {
+                        Some(self.expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect_keyword("do")?;
+                    let body = self.block()?;
+                    self.expect_keyword("end")?;
+                    StmtKind::NumFor(first, start, stop, step, body)
+                } else {
+                    let mut names = vec![first];
+                    while self.accept_symbol(",") {
+                        names.push(self.name()?);
+                    }
+                    self.expect_keyword("in")?;
+                    let exprs = self.exprlist()?;
+                    self.expect_keyword("do")?;
+                    let body = self.block()?;
+                    self.expect_keyword("end")?;
+                    StmtKind::GenFor(names, exprs, body)
+                }
+            }
+            Token::Keyword("function") => {
+                // function a.b.c:m() ... end is the same as a.b.c.m = function(self) ... end
+                let mut target = Expr::Name(self.name()?);
+                let mut method = false;
+                loop {
+                    let is_method = self.accept_symbol(":");
+                    if !is_method && !self.accept_symbol(".") {
+                        break;
+                    }
+                    let key = self.name()?;
+                    target = Expr::Index(Box::new(target), Box::new(Expr::Str(key)));
+                    if is_method {
+                        method = true;
+                        break;
+                    }
+                }
+                let def = self.funcbody(method)?;
+                StmtKind::Assign(vec![target], vec![Expr::Function(def)])
+            }
+            Token::Keyword("local") => {
+                if self.accept_keyword("function") {
+                    let name = self.name()?;
+                    let def = self.funcbody(false)?;
+                    StmtKind::LocalFunction(name, def)
+                } else {
+                    let mut names = vec![self.name()?];
+                    while self.accept_symbol(",") {
+                        names.push(self.name()?);
+                    }
+                    let exprs = if self.accept_symbol("=") {
+                        self.exprlist()?
+                    } else {
+                        vec![]
+                    };
+                    StmtKind::Local(names, exprs)
+                }
+            }
+            Token::Keyword("break") => StmtKind::Break,
+            _ => {
+                self.pos -= 1;
+                let expr = self.suffixed()?;
+                if matches!(self.peek(), Token::Symbol("=" | ",")) {
+                    let mut targets = vec![expr];
+                    while self.accept_symbol(",") {
+                        targets.push(self.suffixed()?);
+                    }
+                    self.expect_symbol("=")?;
+                    for t in &targets {
+                        if !matches!(t, Expr::Name(_) | Expr::Index(..)) {
+                            return Err(Error {
+                                line,
+                                message: "Cannot assign to this expression".to_string(),
+                            });
+                        }
+                    }
+                    StmtKind::Assign(targets, self.exprlist()?)
+                } else if expr.is_multi() {
+                    StmtKind::Call(expr)
+                } else {
+                    return Err(Error {
+                        line,
+                        message: "Syntax error: expression is not a statement".to_string(),
+                    });
+                }
+            }
+        };
+        Ok(Stmt { kind, line })
+    }
+
+    fn funcbody(&mut self, method: bool) -> Result<Rc<FuncDef>> {
+        let mut params = vec![];
+        if method {
+            params.push("self".into());
+        }
+        self.expect_symbol("(")?;
+        if !self.accept_symbol(")") {
+            loop {
+                params.push(self.name()?);
+                if self.accept_symbol(")") {
+                    break;
+                }
+                self.expect_symbol(",")?;
+            }
+        }
+        let body = self.block()?;
+        self.expect_keyword("end")?;
+        Ok(Rc::new(FuncDef { params, body }))
+    }
+
+    fn exprlist(&mut self) -> Result<Vec<Expr>> {
+        let mut exprs = vec![self.expr()?];
+        while self.accept_symbol(",") {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.subexpr(0)
+    }
+
+    fn subexpr(&mut self, limit: u8) -> Result<Expr> {
+        let unary = match self.peek() {
+            Token::Symbol("-") => Some(UnOp::Neg),
+            Token::Keyword("not") => Some(UnOp::Not),
+            Token::Symbol("#") => Some(UnOp::Len),
+            _ => None,
+        };
+        let mut lhs = if let Some(op) = unary {
+            self.next();
+            let e = self.subexpr(UNARY_PRIORITY)?;
+            match (op, e) {
+                (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                (op, e) => Expr::Unary(op, Box::new(e)),
+            }
+        } else {
+            self.simple()?
+        };
+        while let Some(op) = BinOp::from_token(self.peek()) {
+            let (left, right) = op.priority();
+            if left <= limit {
+                break;
+            }
+            self.next();
+            let rhs = self.subexpr(right)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn simple(&mut self) -> Result<Expr> {
+        let e = match self.peek().clone() {
+            Token::Number(n) => Expr::Number(n),
+            Token::Str(s) => Expr::Str(s),
+            Token::Keyword("nil") => Expr::Nil,
+            Token::Keyword("true") => Expr::Bool(true),
+            Token::Keyword("false") => Expr::Bool(false),
+            Token::Keyword("function") => {
+                self.next();
+                return Ok(Expr::Function(self.funcbody(false)?));
+            }
+            Token::Symbol("{") => return self.table(),
+            _ => return self.suffixed(),
+        };
+        self.next();
+        Ok(e)
+    }
+
+    fn table(&mut self) -> Result<Expr> {
+        self.expect_symbol("{")?;
+        let mut fields = vec![];
+        while !self.accept_symbol("}") {
+            if self.accept_symbol("[") {
+                let key = self.expr()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol("=")?;
+                fields.push((Some(key), self.expr()?));
+            } else if matches!(self.peek(), Token::Name(_))
+                && self.tokens[self.pos + 1].0 == Token::Symbol("=")
+            {
+                let key = self.name()?;
+                self.next();
+                fields.push((Some(Expr::Str(key)), self.expr()?));
+            } else {
+                fields.push((None, self.expr()?));
+            }
+            if !self.accept_symbol(",") && !self.accept_symbol(";") {
+                self.expect_symbol("}")?;
+                break;
+            }
+        }
+        Ok(Expr::Table(fields))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.peek().clone() {
+            Token::Name(n) => {
+                self.next();
+                Ok(Expr::Name(n))
+            }
+            Token::Symbol("(") => {
+                self.next();
+                let e = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Paren(Box::new(e)))
+            }
+            _ => self.error("expression"),
+        }
+    }
+
+    fn suffixed(&mut self) -> Result<Expr> {
+        let mut e = self.primary()?;
+        loop {
+            e = match self.peek() {
+                Token::Symbol(".") => {
+                    self.next();
+                    let key = self.name()?;
+                    Expr::Index(Box::new(e), Box::new(Expr::Str(key)))
+                }
+                Token::Symbol("[") => {
+                    self.next();
+                    let key = self.expr()?;
+                    self.expect_symbol("]")?;
+                    Expr::Index(Box::new(e), Box::new(key))
+                }
+                Token::Symbol(":") => {
+                    self.next();
+                    let name = self.name()?;
+                    let args = self.args()?;
+                    Expr::Method(Box::new(e), name, args)
+                }
+                Token::Symbol("(" | "{") | Token::Str(_) => {
+                    let args = self.args()?;
+                    Expr::Call(Box::new(e), args)
+                }
+                _ => return Ok(e),
+            };
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        match self.peek().clone() {
+            Token::Str(s) => {
+                self.next();
+                Ok(vec![Expr::Str(s)])
+            }
+            Token::Symbol("{") => Ok(vec![self.table()?]),
+            Token::Symbol("(") => {
+                self.next();
+                if self.accept_symbol(")") {
+                    return Ok(vec![]);
+                }
+                let args = self.exprlist()?;
+                self.expect_symbol(")")?;
+                Ok(args)
+            }
+            _ => self.error("function arguments"),
+        }
+    }
+}
+
+fn parse(src: &str) -> Result<Block> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+    parser.chunk()
+}
+
+// Evaluator
+
+struct Scope {
+    vars: Vec<(Rc<str>, Value)>,
+    parent: Option<Env>,
+}
+
+type Env = Rc<RefCell<Scope>>;
+
+fn new_scope(parent: Option<Env>) -> Env {
+    Rc::new(RefCell::new(Scope {
+        vars: vec![],
+        parent,
+    }))
+}
+
+fn declare(env: &Env, name: Rc<str>, value: Value) {
+    env.borrow_mut().vars.push((name, value));
+}
+
+pub struct Closure {
+    def: Rc<FuncDef>,
+    env: Env,
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+// Nested function calls. Deeper recursion would overflow the native stack
+const MAX_DEPTH: usize = 100;
+
+pub struct Interp {
+    globals: HashMap<Rc<str>, Value>,
+    steps: usize,
+    depth: usize,
+    pub output: Vec<String>, // Lines printed by print()
+}
+
+impl Default for Interp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or(Value::Nil)
+}
+
+fn bad_arg<T>(name: &str, i: usize, expected: &str, got: &Value) -> Result<T> {
+    Err(Error::new(format!(
+        "Bad argument #{} to '{}' ({} expected, got {})",
+        i + 1,
+        name,
+        expected,
+        got.type_name()
+    )))
+}
+
+pub fn check_str(name: &str, args: &[Value], i: usize) -> Result<Rc<str>> {
+    match arg(args, i) {
+        Value::Str(s) => Ok(s),
+        Value::Number(n) => Ok(Value::Number(n).to_string().into()),
+        v => bad_arg(name, i, "string", &v),
+    }
+}
+
+pub fn check_num(name: &str, args: &[Value], i: usize) -> Result<f64> {
+    let v = arg(args, i);
+    match v.as_number() {
+        Some(n) => Ok(n),
+        None => bad_arg(name, i, "number", &v),
+    }
+}
+
+fn opt_num(name: &str, args: &[Value], i: usize, default: f64) -> Result<f64> {
+    match arg(args, i) {
+        Value::Nil => Ok(default),
+        _ => check_num(name, args, i),
+    }
+}
+
+fn check_table(name: &str, args: &[Value], i: usize) -> Result<Rc<RefCell<Table>>> {
+    match arg(args, i) {
+        Value::Table(t) => Ok(t),
+        v => bad_arg(name, i, "table", &v),
+    }
+}
+
+fn arith_error(l: &Value, r: &Value) -> Error {
+    let v = if l.as_number().is_none() { l } else { r };
+    Error::new(format!(
+        "Attempt to perform arithmetic on a {} value",
+        v.type_name()
+    ))
+}
+
+impl Interp {
+    pub fn new() -> Self {
+        let mut interp = Interp {
+            globals: HashMap::new(),
+            steps: 0,
+            depth: 0,
+            output: vec![],
+        };
+        stdlib(&mut interp);
+        interp
+    }
+
+    pub fn global(&self, name: &str) -> Value {
+        self.globals.get(name).cloned().unwrap_or(Value::Nil)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.into(), value);
+    }
+
+    // Run a chunk of script and return values returned from it
+    pub fn exec(&mut self, src: &str) -> Result<Vec<Value>> {
+        let body = parse(src)?;
+        let def = Rc::new(FuncDef {
+            params: vec![],
+            body,
+        });
+        let f = Value::Function(Rc::new(Closure {
+            def,
+            env: new_scope(None),
+        }));
+        self.call(&f, vec![])
+    }
+
+    pub fn call(&mut self, f: &Value, args: Vec<Value>) -> Result<Vec<Value>> {
+        if self.depth == 0 {
+            self.steps = 0;
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::new("Stack overflow"));
+        }
+        self.depth += 1;
+        let ret = self.call_inner(f, args);
+        self.depth -= 1;
+        ret
+    }
+
+    fn call_inner(&mut self, f: &Value, mut args: Vec<Value>) -> Result<Vec<Value>> {
+        match f {
+            Value::Builtin(b) => b(self, args),
+            Value::Function(c) => {
+                let env = new_scope(Some(c.env.clone()));
+                args.resize(c.def.params.len().max(args.len()), Value::Nil);
+                for (p, a) in c.def.params.iter().zip(args) {
+                    declare(&env, p.clone(), a);
+                }
+                match self.exec_stmts(&c.def.body, &env)? {
+                    Flow::Return(vals) => Ok(vals),
+                    _ => Ok(vec![]),
+                }
+            }
+            v => Err(Error::new(format!(
+                "Attempt to call a {} value",
+                v.type_name()
+            ))),
+        }
+    }
+
+    fn lookup(&self, env: &Env, name: &str) -> Value {
+        let mut scope = env.clone();
+        loop {
+            if let Some((_, v)) = scope.borrow().vars.iter().rev().find(|(n, _)| &**n == name) {
+                return v.clone();
+            }
+            let parent = match &scope.borrow().parent {
+                Some(p) => p.clone(),
+                None => break,
+            };
+            scope = parent;
+        }
+        self.global(name)
+    }
+
+    fn assign_name(&mut self, env: &Env, name: &Rc<str>, value: Value) {
+        let mut scope = env.clone();
+        loop {
+            if let Some((_, v)) = scope
+                .borrow_mut()
+                .vars
+                .iter_mut()
+                .rev()
+                .find(|(n, _)| n == name)
+            {
+                *v = value;
+                return;
+            }
+            let parent = match &scope.borrow().parent {
+                Some(p) => p.clone(),
+                None => break,
+            };
+            scope = parent;
+        }
+        self.globals.insert(name.clone(), value);
+    }
+
+    fn exec_block(&mut self, block: &[Stmt], parent: &Env) -> Result<Flow> {
+        self.exec_stmts(block, &new_scope(Some(parent.clone())))
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(Error::new("Script took too long"));
+        }
+        Ok(())
+    }
+
+    // Empty loop bodies also count one step so that 'while true do end' stops
+    fn exec_stmts(&mut self, block: &[Stmt], env: &Env) -> Result<Flow> {
+        self.step()?;
+        for stmt in block {
+            if let Err(mut e) = self.step() {
+                e.line = stmt.line;
+                return Err(e);
+            }
+            match self.exec_stmt(stmt, env) {
+                Ok(Flow::Normal) => {}
+                Ok(flow) => return Ok(flow),
+                Err(mut e) => {
+                    if e.line == 0 {
+                        e.line = stmt.line;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_loop_body(&mut self, block: &[Stmt], env: &Env) -> Result<Option<Flow>> {
+        match self.exec_block(block, env)? {
+            Flow::Normal => Ok(None),
+            Flow::Break => Ok(Some(Flow::Normal)),
+            ret => Ok(Some(ret)),
+        }
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt, env: &Env) -> Result<Flow> {
+        match &stmt.kind {
+            StmtKind::Local(names, exprs) => {
+                let mut vals = self.eval_list(exprs, env)?;
+                vals.resize(names.len(), Value::Nil);
+                for (n, v) in names.iter().zip(vals) {
+                    declare(env, n.clone(), v);
+                }
+            }
+            StmtKind::LocalFunction(name, def) => {
+                // Declare first so that the function can call itself recursively
+                declare(env, name.clone(), Value::Nil);
+                let f = Value::Function(Rc::new(Closure {
+                    def: def.clone(),
+                    env: env.clone(),
+                }));
+                self.assign_name(env, name, f);
+            }
+            StmtKind::Assign(targets, exprs) => self.exec_assign(targets, exprs, env)?,
+            StmtKind::Call(e) => {
+                self.eval_multi(e, env)?;
+            }
+            StmtKind::If(arms, otherwise) => {
+                for (cond, body) in arms {
+                    if self.eval(cond, env)?.truthy() {
+                        return self.exec_block(body, env);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(body, env);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                while self.eval(cond, env)?.truthy() {
+                    if let Some(flow) = self.exec_loop_body(body, env)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StmtKind::Repeat(body, cond) => loop {
+                // The condition can refer local variables in the body
+                let scope = new_scope(Some(env.clone()));
+                match self.exec_stmts(body, &scope)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    ret => return Ok(ret),
+                }
+                if self.eval(cond, &scope)?.truthy() {
+                    break;
+                }
+            },
+            StmtKind::NumFor(name, start, stop, step, body) => {
+                return self.exec_num_for(name, start, stop, step.as_ref(), body, env)
+            }
+            StmtKind::GenFor(names, exprs, body) => {
+                return self.exec_gen_for(names, exprs, body, env)
+            }
+            StmtKind::Do(body) => return self.exec_block(body, env),
+            StmtKind::Return(exprs) => return Ok(Flow::Return(self.eval_list(exprs, env)?)),
+            StmtKind::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_assign(&mut self, targets: &[Expr], exprs: &[Expr], env: &Env) -> Result<()> {
+        // Evaluate tables and keys of targets before assigning anything
+        let mut places = Vec::with_capacity(targets.len());
+        for t in targets {
+            places.push(match t {
+                Expr::Index(t, k) => Some((self.eval(t, env)?, self.eval(k, env)?)),
+                _ => None,
+            });
+        }
+        let mut vals = self.eval_list(exprs, env)?;
+        vals.resize(targets.len(), Value::Nil);
+        for ((t, p), v) in targets.iter().zip(places).zip(vals) {
+            match (t, p) {
+                (Expr::Name(n), _) => self.assign_name(env, n, v),
+                (_, Some((Value::Table(t), k))) => t.borrow_mut().set(k, v)?,
+                (_, Some((t, _))) => {
+                    return Err(Error::new(format!(
+                        "Attempt to index a {} value",
+                        t.type_name()
+                    )))
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_num_for(
+        &mut self,
+        name: &Rc<str>,
+        start: &Expr,
+        stop: &Expr,
+        step: Option<&Expr>,
+        body: &[Stmt],
+        env: &Env,
+    ) -> Result<Flow> {
+        let num = |v: Value, what: &str| match v.as_number() {
+            Some(n) => Ok(n),
+            None => Err(Error::new(format!("'for' {} must be a number", what))),
+        };
+        let start = num(self.eval(start, env)?, "initial value")?;
+        let stop = num(self.eval(stop, env)?, "limit")?;
+        let step = match step {
+            Some(s) => num(self.eval(s, env)?, "step")?,
+            None => 1.0,
+        };
+        if step == 0.0 {
+            return Err(Error::new("'for' step is zero"));
+        }
+        let mut i = start;
+        while step > 0.0 && i <= stop || step < 0.0 && i >= stop {
+            let scope = new_scope(Some(env.clone()));
+            declare(&scope, name.clone(), Value::Number(i));
+            if let Some(flow) = self.exec_loop_body(body, &scope)? {
+                return Ok(flow);
+            }
+            i += step;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec_gen_for(
+        &mut self,
+        names: &[Rc<str>],
+        exprs: &[Expr],
+        body: &[Stmt],
+        env: &Env,
+    ) -> Result<Flow> {
+        let mut vals = self.eval_list(exprs, env)?.into_iter();
+        let f = vals.next().unwrap_or(Value::Nil);
+        let state = vals.next().unwrap_or(Value::Nil);
+        let mut control = vals.next().unwrap_or(Value::Nil);
+        loop {
+            let mut vals = self.call(&f, vec![state.clone(), control.clone()])?;
+            if vals.is_empty() || matches!(vals[0], Value::Nil) {
+                return Ok(Flow::Normal);
+            }
+            control = vals[0].clone();
+            vals.resize(names.len(), Value::Nil);
+            let scope = new_scope(Some(env.clone()));
+            for (n, v) in names.iter().zip(vals) {
+                declare(&scope, n.clone(), v);
+            }
+            if let Some(flow) = self.exec_loop_body(body, &scope)? {
+                return Ok(flow);
+            }
+        }
+    }
+
+    fn eval_table(&mut self, fields: &[(Option<Expr>, Expr)], env: &Env) -> Result<Value> {
+        let mut t = Table::default();
+        for (i, (k, v)) in fields.iter().enumerate() {
+            match k {
+                Some(k) => {
+                    let k = self.eval(k, env)?;
+                    let v = self.eval(v, env)?;
+                    t.set(k, v)?;
+                }
+                None if i + 1 == fields.len() && v.is_multi() => {
+                    for v in self.eval_multi(v, env)? {
+                        t.set(Value::Number((t.len() + 1) as f64), v)?;
+                    }
+                }
+                None => {
+                    let v = self.eval(v, env)?;
+                    let n = t.len() + 1;
+                    t.set(Value::Number(n as f64), v)?;
+                }
+            }
+        }
+        Ok(table(t))
+    }
+
+    fn eval_unary(&mut self, op: UnOp, e: &Expr, env: &Env) -> Result<Value> {
+        let v = self.eval(e, env)?;
+        match op {
+            UnOp::Not => Ok(Value::Bool(!v.truthy())),
+            UnOp::Neg => match v.as_number() {
+                Some(n) => Ok(Value::Number(-n)),
+                None => Err(arith_error(&v, &v)),
+            },
+            UnOp::Len => match &v {
+                Value::Str(s) => Ok(Value::Number(s.chars().count() as f64)),
+                Value::Table(t) => Ok(Value::Number(t.borrow().len() as f64)),
+                v => Err(Error::new(format!(
+                    "Attempt to get length of a {} value",
+                    v.type_name()
+                ))),
+            },
+        }
+    }
+
+    // Evaluate a list of expressions. Only the last expression can be expanded to multiple values
+    fn eval_list(&mut self, exprs: &[Expr], env: &Env) -> Result<Vec<Value>> {
+        let mut vals = Vec::with_capacity(exprs.len());
+        for (i, e) in exprs.iter().enumerate() {
+            if i + 1 == exprs.len() && e.is_multi() {
+                vals.extend(self.eval_multi(e, env)?);
+            } else {
+                vals.push(self.eval(e, env)?);
+            }
+        }
+        Ok(vals)
+    }
+
+    fn eval_multi(&mut self, expr: &Expr, env: &Env) -> Result<Vec<Value>> {
+        match expr {
+            Expr::Call(f, args) => {
+                let f = self.eval(f, env)?;
+                let args = self.eval_list(args, env)?;
+                self.call(&f, args)
+            }
+            Expr::Method(obj, name, args) => {
+                let obj = self.eval(obj, env)?;
+                let f = self.index(&obj, &Value::Str(name.clone()))?;
+                let mut a = vec![obj];
+                a.extend(self.eval_list(args, env)?);
+                self.call(&f, a)
+            }
+            e => Ok(vec![self.eval(e, env)?]),
+        }
+    }
+
+    fn index(&self, v: &Value, key: &Value) -> Result<Value> {
+        match v {
+            Value::Table(t) => Ok(t.borrow().get(key)),
+            // Strings share functions in string library as methods
+            Value::Str(_) => match self.global("string") {
+                Value::Table(t) => Ok(t.borrow().get(key)),
+                _ => Ok(Value::Nil),
+            },
+            v => Err(Error::new(format!(
+                "Attempt to index a {} value{}",
+                v.type_name(),
+                match key {
+                    Value::Str(s) => format!(" (field '{}')", s),
+                    _ => String::new(),
+                }
+            ))),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, env: &Env) -> Result<Value> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Name(n) => self.lookup(env, n),
+            Expr::Index(t, k) => {
+                let t = self.eval(t, env)?;
+                let k = self.eval(k, env)?;
+                self.index(&t, &k)?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_multi(expr, env)?
+                .into_iter()
+                .next()
+                .unwrap_or(Value::Nil),
+            Expr::Function(def) => Value::Function(Rc::new(Closure {
+                def: def.clone(),
+                env: env.clone(),
+            })),
+            Expr::Table(fields) => self.eval_table(fields, env)?,
+            Expr::Paren(e) => self.eval(e, env)?,
+            Expr::Unary(op, e) => self.eval_unary(*op, e, env)?,
+            Expr::Binary(BinOp::And, l, r) => {
+                let l = self.eval(l, env)?;
+                if l.truthy() {
+                    self.eval(r, env)?
+                } else {
+                    l
+                }
+            }
+            Expr::Binary(BinOp::Or, l, r) => {
+                let l = self.eval(l, env)?;
+                if l.truthy() {
+                    l
+                } else {
+                    self.eval(r, env)?
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let l = self.eval(l, env)?;
+                let r = self.eval(r, env)?;
+                binary(*op, &l, &r)?
+            }
+        })
+    }
+}
+
+fn binary(op: BinOp, l: &Value, r: &Value) -> Result<Value> {
+    use BinOp::*;
+    let arith = |f: fn(f64, f64) -> f64| match (l.as_number(), r.as_number()) {
+        (Some(a), Some(b)) => Ok(Value::Number(f(a, b))),
+        _ => Err(arith_error(l, r)),
+    };
+    let compare = || match (l, r) {
+        (Value::Number(a), Value::Number(b)) => Ok(a.partial_cmp(b)),
+        (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+        _ => Err(Error::new(format!(
+            "Attempt to compare {} with {}",
+            l.type_name(),
+            r.type_name()
+        ))),
+    };
+    Ok(match op {
+        Add => arith(|a, b| a + b)?,
+        Sub => arith(|a, b| a - b)?,
+        Mul => arith(|a, b| a * b)?,
+        Div => arith(|a, b| a / b)?,
+        Mod => arith(|a, b| a - (a / b).floor() * b)?,
+        Pow => arith(f64::powf)?,
+        Concat => match (l, r) {
+            (Value::Str(_) | Value::Number(_), Value::Str(_) | Value::Number(_)) => {
+                Value::string(format!("{}{}", l, r))
+            }
+            _ => {
+                let v = if matches!(l, Value::Str(_) | Value::Number(_)) {
+                    r
+                } else {
+                    l
+                };
+                return Err(Error::new(format!(
+                    "Attempt to concatenate a {} value",
+                    v.type_name()
+                )));
+            }
+        },
+        Eq => Value::Bool(l == r),
+        Ne => Value::Bool(l != r),
+        Lt => Value::Bool(compare()? == Some(Ordering::Less)),
+        Le => Value::Bool(matches!(compare()?, Some(Ordering::Less | Ordering::Equal))),
+        Gt => Value::Bool(compare()? == Some(Ordering::Greater)),
+        Ge => Value::Bool(matches!(
+            compare()?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        And | Or => unreachable!(),
+    })
+}
+
+// Convert 1-based character index which may be negative to 0-based index clamped to [0, len]
+fn char_index(i: f64, len: usize) -> usize {
+    let i = i as i64;
+    if i < 0 {
+        (len as i64 + i).max(0) as usize
+    } else {
+        (i.max(1) as usize - 1).min(len)
+    }
+}
+
+fn format(args: &[Value]) -> Result<String> {
+    let fmt = check_str("format", args, 0)?;
+    let mut out = String::new();
+    let mut chars = fmt.chars().peekable();
+    let mut idx = 1;
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '-' || c == '.' || c.is_ascii_digit() {
+                spec.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let left = spec.starts_with('-');
+        let spec = spec.trim_start_matches('-');
+        let (width, prec) = match spec.split_once('.') {
+            Some((w, p)) => (w, Some(p.parse::<usize>().unwrap_or(0))),
+            None => (spec, None),
+        };
+        let width: usize = width.parse().unwrap_or(0);
+        let s = match chars.next() {
+            Some('%') => {
+                out.push('%');
+                continue;
+            }
+            Some('s') => {
+                let s = tostring(&arg(args, idx));
+                match prec {
+                    Some(p) => s.chars().take(p).collect(),
+                    None => s,
+                }
+            }
+            Some('d') => format!("{}", check_num("format", args, idx)?.trunc() as i64),
+            Some('x') => format!("{:x}", check_num("format", args, idx)? as i64),
+            Some('X') => format!("{:X}", check_num("format", args, idx)? as i64),
+            Some('f') => format!("{:.*}", prec.unwrap_or(6), check_num("format", args, idx)?),
+            Some('q') => format!("{:?}", check_str("format", args, idx)?),
+            Some(c) => {
+                return Err(Error::new(format!(
+                    "Invalid conversion '%{}' to 'format'",
+                    c
+                )))
+            }
+            None => return Err(Error::new("Invalid conversion '%' to 'format'")),
+        };
+        idx += 1;
+        let pad = width.saturating_sub(s.chars().count());
+        if left {
+            out.push_str(&s);
+            out.extend(std::iter::repeat_n(' ', pad));
+        } else {
+            out.extend(std::iter::repeat_n(' ', pad));
+            out.push_str(&s);
+        }
+    }
+    Ok(out)
+}
+
+fn tostring(v: &Value) -> String {
+    v.to_string()
+}
+
+// Table of functions such as 'string' library
+pub fn library(funcs: Vec<(&str, Value)>) -> Value {
+    let mut t = Table::default();
+    for (name, f) in funcs {
+        t.set_str(name, f);
+    }
+    table(t)
+}
+
+fn one(v: Value) -> Result<Vec<Value>> {
+    Ok(vec![v])
+}
+
+fn stdlib(interp: &mut Interp) {
+    interp.set_global(
+        "print",
+        Value::builtin(|interp, args| {
+            let line: Vec<String> = args.iter().map(tostring).collect();
+            interp.output.push(line.join("\t"));
+            Ok(vec![])
+        }),
+    );
+    interp.set_global(
+        "type",
+        Value::builtin(|_, args| match args.first() {
+            Some(v) => one(Value::string(v.type_name())),
+            None => bad_arg("type", 0, "value", &Value::Nil),
+        }),
+    );
+    interp.set_global(
+        "tostring",
+        Value::builtin(|_, args| one(Value::string(tostring(&arg(&args, 0))))),
+    );
+    interp.set_global(
+        "tonumber",
+        Value::builtin(|_, args| {
+            let n = match (arg(&args, 0), arg(&args, 1)) {
+                (v, Value::Nil) => v.as_number(),
+                (Value::Str(s), _) => {
+                    let base = check_num("tonumber", &args, 1)? as u32;
+                    if !(2..=36).contains(&base) {
+                        return Err(Error::new(
+                            "Bad argument #2 to 'tonumber' (base out of range)",
+                        ));
+                    }
+                    i64::from_str_radix(s.trim(), base).ok().map(|i| i as f64)
+                }
+                (v, _) => return bad_arg("tonumber", 0, "string", &v),
+            };
+            one(n.map(Value::Number).unwrap_or(Value::Nil))
+        }),
+    );
+    interp.set_global(
+        "next",
+        Value::builtin(|_, args| {
+            let t = check_table("next", &args, 0)?;
+            let next = t.borrow().next(&arg(&args, 1))?;
+            Ok(match next {
+                Some((k, v)) => vec![k, v],
+                None => vec![Value::Nil],
+            })
+        }),
+    );
+    interp.set_global(
+        "pairs",
+        Value::builtin(|interp, args| {
+            let t = check_table("pairs", &args, 0)?;
+            Ok(vec![interp.global("next"), Value::Table(t), Value::Nil])
+        }),
+    );
+    interp.set_global(
+        "ipairs",
+        Value::builtin(|_, args| {
+            let t = check_table("ipairs", &args, 0)?;
+            let iter = Value::builtin(|_, args| {
+                let t = check_table("ipairs", &args, 0)?;
+                let i = check_num("ipairs", &args, 1)? + 1.0;
+                let v = t.borrow().get(&Value::Number(i));
+                Ok(match v {
+                    Value::Nil => vec![Value::Nil],
+                    v => vec![Value::Number(i), v],
+                })
+            });
+            Ok(vec![iter, Value::Table(t), Value::Number(0.0)])
+        }),
+    );
+    interp.set_global(
+        "error",
+        Value::builtin(|_, args| Err(Error::new(tostring(&arg(&args, 0))))),
+    );
+    interp.set_global(
+        "pcall",
+        Value::builtin(|interp, mut args| {
+            if args.is_empty() {
+                return bad_arg("pcall", 0, "value", &Value::Nil);
+            }
+            let f = args.remove(0);
+            match interp.call(&f, args) {
+                Ok(mut vals) => {
+                    vals.insert(0, Value::Bool(true));
+                    Ok(vals)
+                }
+                Err(e) => Ok(vec![Value::Bool(false), Value::string(e.to_string())]),
+            }
+        }),
+    );
+
+    let string = library(vec![
+        (
+            "len",
+            Value::builtin(|_, args| {
+                let s = check_str("len", &args, 0)?;
+                one(Value::Number(s.chars().count() as f64))
+            }),
+        ),
+        (
+            "upper",
+            Value::builtin(|_, args| {
+                one(Value::string(check_str("upper", &args, 0)?.to_uppercase()))
+            }),
+        ),
+        (
+            "lower",
+            Value::builtin(|_, args| {
+                one(Value::string(check_str("lower", &args, 0)?.to_lowercase()))
+            }),
+        ),
+        (
+            "reverse",
+            Value::builtin(|_, args| {
+                let s = check_str("reverse", &args, 0)?;
+                one(Value::string(s.chars().rev().collect::<String>()))
+            }),
+        ),
+        (
+            "rep",
+            Value::builtin(|_, args| {
+                let s = check_str("rep", &args, 0)?;
+                let n = check_num("rep", &args, 1)?.max(0.0) as usize;
+                let sep = match arg(&args, 2) {
+                    Value::Nil => "".into(),
+                    _ => check_str("rep", &args, 2)?,
+                };
+                one(Value::string(vec![&*s; n].join(&sep)))
+            }),
+        ),
+        (
+            "sub",
+            Value::builtin(|_, args| {
+                let s = check_str("sub", &args, 0)?;
+                let len = s.chars().count();
+                let i = char_index(opt_num("sub", &args, 1, 1.0)?, len);
+                let j = opt_num("sub", &args, 2, -1.0)? as i64;
+                let j = if j < 0 {
+                    len as i64 + j + 1
+                } else {
+                    j.min(len as i64)
+                };
+                let sub: String = if (i as i64) < j {
+                    s.chars().skip(i).take(j as usize - i).collect()
+                } else {
+                    String::new()
+                };
+                one(Value::string(sub))
+            }),
+        ),
+        (
+            // Patterns are not supported. The pattern is always searched as plain text
+            "find",
+            Value::builtin(|_, args| {
+                let s = check_str("find", &args, 0)?;
+                let pat = check_str("find", &args, 1)?;
+                let chars: Vec<char> = s.chars().collect();
+                let start = char_index(opt_num("find", &args, 2, 1.0)?, chars.len());
+                let offset: usize = chars[..start].iter().map(|c| c.len_utf8()).sum();
+                Ok(match s[offset..].find(&*pat) {
+                    Some(byte) => {
+                        let i = start + s[offset..offset + byte].chars().count();
+                        let n = pat.chars().count();
+                        vec![Value::Number((i + 1) as f64), Value::Number((i + n) as f64)]
+                    }
+                    None => vec![Value::Nil],
+                })
+            }),
+        ),
+        (
+            // The pattern is plain text. Replacement is a string or a function receiving the match
+            "gsub",
+            Value::builtin(|interp, args| {
+                let s = check_str("gsub", &args, 0)?;
+                let pat = check_str("gsub", &args, 1)?;
+                let repl = arg(&args, 2);
+                let max = opt_num("gsub", &args, 3, f64::INFINITY)?;
+                if pat.is_empty() {
+                    return Err(Error::new("Empty pattern passed to 'gsub'"));
+                }
+                let mut out = String::new();
+                let mut rest = &*s;
+                let mut count = 0;
+                while (count as f64) < max {
+                    let i = match rest.find(&*pat) {
+                        Some(i) => i,
+                        None => break,
+                    };
+                    out.push_str(&rest[..i]);
+                    match &repl {
+                        Value::Str(_) | Value::Number(_) => out.push_str(&tostring(&repl)),
+                        Value::Function(_) | Value::Builtin(_) => {
+                            let vals = interp.call(&repl, vec![Value::Str(pat.clone())])?;
+                            match vals.into_iter().next() {
+                                Some(Value::Nil) | Some(Value::Bool(false)) | None => {
+                                    out.push_str(&pat)
+                                }
+                                Some(v) => out.push_str(&tostring(&v)),
+                            }
+                        }
+                        v => return bad_arg("gsub", 2, "string or function", v),
+                    }
+                    rest = &rest[i + pat.len()..];
+                    count += 1;
+                }
+                out.push_str(rest);
+                Ok(vec![Value::string(out), Value::Number(count as f64)])
+            }),
+        ),
+        (
+            "format",
+            Value::builtin(|_, args| one(Value::string(format(&args)?))),
+        ),
+    ]);
+    interp.set_global("string", string);
+
+    let table_lib = library(vec![
+        (
+            "insert",
+            Value::builtin(|_, args| {
+                let t = check_table("insert", &args, 0)?;
+                let mut t = t.borrow_mut();
+                let len = t.len();
+                match args.len() {
+                    2 => t.push(args[1].clone()),
+                    3 => {
+                        let pos = check_num("insert", &args, 1)? as usize;
+                        if pos < 1 || pos > len + 1 {
+                            return Err(Error::new(
+                                "Bad argument #2 to 'insert' (position out of bounds)",
+                            ));
+                        }
+                        t.array.insert(pos - 1, args[2].clone());
+                    }
+                    _ => return Err(Error::new("Wrong number of arguments to 'insert'")),
+                }
+                Ok(vec![])
+            }),
+        ),
+        (
+            "remove",
+            Value::builtin(|_, args| {
+                let t = check_table("remove", &args, 0)?;
+                let mut t = t.borrow_mut();
+                let len = t.len();
+                let pos = opt_num("remove", &args, 1, len as f64)? as usize;
+                if len == 0 || pos < 1 || pos > len {
+                    return one(Value::Nil);
+                }
+                one(t.array.remove(pos - 1))
+            }),
+        ),
+        (
+            "concat",
+            Value::builtin(|_, args| {
+                let t = check_table("concat", &args, 0)?;
+                let sep = match arg(&args, 1) {
+                    Value::Nil => "".into(),
+                    _ => check_str("concat", &args, 1)?,
+                };
+                let t = t.borrow();
+                let mut parts = vec![];
+                for (i, v) in t.array.iter().enumerate() {
+                    match v {
+                        Value::Str(_) | Value::Number(_) => parts.push(tostring(v)),
+                        _ => {
+                            return Err(Error::new(format!(
+                                "Invalid value (at index {}) in table for 'concat'",
+                                i + 1
+                            )))
+                        }
+                    }
+                }
+                one(Value::string(parts.join(&sep)))
+            }),
+        ),
+        (
+            "unpack",
+            Value::builtin(|_, args| Ok(check_table("unpack", &args, 0)?.borrow().array.clone())),
+        ),
+    ]);
+    interp.set_global("table", table_lib);
+
+    let math = library(vec![
+        (
+            "floor",
+            Value::builtin(|_, args| one(Value::Number(check_num("floor", &args, 0)?.floor()))),
+        ),
+        (
+            "ceil",
+            Value::builtin(|_, args| one(Value::Number(check_num("ceil", &args, 0)?.ceil()))),
+        ),
+        (
+            "abs",
+            Value::builtin(|_, args| one(Value::Number(check_num("abs", &args, 0)?.abs()))),
+        ),
+        (
+            "max",
+            Value::builtin(|_, args| {
+                let mut m = check_num("max", &args, 0)?;
+                for i in 1..args.len() {
+                    m = m.max(check_num("max", &args, i)?);
+                }
+                one(Value::Number(m))
+            }),
+        ),
+        (
+            "min",
+            Value::builtin(|_, args| {
+                let mut m = check_num("min", &args, 0)?;
+                for i in 1..args.len() {
+                    m = m.min(check_num("min", &args, i)?);
+                }
+                one(Value::Number(m))
+            }),
+        ),
+        ("huge", Value::Number(f64::INFINITY)),
+    ]);
+    interp.set_global("math", math);
+}
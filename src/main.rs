@@ -2,66 +2,110 @@
 //   Build Your Own Text Editor: https://viewsourcecode.org/snaptoken/kilo/index.html
 //   VT100 User Guide: https://vt100.net/docs/vt100-ug/chapter3.html
 
-use getopts::Options;
 use std::env;
 use std::io;
+use std::panic;
 use std::process::exit;
 
-use kiro_editor::{Editor, StdinRawMode, HELP, VERSION};
+use kiro_editor::{
+    config_dir, default_socket, install_panic_hook, load_user_config, load_user_syntaxes,
+    open_files, parse_args, split_location, state_dir, window_size, Args, Cli, Clipboard, Editor,
+    Jump, Result, StdinRawMode, Term, VERSION,
+};
+use std::path::PathBuf;
 
-fn print_help(program: &str, opts: Options) {
-    let description = format!(
-        "{prog}: A tiny UTF-8 terminal text editor
-
-Kiro is a tiny UTF-8 text editor on terminals for Unix-like systems.
-Specify file paths to edit as a command argument or run without argument to
-start to write a new text.
-Help can show up with key mapping Ctrl-?.
-
-Usage:
-    {prog} [options] [FILES...]
-
-Mappings:
-    {maps}",
-        prog = program,
-        maps = HELP,
-    );
-    println!("{}", opts.usage(&description));
-}
-
-fn edit(files: Vec<String>) -> io::Result<()> {
-    // TODO: Read input from stdin before start
-    let input = StdinRawMode::new()?.input_keys();
-    Editor::open(input, io::stdout(), None, &files)?.edit()
+fn edit(mut args: Args) -> Result<()> {
+    let socket = match &args.socket {
+        Some(path) => PathBuf::from(path),
+        None => default_socket(),
+    };
+    if args.remote {
+        if open_files(&socket, &args.files, args.wait)? {
+            return Ok(());
+        }
+        // No editor is listening. Edit the files in this process instead
+        let files = args
+            .files
+            .iter()
+            .map(|f| split_location(f))
+            .collect::<Vec<_>>();
+        if let Some(line) = files[0].1 {
+            args.jump = Some(Jump::Line(line));
+        }
+        args.files = files.into_iter().map(|(f, _)| f.to_string()).collect();
+    }
+    load_user_syntaxes();
+    load_user_config();
+    // When text is piped to stdin, key inputs are read from the terminal instead
+    let raw_mode = if args.files.iter().any(|f| f == "-") {
+        StdinRawMode::from_tty()?
+    } else {
+        StdinRawMode::new()?
+    };
+    install_panic_hook(&raw_mode);
+    let suspender = raw_mode.suspender();
+    let input = raw_mode.input_keys();
+    let mut editor = Editor::open(
+        input,
+        io::stdout(),
+        window_size(),
+        Clipboard::system(),
+        &args.files,
+    )?;
+    editor.set_state_dir(state_dir());
+    if args.listen {
+        editor.listen(&socket)?;
+    }
+    if let Some(dir) = config_dir() {
+        editor.load_script(&dir.join("init.lua"));
+    }
+    if let Some(suspender) = suspender {
+        editor.set_suspender(suspender);
+    }
+    if args.view {
+        editor.set_view_mode();
+    } else if args.readonly {
+        editor.set_readonly();
+    }
+    if let Some(jump) = &args.jump {
+        editor.jump(jump);
+    }
+    if args.diff {
+        editor.start_diff();
+    }
+    // The panic hook restored the terminal and printed the message before the panic is caught here
+    match panic::catch_unwind(panic::AssertUnwindSafe(|| editor.edit())) {
+        Ok(result) => result,
+        Err(payload) => {
+            for msg in editor.recover() {
+                eprintln!("{}", msg);
+            }
+            panic::resume_unwind(payload)
+        }
+    }
 }
 
 fn main() {
     let mut argv = env::args();
     let program = argv.next().unwrap();
 
-    let mut opts = Options::new();
-    opts.optflag("v", "version", "Print version");
-    opts.optflag("h", "help", "Print this help");
-
-    let matches = match opts.parse(argv) {
-        Ok(m) => m,
-        Err(e) => {
-            eprintln!("Error: {}. Please see --help for more details", e);
+    let args = match parse_args(&program, argv) {
+        Ok(Cli::Edit(args)) => args,
+        Ok(Cli::Help(help)) => {
+            println!("{}", help);
+            return;
+        }
+        Ok(Cli::Version) => {
+            println!("{}", VERSION);
+            return;
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
             exit(1);
         }
     };
 
-    if matches.opt_present("v") {
-        println!("{}", VERSION);
-        return;
-    }
-
-    if matches.opt_present("h") {
-        print_help(&program, opts);
-        return;
-    }
-
-    if let Err(err) = edit(matches.free) {
+    if let Err(err) = edit(args) {
         eprintln!("Error: {}", err);
         exit(1);
     }
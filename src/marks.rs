@@ -0,0 +1,108 @@
+// Named positions in text buffer set by users for jumping back to them later. Marks follow their
+// lines when lines are inserted or deleted above them. Marks of files are persisted in a state
+// file so that they are available in next sessions. Each line of the state file is a mark:
+//
+//   {name}\t{x}\t{y}\t{absolute file path}
+
+use crate::error::{Context, Result};
+use crate::undo::EditDiff;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct Marks(Vec<(char, usize, usize)>); // (name, x, y)
+
+impl Marks {
+    pub fn set(&mut self, name: char, x: usize, y: usize) {
+        self.remove(name);
+        self.0.push((name, x, y));
+    }
+
+    pub fn get(&self, name: char) -> Option<(usize, usize)> {
+        self.0
+            .iter()
+            .find(|(n, ..)| *n == name)
+            .map(|(_, x, y)| (*x, *y))
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.0.retain(|(n, ..)| *n != name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = char> + '_ {
+        self.0.iter().map(|(n, ..)| *n)
+    }
+
+    // Mark on a deleted line moves to the next line
+    pub fn on_diff(&mut self, diff: &EditDiff) {
+        match diff {
+            EditDiff::InsertLine(at, _) => {
+                for (_, _, y) in self.0.iter_mut().filter(|(_, _, y)| y >= at) {
+                    *y += 1;
+                }
+            }
+            EditDiff::DeleteLine(at, _) => {
+                for (_, _, y) in self.0.iter_mut().filter(|(_, _, y)| y > at) {
+                    *y -= 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<(char, usize, usize, &str)> {
+    let mut fields = line.splitn(4, '\t');
+    let mut name = fields.next()?.chars();
+    let name = match (name.next(), name.next()) {
+        (Some(c), None) => c,
+        _ => return None,
+    };
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    Some((name, x, y, fields.next()?))
+}
+
+// Files are identified by absolute paths in state files
+pub fn file_key(file: &Path) -> String {
+    fs::canonicalize(file)
+        .unwrap_or_else(|_| file.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Broken lines in the state file are ignored
+pub fn load(state: &Path, file: &Path) -> Marks {
+    let key = file_key(file);
+    let source = fs::read_to_string(state).unwrap_or_default();
+    let marks = source
+        .lines()
+        .filter_map(parse_line)
+        .filter(|(.., path)| *path == key)
+        .map(|(n, x, y, _)| (n, x, y))
+        .collect();
+    Marks(marks)
+}
+
+// Marks of the file in the state file are replaced. Marks of other files are kept
+pub fn save(state: &Path, file: &Path, marks: &Marks) -> Result<()> {
+    let key = file_key(file);
+    let source = match fs::read_to_string(state) {
+        Ok(source) => source,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context("reading", state),
+    };
+    let mut content: String = source
+        .lines()
+        .filter(|l| parse_line(l).map(|(.., path)| path != key).unwrap_or(false))
+        .map(|l| format!("{}\n", l))
+        .collect();
+    for (n, x, y) in &marks.0 {
+        content.push_str(&format!("{}\t{}\t{}\t{}\n", n, x, y, key));
+    }
+    if let Some(dir) = state.parent() {
+        fs::create_dir_all(dir).context("creating", dir)?;
+    }
+    fs::write(state, content).context("saving", state)
+}
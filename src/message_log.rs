@@ -0,0 +1,78 @@
+// Log of messages shown in the message bar. A message disappears from the message bar when the
+// next one is shown or after a few seconds, so every info and error message is recorded here with
+// the time to look back at it later in a read-only buffer.
+
+use crate::clock::unix_time;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+// Number of kept messages. Older ones are dropped
+const MAX_MESSAGES: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageLevel {
+    Info,
+    Error,
+}
+
+#[derive(Default)]
+pub struct MessageLog {
+    messages: VecDeque<(u64, MessageLevel, String)>, // Seconds since the Unix epoch, older first
+}
+
+// "YYYY-MM-DDTHH:MM:SSZ" in UTC. Days are converted to a civil date by the algorithm from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn format_time(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    )
+}
+
+impl MessageLog {
+    // Empty message only clears the message bar so it is not recorded
+    pub fn push(&mut self, level: MessageLevel, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages
+            .push_back((unix_time(), level, text.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Each line is "{time} {level} {text}". Newlines in a message are indented so that one
+    // message is not confused with following ones
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (time, level, message) in &self.messages {
+            let level = match level {
+                MessageLevel::Info => "info ",
+                MessageLevel::Error => "error",
+            };
+            let message = message.replace('\n', "\n    ");
+            writeln!(text, "{} {} {}", format_time(*time), level, message).unwrap();
+        }
+        text
+    }
+}
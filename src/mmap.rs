@@ -0,0 +1,224 @@
+// Read-only memory map of a file. Rows of a buffer opened with `mmap` option refer to lines in the
+// map instead of copying them until the buffer is edited. It reduces time to load a big file which
+// is mostly read and avoids keeping the file twice in memory.
+//
+// The map is private and read-only, but pages not touched yet still reflect the file when another
+// process overwrites or truncates it. Reading a page beyond the end of truncated file raises SIGBUS.
+// The handler of SIGBUS replaces the page with a page filled with zeros so that the read returns
+// and marks the map as faulted. `changed` returns true for the faulted map and the file is loaded
+// again as other changes are. SIGBUS raised outside maps kills the process as usual. Only
+// unmodified buffers refer to a map. All lines are copied on the first edit and the file is checked
+// again before saving.
+//
+// Mapping needs libc, which is only available with the terminal frontend on Unix-like systems.
+// Otherwise `Mmap::open` fails and files are read into memory as usual.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[cfg(all(feature = "terminal", unix))]
+mod guard {
+    use std::io;
+    use std::mem;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    // Maps are looked up in the signal handler, where taking a lock or allocating is not allowed
+    const SLOTS: usize = 64;
+    static STARTS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static LENS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static FAULTED: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    static INSTALL: Once = Once::new();
+
+    #[cfg(target_os = "linux")]
+    unsafe fn fault_addr(info: &libc::siginfo_t) -> usize {
+        info.si_addr() as usize
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    unsafe fn fault_addr(info: &libc::siginfo_t) -> usize {
+        info.si_addr as usize
+    }
+
+    extern "C" fn on_sigbus(_: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+        let addr = unsafe { fault_addr(&*info) };
+        let page = PAGE_SIZE.load(Ordering::Relaxed);
+        for slot in 0..SLOTS {
+            let start = STARTS[slot].load(Ordering::SeqCst);
+            if start == 0 || addr < start || start + LENS[slot].load(Ordering::SeqCst) <= addr {
+                continue;
+            }
+            let mapped = unsafe {
+                libc::mmap(
+                    (addr & !(page - 1)) as *mut libc::c_void,
+                    page,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                    -1,
+                    0,
+                )
+            };
+            if mapped != libc::MAP_FAILED {
+                FAULTED[slot].store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+        // Not caused by a map. Raise it again with the default action, which kills the process
+        unsafe {
+            libc::signal(libc::SIGBUS, libc::SIG_DFL);
+            libc::raise(libc::SIGBUS);
+        }
+    }
+
+    fn install() {
+        PAGE_SIZE.store(
+            unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize,
+            Ordering::Relaxed,
+        );
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_sigbus as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGBUS, &action, ptr::null_mut());
+        }
+    }
+
+    // Returns the slot of the map
+    pub fn register(ptr: *const u8, len: usize) -> io::Result<usize> {
+        INSTALL.call_once(install);
+        for slot in 0..SLOTS {
+            if LENS[slot]
+                .compare_exchange(0, len, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                FAULTED[slot].store(false, Ordering::SeqCst);
+                STARTS[slot].store(ptr as usize, Ordering::SeqCst);
+                return Ok(slot);
+            }
+        }
+        Err(io::Error::other("Too many files are mapped"))
+    }
+
+    pub fn unregister(slot: usize) {
+        STARTS[slot].store(0, Ordering::SeqCst);
+        LENS[slot].store(0, Ordering::SeqCst);
+    }
+
+    pub fn faulted(slot: usize) -> bool {
+        FAULTED[slot].load(Ordering::SeqCst)
+    }
+}
+
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+    #[cfg(all(feature = "terminal", unix))]
+    slot: usize, // Slot in the registry of SIGBUS handler
+    // The file is kept open so that its metadata can be compared even after it is renamed
+    file: File,
+    modified: Option<SystemTime>,
+}
+
+// The mapped memory is never written through the pointer
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    #[cfg(all(feature = "terminal", unix))]
+    pub fn open(path: &Path) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        let len = meta.len() as usize;
+        if len == 0 {
+            // Mapping an empty file fails with EINVAL
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "File is empty"));
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let slot = match guard::register(ptr as *const u8, len) {
+            Ok(slot) => slot,
+            Err(err) => {
+                unsafe { libc::munmap(ptr, len) };
+                return Err(err);
+            }
+        };
+        Ok(Mmap {
+            ptr: ptr as *const u8,
+            len,
+            slot,
+            file,
+            modified: meta.modified().ok(),
+        })
+    }
+
+    #[cfg(not(all(feature = "terminal", unix)))]
+    pub fn open(_path: &Path) -> io::Result<Mmap> {
+        Err(io::Error::other(
+            "Memory map is not supported on this platform",
+        ))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[cfg(all(feature = "terminal", unix))]
+    fn faulted(&self) -> bool {
+        guard::faulted(self.slot)
+    }
+
+    #[cfg(not(all(feature = "terminal", unix)))]
+    fn faulted(&self) -> bool {
+        false
+    }
+
+    // The mapped file was resized or modified after mapping it, or a page beyond the end of the
+    // truncated file was read. Renaming another file to the path such as saving the buffer does not
+    // change it
+    pub fn changed(&self) -> bool {
+        if self.faulted() {
+            return true;
+        }
+        match self.file.metadata() {
+            Ok(meta) => meta.len() as usize != self.len || meta.modified().ok() != self.modified,
+            Err(_) => true,
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(feature = "terminal", unix))]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            guard::unregister(self.slot);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Key mappings of viewer mode (`--view`) to use the editor as a pager like `less`. Buffers are
+// read-only in the mode and keys without modifiers are translated into editor commands:
+//
+//   SPACE f        # Next page
+//   b              # Previous page
+//   j e ENTER      # Next line
+//   k y            # Previous line
+//   g <            # Top of file
+//   G >            # Bottom of file
+//   /              # Incremental search
+//   F              # Follow lines appended to the file
+//   q              # Quit
+//
+// Other keys are looked up in the normal key mappings. Keys which edit text are rejected since
+// buffers are read-only.
+
+use crate::input::{InputSeq, KeySeq};
+use crate::keymap::Command;
+
+pub fn translate(seq: &InputSeq) -> Option<Command> {
+    use Command::*;
+
+    if seq.ctrl || seq.alt {
+        return None;
+    }
+    let cmd = match seq.key {
+        KeySeq::Key(b' ') | KeySeq::Key(b'f') => NextPage,
+        KeySeq::Key(b'b') => PreviousPage,
+        KeySeq::Key(b'j') | KeySeq::Key(b'e') | KeySeq::Key(b'\r') => CursorDown,
+        KeySeq::Key(b'k') | KeySeq::Key(b'y') => CursorUp,
+        KeySeq::Key(b'g') | KeySeq::Key(b'<') => TopOfFile,
+        KeySeq::Key(b'G') | KeySeq::Key(b'>') => BottomOfFile,
+        KeySeq::Key(b'/') => Search,
+        KeySeq::Key(b'F') => Follow,
+        KeySeq::Key(b'q') => Quit,
+        _ => return None,
+    };
+    Some(cmd)
+}
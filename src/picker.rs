@@ -0,0 +1,96 @@
+// State of interactive list picker such as fuzzy file finder and command palette. Items are
+// filtered by the query typed in prompt with fuzzy matching. Editor shows the filtered items over
+// text area from the bottom, which is the best match, and the selection is moved by UP/DOWN keys.
+// A list longer than the screen is shown by pages which follow the selection.
+
+use crate::fuzzy;
+
+pub struct Picker {
+    items: Vec<String>,
+    query: String,
+    matches: Vec<usize>, // Indices of items matching the query from the best match
+    selected: usize,     // Index in matches
+    offset: usize,       // Index in matches of the first item in the page
+}
+
+impl Picker {
+    pub fn new(items: Vec<String>) -> Picker {
+        let matches = (0..items.len()).collect();
+        Picker {
+            items,
+            query: String::new(),
+            matches,
+            selected: 0,
+            offset: 0,
+        }
+    }
+
+    // Selection is reset to the best match only when the query is changed. Empty query matches
+    // all items in the original order
+    pub fn filter(&mut self, query: &str) {
+        if query == self.query {
+            return;
+        }
+        self.matches = if query.is_empty() {
+            (0..self.items.len()).collect()
+        } else {
+            fuzzy::filter(query, self.items.iter().map(String::as_str))
+        };
+        self.query = query.to_string();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    // Move selection by `n` items. Positive `n` moves to worse matches
+    pub fn move_selection(&mut self, n: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = if n < 0 {
+            self.selected.saturating_sub(n.unsigned_abs())
+        } else {
+            self.selected.saturating_add(n as usize).min(last)
+        };
+    }
+
+    // Index of the selected item in items passed to `new`
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.selected).copied()
+    }
+
+    pub fn item(&self, idx: usize) -> &str {
+        &self.items[idx]
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    // 1-based position of the selection for showing it like "[3/120]". 0 when nothing matches
+    pub fn position(&self) -> usize {
+        if self.is_empty() {
+            0
+        } else {
+            self.selected + 1
+        }
+    }
+
+    // Items in the page containing the selection from the best match, and index of the selection
+    // in them. At most `height` items are returned
+    pub fn page(&mut self, height: usize) -> (Vec<String>, usize) {
+        let height = height.max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+        let lines = self.matches[self.offset..]
+            .iter()
+            .take(height)
+            .map(|i| self.items[*i].clone())
+            .collect();
+        (lines, self.selected - self.offset)
+    }
+}
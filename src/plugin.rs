@@ -0,0 +1,399 @@
+// Plugins extend the editor with commands and highlights. A plugin registers commands which are run
+// by keys or by name in the command palette, and may highlight lines of some languages on top of
+// the builtin syntax highlighting.
+//
+// A plugin is either compiled into the frontend and added by `Editor::add_plugin`, or an external
+// process configured in [plugins] table of config.toml which talks the line protocol described at
+// `Process`. Commands are run synchronously. A plugin receives the state of the current buffer and
+// returns edits to apply to it, so that the same request and reply work for both kinds of plugins.
+
+use crate::error::{Error, Result};
+use crate::highlight::Highlight;
+use crate::keymap::{self, Command};
+use crate::language::Language;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+
+// Command provided by a plugin. Its keys are bound unless they are bound to other commands or the
+// user configured keys of the command
+pub struct PluginCommand {
+    pub name: String,
+    pub description: String,
+    pub keys: Vec<String>,
+}
+
+// State of the current buffer when a plugin command is run
+pub struct Request<'a> {
+    pub command: &'a str,
+    pub filename: &'a str,
+    pub lang: Language,
+    pub cursor: (usize, usize), // (x, y) in characters
+    pub selection: Option<String>,
+    pub lines: Vec<&'a str>,
+}
+
+// Edits and messages returned by a plugin command. They are applied in order
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    Insert(String), // Insert text at cursor. Selected text is replaced with it
+    MoveCursor(usize, usize),
+    ReplaceLine(usize, String), // Replace the line at y with text which has no newline
+    Message(String),
+    Error(String),
+}
+
+// Highlight of characters from `start` to `end` (exclusive) in the line at `y`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span {
+    pub y: usize,
+    pub start: usize,
+    pub end: usize,
+    pub hl: Highlight,
+}
+
+pub trait Plugin {
+    fn name(&self) -> &str;
+    fn commands(&self) -> Vec<PluginCommand>;
+    fn run(&mut self, req: &Request<'_>) -> Result<Vec<Action>>;
+
+    // Languages highlighted by `highlight`
+    fn languages(&self) -> Vec<Language> {
+        vec![]
+    }
+
+    fn highlight(&mut self, _lang: Language, _lines: &[&str]) -> Result<Vec<Span>> {
+        Ok(vec![])
+    }
+}
+
+// Text is sent in one field of a line. Backslash, tab and newline are escaped
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> String {
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('t')) => text.push('\t'),
+            ('\\', Some('n')) => text.push('\n'),
+            ('\\', Some('\\')) => text.push('\\'),
+            (c, _) => {
+                text.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    text
+}
+
+// Plugin running as an external process. Messages are lines of fields separated by tabs. Fields
+// containing text are escaped with `\\`, `\t` and `\n`.
+//
+// On start, the process prints the commands and languages it provides and then 'ready':
+//
+//   command <TAB> upcase <TAB> Uppercase selected text
+//   key <TAB> upcase <TAB> Alt-U
+//   highlight <TAB> rust
+//   ready
+//
+// Running a command sends the buffer state ending with 'end', and the process replies actions
+// ending with 'done':
+//
+//   run <TAB> upcase          insert <TAB> HELLO
+//   file <TAB> main.rs        cursor <TAB> 0 <TAB> 3
+//   language <TAB> rust       message <TAB> Uppercased
+//   cursor <TAB> 5 <TAB> 2    replace <TAB> 0 <TAB> fn main() {}
+//   selection <TAB> hello     error <TAB> Nothing is selected
+//   line <TAB> fn main() {    done
+//   ...
+//   end
+//
+// Highlighting a buffer sends 'highlight <TAB> rust' followed by the lines and 'end', and the
+// process replies 'span <TAB> y <TAB> start <TAB> end <TAB> keyword' lines ending with 'done'.
+// Highlight names are the same as colors of themes such as 'keyword', 'type' and 'comment'.
+pub struct Process {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    commands: Vec<PluginCommand>,
+    langs: Vec<Language>,
+}
+
+impl Process {
+    pub fn spawn(name: &str, cmdline: &str) -> Result<Process> {
+        let error = |e| Error::subprocess(cmdline, e);
+        let mut child = crate::shell::command(cmdline)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(error)?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut process = Process {
+            name: name.to_string(),
+            child,
+            stdin,
+            stdout,
+            commands: vec![],
+            langs: vec![],
+        };
+
+        loop {
+            let line = process.read_line()?;
+            let fields: Vec<_> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["ready"] => break,
+                ["command", name, desc] => process.commands.push(PluginCommand {
+                    name: name.to_string(),
+                    description: unescape(desc),
+                    keys: vec![],
+                }),
+                ["key", name, key] => match process.commands.iter_mut().find(|c| c.name == *name) {
+                    Some(cmd) => cmd.keys.push(key.to_string()),
+                    None => return Err(process.protocol_error(&line)),
+                },
+                ["highlight", "plain"] => process.langs.push(Language::Plain),
+                ["highlight", lang] => match Language::from_name(lang) {
+                    Some(lang) => process.langs.push(lang),
+                    None => return Err(process.protocol_error(&line)),
+                },
+                _ => return Err(process.protocol_error(&line)),
+            }
+        }
+        Ok(process)
+    }
+
+    fn protocol_error(&self, line: &str) -> Error {
+        Error::subprocess(&self.name, format!("Unexpected message {:?}", line))
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        let read = self
+            .stdout
+            .read_line(&mut line)
+            .map_err(|e| Error::subprocess(&self.name, e))?;
+        if read == 0 {
+            return Err(Error::subprocess(&self.name, "Plugin process exited"));
+        }
+        let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+        line.truncate(len);
+        Ok(line)
+    }
+
+    fn send(&mut self, message: &str) -> Result<()> {
+        let Process { name, stdin, .. } = self;
+        stdin
+            .write_all(message.as_bytes())
+            .and_then(|_| stdin.flush())
+            .map_err(|e| Error::subprocess(name.as_str(), e))
+    }
+
+    // Read lines until 'done'
+    fn replies(&mut self) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let line = self.read_line()?;
+            if line == "done" {
+                return Ok(lines);
+            }
+            lines.push(line);
+        }
+    }
+}
+
+impl Plugin for Process {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commands(&self) -> Vec<PluginCommand> {
+        self.commands
+            .iter()
+            .map(|c| PluginCommand {
+                name: c.name.clone(),
+                description: c.description.clone(),
+                keys: c.keys.clone(),
+            })
+            .collect()
+    }
+
+    fn run(&mut self, req: &Request<'_>) -> Result<Vec<Action>> {
+        let mut message = format!(
+            "run\t{}\nfile\t{}\nlanguage\t{}\ncursor\t{}\t{}\n",
+            req.command,
+            escape(req.filename),
+            req.lang.name(),
+            req.cursor.0,
+            req.cursor.1,
+        );
+        if let Some(selection) = &req.selection {
+            message.push_str(&format!("selection\t{}\n", escape(selection)));
+        }
+        for line in &req.lines {
+            message.push_str(&format!("line\t{}\n", escape(line)));
+        }
+        message.push_str("end\n");
+        self.send(&message)?;
+
+        let mut actions = vec![];
+        for line in self.replies()? {
+            let fields: Vec<_> = line.split('\t').collect();
+            let action = match fields.as_slice() {
+                ["insert", text] => Action::Insert(unescape(text)),
+                ["message", text] => Action::Message(unescape(text)),
+                ["error", text] => Action::Error(unescape(text)),
+                ["cursor", x, y] => match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => Action::MoveCursor(x, y),
+                    _ => return Err(self.protocol_error(&line)),
+                },
+                ["replace", y, text] => match y.parse() {
+                    Ok(y) => Action::ReplaceLine(y, unescape(text)),
+                    Err(_) => return Err(self.protocol_error(&line)),
+                },
+                _ => return Err(self.protocol_error(&line)),
+            };
+            actions.push(action);
+        }
+        Ok(actions)
+    }
+
+    fn languages(&self) -> Vec<Language> {
+        self.langs.clone()
+    }
+
+    fn highlight(&mut self, lang: Language, lines: &[&str]) -> Result<Vec<Span>> {
+        let mut message = format!("highlight\t{}\n", lang.name());
+        for line in lines {
+            message.push_str(&format!("line\t{}\n", escape(line)));
+        }
+        message.push_str("end\n");
+
+        self.send(&message)?;
+
+        let mut spans = vec![];
+        for line in self.replies()? {
+            let fields: Vec<_> = line.split('\t').collect();
+            let span = match fields.as_slice() {
+                ["span", y, start, end, hl] => {
+                    match (
+                        y.parse(),
+                        start.parse(),
+                        end.parse(),
+                        Highlight::from_name(hl),
+                    ) {
+                        (Ok(y), Ok(start), Ok(end), Some(hl)) => Some(Span { y, start, end, hl }),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            spans.push(span.ok_or_else(|| self.protocol_error(&line))?);
+        }
+        Ok(spans)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Plugin processes keep no state to be saved. They are killed when the editor quits
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Registered plugins and their commands. Command names of plugins must not conflict with builtin
+// commands or commands of other plugins
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+    commands: Vec<(String, String, usize)>, // Name, description and index of the plugin
+    langs: Vec<(Language, usize)>,          // Languages highlighted by each plugin
+}
+
+impl Plugins {
+    // Register the plugin and returns its commands to bind keys
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) -> Result<Vec<PluginCommand>> {
+        let commands = plugin.commands();
+        for cmd in &commands {
+            if Command::from_name(&cmd.name).is_some() || self.find(&cmd.name).is_some() {
+                return Err(Error::other(format!(
+                    "Command '{}' of plugin '{}' is already defined",
+                    cmd.name,
+                    plugin.name(),
+                )));
+            }
+            if let Some(key) = cmd.keys.iter().find(|k| keymap::parse_key(k).is_none()) {
+                return Err(Error::other(format!(
+                    "Invalid key '{}' of command '{}' in plugin '{}'",
+                    key,
+                    cmd.name,
+                    plugin.name(),
+                )));
+            }
+        }
+        let idx = self.plugins.len();
+        self.commands.extend(
+            commands
+                .iter()
+                .map(|c| (c.name.clone(), c.description.clone(), idx)),
+        );
+        self.langs
+            .extend(plugin.languages().into_iter().map(|l| (l, idx)));
+        self.plugins.push(plugin);
+        Ok(commands)
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.commands
+            .iter()
+            .find(|(n, ..)| n == name)
+            .map(|(.., idx)| *idx)
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    // Names and descriptions of all commands of plugins in the order of registration
+    pub fn commands(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.commands
+            .iter()
+            .map(|(name, desc, _)| (name.as_str(), desc.as_str()))
+    }
+
+    // None when no plugin provides the command
+    pub fn run(&mut self, req: &Request<'_>) -> Option<Result<Vec<Action>>> {
+        let idx = self.find(req.command)?;
+        Some(self.plugins[idx].run(req))
+    }
+
+    pub fn highlights(&self, lang: Language) -> bool {
+        self.langs.iter().any(|(l, _)| *l == lang)
+    }
+
+    // Spans of all plugins highlighting the language
+    pub fn highlight(&mut self, lang: Language, lines: &[&str]) -> Result<Vec<Span>> {
+        let mut spans = vec![];
+        for &(l, idx) in &self.langs {
+            if l == lang {
+                spans.extend(self.plugins[idx].highlight(lang, lines)?);
+            }
+        }
+        Ok(spans)
+    }
+}
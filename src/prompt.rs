@@ -0,0 +1,166 @@
+// Helpers for input of prompt. `PromptInput` edits the input line like a tiny Emacs-style line
+// editor and the functions complete the input by Tab key
+use crate::completion::is_word_char;
+use crate::input::{InputSeq, KeySeq};
+use std::fs;
+use std::path::Path;
+
+// Input line of prompt with a cursor in it. The cursor is a byte index in the text
+#[derive(Default)]
+pub struct PromptInput {
+    text: String,
+    cursor: usize,
+}
+
+impl PromptInput {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Replace the whole input such as with a completion or a history entry. The cursor moves to
+    // the end
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    fn prev_char(&self) -> usize {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_char(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    // Start of the word before the cursor skipping non-word characters before it
+    fn prev_word(&self) -> usize {
+        let mut chars = self.text[..self.cursor].char_indices().rev().peekable();
+        while chars.next_if(|(_, c)| !is_word_char(*c)).is_some() {}
+        let mut idx = chars.peek().map_or(0, |(i, _)| *i);
+        while let Some((i, _)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+            idx = i;
+        }
+        idx
+    }
+
+    // End of the word after the cursor skipping non-word characters after it
+    fn next_word(&self) -> usize {
+        let rest = &self.text[self.cursor..];
+        let start = rest.find(is_word_char).unwrap_or(rest.len());
+        let end = rest[start..]
+            .find(|c| !is_word_char(c))
+            .map_or(rest.len(), |i| start + i);
+        self.cursor + end
+    }
+
+    fn delete(&mut self, start: usize, end: usize) -> bool {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        start < end
+    }
+
+    // Edit the input by the key. Returns None when the key is not for editing. Otherwise returns
+    // whether the text was changed. The cursor moves without changing the text on false
+    pub fn edit(&mut self, seq: &InputSeq) -> Option<bool> {
+        use KeySeq::*;
+
+        let prev = self.cursor;
+        match (&seq.key, seq.ctrl, seq.alt) {
+            (LeftKey, false, false) | (Key(b'b'), true, false) => self.cursor = self.prev_char(),
+            (RightKey, false, false) | (Key(b'f'), true, false) => self.cursor = self.next_char(),
+            (LeftKey, false, true) | (Key(b'b'), false, true) => self.cursor = self.prev_word(),
+            (RightKey, false, true) | (Key(b'f'), false, true) => self.cursor = self.next_word(),
+            (HomeKey, ..) | (Key(b'a'), true, false) => self.cursor = 0,
+            (EndKey, ..) | (Key(b'e'), true, false) => self.cursor = self.text.len(),
+            (Key(b'h'), true, false) | (Key(0x7f), false, false) => {
+                return Some(self.delete(self.prev_char(), prev));
+            }
+            (DeleteKey, ..) | (Key(b'd'), true, false) => {
+                return Some(self.delete(prev, self.next_char()));
+            }
+            (Key(b'w'), true, false) | (Key(0x7f), false, true) => {
+                return Some(self.delete(self.prev_word(), prev));
+            }
+            (Key(b'd'), false, true) => return Some(self.delete(prev, self.next_word())),
+            (Key(b'k'), true, false) => return Some(self.delete(prev, self.text.len())),
+            (Key(b'u'), true, false) => return Some(self.delete(0, prev)),
+            (Key(b), false, false) if b.is_ascii() && !b.is_ascii_control() => {
+                self.text.insert(self.cursor, *b as char);
+                self.cursor += 1;
+                return Some(true);
+            }
+            (Utf8Key(c), false, false) => {
+                self.text.insert(self.cursor, *c);
+                self.cursor += c.len_utf8();
+                return Some(true);
+            }
+            _ => return None,
+        }
+        Some(false)
+    }
+}
+
+// Longest common prefix of the candidates which start with the input. The input is returned as-is
+// when no candidate matches
+pub fn completion<S: AsRef<str>>(input: &str, candidates: &[S]) -> String {
+    let mut matched = candidates
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|c| c.starts_with(input));
+    let first = match matched.next() {
+        Some(first) => first,
+        None => return input.to_string(),
+    };
+    let len = matched.fold(first.len(), |len, c| {
+        first[..len]
+            .char_indices()
+            .zip(c.chars())
+            .find(|((_, x), y)| x != y)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| len.min(c.len()))
+    });
+    first[..len].to_string()
+}
+
+// Entries in the directory of the path which start with its file name part. Directories end with
+// '/' so that completing them continues to their entries. Hidden files are listed only when the
+// file name part starts with '.'
+pub fn path_candidates(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(idx) => input.split_at(idx + 1),
+        None => ("", input),
+    };
+    let read = if dir.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(dir)
+    };
+    let entries = match fs::read_dir(read) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut candidates: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            // Follow symbolic links to know the target is a directory
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
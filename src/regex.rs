@@ -0,0 +1,553 @@
+// Small regular expression engine for text search. A pattern is compiled into instructions run by
+// Pike VM, which steps all possible matches in lockstep over characters. Time is proportional to
+// length of text times size of the program and memory is bounded by the size of the program, so
+// no pattern can blow the stack or take exponential time.
+//
+// Supported syntax:
+//   Literal chars, '.', '^', '$', '[abc]', '[^a-z]', '(...)', '|', '*', '+', '?', '{n}', '{n,}', '{n,m}'
+//   Escapes '\d', '\D', '\w', '\W', '\s', '\S' and escaped meta characters such as '\.'
+//
+// Non-greedy quantifiers, backreferences and lookarounds are not supported.
+
+use std::fmt;
+use std::mem;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => c == '_' || c.is_alphanumeric(),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool), // (class, negated)
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(s, e) => s <= c && c <= e,
+            ClassItem::Perl(p, negated) => p.matches(c) != negated,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool), // (items, negated)
+    LineStart,
+    LineEnd,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>), // (node, min, max) Always greedy
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    pattern: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &'static str) -> Result<T, ParseError> {
+        // Offset in bytes is more useful for showing the error position in original pattern
+        let offset = self
+            .pattern
+            .char_indices()
+            .nth(self.pos)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.pattern.len());
+        Err(ParseError { offset, message })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, ParseError> {
+        let mut alts = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alts.push(self.parse_concat()?);
+        }
+        Ok(if alts.len() == 1 {
+            alts.pop().unwrap()
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, ParseError> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, ParseError> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.pos += 1;
+                let min = match self.parse_number() {
+                    Some(n) => n,
+                    None => return self.error("Number is expected in {}"),
+                };
+                let max = if self.eat(',') {
+                    self.parse_number()
+                } else {
+                    Some(min)
+                };
+                if self.peek() != Some('}') {
+                    return self.error("'}' is expected");
+                }
+                if max.map(|m| m < min).unwrap_or(false) {
+                    return self.error("Invalid range in {}");
+                }
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+
+        match atom {
+            Node::LineStart | Node::LineEnd => self.error("Nothing to repeat"),
+            Node::Repeat(..) => self.error("Multiple repeat"),
+            atom => Ok(Node::Repeat(Box::new(atom), min, max)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<ClassItem, ParseError> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return self.error("Pattern must not end with '\\'"),
+        };
+        self.pos += 1;
+        Ok(match c {
+            'd' => ClassItem::Perl(Perl::Digit, false),
+            'D' => ClassItem::Perl(Perl::Digit, true),
+            'w' => ClassItem::Perl(Perl::Word, false),
+            'W' => ClassItem::Perl(Perl::Word, true),
+            's' => ClassItem::Perl(Perl::Space, false),
+            'S' => ClassItem::Perl(Perl::Space, true),
+            't' => ClassItem::Range('\t', '\t'),
+            c => ClassItem::Range(c, c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, ParseError> {
+        let negated = self.eat('^');
+        let mut items = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(']') if !first => {
+                    self.pos += 1;
+                    return Ok(Node::Class(items, negated));
+                }
+                Some(c) => c,
+                None => return self.error("Character class is not closed"),
+            };
+            self.pos += 1;
+            first = false;
+
+            let start = if c == '\\' {
+                match self.parse_escape()? {
+                    ClassItem::Range(c, _) => c,
+                    item => {
+                        items.push(item);
+                        continue;
+                    }
+                }
+            } else {
+                c
+            };
+
+            // Note that '-' at the end of class is a literal like [a-]
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                let end = match self.peek() {
+                    Some('\\') => {
+                        self.pos += 1;
+                        match self.parse_escape()? {
+                            ClassItem::Range(c, _) => c,
+                            _ => return self.error("Invalid range in character class"),
+                        }
+                    }
+                    Some(c) => {
+                        self.pos += 1;
+                        c
+                    }
+                    None => return self.error("Character class is not closed"),
+                };
+                if end < start {
+                    return self.error("Invalid range in character class");
+                }
+                items.push(ClassItem::Range(start, end));
+            } else {
+                items.push(ClassItem::Range(start, start));
+            }
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, ParseError> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::LineStart),
+            '$' => Ok(Node::LineEnd),
+            '[' => self.parse_class(),
+            '(' => {
+                let node = self.parse_alt()?;
+                if !self.eat(')') {
+                    return self.error("Group is not closed");
+                }
+                Ok(node)
+            }
+            '*' | '+' | '?' | '{' => {
+                self.pos -= 1;
+                self.error("Nothing to repeat")
+            }
+            '\\' => match self.parse_escape()? {
+                ClassItem::Range(c, _) => Ok(Node::Char(c)),
+                item => Ok(Node::Class(vec![item], false)),
+            },
+            c => Ok(Node::Char(c)),
+        }
+    }
+}
+
+// Too large program makes each step slow. It can be made by nested counted repeats like
+// '(a{100}b){100}'
+const MAX_INSTS: usize = 10_000;
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool), // (items, negated)
+    LineStart,
+    LineEnd,
+    Split(usize, usize), // Threads of the first branch are preferred
+    Jump(usize),
+    Match,
+}
+
+#[derive(Default)]
+struct Compiler {
+    insts: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, &'static str> {
+        if self.insts.len() >= MAX_INSTS {
+            return Err("Pattern is too complex");
+        }
+        self.insts.push(inst);
+        Ok(self.insts.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), &'static str> {
+        match node {
+            Node::Char(c) => self.emit(Inst::Char(*c)).map(|_| ()),
+            Node::Any => self.emit(Inst::Any).map(|_| ()),
+            Node::Class(items, negated) => {
+                self.emit(Inst::Class(items.clone(), *negated)).map(|_| ())
+            }
+            Node::LineStart => self.emit(Inst::LineStart).map(|_| ()),
+            Node::LineEnd => self.emit(Inst::LineEnd).map(|_| ()),
+            Node::Concat(nodes) => nodes.iter().try_for_each(|n| self.compile(n)),
+            Node::Alt(alts) => {
+                let (last, init) = alts.split_last().unwrap();
+                let mut jumps = vec![];
+                for alt in init {
+                    let split = self.emit(Inst::Split(0, 0))?;
+                    self.compile(alt)?;
+                    jumps.push(self.emit(Inst::Jump(0))?);
+                    self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                }
+                self.compile(last)?;
+                let end = self.insts.len();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jump(end);
+                }
+                Ok(())
+            }
+            Node::Repeat(node, min, max) => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                if let Some(max) = max {
+                    // 'a{1,3}' is compiled as 'a(a(a)?)?'
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(self.emit(Inst::Split(0, 0))?);
+                        self.compile(node)?;
+                    }
+                    let end = self.insts.len();
+                    for split in splits {
+                        self.insts[split] = Inst::Split(split + 1, end);
+                    }
+                } else {
+                    let split = self.emit(Inst::Split(0, 0))?;
+                    self.compile(node)?;
+                    self.emit(Inst::Jump(split))?;
+                    self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Threads of Pike VM in priority order. Each thread is a pair of its program counter and the start
+// position of its match
+struct Threads {
+    list: Vec<(usize, usize)>,
+    added: Vec<usize>, // Text position where each instruction was added last. Duplicates are dropped
+    stack: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            list: Vec::with_capacity(size),
+            added: vec![usize::MAX; size],
+            stack: vec![],
+        }
+    }
+}
+
+// Strings one of which is contained in any match of the node. None when it is unknown. Longer
+// strings are preferred since they reject more text
+fn required_literals(node: &Node) -> Option<Vec<String>> {
+    match node {
+        Node::Char(c) => Some(vec![c.to_string()]),
+        Node::Concat(nodes) => {
+            let mut candidates = vec![];
+            let mut run = String::new();
+            for node in nodes {
+                if let Node::Char(c) = node {
+                    run.push(*c);
+                    continue;
+                }
+                if !run.is_empty() {
+                    candidates.push(vec![mem::take(&mut run)]);
+                }
+                candidates.extend(required_literals(node));
+            }
+            if !run.is_empty() {
+                candidates.push(vec![run]);
+            }
+            candidates
+                .into_iter()
+                .max_by_key(|lits| lits.iter().map(String::len).min().unwrap_or(0))
+        }
+        Node::Alt(alts) => {
+            let mut lits = vec![];
+            for alt in alts {
+                lits.extend(required_literals(alt)?);
+            }
+            Some(lits)
+        }
+        Node::Repeat(node, min, _) if *min > 0 => required_literals(node),
+        _ => None,
+    }
+}
+
+pub struct Regex {
+    insts: Vec<Inst>,
+    // Text which contains none of them never matches. Checking them is much faster than running
+    // the matcher
+    literals: Option<Vec<String>>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, ParseError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            pattern,
+        };
+        let node = parser.parse_alt()?;
+        if parser.pos < parser.chars.len() {
+            // Only unmatched ')' can remain here
+            return parser.error("Unmatched ')'");
+        }
+        let literals = required_literals(&node);
+        let mut compiler = Compiler::default();
+        let compiled = compiler
+            .compile(&node)
+            .and_then(|_| compiler.emit(Inst::Match));
+        if let Err(message) = compiled {
+            let offset = pattern.len();
+            return Err(ParseError { offset, message });
+        }
+        Ok(Regex {
+            insts: compiler.insts,
+            literals,
+        })
+    }
+
+    fn may_match(&self, text: &str) -> bool {
+        match &self.literals {
+            Some(lits) => lits.iter().any(|l| text.contains(l.as_str())),
+            None => true,
+        }
+    }
+
+    // Add a thread at `pc` following jumps and zero-width assertions at `pos`. Threads are added
+    // in priority order of the branches
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        start: usize,
+        chars: &[char],
+        pos: usize,
+    ) {
+        let mut stack = mem::take(&mut threads.stack);
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if threads.added[pc] == pos {
+                continue;
+            }
+            threads.added[pc] = pos;
+            match self.insts[pc] {
+                Inst::Jump(to) => stack.push(to),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::LineStart if pos == 0 => stack.push(pc + 1),
+                Inst::LineEnd if pos == chars.len() => stack.push(pc + 1),
+                Inst::LineStart | Inst::LineEnd => {}
+                _ => threads.list.push((pc, start)),
+            }
+        }
+        threads.stack = stack;
+    }
+
+    fn find_in_chars(&self, chars: &[char], from: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let mut found = None;
+        let mut pos = from;
+        while pos <= chars.len() {
+            // New thread starting here has the lowest priority so that the leftmost match wins
+            if found.is_none() {
+                self.add_thread(&mut current, 0, pos, chars, pos);
+            }
+            if current.list.is_empty() {
+                break;
+            }
+            for i in 0..current.list.len() {
+                let (pc, start) = current.list[i];
+                let matched = match &self.insts[pc] {
+                    Inst::Char(c) => chars.get(pos) == Some(c),
+                    Inst::Any => pos < chars.len(),
+                    Inst::Class(items, negated) => match chars.get(pos) {
+                        Some(&c) => items.iter().any(|i| i.matches(c)) != *negated,
+                        None => false,
+                    },
+                    Inst::Match => {
+                        // Threads after this one have lower priority
+                        found = Some((start, pos));
+                        break;
+                    }
+                    _ => unreachable!(),
+                };
+                if matched {
+                    self.add_thread(&mut next, pc + 1, start, chars, pos + 1);
+                }
+            }
+            current.list.clear();
+            mem::swap(&mut current, &mut next);
+            pos += 1;
+        }
+        found
+    }
+
+    // Find the leftmost match starting at or after `from` character index. Returned range is start
+    // and end indices of characters (not bytes) so that they can be used as x coordinates of Row.
+    pub fn find_at(&self, text: &str, from: usize) -> Option<(usize, usize)> {
+        if !self.may_match(text) {
+            return None;
+        }
+        let chars: Vec<char> = text.chars().collect();
+        self.find_in_chars(&chars, from)
+    }
+
+    // All non-overlapping matches from left to right. Empty match is skipped
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut found = vec![];
+        if !self.may_match(text) {
+            return found;
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let mut from = 0;
+        while let Some((start, end)) = self.find_in_chars(&chars, from) {
+            if start < end {
+                found.push((start, end));
+                from = end;
+            } else {
+                from = start + 1;
+            }
+        }
+        found
+    }
+}
@@ -0,0 +1,303 @@
+// Remote control of a running editor over a Unix domain socket. `kiro --listen` accepts connections
+// on the socket and handles requests from other processes in its main loop. `kiro --remote` is a
+// client which opens files in the running editor instead of starting a new one, and
+// `kiro --remote-wait` returns after the files are saved so that it works as $EDITOR of tools such
+// as git.
+//
+// Each message is a JSON object in one line. A client sends requests and receives one response for
+// each request in order:
+//
+//   {"command":"open","files":[{"path":"/src/main.rs","line":10}],"wait":false}
+//   {"command":"run","name":"save"}        Run a command of the editor or of a plugin
+//   {"command":"text","buffer":"main.rs"}  Text of the buffer. The current buffer without "buffer"
+//
+//   {"ok":true}
+//   {"ok":true,"text":"fn main() {}"}
+//   {"ok":false,"error":"Unknown command 'foo'"}
+//
+// With "wait":true, the response to 'open' is sent after all the files are saved or the editor quits.
+
+use crate::error::{Context, Error, Result};
+use crate::json::Json;
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(unix)]
+use std::thread;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Request {
+    Open {
+        files: Vec<(String, Option<usize>)>, // Paths with 1-based line numbers
+        wait: bool,
+    },
+    Run(String),
+    Text(Option<String>),
+}
+
+impl Request {
+    fn from_json(json: &Json) -> std::result::Result<Request, String> {
+        let field = |name: &str| json.get(name).filter(|v| **v != Json::Null);
+        let command = field("command").and_then(Json::as_str);
+        match command {
+            Some("open") => {
+                let mut files = vec![];
+                if let Some(Json::Array(elems)) = field("files") {
+                    for elem in elems {
+                        let path = elem.get("path").and_then(Json::as_str);
+                        let path = path.ok_or("\"path\" of file must be a string")?;
+                        let line = match elem.get("line") {
+                            None | Some(Json::Null) => None,
+                            Some(line) => {
+                                Some(line.as_usize().ok_or("\"line\" must be an integer")?)
+                            }
+                        };
+                        files.push((path.to_string(), line));
+                    }
+                }
+                if files.is_empty() {
+                    return Err("\"files\" must be a non-empty array".to_string());
+                }
+                let wait = field("wait").and_then(Json::as_bool).unwrap_or(false);
+                Ok(Request::Open { files, wait })
+            }
+            Some("run") => match field("name").and_then(Json::as_str) {
+                Some(name) => Ok(Request::Run(name.to_string())),
+                None => Err("\"name\" of command must be a string".to_string()),
+            },
+            Some("text") => match field("buffer") {
+                None => Ok(Request::Text(None)),
+                Some(Json::String(name)) => Ok(Request::Text(Some(name.clone()))),
+                Some(_) => Err("\"buffer\" must be a string".to_string()),
+            },
+            Some(cmd) => Err(format!("Unknown command '{}'", cmd)),
+            None => Err("\"command\" must be a string".to_string()),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let command = |name: &str| ("command".to_string(), Json::String(name.to_string()));
+        match self {
+            Request::Open { files, wait } => {
+                let files = files.iter().map(|(path, line)| {
+                    let mut file = vec![("path".to_string(), Json::String(path.clone()))];
+                    if let Some(line) = line {
+                        file.push(("line".to_string(), Json::Number(*line as f64)));
+                    }
+                    Json::Object(file)
+                });
+                Json::Object(vec![
+                    command("open"),
+                    ("files".to_string(), Json::Array(files.collect())),
+                    ("wait".to_string(), Json::Bool(*wait)),
+                ])
+            }
+            Request::Run(name) => Json::Object(vec![
+                command("run"),
+                ("name".to_string(), Json::String(name.clone())),
+            ]),
+            Request::Text(None) => Json::Object(vec![command("text")]),
+            Request::Text(Some(name)) => Json::Object(vec![
+                command("text"),
+                ("buffer".to_string(), Json::String(name.clone())),
+            ]),
+        }
+    }
+}
+
+// Text returned to the client, or an error message
+pub type Response = std::result::Result<Option<String>, String>;
+
+fn response_to_json(response: &Response) -> Json {
+    let ok = |b| ("ok".to_string(), Json::Bool(b));
+    match response {
+        Ok(None) => Json::Object(vec![ok(true)]),
+        Ok(Some(text)) => Json::Object(vec![
+            ok(true),
+            ("text".to_string(), Json::String(text.clone())),
+        ]),
+        Err(msg) => Json::Object(vec![
+            ok(false),
+            ("error".to_string(), Json::String(msg.clone())),
+        ]),
+    }
+}
+
+fn response_from_json(json: &Json) -> Response {
+    let text = json.get("text").and_then(Json::as_str).map(str::to_string);
+    match json.get("ok") {
+        Some(Json::Bool(true)) => Ok(text),
+        _ => Err(json
+            .get("error")
+            .and_then(Json::as_str)
+            .unwrap_or("Invalid response")
+            .to_string()),
+    }
+}
+
+// Request received from a client. The client waits until the editor replies to it
+pub struct Call {
+    pub request: Request,
+    sender: Sender<Response>,
+}
+
+impl Call {
+    pub fn reply(self, response: Response) {
+        // Sending fails when the client has disconnected
+        let _ = self.sender.send(response);
+    }
+}
+
+// Path of the socket. $KIRO_SOCKET or kiro-$USER.sock in $XDG_RUNTIME_DIR or the temporary directory
+pub fn default_socket() -> PathBuf {
+    if let Some(path) = env::var_os("KIRO_SOCKET") {
+        return PathBuf::from(path);
+    }
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let user = env::var("USER").unwrap_or_default();
+    dir.join(format!("kiro-{}.sock", user))
+}
+
+// Argument of --remote such as 'src/main.rs:10' split into the path and the line number
+pub fn split_location(arg: &str) -> (&str, Option<usize>) {
+    match arg.rsplit_once(':') {
+        Some((path, line)) if !path.is_empty() => match line.parse() {
+            Ok(line) => (path, Some(line)),
+            Err(_) => (arg, None),
+        },
+        _ => (arg, None),
+    }
+}
+
+// The path is made absolute since the editor may run in another directory
+fn absolute_location(arg: &str) -> (String, Option<usize>) {
+    let (path, line) = split_location(arg);
+    let path = match env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => PathBuf::from(path),
+    };
+    (path.to_string_lossy().into_owned(), line)
+}
+
+// The socket file is removed when the editor stops listening
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Handle requests of one client until it disconnects
+#[cfg(unix)]
+fn serve(stream: UnixStream, calls: Sender<Call>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match Json::parse(&line).and_then(|json| Request::from_json(&json)) {
+            Ok(request) => {
+                let (sender, receiver) = mpsc::channel();
+                if calls.send(Call { request, sender }).is_err() {
+                    return Ok(()); // The editor already quit
+                }
+                receiver
+                    .recv()
+                    .unwrap_or_else(|_| Err("Editor quit before replying".to_string()))
+            }
+            Err(msg) => Err(format!("Invalid request: {}", msg)),
+        };
+        writeln!(writer, "{}", response_to_json(&response))?;
+    }
+    Ok(())
+}
+
+// Start accepting clients on the socket. Requests are received from the returned receiver
+#[cfg(unix)]
+pub fn listen(path: &Path) -> Result<(Server, Receiver<Call>)> {
+    // A socket file left by a crashed editor is reused, but another running editor is not taken over
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            let msg = format!("Other editor is listening on {}", path.display());
+            return Err(Error::other(msg));
+        }
+        fs::remove_file(path).context("removing", path)?;
+    }
+    let listener = UnixListener::bind(path).context("listening on", path)?;
+    let server = Server {
+        path: path.to_path_buf(),
+    };
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || serve(stream, sender));
+        }
+    });
+    Ok((server, receiver))
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: &Path) -> Result<(Server, Receiver<Call>)> {
+    Err(Error::other(
+        "Listening on a socket is only supported on Unix",
+    ))
+}
+
+// Send the request to the editor listening on the socket and wait for its response. None when no
+// editor is listening
+#[cfg(unix)]
+pub fn send(socket: &Path, request: &Request) -> Result<Option<Response>> {
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err).context("connecting to", socket),
+    };
+    writeln!(stream, "{}", request.to_json()).context("writing to", socket)?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("reading", socket)?;
+    match Json::parse(&line) {
+        Ok(json) => Ok(Some(response_from_json(&json))),
+        Err(msg) => Err(Error::other(format!(
+            "Invalid response from {}: {}",
+            socket.display(),
+            msg
+        ))),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn send(_socket: &Path, _request: &Request) -> Result<Option<Response>> {
+    Ok(None)
+}
+
+// Open files in the editor listening on the socket. Returns false when no editor is listening so
+// that the caller starts a new editor instead
+pub fn open_files(socket: &Path, files: &[String], wait: bool) -> Result<bool> {
+    let files = files.iter().map(|f| absolute_location(f)).collect();
+    match send(socket, &Request::Open { files, wait })? {
+        Some(Ok(_)) => Ok(true),
+        Some(Err(msg)) => Err(Error::other(msg)),
+        None => Ok(false),
+    }
+}
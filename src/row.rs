@@ -1,28 +1,162 @@
+use crate::grapheme;
+use crate::mmap::Mmap;
+use std::cmp;
+use std::iter;
 use std::ops;
-use unicode_width::UnicodeWidthChar;
+use std::str;
+use std::sync::Arc;
 
-const TAB_STOP: usize = 8;
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+// Control characters other than tab are displayed in caret notation like `^A` for C0 and DEL, and
+// in hex like `<85>` for C1, which appears when bytes invalid as UTF-8 are decoded as Latin-1.
+// Writing them to the terminal as-is would break its state. Returns the characters and the width
+fn control_notation(ch: char) -> Option<([char; 4], usize)> {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let code = ch as u32;
+    match code {
+        0x00..=0x08 | 0x0a..=0x1f => Some((['^', char::from(code as u8 + 0x40), ' ', ' '], 2)),
+        0x7f => Some((['^', '?', ' ', ' '], 2)),
+        0x80..=0x9f => {
+            let hex = |n: u32| char::from(HEX[n as usize]);
+            Some((['<', hex(code >> 4), hex(code & 0xf), '>'], 4))
+        }
+        _ => None,
+    }
+}
+
+// Lines longer than this are copied from the map. A range of mapped line overwritten with non-ASCII
+// bytes by other process reads as spaces of the same length from this buffer until the file is
+// loaded again
+const MAX_MAPPED_LEN: usize = 1024;
+static BLANK: [u8; MAX_MAPPED_LEN] = [b' '; MAX_MAPPED_LEN];
+
+// Text of a row. A line in memory-mapped file is referred without copying until it is edited
+enum Text {
+    Owned(String),
+    Mapped(Arc<Mmap>, usize, usize), // Byte range of ASCII line in the map
+}
+
+impl Text {
+    // Text in the byte range. Mapped bytes are checked again since the file may be overwritten,
+    // but only in the range so that accessing a character does not scan the whole line
+    fn slice(&self, start: usize, end: usize) -> &str {
+        match self {
+            Text::Owned(s) => &s[start..end],
+            Text::Mapped(map, offset, line_end) => {
+                let bytes = &map.bytes()[*offset..*line_end][start..end];
+                let bytes = if bytes.is_ascii() {
+                    bytes
+                } else {
+                    &BLANK[..bytes.len()]
+                };
+                str::from_utf8(bytes).unwrap_or_default()
+            }
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        self.slice(0, self.len())
+    }
+
+    // A mapped line was validated as ASCII on creating the row and its length never changes
+    fn len(&self) -> usize {
+        match self {
+            Text::Owned(s) => s.len(),
+            Text::Mapped(_, start, end) => end - start,
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct Row {
-    buf: String,
-    render: String,
+    buf: Text,
     // Cache of byte indices of characters in `buf`. This will be empty when `buf` only contains
     // single byte characters not to allocate memory.
     indices: Vec<usize>,
+    // Tab character is rendered as spaces until next multiple of this width
+    tab_width: usize,
 }
 
 impl Row {
-    pub fn new<S: Into<String>>(line: S) -> Row {
+    pub fn new<S: Into<String>>(line: S, tab_width: usize) -> Row {
+        let mut row = Row {
+            buf: Text::Owned(line.into()),
+            indices: Vec::with_capacity(0),
+            tab_width,
+        };
+        row.update_indices();
+        row
+    }
+
+    // Row referring to the line at the byte range in the map. Only a short ASCII line is referred
+    // so that indices of characters are never computed from the map, which other process may
+    // change. Other lines are copied and their invalid UTF-8 sequences are replaced with U+FFFD
+    pub fn mapped(map: &Arc<Mmap>, start: usize, end: usize, tab_width: usize) -> Row {
+        let bytes = &map.bytes()[start..end];
+        let buf = if bytes.len() <= MAX_MAPPED_LEN && bytes.is_ascii() {
+            Text::Mapped(map.clone(), start, end)
+        } else {
+            Text::Owned(String::from_utf8_lossy(bytes).into_owned())
+        };
         let mut row = Row {
-            buf: line.into(),
-            render: "".to_string(),
+            buf,
             indices: Vec::with_capacity(0),
+            tab_width,
         };
-        row.update_render();
+        row.update_indices();
         row
     }
 
+    // Copy the line from the map so that the row no longer refers to it
+    pub fn copy(&mut self) {
+        self.owned();
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.buf, Text::Mapped(..))
+    }
+
+    // Line to be edited. A line in the map is copied on the first edit
+    fn owned(&mut self) -> &mut String {
+        if let Text::Mapped(..) = self.buf {
+            self.buf = Text::Owned(self.buf.as_str().to_string());
+        }
+        match &mut self.buf {
+            Text::Owned(s) => s,
+            Text::Mapped(..) => unreachable!(),
+        }
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+    }
+
+    // Display width of the grapheme cluster at the display position `rx`
+    fn cluster_width(&self, cluster: &str, rx: usize) -> usize {
+        match cluster.chars().next() {
+            // Proceed to the next tab stop
+            Some('\t') => self.tab_width - (rx % self.tab_width),
+            Some(ch) => match control_notation(ch) {
+                Some((_, width)) => width,
+                None => grapheme::width(cluster),
+            },
+            None => 0,
+        }
+    }
+
+    // Each character and its display width. The first character of a grapheme cluster has the
+    // width of the whole cluster and the rest have zero width
+    fn char_widths(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        let mut rx = 0;
+        grapheme::graphemes(self.buf.as_str()).flat_map(move |cluster| {
+            let width = self.cluster_width(cluster, rx);
+            rx += width;
+            let mut chars = cluster.chars();
+            let first = chars.next().map(|c| (c, width));
+            first.into_iter().chain(chars.map(|c| (c, 0)))
+        })
+    }
+
     // Returns number of characters
     pub fn len(&self) -> usize {
         if self.indices.is_empty() {
@@ -32,6 +166,10 @@ impl Row {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.buf.len() == 0
+    }
+
     fn byte_idx_of(&self, char_idx: usize) -> usize {
         let len = self.indices.len();
         if len == 0 {
@@ -57,8 +195,27 @@ impl Row {
         self.buf.as_str()
     }
 
-    pub fn render_text(&self) -> &str {
-        self.render.as_str()
+    // Characters to display the row. The display text is not cached but computed on drawing since
+    // only visible rows need it. Tabs are expanded to spaces until the next tab stop and control
+    // characters are replaced with their notations. Each item is index of the character in the
+    // buffer which it comes from, a display character and its width. Characters following the
+    // first one of a grapheme cluster have zero width
+    pub fn render_chars(&self) -> impl Iterator<Item = (usize, char, usize)> + '_ {
+        self.char_widths()
+            .enumerate()
+            .flat_map(move |(cx, (ch, width))| {
+                // A display character, its width and count of repeating it, and the notation
+                let ((c, w, count), (notation, len)) = match (ch, control_notation(ch)) {
+                    ('\t', _) => ((' ', 1, width), ([' '; 4], 0)),
+                    (_, Some(notation)) => ((ch, 0, 0), notation),
+                    _ => ((ch, width, 1), ([' '; 4], 0)),
+                };
+                iter::repeat_n((cx, c, w), count).chain(
+                    IntoIterator::into_iter(notation)
+                        .take(len)
+                        .map(move |c| (cx, c, 1)),
+                )
+            })
     }
 
     pub fn char_at(&self, at: usize) -> char {
@@ -66,37 +223,21 @@ impl Row {
     }
 
     pub fn char_at_checked(&self, at: usize) -> Option<char> {
-        self[at..].chars().next()
-    }
-
-    fn update_render(&mut self) {
-        self.render = String::with_capacity(self.buf.len());
-        let mut index = 0;
-        let mut num_chars = 0;
-
-        for c in self.buf.chars() {
-            if c == '\t' {
-                loop {
-                    self.render.push(' ');
-                    index += 1;
-                    if index % TAB_STOP == 0 {
-                        break;
-                    }
-                }
-            } else {
-                self.render.push(c);
-                index += c.width_cjk().unwrap();
-            }
-            num_chars += 1;
+        if at >= self.len() {
+            return None;
         }
+        self[at..at + 1].chars().next()
+    }
 
+    fn update_indices(&mut self) {
+        let num_chars = self.buf.as_str().chars().count();
         self.indices = if num_chars == self.buf.len() {
             // If number of chars is the same as byte length, this line includes no multi-byte char
             Vec::with_capacity(0)
         } else {
             let mut v = Vec::with_capacity(num_chars);
             let mut idx = 0;
-            for c in self.buf.chars() {
+            for c in self.buf.as_str().chars() {
                 v.push(idx);
                 idx += c.len_utf8();
             }
@@ -105,39 +246,95 @@ impl Row {
     }
 
     pub fn rx_from_cx(&self, cx: usize) -> usize {
-        self[..cx].chars().fold(0, |rx, ch| {
-            if ch == '\t' {
-                // Proceed TAB_STOP spaces then subtract spaces by mod TAB_STOP
-                rx + TAB_STOP - (rx % TAB_STOP)
-            } else {
-                rx + ch.width_cjk().unwrap()
+        self.char_widths().take(cx).map(|(_, w)| w).sum()
+    }
+
+    // Inverse of rx_from_cx(). When rx points the middle of a wide character, tab or grapheme
+    // cluster, it returns index of the first character of it
+    pub fn cx_from_rx(&self, rx: usize) -> usize {
+        let mut current_rx = 0;
+        for (cx, (_, width)) in self.char_widths().enumerate() {
+            current_rx += width;
+            if current_rx > rx {
+                return cx;
             }
-        })
+        }
+        self.len()
+    }
+
+    // Index of the start of the grapheme cluster following the one at `cx`
+    pub fn next_boundary(&self, cx: usize) -> usize {
+        if self.indices.is_empty() {
+            return cmp::min(cx + 1, self.len()); // ASCII characters are never combined
+        }
+        let mut start = 0;
+        for cluster in grapheme::graphemes(self.buf.as_str()) {
+            start += cluster.chars().count();
+            if start > cx {
+                return start;
+            }
+        }
+        self.len()
     }
 
+    // Index of the start of the grapheme cluster preceding `cx`
+    pub fn prev_boundary(&self, cx: usize) -> usize {
+        if self.indices.is_empty() {
+            return cx.saturating_sub(1);
+        }
+        let mut start = 0;
+        for cluster in grapheme::graphemes(self.buf.as_str()) {
+            let end = start + cluster.chars().count();
+            if end >= cx {
+                return start;
+            }
+            start = end;
+        }
+        start
+    }
+
+    // Editing one character patches the indices instead of building them again. Typing on a long
+    // line does not decode the whole line
     pub fn insert_char(&mut self, at: usize, c: char) {
-        if self.len() <= at {
-            self.buf.push(c);
-        } else {
-            self.buf.insert(self.byte_idx_of(at), c);
+        let at = cmp::min(at, self.len());
+        let idx = self.byte_idx_of(at);
+        self.owned().insert(idx, c);
+        if !self.indices.is_empty() {
+            self.indices.insert(at, idx);
+            for i in self.indices[at + 1..].iter_mut() {
+                *i += c.len_utf8();
+            }
+        } else if !c.is_ascii() {
+            self.update_indices(); // First multi-byte character in the line
         }
-        // TODO: More efficient update for self.render
-        self.update_render();
     }
 
     pub fn insert_str<S: AsRef<str>>(&mut self, at: usize, s: S) {
         if self.len() <= at {
-            self.buf.push_str(s.as_ref());
+            self.owned().push_str(s.as_ref());
         } else {
-            self.buf.insert_str(self.byte_idx_of(at), s.as_ref());
+            let idx = self.byte_idx_of(at);
+            self.owned().insert_str(idx, s.as_ref());
         }
-        self.update_render();
+        self.update_indices();
     }
 
     pub fn delete_char(&mut self, at: usize) {
-        if at < self.len() {
-            self.buf.remove(self.byte_idx_of(at));
-            self.update_render();
+        if at >= self.len() {
+            return;
+        }
+        let idx = self.byte_idx_of(at);
+        let c = self.owned().remove(idx);
+        if self.indices.is_empty() {
+            return;
+        }
+        self.indices.remove(at);
+        if self.indices.len() == self.buf.len() {
+            self.indices = Vec::with_capacity(0); // No multi-byte character remains
+        } else {
+            for i in self.indices[at..].iter_mut() {
+                *i -= c.len_utf8();
+            }
         }
     }
 
@@ -146,23 +343,26 @@ impl Row {
         if s.is_empty() {
             return;
         }
-        self.buf.push_str(s);
-        self.update_render();
+        self.owned().push_str(s);
+        self.update_indices();
     }
 
     pub fn truncate(&mut self, at: usize) {
         if at < self.len() {
-            self.buf.truncate(self.byte_idx_of(at));
-            self.update_render();
+            let idx = self.byte_idx_of(at);
+            self.owned().truncate(idx);
+            self.update_indices();
         }
     }
 
+    // Removes characters in the range. The end beyond the row is clamped to the end of the row
     pub fn remove(&mut self, start: usize, end: usize) {
+        let end = cmp::min(end, self.len());
         if start < end {
             let start_idx = self.byte_idx_of(start);
             let end_idx = self.byte_idx_of(end);
-            self.buf.drain(start_idx..end_idx);
-            self.update_render();
+            self.owned().drain(start_idx..end_idx);
+            self.update_indices();
         }
     }
 }
@@ -173,7 +373,7 @@ impl ops::Index<ops::Range<usize>> for Row {
     fn index(&self, r: ops::Range<usize>) -> &Self::Output {
         let start = self.byte_idx_of(r.start);
         let end = self.byte_idx_of(r.end);
-        &self.buf[start..end]
+        self.buf.slice(start, end)
     }
 }
 
@@ -182,7 +382,7 @@ impl ops::Index<ops::RangeFrom<usize>> for Row {
 
     fn index(&self, r: ops::RangeFrom<usize>) -> &Self::Output {
         let start = self.byte_idx_of(r.start);
-        &self.buf[start..]
+        self.buf.slice(start, self.buf.len())
     }
 }
 
@@ -191,7 +391,7 @@ impl ops::Index<ops::RangeTo<usize>> for Row {
 
     fn index(&self, r: ops::RangeTo<usize>) -> &Self::Output {
         let end = self.byte_idx_of(r.end);
-        &self.buf[..end]
+        self.buf.slice(0, end)
     }
 }
 
@@ -201,7 +401,7 @@ impl ops::Index<ops::RangeInclusive<usize>> for Row {
     fn index(&self, r: ops::RangeInclusive<usize>) -> &Self::Output {
         let start = self.byte_idx_of(*r.start());
         let end = self.byte_idx_of(*r.end());
-        &self.buf[start..=end]
+        self.buf.slice(start, end + 1)
     }
 }
 
@@ -210,6 +410,6 @@ impl ops::Index<ops::RangeToInclusive<usize>> for Row {
 
     fn index(&self, r: ops::RangeToInclusive<usize>) -> &Self::Output {
         let end = self.byte_idx_of(r.end);
-        &self.buf[..=end]
+        self.buf.slice(0, end + 1)
     }
 }
@@ -1,55 +1,96 @@
-use crate::ansi_color::{AnsiColor, ColorSupport};
+use crate::ansi_color::{AnsiColor, Background, ColorSupport, Palette};
+use crate::clipboard;
+use crate::clock::Instant;
+use crate::conflict::Part;
+use crate::diff::{self, Change, Hunk};
+use crate::error::Result;
+use crate::fold::Folds;
+use crate::gap_buffer::GapBuffer;
+use crate::grapheme::{self, AmbiguousWidth};
+use crate::grid::{Color, Grid};
 use crate::highlight::Highlighting;
 use crate::input::{InputSeq, KeySeq};
+use crate::language::Language;
+use crate::message_log::{MessageLevel, MessageLog};
 use crate::row::Row;
-use crate::signal::SigwinchWatcher;
 use crate::status_bar::StatusBar;
 use crate::text_buffer::TextBuffer;
+use crate::theme::Theme;
+use crate::vcs::Mark;
 use std::cmp;
 use std::io::{self, Write};
-use std::time::SystemTime;
-use unicode_width::UnicodeWidthChar;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const HELP: &str = "\
-    Ctrl-Q                        : Quit
-    Ctrl-S                        : Save to file
-    Ctrl-O                        : Open text buffer
-    Ctrl-X                        : Next text buffer
-    Alt-X                         : Previous text buffer
-    Ctrl-P or UP                  : Move cursor up
-    Ctrl-N or DOWN                : Move cursor down
-    Ctrl-F or RIGHT               : Move cursor right
-    Ctrl-B or LEFT                : Move cursor left
-    Ctrl-A or Alt-LEFT or HOME    : Move cursor to head of line
-    Ctrl-E or Alt-RIGHT or END    : Move cursor to end of line
-    Ctrl-[ or Ctrl-V or PAGE DOWN : Next page
-    Ctrl-] or Alt-V or PAGE UP    : Previous page
-    Alt-F or Ctrl-RIGHT           : Move cursor to next word
-    Alt-B or Ctrl-LEFT            : Move cursor to previous word
-    Alt-N or Ctrl-DOWN            : Move cursor to next paragraph
-    Alt-P or Ctrl-UP              : Move cursor to previous paragraph
-    Alt-<                         : Move cursor to top of file
-    Alt->                         : Move cursor to bottom of file
-    Ctrl-H or BACKSPACE           : Delete character
-    Ctrl-D or DELETE              : Delete next character
-    Ctrl-W                        : Delete a word
-    Ctrl-J                        : Delete until head of line
-    Ctrl-K                        : Delete until end of line
-    Ctrl-G                        : Search text
-    Ctrl-M                        : New line
-    Ctrl-L                        : Refresh screen
-    Ctrl-?                        : Show this help";
+
+// Save the cursor and enter alternate screen buffer to restore contents of the shell on quit. And
+// enable mouse reporting on button press and wheel with SGR extended coordinates
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-The-Alternate-Screen-Buffer
+// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+const ENTER_SEQUENCE: &[u8] = b"\x1b[?1049h\x1b[?1000h\x1b[?1006h";
+// Disable mouse reporting, reset colors, show the cursor and back to normal screen buffer, which
+// restores the cursor. The cursor is moved to the bottom before that for terminals which have no
+// alternate screen buffer so that the shell prompt does not overwrite the last frame
+const LEAVE_SEQUENCE: &[u8] = b"\x1b[?1006l\x1b[?1000l\x1b[0m\x1b[?25h\x1b[9999B\r\x1b[?1049l";
+
+// Set when the panic hook left the alternate screen buffer. Leaving it again on unwinding would
+// restore the cursor above the panic message
+static LEFT_ON_PANIC: AtomicBool = AtomicBool::new(false);
+
+// Print an ambiguous width character at the top-left corner and query the cursor position. The
+// reported column tells its width on the terminal. The character is overwritten by the next frame
+const AMBIGUOUS_WIDTH_PROBE: &[u8] = "\x1b[H\u{25cb}\x1b[6n".as_bytes();
+
+// Glyphs drawn in place of invisible characters. '→' and '·' are East Asian ambiguous width and
+// may be wide depending on the terminal, so narrow look-alikes are used instead
+const TAB_GLYPH: char = '›';
+const SPACE_GLYPH: char = '⋅';
+const NBSP_GLYPH: char = '␣';
+// Drawn after the first line of a closed fold with the number of hidden lines
+const FOLD_GLYPH: char = '⋯';
 
 #[derive(PartialEq)]
 enum StatusMessageKind {
     Info,
     Error,
+    Prompt, // Kept until the next message since the prompt waits for input
+}
+
+// The buffer on the other side shown next to the current buffer in diff mode
+pub struct DiffPane<'a> {
+    pub buf: &'a TextBuffer,
+    pub hl: &'a mut Highlighting,
+    pub hunks: &'a [Hunk],
+    pub side: usize, // Side of the current buffer
+}
+
+// Lines of a buffer drawn in the pane on the side. Without diff mode the only pane is on side 0
+#[derive(Clone, Copy)]
+struct Pane<'a> {
+    rowoff: usize,
+    side: usize,
+    hunks: &'a [Hunk],
+}
+
+// Popup shown near the cursor over text rows such as completion candidates
+struct Popup {
+    lines: Vec<String>,
+    selected: usize,
+    x: usize, // Character index in the cursor line where the popup is aligned
+}
+
+// Where the popup is drawn on screen. 0-based
+#[derive(Clone, Copy, PartialEq)]
+struct PopupArea {
+    top: usize,
+    left: usize,
+    width: usize,
+    height: usize,
 }
 
 struct StatusMessage {
     text: String,
-    timestamp: Option<SystemTime>,
+    timestamp: Option<Instant>,
     kind: StatusMessageKind,
 }
 
@@ -68,10 +109,6 @@ where
     I: Iterator<Item = io::Result<InputSeq>>,
     W: Write,
 {
-    if let Some(s) = term_size::dimensions_stdout() {
-        return Ok(s);
-    }
-
     // By moving cursor at the bottom-right corner by 'B' and 'C' commands, get the size of
     // current screen. \x1b[9999;9999H is not available since it does not guarantee cursor
     // stops on the corner. Finally command 'n' queries cursor position.
@@ -88,27 +125,143 @@ where
     Ok((0, 0)) // Give up
 }
 
+// Byte range of the message which fits in the message bar. Head of a long prompt is hidden so that
+// the cursor at the byte index is always visible. Wide characters are never split
+fn visible_range(text: &str, cursor: Option<usize>, cols: usize) -> (usize, usize) {
+    let mut start = 0;
+    if let Some(cursor) = cursor.filter(|c| text.is_char_boundary(*c)) {
+        let mut width = grapheme::str_width(&text[..cursor]);
+        for cluster in grapheme::graphemes(&text[..cursor]) {
+            if width < cols {
+                break;
+            }
+            width -= grapheme::width(cluster);
+            start += cluster.len();
+        }
+    }
+    let mut end = start;
+    let mut width = 0;
+    for cluster in grapheme::graphemes(&text[start..]) {
+        width += grapheme::width(cluster);
+        if width > cols {
+            break;
+        }
+        end += cluster.len();
+    }
+    (start, end)
+}
+
+fn mark_color(mark: Option<Mark>) -> AnsiColor {
+    match mark {
+        Some(Mark::Added) => AnsiColor::Green,
+        Some(Mark::Modified) => AnsiColor::Yellow,
+        Some(Mark::Deleted) => AnsiColor::Red,
+        None => AnsiColor::Reset,
+    }
+}
+
+fn diff_background(change: Change) -> Background {
+    match change {
+        Change::Added => Background::DiffAdded,
+        Change::Removed => Background::DiffRemoved,
+        Change::Changed => Background::DiffChanged,
+    }
+}
+
+fn conflict_background(part: Part) -> Background {
+    match part {
+        Part::Marker | Part::Base => Background::ConflictMarker,
+        Part::Ours => Background::ConflictOurs,
+        Part::Theirs => Background::ConflictTheirs,
+    }
+}
+
+// Display width of indentation where indent guides are drawn. Blank lines continue the guides of
+// the lines around them
+fn indent_guides_width(rows: &GapBuffer<Row>, y: usize) -> usize {
+    let indent = |row: &Row| {
+        let spaces = row
+            .buffer()
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        Some(row.rx_from_cx(spaces)).filter(|_| spaces < row.len())
+    };
+    if let Some(width) = indent(&rows[y]) {
+        return width;
+    }
+    let prev = (0..y).rev().find_map(|i| indent(&rows[i]));
+    let next = (y + 1..rows.len()).find_map(|i| indent(&rows[i]));
+    cmp::min(prev.unwrap_or(0), next.unwrap_or(0))
+}
+
+// Size of text area for the window size. Screen height is 2 lines less than window height due to
+// status bar and message bar. At least one line and one column are kept even in a tiny window
+// since cursor position is calculated assuming non-empty area.
+fn screen_size(w: usize, h: usize) -> (usize, usize) {
+    (cmp::max(w, 1), cmp::max(h.saturating_sub(2), 1))
+}
+
 pub struct Screen<W: Write> {
     output: W,
-    // X coordinate in `render` text of rows
+    // X coordinate in display columns of rows
     rx: usize,
     // Screen size
     num_cols: usize,
     num_rows: usize,
     message: Option<StatusMessage>,
+    // All messages shown in the message bar except for prompts
+    log: MessageLog,
+    // Byte index of the cursor in the prompt message while prompt is waiting for input
+    prompt_cursor: Option<usize>,
     // Dirty line which requires rendering update. After this line must be updated since
     // updating line may affect highlights of succeeding lines
     dirty_start: Option<usize>,
-    // Watch resize signal
-    sigwinch: SigwinchWatcher,
     pub cursor_moved: bool,
     pub rowoff: usize, // Row scroll offset
     pub coloff: usize, // Column scroll offset
     pub color_support: ColorSupport,
+    palette: Palette,
+    // Candidates shown over text area and index of selected one. e.g. Matches of file finder
+    overlay: Option<(Vec<String>, usize)>,
+    // Width of the column at left of text area to show marks of changes from git HEAD
+    gutter: usize,
+    popup: Option<Popup>,
+    // Area where the popup was drawn last. Rows under it are rendered again when it is changed
+    popup_area: Option<PopupArea>,
+    // Contents of the screen drawn last including status bar and message bar. A new frame is drawn
+    // over its copy and only the differences are written to the terminal
+    frame: Grid,
+    // Terminal shows `frame`. False when the terminal contents are unknown such as after resize
+    synced: bool,
+    // Lines to scroll the text area of the terminal before writing the next frame
+    scroll: isize,
+    // Lines kept visible above and below the cursor
+    scrolloff: usize,
+    // Columns scrolled at once when the cursor goes out of the screen horizontally
+    sidescroll: usize,
+    // Tabs, trailing spaces and non-breaking spaces are drawn with visible glyphs
+    invisibles: bool,
+    // The line at the cursor is drawn with a subtle background
+    cursor_line: bool,
+    // The line where the cursor line was drawn last. It is drawn again when the cursor leaves it
+    cursor_line_drawn: Option<usize>,
+    // Display columns drawn with the background of rulers such as 80, in ascending order
+    rulers: Vec<usize>,
+    // Indentation of code is drawn with faint guides at each level
+    indent_guides: bool,
+    // The rightmost column shows the position of the screen in the buffer
+    scrollbar: bool,
+    // Side of the current buffer while diff mode shows two buffers side by side
+    diff_side: Option<usize>,
+    // The probe of ambiguous width is written before the next frame
+    probe_requested: bool,
+    // The cursor position reported next is the answer to the probe
+    probing: bool,
 }
 
 impl<W: Write> Screen<W> {
-    pub fn new<I>(size: Option<(usize, usize)>, input: I, mut output: W) -> io::Result<Self>
+    pub fn new<I>(size: Option<(usize, usize)>, input: I, mut output: W) -> Result<Self>
     where
         I: Iterator<Item = io::Result<InputSeq>>,
     {
@@ -118,35 +271,86 @@ impl<W: Write> Screen<W> {
             get_window_size(input, &mut output)?
         };
 
-        // Enter alternate screen buffer to restore previous screen on quit
-        // https://www.xfree86.org/current/ctlseqs.html#The%20Alternate%20Screen%20Buffer
-        output.write(b"\x1b[?47h")?;
+        output.write(ENTER_SEQUENCE)?;
 
+        let (num_cols, num_rows) = screen_size(w, h);
+        let color_support = ColorSupport::from_env();
         Ok(Self {
             output,
             rx: 0,
-            num_cols: w,
-            // Screen height is 1 line less than window height due to status bar
-            num_rows: h.saturating_sub(2),
+            num_cols,
+            num_rows,
             message: Some(StatusMessage::new(
                 "Ctrl-? for help",
                 StatusMessageKind::Info,
             )),
+            log: MessageLog::default(),
+            prompt_cursor: None,
             dirty_start: Some(0), // Render entire screen at first paint
-            sigwinch: SigwinchWatcher::new()?,
             cursor_moved: true,
             rowoff: 0,
             coloff: 0,
-            color_support: ColorSupport::from_env(),
+            color_support,
+            palette: Palette::new(color_support, &Theme::default()),
+            overlay: None,
+            gutter: 0,
+            popup: None,
+            popup_area: None,
+            frame: Grid::new(num_cols, num_rows + 2),
+            synced: false,
+            scroll: 0,
+            scrolloff: 0,
+            sidescroll: 0,
+            invisibles: false,
+            cursor_line: false,
+            cursor_line_drawn: None,
+            rulers: vec![],
+            indent_guides: false,
+            scrollbar: false,
+            diff_side: None,
+            probe_requested: false,
+            probing: false,
         })
     }
 
-    fn write_flush(&mut self, bytes: &[u8]) -> io::Result<()> {
+    fn write_flush(&mut self, bytes: &[u8]) -> Result<()> {
         self.output.write(bytes)?;
-        self.output.flush()
+        Ok(self.output.flush()?)
     }
 
-    fn trim_line<'a, S: AsRef<str>>(&self, line: &'a S) -> String {
+    // Draw the output of draw_* methods through the frame so that only changed cells are written
+    fn present(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut buf = Vec::with_capacity(bytes.len());
+        let scroll = std::mem::take(&mut self.scroll);
+        if self.synced && scroll != 0 {
+            // Lines scrolled in are filled with the background color of the theme
+            self.frame.apply(self.palette.sequence(AnsiColor::Reset));
+            self.frame.scroll(self.num_rows, scroll, &mut buf)?;
+        }
+        let mut next = self.frame.clone();
+        next.apply(bytes);
+        let prev = if self.synced { Some(&self.frame) } else { None };
+        next.write_diff(prev, &mut buf)?;
+        let (x, y) = next.cursor();
+        self.frame = next;
+        self.synced = true;
+        if buf.is_empty() {
+            write!(self.output, "\x1b[{};{}H", y + 1, x + 1)?;
+            return Ok(self.output.flush()?);
+        }
+
+        // \x1b[: Escape sequence header
+        // Hide cursor while updating screen. 'l' is command to set mode http://vt100.net/docs/vt100-ug/chapter3.html#SM
+        // This command must be flushed at first otherwise cursor may move before being hidden
+        self.write_flush(b"\x1b[?25l")?;
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
+        write!(buf, "\x1b[{};{}H", y + 1, x + 1)?;
+        // Reveal cursor again. 'h' is command to reset mode https://vt100.net/docs/vt100-ug/chapter3.html#RM
+        buf.write(b"\x1b[?25h")?;
+        self.write_flush(&buf)
+    }
+
+    fn trim_line<S: AsRef<str>>(&self, line: &S) -> String {
         let line = line.as_ref();
         if line.len() <= self.coloff {
             return "".to_string();
@@ -161,33 +365,34 @@ impl<W: Write> Screen<W> {
 
         write!(buf, "\x1b[{}H", self.num_rows + 1)?;
 
-        buf.write(AnsiColor::Invert.sequence(self.color_support))?;
+        buf.write(self.palette.sequence(AnsiColor::Invert))?;
 
         let left = status_bar.left();
-        // TODO: Handle multi-byte chars correctly
-        let left = &left[..cmp::min(left.len(), self.num_cols)];
+        let (_, end) = visible_range(&left, None, self.num_cols);
+        let left = &left[..end];
         buf.write(left.as_bytes())?; // Left of status bar
 
-        let rest_len = self.num_cols - left.len();
+        let rest_len = self.num_cols - grapheme::str_width(left);
         if rest_len == 0 {
             return Ok(());
         }
 
         let right = status_bar.right();
-        if right.len() > rest_len {
+        let right_len = grapheme::str_width(&right);
+        if right_len > rest_len {
             for _ in 0..rest_len {
                 buf.write(b" ")?;
             }
             return Ok(());
         }
 
-        for _ in 0..rest_len - right.len() {
+        for _ in 0..rest_len - right_len {
             buf.write(b" ")?; // Add spaces at center of status bar
         }
         buf.write(right.as_bytes())?;
 
         // Default argument of 'm' command is 0 so it resets attributes
-        buf.write(AnsiColor::Reset.sequence(self.color_support))?;
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
         Ok(())
     }
 
@@ -199,25 +404,23 @@ impl<W: Write> Screen<W> {
         };
 
         if let Some(timestamp) = message.timestamp {
-            if let Ok(d) = SystemTime::now().duration_since(timestamp) {
-                if d.as_secs() < 5 {
-                    return Ok(());
-                }
+            if message.kind == StatusMessageKind::Prompt || timestamp.elapsed().as_secs() < 5 {
+                return Ok(());
             }
             write!(buf, "\x1b[{}H", self.num_rows + 2)?;
             self.message = None;
         } else {
             write!(buf, "\x1b[{}H", self.num_rows + 2)?;
-            // TODO: Handle multi-byte chars correctly
-            let msg = &message.text[..cmp::min(message.text.len(), self.num_cols)];
+            let (start, end) = visible_range(&message.text, self.prompt_cursor, self.num_cols);
+            let msg = &message.text[start..end];
             if message.kind == StatusMessageKind::Error {
-                buf.write(AnsiColor::RedBG.sequence(self.color_support))?;
+                buf.write(self.palette.sequence(AnsiColor::RedBG))?;
                 buf.write(msg.as_bytes())?;
-                buf.write(AnsiColor::Reset.sequence(self.color_support))?;
+                buf.write(self.palette.sequence(AnsiColor::Reset))?;
             } else {
                 buf.write(msg.as_bytes())?;
             }
-            message.timestamp = Some(SystemTime::now());
+            message.timestamp = Some(Instant::now());
         }
 
         buf.write(b"\x1b[K")?;
@@ -238,81 +441,437 @@ impl<W: Write> Screen<W> {
         Ok(())
     }
 
-    fn draw_rows<B: Write>(&self, mut buf: B, rows: &[Row], hl: &Highlighting) -> io::Result<()> {
-        let dirty_start = if let Some(s) = self.dirty_start {
-            s
-        } else {
+    // Width of the pane of a buffer including the gutter. Diff mode splits the text area into two
+    // panes with a separator column between them
+    fn pane_cols(&self) -> usize {
+        let scrollbar = if self.scrollbar { 1 } else { 0 };
+        let cols = self.num_cols.saturating_sub(scrollbar);
+        match self.diff_side {
+            Some(_) => cols.saturating_sub(1) / 2,
+            None => cols,
+        }
+    }
+
+    fn pane_left(&self, side: usize) -> usize {
+        side * (self.pane_cols() + 1)
+    }
+
+    // Width of text area excluding the gutter and the scrollbar
+    fn text_cols(&self) -> usize {
+        cmp::max(self.pane_cols().saturating_sub(self.gutter), 1)
+    }
+
+    fn draw_separator<B: Write>(&self, mut buf: B) -> io::Result<()> {
+        if self.diff_side.is_none() {
             return Ok(());
+        }
+        buf.write(self.palette.sequence(AnsiColor::Gray))?;
+        for y in 0..self.num_rows {
+            write!(buf, "\x1b[{};{}H|", y + 1, self.pane_cols() + 1)?;
+        }
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
+        Ok(())
+    }
+
+    // Lines in the buffer shown by the row of the scrollbar. Short buffer is scaled as if it had as
+    // many lines as the rows
+    fn scrollbar_range(&self, y: usize, len: usize) -> (usize, usize) {
+        let total = cmp::max(len, self.num_rows);
+        (y * total / self.num_rows, (y + 1) * total / self.num_rows)
+    }
+
+    // The thumb shows the lines on the screen. Marks show changes from VCS and matches of search
+    fn draw_scrollbar<B: Write>(
+        &self,
+        mut buf: B,
+        text_buf: &TextBuffer,
+        hl: &Highlighting,
+    ) -> io::Result<()> {
+        if !self.scrollbar {
+            return Ok(());
+        }
+        let (top, bottom) = (self.rowoff, self.bottom_line(text_buf));
+        let len = text_buf.rows().len();
+        let mut prev = (AnsiColor::Reset, Background::Default);
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
+        for y in 0..self.num_rows {
+            let (start, end) = self.scrollbar_range(y, len);
+            let bg = if start < bottom && top < end {
+                Background::Thumb
+            } else {
+                Background::Scrollbar
+            };
+            let (c, color) = if hl.has_match_in(start..end) {
+                ('*', AnsiColor::Cyan)
+            } else {
+                let mark = (start..cmp::min(end, len)).find_map(|y| text_buf.vcs_mark(y));
+                (mark.map_or(' ', Mark::symbol), mark_color(mark))
+            };
+            write!(buf, "\x1b[{};{}H", y + 1, self.num_cols)?;
+            self.set_color(&mut buf, color, bg, &mut prev)?;
+            write!(buf, "{}", c)?;
+        }
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
+        Ok(())
+    }
+
+    // Set the foreground color and the background layered under it. Resetting attributes also
+    // resets the background, so the background is set after the color
+    fn set_color<B: Write>(
+        &self,
+        mut buf: B,
+        color: AnsiColor,
+        bg: Background,
+        prev: &mut (AnsiColor, Background),
+    ) -> io::Result<()> {
+        let bg = if color.has_background() {
+            Background::Default
+        } else {
+            bg
         };
-        let mut prev_color = AnsiColor::Reset;
+        if color != prev.0 {
+            // Stop underline or reverse video. The color with its own background is not mixed
+            if prev.0.has_attribute() || color.has_background() && prev.1 != Background::Default {
+                buf.write(self.palette.sequence(AnsiColor::Reset))?;
+                *prev = (AnsiColor::Reset, Background::Default);
+            }
+            if color != prev.0 {
+                buf.write(self.palette.sequence(color))?;
+                prev.0 = color;
+                if color == AnsiColor::Reset {
+                    prev.1 = Background::Default;
+                }
+            }
+        }
+        if bg != prev.1 {
+            buf.write(self.palette.background(bg))?;
+            prev.1 = bg;
+        }
+        Ok(())
+    }
+
+    fn draw_gutter<B: Write>(
+        &self,
+        mut buf: B,
+        mark: Option<Mark>,
+        prev: &mut (AnsiColor, Background),
+    ) -> io::Result<()> {
+        self.set_color(&mut buf, mark_color(mark), Background::Default, prev)?;
+        write!(buf, "{}", mark.map_or(' ', Mark::symbol))
+    }
+
+    // Background of the cell at the display columns `start..end` of the line. `guides` is the width
+    // of indentation where indent guides are drawn and the width of one level
+    fn background_at(
+        &self,
+        start: usize,
+        end: usize,
+        line_bg: Background,
+        guides: (usize, usize),
+    ) -> Background {
+        let (indent, level) = guides;
+        if self.rulers.iter().any(|r| start <= *r && *r < end) {
+            Background::Ruler
+        } else if start < indent && start.is_multiple_of(level) {
+            Background::IndentGuide
+        } else {
+            line_bg
+        }
+    }
+
+    fn draw_rows<B: Write>(
+        &self,
+        mut buf: B,
+        text_buf: &TextBuffer,
+        hl: &Highlighting,
+        pane: Pane<'_>,
+    ) -> io::Result<()> {
+        let rows = text_buf.rows();
+        // The pane of the other side in diff mode has no cursor and is always drawn entirely
+        let current = Some(pane.side) == self.diff_side.or(Some(0));
+        let dirty_start = match self.dirty_start {
+            Some(s) if current => s,
+            Some(_) => pane.rowoff,
+            None => return Ok(()),
+        };
+        let left = self.pane_left(pane.side);
+        let mut prev = (AnsiColor::Reset, Background::Default);
         let row_len = rows.len();
+        let folds = text_buf.folds();
 
-        buf.write(AnsiColor::Reset.sequence(self.color_support))?;
+        buf.write(self.palette.sequence(AnsiColor::Reset))?;
 
+        let mut next_row = pane.rowoff;
         for y in 0..self.num_rows {
-            let file_row = y + self.rowoff;
+            // Lines hidden by folds are skipped
+            let file_row = next_row;
+            next_row = folds.next_line(file_row);
 
             if file_row < dirty_start {
                 continue;
             }
 
             // H: Command to move cursor. Here \x1b[H is the same as \x1b[1;1H
-            write!(buf, "\x1b[{}H", y + 1)?;
+            write!(buf, "\x1b[{};{}H", y + 1, left + 1)?;
+
+            if self.gutter > 0 {
+                let mark = text_buf.vcs_mark(file_row).filter(|_| file_row < row_len);
+                self.draw_gutter(&mut buf, mark, &mut prev)?;
+            }
 
             if file_row >= row_len {
-                if rows.is_empty() && y == self.num_rows / 3 {
+                if rows.is_empty() && self.diff_side.is_none() && y == self.num_rows / 3 {
                     self.draw_welcome_message(&mut buf)?;
                 } else {
-                    if prev_color != AnsiColor::Reset {
-                        buf.write(AnsiColor::Reset.sequence(self.color_support))?;
-                        prev_color = AnsiColor::Reset;
-                    }
+                    self.set_color(&mut buf, AnsiColor::Reset, Background::Default, &mut prev)?;
                     buf.write(b"~")?;
                 }
             } else {
                 let row = &rows[file_row];
-
+                let cursor_line = self.cursor_line && current && file_row == text_buf.cy();
+                let change = diff::change_at(pane.hunks, pane.side, file_row);
+                let line_bg = match (change, text_buf.conflict_part(file_row)) {
+                    (Some(change), _) => diff_background(change),
+                    (None, Some(part)) => conflict_background(part),
+                    (None, None) if cursor_line => Background::CursorLine,
+                    (None, None) => Background::Default,
+                };
+                let code = !matches!(text_buf.lang(), Language::Plain | Language::Markdown);
+                let guides = if self.indent_guides && code {
+                    (
+                        indent_guides_width(rows, file_row),
+                        text_buf.tab_width().max(1),
+                    )
+                } else {
+                    (0, 1)
+                };
+
+                // Markers at the first and last columns tell that the line continues out of screen
+                let hidden_left = self.coloff > 0;
+                let hidden_right = self.hidden_right(row);
+                let trailing = if self.invisibles {
+                    let spaces = row
+                        .buffer()
+                        .chars()
+                        .rev()
+                        .take_while(|c| *c == ' ' || *c == '\t');
+                    row.len() - spaces.count()
+                } else {
+                    usize::MAX
+                };
                 let mut col = 0;
-                for (c, hl) in row.render_text().chars().zip(hl.lines[file_row].iter()) {
-                    col += c.width_cjk().unwrap_or(1);
+                let mut prev_x = None;
+                let mut orig = '\0'; // Character in the buffer which the display character comes from
+                let mut replaced = false; // The last cluster was replaced with a marker
+                let line = &hl.lines[file_row];
+                for (x, c, width, hl) in row
+                    .render_chars()
+                    .map_while(|(x, c, w)| Some((x, c, w, line.get(x)?)))
+                {
+                    if width == 0 && replaced {
+                        continue; // Rest of the grapheme cluster
+                    }
+                    replaced = false;
+                    col += width;
+                    let first_cell = prev_x != Some(x);
+                    if first_cell {
+                        orig = row.char_at(x);
+                    }
+                    prev_x = Some(x);
                     if col <= self.coloff {
                         continue;
-                    } else if col > self.num_cols + self.coloff {
-                        break;
                     }
-
-                    let color = hl.color();
-                    if color != prev_color {
-                        if prev_color.is_underlined() {
-                            buf.write(AnsiColor::Reset.sequence(self.color_support))?; // Stop underline
+                    let marker = if hidden_right && col >= self.text_cols() + self.coloff {
+                        // Wide character at the last column is replaced with padding and marker
+                        let pad = self.text_cols() + self.coloff - (col - width) - 1;
+                        Some((pad, '>'))
+                    } else if col > self.text_cols() + self.coloff {
+                        break;
+                    } else if hidden_left && col - width <= self.coloff {
+                        // Wide character across the first column is also replaced
+                        Some((0, '<'))
+                    } else {
+                        None
+                    };
+                    if let Some((pad, marker)) = marker {
+                        self.set_color(&mut buf, AnsiColor::Reset, line_bg, &mut prev)?;
+                        write!(buf, "{:pad$}{}", "", marker, pad = pad)?;
+                        replaced = true;
+                        if marker == '>' {
+                            break;
                         }
-                        buf.write(color.sequence(self.color_support))?;
-                        prev_color = color;
+                        write!(buf, "{:pad$}", "", pad = col - self.coloff - 1)?;
+                        continue;
                     }
 
+                    let glyph = match c {
+                        _ if !self.invisibles => None,
+                        ' ' if first_cell && orig == '\t' => Some(TAB_GLYPH),
+                        ' ' if x >= trailing && orig == ' ' => Some(SPACE_GLYPH),
+                        '\u{a0}' => Some(NBSP_GLYPH),
+                        _ => None,
+                    };
+                    // Glyphs are dim and notations of control characters are colored unless they
+                    // are in highlighted region like selection
+                    let (c, color) = match glyph {
+                        _ if hl.color().has_attribute() => (glyph.unwrap_or(c), hl.color()),
+                        Some(g) => (g, AnsiColor::Gray),
+                        None if orig != c && orig != '\t' => (c, AnsiColor::Cyan),
+                        None => (c, hl.color()),
+                    };
+                    let bg = self.background_at(col - width, col, line_bg, guides);
+                    self.set_color(&mut buf, color, bg, &mut prev)?;
+
                     write!(buf, "{}", c)?;
                 }
+
+                if hl.has_caret_at(hl.lines[file_row].len(), file_row)
+                    && col >= self.coloff
+                    && col < self.text_cols() + self.coloff
+                {
+                    self.set_color(&mut buf, AnsiColor::Reset, Background::Default, &mut prev)?;
+                    self.set_color(&mut buf, AnsiColor::Invert, Background::Default, &mut prev)?;
+                    buf.write(b" ")?;
+                    col += 1;
+                }
+
+                if let Some(last) = folds.closed_at(file_row).filter(|_| !hidden_right) {
+                    let room =
+                        (self.text_cols() + self.coloff).saturating_sub(col.max(self.coloff));
+                    let hidden = last - file_row;
+                    let unit = if hidden == 1 { "line" } else { "lines" };
+                    let marker = format!(" {} {} {}", FOLD_GLYPH, hidden, unit);
+                    let marker: String = marker.chars().take(room).collect();
+                    self.set_color(&mut buf, AnsiColor::Gray, line_bg, &mut prev)?;
+                    buf.write(marker.as_bytes())?;
+                    col = col.max(self.coloff) + marker.chars().count();
+                }
+
+                // Rulers and indent guides after the end of line are drawn on padding
+                let right = self.text_cols() + self.coloff;
+                let last_ruler = self.rulers.iter().rev().find(|r| **r < right);
+                let end = cmp::min(cmp::max(last_ruler.map_or(0, |r| r + 1), guides.0), right);
+                for x in col.max(self.coloff)..end {
+                    let bg = self.background_at(x, x + 1, line_bg, guides);
+                    self.set_color(&mut buf, AnsiColor::Reset, bg, &mut prev)?;
+                    buf.write(b" ")?;
+                }
+                // Erasing line fills the rest with current background color
+                self.set_color(&mut buf, AnsiColor::Reset, line_bg, &mut prev)?;
             }
 
             // Erases the part of the line to the right of the cursor. http://vt100.net/docs/vt100-ug/chapter3.html#EL
             buf.write(b"\x1b[K")?;
         }
 
-        if prev_color != AnsiColor::Reset {
-            buf.write(AnsiColor::Reset.sequence(self.color_support))?; // Ensure to reset color at end of screen
+        if prev != (AnsiColor::Reset, Background::Default) {
+            buf.write(self.palette.sequence(AnsiColor::Reset))?; // Ensure to reset color at end of screen
         }
 
         Ok(())
     }
 
+    // Lines are drawn upward from the bottom of text area so that the first line is next to the
+    // prompt in message bar
+    fn draw_overlay<B: Write>(&self, mut buf: B) -> io::Result<()> {
+        let (lines, selected) = match &self.overlay {
+            Some(overlay) => overlay,
+            None => return Ok(()),
+        };
+        for (i, line) in lines.iter().take(self.num_rows).enumerate() {
+            write!(buf, "\x1b[{}H", self.num_rows - i)?;
+            let line: String = line.chars().take(self.num_cols.saturating_sub(2)).collect();
+            if i == *selected {
+                buf.write(self.palette.sequence(AnsiColor::Invert))?;
+                write!(buf, "> {}", line)?;
+                buf.write(self.palette.sequence(AnsiColor::Reset))?;
+            } else {
+                write!(buf, "  {}", line)?;
+            }
+            buf.write(b"\x1b[K")?;
+        }
+        Ok(())
+    }
+
+    // The popup is put under the cursor line, or above it when there is no room
+    fn layout_popup(&self, text_buf: &TextBuffer, popup: &Popup) -> Option<PopupArea> {
+        let cy = text_buf.cy();
+        if cy < self.rowoff {
+            return None;
+        }
+        let row = text_buf.folds().count(self.rowoff, cy);
+        let below = self.num_rows.saturating_sub(row + 1);
+        let len = popup.lines.len();
+        let (top, height) = if len <= below || below >= row {
+            (row + 1, cmp::min(len, below))
+        } else {
+            (row - cmp::min(len, row), cmp::min(len, row))
+        };
+        let width = popup.lines.iter().map(|l| grapheme::str_width(l)).max()? + 2; // With padding
+        let width = cmp::min(width, self.num_cols);
+        if height == 0 || width == 0 {
+            return None;
+        }
+        let rx = text_buf
+            .rows()
+            .get(text_buf.cy())
+            .map_or(0, |r| r.rx_from_cx(popup.x));
+        let left = rx.saturating_sub(self.coloff) + self.gutter + self.text_left();
+        let left = cmp::min(left, self.num_cols - width);
+        Some(PopupArea {
+            top,
+            left,
+            width,
+            height,
+        })
+    }
+
+    // Popup is drawn after text rows are rendered so that it is composed over them
+    fn draw_popup<B: Write>(&self, mut buf: B) -> io::Result<()> {
+        let (popup, area) = match (&self.popup, &self.popup_area) {
+            (Some(popup), Some(area)) => (popup, area),
+            _ => return Ok(()),
+        };
+        // Scroll candidates so that the selected one is always visible
+        let skip = (popup.selected + 1).saturating_sub(area.height);
+        for (i, line) in popup.lines.iter().enumerate().skip(skip).take(area.height) {
+            write!(buf, "\x1b[{};{}H", area.top + i - skip + 1, area.left + 1)?;
+            let mut text = String::from(if i == popup.selected { '>' } else { ' ' });
+            let mut width = 1;
+            for c in line.chars() {
+                let w = grapheme::char_width(c).unwrap_or(1);
+                if width + w >= area.width {
+                    break;
+                }
+                text.push(c);
+                width += w;
+            }
+            text.extend((width..area.width).map(|_| ' '));
+            buf.write(self.palette.sequence(AnsiColor::Invert))?;
+            buf.write(text.as_bytes())?;
+            buf.write(self.palette.sequence(AnsiColor::Reset))?;
+        }
+        Ok(())
+    }
+
     fn redraw(
         &mut self,
         text_buf: &TextBuffer,
         hl: &Highlighting,
+        other: Option<(&TextBuffer, &Highlighting, Pane<'_>)>,
         status_bar: &StatusBar,
-    ) -> io::Result<()> {
-        let cursor_row = text_buf.cy() - self.rowoff + 1;
-        let cursor_col = self.rx - self.coloff + 1;
+    ) -> Result<()> {
+        let (cursor_row, cursor_col) = self.prompt_cursor_position().unwrap_or((
+            text_buf.folds().count(self.rowoff, text_buf.cy()) + 1,
+            self.rx - self.coloff + self.gutter + self.text_left() + 1,
+        ));
+
+        if self.probe_requested {
+            self.probe_requested = false;
+            self.probing = true;
+            self.synced = false;
+            self.write_flush(AMBIGUOUS_WIDTH_PROBE)?;
+        }
 
         if self.dirty_start.is_none() && !status_bar.redraw && self.message.is_none() {
             if self.cursor_moved {
@@ -322,30 +881,45 @@ impl<W: Write> Screen<W> {
             return Ok(());
         }
 
-        // \x1b[: Escape sequence header
-        // Hide cursor while updating screen. 'l' is command to set mode http://vt100.net/docs/vt100-ug/chapter3.html#SM
-        // This command must be flushed at first otherwise cursor may move before being hidden
-        self.write_flush(b"\x1b[?25l")?;
-
         let mut buf = Vec::with_capacity((self.num_rows + 2) * self.num_cols);
 
-        self.draw_rows(&mut buf, text_buf.rows(), hl)?;
+        let pane = Pane {
+            rowoff: self.rowoff,
+            side: self.diff_side.unwrap_or(0),
+            hunks: other.as_ref().map_or(&[], |(_, _, p)| p.hunks),
+        };
+        // Erasing the rest of lines in the left pane also erases the right pane. It is drawn later
+        match other {
+            Some((other_buf, other_hl, other)) if other.side == 0 => {
+                self.draw_rows(&mut buf, other_buf, other_hl, other)?;
+                self.draw_rows(&mut buf, text_buf, hl, pane)?;
+            }
+            Some((other_buf, other_hl, other)) => {
+                self.draw_rows(&mut buf, text_buf, hl, pane)?;
+                self.draw_rows(&mut buf, other_buf, other_hl, other)?;
+            }
+            None => self.draw_rows(&mut buf, text_buf, hl, pane)?,
+        }
+        self.draw_separator(&mut buf)?;
+        self.draw_scrollbar(&mut buf, text_buf, hl)?;
+        self.draw_overlay(&mut buf)?;
+        self.draw_popup(&mut buf)?;
         self.draw_status_bar(&mut buf, status_bar)?;
         self.draw_message_bar(&mut buf)?;
 
         // Move cursor even if cursor_moved is false since cursor is moved by draw_* methods
         write!(buf, "\x1b[{};{}H", cursor_row, cursor_col)?;
 
-        // Reveal cursor again. 'h' is command to reset mode https://vt100.net/docs/vt100-ug/chapter3.html#RM
-        buf.write(b"\x1b[?25h")?;
-
-        self.write_flush(&buf)
+        self.present(&buf)
     }
 
     fn next_coloff(&self, want_stop: usize, row: &Row) -> usize {
+        if want_stop == 0 {
+            return 0;
+        }
         let mut coloff = 0;
-        for c in row.render_text().chars() {
-            coloff += c.width_cjk().unwrap_or(1);
+        for (_, _, width) in row.render_chars() {
+            coloff += width;
             if coloff >= want_stop {
                 // Screen cannot start from at the middle of double-width character
                 break;
@@ -354,9 +928,40 @@ impl<W: Write> Screen<W> {
         coloff
     }
 
-    fn do_scroll(&mut self, rows: &[Row], cx: usize, cy: usize) {
+    // The nearest boundary between characters at or before the column
+    fn prev_coloff(&self, col: usize, row: &Row) -> usize {
+        let mut coloff = 0;
+        for (_, _, width) in row.render_chars() {
+            let next = coloff + width;
+            if next > col {
+                break;
+            }
+            coloff = next;
+        }
+        coloff
+    }
+
+    // Text after the last column of screen is hidden and the column shows a marker instead
+    fn hidden_right(&self, row: &Row) -> bool {
+        row.rx_from_cx(row.len()) > self.coloff + self.text_cols()
+    }
+
+    // Columns scrolled at once. 0 scrolls half of the screen. The cursor must stay in the screen
+    fn sidescroll_step(&self) -> usize {
+        let half = self.text_cols() / 2;
+        let step = if self.sidescroll == 0 {
+            half
+        } else {
+            cmp::min(self.sidescroll, half)
+        };
+        cmp::max(step, 1)
+    }
+
+    fn do_scroll(&mut self, rows: &GapBuffer<Row>, folds: &Folds, cx: usize, cy: usize) {
         let prev_rowoff = self.rowoff;
         let prev_coloff = self.coloff;
+        // The top line may be hidden by a fold closed after scrolling
+        self.rowoff = folds.visible_line(self.rowoff);
 
         // Calculate X coordinate to render considering tab stop
         if cy < rows.len() {
@@ -365,29 +970,63 @@ impl<W: Write> Screen<W> {
             self.rx = 0;
         }
 
-        // Adjust scroll position when cursor is outside screen
-        if cy < self.rowoff {
+        // Adjust scroll position when cursor is outside screen or within the scroll margin. Lines
+        // after the end of buffer are not counted in the margin below the cursor. Lines hidden by
+        // folds are not counted in the distances
+        let above = self.scroll_margin();
+        let below = above.min(folds.count(cy + 1, rows.len()));
+        if cy < self.rowoff || folds.count(self.rowoff, cy) < above {
             // Scroll up when cursor is above the top of window
-            self.rowoff = cy;
+            self.rowoff = folds.up(cy, above);
         }
-        if cy >= self.rowoff + self.num_rows {
+        if folds.count(self.rowoff, cy) + below >= self.num_rows {
             // Scroll down when cursor is below the bottom of screen
-            self.rowoff = cy - self.num_rows + 1;
+            self.rowoff = folds.up(cy, self.num_rows - 1 - below);
         }
-        if self.rx < self.coloff {
-            self.coloff = self.rx;
+
+        // Scroll horizontally by the step when cursor is outside screen or on the markers of hidden
+        // text at the first and last columns. Scroll offset is at boundary between characters
+        let step = self.sidescroll_step();
+        let left = if self.coloff > 0 { 1 } else { 0 };
+        if self.rx < self.coloff + left {
+            let col = self.rx.saturating_sub(step);
+            self.coloff = rows.get(cy).map_or(0, |r| self.prev_coloff(col, r));
         }
-        if self.rx >= self.coloff + self.num_cols {
-            // TODO: coloff must not be in the middle of character. It must be at boundary between characters
-            self.coloff = self.next_coloff(self.rx - self.num_cols + 1, &rows[cy]);
+        if let Some(row) = rows.get(cy) {
+            let right = if self.hidden_right(row) { 1 } else { 0 };
+            if self.rx + right >= self.coloff + self.text_cols() {
+                let col = (self.rx + 1 + step).saturating_sub(self.text_cols());
+                self.coloff = self.next_coloff(col, row);
+            }
         }
 
         if prev_rowoff != self.rowoff || prev_coloff != self.coloff {
-            // If scroll happens, all rows on screen must be updated
-            // TODO: Improve rendering on scrolling up/down using scroll region commands \x1b[M/\x1b[D.
-            // But scroll down region command was implemented in tmux recently and not included in
-            // stable release: https://github.com/tmux/tmux/commit/45f4ff54850ff9b448070a96b33e63451f973e33
-            self.set_dirty_start(self.rowoff);
+            let n = if self.rowoff >= prev_rowoff {
+                folds.count(prev_rowoff, self.rowoff) as isize
+            } else {
+                -(folds.count(self.rowoff, prev_rowoff) as isize)
+            };
+            let overlapped = self.overlay.is_some() || self.popup.is_some();
+            if prev_coloff == self.coloff
+                && self.synced
+                && !overlapped
+                && self.diff_side.is_none()
+                && n.unsigned_abs() < self.num_rows
+            {
+                // Fast path of vertical scroll. Lines on screen are moved by scroll region and
+                // only lines scrolled into the view are rendered when scrolling down
+                self.scroll += n;
+                let exposed = if n > 0 {
+                    let shown = self.num_rows - n as usize;
+                    folds.down(self.rowoff, shown, rows.len())
+                } else {
+                    self.rowoff
+                };
+                self.set_dirty_start(exposed);
+            } else {
+                // If scroll happens, all rows on screen must be updated
+                self.set_dirty_start(self.rowoff);
+            }
         }
     }
 
@@ -395,29 +1034,80 @@ impl<W: Write> Screen<W> {
         &mut self,
         buf: &TextBuffer,
         hl: &mut Highlighting,
+        diff: Option<DiffPane<'_>>,
         status_bar: &StatusBar,
-    ) -> io::Result<()> {
-        self.do_scroll(buf.rows(), buf.cx(), buf.cy());
-        hl.update(buf.rows(), self.rowoff + self.num_rows);
-        self.redraw(buf, hl, status_bar)?;
+    ) -> Result<()> {
+        let gutter = if buf.has_vcs() { 1 } else { 0 };
+        if gutter != self.gutter {
+            self.gutter = gutter;
+            self.set_dirty_start(0);
+        }
+        let diff_side = diff.as_ref().map(|d| d.side);
+        if diff_side != self.diff_side {
+            self.diff_side = diff_side;
+            self.set_dirty_start(0);
+        }
+        self.do_scroll(buf.rows(), buf.folds(), buf.cx(), buf.cy());
+        if diff_side.is_some() {
+            // The other pane follows the scroll of the current pane
+            self.set_dirty_start(self.rowoff);
+        }
+        let area = self.popup.as_ref().and_then(|p| self.layout_popup(buf, p));
+        if area != self.popup_area {
+            // Render rows under both of the previous area and the new one
+            let top = self
+                .popup_area
+                .iter()
+                .chain(area.iter())
+                .map(|a| a.top)
+                .min();
+            if let Some(top) = top {
+                self.set_dirty_start(self.rowoff + top);
+            }
+            self.popup_area = area;
+        }
+        let cursor_line = Some(buf.cy()).filter(|_| self.cursor_line);
+        if cursor_line != self.cursor_line_drawn {
+            if let Some(y) = cursor_line.into_iter().chain(self.cursor_line_drawn).min() {
+                self.set_dirty_start(y);
+            }
+            self.cursor_line_drawn = cursor_line;
+        }
+        hl.update(buf.rows(), self.bottom_line(buf));
+        let other = diff.map(|d| {
+            let (rows, folds) = (d.buf.rows(), d.buf.folds());
+            let y = diff::corresponding_line(d.hunks, d.side, self.rowoff);
+            let rowoff = folds.visible_line(cmp::min(y, rows.len()));
+            d.hl.update(rows, folds.down(rowoff, self.num_rows, rows.len()));
+            let pane = Pane {
+                rowoff,
+                side: 1 - d.side,
+                hunks: d.hunks,
+            };
+            (d.buf, &*d.hl, pane)
+        });
+        self.redraw(buf, hl, other, status_bar)?;
         self.dirty_start = None;
         self.cursor_moved = false;
         Ok(())
     }
 
-    pub fn draw_help(&mut self) -> io::Result<()> {
-        let help: Vec<_> = HELP
-            .split('\n')
-            .skip_while(|s| !s.contains(':'))
-            .map(str::trim_start)
-            .collect();
+    // Help longer than the text area is shown from the line at `offset` and the last row shows the
+    // scroll position. Returns the offset clamped so that the last page fills the text area
+    pub fn draw_help(&mut self, help: &str, offset: usize) -> Result<usize> {
+        let help: Vec<_> = help.split('\n').collect();
 
-        let vertical_margin = if help.len() < self.num_rows {
-            (self.num_rows - help.len()) / 2
+        let scrollable = help.len() > self.num_rows;
+        let (height, offset) = if scrollable {
+            let height = self.num_rows.saturating_sub(1);
+            (height, cmp::min(offset, help.len() - height))
         } else {
-            0
+            (help.len(), 0)
         };
-        let help_max_width = help.iter().map(|l| l.len()).max().unwrap();;
+        let vertical_margin = (self.num_rows - height) / 2;
+        let vertical_margin = if scrollable { 0 } else { vertical_margin };
+        // Width of all lines so that the help does not move horizontally while scrolling
+        let help_max_width = help.iter().map(|l| l.len()).max().unwrap();
         let left_margin = if help_max_width < self.num_cols {
             (self.num_cols - help_max_width) / 2
         } else {
@@ -432,21 +1122,23 @@ impl<W: Write> Screen<W> {
         }
 
         let left_pad = " ".repeat(left_margin);
-        let help_height = cmp::min(vertical_margin + help.len(), self.num_rows);
+        let help_height = vertical_margin + height;
         for y in vertical_margin..help_height {
-            let idx = y - vertical_margin;
+            let idx = y - vertical_margin + offset;
             write!(buf, "\x1b[{}H", y + 1)?;
             buf.write(left_pad.as_bytes())?;
 
-            let help = &help[idx][..cmp::min(help[idx].len(), self.num_cols)];
-            buf.write(AnsiColor::Cyan.sequence(self.color_support))?;
-            let mut cols = help.split(':');
-            if let Some(col) = cols.next() {
-                buf.write(col.as_bytes())?;
-            }
-            buf.write(AnsiColor::Reset.sequence(self.color_support))?;
-            if let Some(col) = cols.next() {
-                write!(buf, ":{}", col)?;
+            let help = match help[idx].char_indices().nth(self.num_cols) {
+                Some((i, _)) => &help[idx][..i],
+                None => help[idx],
+            };
+            buf.write(self.palette.sequence(AnsiColor::Cyan))?;
+            // Key may contain ':' so split at the separator between key and description
+            let (keys, desc) = help.split_once(" : ").unwrap_or((help, ""));
+            buf.write(keys.as_bytes())?;
+            buf.write(self.palette.sequence(AnsiColor::Reset))?;
+            if !desc.is_empty() {
+                write!(buf, " : {}", desc)?;
             }
 
             buf.write(b"\x1b[K")?;
@@ -457,7 +1149,55 @@ impl<W: Write> Screen<W> {
             buf.write(b"\x1b[K")?;
         }
 
-        self.write_flush(&buf)
+        if scrollable && self.num_rows > 0 {
+            let position = format!(
+                "Lines {}-{} of {} (UP/DOWN/PAGE-UP/PAGE-DOWN to scroll, other key to close)",
+                offset + 1,
+                offset + height,
+                help.len(),
+            );
+            let position: String = position.chars().take(self.num_cols).collect();
+            write!(buf, "\x1b[{}H", self.num_rows)?;
+            buf.write(self.palette.sequence(AnsiColor::Invert))?;
+            buf.write(position.as_bytes())?;
+            buf.write(self.palette.sequence(AnsiColor::Reset))?;
+            buf.write(b"\x1b[K")?;
+        }
+
+        self.present(&buf)?;
+        Ok(offset)
+    }
+
+    // Set text to clipboard of terminal with OSC 52 sequence
+    pub fn set_clipboard(&mut self, text: &str) -> Result<()> {
+        self.write_flush(clipboard::osc52_sequence(text).as_bytes())
+    }
+
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.palette = Palette::new(self.color_support, theme);
+        self.dirty_start = Some(0); // Render entire screen with new colors
+    }
+
+    // Back to normal screen buffer temporarily e.g. while the process is suspended
+    pub fn suspend(&mut self) -> Result<()> {
+        self.write_flush(LEAVE_SEQUENCE)
+    }
+
+    // Enter alternate screen buffer again. Entire screen is rendered since the terminal may be
+    // overwritten while suspended
+    pub fn resume(&mut self) -> Result<()> {
+        self.write_flush(ENTER_SEQUENCE)?;
+        self.synced = false;
+        self.dirty_start = Some(0);
+        self.cursor_moved = true;
+        Ok(())
+    }
+
+    // Write all cells on next refresh even if they are not changed. It fixes the screen broken by
+    // other programs
+    pub fn force_redraw(&mut self) {
+        self.synced = false;
+        self.set_dirty_start(self.rowoff);
     }
 
     pub fn set_dirty_start(&mut self, start: usize) {
@@ -469,29 +1209,228 @@ impl<W: Write> Screen<W> {
         self.dirty_start = Some(start);
     }
 
-    pub fn maybe_resize<I>(&mut self, input: I) -> io::Result<bool>
-    where
-        I: Iterator<Item = io::Result<InputSeq>>,
-    {
-        if !self.sigwinch.notified() {
-            return Ok(false); // Did not receive signal
+    // Returns true when the input is a resize event from frontend
+    pub fn maybe_resize(&mut self, seq: &InputSeq) -> bool {
+        let (w, h) = match seq.key {
+            KeySeq::Resize(w, h) => (w, h),
+            _ => return false,
+        };
+        let (num_cols, num_rows) = screen_size(w, h);
+        self.num_cols = num_cols;
+        self.num_rows = num_rows;
+        self.frame = Grid::new(num_cols, num_rows + 2);
+        self.synced = false;
+        // Scroll offsets are adjusted to the new size on next refresh. Render entire screen
+        // since all lines on screen may be moved
+        self.dirty_start = Some(0);
+        true
+    }
+
+    pub fn set_ambiguous_width(&mut self, width: AmbiguousWidth) {
+        self.probing = false;
+        self.probe_requested = false;
+        match width {
+            AmbiguousWidth::Auto => {
+                self.probe_requested = true;
+                self.dirty_start = Some(0);
+            }
+            AmbiguousWidth::Narrow => self.set_ambiguous_wide(false),
+            AmbiguousWidth::Wide => self.set_ambiguous_wide(true),
         }
+    }
 
-        let (w, h) = get_window_size(input, &mut self.output)?;
-        self.num_rows = h.saturating_sub(2);
-        self.num_cols = w;
-        self.dirty_start = Some(0);
-        Ok(true)
+    fn set_ambiguous_wide(&mut self, wide: bool) {
+        if grapheme::ambiguous_wide() != wide {
+            grapheme::set_ambiguous_wide(wide);
+            // Positions of all characters after ambiguous ones may move
+            self.synced = false;
+            self.dirty_start = Some(0);
+        }
+    }
+
+    // Cursor position reports are only requested by the probe of ambiguous width. Returns true when
+    // the input was a report
+    pub fn maybe_cursor_report(&mut self, seq: &InputSeq) -> bool {
+        let col = match seq.key {
+            KeySeq::Cursor(_, col) => col,
+            _ => return false,
+        };
+        if self.probing {
+            self.probing = false;
+            self.set_ambiguous_wide(col > 2);
+        }
+        true
+    }
+
+    pub fn set_scrolloff(&mut self, scrolloff: usize) {
+        self.scrolloff = scrolloff;
+    }
+
+    pub fn set_sidescroll(&mut self, sidescroll: usize) {
+        self.sidescroll = sidescroll;
+    }
+
+    pub fn invisibles(&self) -> bool {
+        self.invisibles
+    }
+
+    pub fn set_invisibles(&mut self, enabled: bool) {
+        if self.invisibles != enabled {
+            self.invisibles = enabled;
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    pub fn set_cursor_line(&mut self, enabled: bool) {
+        if self.cursor_line != enabled {
+            self.cursor_line = enabled;
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    pub fn indent_guides(&self) -> bool {
+        self.indent_guides
+    }
+
+    pub fn set_indent_guides(&mut self, enabled: bool) {
+        if self.indent_guides != enabled {
+            self.indent_guides = enabled;
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    pub fn set_scrollbar(&mut self, enabled: bool) {
+        if self.scrollbar != enabled {
+            self.scrollbar = enabled;
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    // The line at the position of the scrollbar clicked at (row, col). Both are 1-based
+    pub fn scrollbar_line(&self, row: usize, col: usize, len: usize) -> Option<usize> {
+        if !self.scrollbar || col != self.num_cols || row == 0 || row > self.num_rows {
+            return None;
+        }
+        let (start, _) = self.scrollbar_range(row - 1, len);
+        Some(cmp::min(start, len.saturating_sub(1)))
+    }
+
+    pub fn set_rulers(&mut self, rulers: &[usize]) {
+        let mut rulers = rulers.to_vec();
+        rulers.sort_unstable();
+        rulers.dedup();
+        if self.rulers != rulers {
+            self.rulers = rulers;
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    // Index of the last character on the screen line not hidden by the marker of the hidden text
+    pub fn end_of_screen_line(&self, row: &Row) -> usize {
+        let end = self.coloff + self.text_cols();
+        if row.rx_from_cx(row.len()) < end {
+            return row.len();
+        }
+        let right = if self.hidden_right(row) { 2 } else { 1 };
+        row.cx_from_rx(end - right)
+    }
+
+    // Scroll margin is at most half of the screen so that the cursor can always be placed
+    pub fn scroll_margin(&self) -> usize {
+        cmp::min(self.scrolloff, self.num_rows.saturating_sub(1) / 2)
+    }
+
+    fn set_rowoff(&mut self, rowoff: usize) {
+        if rowoff != self.rowoff {
+            self.rowoff = rowoff;
+            self.set_dirty_start(rowoff);
+        }
+    }
+
+    // Scroll screen so that the line is at the center. Used on jumping to a distant line
+    pub fn center_on(&mut self, y: usize, folds: &Folds) {
+        self.set_rowoff(folds.up(y, self.num_rows / 2));
+    }
+
+    // Scroll screen so that the line is at the top or bottom leaving the scroll margin
+    pub fn top_on(&mut self, y: usize, folds: &Folds) {
+        self.set_rowoff(folds.up(y, self.scroll_margin()));
+    }
+
+    pub fn bottom_on(&mut self, y: usize, folds: &Folds) {
+        let above = self.num_rows.saturating_sub(self.scroll_margin() + 1);
+        self.set_rowoff(folds.up(y, above));
+    }
+
+    // The line after the last line in the text area
+    pub fn bottom_line(&self, buf: &TextBuffer) -> usize {
+        buf.folds()
+            .down(self.rowoff, self.num_rows, buf.rows().len())
+    }
+
+    pub fn set_overlay(&mut self, lines: Vec<String>, selected: usize) {
+        self.overlay = Some((lines, selected));
+        // Rows under the previous overlay must be restored when it is shrunk
+        self.set_dirty_start(self.rowoff);
+    }
+
+    pub fn clear_overlay(&mut self) {
+        if self.overlay.take().is_some() {
+            self.set_dirty_start(self.rowoff);
+        }
+    }
+
+    pub fn set_popup(&mut self, lines: Vec<String>, selected: usize, x: usize) {
+        // The area is updated on next refresh. Rows under the current area must be rendered again
+        // when only the contents are changed
+        if let Some(area) = self.popup_area {
+            self.set_dirty_start(self.rowoff + area.top);
+        }
+        self.popup = Some(Popup { lines, selected, x });
+    }
+
+    pub fn clear_popup(&mut self) {
+        self.popup = None;
     }
 
     pub fn set_info_message<S: Into<String>>(&mut self, message: S) {
+        let message = message.into();
+        self.log.push(MessageLevel::Info, &message);
         self.message = Some(StatusMessage::new(message, StatusMessageKind::Info));
     }
 
     pub fn set_error_message<S: Into<String>>(&mut self, message: S) {
+        let message = message.into();
+        self.log.push(MessageLevel::Error, &message);
         self.message = Some(StatusMessage::new(message, StatusMessageKind::Error));
     }
 
+    // Message of prompt input is updated on each key input so it is not recorded in the log
+    pub fn set_prompt_message<S: Into<String>>(&mut self, message: S) {
+        self.message = Some(StatusMessage::new(message, StatusMessageKind::Prompt));
+    }
+
+    // While the prompt cursor is set, the terminal cursor is put in the message bar instead of text
+    pub fn set_prompt_cursor(&mut self, cursor: Option<usize>) {
+        self.prompt_cursor = cursor;
+        self.cursor_moved = true;
+    }
+
+    // Cursor position in the message bar. 1-based
+    // The hardware cursor is put exactly at the insertion point in the prompt since input methods
+    // show the composition at the cursor
+    fn prompt_cursor_position(&self) -> Option<(usize, usize)> {
+        let idx = self.prompt_cursor?;
+        let text = &self.message.as_ref()?.text;
+        let (start, _) = visible_range(text, Some(idx), self.num_cols);
+        let width = text.get(start..idx).map_or(0, grapheme::str_width);
+        Some((self.num_rows + 2, cmp::min(width, self.num_cols - 1) + 1))
+    }
+
+    pub fn message_log(&self) -> &MessageLog {
+        &self.log
+    }
+
     pub fn rows(&self) -> usize {
         self.num_rows
     }
@@ -500,17 +1439,50 @@ impl<W: Write> Screen<W> {
         self.num_cols
     }
 
+    // The first column of the pane of the current buffer
+    pub fn text_left(&self) -> usize {
+        self.pane_left(self.diff_side.unwrap_or(0))
+    }
+
+    pub fn gutter_width(&self) -> usize {
+        self.gutter
+    }
+
     pub fn message_text(&self) -> &'_ str {
         self.message.as_ref().map(|m| m.text.as_str()).unwrap_or("")
     }
+
+    // Text on the screen as the terminal shows it, including the status bar and the message bar
+    // at the bottom
+    pub fn rendered_lines(&self) -> Vec<String> {
+        (0..self.num_rows + 2).map(|y| self.frame.text(y)).collect()
+    }
+
+    // 0-based position of the cursor on the screen as (column, row)
+    pub fn rendered_cursor(&self) -> (usize, usize) {
+        self.frame.cursor()
+    }
+
+    pub fn rendered_background(&self, x: usize, y: usize) -> Color {
+        self.frame.background(x, y)
+    }
+}
+
+// Back to normal screen buffer before the panic message is printed. Called by the panic hook of the
+// terminal frontend
+#[cfg(feature = "terminal")]
+pub fn leave_on_panic<W: Write>(mut output: W) {
+    output.write_all(LEAVE_SEQUENCE).ok();
+    output.flush().ok();
+    LEFT_ON_PANIC.store(true, Ordering::SeqCst);
 }
 
 impl<W: Write> Drop for Screen<W> {
     fn drop(&mut self) {
-        // Back to normal screen buffer from alternate screen buffer
-        // https://www.xfree86.org/current/ctlseqs.html#The%20Alternate%20Screen%20Buffer
-        // Note that we used \x1b[2J\x1b[H previously but it did not erase screen.
-        self.write_flush(b"\x1b[?47l\x1b[H")
+        if LEFT_ON_PANIC.load(Ordering::SeqCst) {
+            return;
+        }
+        self.write_flush(LEAVE_SEQUENCE)
             .expect("Back to normal screen buffer");
     }
 }
@@ -0,0 +1,335 @@
+// Init script written in Lua. ~/.config/kiro/init.lua is run on startup and defines commands which
+// are run like commands of plugins. The script accesses the editor through `kiro` table:
+//
+//   kiro.command(name, description, function)  Define a command. Only while the script is loaded
+//   kiro.bind(key, name)                        Bind a key such as "Alt-U" to the command
+//   kiro.message(text), kiro.error(text)        Show a message in the status bar
+//   kiro.buffer.line(n)                         Text of line n or nil
+//   kiro.buffer.line_count()
+//   kiro.buffer.filename(), kiro.buffer.language()
+//   kiro.buffer.insert(text)                    Insert text at cursor replacing selected text
+//   kiro.buffer.set_line(n, text)               Replace line n with the text
+//   kiro.cursor.line(), kiro.cursor.column()
+//   kiro.cursor.set(line, column)
+//   kiro.selection.text()                       Selected text or nil
+//
+// Lines and columns are 1-based as indices of Lua. Columns count characters. Edits are applied
+// after the command returns, so reading functions see the buffer at the time the command started.
+// print() shows its arguments in the status bar.
+
+use crate::error::{Context, Error, Result};
+use crate::lua::{self, check_num, check_str, library, Interp, Value};
+use crate::plugin::{Action, Plugin, PluginCommand, Request};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// State of the current buffer while a command is running
+struct Buffer {
+    filename: String,
+    lang: &'static str,
+    cursor: (usize, usize),
+    selection: Option<String>,
+    lines: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+    commands: Vec<(PluginCommand, Value)>,
+    loaded: bool,
+    buffer: Option<Buffer>,
+    actions: Vec<Action>,
+}
+
+type Shared = Rc<RefCell<State>>;
+
+fn with_buffer<T>(state: &Shared, f: impl FnOnce(&Buffer) -> T) -> lua::Result<T> {
+    match &state.borrow().buffer {
+        Some(buf) => Ok(f(buf)),
+        None => Err(lua::Error::new(
+            "Buffer is not available while loading init script",
+        )),
+    }
+}
+
+// Positive integer argument converted to 0-based index
+fn check_index(name: &str, args: &[Value], i: usize) -> lua::Result<usize> {
+    let n = check_num(name, args, i)?;
+    if n < 1.0 || n.fract() != 0.0 {
+        return Err(lua::Error::new(format!(
+            "Bad argument #{} to '{}' (positive integer expected, got {})",
+            i + 1,
+            name,
+            args[i],
+        )));
+    }
+    Ok(n as usize - 1)
+}
+
+fn optional(s: Option<&str>) -> Vec<Value> {
+    vec![s.map(Value::string).unwrap_or(Value::Nil)]
+}
+
+fn action(state: &Shared, name: &'static str, f: fn(String) -> Action) -> Value {
+    let state = state.clone();
+    Value::builtin(move |_, args| {
+        let text = check_str(name, &args, 0)?;
+        state.borrow_mut().actions.push(f(text.to_string()));
+        Ok(vec![])
+    })
+}
+
+fn buffer_api(state: &Shared) -> Value {
+    let (s1, s2, s3, s4, s5, s6) = (
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+        state.clone(),
+    );
+    library(vec![
+        (
+            "line",
+            Value::builtin(move |_, args| {
+                let y = check_index("line", &args, 0)?;
+                with_buffer(&s1, |b| optional(b.lines.get(y).map(String::as_str)))
+            }),
+        ),
+        (
+            "line_count",
+            Value::builtin(move |_, _| {
+                with_buffer(&s2, |b| vec![Value::Number(b.lines.len() as f64)])
+            }),
+        ),
+        (
+            "filename",
+            Value::builtin(move |_, _| with_buffer(&s3, |b| vec![Value::string(&b.filename)])),
+        ),
+        (
+            "language",
+            Value::builtin(move |_, _| with_buffer(&s4, |b| vec![Value::string(b.lang)])),
+        ),
+        (
+            "insert",
+            Value::builtin(move |_, args| {
+                let text = check_str("insert", &args, 0)?;
+                with_buffer(&s5, |_| ())?;
+                s5.borrow_mut()
+                    .actions
+                    .push(Action::Insert(text.to_string()));
+                Ok(vec![])
+            }),
+        ),
+        (
+            "set_line",
+            Value::builtin(move |_, args| {
+                let y = check_index("set_line", &args, 0)?;
+                let text = check_str("set_line", &args, 1)?;
+                let len = with_buffer(&s6, |b| b.lines.len())?;
+                if y >= len {
+                    return Err(lua::Error::new(format!("Line {} is out of range", y + 1)));
+                }
+                if text.contains('\n') {
+                    return Err(lua::Error::new("Line must not contain newline"));
+                }
+                let action = Action::ReplaceLine(y, text.to_string());
+                s6.borrow_mut().actions.push(action);
+                Ok(vec![])
+            }),
+        ),
+    ])
+}
+
+fn cursor_api(state: &Shared) -> Value {
+    let (s1, s2, s3) = (state.clone(), state.clone(), state.clone());
+    library(vec![
+        (
+            "line",
+            Value::builtin(move |_, _| {
+                with_buffer(&s1, |b| vec![Value::Number((b.cursor.1 + 1) as f64)])
+            }),
+        ),
+        (
+            "column",
+            Value::builtin(move |_, _| {
+                with_buffer(&s2, |b| vec![Value::Number((b.cursor.0 + 1) as f64)])
+            }),
+        ),
+        (
+            "set",
+            Value::builtin(move |_, args| {
+                let y = check_index("set", &args, 0)?;
+                let x = check_index("set", &args, 1)?;
+                with_buffer(&s3, |_| ())?;
+                s3.borrow_mut().actions.push(Action::MoveCursor(x, y));
+                Ok(vec![])
+            }),
+        ),
+    ])
+}
+
+fn selection_api(state: &Shared) -> Value {
+    let state = state.clone();
+    library(vec![(
+        "text",
+        Value::builtin(move |_, _| with_buffer(&state, |b| optional(b.selection.as_deref()))),
+    )])
+}
+
+fn kiro_api(state: &Shared) -> Value {
+    let (s1, s2) = (state.clone(), state.clone());
+    library(vec![
+        (
+            "command",
+            Value::builtin(move |_, args| {
+                let name = check_str("command", &args, 0)?;
+                let desc = check_str("command", &args, 1)?;
+                let f = args.get(2).cloned().unwrap_or(Value::Nil);
+                if !matches!(f, Value::Function(_) | Value::Builtin(_)) {
+                    return Err(lua::Error::new(format!(
+                        "Bad argument #3 to 'command' (function expected, got {})",
+                        f.type_name()
+                    )));
+                }
+                let mut state = s1.borrow_mut();
+                if state.loaded {
+                    return Err(lua::Error::new(
+                        "Commands must be defined while init script is loaded",
+                    ));
+                }
+                if state.commands.iter().any(|(c, _)| *c.name == *name) {
+                    return Err(lua::Error::new(format!(
+                        "Command '{}' is defined twice",
+                        name
+                    )));
+                }
+                let cmd = PluginCommand {
+                    name: name.to_string(),
+                    description: desc.to_string(),
+                    keys: vec![],
+                };
+                state.commands.push((cmd, f));
+                Ok(vec![])
+            }),
+        ),
+        (
+            "bind",
+            Value::builtin(move |_, args| {
+                let key = check_str("bind", &args, 0)?;
+                let name = check_str("bind", &args, 1)?;
+                let mut state = s2.borrow_mut();
+                match state.commands.iter_mut().find(|(c, _)| *c.name == *name) {
+                    Some((cmd, _)) => cmd.keys.push(key.to_string()),
+                    None => return Err(lua::Error::new(format!("Unknown command '{}'", name))),
+                }
+                Ok(vec![])
+            }),
+        ),
+        ("message", action(state, "message", Action::Message)),
+        ("error", action(state, "error", Action::Error)),
+        ("buffer", buffer_api(state)),
+        ("cursor", cursor_api(state)),
+        ("selection", selection_api(state)),
+    ])
+}
+
+pub struct InitScript {
+    name: String,
+    path: PathBuf,
+    interp: Interp,
+    state: Shared,
+}
+
+impl InitScript {
+    // Run the script at the path. None when the file does not exist
+    pub fn load(path: &Path) -> Result<Option<InitScript>> {
+        match fs::read_to_string(path) {
+            Ok(src) => Self::new(path, &src).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).context("reading", path),
+        }
+    }
+
+    // The file stem of the path is the name of the plugin such as 'init' for 'init.lua'
+    pub fn new(path: &Path, src: &str) -> Result<InitScript> {
+        let state = Shared::default();
+        let mut interp = Interp::new();
+        interp.set_global("kiro", kiro_api(&state));
+        let s = state.clone();
+        interp.set_global(
+            "print",
+            Value::builtin(move |_, args| {
+                let text: Vec<_> = args.iter().map(Value::to_string).collect();
+                s.borrow_mut().actions.push(Action::Message(text.join(" ")));
+                Ok(vec![])
+            }),
+        );
+        interp.exec(src).map_err(|e| Error::Config {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        state.borrow_mut().loaded = true;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        Ok(InitScript {
+            name: name.into_owned(),
+            path: path.to_path_buf(),
+            interp,
+            state,
+        })
+    }
+}
+
+impl Plugin for InitScript {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commands(&self) -> Vec<PluginCommand> {
+        self.state
+            .borrow()
+            .commands
+            .iter()
+            .map(|(c, _)| PluginCommand {
+                name: c.name.clone(),
+                description: c.description.clone(),
+                keys: c.keys.clone(),
+            })
+            .collect()
+    }
+
+    fn run(&mut self, req: &Request<'_>) -> Result<Vec<Action>> {
+        let f = {
+            let mut state = self.state.borrow_mut();
+            let f = state.commands.iter().find(|(c, _)| c.name == req.command);
+            let f = match f {
+                Some((_, f)) => f.clone(),
+                None => return Err(Error::other(format!("Unknown command '{}'", req.command))),
+            };
+            state.actions.clear();
+            state.buffer = Some(Buffer {
+                filename: req.filename.to_string(),
+                lang: req.lang.name(),
+                cursor: req.cursor,
+                selection: req.selection.clone(),
+                lines: req.lines.iter().map(|l| l.to_string()).collect(),
+            });
+            f
+        };
+        let result = self.interp.call(&f, vec![]);
+        let mut state = self.state.borrow_mut();
+        state.buffer = None;
+        let actions = std::mem::take(&mut state.actions);
+        match result {
+            Ok(_) => Ok(actions),
+            Err(e) => Err(Error::other(format!(
+                "Command '{}' failed: {} in {}",
+                req.command,
+                e,
+                self.path.display()
+            ))),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Matcher of text search. Searching lines of a large buffer on each key input of incremental search
+// must be fast, so lines which do not match are rejected without decoding their characters. Plain
+// text query is searched with `str::find` which uses memchr and the Two-Way algorithm. Regular
+// expression is only run on lines containing one of the literals its matches require.
+//
+// Positions of matches are character indices so that they can be used as x coordinates of Row.
+
+use crate::regex::{ParseError, Regex};
+
+pub enum Matcher {
+    Text(String),
+    Regex(Regex),
+}
+
+// Character index of the byte index in the line. `base` is a pair of known byte index and character
+// index before the byte index to avoid counting characters from the head of line again
+fn char_idx(line: &str, base: (usize, usize), byte_idx: usize) -> usize {
+    base.1 + line[base.0..byte_idx].chars().count()
+}
+
+impl Matcher {
+    pub fn new(query: &str, regex: bool) -> Result<Matcher, ParseError> {
+        if regex {
+            Regex::new(query).map(Matcher::Regex)
+        } else {
+            Ok(Matcher::Text(query.to_string()))
+        }
+    }
+
+    // Find the first match in the line at or after character index `from`. Returned range is
+    // character indices of start and end of the match. Nothing is found past the end of the line
+    // or by an empty query
+    pub fn find_at(&self, line: &str, from: usize) -> Option<(usize, usize)> {
+        if from > line.chars().count() {
+            return None;
+        }
+        let query = match self {
+            Matcher::Text(query) if query.is_empty() => return None,
+            Matcher::Text(query) => query,
+            Matcher::Regex(regex) => return regex.find_at(line, from),
+        };
+        let from_byte = line
+            .char_indices()
+            .nth(from)
+            .map(|(i, _)| i)
+            .unwrap_or(line.len());
+        let byte_idx = from_byte + line[from_byte..].find(query.as_str())?;
+        let start = char_idx(line, (from_byte, from), byte_idx);
+        Some((start, start + query.chars().count()))
+    }
+
+    // All non-overlapping matches in the line from left to right. Empty matches are not contained
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize)> {
+        let query = match self {
+            Matcher::Text(query) if query.is_empty() => return vec![],
+            Matcher::Text(query) => query,
+            Matcher::Regex(regex) => return regex.find_all(line),
+        };
+        let len = query.chars().count();
+        let mut base = (0, 0);
+        line.match_indices(query.as_str())
+            .map(|(byte_idx, _)| {
+                let start = char_idx(line, base, byte_idx);
+                base = (byte_idx + query.len(), start + len);
+                (start, start + len)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,83 @@
+// Session state kept across editor processes. Cursor position and scroll offset of each file are
+// restored when the file is opened again, and the files are listed as recent files. Each line of
+// the state file is a file ordered from the most recently used one:
+//
+//   {x}\t{y}\t{row offset}\t{absolute file path}
+//
+// The state file is only a cache. Broken lines are ignored and a missing or unreadable state file
+// is the same as an empty session so that it never prevents the editor from starting.
+
+use crate::error::{Context, Result};
+use crate::marks::file_key;
+use std::fs;
+use std::path::Path;
+
+// Number of files remembered. Older ones are dropped
+const MAX_FILES: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+    pub rowoff: usize,
+}
+
+#[derive(Default)]
+pub struct Session {
+    files: Vec<(String, Position)>, // Most recently used first
+}
+
+fn parse_line(line: &str) -> Option<(String, Position)> {
+    let mut fields = line.splitn(4, '\t');
+    let x = fields.next()?.parse().ok()?;
+    let y = fields.next()?.parse().ok()?;
+    let rowoff = fields.next()?.parse().ok()?;
+    let path = fields.next().filter(|p| !p.is_empty())?;
+    Some((path.to_string(), Position { x, y, rowoff }))
+}
+
+impl Session {
+    pub fn load(state: &Path) -> Session {
+        let source = fs::read_to_string(state).unwrap_or_default();
+        let mut files: Vec<(String, Position)> = vec![];
+        for (path, pos) in source.lines().filter_map(parse_line) {
+            if files.iter().all(|(p, _)| *p != path) {
+                files.push((path, pos));
+            }
+        }
+        files.truncate(MAX_FILES);
+        Session { files }
+    }
+
+    pub fn save(&self, state: &Path) -> Result<()> {
+        let content: String = self
+            .files
+            .iter()
+            .map(|(path, p)| format!("{}\t{}\t{}\t{}\n", p.x, p.y, p.rowoff, path))
+            .collect();
+        if let Some(dir) = state.parent() {
+            fs::create_dir_all(dir).context("creating", dir)?;
+        }
+        fs::write(state, content).context("saving", state)
+    }
+
+    pub fn position(&self, file: &Path) -> Option<Position> {
+        let key = file_key(file);
+        self.files
+            .iter()
+            .find(|(p, _)| *p == key)
+            .map(|(_, pos)| *pos)
+    }
+
+    // The file becomes the most recently used one
+    pub fn update(&mut self, file: &Path, pos: Position) {
+        let key = file_key(file);
+        self.files.retain(|(p, _)| *p != key);
+        self.files.insert(0, (key, pos));
+        self.files.truncate(MAX_FILES);
+    }
+
+    pub fn recent_files(&self) -> Vec<String> {
+        self.files.iter().map(|(p, _)| p.clone()).collect()
+    }
+}
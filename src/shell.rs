@@ -0,0 +1,101 @@
+// Run shell commands from the editor. Text is passed to stdin of the command and its stdout is
+// captured. Since all standard streams of the command are pipes, it does not touch the terminal in
+// raw mode and the screen is not broken while it runs.
+
+use crate::error::{Error, Result};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[cfg(windows)]
+fn shell() -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C");
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell() -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c");
+    cmd
+}
+
+// Command to run the command line with shell. Its standard streams are set by the caller
+pub fn command(cmdline: &str) -> Command {
+    let mut cmd = shell();
+    cmd.arg(cmdline);
+    cmd
+}
+
+// Returns stdout of the command. When it fails, the first line of its stderr is returned as error
+pub fn run(cmdline: &str, input: Option<&str>) -> Result<String> {
+    run_program(command(cmdline), cmdline, input)
+}
+
+// Run the program directly without shell. `label` is used for error messages
+pub fn run_program(mut cmd: Command, label: &str, input: Option<&str>) -> Result<String> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::subprocess(label, e))?;
+
+    // Write input in another thread since the command may block on writing its output before
+    // reading all input. Writing fails when the command exits without reading all input such as
+    // `head`. It is not an error
+    let writer = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => {
+            let input = input.to_string();
+            Some(thread::spawn(move || {
+                stdin.write_all(input.as_bytes()).ok()
+            }))
+        }
+        _ => None,
+    };
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::subprocess(label, e))?;
+    if let Some(writer) = writer {
+        writer.join().ok();
+    }
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.lines().find(|l| !l.trim().is_empty()) {
+            Some(line) => line.to_string(),
+            None => output.status.to_string(),
+        };
+        return Err(Error::subprocess(label, reason));
+    }
+    String::from_utf8(output.stdout).map_err(|_| Error::Utf8(format!("Output of '{}'", label)))
+}
+
+// Returns stdout and stderr of the command mixed in order of output, and whether it succeeded. It
+// is used for build commands whose errors are reported to stderr
+pub fn run_with_status(cmdline: &str) -> Result<(String, bool)> {
+    let error = |e: io::Error| Error::subprocess(cmdline, e);
+    let (mut reader, writer) = io::pipe().map_err(error)?;
+    let mut child = {
+        // Command must be dropped before reading the pipe. Otherwise the write end is kept open
+        // and reading never reaches EOF
+        let mut cmd = shell();
+        cmd.arg(cmdline)
+            .stdin(Stdio::null())
+            .stdout(writer.try_clone().map_err(error)?)
+            .stderr(writer);
+        cmd.spawn().map_err(error)?
+    };
+    let mut output = vec![];
+    reader.read_to_end(&mut output).map_err(error)?;
+    let status = child.wait().map_err(error)?;
+    Ok((
+        String::from_utf8_lossy(&output).into_owned(),
+        status.success(),
+    ))
+}
@@ -0,0 +1,402 @@
+// Spell checking with hunspell dictionaries. A dictionary consists of '.dic' file which lists stems
+// with flags of affixes and '.aff' file which defines the affixes:
+//
+//   # en_US.dic     # en_US.aff
+//   2               SFX S Y 1
+//   walk/S          SFX S 0 s [^sxzhy]
+//   word/S
+//
+// All words are expanded from stems and affixes on loading. Only prefixes and suffixes are
+// supported. Compounds and other options are ignored. A plain word list such as
+// /usr/share/dict/words is also available as dictionary.
+//
+// Identifier-like words (snake_case, camelCase, words with digits) and parts of paths or URLs are
+// not checked.
+
+use crate::error::{Context, Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_SUGGESTIONS: usize = 10;
+
+#[derive(Clone, Copy)]
+enum FlagType {
+    Char, // Single character (default)
+    Long, // Two characters
+    Num,  // Decimal numbers separated by ','
+}
+
+fn parse_flags(s: &str, ty: FlagType) -> Vec<String> {
+    match ty {
+        FlagType::Char => s.chars().map(String::from).collect(),
+        FlagType::Long => {
+            let chars: Vec<_> = s.chars().collect();
+            chars.chunks(2).map(|c| c.iter().collect()).collect()
+        }
+        FlagType::Num => s.split(',').map(String::from).collect(),
+    }
+}
+
+// One character of affix condition such as 'a', '.', '[aeiou]' or '[^aeiou]'
+enum Cond {
+    Any,
+    Chars(Vec<char>, bool), // Characters and whether they are negated
+}
+
+impl Cond {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Cond::Any => true,
+            Cond::Chars(chars, negated) => chars.contains(&c) != *negated,
+        }
+    }
+}
+
+fn parse_condition(s: &str) -> Vec<Cond> {
+    let mut conds = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        conds.push(match c {
+            '.' => Cond::Any,
+            '[' => {
+                let class: Vec<_> = chars.by_ref().take_while(|c| *c != ']').collect();
+                match class.split_first() {
+                    Some(('^', rest)) => Cond::Chars(rest.to_vec(), true),
+                    _ => Cond::Chars(class, false),
+                }
+            }
+            c => Cond::Chars(vec![c], false),
+        });
+    }
+    conds
+}
+
+struct Affix {
+    prefix: bool,
+    cross_product: bool, // Can be combined with affixes of the other kind
+    strip: String,
+    add: String,
+    cond: Vec<Cond>,
+}
+
+impl Affix {
+    fn apply(&self, word: &str) -> Option<String> {
+        let chars: Vec<_> = word.chars().collect();
+        if chars.len() < self.cond.len() {
+            return None;
+        }
+        if self.prefix {
+            let matched = self.cond.iter().zip(&chars).all(|(c, ch)| c.matches(*ch));
+            let rest = word.strip_prefix(self.strip.as_str()).filter(|_| matched)?;
+            Some(format!("{}{}", self.add, rest))
+        } else {
+            let tail = &chars[chars.len() - self.cond.len()..];
+            let matched = self.cond.iter().zip(tail).all(|(c, ch)| c.matches(*ch));
+            let rest = word.strip_suffix(self.strip.as_str()).filter(|_| matched)?;
+            Some(format!("{}{}", rest, self.add))
+        }
+    }
+}
+
+// Affixes keyed by their flags
+fn parse_aff(aff: &str) -> (HashMap<String, Vec<Affix>>, FlagType) {
+    let mut flag_type = FlagType::Char;
+    let mut affixes: HashMap<String, Vec<Affix>> = HashMap::new();
+    let mut cross_products = HashMap::new();
+    for line in aff.lines() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["FLAG", "long", ..] => flag_type = FlagType::Long,
+            ["FLAG", "num", ..] => flag_type = FlagType::Num,
+            // Header such as 'SFX S Y 1'
+            [kind @ ("PFX" | "SFX"), flag, cross, count]
+                if count.parse::<usize>().is_ok() && (*cross == "Y" || *cross == "N") =>
+            {
+                cross_products.insert((*kind, flag.to_string()), *cross == "Y");
+            }
+            [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                let zero = |s: &str| {
+                    if s == "0" {
+                        String::new()
+                    } else {
+                        s.to_string()
+                    }
+                };
+                let add = add.split('/').next().unwrap_or(""); // Continuation flags are ignored
+                let cross_product = cross_products
+                    .get(&(*kind, flag.to_string()))
+                    .copied()
+                    .unwrap_or(false);
+                affixes.entry(flag.to_string()).or_default().push(Affix {
+                    prefix: *kind == "PFX",
+                    cross_product,
+                    strip: zero(strip),
+                    add: zero(add),
+                    cond: parse_condition(rest.first().copied().unwrap_or(".")),
+                });
+            }
+            _ => {}
+        }
+    }
+    (affixes, flag_type)
+}
+
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    pub fn parse(dic: &str, aff: &str) -> Dictionary {
+        let (affixes, flag_type) = parse_aff(aff);
+        let mut words = HashSet::new();
+        // The first line is the number of stems
+        for line in dic.lines().skip(1) {
+            // Morphological fields follow after whitespace
+            let entry = line.split(['\t', ' ']).next().unwrap_or("");
+            let (stem, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            if stem.is_empty() {
+                continue;
+            }
+            let rules: Vec<&Affix> = parse_flags(flags, flag_type)
+                .iter()
+                .filter_map(|f| affixes.get(f))
+                .flatten()
+                .collect();
+            for sfx in rules.iter().filter(|a| !a.prefix) {
+                if let Some(word) = sfx.apply(stem) {
+                    for pfx in rules.iter().filter(|a| a.prefix && a.cross_product) {
+                        if let Some(w) = pfx.apply(&word).filter(|_| sfx.cross_product) {
+                            words.insert(w);
+                        }
+                    }
+                    words.insert(word);
+                }
+            }
+            for pfx in rules.iter().filter(|a| a.prefix) {
+                if let Some(word) = pfx.apply(stem) {
+                    words.insert(word);
+                }
+            }
+            words.insert(stem.to_string());
+        }
+        Dictionary { words }
+    }
+
+    pub fn from_words(list: &str) -> Dictionary {
+        let words = list
+            .lines()
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(String::from)
+            .collect();
+        Dictionary { words }
+    }
+
+    // `path` is a hunspell dictionary with or without '.dic' extension, or a word list
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Dictionary> {
+        let path = path.as_ref();
+        let read = |p: &Path| {
+            fs::read(p)
+                .map(|b| String::from_utf8_lossy(&b).into_owned())
+                .context("reading dictionary", p)
+        };
+        let dic = if path.extension().is_some_and(|e| e == "dic") {
+            path.to_path_buf()
+        } else {
+            path.with_extension("dic")
+        };
+        if !dic.is_file() {
+            return read(path).map(|list| Self::from_words(&list));
+        }
+        let aff = read(&dic.with_extension("aff")).unwrap_or_default();
+        Ok(Self::parse(&read(&dic)?, &aff))
+    }
+
+    // Find a dictionary of the language of locale from hunspell's directories. Word list of the
+    // system is used when no hunspell dictionary is installed
+    pub fn find() -> Result<Dictionary> {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|v| env::var(v).ok())
+            .find(|v| !v.is_empty() && v != "C" && v != "POSIX");
+        let lang = locale
+            .as_deref()
+            .and_then(|l| l.split(['.', '@']).next())
+            .unwrap_or("en_US");
+        let mut dirs: Vec<PathBuf> = env::var_os("DICPATH")
+            .map(|p| env::split_paths(&p).collect())
+            .unwrap_or_default();
+        dirs.extend(
+            [
+                "/usr/share/hunspell",
+                "/usr/share/myspell",
+                "/usr/share/myspell/dicts",
+                "/usr/local/share/hunspell",
+                "/Library/Spelling",
+            ]
+            .iter()
+            .map(PathBuf::from),
+        );
+        if let Some(home) = env::var_os("HOME") {
+            dirs.push(Path::new(&home).join("Library").join("Spelling"));
+        }
+        for name in [lang, "en_US"] {
+            let found = dirs.iter().map(|d| d.join(format!("{}.dic", name)));
+            if let Some(dic) = found.into_iter().find(|p| p.is_file()) {
+                return Self::load(dic);
+            }
+        }
+        let words = Path::new("/usr/share/dict/words");
+        if words.is_file() {
+            return Self::load(words);
+        }
+        Err(Error::other(
+            "Dictionary not found. Please set path to it in 'dictionary' option",
+        ))
+    }
+
+    // Words at start of sentence and words in upper case are checked in lower case and in
+    // capitalized form
+    pub fn check(&self, word: &str) -> bool {
+        if self.words.contains(word) {
+            return true;
+        }
+        let lower = word.to_lowercase();
+        if lower != word && self.words.contains(&lower) {
+            return true;
+        }
+        word.chars().all(|c| !c.is_lowercase()) && self.words.contains(&capitalize(&lower))
+    }
+
+    // Known words within one edit (transpose, replace, delete, insert) or split into two words
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let chars: Vec<_> = word.chars().collect();
+        let mut alphabet: Vec<_> = ('a'..='z').collect();
+        alphabet.extend(word.to_lowercase().chars().filter(|c| !c.is_ascii()));
+        let mut edits = vec![];
+        for i in 0..chars.len().saturating_sub(1) {
+            let mut w = chars.clone();
+            w.swap(i, i + 1);
+            edits.push(w.into_iter().collect::<String>());
+        }
+        for i in 0..chars.len() {
+            for c in &alphabet {
+                let mut w = chars.clone();
+                w[i] = *c;
+                edits.push(w.into_iter().collect());
+            }
+        }
+        for i in 0..chars.len() {
+            let mut w = chars.clone();
+            w.remove(i);
+            edits.push(w.into_iter().collect());
+        }
+        for i in 0..=chars.len() {
+            for c in &alphabet {
+                let mut w = chars.clone();
+                w.insert(i, *c);
+                edits.push(w.into_iter().collect());
+            }
+        }
+
+        let capitalized = chars.first().is_some_and(|c| c.is_uppercase());
+        let mut suggestions: Vec<String> = vec![];
+        let mut push = |w: String| {
+            let w = if capitalized { capitalize(&w) } else { w };
+            if !suggestions.contains(&w) {
+                suggestions.push(w);
+            }
+        };
+        for w in edits {
+            if w != word && self.check(&w) {
+                push(w);
+            }
+        }
+        for i in 1..chars.len() {
+            let (l, r): (String, String) =
+                (chars[..i].iter().collect(), chars[i..].iter().collect());
+            if self.check(&l) && self.check(&r) {
+                push(format!("{} {}", l, r));
+            }
+        }
+        suggestions.truncate(MAX_SUGGESTIONS);
+        suggestions
+    }
+
+    // Ranges of misspelled words in the line in characters. `checked` decides whether the
+    // character at the index is a part of text to check such as comments or strings
+    pub fn misspelled<F: Fn(usize) -> bool>(&self, line: &str, checked: F) -> Vec<(usize, usize)> {
+        let chars: Vec<_> = line.chars().collect();
+        words(&chars)
+            .into_iter()
+            .filter(|(s, e)| (*s..*e).all(&checked))
+            .filter(|(s, e)| !self.check(&chars[*s..*e].iter().collect::<String>()))
+            .collect()
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+// Ranges of words to be checked in the characters
+fn words(chars: &[char]) -> Vec<(usize, usize)> {
+    let mut words = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_word_char(chars[i]) {
+            i += 1;
+            continue;
+        }
+        let mut start = i;
+        while i < chars.len() && is_word_char(chars[i]) {
+            i += 1;
+        }
+        let mut end = i;
+        // Apostrophes are a part of word only in the middle such as "don't"
+        while start < end && chars[start] == '\'' {
+            start += 1;
+        }
+        while start < end && chars[end - 1] == '\'' {
+            end -= 1;
+        }
+        if end - start < 2 {
+            continue;
+        }
+
+        let word = &chars[start..end];
+        let identifier = word.iter().any(|c| c.is_numeric() || *c == '_')
+            || (word.iter().any(|c| c.is_lowercase())
+                && word[1..].iter().any(|c| c.is_uppercase()));
+        // Parts of paths, URLs, e-mail addresses or qualified names such as 'foo.bar'
+        let at = |i: Option<usize>| i.and_then(|i| chars.get(i)).copied().unwrap_or(' ');
+        let (prev, before_prev) = (at(start.checked_sub(1)), at(start.checked_sub(2)));
+        let (next, after_next) = (at(Some(end)), at(Some(end + 1)));
+        let in_path = matches!(prev, '/' | '\\' | '@')
+            || matches!(next, '/' | '\\' | '@')
+            || prev == '.' && before_prev.is_alphanumeric()
+            || matches!(next, '.' | ':') && (after_next.is_alphanumeric() || after_next == '/');
+        if !identifier && !in_path {
+            words.push((start, end));
+        }
+    }
+    words
+}
+
+// Range of the word at or just before character index `cx`
+pub fn word_at(line: &str, cx: usize) -> Option<(usize, usize)> {
+    let chars: Vec<_> = line.chars().collect();
+    words(&chars)
+        .into_iter()
+        .find(|(s, e)| *s <= cx && cx <= *e)
+}
@@ -1,4 +1,93 @@
 use crate::language::Language;
+use crate::toml::{Table, Value};
+
+// Pieces of information shown in status bar
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Segment {
+    Filename,
+    Modified,
+    Buffers,
+    Language,
+    Position,
+    Percentage,
+    Encoding,
+    Indent,
+}
+
+impl Segment {
+    const ALL: &'static [Segment] = &[
+        Segment::Filename,
+        Segment::Modified,
+        Segment::Buffers,
+        Segment::Language,
+        Segment::Position,
+        Segment::Percentage,
+        Segment::Encoding,
+        Segment::Indent,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Segment::Filename => "filename",
+            Segment::Modified => "modified",
+            Segment::Buffers => "buffers",
+            Segment::Language => "language",
+            Segment::Position => "position",
+            Segment::Percentage => "percentage",
+            Segment::Encoding => "encoding",
+            Segment::Indent => "indent",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Segment> {
+        Segment::ALL.iter().find(|s| s.name() == name).copied()
+    }
+}
+
+// Which segments are shown at left and right sides of status bar, in order
+#[derive(Clone, PartialEq, Debug)]
+pub struct StatusLayout {
+    pub left: Vec<Segment>,
+    pub right: Vec<Segment>,
+}
+
+impl Default for StatusLayout {
+    fn default() -> Self {
+        use Segment::*;
+        StatusLayout {
+            left: vec![Filename, Buffers, Modified],
+            right: vec![Language, Indent, Encoding, Position, Percentage],
+        }
+    }
+}
+
+impl StatusLayout {
+    // [status] table in config file. 'left' and 'right' are arrays of segment names. Omitted side
+    // is kept as default and an empty array hides the side
+    pub fn from_table(table: &Table) -> Result<StatusLayout, String> {
+        let mut layout = StatusLayout::default();
+        for (key, value) in table {
+            let side = match key.as_str() {
+                "left" => &mut layout.left,
+                "right" => &mut layout.right,
+                key => return Err(format!("Unknown key '{}' in [status]", key)),
+            };
+            let elems = match value {
+                Value::Array(elems) => elems,
+                v => return Err(format!("'{}' must be array but got {}", key, v.type_name())),
+            };
+            *side = elems
+                .iter()
+                .map(|v| match v.as_str() {
+                    Some(name) => Segment::from_name(name)
+                        .ok_or_else(|| format!("Unknown segment '{}'", name)),
+                    None => Err(format!("Segments of '{}' must be strings", key)),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(layout)
+    }
+}
 
 #[derive(Default)]
 pub struct StatusBar {
@@ -7,6 +96,14 @@ pub struct StatusBar {
     pub lang: Language,
     pub buf_pos: (usize, usize),
     pub line_pos: (usize, usize),
+    pub col: usize,
+    pub offset: Option<usize>, // Byte offset in hex view of binary file
+    pub loading: Option<u64>,  // Percentage of large file loaded in background
+    pub follow: Option<bool>,  // Following appended lines. false while it is paused
+    pub encoding: &'static str,
+    pub line_ending: &'static str,
+    pub indent: (bool, usize), // (expand tab, tab width)
+    pub layout: StatusLayout,
     pub redraw: bool,
 }
 
@@ -35,19 +132,52 @@ impl StatusBar {
     setter!(set_filename, filename, &str, filename.to_string());
     setter!(set_lang, lang, Language);
     setter!(set_line_pos, line_pos, (usize, usize));
+    setter!(set_col, col, usize);
+    setter!(set_offset, offset, Option<usize>);
+    setter!(set_loading, loading, Option<u64>);
+    setter!(set_follow, follow, Option<bool>);
+    setter!(set_encoding, encoding, &'static str);
+    setter!(set_line_ending, line_ending, &'static str);
+    setter!(set_indent, indent, (bool, usize));
+    setter!(set_layout, layout, StatusLayout);
+
+    // Returns None when the segment has nothing to show
+    fn segment(&self, seg: Segment) -> Option<String> {
+        let (y, len) = self.line_pos;
+        match seg {
+            Segment::Filename => Some(self.filename.clone()),
+            Segment::Modified if self.modified => Some("(modified)".to_string()),
+            Segment::Modified if self.loading.is_some() => {
+                self.loading.map(|p| format!("(loading {}%)", p))
+            }
+            Segment::Modified => self
+                .follow
+                .map(|f| if f { "(following)" } else { "(paused)" }.to_string()),
+            Segment::Buffers => Some(format!("[{}/{}]", self.buf_pos.0, self.buf_pos.1)),
+            Segment::Language if self.offset.is_some() => Some("binary".to_string()),
+            Segment::Language => Some(self.lang.name().to_string()),
+            Segment::Position => match self.offset {
+                Some(offset) => Some(format!("0x{:08x}", offset)),
+                None => Some(format!("{}:{}", y + 1, self.col + 1)),
+            },
+            Segment::Percentage if len == 0 => Some("100%".to_string()),
+            Segment::Percentage => Some(format!("{}%", (y + 1).min(len) * 100 / len)),
+            Segment::Encoding => Some(format!("{} {}", self.encoding, self.line_ending)),
+            Segment::Indent if self.indent.0 => Some(format!("spaces:{}", self.indent.1)),
+            Segment::Indent => Some(format!("tabs:{}", self.indent.1)),
+        }
+    }
+
+    fn render(&self, segments: &[Segment]) -> String {
+        let rendered: Vec<_> = segments.iter().filter_map(|s| self.segment(*s)).collect();
+        rendered.join(" ")
+    }
 
     pub fn left(&self) -> String {
-        format!(
-            "{:<20?} - {}/{} {}",
-            self.filename,
-            self.buf_pos.0,
-            self.buf_pos.1,
-            if self.modified { "(modified) " } else { "" }
-        )
+        self.render(&self.layout.left)
     }
 
     pub fn right(&self) -> String {
-        let (lang, (y, len)) = (self.lang, self.line_pos);
-        format!("{} {}/{}", lang.name(), y, len)
+        self.render(&self.layout.right)
     }
 }
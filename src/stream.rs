@@ -0,0 +1,163 @@
+// Lazy loading of large files. Reading and decoding a whole file of gigabytes before showing it
+// takes seconds, so a file larger than `THRESHOLD` is opened with only its head read. The rest is
+// read chunk by chunk in background tasks and appended to the buffer as each chunk arrives. The
+// buffer is read-only until the last chunk arrives so that a partially loaded file is never saved.
+//
+// Each chunk ends at a line boundary and its rows are built in the background task so that the main
+// thread only appends them. Lines are decoded as UTF-8, so a file whose head is binary, has a BOM or
+// is not valid UTF-8 is not loaded lazily. Invalid sequences found in later chunks are replaced with
+// U+FFFD and the buffer stays read-only so that saving never corrupts the file.
+//
+// Lines appended to a file while following it like `tail -f` are read in the same way.
+
+use crate::encoding::Encoding;
+use crate::error::{Context, Error, Result};
+use crate::hexview;
+use crate::mmap::Mmap;
+use crate::row::Row;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+// Files larger than this are loaded lazily
+pub const THRESHOLD: u64 = 16 * 1024 * 1024;
+// Bytes read on opening. It is enough to fill the screen with a margin for scrolling
+pub const HEAD_SIZE: usize = 256 * 1024;
+// Bytes read by each background task. Finished tasks are received at most every 100ms while no key
+// is pressed, so a chunk must be large enough to load a file quickly
+pub const CHUNK_SIZE: usize = 32 * 1024 * 1024;
+
+pub struct Chunk {
+    pub rows: Vec<Row>,
+    pub start: u64, // Byte offset of the first line
+    pub end: u64,   // Byte offset of the line following the last line
+    pub eof: bool,
+    pub crlf: bool,  // The first line ends with "\r\n"
+    pub lossy: bool, // Some lines were not valid UTF-8
+}
+
+// The file needs decoding of its entire contents instead of lazy loading. An incomplete UTF-8
+// sequence at the end of the head may continue in the rest
+pub fn needs_decoding(head: &[u8]) -> bool {
+    let invalid = std::str::from_utf8(head).map_or_else(|e| e.error_len().is_some(), |_| false);
+    invalid || hexview::is_binary(head) || !Encoding::detect(head).bom().is_empty()
+}
+
+// Head bytes of the file to check whether it can be loaded lazily
+pub fn read_head(path: &Path) -> Result<Vec<u8>> {
+    let mut head = vec![];
+    File::open(path)
+        .and_then(|f| f.take(HEAD_SIZE as u64).read_to_end(&mut head))
+        .context("opening", path)?;
+    Ok(head)
+}
+
+// Byte ranges of lines in the bytes ending at a line boundary. Line endings are not contained
+fn line_ranges(bytes: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    while start < bytes.len() {
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |i| start + i);
+        let line = &bytes[start..end];
+        let len = line.strip_suffix(b"\r").unwrap_or(line).len();
+        ranges.push((start, start + len));
+        start = end + 1;
+    }
+    ranges
+}
+
+fn crlf(bytes: &[u8]) -> bool {
+    let first = bytes.split(|&b| b == b'\n').next().unwrap_or(&[]);
+    first.ends_with(b"\r")
+}
+
+// Bytes from `start` until about `size` bytes, and whether the file ended. They are read until a
+// newline so that a line longer than the size is read entirely
+fn read_bytes(path: &Path, start: u64, size: usize) -> Result<(Vec<u8>, bool)> {
+    let mut file = File::open(path).context("reading", path)?;
+    file.seek(SeekFrom::Start(start)).context("reading", path)?;
+    let mut bytes = vec![];
+    loop {
+        let read = (&mut file)
+            .take(size as u64)
+            .read_to_end(&mut bytes)
+            .context("reading", path)?;
+        if read < size {
+            return Ok((bytes, true));
+        }
+        if bytes.contains(&b'\n') {
+            return Ok((bytes, false));
+        }
+    }
+}
+
+fn owned_chunk(bytes: &[u8], start: u64, eof: bool, tab_width: usize) -> Chunk {
+    let rows = line_ranges(bytes)
+        .into_iter()
+        .map(|(s, e)| Row::new(String::from_utf8_lossy(&bytes[s..e]), tab_width))
+        .collect();
+    Chunk {
+        rows,
+        start,
+        end: start + bytes.len() as u64,
+        eof,
+        crlf: crlf(bytes),
+        lossy: std::str::from_utf8(bytes).is_err(),
+    }
+}
+
+fn line_end(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1)
+}
+
+// Read lines from `start` until about `size` bytes
+pub fn read_chunk(path: &Path, start: u64, size: usize, tab_width: usize) -> Result<Chunk> {
+    let (bytes, eof) = read_bytes(path, start, size)?;
+    // Bytes after the last newline are the head of the next chunk
+    let len = if eof { bytes.len() } else { line_end(&bytes) };
+    Ok(owned_chunk(&bytes[..len], start, eof, tab_width))
+}
+
+// Lines appended after `start` by other process such as a logger. A line is not read until its
+// newline is written
+pub fn read_appended(path: &Path, start: u64, tab_width: usize) -> Result<Chunk> {
+    let (bytes, eof) = read_bytes(path, start, CHUNK_SIZE)?;
+    let len = line_end(&bytes);
+    Ok(owned_chunk(
+        &bytes[..len],
+        start,
+        eof && len == bytes.len(),
+        tab_width,
+    ))
+}
+
+// The same as `read_chunk` but rows refer to lines in the memory map instead of copying them
+pub fn map_chunk(map: &Arc<Mmap>, start: u64, size: usize, tab_width: usize) -> Result<Chunk> {
+    if map.changed() {
+        return Err(Error::other("File was changed while loading"));
+    }
+    let bytes = map.bytes();
+    let start = start as usize;
+    let limit = start.saturating_add(size).min(bytes.len());
+    let end = match bytes[limit..].iter().position(|&b| b == b'\n') {
+        Some(idx) => limit + idx + 1,
+        None => bytes.len(),
+    };
+    let chunk = &bytes[start..end];
+    let rows = line_ranges(chunk)
+        .into_iter()
+        .map(|(s, e)| Row::mapped(map, start + s, start + e, tab_width))
+        .collect();
+    Ok(Chunk {
+        rows,
+        start: start as u64,
+        end: end as u64,
+        eof: end == bytes.len(),
+        crlf: crlf(chunk),
+        lossy: std::str::from_utf8(chunk).is_err(),
+    })
+}
@@ -0,0 +1,126 @@
+// Terminal setup on Unix-like systems with termios. Window resize is notified by SIGWINCH signal.
+// Suspend is done by SIGTSTP signal.
+
+use crate::signal::SigwinchWatcher;
+use crate::terminal::Term;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+pub struct StdinRawMode {
+    stdin: io::Stdin,
+    // Controlling terminal opened when stdin is not a terminal. Key inputs are read from it
+    tty: Option<File>,
+    orig: termios::Termios,
+    sigwinch: SigwinchWatcher,
+}
+
+impl StdinRawMode {
+    pub fn new() -> io::Result<StdinRawMode> {
+        Self::setup(None)
+    }
+
+    // Read key inputs from /dev/tty since stdin is used for other purposes such as a pipe
+    pub fn from_tty() -> io::Result<StdinRawMode> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        Self::setup(Some(tty))
+    }
+
+    fn setup(tty: Option<File>) -> io::Result<StdinRawMode> {
+        use termios::*;
+
+        let stdin = io::stdin();
+        let fd = tty.as_ref().map_or(stdin.as_raw_fd(), |f| f.as_raw_fd());
+        let mut termios = Termios::from_fd(fd)?;
+        let orig = termios;
+
+        // Set terminal raw mode. Disable echo back, canonical mode, signals (SIGINT, SIGTSTP) and Ctrl+V.
+        termios.c_lflag &= !(ECHO | ICANON | ISIG | IEXTEN);
+        // Disable control flow mode (Ctrl+Q/Ctrl+S) and CR-to-NL translation
+        termios.c_iflag &= !(IXON | ICRNL | BRKINT | INPCK | ISTRIP);
+        // Disable output processing such as \n to \r\n translation
+        termios.c_oflag &= !OPOST;
+        // Ensure character size is 8bits
+        termios.c_cflag |= CS8;
+        // Do not wait for next byte with blocking since reading 0 byte is permitted
+        termios.c_cc[VMIN] = 0;
+        // Set read timeout to 1/10 second it enables 100ms timeout on read()
+        termios.c_cc[VTIME] = 1;
+        // Apply terminal configurations
+        tcsetattr(fd, TCSAFLUSH, &termios)?;
+
+        Ok(StdinRawMode {
+            stdin,
+            tty,
+            orig,
+            sigwinch: SigwinchWatcher::new()?,
+        })
+    }
+
+    fn fd(&self) -> RawFd {
+        self.tty
+            .as_ref()
+            .map_or(self.stdin.as_raw_fd(), |f| f.as_raw_fd())
+    }
+}
+
+impl Term for StdinRawMode {
+    fn resized(&mut self) -> bool {
+        self.sigwinch.notified()
+    }
+
+    // Since ISIG is disabled, Ctrl-Z does not send SIGTSTP. Instead, original terminal mode is
+    // restored and SIGTSTP is sent to the process group as a shell does. Sending the signal stops the
+    // process until SIGCONT so raw mode is enabled again after it
+    fn suspender(&self) -> Option<Box<dyn FnMut() -> io::Result<()>>> {
+        let (fd, orig) = (self.fd(), self.orig);
+        Some(Box::new(move || {
+            let raw = termios::Termios::from_fd(fd)?;
+            termios::tcsetattr(fd, termios::TCSAFLUSH, &orig)?;
+            if unsafe { libc::kill(0, libc::SIGTSTP) } != 0 {
+                let err = io::Error::last_os_error();
+                termios::tcsetattr(fd, termios::TCSAFLUSH, &raw)?;
+                return Err(err);
+            }
+            termios::tcsetattr(fd, termios::TCSAFLUSH, &raw)
+        }))
+    }
+
+    fn restorer(&self) -> Box<dyn Fn() + Send + Sync> {
+        let (fd, orig) = (self.fd(), self.orig);
+        Box::new(move || {
+            termios::tcsetattr(fd, termios::TCSAFLUSH, &orig).ok();
+        })
+    }
+}
+
+impl Drop for StdinRawMode {
+    fn drop(&mut self) {
+        // Restore original terminal mode
+        termios::tcsetattr(self.fd(), termios::TCSAFLUSH, &self.orig).unwrap();
+    }
+}
+
+impl Deref for StdinRawMode {
+    type Target = io::Stdin;
+
+    fn deref(&self) -> &Self::Target {
+        &self.stdin
+    }
+}
+
+impl DerefMut for StdinRawMode {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stdin
+    }
+}
+
+impl Read for StdinRawMode {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.tty {
+            Some(tty) => tty.read(buf),
+            None => self.stdin.read(buf),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Terminal setup on Windows with console API. Console sends key inputs as VT sequences with
+// ENABLE_VIRTUAL_TERMINAL_INPUT and interprets escape sequences output by the editor with
+// ENABLE_VIRTUAL_TERMINAL_PROCESSING. They are available on Windows 10 or later. Since Windows has no
+// SIGWINCH, window resize is detected by comparing window sizes on each input.
+
+use crate::terminal::{window_size, Term};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::raw::c_void;
+use std::os::windows::io::AsRawHandle;
+use std::ptr;
+
+type Handle = *mut c_void;
+
+const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+const WAIT_OBJECT_0: u32 = 0;
+
+const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+const ENABLE_LINE_INPUT: u32 = 0x0002;
+const ENABLE_ECHO_INPUT: u32 = 0x0004;
+const ENABLE_MOUSE_INPUT: u32 = 0x0010;
+const ENABLE_QUICK_EDIT_MODE: u32 = 0x0040;
+const ENABLE_EXTENDED_FLAGS: u32 = 0x0080;
+const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
+const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetStdHandle(std_handle: u32) -> Handle;
+    fn GetConsoleMode(handle: Handle, mode: *mut u32) -> i32;
+    fn SetConsoleMode(handle: Handle, mode: u32) -> i32;
+    fn WaitForSingleObject(handle: Handle, millis: u32) -> u32;
+    fn ReadConsoleW(
+        handle: Handle,
+        buf: *mut c_void,
+        to_read: u32,
+        read: *mut u32,
+        control: *mut c_void,
+    ) -> i32;
+}
+
+fn std_handle(which: u32) -> io::Result<Handle> {
+    let handle = unsafe { GetStdHandle(which) };
+    if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(handle)
+}
+
+fn console_mode(handle: Handle) -> io::Result<u32> {
+    let mut mode = 0;
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(mode)
+}
+
+fn set_console_mode(handle: Handle, mode: u32) -> io::Result<()> {
+    if unsafe { SetConsoleMode(handle, mode) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub struct StdinRawMode {
+    // Console input opened when stdin is not a console. It must be alive while its handle is used
+    _conin: Option<File>,
+    input: Handle,
+    output: Handle,
+    orig: (u32, u32), // Original modes of input and output
+    size: Option<(usize, usize)>,
+    // UTF-8 bytes converted from UTF-16 input which are not read yet
+    pending: Vec<u8>,
+    // High surrogate at end of the previous read. It is combined with the next read
+    surrogate: Option<u16>,
+}
+
+impl StdinRawMode {
+    pub fn new() -> io::Result<StdinRawMode> {
+        Self::setup(None)
+    }
+
+    // Read key inputs from console input since stdin is used for other purposes such as a pipe
+    pub fn from_tty() -> io::Result<StdinRawMode> {
+        let conin = OpenOptions::new().read(true).write(true).open("CONIN$")?;
+        Self::setup(Some(conin))
+    }
+
+    fn setup(conin: Option<File>) -> io::Result<StdinRawMode> {
+        let input = match &conin {
+            Some(f) => f.as_raw_handle() as Handle,
+            None => std_handle(STD_INPUT_HANDLE)?,
+        };
+        let output = std_handle(STD_OUTPUT_HANDLE)?;
+        let orig = (console_mode(input)?, console_mode(output)?);
+
+        // Disable echo back, line buffering and Ctrl-C handling by system. Mouse events are sent as
+        // VT sequences instead of console events. Quick edit mode is disabled since it steals clicks
+        let disabled = ENABLE_ECHO_INPUT
+            | ENABLE_LINE_INPUT
+            | ENABLE_PROCESSED_INPUT
+            | ENABLE_MOUSE_INPUT
+            | ENABLE_QUICK_EDIT_MODE;
+        let mode = orig.0 & !disabled | ENABLE_VIRTUAL_TERMINAL_INPUT | ENABLE_EXTENDED_FLAGS;
+        set_console_mode(input, mode)?;
+        if let Err(err) = set_console_mode(output, orig.1 | ENABLE_VIRTUAL_TERMINAL_PROCESSING) {
+            set_console_mode(input, orig.0).ok();
+            return Err(err);
+        }
+
+        Ok(StdinRawMode {
+            _conin: conin,
+            input,
+            output,
+            orig,
+            size: window_size(),
+            pending: vec![],
+            surrogate: None,
+        })
+    }
+
+    // Console input is UTF-16. It is converted to UTF-8 bytes which the decoder of key inputs expects
+    fn read_console(&mut self) -> io::Result<()> {
+        let mut buf = [0u16; 64];
+        let mut len = 0;
+        let ok = unsafe {
+            ReadConsoleW(
+                self.input,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as u32,
+                &mut len,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut units: Vec<_> = self.surrogate.take().into_iter().collect();
+        units.extend_from_slice(&buf[..len as usize]);
+        if units.last().is_some_and(|u| (0xd800..0xdc00).contains(u)) {
+            self.surrogate = units.pop();
+        }
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.pending
+                .extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl Read for StdinRawMode {
+    // Wait for input within 100ms like VTIME of termios. Reading 0 byte means timeout. Note that
+    // ReadConsoleW blocks after console events without characters such as focus change until next
+    // key input
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if unsafe { WaitForSingleObject(self.input, 100) } != WAIT_OBJECT_0 {
+                return Ok(0);
+            }
+            self.read_console()?;
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Term for StdinRawMode {
+    fn resized(&mut self) -> bool {
+        let size = window_size();
+        if size == self.size {
+            return false;
+        }
+        self.size = size;
+        true
+    }
+
+    fn restorer(&self) -> Box<dyn Fn() + Send + Sync> {
+        // Handles are raw pointers which cannot be sent to other threads
+        let (input, output, orig) = (self.input as usize, self.output as usize, self.orig);
+        Box::new(move || {
+            set_console_mode(input as Handle, orig.0).ok();
+            set_console_mode(output as Handle, orig.1).ok();
+        })
+    }
+}
+
+impl Drop for StdinRawMode {
+    fn drop(&mut self) {
+        // Restore original console modes
+        set_console_mode(self.input, self.orig.0).unwrap();
+        set_console_mode(self.output, self.orig.1).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+// Frontend of the editor on terminals. It sets up terminal raw mode, reads key inputs from stdin and
+// notifies window resize. The editor backend only sees a stream of `InputSeq` and writes escape
+// sequences to its output so it does not depend on terminal APIs of OS. Setup of terminal differs
+// between OSes and is implemented by `StdinRawMode` in term_unix.rs and term_windows.rs.
+
+use crate::input::{InputSeq, InputSequences, KeySeq};
+use crate::screen;
+use std::io::{self, Read};
+use std::panic;
+
+#[cfg(unix)]
+pub use crate::term_unix::StdinRawMode;
+#[cfg(windows)]
+pub use crate::term_windows::StdinRawMode;
+
+// Size of terminal window (cols, rows). None when it cannot be known via OS API. Then the backend
+// asks the terminal for its size with escape sequences
+pub fn window_size() -> Option<(usize, usize)> {
+    term_size::dimensions_stdout()
+}
+
+// Stdin in raw mode. Raw mode is entered on creation and the original mode is restored on drop.
+// Reading it must time out in 100ms when no input arrives
+pub trait Term: Read + Sized {
+    // Returns true when window size has changed since the previous call
+    fn resized(&mut self) -> bool;
+
+    // Function to suspend the process, which is passed to `Editor::set_suspender`. It must be
+    // taken before the terminal is moved into `TerminalInput`. None when OS has no job control
+    fn suspender(&self) -> Option<Box<dyn FnMut() -> io::Result<()>>> {
+        None
+    }
+
+    // Function to restore the original terminal mode, which is called by the panic hook. Dropping
+    // the terminal restores it too, but it happens after the panic message is printed
+    fn restorer(&self) -> Box<dyn Fn() + Send + Sync>;
+
+    fn input_keys(self) -> TerminalInput<Self> {
+        TerminalInput {
+            seqs: InputSequences::new(self),
+        }
+    }
+}
+
+// Leave the alternate screen buffer and raw mode before printing the panic message. Otherwise the
+// message is printed on the alternate screen without newline translation and is lost on quit
+pub fn install_panic_hook<T: Term>(term: &T) {
+    let restore = term.restorer();
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        screen::leave_on_panic(io::stdout());
+        restore();
+        default(info);
+    }));
+}
+
+pub struct TerminalInput<T: Term> {
+    seqs: InputSequences<T>,
+}
+
+impl<T: Term> Iterator for TerminalInput<T> {
+    type Item = io::Result<InputSeq>;
+
+    // Resize is checked on every input. Reading stdin times out every 100ms so the resize is
+    // notified soon even if no key is pressed
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.seqs.reader_mut().resized() {
+            if let Some((w, h)) = window_size() {
+                return Some(Ok(InputSeq::new(KeySeq::Resize(w, h))));
+            }
+        }
+        self.seqs.next()
+    }
+}
@@ -1,10 +1,29 @@
+use crate::carets;
+use crate::completion::Words;
+use crate::conflict::{self, Conflict, Part, Resolution};
+use crate::diff::{self, Op};
+use crate::dired;
+use crate::encoding::Encoding;
+use crate::error::{Context, Error, Result};
+use crate::fold::{self, Folds};
+use crate::gap_buffer::{self, GapBuffer};
+use crate::grapheme;
+use crate::hexview;
+use crate::highlight;
+use crate::jumplist::JumpList;
 use crate::language::{Indent, Language};
-use crate::row::Row;
+use crate::marks::Marks;
+use crate::mmap::Mmap;
+use crate::row::{Row, DEFAULT_TAB_WIDTH};
+use crate::stream::{self, Chunk};
+use crate::undo::{EditCommand, EditDiff, History};
+use crate::vcs::{Mark, VcsDiff};
 use std::cmp;
-use std::fs::File;
-use std::io::{self, BufRead, Write};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::slice;
+use std::process;
+use std::sync::Arc;
 
 // Contain both actual path sequence and display string
 pub struct FilePath {
@@ -38,7 +57,7 @@ pub enum CursorDir {
     Down,
 }
 
-pub struct Lines<'a>(slice::Iter<'a, Row>);
+pub struct Lines<'a>(gap_buffer::Iter<'a, Row>);
 
 impl<'a> Iterator for Lines<'a> {
     type Item = &'a str;
@@ -48,11 +67,57 @@ impl<'a> Iterator for Lines<'a> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let len = self.0.as_slice().len();
-        (len, Some(len))
+        self.0.size_hint()
     }
 }
 
+// Kind of character for moving cursor by word
+#[derive(PartialEq)]
+enum CharKind {
+    Ident,
+    Punc,
+    Space,
+}
+
+impl CharKind {
+    fn new_at(rows: &GapBuffer<Row>, x: usize, y: usize) -> Self {
+        rows.get(y)
+            .and_then(|r| r.char_at_checked(x))
+            .map(|c| {
+                if c.is_ascii_whitespace() {
+                    CharKind::Space
+                } else if c == '_' || c.is_ascii_alphanumeric() {
+                    CharKind::Ident
+                } else {
+                    CharKind::Punc
+                }
+            })
+            .unwrap_or(CharKind::Space)
+    }
+}
+
+fn at_word_start(left: &CharKind, right: &CharKind) -> bool {
+    matches!(
+        (left, right),
+        (&CharKind::Space, &CharKind::Ident)
+            | (&CharKind::Space, &CharKind::Punc)
+            | (&CharKind::Punc, &CharKind::Ident)
+            | (&CharKind::Ident, &CharKind::Punc)
+    )
+}
+
+fn at_word_end(left: &CharKind, right: &CharKind) -> bool {
+    at_word_start(right, left)
+}
+
+// Columns on screen of corners of selected rectangle. They can be beyond end of short lines. The
+// column of the cursor is valid until the cursor moves
+#[derive(Clone, Copy)]
+struct RectColumns {
+    anchor: usize,
+    cursor: ((usize, usize), usize),
+}
+
 #[derive(Default)]
 pub struct TextBuffer {
     // (x, y) coordinate in internal text buffer of rows
@@ -60,12 +125,64 @@ pub struct TextBuffer {
     cy: usize,
     // File editor is opening
     file: Option<FilePath>,
+    // Name of buffer without file such as output of build command. It is shown as file name
+    name: Option<String>,
     // Lines of text buffer
-    row: Vec<Row>,
+    row: GapBuffer<Row>,
     // Flag set to true when buffer is modified after loading a file
     modified: bool,
     // Language which current buffer belongs to
     lang: Language,
+    // Undo/Redo history of edits
+    history: History,
+    // Start point of selected region. The region is between this anchor and the cursor
+    anchor: Option<(usize, usize)>,
+    // Tab key inserts spaces instead of a tab character
+    expand_tab: bool,
+    // Width of a tab stop. It is also the width of one indentation level with `expand_tab`
+    tab_width: usize,
+    // Size of binary file shown as hex dump. The buffer is read-only in the case
+    binary: Option<usize>,
+    // Buffer is listing of directory entries. The buffer is read-only in the case
+    directory: bool,
+    // Large file being loaded in background as (byte offset of rest of lines, file size). The
+    // buffer is read-only until all lines are loaded
+    stream: Option<(u64, u64)>,
+    // Lines loaded lazily were not valid UTF-8 and contain U+FFFD. The buffer is read-only forever
+    // since saving it would corrupt the file
+    lossy: bool,
+    // Memory map of the file which rows refer to. All rows copy their lines on the first edit
+    map: Option<Arc<Mmap>>,
+    // Memory map which rows were copied from. The file must not be changed by other process until
+    // the buffer is saved
+    copied: Option<Arc<Mmap>>,
+    // Byte offset of the end of lines read from the file while following lines appended to it.
+    // The buffer is read-only while following
+    follow: Option<u64>,
+    // Buffer is opened as read-only by user such as `-R` command line option
+    readonly: bool,
+    // Encoding of the file. Text is encoded with it on saving
+    encoding: Encoding,
+    // Lines are ended with "\r\n" instead of "\n". Detected from the first line of the file
+    crlf: bool,
+    // Edits are not written to the swap file yet
+    swap_pending: bool,
+    // Cursor positions before jumps
+    jumps: JumpList,
+    // Named positions set by users
+    marks: Marks,
+    // Closed folds hiding lines
+    folds: Folds,
+    // Changes from the file in HEAD of git repository. None when the file is not tracked by git
+    vcs: Option<VcsDiff>,
+    // Merge conflicts left by git. Updated after edits
+    conflicts: Vec<Conflict>,
+    // Words for completion. Collected on demand and cleared when rows are modified
+    words: Option<Words>,
+    // Extra cursors. Edits and cursor moves are applied to them as well as the cursor
+    carets: Vec<(usize, usize)>,
+    // Selected region is a rectangle whose corners are the anchor and the cursor
+    rect: Option<RectColumns>,
     // Flag to require screen update
     // TODO: Merge with Screen's dirty_start field by using RenderContext struct
     pub dirty_start: Option<usize>,
@@ -73,76 +190,358 @@ pub struct TextBuffer {
 
 impl TextBuffer {
     pub fn new() -> Self {
-        let mut buf = Self::default();
-        buf.dirty_start = Some(0); // Ensure to render first screen
-        buf
+        Self {
+            dirty_start: Some(0), // Ensure to render first screen
+            tab_width: DEFAULT_TAB_WIDTH,
+            ..Default::default()
+        }
     }
 
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
+        if path.is_dir() {
+            return Self::open_dir(path);
+        }
         let mut buf = Self::new();
 
         if path.exists() {
-            let file = File::open(path)?;
-            buf.row = io::BufReader::new(file)
-                .lines()
-                .map(|r| Ok(Row::new(r?)))
-                .collect::<io::Result<_>>()?;
+            let size = fs::metadata(path).context("opening", path)?.len();
+            if size > stream::THRESHOLD && !stream::needs_decoding(&stream::read_head(path)?) {
+                return Self::open_head(path, size);
+            }
+            let bytes = fs::read(path).context("opening", path)?;
+            if hexview::is_binary(&bytes) && !Encoding::detect(&bytes).is_utf16() {
+                buf.row = hexview::dump(&bytes)
+                    .into_iter()
+                    .map(|l| Row::new(l, buf.tab_width))
+                    .collect();
+                buf.binary = Some(bytes.len());
+                buf.file = Some(FilePath::from(path));
+                return Ok(buf);
+            }
+            buf.set_contents(&bytes);
             buf.modified = false;
+            buf.vcs = VcsDiff::load(path);
+            buf.update_vcs();
         } else {
             // When the path does not exist, consider it as a new file
             buf.modified = true;
         }
-        buf.lang = Language::detect(path);
+        buf.file = Some(FilePath::from(path));
+        let lang = buf.detect_lang();
+        buf.set_lang(lang);
+        buf.detect_indent();
+        Ok(buf)
+    }
+
+    // Only the head of the large file is read. The rest is appended by `append_chunk`
+    fn open_head(path: &Path, size: u64) -> Result<Self> {
+        let mut buf = Self::new();
+        let chunk = stream::read_chunk(path, 0, stream::HEAD_SIZE, buf.tab_width)?;
+        buf.stream = Some((0, size));
+        buf.append_chunk(chunk);
+        buf.file = Some(FilePath::from(path));
+        let lang = buf.detect_lang();
+        buf.set_lang(lang);
+        buf.detect_indent();
+        Ok(buf)
+    }
+
+    // Open the file with memory map so that rows refer to lines in the map. Files which cannot be
+    // mapped such as directories, and files which need decoding such as binary files or files with
+    // BOM are read as `open` does
+    pub fn open_mapped<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let map = match Mmap::open(path) {
+            Ok(map) if path.is_file() => Arc::new(map),
+            _ => return Self::open(path),
+        };
+        let bytes = map.bytes();
+        let head = &bytes[..bytes.len().min(stream::HEAD_SIZE)];
+        if stream::needs_decoding(head) {
+            return Self::open(path);
+        }
+
+        let mut buf = Self::new();
+        let size = map.len() as u64;
+        let streaming = size > stream::THRESHOLD;
+        let len = if streaming {
+            stream::HEAD_SIZE
+        } else {
+            map.len()
+        };
+        buf.stream = Some((0, size));
+        buf.append_chunk(stream::map_chunk(&map, 0, len, buf.tab_width)?);
+        buf.map = Some(map);
+        buf.file = Some(FilePath::from(path));
+        if !streaming {
+            buf.vcs = VcsDiff::load(path);
+            buf.update_vcs();
+        }
+        let lang = buf.detect_lang();
+        buf.set_lang(lang);
+        buf.detect_indent();
+        Ok(buf)
+    }
+
+    pub fn mapping(&self) -> Option<&Arc<Mmap>> {
+        self.map.as_ref()
+    }
+
+    // The mapped file was modified by other process after mapping it
+    pub fn mapping_changed(&self) -> bool {
+        self.map.as_ref().map(|m| m.changed()).unwrap_or(false)
+    }
+
+    // Copy all lines from the map and stop referring to it. This is done on the first edit so that
+    // a modified buffer never refers to the map. The map must not be changed yet
+    pub fn copy_mapped(&mut self) {
+        if let Some(map) = self.map.take() {
+            for row in self.row.iter_mut() {
+                row.copy();
+            }
+            self.copied = Some(map);
+        }
+    }
+
+    // Byte offset of lines not loaded yet. None when the file is entirely loaded
+    pub fn stream_offset(&self) -> Option<u64> {
+        self.stream.map(|(offset, _)| offset)
+    }
+
+    // Percentage of loaded bytes of the large file
+    pub fn loaded_percent(&self) -> Option<u64> {
+        self.stream.map(|(offset, size)| offset * 100 / size.max(1))
+    }
+
+    // Append lines read in background. The chunk is ignored unless it follows the loaded lines
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        let size = match self.stream {
+            Some((offset, size)) if offset == chunk.start => size,
+            _ => return,
+        };
+        if chunk.start == 0 {
+            self.crlf = chunk.crlf;
+        }
+        self.lossy |= chunk.lossy;
+        self.stream = if chunk.eof {
+            None
+        } else {
+            Some((chunk.end, size))
+        };
+        self.push_rows(chunk.rows);
+    }
+
+    fn push_rows(&mut self, rows: Vec<Row>) {
+        let tab_width = self.tab_width;
+        let y = self.row.len();
+        for mut row in rows {
+            row.set_tab_width(tab_width); // Tab width may be changed while reading the chunk
+            self.row.insert(self.row.len(), row);
+        }
+        self.set_dirty_start(y);
+    }
+
+    pub fn follow_offset(&self) -> Option<u64> {
+        self.follow
+    }
+
+    // Start following lines appended after the offset, or stop following with None
+    pub fn set_follow(&mut self, offset: Option<u64>) {
+        self.follow = offset;
+    }
+
+    // Append lines read while following the file. The cursor at the last line keeps at the last
+    // line so that the new lines are shown. Returns the number of the appended lines
+    pub fn append_followed(&mut self, chunk: Chunk) -> usize {
+        if self.follow != Some(chunk.start) {
+            return 0;
+        }
+        self.follow = Some(chunk.end);
+        self.lossy |= chunk.lossy;
+        let added = chunk.rows.len();
+        if added > 0 && self.cy + 1 >= self.row.len() {
+            self.cy += added;
+            self.cx = 0;
+        }
+        self.push_rows(chunk.rows);
+        added
+    }
+
+    // Unnamed buffer with contents read from e.g. a pipe of stdin. It asks file name on saving
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).context("reading", "stdin")?;
+        let mut buf = Self::new();
+        buf.set_contents(&bytes);
+        buf.modified = !bytes.is_empty(); // Contents are not saved anywhere yet
+        let lang = buf.detect_lang();
+        buf.set_lang(lang);
+        buf.detect_indent();
+        Ok(buf)
+    }
+
+    // Read-only buffer to show text such as output of commands
+    pub fn scratch<S: Into<String>>(name: S, text: &str) -> Self {
+        let mut buf = Self::new();
+        buf.set_contents(text.as_bytes());
+        buf.name = Some(name.into());
+        buf.readonly = true;
+        buf
+    }
+
+    fn set_contents(&mut self, bytes: &[u8]) {
+        self.encoding = Encoding::detect(bytes);
+        let decoded = self.encoding.decode(bytes);
+        let text = decoded.strip_suffix('\n').unwrap_or(&decoded);
+        self.crlf = text.split('\n').next().unwrap_or("").ends_with('\r');
+        if !decoded.is_empty() {
+            let tab_width = self.tab_width;
+            self.row = text
+                .split('\n')
+                .map(|l| Row::new(l.strip_suffix('\r').unwrap_or(l), tab_width))
+                .collect();
+        }
+        self.update_conflicts();
+    }
+
+    fn open_dir(path: &Path) -> Result<Self> {
+        let path = fs::canonicalize(path).context("opening", path)?;
+        let mut buf = Self::new();
+        buf.row = dired::list(&path)?
+            .into_iter()
+            .map(|l| Row::new(l, buf.tab_width))
+            .collect();
+        buf.directory = true;
         buf.file = Some(FilePath::from(path));
         Ok(buf)
     }
 
-    fn set_dirty_start(&mut self) {
+    // Infer indentation style from the first lines of the file. Lines indented with a tab enable
+    // tab characters. Otherwise the most frequent difference of indentations between adjacent
+    // lines is used as indent width. Default of the language is kept when nothing is indented
+    fn detect_indent(&mut self) {
+        const MAX_LINES: usize = 500;
+        let (mut tabs, mut spaces) = (0, 0);
+        let mut widths = [0usize; 9]; // Count of each indent difference 2~8
+        let mut prev = 0;
+        for row in self.row.iter().take(MAX_LINES) {
+            let line = row.buffer();
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with('\t') {
+                tabs += 1;
+                continue;
+            }
+            let indent = line.chars().take_while(|c| *c == ' ').count();
+            if indent > 0 {
+                spaces += 1;
+            }
+            let diff = indent.abs_diff(prev);
+            // Difference by one space is often alignment such as ' *' in block comments
+            if 1 < diff && diff < widths.len() {
+                widths[diff] += 1;
+            }
+            prev = indent;
+        }
+
+        if tabs == 0 && spaces == 0 {
+            return;
+        }
+        if tabs >= spaces {
+            self.expand_tab = false;
+            return;
+        }
+        // Prefer smaller width when counts are the same since dedenting multiple levels at once
+        // makes larger differences
+        let width = (2..widths.len()).rev().max_by_key(|w| widths[*w]).unwrap();
+        if widths[width] > 0 {
+            self.expand_tab = true;
+            self.set_tab_width(width);
+        }
+    }
+
+    fn detect_lang(&self) -> Language {
+        let lang = self
+            .file
+            .as_ref()
+            .map(|f| Language::detect(&f.path))
+            .unwrap_or_default();
+        if lang != Language::Plain {
+            return lang;
+        }
+        // Scripts often have no file extension
+        self.row
+            .get(0)
+            .and_then(|r| Language::detect_shebang(r.buffer()))
+            .unwrap_or(lang)
+    }
+
+    fn set_dirty_start(&mut self, line: usize) {
+        self.words = None;
         if let Some(l) = self.dirty_start {
-            if l <= self.cy {
+            if l <= line {
                 return;
             }
         }
-        self.dirty_start = Some(self.cy);
+        self.dirty_start = Some(line);
+    }
+
+    // All modifications to rows must be done via this method to record them in undo history.
+    // It must be called before moving the cursor so that undo can restore the cursor position.
+    fn apply_diff(&mut self, diff: EditDiff) {
+        self.copy_mapped();
+        diff.apply(&mut self.row, self.tab_width);
+        self.marks.on_diff(&diff);
+        self.folds.on_diff(&diff);
+        self.anchor = None; // Selected region is no longer valid after modification
+        self.set_dirty_start(diff.line());
+        self.history.push(diff, (self.cx, self.cy));
+        self.modified = true;
+        self.swap_pending = true;
     }
 
     pub fn insert_char(&mut self, ch: char) {
         if self.cy == self.row.len() {
-            self.row.push(Row::default());
+            self.apply_diff(EditDiff::InsertLine(self.cy, "".to_string()));
         }
-        self.row[self.cy].insert_char(self.cx, ch);
+        self.apply_diff(EditDiff::InsertChar(self.cx, self.cy, ch));
         self.cx += 1;
-        self.modified = true;
-        self.set_dirty_start();
     }
 
+    // With `expand_tab`, spaces are inserted until the next tab stop
     pub fn insert_tab(&mut self) {
-        match self.lang.indent() {
-            Indent::AsIs => self.insert_char('\t'),
-            Indent::Fixed(indent) => self.insert_str(indent),
+        if !self.expand_tab {
+            self.insert_char('\t');
+            return;
         }
+        let rx = self
+            .row
+            .get(self.cy)
+            .map(|r| r.rx_from_cx(self.cx))
+            .unwrap_or(0);
+        let width = self.tab_width - rx % self.tab_width;
+        self.insert_str(" ".repeat(width));
     }
 
-    pub fn insert_str<S: AsRef<str>>(&mut self, s: S) {
+    pub fn insert_str<S: Into<String>>(&mut self, s: S) {
         if self.cy == self.row.len() {
-            self.row.push(Row::default());
+            self.apply_diff(EditDiff::InsertLine(self.cy, "".to_string()));
         }
-        let s = s.as_ref();
-        self.row[self.cy].insert_str(self.cx, s);
-        self.cx += s.as_bytes().len();
-        self.modified = true;
-        self.set_dirty_start();
+        let s = s.into();
+        let len = s.chars().count();
+        self.apply_diff(EditDiff::Insert(self.cx, self.cy, s));
+        self.cx += len;
     }
 
     pub fn squash_to_previous_line(&mut self) {
         // At top of line, backspace concats current line to previous line
-        self.cx = self.row[self.cy - 1].len(); // Move cursor column to end of previous line
-        let row = self.row.remove(self.cy);
+        let line = self.row[self.cy].buffer().to_string();
+        self.apply_diff(EditDiff::DeleteLine(self.cy, line.clone()));
         self.cy -= 1; // Move cursor to previous line
-        self.row[self.cy].append(row.buffer()); // TODO: Move buffer rather than copy
-        self.modified = true;
-        self.set_dirty_start();
+        self.cx = self.row[self.cy].len(); // Move cursor column to end of previous line
+        self.apply_diff(EditDiff::Append(self.cy, line));
     }
 
     pub fn delete_char(&mut self) {
@@ -150,51 +549,64 @@ impl TextBuffer {
             return;
         }
         if self.cx > 0 {
-            self.row[self.cy].delete_char(self.cx - 1);
-            self.cx -= 1;
-            self.modified = true;
-            self.set_dirty_start();
+            // Whole grapheme cluster such as a character with combining accents is deleted
+            let row = &self.row[self.cy];
+            let start = row.prev_boundary(self.cx);
+            if start + 1 == self.cx {
+                let c = row.char_at(start);
+                self.apply_diff(EditDiff::DeleteChar(start, self.cy, c));
+            } else {
+                let removed = row[start..self.cx].to_string();
+                self.apply_diff(EditDiff::Remove(start, self.cy, removed));
+            }
+            self.cx = start;
         } else {
             self.squash_to_previous_line();
         }
     }
 
-    pub fn delete_until_end_of_line(&mut self) {
+    // Returns deleted text. Newline is represented as "\n"
+    pub fn delete_until_end_of_line(&mut self) -> String {
         if self.cy == self.row.len() {
-            return;
+            return "".to_string();
         }
         if self.cx == self.row[self.cy].len() {
             // Do nothing when cursor is at end of line of end of text buffer
             if self.cy == self.row.len() - 1 {
-                return;
+                return "".to_string();
             }
             // At end of line, concat with next line
-            let deleted = self.row.remove(self.cy + 1);
-            self.row[self.cy].append(deleted.buffer()); // TODO: Move buffer rather than copy
+            let deleted = self.row[self.cy + 1].buffer().to_string();
+            self.apply_diff(EditDiff::DeleteLine(self.cy + 1, deleted.clone()));
+            self.apply_diff(EditDiff::Append(self.cy, deleted));
+            "\n".to_string()
         } else {
-            self.row[self.cy].truncate(self.cx);
+            let truncated = self.row[self.cy][self.cx..].to_string();
+            self.apply_diff(EditDiff::Truncate(self.cy, truncated.clone()));
+            truncated
         }
-        self.modified = true;
-        self.set_dirty_start();
     }
 
-    pub fn delete_until_head_of_line(&mut self) {
+    // Returns deleted text. Newline is represented as "\n"
+    pub fn delete_until_head_of_line(&mut self) -> String {
         if self.cx == 0 && self.cy == 0 || self.cy == self.row.len() {
-            return;
+            return "".to_string();
         }
         if self.cx == 0 {
             self.squash_to_previous_line();
+            "\n".to_string()
         } else {
-            self.row[self.cy].remove(0, self.cx);
+            let removed = self.row[self.cy][..self.cx].to_string();
+            self.apply_diff(EditDiff::Remove(0, self.cy, removed.clone()));
             self.cx = 0;
-            self.modified = true;
-            self.set_dirty_start();
+            removed
         }
     }
 
-    pub fn delete_word(&mut self) {
+    // Returns deleted text
+    pub fn delete_word(&mut self) -> String {
         if self.cx == 0 || self.cy == self.row.len() {
-            return;
+            return "".to_string();
         }
 
         let mut x = self.cx - 1;
@@ -208,10 +620,526 @@ impl TextBuffer {
         }
 
         if x < self.cx {
-            self.row[self.cy].remove(x, self.cx);
+            let removed = self.row[self.cy][x..self.cx].to_string();
+            self.apply_diff(EditDiff::Remove(x, self.cy, removed.clone()));
             self.cx = x;
-            self.modified = true;
-            self.set_dirty_start();
+            removed
+        } else {
+            "".to_string()
+        }
+    }
+
+    // Insert text which may contain newlines at cursor
+    pub fn insert_text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.insert_line();
+            }
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if !line.is_empty() {
+                self.insert_str(line);
+            }
+        }
+    }
+
+    // Replace text between `start` and `end` character indices at line `y`. Cursor is moved to end
+    // of the replaced text.
+    pub fn replace_range(&mut self, start: usize, y: usize, end: usize, text: &str) {
+        self.set_cursor(start, y);
+        let removed = self.row[y][start..end].to_string();
+        self.apply_diff(EditDiff::Remove(start, y, removed));
+        self.apply_diff(EditDiff::Insert(start, y, text.to_string()));
+        self.cx = start + text.chars().count();
+    }
+
+    // Replace text between `start` and `end` character indices before the cursor in the cursor
+    // line such as an abbreviation. The cursor stays after the same character
+    pub fn replace_before_cursor(&mut self, start: usize, end: usize, text: &str) {
+        let (cx, cy) = (self.cx, self.cy);
+        let removed = self.row[cy][start..end].to_string();
+        self.apply_diff(EditDiff::Remove(start, cy, removed));
+        self.apply_diff(EditDiff::Insert(start, cy, text.to_string()));
+        self.cx = cx - (end - start) + text.chars().count();
+    }
+
+    pub fn set_anchor(&mut self) {
+        self.anchor = Some((self.cx, self.cy));
+        self.rect = None;
+    }
+
+    pub fn set_rect_anchor(&mut self) {
+        self.anchor = Some((self.cx, self.cy));
+        let rx = self.rx_at((self.cx, self.cy));
+        self.rect = Some(RectColumns {
+            anchor: rx,
+            cursor: ((self.cx, self.cy), rx),
+        });
+    }
+
+    pub fn clear_anchor(&mut self) {
+        self.anchor = None;
+    }
+
+    pub fn has_selection(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    // Returns start and end positions of selected region. Start is always before end
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.anchor?;
+        let cursor = (self.cx, self.cy);
+        // Compare (y, x) since positions are ordered by line at first
+        if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    // Returns selected text. Newlines are represented as "\n"
+    pub fn selected_text(&self) -> String {
+        let ((sx, sy), (ex, ey)) = if let Some(r) = self.selection() {
+            r
+        } else {
+            return "".to_string();
+        };
+        let mut text = String::new();
+        for (y, row) in self.row.iter().enumerate().take(ey + 1).skip(sy) {
+            let start = if y == sy { sx } else { 0 };
+            let end = if y == ey { ex } else { row.len() };
+            text.push_str(&row[start..end]);
+            if y < ey {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    // Delete selected region and returns the deleted text. Cursor is moved to start of the region
+    pub fn delete_selection(&mut self) -> String {
+        let ((sx, sy), (ex, ey)) = if let Some(r) = self.selection() {
+            r
+        } else {
+            return "".to_string();
+        };
+        let deleted = self.selected_text();
+        if deleted.is_empty() {
+            self.anchor = None;
+            return deleted;
+        }
+
+        self.set_cursor(sx, sy);
+        if sy == ey {
+            let removed = self.row[sy][sx..ex].to_string();
+            self.apply_diff(EditDiff::Remove(sx, sy, removed));
+            return deleted;
+        }
+
+        // Region may end at the line after the last line
+        let tail = self
+            .row
+            .get(ey)
+            .map(|r| r[ex..].to_string())
+            .unwrap_or_default();
+        let truncated = self.row[sy][sx..].to_string();
+        if !truncated.is_empty() {
+            self.apply_diff(EditDiff::Truncate(sy, truncated));
+        }
+        for _ in sy + 1..cmp::min(ey + 1, self.row.len()) {
+            let line = self.row[sy + 1].buffer().to_string();
+            self.apply_diff(EditDiff::DeleteLine(sy + 1, line));
+        }
+        if !tail.is_empty() {
+            self.apply_diff(EditDiff::Append(sy, tail));
+        }
+        deleted
+    }
+
+    fn rx_at(&self, (x, y): (usize, usize)) -> usize {
+        self.row.get(y).map(|r| r.rx_from_cx(x)).unwrap_or(0)
+    }
+
+    // Returns (left, right, top, bottom) of selected rectangle. Left and right are columns on
+    // screen so that the rectangle is aligned even if lines contain tabs or wide characters. Right
+    // is exclusive
+    pub fn rect_selection(&self) -> Option<(usize, usize, usize, usize)> {
+        let rect = self.rect?;
+        let anchor = self.anchor?;
+        let cursor = (self.cx, self.cy);
+        let ra = rect.anchor;
+        let rc = match rect.cursor {
+            (pos, rx) if pos == cursor => rx,
+            _ => self.rx_at(cursor),
+        };
+        let top = cmp::min(anchor.1, cursor.1);
+        let bottom = cmp::max(anchor.1, cursor.1);
+        Some((cmp::min(ra, rc), cmp::max(ra, rc), top, bottom))
+    }
+
+    // Character ranges of selected rectangle in each line as (y, start, end)
+    fn rect_ranges(&self) -> Vec<(usize, usize, usize)> {
+        let (left, right, top, bottom) = match self.rect_selection() {
+            Some(r) if r.2 < self.row.len() => r,
+            _ => return vec![],
+        };
+        (top..=cmp::min(bottom, self.row.len() - 1))
+            .map(|y| {
+                let row = &self.row[y];
+                (y, row.cx_from_rx(left), row.cx_from_rx(right))
+            })
+            .collect()
+    }
+
+    // Text in selected rectangle. One item per line
+    pub fn rect_text(&self) -> Vec<String> {
+        self.rect_ranges()
+            .into_iter()
+            .map(|(y, start, end)| self.row[y][start..end].to_string())
+            .collect()
+    }
+
+    // Select empty rectangle at the column so that successive edits are applied to the same column
+    fn select_rect_column(&mut self, rx: usize, anchor_y: usize, cursor_y: usize) {
+        let row = &self.row;
+        let cx = |y: usize| row.get(y).map(|r| r.cx_from_rx(rx)).unwrap_or(0);
+        let (ax, x) = (cx(anchor_y), cx(cursor_y));
+        self.anchor = Some((ax, anchor_y));
+        self.rect = Some(RectColumns {
+            anchor: rx,
+            cursor: ((x, cursor_y), rx),
+        });
+        self.set_cursor(x, cursor_y);
+    }
+
+    // Delete text in selected rectangle and returns it. The rectangle becomes empty at its left
+    pub fn delete_rect(&mut self) -> Vec<String> {
+        let (left, anchor_y, cursor_y) = match (self.rect_selection(), self.anchor) {
+            (Some((left, ..)), Some((_, ay))) => (left, ay, self.cy),
+            _ => return vec![],
+        };
+        let deleted = self.rect_text();
+        for (y, start, end) in self.rect_ranges() {
+            if start < end {
+                let removed = self.row[y][start..end].to_string();
+                self.apply_diff(EditDiff::Remove(start, y, removed));
+            }
+        }
+        self.select_rect_column(left, anchor_y, cursor_y);
+        deleted
+    }
+
+    // Insert text at the column in each line of selected rectangle after deleting text in it.
+    // Lines shorter than the column are padded with spaces
+    pub fn insert_rect_str(&mut self, s: &str) {
+        if self.rect_selection().is_none() {
+            return;
+        }
+        self.delete_rect();
+        let (left, anchor_y, cursor_y) = match (self.rect_selection(), self.anchor) {
+            (Some((left, ..)), Some((_, ay))) => (left, ay, self.cy),
+            _ => return,
+        };
+        let ranges = self.rect_ranges();
+        for (y, start, _) in ranges {
+            let pad = left.saturating_sub(self.row[y].rx_from_cx(self.row[y].len()));
+            let text = format!("{}{}", " ".repeat(pad), s);
+            self.apply_diff(EditDiff::Insert(start, y, text));
+        }
+        self.select_rect_column(left + grapheme::str_width(s), anchor_y, cursor_y);
+    }
+
+    // Delete text in selected rectangle. When the rectangle is empty, the character before it is
+    // deleted in each line
+    pub fn delete_rect_char(&mut self) {
+        let (left, right, ..) = match self.rect_selection() {
+            Some(r) => r,
+            None => return,
+        };
+        if left < right || left == 0 {
+            self.delete_rect();
+            return;
+        }
+        let anchor_y = self.anchor.map(|(_, y)| y).unwrap_or(self.cy);
+        let cursor_y = self.cy;
+        let mut rx = left - 1;
+        for (y, start, _) in self.rect_ranges() {
+            // Lines shorter than the column have no character to delete
+            if start > 0 && self.row[y].rx_from_cx(start) == left {
+                rx = cmp::min(rx, self.row[y].rx_from_cx(start - 1));
+                let c = self.row[y].char_at(start - 1);
+                self.apply_diff(EditDiff::DeleteChar(start - 1, y, c));
+            }
+        }
+        self.select_rect_column(rx, anchor_y, cursor_y);
+    }
+
+    // Insert lines of rectangle at the cursor column from the cursor line. Lines are added at end
+    // of buffer if needed
+    pub fn insert_rect(&mut self, lines: &[String]) {
+        let rx = self.rx_at((self.cx, self.cy));
+        let (cx, cy) = (self.cx, self.cy);
+        for (i, line) in lines.iter().enumerate() {
+            let y = cy + i;
+            if y >= self.row.len() {
+                self.apply_diff(EditDiff::InsertLine(y, "".to_string()));
+            }
+            let row = &self.row[y];
+            let pad = rx.saturating_sub(row.rx_from_cx(row.len()));
+            let text = format!("{}{}", " ".repeat(pad), line);
+            let x = row.cx_from_rx(rx);
+            self.apply_diff(EditDiff::Insert(x, y, text));
+        }
+        self.set_cursor(cx, cy);
+    }
+
+    // Delete text between the position and the cursor. Returns the deleted text
+    pub fn delete_to(&mut self, x: usize, y: usize) -> String {
+        self.anchor = Some((x, y));
+        self.delete_selection()
+    }
+
+    fn indent_unit(&self) -> String {
+        if self.expand_tab {
+            " ".repeat(self.tab_width)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    // Lines in selected region, or the current line if nothing is selected
+    fn selected_lines(&self) -> Option<(usize, usize)> {
+        let (sy, ey) = match self.selection() {
+            // Line where the region ends at its head is not included
+            Some(((_, sy), (0, ey))) if sy < ey => (sy, ey - 1),
+            Some(((_, sy), (_, ey))) => (sy, ey),
+            None => (self.cy, self.cy),
+        };
+        if sy >= self.row.len() {
+            return None;
+        }
+        Some((sy, cmp::min(ey, self.row.len() - 1)))
+    }
+
+    // Indent (or dedent) lines in selected region, or the current line if nothing is selected.
+    // Selection is kept after indentation so that it can be repeated
+    pub fn indent_lines(&mut self, dedent: bool) {
+        let (sy, ey) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => return,
+        };
+        let unit = self.indent_unit();
+        let unit_len = unit.chars().count();
+
+        let mut anchor = self.anchor;
+        let mut cursor = (self.cx, self.cy);
+        for y in sy..=ey {
+            let row = &self.row[y];
+            let width = if !dedent {
+                if row.is_empty() {
+                    continue; // Do not put trailing whitespaces to empty line
+                }
+                self.apply_diff(EditDiff::Insert(0, y, unit.clone()));
+                unit_len
+            } else {
+                let width = if row.buffer().starts_with(&unit) {
+                    unit_len
+                } else {
+                    row.buffer()
+                        .chars()
+                        .take(unit_len)
+                        .take_while(|c| *c == ' ' || *c == '\t')
+                        .count()
+                };
+                if width == 0 {
+                    continue;
+                }
+                let removed = row[..width].to_string();
+                self.apply_diff(EditDiff::Remove(0, y, removed));
+                width
+            };
+
+            for pos in anchor.iter_mut().chain(Some(&mut cursor)) {
+                if pos.1 == y {
+                    pos.0 = if dedent {
+                        pos.0.saturating_sub(width)
+                    } else {
+                        pos.0 + width
+                    };
+                }
+            }
+        }
+
+        self.anchor = anchor;
+        self.set_cursor(cursor.0, cursor.1);
+    }
+
+    // Duplicate lines in selected region, or the current line if nothing is selected. The copy is
+    // put below the lines and the cursor and the selection move to it
+    pub fn duplicate_lines(&mut self) {
+        let (sy, ey) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => return,
+        };
+        let n = ey - sy + 1;
+        let mut anchor = self.anchor;
+        for y in sy..=ey {
+            let line = self.row[y].buffer().to_string();
+            self.apply_diff(EditDiff::InsertLine(y + n, line));
+        }
+        for pos in anchor.iter_mut() {
+            pos.1 += n;
+        }
+        self.anchor = anchor;
+        let cy = cmp::min(self.cy + n, self.row.len());
+        self.set_cursor(self.cx, cy);
+    }
+
+    // Move lines in selected region, or the current line if nothing is selected, up or down by one
+    // line. Selection is kept after moving so that it can be repeated
+    pub fn move_lines(&mut self, dir: CursorDir) {
+        let (sy, ey) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => return,
+        };
+        // The line next to the lines moves to the opposite side instead
+        let (from, to) = match dir {
+            CursorDir::Up if sy > 0 => (sy - 1, ey),
+            CursorDir::Down if ey + 1 < self.row.len() => (ey + 1, sy),
+            _ => return,
+        };
+        let mut anchor = self.anchor;
+        let mut cursor = (self.cx, self.cy);
+        let line = self.row[from].buffer().to_string();
+        self.apply_diff(EditDiff::DeleteLine(from, line.clone()));
+        self.apply_diff(EditDiff::InsertLine(to, line));
+        for pos in anchor.iter_mut().chain(Some(&mut cursor)) {
+            if dir == CursorDir::Up {
+                pos.1 -= 1;
+            } else {
+                pos.1 += 1;
+            }
+        }
+        self.anchor = anchor;
+        self.set_cursor(cursor.0, cursor.1);
+    }
+
+    // Comment out lines in selected region, or the current line if nothing is selected. When all
+    // the lines are already commented out, the comments are removed instead. Languages without line
+    // comment wrap the lines with block comment. Returns false when the language has no comment
+    pub fn toggle_comment(&mut self) -> bool {
+        let (line, block) = highlight::comment_delimiters(self.lang);
+        let (sy, ey) = match self.selected_lines() {
+            Some(lines) => lines,
+            None => return line.is_some() || block.is_some(),
+        };
+        match (line, block) {
+            (Some(leader), _) => self.toggle_line_comment(sy, ey, leader),
+            (None, Some((start, end))) => self.toggle_block_comment(sy, ey, start, end),
+            (None, None) => return false,
+        }
+        true
+    }
+
+    fn indent_width(&self, y: usize) -> usize {
+        self.row[y]
+            .buffer()
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count()
+    }
+
+    // Insert text to the line keeping the cursor and the anchor at the same characters
+    fn insert_keeping_positions(&mut self, x: usize, y: usize, text: String) {
+        let len = text.chars().count();
+        let mut anchor = self.anchor;
+        self.apply_diff(EditDiff::Insert(x, y, text));
+        let mut cursor = (self.cx, self.cy);
+        for pos in anchor.iter_mut().chain(Some(&mut cursor)) {
+            if pos.1 == y && pos.0 >= x {
+                pos.0 += len;
+            }
+        }
+        self.anchor = anchor;
+        self.set_cursor(cursor.0, cursor.1);
+    }
+
+    // Remove characters from the line keeping the cursor and the anchor at the same characters.
+    // Positions in the removed range move to the start of the range
+    fn remove_keeping_positions(&mut self, x: usize, y: usize, len: usize) {
+        let removed = self.row[y][x..x + len].to_string();
+        let mut anchor = self.anchor;
+        self.apply_diff(EditDiff::Remove(x, y, removed));
+        let mut cursor = (self.cx, self.cy);
+        for pos in anchor.iter_mut().chain(Some(&mut cursor)) {
+            if pos.1 == y && pos.0 > x {
+                pos.0 = cmp::max(pos.0.saturating_sub(len), x);
+            }
+        }
+        self.anchor = anchor;
+        self.set_cursor(cursor.0, cursor.1);
+    }
+
+    // Blank lines are neither commented out nor checked. Comment leaders are put at the same column
+    // aligned to the least indented line
+    fn toggle_line_comment(&mut self, sy: usize, ey: usize, leader: &str) {
+        let lines: Vec<_> = (sy..=ey)
+            .filter(|y| !self.row[*y].buffer().trim().is_empty())
+            .collect();
+        let commented = lines
+            .iter()
+            .all(|y| self.row[*y].buffer().trim_start().starts_with(leader));
+        let leader_len = leader.chars().count();
+
+        if commented {
+            for y in lines {
+                let x = self.indent_width(y);
+                let mut len = leader_len;
+                // Remove one space after the leader put by commenting out
+                if self.row[y][x + len..].starts_with(' ') {
+                    len += 1;
+                }
+                self.remove_keeping_positions(x, y, len);
+            }
+        } else {
+            let col = lines
+                .iter()
+                .map(|y| self.indent_width(*y))
+                .min()
+                .unwrap_or(0);
+            for y in lines {
+                self.insert_keeping_positions(col, y, format!("{} ", leader));
+            }
+        }
+    }
+
+    // Lines are wrapped as one block comment from the indentation of the first line to the end of
+    // the last line
+    fn toggle_block_comment(&mut self, sy: usize, ey: usize, start: &str, end: &str) {
+        let first = self.row[sy].buffer().trim_start();
+        let last = self.row[ey].buffer().trim_end();
+        let commented = first.starts_with(start)
+            && last.ends_with(end)
+            && (sy < ey || first.len() >= start.len() + end.len());
+
+        if commented {
+            // Remove the end delimiter at first not to move the start delimiter
+            let last_len = last.chars().count();
+            let mut x = last_len - end.chars().count();
+            if self.row[ey][..x].ends_with(' ') {
+                x -= 1;
+            }
+            self.remove_keeping_positions(x, ey, last_len - x);
+            let x = self.indent_width(sy);
+            let mut len = start.chars().count();
+            if self.row[sy][x + len..].starts_with(' ') {
+                len += 1;
+            }
+            self.remove_keeping_positions(x, sy, len);
+        } else if (sy..=ey).any(|y| !self.row[y].buffer().trim().is_empty()) {
+            let len = self.row[ey].buffer().trim_end().chars().count();
+            self.insert_keeping_positions(len, ey, format!(" {}", end));
+            let x = self.indent_width(sy);
+            self.insert_keeping_positions(x, sy, format!("{} ", start));
         }
     }
 
@@ -222,27 +1150,60 @@ impl TextBuffer {
 
     pub fn insert_line(&mut self) {
         if self.cy >= self.row.len() {
-            self.row.push(Row::default());
+            self.apply_diff(EditDiff::InsertLine(self.cy, "".to_string()));
         } else if self.cx >= self.row[self.cy].len() {
-            self.row.insert(self.cy + 1, Row::default());
+            self.apply_diff(EditDiff::InsertLine(self.cy + 1, "".to_string()));
         } else {
             let split = self.row[self.cy][self.cx..].to_string();
-            self.row[self.cy].truncate(self.cx);
-            self.row.insert(self.cy + 1, Row::new(split));
+            self.apply_diff(EditDiff::Truncate(self.cy, split.clone()));
+            self.apply_diff(EditDiff::InsertLine(self.cy + 1, split));
         }
 
-        self.set_dirty_start();
-
         self.cy += 1;
         self.cx = 0;
     }
 
+    // New line inserted by Enter key starts with the indentation of the current line. When the text
+    // before the cursor ends with e.g. '{', one more level is added
+    pub fn insert_line_indented(&mut self) {
+        if self.cy >= self.row.len() || !highlight::auto_indent(self.lang) {
+            self.insert_line();
+            return;
+        }
+
+        let is_space = |c: &char| *c == ' ' || *c == '\t';
+        let row = &self.row[self.cy];
+        let before = &row[..self.cx];
+        let mut indent: String = before.chars().take_while(is_space).collect();
+        let code = before.trim_end();
+        if !code.is_empty()
+            && highlight::indent_after(self.lang)
+                .iter()
+                .any(|s| code.ends_with(s))
+        {
+            indent.push_str(&self.indent_unit());
+        }
+
+        // Whitespaces after the cursor would be put between the indentation and the text
+        let spaces: String = row[self.cx..].chars().take_while(is_space).collect();
+        if !spaces.is_empty() {
+            self.apply_diff(EditDiff::Remove(self.cx, self.cy, spaces));
+        }
+
+        self.insert_line();
+        if !indent.is_empty() {
+            self.insert_str(indent);
+        }
+    }
+
     pub fn move_cursor_one(&mut self, dir: CursorDir) {
         match dir {
-            CursorDir::Up => self.cy = self.cy.saturating_sub(1),
+            // Lines hidden by folds are skipped moving up and down. Moving to left or right onto a
+            // hidden line opens the fold
+            CursorDir::Up => self.cy = self.folds.up(self.cy, 1),
             CursorDir::Left => {
                 if self.cx > 0 {
-                    self.cx -= 1;
+                    self.cx = self.row[self.cy].prev_boundary(self.cx);
                 } else if self.cy > 0 {
                     // When moving to left at top of line, move cursor to end of previous line
                     self.cy -= 1;
@@ -252,9 +1213,7 @@ impl TextBuffer {
             CursorDir::Down => {
                 // Allow to move cursor until next line to the last line of file to enable to add a
                 // new line at the end.
-                if self.cy < self.row.len() {
-                    self.cy += 1;
-                }
+                self.cy = self.folds.down(self.cy, 1, self.row.len());
             }
             CursorDir::Right => {
                 if self.cy < self.row.len() {
@@ -262,7 +1221,7 @@ impl TextBuffer {
                     if self.cx < len {
                         // Allow to move cursor until next col to the last col of line to enable to
                         // add a new character at the end of line.
-                        self.cx += 1;
+                        self.cx = self.row[self.cy].next_boundary(self.cx);
                     } else if self.cx >= len {
                         // When moving to right at the end of line, move cursor to top of next line.
                         self.cy += 1;
@@ -283,7 +1242,8 @@ impl TextBuffer {
         self.cy = match dir {
             CursorDir::Up => rowoff, // Top of screen
             CursorDir::Down => {
-                cmp::min(rowoff + num_rows - 1, self.row.len()) // Bottom of screen
+                let bottom = num_rows.saturating_sub(1);
+                self.folds.down(rowoff, bottom, self.row.len()) // Bottom of screen
             }
             _ => unreachable!(),
         };
@@ -300,46 +1260,18 @@ impl TextBuffer {
                     self.cx = self.row[self.cy].len();
                 }
             }
-            CursorDir::Up => self.cy = 0,
-            CursorDir::Down => self.cy = self.row.len(),
-        }
-    }
-
-    pub fn move_cursor_by_word(&mut self, dir: CursorDir) {
-        #[derive(PartialEq)]
-        enum CharKind {
-            Ident,
-            Punc,
-            Space,
-        }
-
-        impl CharKind {
-            fn new_at(rows: &[Row], x: usize, y: usize) -> Self {
-                rows.get(y)
-                    .and_then(|r| r.char_at_checked(x))
-                    .map(|c| {
-                        if c.is_ascii_whitespace() {
-                            CharKind::Space
-                        } else if c == '_' || c.is_ascii_alphanumeric() {
-                            CharKind::Ident
-                        } else {
-                            CharKind::Punc
-                        }
-                    })
-                    .unwrap_or(CharKind::Space)
+            CursorDir::Up => {
+                self.cy = 0;
+                self.cx = 0;
             }
-        }
-
-        fn at_word_start(left: &CharKind, right: &CharKind) -> bool {
-            match (left, right) {
-                (&CharKind::Space, &CharKind::Ident)
-                | (&CharKind::Space, &CharKind::Punc)
-                | (&CharKind::Punc, &CharKind::Ident)
-                | (&CharKind::Ident, &CharKind::Punc) => true,
-                _ => false,
+            CursorDir::Down => {
+                self.cy = self.row.len();
+                self.cx = 0;
             }
         }
+    }
 
+    pub fn move_cursor_by_word(&mut self, dir: CursorDir) {
         self.move_cursor_one(dir);
         let mut prev = CharKind::new_at(&self.row, self.cx, self.cy);
         self.move_cursor_one(dir);
@@ -365,6 +1297,20 @@ impl TextBuffer {
         }
     }
 
+    // Move cursor to just after the end of the next word
+    pub fn move_cursor_to_word_end(&mut self) {
+        self.move_cursor_one(CursorDir::Right);
+        let mut prev = CharKind::new_at(&self.row, self.cx, self.cy);
+        self.move_cursor_one(CursorDir::Right);
+        let mut current = CharKind::new_at(&self.row, self.cx, self.cy);
+
+        while self.cy < self.row.len() && !at_word_end(&prev, &current) {
+            prev = current;
+            self.move_cursor_one(CursorDir::Right);
+            current = CharKind::new_at(&self.row, self.cx, self.cy);
+        }
+    }
+
     pub fn move_cursor_paragraph(&mut self, dir: CursorDir) {
         debug_assert!(dir != CursorDir::Left && dir != CursorDir::Right);
         loop {
@@ -379,7 +1325,7 @@ impl TextBuffer {
         }
     }
 
-    pub fn rows(&self) -> &[Row] {
+    pub fn rows(&self) -> &GapBuffer<Row> {
         &self.row
     }
 
@@ -391,9 +1337,21 @@ impl TextBuffer {
         self.file
             .as_ref()
             .map(|f| f.display.as_str())
+            .or(self.name.as_deref())
             .unwrap_or("[No Name]")
     }
 
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|f| f.path.as_path())
+    }
+
+    pub fn is_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.file
+            .as_ref()
+            .map(|f| f.path == path.as_ref())
+            .unwrap_or(false)
+    }
+
     pub fn modified(&self) -> bool {
         self.modified
     }
@@ -402,6 +1360,75 @@ impl TextBuffer {
         self.lang
     }
 
+    pub fn readonly(&self) -> bool {
+        self.readonly
+            || self.binary.is_some()
+            || self.directory
+            || self.stream.is_some()
+            || self.follow.is_some()
+            || self.lossy
+    }
+
+    // Some lines of the file were not valid UTF-8 while loading it lazily
+    pub fn lossy(&self) -> bool {
+        self.lossy
+    }
+
+    // Read-only flag set by `set_readonly`
+    pub fn readonly_by_user(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    // Path of the entry at the cursor when the buffer is listing of directory
+    pub fn dir_entry(&self) -> Option<PathBuf> {
+        if !self.directory {
+            return None;
+        }
+        let dir = &self.file.as_ref()?.path;
+        let line = self.row.get(self.cy)?.buffer();
+        Some(dired::entry_path(dir, line))
+    }
+
+    // Byte offset at the cursor when the buffer is hex dump of binary file
+    pub fn binary_offset(&self) -> Option<usize> {
+        let size = self.binary?;
+        Some(cmp::min(
+            hexview::offset_at(self.cx, self.cy),
+            size.saturating_sub(1),
+        ))
+    }
+
+    pub fn move_cursor_to_offset(&mut self, offset: usize) {
+        if let Some(size) = self.binary {
+            let (x, y) = hexview::position_of(cmp::min(offset, size.saturating_sub(1)));
+            self.set_cursor(x, y);
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn line_ending(&self) -> &'static str {
+        if self.crlf {
+            "CRLF"
+        } else {
+            "LF"
+        }
+    }
+
     pub fn cx(&self) -> usize {
         self.cx
     }
@@ -415,42 +1442,602 @@ impl TextBuffer {
     }
 
     pub fn set_file<S: Into<String>>(&mut self, file_path: S) {
-        let file = FilePath::from_string(file_path);
-        self.lang = Language::detect(&file.path);
-        self.file = Some(file);
+        self.file = Some(FilePath::from_string(file_path));
+        self.lang = self.detect_lang();
+    }
+
+    // Indentation is reset to the default of the language
+    pub fn set_lang(&mut self, lang: Language) {
+        self.lang = lang;
+        match lang.indent() {
+            Indent::AsIs => {
+                self.expand_tab = false;
+                self.set_tab_width(DEFAULT_TAB_WIDTH);
+            }
+            Indent::Fixed(indent) => {
+                self.expand_tab = true;
+                self.set_tab_width(indent.len());
+            }
+        }
+    }
+
+    pub fn expand_tab(&self) -> bool {
+        self.expand_tab
+    }
+
+    pub fn set_expand_tab(&mut self, expand_tab: bool) {
+        self.expand_tab = expand_tab;
+    }
+
+    // Set indentation by number of spaces. `None` means indenting with a tab character
+    pub fn set_indent(&mut self, width: Option<usize>) {
+        match width {
+            Some(width) => {
+                self.expand_tab = true;
+                self.set_tab_width(width);
+            }
+            None => self.expand_tab = false,
+        }
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    // Rows are rendered again since positions of tab stops are changed
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        if self.tab_width == tab_width {
+            return;
+        }
+        self.tab_width = tab_width;
+        for row in self.row.iter_mut() {
+            row.set_tab_width(tab_width);
+        }
+        self.set_dirty_start(0);
+    }
+
+    // Returns the first line whose mark of changes was updated
+    pub fn update_vcs(&mut self) -> Option<usize> {
+        let vcs = self.vcs.as_mut()?;
+        vcs.update(self.row.iter().map(Row::buffer))
+    }
+
+    // Read the file in HEAD again since it may be committed or newly tracked
+    pub fn reload_vcs(&mut self) {
+        self.vcs = self.file.as_ref().and_then(|f| VcsDiff::load(&f.path));
+        self.update_vcs();
+    }
+
+    pub fn has_vcs(&self) -> bool {
+        self.vcs.is_some()
+    }
+
+    pub fn vcs_mark(&self, y: usize) -> Option<Mark> {
+        self.vcs.as_ref()?.mark_at(y)
+    }
+
+    // Returns the first line whose part of conflicts was changed
+    pub fn update_conflicts(&mut self) -> Option<usize> {
+        let conflicts = conflict::find(self.row.iter().map(Row::buffer));
+        let i = self
+            .conflicts
+            .iter()
+            .zip(&conflicts)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let changed = [self.conflicts.get(i), conflicts.get(i)]
+            .iter()
+            .flatten()
+            .map(|c| c.start)
+            .min();
+        self.conflicts = conflicts;
+        changed
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    pub fn words(&mut self) -> &Words {
+        let row = &self.row;
+        self.words
+            .get_or_insert_with(|| Words::collect(row.iter().map(Row::buffer)))
+    }
+
+    pub fn conflict_part(&self, y: usize) -> Option<Part> {
+        let i = self.conflicts.partition_point(|c| c.end < y);
+        self.conflicts.get(i)?.part_at(y)
+    }
+
+    // Replace the conflict at the cursor with the lines kept by the resolution. Returns false when
+    // the cursor is not in a conflict
+    pub fn resolve_conflict(&mut self, resolution: Resolution) -> bool {
+        let cy = self.cy;
+        let conflict = match self.conflicts.iter().find(|c| c.contains(cy)) {
+            Some(c) => c.clone(),
+            None => return false,
+        };
+        let kept = conflict.kept(resolution);
+        for y in (conflict.start..=conflict.end).rev() {
+            if !kept.iter().any(|r| r.contains(&y)) {
+                let line = self.row[y].buffer().to_string();
+                self.apply_diff(EditDiff::DeleteLine(y, line));
+            }
+        }
+        self.set_cursor_clamped(0, conflict.start);
+        self.update_conflicts();
+        true
     }
 
     pub fn set_unnamed(&mut self) {
         self.file = None;
     }
 
-    pub fn save(&mut self) -> Result<String, String> {
-        let file = if let Some(file) = &self.file {
-            file
-        } else {
+    // Remove whitespaces at end of each line. It can be undone as other edits
+    pub fn strip_trailing_spaces(&mut self) {
+        for y in 0..self.row.len() {
+            let line = self.row[y].buffer();
+            let trailing = &line[line.trim_end_matches([' ', '\t']).len()..];
+            if trailing.is_empty() {
+                continue;
+            }
+            let trailing = trailing.to_string();
+            self.apply_diff(EditDiff::Truncate(y, trailing));
+            if self.cy == y {
+                self.cx = cmp::min(self.cx, self.row[y].len());
+            }
+        }
+    }
+
+    // Replace contents with the lines by deleting and inserting only differing lines so that marks
+    // on other lines are kept. The cursor stays at the same line when it is not changed. Returns
+    // whether something was changed
+    pub fn replace_lines(&mut self, lines: &[&str]) -> bool {
+        let old: Vec<_> = self.row.iter().map(|r| r.buffer().to_string()).collect();
+        let old: Vec<_> = old.iter().map(String::as_str).collect();
+        let (mut y, mut i, mut cy, mut changed) = (0, 0, None, false);
+        for op in diff::line_ops(&old, lines) {
+            if i == self.cy && cy.is_none() && op != Op::Insert {
+                cy = Some(y); // When the cursor line is deleted, it moves to the replaced line
+            }
+            match op {
+                Op::Equal => {
+                    y += 1;
+                    i += 1;
+                }
+                Op::Delete => {
+                    self.apply_diff(EditDiff::DeleteLine(y, old[i].to_string()));
+                    i += 1;
+                    changed = true;
+                }
+                Op::Insert => {
+                    self.apply_diff(EditDiff::InsertLine(y, lines[y].to_string()));
+                    y += 1;
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            let cy = cy.unwrap_or(y); // Cursor was at end of buffer
+            self.set_cursor_clamped(self.cx, cy);
+        }
+        changed
+    }
+
+    // Every line including the last one is terminated with newline. Returns number of bytes written
+    fn write_contents<W: Write>(&self, mut w: W, path: &Path) -> Result<usize> {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let newline = self.encoding.encode(newline).unwrap();
+        let bom = self.encoding.bom();
+        w.write_all(bom).context("writing", path)?;
+        let mut bytes = bom.len();
+        for line in self.row.iter() {
+            let encoded = self
+                .encoding
+                .encode(line.buffer())
+                .map_err(|c| Error::Encode {
+                    c,
+                    encoding: self.encoding.name(),
+                })?;
+            w.write_all(&encoded).context("writing", path)?;
+            w.write_all(&newline).context("writing", path)?;
+            bytes += encoded.len() + newline.len();
+        }
+        Ok(bytes)
+    }
+
+    // Contents are written to a temporary file in the same directory and then it is renamed to the
+    // file so that the file is not broken even if the editor or machine dies while writing. When
+    // `backup` is true, the previous contents are kept in 'file~'
+    pub fn save(&mut self, backup: bool) -> Result<String> {
+        if self.file.is_none() {
             return Ok("".to_string()); // Canceled
+        }
+        if self.readonly() {
+            return Err(Error::other("Could not save: buffer is read-only"));
+        }
+        if self.copied.as_ref().is_some_and(|m| m.changed()) {
+            self.copied = None; // Saving again overwrites the file
+            return Err(Error::other(
+                "Could not save: file was changed on disk after it was edited. Save again to overwrite it",
+            ));
+        }
+        if cfg!(target_arch = "wasm32") {
+            return Err(Error::other("Could not save: no file system in browser"));
+        }
+        if highlight::strip_trailing_spaces(self.lang) {
+            self.strip_trailing_spaces();
+        }
+        let file = self.file.as_ref().unwrap();
+
+        // Write to the symbolic link target instead of replacing the link with a file
+        let path = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.kiro-save-{}", name, process::id()));
+
+        // Errors are reported with the path of the file instead of the temporary file
+        let write = || {
+            let f = File::create(&tmp).context("saving", &file.path)?;
+            let mut w = io::BufWriter::new(f);
+            let bytes = self.write_contents(&mut w, &file.path)?;
+            let f = w
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .context("writing", &file.path)?;
+            f.sync_all().context("writing", &file.path)?;
+
+            if let Ok(metadata) = fs::metadata(&path) {
+                // Keep permissions of the original file such as executable bit
+                let _ = fs::set_permissions(&tmp, metadata.permissions());
+                if backup {
+                    let mut backup = path.clone().into_os_string();
+                    backup.push("~");
+                    fs::copy(&path, &backup).context("creating backup of", &file.path)?;
+                }
+            }
+
+            fs::rename(&tmp, &path).context("saving", &file.path)?;
+            Ok(bytes)
         };
 
-        let f = match File::create(&file.path) {
-            Ok(f) => f,
-            Err(e) => return Err(format!("Could not save: {}", e)),
+        let bytes = match write() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(err);
+            }
+        };
+        self.modified = false;
+        let msg = format!("{} bytes written to {}", bytes, &file.display);
+        self.remove_swap();
+        self.copied = None;
+        Ok(msg)
+    }
+
+    // Write contents to another file in the same encoding and line endings as saving the buffer
+    pub fn write_copy(&self, path: &Path) -> Result<()> {
+        let mut w = io::BufWriter::new(File::create(path).context("creating", path)?);
+        self.write_contents(&mut w, path)?;
+        w.flush().context("writing", path)
+    }
+
+    // Swap file is put in the same directory as the file: 'dir/.file.kiro-swap'
+    fn swap_path(&self) -> Option<PathBuf> {
+        let file = self.file.as_ref()?;
+        let path = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+        let name = path.file_name()?.to_string_lossy();
+        Some(path.with_file_name(format!(".{}.kiro-swap", name)))
+    }
+
+    pub fn has_swap(&self) -> bool {
+        self.swap_path().map(|p| p.exists()).unwrap_or(false)
+    }
+
+    // Write unsaved contents to the swap file for crash recovery. Contents are always in UTF-8 with
+    // LF line endings. Encoding and line endings of the file are kept on recovery
+    pub fn write_swap(&mut self) -> Result<()> {
+        if !self.swap_pending || !self.modified || self.readonly() {
+            return Ok(());
+        }
+        let path = match self.swap_path() {
+            Some(path) => path,
+            None => return Ok(()),
         };
-        let mut f = io::BufWriter::new(f);
-        let mut bytes = 0;
+        let mut w = io::BufWriter::new(File::create(&path).context("creating", &path)?);
         for line in self.row.iter() {
-            let b = line.buffer();
-            writeln!(f, "{}", b).map_err(|e| format!("Could not write to file: {}", e))?;
-            bytes += b.as_bytes().len() + 1;
+            w.write_all(line.buffer().as_bytes())
+                .context("writing", &path)?;
+            w.write_all(b"\n").context("writing", &path)?;
         }
-        f.flush()
-            .map_err(|e| format!("Could not flush to file: {}", e))?;
+        w.flush().context("writing", &path)?;
+        self.swap_pending = false;
+        Ok(())
+    }
 
-        self.modified = false;
-        Ok(format!("{} bytes written to {}", bytes, &file.display))
+    pub fn remove_swap(&mut self) {
+        if let Some(path) = self.swap_path() {
+            let _ = fs::remove_file(path);
+        }
+        self.swap_pending = self.modified;
+    }
+
+    // Replace contents with the swap file left by a crashed editor. The swap file is kept until the
+    // recovered contents are saved
+    pub fn recover_swap(&mut self) -> Result<()> {
+        let path = match self.swap_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text =
+            String::from_utf8_lossy(&fs::read(&path).context("reading", &path)?).into_owned();
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        self.row = if text.is_empty() {
+            GapBuffer::default()
+        } else {
+            text.split('\n')
+                .map(|l| Row::new(l, self.tab_width))
+                .collect()
+        };
+        self.history = History::default();
+        self.set_cursor(0, 0);
+        self.anchor = None;
+        self.words = None;
+        self.dirty_start = Some(0);
+        self.modified = true;
+        self.swap_pending = true;
+        self.detect_indent();
+        Ok(())
     }
 
     pub fn set_cursor(&mut self, x: usize, y: usize) {
         self.cx = x;
         self.cy = y;
     }
+
+    pub fn carets(&self) -> &[(usize, usize)] {
+        &self.carets
+    }
+
+    pub fn clear_carets(&mut self) {
+        self.carets.clear();
+    }
+
+    // Add an extra cursor at the next occurrence of the word at the cursor. The cursor is put at
+    // the same offset in the word. Returns the word or None when no word is at the cursor. The word
+    // is returned with false when no more occurrence is found
+    pub fn add_caret_at_next_word(&mut self) -> Option<(String, bool)> {
+        let line = self.row.get(self.cy)?.buffer();
+        let (start, end) = carets::word_at(line, self.cx)?;
+        let word: String = line.chars().skip(start).take(end - start).collect();
+        let offset = self.cx - start;
+
+        // Search from the last added cursor
+        let (lx, ly) = self.carets.last().copied().unwrap_or((self.cx, self.cy));
+        let from = match carets::word_at(self.row[ly].buffer(), lx) {
+            Some((s, _)) => (s, ly),
+            None => (lx, ly),
+        };
+        let mut pos = from;
+        // Occurrences where cursors are already put are skipped
+        for _ in 0..self.carets.len() + 2 {
+            pos = match carets::next_word(&self.row, &word, pos) {
+                Some(p) if p != from => p,
+                _ => break,
+            };
+            let caret = (pos.0 + offset, pos.1);
+            if caret != (self.cx, self.cy) && !self.carets.contains(&caret) {
+                self.carets.push(caret);
+                return Some((word, true));
+            }
+        }
+        Some((word, false))
+    }
+
+    // Run the operation at the cursor and each extra cursor. Operations at cursors are run from the
+    // bottom of the buffer. Cursors which are put at the same position are merged
+    pub fn for_each_caret<F: FnMut(&mut Self)>(&mut self, mut op: F) {
+        if self.carets.is_empty() {
+            op(self);
+            return;
+        }
+
+        let mut carets = std::mem::take(&mut self.carets);
+        carets.push((self.cx, self.cy)); // The last one is the cursor
+        let mut order: Vec<_> = (0..carets.len()).collect();
+        order.sort_by_key(|&i| cmp::Reverse((carets[i].1, carets[i].0)));
+
+        for i in order {
+            let before = carets[i];
+            let (lines, len) = (self.row.len(), self.row.get(before.1).map_or(0, Row::len));
+            self.set_cursor(before.0, before.1);
+            op(self);
+            let edit = carets::Edit {
+                before,
+                after: (self.cx, self.cy),
+                lines_delta: self.row.len() as isize - lines as isize,
+                len_delta: self.row.get(before.1).map_or(0, Row::len) as isize - len as isize,
+            };
+            for (j, pos) in carets.iter_mut().enumerate() {
+                *pos = if j == i { edit.after } else { edit.shift(*pos) };
+            }
+        }
+
+        let (cx, cy) = carets.pop().unwrap();
+        self.set_cursor(cx, cy);
+        let mut merged = vec![];
+        for pos in carets {
+            if pos != (cx, cy) && !merged.contains(&pos) {
+                merged.push(pos);
+            }
+        }
+        self.carets = merged;
+    }
+
+    // Remember the position before moving the cursor to a distant position
+    pub fn push_jump(&mut self, x: usize, y: usize) {
+        self.jumps.push((x, y));
+    }
+
+    pub fn jump_back(&mut self) -> bool {
+        match self.jumps.back((self.cx, self.cy)) {
+            Some((x, y)) => {
+                self.set_cursor_clamped(x, y);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn jump_forward(&mut self) -> bool {
+        match self.jumps.forward() {
+            Some((x, y)) => {
+                self.set_cursor_clamped(x, y);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn marks(&self) -> &Marks {
+        &self.marks
+    }
+
+    pub fn set_marks(&mut self, marks: Marks) {
+        self.marks = marks;
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        self.marks.set(name, self.cx, self.cy);
+    }
+
+    pub fn remove_mark(&mut self, name: char) {
+        self.marks.remove(name);
+    }
+
+    pub fn move_cursor_to_mark(&mut self, name: char) -> bool {
+        match self.marks.get(name) {
+            Some((x, y)) => {
+                self.set_cursor_clamped(x, y);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Remembered position may be out of the text since lines were deleted after that
+    pub fn folds(&self) -> &Folds {
+        &self.folds
+    }
+
+    // Close the innermost foldable region containing the cursor. Returns false when there is no
+    // region. The cursor moves to the first line of the fold
+    pub fn close_fold(&mut self) -> bool {
+        let (first, last) = match fold::enclosing_region(&self.row, self.cy) {
+            Some(region) => region,
+            None => return false,
+        };
+        self.folds.close(first, last);
+        if self.cy != first {
+            self.cy = first;
+            self.cx = 0;
+        }
+        self.set_dirty_start(first);
+        true
+    }
+
+    // Open the fold at the cursor line. Returns false when the line is not folded
+    pub fn open_fold(&mut self) -> bool {
+        let opened = self.folds.open(self.cy);
+        if opened {
+            self.set_dirty_start(self.cy);
+        }
+        opened
+    }
+
+    pub fn toggle_fold(&mut self) -> bool {
+        self.open_fold() || self.close_fold()
+    }
+
+    // Close all foldable regions. Returns the number of closed folds
+    pub fn close_all_folds(&mut self) -> usize {
+        let regions = fold::all_regions(&self.row);
+        for &(first, last) in &regions {
+            self.folds.close(first, last);
+        }
+        let y = self.folds.visible_line(self.cy);
+        if y != self.cy {
+            self.cy = y;
+            self.cx = 0;
+        }
+        self.set_dirty_start(0);
+        regions.len()
+    }
+
+    pub fn open_all_folds(&mut self) -> bool {
+        if self.folds.is_empty() {
+            return false;
+        }
+        self.folds.clear();
+        self.set_dirty_start(0);
+        true
+    }
+
+    // The cursor moved onto a hidden line by jumps such as search opens folds hiding it
+    pub fn reveal_cursor(&mut self) -> bool {
+        let hidden_by = self.folds.visible_line(self.cy);
+        let revealed = self.folds.reveal(self.cy);
+        if revealed {
+            self.set_dirty_start(hidden_by);
+        }
+        revealed
+    }
+
+    fn set_cursor_clamped(&mut self, x: usize, y: usize) {
+        let y = cmp::min(y, self.row.len());
+        let x = self.row.get(y).map(|r| cmp::min(x, r.len())).unwrap_or(0);
+        self.set_cursor(x, y);
+    }
+
+    pub fn finish_edit(&mut self, command: Option<EditCommand>) {
+        self.history
+            .finish_ongoing_edit((self.cx, self.cy), command);
+    }
+
+    // Returns None when no change can be undone. Otherwise returns the command of the change
+    pub fn undo(&mut self) -> Option<Option<EditCommand>> {
+        self.finish_edit(None);
+        let (row, marks, folds) = (&mut self.row, &mut self.marks, &mut self.folds);
+        let tab_width = self.tab_width;
+        let applied = self.history.undo(|diff| {
+            diff.apply(row, tab_width);
+            marks.on_diff(diff);
+            folds.on_diff(diff);
+        });
+        let (line, (x, y), command) = applied?;
+        self.after_undo_redo(line, x, y);
+        Some(command)
+    }
+
+    // Returns None when no change can be redone. Otherwise returns the command of the change
+    pub fn redo(&mut self) -> Option<Option<EditCommand>> {
+        self.finish_edit(None);
+        let (row, marks, folds) = (&mut self.row, &mut self.marks, &mut self.folds);
+        let tab_width = self.tab_width;
+        let applied = self.history.redo(|diff| {
+            diff.apply(row, tab_width);
+            marks.on_diff(diff);
+            folds.on_diff(diff);
+        });
+        let (line, (x, y), command) = applied?;
+        self.after_undo_redo(line, x, y);
+        Some(command)
+    }
+
+    fn after_undo_redo(&mut self, dirty_line: usize, x: usize, y: usize) {
+        self.anchor = None;
+        self.set_dirty_start(dirty_line);
+        self.set_cursor(x, y);
+        self.modified = true;
+        self.swap_pending = true;
+    }
 }
@@ -0,0 +1,187 @@
+use crate::toml::{Table, Value};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    // Parse "#rrggbb" notation
+    pub fn parse(s: &str) -> Option<Rgb> {
+        let hex = s.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let c = |i| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Rgb(c(0)?, c(2)?, c(4)?))
+    }
+
+    // Nearest color in xterm 256 colors palette. Colors 16~231 are 6x6x6 color cube and 232~255
+    // are grayscale ramp. Colors 0~15 are not used since they depend on terminal's settings.
+    pub fn to_256(self) -> u8 {
+        const CUBE: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+        let nearest_cube = |v: u8| {
+            (0..6)
+                .min_by_key(|i| (i32::from(CUBE[*i]) - i32::from(v)).abs())
+                .unwrap()
+        };
+        let Rgb(r, g, b) = self;
+        let (ri, gi, bi) = (nearest_cube(r), nearest_cube(g), nearest_cube(b));
+        let cube = Rgb(CUBE[ri], CUBE[gi], CUBE[bi]);
+
+        // Gray levels are 8, 18, ..., 238
+        let avg = (u32::from(r) + u32::from(g) + u32::from(b)) / 3;
+        let gray_idx = (avg.saturating_sub(3) / 10).min(23) as u8;
+        let level = 8 + gray_idx * 10;
+        let gray = Rgb(level, level, level);
+
+        let dist = |c: Rgb| {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+            d(c.0, r) + d(c.1, g) + d(c.2, b)
+        };
+        if dist(gray) < dist(cube) {
+            232 + gray_idx
+        } else {
+            16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+        }
+    }
+
+    // Color between this color and the other. `percent` is the ratio of the other color
+    pub fn mix(self, other: Rgb, percent: u8) -> Rgb {
+        let m = |a: u8, b: u8| {
+            let (a, b, p) = (u32::from(a), u32::from(b), u32::from(percent));
+            ((a * (100 - p) + b * p) / 100) as u8
+        };
+        Rgb(m(self.0, other.0), m(self.1, other.1), m(self.2, other.2))
+    }
+}
+
+// Colors used for rendering screen. Colors for 16 colors terminals are not included since they
+// are determined by terminal's palette.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Theme {
+    pub name: &'static str,
+    pub foreground: Rgb,
+    pub background: Rgb,
+    pub number: Rgb,
+    pub string: Rgb,
+    pub comment: Rgb,
+    pub keyword: Rgb,
+    pub type_: Rgb,
+    pub statement: Rgb,
+    pub decorator: Rgb,
+    pub matched: Rgb,
+    pub error: Rgb, // Background of error messages
+}
+
+// https://github.com/morhetz/gruvbox#palette
+const GRUVBOX: Theme = Theme {
+    name: "gruvbox",
+    foreground: Rgb(0xfb, 0xf1, 0xc7),
+    background: Rgb(0x28, 0x28, 0x28),
+    number: Rgb(0xd3, 0x86, 0x9b),
+    string: Rgb(0xb8, 0xbb, 0x26),
+    comment: Rgb(0xa8, 0x99, 0x84),
+    keyword: Rgb(0x83, 0xa5, 0x98),
+    type_: Rgb(0xfa, 0xbd, 0x2f),
+    statement: Rgb(0xfb, 0x49, 0x34),
+    decorator: Rgb(0x8e, 0xc0, 0x7c),
+    matched: Rgb(0x8e, 0xc0, 0x7c),
+    error: Rgb(0xcc, 0x24, 0x1d),
+};
+
+// https://ethanschoonover.com/solarized/
+const SOLARIZED: Theme = Theme {
+    name: "solarized",
+    foreground: Rgb(0x83, 0x94, 0x96),
+    background: Rgb(0x00, 0x2b, 0x36),
+    number: Rgb(0xd3, 0x36, 0x82),
+    string: Rgb(0x2a, 0xa1, 0x98),
+    comment: Rgb(0x58, 0x6e, 0x75),
+    keyword: Rgb(0x85, 0x99, 0x00),
+    type_: Rgb(0xb5, 0x89, 0x00),
+    statement: Rgb(0xcb, 0x4b, 0x16),
+    decorator: Rgb(0x6c, 0x71, 0xc4),
+    matched: Rgb(0x26, 0x8b, 0xd2),
+    error: Rgb(0xdc, 0x32, 0x2f),
+};
+
+// https://monokai.pro/
+const MONOKAI: Theme = Theme {
+    name: "monokai",
+    foreground: Rgb(0xf8, 0xf8, 0xf2),
+    background: Rgb(0x27, 0x28, 0x22),
+    number: Rgb(0xae, 0x81, 0xff),
+    string: Rgb(0xe6, 0xdb, 0x74),
+    comment: Rgb(0x75, 0x71, 0x5e),
+    keyword: Rgb(0xf9, 0x26, 0x72),
+    type_: Rgb(0x66, 0xd9, 0xef),
+    statement: Rgb(0xfd, 0x97, 0x1f),
+    decorator: Rgb(0xa6, 0xe2, 0x2e),
+    matched: Rgb(0xa6, 0xe2, 0x2e),
+    error: Rgb(0xf9, 0x26, 0x72),
+};
+
+// https://www.nordtheme.com/docs/colors-and-palettes
+const NORD: Theme = Theme {
+    name: "nord",
+    foreground: Rgb(0xd8, 0xde, 0xe9),
+    background: Rgb(0x2e, 0x34, 0x40),
+    number: Rgb(0xb4, 0x8e, 0xad),
+    string: Rgb(0xa3, 0xbe, 0x8c),
+    comment: Rgb(0x61, 0x6e, 0x88),
+    keyword: Rgb(0x81, 0xa1, 0xc1),
+    type_: Rgb(0x8f, 0xbc, 0xbb),
+    statement: Rgb(0xd0, 0x87, 0x70),
+    decorator: Rgb(0x88, 0xc0, 0xd0),
+    matched: Rgb(0xeb, 0xcb, 0x8b),
+    error: Rgb(0xbf, 0x61, 0x6a),
+};
+
+pub const BUILTIN_THEMES: &[Theme] = &[GRUVBOX, SOLARIZED, MONOKAI, NORD];
+
+impl Default for Theme {
+    fn default() -> Self {
+        GRUVBOX
+    }
+}
+
+impl Theme {
+    pub fn builtin(name: &str) -> Option<Theme> {
+        BUILTIN_THEMES.iter().find(|t| t.name == name).copied()
+    }
+
+    // [theme] table in config file. 'name' selects builtin theme and other keys override colors
+    // of the theme with "#rrggbb" notation
+    pub fn from_table(table: &Table) -> Result<Theme, String> {
+        let mut theme = match table.get("name") {
+            None => Theme::default(),
+            Some(Value::String(name)) => match Theme::builtin(name) {
+                Some(theme) => theme,
+                None => return Err(format!("Unknown theme '{}'", name)),
+            },
+            Some(v) => return Err(format!("'name' must be string but got {}", v.type_name())),
+        };
+
+        for (key, value) in table {
+            let field = match key.as_str() {
+                "name" => continue,
+                "foreground" => &mut theme.foreground,
+                "background" => &mut theme.background,
+                "number" => &mut theme.number,
+                "string" => &mut theme.string,
+                "comment" => &mut theme.comment,
+                "keyword" => &mut theme.keyword,
+                "type" => &mut theme.type_,
+                "statement" => &mut theme.statement,
+                "decorator" => &mut theme.decorator,
+                "match" => &mut theme.matched,
+                "error" => &mut theme.error,
+                key => return Err(format!("Unknown key '{}' in [theme]", key)),
+            };
+            *field = match value.as_str().and_then(Rgb::parse) {
+                Some(rgb) => rgb,
+                None => return Err(format!("'{}' must be color like \"#rrggbb\"", key)),
+            };
+        }
+        Ok(theme)
+    }
+}
@@ -0,0 +1,116 @@
+// Token streams of highlighting. Syntax highlighting of a language produces a stream of tokens for
+// each line, and each token is a range of characters with its kind. Layers such as highlights by
+// plugins are token streams too, and they are composed by painting them in order over the tokens
+// produced by the language. Only the composed result is rendered as one highlight per character.
+
+use crate::highlight::Highlight;
+use std::cmp;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Token {
+    pub start: usize, // Index of the first character
+    pub end: usize,   // Exclusive
+    pub kind: Highlight,
+}
+
+// Tokens sorted by their positions without overlapping. Adjacent tokens of the same kind are merged.
+// Characters not covered by any token are transparent: the layer below is visible there
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct TokenStream(Vec<Token>);
+
+impl TokenStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Run-length tokens of highlights of characters. Characters of Normal are also covered
+    pub fn from_highlights(line: &[Highlight]) -> Self {
+        let mut tokens = Self::new();
+        for (x, kind) in line.iter().enumerate() {
+            tokens.push(x, x + 1, *kind);
+        }
+        tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Token> {
+        self.0.iter()
+    }
+
+    // Kind of the token covering the character. None when it is transparent
+    pub fn kind_at(&self, x: usize) -> Option<Highlight> {
+        let i = self.0.partition_point(|t| t.end <= x);
+        self.0.get(i).filter(|t| t.start <= x).map(|t| t.kind)
+    }
+
+    // Paint the range with the kind. Tokens previously pushed in the range are replaced. Pushing
+    // after the last token is cheap since languages produce tokens from left to right
+    pub fn push(&mut self, start: usize, end: usize, kind: Highlight) {
+        if start >= end {
+            return;
+        }
+
+        match self.0.last_mut() {
+            Some(last) if last.end == start && last.kind == kind => {
+                last.end = end;
+                return;
+            }
+            Some(last) if last.end <= start => {
+                self.0.push(Token { start, end, kind });
+                return;
+            }
+            None => {
+                self.0.push(Token { start, end, kind });
+                return;
+            }
+            Some(_) => {}
+        }
+
+        // Tokens in `first..last` overlap with the range. Their parts outside the range remain
+        let first = self.0.partition_point(|t| t.end <= start);
+        let last = cmp::max(first, self.0.partition_point(|t| t.start < end));
+        let mut replaced = Vec::with_capacity(3);
+        if let Some(t) = self.0[first..last].first().filter(|t| t.start < start) {
+            replaced.push(Token { end: start, ..*t });
+        }
+        replaced.push(Token { start, end, kind });
+        if let Some(t) = self.0[first..last].last().filter(|t| end < t.end) {
+            replaced.push(Token { start: end, ..*t });
+        }
+        self.0.splice(first..last, replaced);
+
+        self.0.dedup_by(|next, prev| {
+            let merged = prev.end == next.start && prev.kind == next.kind;
+            if merged {
+                prev.end = next.end;
+            }
+            merged
+        });
+    }
+
+    // Paint the tokens over the highlights of characters. Tokens out of the line are clipped
+    pub fn paint(&self, line: &mut [Highlight]) {
+        for t in &self.0 {
+            let end = cmp::min(t.end, line.len());
+            if t.start < end {
+                line[t.start..end].fill(t.kind);
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenStream {
+    type Item = &'a Token;
+    type IntoIter = std::slice::Iter<'a, Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
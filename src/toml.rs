@@ -0,0 +1,368 @@
+// Small parser for subset of TOML used by configuration files of Kiro.
+//
+// Supported syntax:
+//   Comments, '[table]' headers, bare/quoted/dotted keys, basic strings "..." with escapes,
+//   literal strings '...', integers, floats, booleans, arrays (can span lines) and inline tables
+//
+// Multi-line strings, array of tables '[[...]]' and date-times are not supported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize, // 1-based
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error<T, S: Into<String>>(&self, message: S) -> Result<T, ParseError> {
+        let line = self.chars[..self.pos]
+            .iter()
+            .filter(|c| **c == '\n')
+            .count()
+            + 1;
+        Err(ParseError {
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Skip whitespaces in the same line
+    fn skip_spaces(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    // Skip whitespaces, newlines and comments. This is used in arrays and between statements
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => self.pos += 1,
+                Some('#') => {
+                    while !matches!(self.peek(), Some('\n') | None) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // Only whitespaces and a comment can follow a statement in the same line
+    fn expect_end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        match self.peek() {
+            None | Some('\n') | Some('\r') | Some('#') => Ok(()),
+            Some(c) => self.error(format!("Unexpected character '{}' after value", c)),
+        }
+    }
+
+    fn parse_string(&mut self, quote: char) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return self.error("String is not closed"),
+                Some(c) => c,
+            };
+            self.pos += 1;
+            if c == quote {
+                return Ok(s);
+            }
+            if c != '\\' || quote == '\'' {
+                s.push(c); // No escape sequence in literal string
+                continue;
+            }
+            let escaped = match self.peek() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('u') => {
+                    let hex: String = self.chars.iter().skip(self.pos + 1).take(4).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) if hex.len() == 4 => {
+                            self.pos += 4;
+                            c
+                        }
+                        _ => return self.error("Invalid unicode escape"),
+                    }
+                }
+                _ => return self.error("Invalid escape sequence"),
+            };
+            self.pos += 1;
+            s.push(escaped);
+        }
+    }
+
+    fn parse_key_part(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(q @ '"') | Some(q @ '\'') => {
+                self.pos += 1;
+                self.parse_string(q)
+            }
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                if start == self.pos {
+                    return self.error("Key is expected");
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    // Dotted key like 'a.b.c'
+    fn parse_key(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut keys = vec![];
+        loop {
+            self.skip_spaces();
+            keys.push(self.parse_key_part()?);
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(keys);
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, ParseError> {
+        let mut elems = vec![];
+        loop {
+            self.skip_blank();
+            if self.eat(']') {
+                return Ok(Value::Array(elems));
+            }
+            elems.push(self.parse_value()?);
+            self.skip_blank();
+            if self.eat(']') {
+                return Ok(Value::Array(elems));
+            }
+            if !self.eat(',') {
+                return self.error("',' or ']' is expected in array");
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Value, ParseError> {
+        let mut table = Table::new();
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Value::Table(table));
+        }
+        loop {
+            let keys = self.parse_key()?;
+            if !self.eat('=') {
+                return self.error("'=' is expected after key");
+            }
+            self.skip_spaces();
+            let value = self.parse_value()?;
+            self.insert(&mut table, &keys, value)?;
+            self.skip_spaces();
+            if self.eat('}') {
+                return Ok(Value::Table(table));
+            }
+            if !self.eat(',') {
+                return self.error("',' or '}' is expected in inline table");
+            }
+        }
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || "+-._".contains(c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        let digits = word.replace('_', "");
+        if word == "true" {
+            Ok(Value::Boolean(true))
+        } else if word == "false" {
+            Ok(Value::Boolean(false))
+        } else if let Some(hex) = digits.strip_prefix("0x") {
+            match i64::from_str_radix(hex, 16) {
+                Ok(i) => Ok(Value::Integer(i)),
+                Err(_) => self.error(format!("Invalid hex integer '{}'", word)),
+            }
+        } else if let Ok(i) = digits.parse() {
+            Ok(Value::Integer(i))
+        } else if let Ok(f) = digits.parse() {
+            Ok(Value::Float(f))
+        } else if word.is_empty() {
+            self.error("Value is expected")
+        } else {
+            self.error(format!("Invalid value '{}'", word))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some(q @ '"') | Some(q @ '\'') => {
+                self.pos += 1;
+                Ok(Value::String(self.parse_string(q)?))
+            }
+            Some('[') => {
+                self.pos += 1;
+                self.parse_array()
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.parse_inline_table()
+            }
+            _ => self.parse_scalar(),
+        }
+    }
+
+    fn insert(&self, table: &mut Table, keys: &[String], value: Value) -> Result<(), ParseError> {
+        let (last, parents) = keys.split_last().unwrap();
+        let mut table = table;
+        for key in parents {
+            table = match table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(t) => t,
+                _ => return self.error(format!("Key '{}' is not a table", key)),
+            };
+        }
+        if table.contains_key(last) {
+            return self.error(format!("Key '{}' is defined twice", last));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    // Returns the table specified by '[a.b]' header. It is created when it does not exist
+    fn table_at<'t>(
+        &self,
+        root: &'t mut Table,
+        keys: &[String],
+    ) -> Result<&'t mut Table, ParseError> {
+        let mut table = root;
+        for key in keys {
+            table = match table
+                .entry(key.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(t) => t,
+                _ => return self.error(format!("Key '{}' is not a table", key)),
+            };
+        }
+        Ok(table)
+    }
+
+    fn parse(&mut self) -> Result<Table, ParseError> {
+        let mut root = Table::new();
+        let mut current: Vec<String> = vec![];
+        loop {
+            self.skip_blank();
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Ok(root),
+            };
+            if c == '[' {
+                self.pos += 1;
+                if self.peek() == Some('[') {
+                    return self.error("Array of tables is not supported");
+                }
+                current = self.parse_key()?;
+                if !self.eat(']') {
+                    return self.error("']' is expected after table name");
+                }
+                self.table_at(&mut root, &current)?;
+            } else {
+                let keys = self.parse_key()?;
+                if !self.eat('=') {
+                    return self.error("'=' is expected after key");
+                }
+                self.skip_spaces();
+                let value = self.parse_value()?;
+                let table = self.table_at(&mut root, &current)?;
+                self.insert(table, &keys, value)?;
+            }
+            self.expect_end_of_line()?;
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Table, ParseError> {
+    Parser {
+        chars: source.chars().collect(),
+        pos: 0,
+    }
+    .parse()
+}
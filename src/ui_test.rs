@@ -1,8 +1,39 @@
+use crate::clipboard::{Clipboard, ClipboardBackend};
+use crate::compile;
+use crate::config::Config;
 use crate::editor::Editor;
-use crate::input::{InputSeq, KeySeq};
+use crate::encoding::Encoding;
+use crate::headless::Script;
+use crate::hexview;
+use crate::highlight::{Highlight, Highlighting};
+use crate::input::{InputSeq, InputSequences, KeySeq, ScrollDir};
+use crate::json::Json;
 use crate::language::Language;
-use std::fs::File;
+use crate::lua::Interp;
+use crate::picker::Picker;
+use crate::plugin::{self, Action, Plugin, PluginCommand, Request, Span};
+use crate::row::Row;
+use crate::script::InitScript;
+use crate::search::Matcher;
+use crate::shell;
+use crate::status_bar::{StatusBar, StatusLayout};
+use crate::stream;
+use crate::text_buffer::{CursorDir, TextBuffer};
+use crate::theme::Rgb;
+use crate::token::{Token, TokenStream};
+use crate::vcs;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::env;
+use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::time::Duration;
+
+// Tests do not run on a real terminal so window size cannot be queried
+const WINDOW_SIZE: Option<(usize, usize)> = Some((80, 24));
 
 struct DummyInputs(Vec<InputSeq>);
 
@@ -38,17 +69,33 @@ fn ctrl(c: char) -> InputSeq {
     InputSeq::ctrl(KeySeq::Key(c as u8))
 }
 
+fn alt(c: char) -> InputSeq {
+    let mut seq = key(c);
+    seq.alt = true;
+    seq
+}
+
+fn keys(s: &str) -> Vec<InputSeq> {
+    s.chars().map(key).collect()
+}
+
 fn sp(k: KeySeq) -> InputSeq {
     if let KeySeq::Key(_) = k {
-        assert!(false, "{:?}", k);
+        panic!("{:?}", k);
     }
     InputSeq::new(k)
 }
 
+fn shift(k: KeySeq) -> InputSeq {
+    let mut seq = sp(k);
+    seq.shift = true;
+    seq
+}
+
 #[test]
 fn test_empty_buffer() {
     let input = DummyInputs(vec![InputSeq::ctrl(KeySeq::Key(b'q'))]);
-    let mut editor = Editor::new(input, Discard, None).unwrap();
+    let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
     editor.edit().unwrap();
 
     assert!(editor.screen().rows() > 0);
@@ -62,7 +109,7 @@ fn test_empty_buffer() {
 #[test]
 fn test_write_to_empty_buffer() {
     let input = DummyInputs(vec![key('a'), key('b'), key('c'), ctrl('q'), ctrl('q')]);
-    let mut editor = Editor::new(input, Discard, None).unwrap();
+    let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
     editor.edit().unwrap();
 
     let lines = editor.lines().collect::<Vec<_>>();
@@ -89,7 +136,7 @@ fn test_move_cursor_down() {
         ctrl('q'),
         ctrl('q'),
     ]);
-    let mut editor = Editor::new(input, Discard, None).unwrap();
+    let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
     editor.edit().unwrap();
 
     assert!(editor.screen().rows() > 0);
@@ -104,7 +151,14 @@ fn test_open_file() {
     let input = DummyInputs(vec![ctrl('q')]);
 
     let this_file = file!();
-    let mut editor = Editor::open(input, Discard, None, &[this_file]).unwrap();
+    let mut editor = Editor::open(
+        input,
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[this_file],
+    )
+    .unwrap();
     editor.edit().unwrap();
 
     let f = BufReader::new(File::open(this_file).unwrap());
@@ -114,3 +168,4455 @@ fn test_open_file() {
 
     assert_eq!(editor.lang(), Language::Rust);
 }
+
+#[test]
+fn test_undo_redo() {
+    let before = vec![
+        key('a'),
+        key('b'),
+        key('c'),
+        ctrl('m'),
+        key('d'),
+        ctrl('h'),
+        key('e'),
+    ];
+    let cases: Vec<(Vec<InputSeq>, Vec<&str>)> = vec![
+        (vec![], vec!["abc", "e"]),
+        (vec![ctrl('z')], vec!["abc", ""]),
+        (vec![ctrl('z'), ctrl('z')], vec!["abc", "d"]),
+        (vec![ctrl('z'), ctrl('z'), ctrl('z')], vec!["abc", ""]),
+        (
+            vec![ctrl('z'), ctrl('z'), ctrl('z'), ctrl('z')],
+            vec!["abc"],
+        ),
+        (
+            vec![ctrl('z'), ctrl('z'), ctrl('z'), ctrl('z'), ctrl('z')],
+            vec![],
+        ),
+        (vec![ctrl('z'), ctrl('z'), ctrl('r')], vec!["abc", ""]),
+        (vec![ctrl('z'), ctrl('r'), ctrl('r')], vec!["abc", "e"]),
+        (vec![ctrl('z'), ctrl('z'), key('x')], vec!["abc", "dx"]),
+        (
+            vec![ctrl('z'), ctrl('z'), key('x'), ctrl('r')],
+            vec!["abc", "dx"],
+        ),
+    ];
+
+    for (keys, expected) in cases {
+        let mut input = before.clone();
+        input.extend(keys.iter().cloned());
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "keys: {:?}", keys);
+    }
+}
+
+#[test]
+fn test_regex_search() {
+    let cases = vec![
+        ("\\d+", vec!["foo bar", "baz X123 qux"]),
+        ("b[a-z]+$", vec!["foo Xbar", "baz 123 qux"]),
+        ("(qux|baz) 1", vec!["foo bar", "Xbaz 123 qux"]),
+        ("^q", vec!["foo bar", "baz 123 qux"]), // Not found
+    ];
+
+    for (query, expected) in cases {
+        let mut input = keys("foo bar");
+        input.push(ctrl('m'));
+        input.extend(keys("baz 123 qux"));
+        input.push(alt('<'));
+        input.push(alt('r'));
+        input.extend(keys(query));
+        input.push(ctrl('m'));
+        if expected != vec!["foo bar", "baz 123 qux"] {
+            input.push(key('X'));
+        }
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "query: {:?}", query);
+    }
+}
+
+#[test]
+fn test_search_matches_in_line() {
+    // (query, keys after query, expected)
+    let cases: &[(&str, &[InputSeq], &[&str])] = &[
+        ("ab", &[], &["Xab ab", "ab"]),
+        ("ab", &[ctrl('n')], &["ab Xab", "ab"]),
+        ("ab", &[ctrl('n'), ctrl('n')], &["ab ab", "Xab"]),
+        ("ab", &[ctrl('n'), ctrl('n'), ctrl('n')], &["Xab ab", "ab"]), // Wrap around
+        ("ab", &[ctrl('p')], &["ab ab", "Xab"]),
+        ("ab", &[ctrl('p'), ctrl('p')], &["ab Xab", "ab"]),
+        ("ab", &[ctrl('n'), ctrl('p')], &["Xab ab", "ab"]),
+    ];
+    for (query, moves, expected) in cases {
+        let mut input = keys("ab ab");
+        input.push(ctrl('m'));
+        input.extend(keys("ab"));
+        input.push(alt('<'));
+        input.push(ctrl('g'));
+        input.extend(keys(query));
+        input.extend(moves.iter().cloned());
+        input.push(ctrl('m'));
+        input.push(key('X'));
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(&lines, expected, "query: {:?}, moves: {:?}", query, moves);
+    }
+
+    let matcher = Matcher::new("良い", false).unwrap();
+    assert_eq!(matcher.find_all("良い🦀良い"), vec![(0, 2), (3, 5)]);
+    assert_eq!(matcher.find_at("良い🦀良い", 1), Some((3, 5)));
+    let matcher = Matcher::new("(foo|ba)r+", true).unwrap();
+    assert_eq!(matcher.find_all("foor bar baz"), vec![(0, 4), (5, 8)]);
+    assert!(matcher.find_all("fo ba").is_empty());
+}
+
+#[test]
+fn test_regex_pathological_patterns() {
+    // Match as long as the line does not overflow the stack
+    let line = format!("a{}b", "x".repeat(100_000));
+    let matcher = Matcher::new("a.*b", true).unwrap();
+    assert_eq!(matcher.find_all(&line), vec![(0, 100_002)]);
+
+    // Ambiguous repeat does not take exponential time
+    let line = "a".repeat(26);
+    let matcher = Matcher::new("(a|a)*[bc]", true).unwrap();
+    assert!(matcher.find_all(&line).is_empty());
+    let matcher = Matcher::new("(a*)*b", true).unwrap();
+    assert_eq!(matcher.find_at(&format!("{}b", line), 0), Some((0, 27)));
+
+    // Greedy repeat and the first alternative are preferred
+    let matcher = Matcher::new("a.*b", true).unwrap();
+    assert_eq!(matcher.find_at("xaxbxb", 0), Some((1, 6)));
+    let matcher = Matcher::new("(a|ab)(c|bcd)", true).unwrap();
+    assert_eq!(matcher.find_at("abcd", 0), Some((0, 4)));
+
+    let err = Matcher::new("(a{100}b){200}", true).err().unwrap();
+    assert_eq!(err.message, "Pattern is too complex");
+}
+
+#[test]
+fn test_highlight_all_matches() {
+    let mut buf = TextBuffer::new();
+    buf.insert_text("ab ab\nab");
+    let mut hl = Highlighting::new(Language::Plain, buf.rows());
+    let matcher = Matcher::new("ab", false).unwrap();
+    let matches: Vec<_> = buf
+        .lines()
+        .enumerate()
+        .flat_map(|(y, l)| matcher.find_all(l).into_iter().map(move |(s, e)| (y, s, e)))
+        .collect();
+    assert_eq!(matches, vec![(0, 0, 2), (0, 3, 5), (1, 0, 2)]);
+    assert_eq!(hl.set_other_matches(&matches), Some(0));
+    assert_eq!(hl.set_other_matches(&matches), None);
+    hl.set_match(0, 3, 5);
+    hl.update(buf.rows(), buf.rows().len());
+    use Highlight::{Match, Normal, OtherMatch};
+    assert!(hl.lines[0] == vec![OtherMatch, OtherMatch, Normal, Match, Match]);
+    assert!(hl.lines[1] == vec![OtherMatch, OtherMatch]);
+
+    hl.clear_previous_match();
+    assert_eq!(hl.set_other_matches(&[]), Some(0));
+    hl.update(buf.rows(), buf.rows().len());
+    assert!(hl.lines.iter().flatten().all(|h| *h == Normal));
+}
+
+#[test]
+fn test_highlight_tokens() {
+    use Highlight::{Comment, Keyword, Normal, Number, Type};
+    let token = |start, end, kind| Token { start, end, kind };
+
+    let mut tokens = TokenStream::new();
+    tokens.push(0, 3, Keyword);
+    tokens.push(3, 5, Normal);
+    tokens.push(5, 6, Normal);
+    tokens.push(8, 10, Number);
+    assert_eq!(
+        tokens.iter().copied().collect::<Vec<_>>(),
+        vec![
+            token(0, 3, Keyword),
+            token(3, 6, Normal),
+            token(8, 10, Number)
+        ],
+    );
+    assert_eq!(tokens.kind_at(4), Some(Normal));
+    assert_eq!(tokens.kind_at(7), None);
+    // Painting over tokens splits them and merges neighbors of the same kind
+    tokens.push(2, 4, Type);
+    tokens.push(4, 9, Type);
+    assert_eq!(
+        tokens.iter().copied().collect::<Vec<_>>(),
+        vec![
+            token(0, 2, Keyword),
+            token(2, 9, Type),
+            token(9, 10, Number)
+        ],
+    );
+    let mut line = vec![Normal; 12];
+    tokens.paint(&mut line);
+    assert_eq!(TokenStream::from_highlights(&line[..10]), tokens);
+
+    let mut buf = TextBuffer::new();
+    buf.insert_text("let x = 0x1f; // TODO");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    hl.set_spans(&[(0, 16, 20, Type)]);
+    hl.update(buf.rows(), 1);
+    assert_eq!(
+        hl.tokens(0).unwrap().iter().copied().collect::<Vec<_>>(),
+        vec![
+            token(0, 3, Keyword),
+            token(3, 8, Normal),
+            token(8, 12, Number),
+            token(12, 14, Normal),
+            token(14, 21, Comment),
+        ],
+    );
+    // Highlights by plugins are composed over the tokens of the language
+    let composed = hl.composed_tokens(0).unwrap();
+    assert_eq!(composed.kind_at(15), Some(Comment));
+    assert_eq!(composed.kind_at(16), Some(Type));
+    assert_eq!(composed.kind_at(20), Some(Comment));
+    assert_eq!(hl.composed_tokens(1), None);
+}
+
+#[test]
+fn test_search_history() {
+    let state = env::temp_dir().join(format!("kiro-test-history-{}", process::id()));
+    let alt_key = |key| InputSeq {
+        key,
+        ctrl: false,
+        alt: true,
+        shift: false,
+    };
+    let run = |input: Vec<InputSeq>| {
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.set_state_dir(Some(state.clone()));
+        editor.edit().unwrap();
+        editor.lines().map(String::from).collect::<Vec<_>>()
+    };
+
+    let text = || {
+        let mut input = keys("foo");
+        input.push(ctrl('m'));
+        input.extend(keys("bar"));
+        input.push(alt('<'));
+        input
+    };
+
+    let mut input = text();
+    input.push(ctrl('g'));
+    input.extend(keys("x\\y"));
+    input.push(ctrl('m'));
+    input.push(ctrl('g'));
+    input.extend(keys("bar"));
+    input.push(ctrl('m'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    run(input);
+    let saved = fs::read_to_string(state.join("history")).unwrap();
+    assert_eq!(saved, "search\tx\\\\y\nsearch\tbar\n");
+
+    // Inputs are recalled in next session. Going newer than the newest restores the typed input
+    let mut input = text();
+    input.push(ctrl('g'));
+    input.push(key('b'));
+    input.extend(vec![alt_key(KeySeq::UpKey); 3]);
+    input.push(alt_key(KeySeq::DownKey));
+    input.push(ctrl('m'));
+    input.push(key('X'));
+    input.push(ctrl('g'));
+    input.push(alt_key(KeySeq::UpKey));
+    input.push(alt_key(KeySeq::DownKey));
+    input.push(key('o'));
+    input.push(ctrl('m'));
+    input.push(key('Y'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    assert_eq!(run(input), vec!["fYoo", "Xbar"]);
+    fs::remove_dir_all(&state).unwrap();
+}
+
+#[test]
+fn test_session_restore() {
+    let dir = env::temp_dir().join(format!("kiro-test-session-{}", process::id()));
+    let state = dir.join("state");
+    let path = dir.join("file.txt");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(&path, "abc\ndef\nghi\n").unwrap();
+    let run = |input: Vec<InputSeq>| {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.set_state_dir(Some(state.clone()));
+        editor.edit().unwrap();
+        editor.lines().map(String::from).collect::<Vec<_>>()
+    };
+
+    run(vec![ctrl('n'), ctrl('n'), ctrl('f'), ctrl('q')]);
+    let abs = fs::canonicalize(&path).unwrap();
+    let saved = fs::read_to_string(state.join("session")).unwrap();
+    assert_eq!(saved, format!("1\t2\t0\t{}\n", abs.display()));
+
+    // Cursor is restored in next session
+    let lines = run(vec![key('X'), ctrl('q'), ctrl('q')]);
+    assert_eq!(lines, vec!["abc", "def", "gXhi"]);
+
+    // Broken lines are ignored and a position out of the buffer is clamped
+    let broken = format!("garbage\n1\tx\t0\t/a\n9\t9\t0\t{}\n", abs.display());
+    fs::write(state.join("session"), broken).unwrap();
+    let lines = run(vec![key('X'), ctrl('q'), ctrl('q')]);
+    assert_eq!(lines, vec!["abc", "def", "ghiX"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_show_messages() {
+    let mut input = vec![ctrl('z'), ctrl('g')];
+    input.extend(keys("foo"));
+    input.push(ctrl('m'));
+    input.push(alt('c'));
+    input.extend(keys("show-messages"));
+    input.push(ctrl('m'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    // Prompts are not recorded
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].ends_with("Z info  No older change"), "{:?}", lines);
+    assert!(lines[1].ends_with("Z error Not Found"), "{:?}", lines);
+    let date = lines[0].split(' ').next().unwrap();
+    assert_eq!(date.len(), "2020-01-01T00:00:00Z".len(), "{:?}", date);
+}
+
+#[test]
+fn test_replace() {
+    let cases = vec![
+        ("a", vec!['a'], vec!["xx b xx", "b xx"]),
+        ("y", vec!['n', 'y', 'q'], vec!["a b xx", "b a"]),
+        ("yn", vec!['y', 'y', 'n'], vec!["xx b xx", "b a"]),
+        ("", vec!['q'], vec!["a b a", "b a"]),
+    ];
+
+    for (name, answers, expected) in cases {
+        let mut input = keys("a b a");
+        input.push(ctrl('m'));
+        input.extend(keys("b a"));
+        input.push(alt('<'));
+        input.push(alt('%'));
+        input.push(key('a'));
+        input.push(ctrl('m'));
+        input.extend(keys("xx"));
+        input.push(ctrl('m'));
+        input.extend(answers.into_iter().map(key));
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "case: {:?}", name);
+    }
+}
+
+#[test]
+fn test_select_buffer_by_name() {
+    let files = [file!(), "src/lib.rs"];
+    let cases = vec![
+        ("lib", 1),
+        ("1", 0),
+        ("src/lib.rs", 1),
+        ("nothing", 0),
+        ("src", 0),
+    ];
+
+    for (name, expected) in cases {
+        let mut input = vec![alt('o')];
+        input.extend(keys(name));
+        input.push(ctrl('m'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &files,
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let f = BufReader::new(File::open(files[expected]).unwrap());
+        let expected_lines = f.lines().map(Result::unwrap).collect::<Vec<_>>();
+        let actual_lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(expected_lines, actual_lines, "name: {:?}", name);
+    }
+}
+
+#[test]
+fn test_mouse() {
+    use KeySeq::*;
+
+    let cases = vec![
+        (MouseClick(1, 2, 0), vec!["aXbc", "d\tef"]),
+        (MouseClick(2, 5, 0), vec!["abc", "dX\tef"]), // In the middle of tab
+        (MouseClick(2, 10, 0), vec!["abc", "d\teXf"]),
+        (MouseClick(9, 1, 0), vec!["abc", "d\tef", "X"]), // Below the last line
+        (MouseClick(1, 2, 2), vec!["abc", "d\tefX"]),     // Right click does nothing
+        (MouseScroll(ScrollDir::Down), vec!["abc", "d\tefX"]),
+    ];
+
+    for (seq, expected) in cases {
+        let mut input = keys("abc");
+        input.push(ctrl('m'));
+        input.push(key('d'));
+        input.push(ctrl('i'));
+        input.extend(keys("ef"));
+        input.push(sp(seq.clone()));
+        input.push(key('X'));
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "seq: {:?}", seq);
+    }
+}
+
+#[test]
+fn test_copy_paste() {
+    let cases: Vec<(Vec<InputSeq>, Vec<&str>)> = vec![
+        (vec![ctrl('k'), ctrl('v'), ctrl('v')], vec!["abcabc", "def"]),
+        (vec![ctrl('c'), ctrl('v')], vec!["abc", "abc", "def"]),
+        (vec![ctrl('e'), ctrl('k'), ctrl('v')], vec!["abc", "def"]),
+        (
+            vec![ctrl('e'), ctrl('w'), ctrl('n'), ctrl('v')],
+            vec!["", "abcdef"],
+        ),
+        (vec![ctrl('v')], vec!["abc", "def"]), // Clipboard is empty
+    ];
+
+    for (seq, expected) in cases {
+        let mut input = keys("abc");
+        input.push(ctrl('m'));
+        input.extend(keys("def"));
+        input.push(alt('<'));
+        input.extend(seq.iter().cloned());
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "seq: {:?}", seq);
+    }
+}
+
+// Clipboard shared with other applications
+struct SharedClipboard(Rc<RefCell<String>>);
+
+impl ClipboardBackend for SharedClipboard {
+    fn copy(&self, text: &str) -> io::Result<()> {
+        *self.0.borrow_mut() = text.to_string();
+        Ok(())
+    }
+
+    fn paste(&self) -> io::Result<String> {
+        Ok(self.0.borrow().clone())
+    }
+}
+
+#[test]
+fn test_clipboard_backend() {
+    let shared = Rc::new(RefCell::new("xyz".to_string()));
+    let mut input = keys("abc");
+    input.push(ctrl('v')); // Paste text copied by other application
+    input.push(ctrl('a'));
+    input.push(ctrl('k'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let clipboard = Clipboard::new(Box::new(SharedClipboard(shared.clone())));
+    let mut editor = Editor::new(DummyInputs(input), Discard, WINDOW_SIZE, clipboard).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec![""]);
+    assert_eq!(*shared.borrow(), "abcxyz");
+}
+
+#[test]
+fn test_selection() {
+    use KeySeq::*;
+
+    let mut shift_tab = ctrl('i');
+    shift_tab.shift = true;
+
+    let cases: Vec<(Vec<InputSeq>, Vec<&str>)> = vec![
+        (
+            vec![shift(RightKey), shift(RightKey), ctrl('w')],
+            vec!["c", "def", "ghi"],
+        ),
+        (vec![ctrl(' '), ctrl('n'), ctrl('w')], vec!["def", "ghi"]),
+        (
+            vec![
+                shift(DownKey),
+                shift(RightKey),
+                ctrl('c'),
+                alt('>'),
+                ctrl('v'),
+            ],
+            vec!["abc", "def", "ghi", "abc", "d"],
+        ),
+        (
+            vec![shift(DownKey), shift(DownKey), ctrl('i')],
+            vec!["\tabc", "\tdef", "ghi"],
+        ),
+        (
+            vec![shift(DownKey), ctrl('i'), ctrl('i'), shift_tab],
+            vec!["\tabc", "def", "ghi"],
+        ),
+        (
+            // Moving cursor without shift cancels selection
+            vec![shift(RightKey), sp(RightKey), ctrl('c'), ctrl('v')],
+            vec!["ababc", "c", "def", "ghi"],
+        ),
+        (
+            vec![shift(RightKey), ctrl('w'), ctrl('z')],
+            vec!["abc", "def", "ghi"],
+        ),
+    ];
+
+    for (seq, expected) in cases {
+        let mut input = keys("abc");
+        input.push(ctrl('m'));
+        input.extend(keys("def"));
+        input.push(ctrl('m'));
+        input.extend(keys("ghi"));
+        input.push(alt('<'));
+        input.extend(seq.iter().cloned());
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "seq: {:?}", seq);
+    }
+}
+
+#[test]
+fn test_edit_distant_lines() {
+    let cases: Vec<(Vec<InputSeq>, Vec<&str>)> = vec![
+        (vec![], vec!["0", "2", "3", "4", "5"]),
+        (
+            vec![ctrl('z'), ctrl('z')],
+            vec!["0", "1", "2", "3", "4", "5"],
+        ),
+    ];
+
+    for (seq, expected) in cases {
+        let mut input = vec![];
+        for c in "1234".chars() {
+            input.push(key(c));
+            input.push(ctrl('m'));
+        }
+        input.push(ctrl('h'));
+        // Edit lines at top and bottom alternately
+        input.push(alt('<'));
+        input.push(key('0'));
+        input.push(ctrl('m'));
+        input.push(alt('>'));
+        input.push(key('5'));
+        input.push(alt('<'));
+        input.push(ctrl('n'));
+        input.push(ctrl('k'));
+        input.push(ctrl('k'));
+        input.extend(seq.iter().cloned());
+        input.push(ctrl('q'));
+        input.push(ctrl('q'));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        let lines = editor.lines().collect::<Vec<_>>();
+        assert_eq!(lines, expected, "seq: {:?}", seq);
+    }
+}
+
+#[test]
+fn test_detect_lang_from_shebang() {
+    let cases = vec![
+        ("#!/usr/bin/env python3", Language::Python),
+        ("#!/usr/bin/python", Language::Python),
+        ("#!/usr/bin/env -S python3.8 -u", Language::Python),
+        ("#!/bin/sh", Language::Plain),
+        ("python", Language::Plain),
+    ];
+
+    let path = env::temp_dir().join(format!("kiro-test-shebang-{}", process::id()));
+    for (shebang, expected) in cases {
+        fs::write(&path, format!("{}\nprint('hello')\n", shebang)).unwrap();
+        let input = DummyInputs(vec![ctrl('q')]);
+        let mut editor =
+            Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lang(), expected, "shebang: {}", shebang);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_help_scroll() {
+    let help = Config::default().keymap.help();
+    assert!(help.lines().any(|l| l.starts_with("Alt-C recent-files ")
+        && l.ends_with(": Open a file edited in previous sessions")));
+
+    // Cursor keys scroll the long help and do not close it. Other key closes it without inserting
+    let mut input = vec![ctrl('?')];
+    input.extend(vec![sp(KeySeq::DownKey); 200]);
+    input.push(sp(KeySeq::PageUpKey));
+    input.push(ctrl('v'));
+    input.push(key('x'));
+    input.push(key('a'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["a"]);
+}
+
+#[test]
+fn test_rebind_keys() {
+    let config =
+        Config::parse("[keymap]\nsave = \"Ctrl-W\"\nundo = [\"Alt-U\", \"C-/\"]\n").unwrap();
+    let help = config.keymap.help();
+    assert!(help.contains("Ctrl-W"), "{}", help);
+    assert!(help
+        .lines()
+        .any(|l| l.starts_with("Ctrl-W ") && l.ends_with(": Save to file")));
+    assert!(help.lines().any(|l| l.starts_with("Alt-U or Ctrl-/ ")));
+    assert!(!help.contains("Ctrl-S "), "{}", help);
+    assert!(!help.contains("Ctrl-Z "), "{}", help);
+
+    let path = env::temp_dir().join(format!("kiro-test-keymap-{}", process::id()));
+    fs::write(&path, "").unwrap();
+    let mut input = keys("abc");
+    input.push(alt('u'));
+    input.extend(keys("def"));
+    input.push(ctrl('w')); // Save
+    input.push(ctrl('s')); // Not mapped
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.set_keymap(config.keymap);
+    editor.edit().unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "def\n");
+    assert_eq!(editor.screen().message_text(), "Key 'C-s' not mapped");
+    fs::remove_file(&path).unwrap();
+
+    for invalid in &[
+        "[keymap]\nsave = \"Ctrl-FOO\"",
+        "[keymap]\nunknown = \"Ctrl-S\"",
+        "[keymap]\nsave = 1",
+        "unknown = true",
+    ] {
+        assert!(Config::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_command_palette() {
+    let mut input = vec![alt('c')];
+    input.extend(keys("stlng\r")); // Fuzzy match with 'set-language'
+    input.extend(keys("python\r"));
+    input.push(alt('c'));
+    input.extend(keys("xyz\r"));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    assert_eq!(editor.lang(), Language::Python);
+    assert_eq!(editor.screen().message_text(), "No command matches 'xyz'");
+
+    let mut input = keys("abc");
+    input.push(alt('c'));
+    input.extend(keys("undo\r"));
+    input.push(alt('c'));
+    input.extend(keys("quit\r"));
+    input.push(ctrl('q')); // Quit with unsaved changes
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    assert_eq!(editor.lines().count(), 0);
+}
+
+// Plugin compiled into the editor. It uppercases selected text and highlights 'TODO' in Rust
+struct Upcase(Rc<Cell<usize>>); // Count of highlighting
+
+impl Plugin for Upcase {
+    fn name(&self) -> &str {
+        "case"
+    }
+
+    fn commands(&self) -> Vec<PluginCommand> {
+        vec![PluginCommand {
+            name: "upcase".to_string(),
+            description: "Uppercase selected text".to_string(),
+            keys: vec!["Alt-U".to_string(), "Ctrl-S".to_string()],
+        }]
+    }
+
+    fn run(&mut self, req: &Request<'_>) -> crate::Result<Vec<Action>> {
+        assert_eq!(req.command, "upcase");
+        Ok(match &req.selection {
+            Some(text) => vec![
+                Action::Insert(text.to_uppercase()),
+                Action::Message(format!("Uppercased at line {}", req.cursor.1 + 1)),
+            ],
+            None => vec![Action::Error("Nothing is selected".to_string())],
+        })
+    }
+
+    fn languages(&self) -> Vec<Language> {
+        vec![Language::Rust]
+    }
+
+    fn highlight(&mut self, _lang: Language, lines: &[&str]) -> crate::Result<Vec<Span>> {
+        self.0.set(self.0.get() + 1);
+        let spans = lines.iter().enumerate().filter_map(|(y, line)| {
+            let start = line.find("TODO")?;
+            let end = start + 4;
+            let hl = Highlight::Keyword;
+            Some(Span { y, start, end, hl })
+        });
+        Ok(spans.collect())
+    }
+}
+
+#[test]
+fn test_plugin_commands() {
+    let highlighted = Rc::new(Cell::new(0));
+    let script = Script::new()
+        .keys("hello world")
+        .ctrl(' ')
+        .alt('b')
+        .alt('u') // Bound by the plugin
+        .alt('c')
+        .keys("upcase\r");
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor
+        .add_plugin(Box::new(Upcase(highlighted.clone())))
+        .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["hello WORLD"]);
+    assert_eq!(editor.screen().message_text(), "Nothing is selected");
+    assert_eq!(highlighted.get(), 0); // Plain text is not highlighted by the plugin
+
+    // Ctrl-S is not bound by the plugin since it is bound to save
+    let script = Script::new()
+        .alt('c')
+        .keys("set-language\rrust\r")
+        .keys("// TODO")
+        .ctrl(' ')
+        .key(KeySeq::HomeKey)
+        .alt('c')
+        .keys("upcase\r");
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor
+        .add_plugin(Box::new(Upcase(highlighted.clone())))
+        .unwrap();
+    assert!(editor
+        .add_plugin(Box::new(Upcase(highlighted.clone())))
+        .is_err());
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["// TODO"]);
+    assert_eq!(editor.screen().message_text(), "Uppercased at line 1");
+    assert!(highlighted.get() > 0);
+
+    let mut buf = TextBuffer::new();
+    buf.insert_text("let x; // TODO");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    assert_eq!(hl.set_spans(&[(0, 10, 14, Highlight::Type)]), Some(0));
+    assert_eq!(hl.set_spans(&[(0, 10, 14, Highlight::Type)]), None);
+    hl.update(buf.rows(), 1);
+    assert_eq!(hl.lines[0][0], Highlight::Keyword);
+    assert_eq!(hl.lines[0][7], Highlight::Comment);
+    assert_eq!(hl.lines[0][10], Highlight::Type);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_plugin_process() {
+    let script = r#"
+        printf 'command\treverse\tReverse\\tlines\nkey\treverse\tAlt-U\nhighlight\tplain\nready\n'
+        while IFS= read -r line; do
+            case "$line" in
+                end) printf 'insert\tx\\ty\\nz\nreplace\t1\tZ\ncursor\t0\t0\nmessage\tReversed\ndone\n' ;;
+                highlight*) printf 'span\t0\t0\t1\tnumber\ndone\n' ;;
+            esac
+        done
+    "#;
+    let process = plugin::Process::spawn("reverse", script).unwrap();
+    let commands = process.commands();
+    assert_eq!(commands[0].name, "reverse");
+    assert_eq!(commands[0].description, "Reverse\tlines");
+    assert_eq!(commands[0].keys, vec!["Alt-U"]);
+
+    let input = Script::new().keys("abc").alt('u');
+    let mut editor =
+        Editor::new(input.into_iter(), vec![], WINDOW_SIZE, Clipboard::default()).unwrap();
+    editor.add_plugin(Box::new(process)).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["abcx\ty", "Z"]);
+    assert_eq!(editor.screen().message_text(), "Reversed");
+    assert_eq!(editor.screen().rendered_cursor(), (0, 0));
+
+    // The process exits before replying
+    let mut process = plugin::Process::spawn("broken", "echo ready").unwrap();
+    let req = Request {
+        command: "reverse",
+        filename: "",
+        lang: Language::Plain,
+        cursor: (0, 0),
+        selection: None,
+        lines: vec![],
+    };
+    assert!(process.run(&req).is_err());
+    assert!(plugin::Process::spawn("broken", "echo hello").is_err());
+}
+
+#[test]
+fn test_plugin_keymap() {
+    let config = Config::parse("[keymap]\ncase.upcase = \"Ctrl-S\"\n").unwrap();
+    let script = Script::new()
+        .keys("abc")
+        .ctrl(' ')
+        .key(KeySeq::HomeKey)
+        .ctrl('s');
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.set_keymap(config.keymap);
+    editor
+        .add_plugin(Box::new(Upcase(Rc::new(Cell::new(0)))))
+        .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ABC"]);
+
+    let config = Config::parse("[plugins]\ncase = \"kiro-case\"\n").unwrap();
+    assert_eq!(config.plugins["case"], "kiro-case");
+    assert!(Config::parse("[plugins]\ncase = 1\n").is_err());
+    assert!(Config::parse("[keymap.case]\nupcase = 1\n").is_err());
+}
+
+#[test]
+fn test_lua_interpreter() {
+    let run = |src: &str| {
+        let mut interp = Interp::new();
+        interp.exec(src).map(|vals| {
+            let vals: Vec<_> = vals.iter().map(|v| v.to_string()).collect();
+            vals.join(",")
+        })
+    };
+    assert_eq!(
+        run("return 1 + 2 * 3 ^ 2, -2 ^ 2, 7 % 3, -7 % 3, 1 / 2").unwrap(),
+        "19,-4,1,2,0.5"
+    );
+    assert_eq!(
+        run("return 'a' .. 1 .. 'b', #'日本語', 10 == 10.0, 'a' < 'b'").unwrap(),
+        "a1b,3,true,true"
+    );
+    assert_eq!(
+        run("return nil or false, 1 and 2, not nil, 0 and 'zero'").unwrap(),
+        "false,2,true,zero"
+    );
+    assert_eq!(
+        run(r#"
+            local function fib(n)
+                if n < 2 then return n end
+                return fib(n - 1) + fib(n - 2)
+            end
+            local t = {}
+            for i = 1, 10 do t[#t + 1] = fib(i) end
+            return table.concat(t, " ")
+        "#)
+        .unwrap(),
+        "1 1 2 3 5 8 13 21 34 55"
+    );
+    assert_eq!(
+        run(r#"
+            local function counter()
+                local n = 0
+                return function() n = n + 1; return n end
+            end
+            local c1, c2 = counter(), counter()
+            c1(); c1()
+            return c1(), c2()
+        "#)
+        .unwrap(),
+        "3,1"
+    );
+    assert_eq!(
+        run(r#"
+            local obj = {name = "kiro", items = {"a", "b", "c"}, [10] = "ten"}
+            function obj:greet(s) return s .. ", " .. self.name end
+            local keys = {}
+            for k, v in pairs(obj.items) do keys[#keys + 1] = k .. "=" .. v end
+            local i, out = 0, {}
+            while true do
+                i = i + 1
+                if i > 5 then break end
+                if i % 2 == 0 then out[#out + 1] = i end
+            end
+            repeat local j = i; i = i - 1 until j <= 3
+            return obj:greet("hello"), table.concat(keys, " "), obj[10], #out, i
+        "#)
+        .unwrap(),
+        "hello, kiro,1=a 2=b 3=c,ten,2,2"
+    );
+    assert_eq!(
+        run(r#"
+            local s = "Hello, World"
+            local a, b = s:find("World")
+            return s:upper(), s:sub(1, 5), s:sub(-5), a, b, s:gsub("o", "0"),
+                string.format("%s=%d %5.2f|%-3s|", "x", 42.9, 3.14159, "y"), s:rep(2, "/"),
+                ("%d"):format(7)
+        "#)
+        .unwrap(),
+        "HELLO, WORLD,Hello,World,8,12,Hell0, W0rld,x=42  3.14|y  |,Hello, World/Hello, World,7"
+    );
+    assert_eq!(
+        run(r#"
+            local ok, err = pcall(function() error("boom") end)
+            local t = {1, 2, 3}
+            table.insert(t, 1, 0)
+            table.remove(t)
+            return ok, err, tostring(nil), tonumber("0x10"), tonumber("z", 36), type(print), #t, t[1]
+        "#)
+        .unwrap(),
+        "false,boom at line 2,nil,16,35,function,3,0"
+    );
+
+    let err = run("local x = 1\nlocal y = x + nil").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Attempt to perform arithmetic on a nil value at line 2"
+    );
+    let err = run("local t = {}\n\nt.a.b = 1").unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(run("x = = 1").is_err());
+    assert!(run("if true then").is_err());
+    assert!(run("return 'abc").is_err());
+    assert!(run("1 + 2").is_err());
+    assert_eq!(
+        run("while true do end").unwrap_err().message,
+        "Script took too long"
+    );
+    assert_eq!(
+        run("local function f() return f() + 1 end\nreturn f()")
+            .unwrap_err()
+            .message,
+        "Stack overflow"
+    );
+}
+
+#[test]
+fn test_init_script() {
+    let src = r#"
+        -- Comments are ignored
+        kiro.command("upcase-line", "Uppercase the current line", function()
+            local y = kiro.cursor.line()
+            kiro.buffer.set_line(y, kiro.buffer.line(y):upper())
+            kiro.cursor.set(y, 100)
+            print("Uppercased line", y)
+        end)
+        kiro.command("wrap", "Wrap selected text with brackets", function()
+            local text = kiro.selection.text()
+            if not text then
+                kiro.error("Nothing is selected")
+                return
+            end
+            kiro.buffer.insert("[" .. text .. "]")
+        end)
+        kiro.command("broken", "Fails", function() local x = nil; return x.y end)
+        kiro.bind("Alt-U", "upcase-line")
+    "#;
+    let init = InitScript::new(Path::new("init.lua"), src).unwrap();
+    let input = Script::new()
+        .keys("hello\rworld")
+        .key(KeySeq::UpKey)
+        .alt('u')
+        .key(KeySeq::DownKey)
+        .key(KeySeq::HomeKey)
+        .ctrl(' ')
+        .key(KeySeq::EndKey)
+        .alt('c')
+        .keys("wrap\r");
+    let mut editor =
+        Editor::new(input.into_iter(), vec![], WINDOW_SIZE, Clipboard::default()).unwrap();
+    editor.add_plugin(Box::new(init)).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["HELLO", "[world]"]);
+
+    let init = InitScript::new(Path::new("init.lua"), src).unwrap();
+    let input = Script::new().keys("abc").alt('u').alt('c').keys("wrap\r");
+    let mut editor =
+        Editor::new(input.into_iter(), vec![], WINDOW_SIZE, Clipboard::default()).unwrap();
+    editor.add_plugin(Box::new(init)).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ABC"]);
+    assert_eq!(editor.screen().message_text(), "Nothing is selected");
+    assert_eq!(editor.screen().rendered_cursor().0, 3); // Column is clamped to the end of line
+
+    let init = InitScript::new(Path::new("init.lua"), src).unwrap();
+    let input = Script::new().alt('c').keys("broken\r");
+    let mut editor =
+        Editor::new(input.into_iter(), vec![], WINDOW_SIZE, Clipboard::default()).unwrap();
+    editor.add_plugin(Box::new(init)).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.screen().message_text(),
+        "Command 'broken' failed: Attempt to index a nil value (field 'y') at line 17 in init.lua"
+    );
+
+    let err = InitScript::new(Path::new("init.lua"), "kiro.buffer.line(1)")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Could not load init.lua: Buffer is not available while loading init script at line 1"
+    );
+    assert!(InitScript::new(Path::new("init.lua"), "kiro.bind('Alt-U', 'nothing')").is_err());
+
+    let missing = env::temp_dir().join(format!("kiro-test-init-{}.lua", process::id()));
+    assert!(InitScript::load(&missing).unwrap().is_none());
+    fs::write(&missing, "kiro.command('x', 'x', 42)").unwrap();
+    let mut editor = Editor::new(
+        Script::new().into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.load_script(&missing);
+    fs::remove_file(&missing).unwrap();
+    assert!(editor
+        .screen()
+        .message_text()
+        .contains("function expected, got number"));
+}
+
+#[test]
+fn test_list_picker() {
+    let items: Vec<_> = (0..30).map(|i| format!("item-{}", i)).collect();
+    let mut picker = Picker::new(items);
+    assert_eq!(picker.page(10).0[0], "item-0");
+
+    // Page follows the selection
+    picker.move_selection(15);
+    let (lines, selected) = picker.page(10);
+    assert_eq!((lines[0].as_str(), selected), ("item-6", 9));
+    picker.move_selection(-12);
+    let (lines, selected) = picker.page(10);
+    assert_eq!((lines[0].as_str(), selected), ("item-3", 0));
+    picker.move_selection(100);
+    assert_eq!((picker.position(), picker.len()), (30, 30));
+    picker.move_selection(-100);
+    assert_eq!(picker.selected(), Some(0));
+
+    picker.filter("m2");
+    assert_eq!(picker.selected().map(|i| picker.item(i)), Some("item-2"));
+    assert_eq!(picker.len(), 12); // item-2, item-12 and item-20~29
+    picker.filter("xyz");
+    assert_eq!((picker.selected(), picker.position()), (None, 0));
+
+    // The list in command palette is selected by UP/DOWN keys
+    let mut input = vec![alt('c')];
+    input.extend(keys("set-"));
+    input.push(sp(KeySeq::UpKey));
+    input.push(sp(KeySeq::UpKey));
+    input.push(sp(KeySeq::DownKey));
+    input.push(sp(KeySeq::DownKey));
+    input.extend(keys("l"));
+    input.push(sp(KeySeq::PageUpKey));
+    input.push(sp(KeySeq::PageDownKey));
+    input.extend(keys("\rpython\r"));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lang(), Language::Python);
+}
+
+#[test]
+fn test_kill_ring() {
+    let mut input = keys("foo bar");
+    input.push(ctrl('w'));
+    input.push(ctrl('w')); // Successive kills are concatenated
+    input.extend(keys("baz"));
+    input.push(ctrl('a'));
+    input.push(ctrl('k'));
+    input.push(ctrl('y')); // Yank 'baz'
+    input.push(alt('y')); // Replace it with 'foo bar'
+    input.extend(keys(" "));
+    input.push(ctrl('y'));
+    input.push(key('!'));
+    input.push(alt('y'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["foo bar baz!"]);
+    assert_eq!(
+        editor.screen().message_text(),
+        "Previous command was not a yank"
+    );
+}
+
+#[test]
+fn test_theme_config() {
+    let config = Config::parse("[theme]\nname = \"nord\"\nkeyword = \"#11223f\"\n").unwrap();
+    assert_eq!(config.theme.name, "nord");
+    assert_eq!(config.theme.keyword, Rgb(0x11, 0x22, 0x3f));
+    assert_eq!(config.theme.string, Rgb(0xa3, 0xbe, 0x8c));
+
+    for invalid in &[
+        "[theme]\nname = \"unknown\"",
+        "[theme]\nkeyword = \"#12345\"",
+        "[theme]\nunknown = \"#123456\"",
+    ] {
+        assert!(Config::parse(invalid).is_err(), "{}", invalid);
+    }
+
+    // Fallback to 256 colors
+    assert_eq!(Rgb(0x00, 0x00, 0x00).to_256(), 16);
+    assert_eq!(Rgb(0xff, 0xff, 0xff).to_256(), 231);
+    assert_eq!(Rgb(0x80, 0x80, 0x80).to_256(), 244);
+    assert_eq!(Rgb(0xd7, 0x87, 0x00).to_256(), 172);
+}
+
+#[test]
+fn test_status_segments() {
+    let config =
+        Config::parse("[status]\nleft = [\"modified\", \"filename\"]\nright = [\"percentage\"]\n")
+            .unwrap();
+    let mut bar = StatusBar {
+        layout: config.status,
+        ..Default::default()
+    };
+    bar.set_filename("foo.rs");
+    bar.set_line_pos((4, 10));
+    assert_eq!(bar.left(), "foo.rs");
+    assert_eq!(bar.right(), "50%");
+    bar.set_modified(true);
+    assert_eq!(bar.left(), "(modified) foo.rs");
+
+    bar.set_layout(StatusLayout::default());
+    bar.set_col(2);
+    bar.set_encoding("utf-8");
+    bar.set_line_ending("CRLF");
+    bar.set_lang(Language::Rust);
+    bar.set_indent((true, 4));
+    assert_eq!(bar.right(), "rust spaces:4 utf-8 CRLF 5:3 50%");
+
+    for invalid in &[
+        "[status]\nleft = [\"unknown\"]",
+        "[status]\nleft = \"filename\"",
+        "[status]\ncenter = []",
+    ] {
+        assert!(Config::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_auto_indent() {
+    let path = env::temp_dir().join(format!("kiro-test-indent-{}.rs", process::id()));
+    fs::write(&path, "").unwrap();
+    let mut input = keys("fn f() {\rif x {  }");
+    input.extend(vec![ctrl('b'); 3]);
+    input.extend(keys("\ra();\r"));
+    input.extend(vec![ctrl('h'); 4]);
+    input.push(ctrl('e'));
+    input.push(key('\r'));
+    input.extend(vec![ctrl('h'); 4]);
+    input.push(key('}'));
+    input.push(ctrl('s'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    // Spaces after the cursor are removed on splitting a line
+    let expected = "fn f() {\n    if x {\n        a();\n    }\n}\n";
+    assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_expand_tab() {
+    let path = env::temp_dir().join(format!("kiro-test-expand-tab-{}", process::id()));
+    fs::write(&path, "").unwrap();
+    let mut input = keys("a");
+    input.push(ctrl('i'));
+    input.extend(keys("|\r"));
+    input.push(alt('c'));
+    input.extend(keys("toggle-expand-tab\r"));
+    input.extend(keys("ab"));
+    input.push(ctrl('i'));
+    input.extend(keys("|\r"));
+    input.push(alt('c'));
+    input.extend(keys("set-tab-width\r4\r"));
+    input.extend(keys("abc"));
+    input.push(ctrl('i'));
+    input.push(key('|'));
+    input.push(ctrl('s'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let expected = "a\t|\nab      |\nabc |\n";
+    assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+    fs::remove_file(&path).unwrap();
+
+    let row = Row::new("a\tb", 4);
+    let rendered: String = row.render_chars().map(|(_, c, _)| c).collect();
+    assert_eq!(rendered, "a   b");
+    assert_eq!(row.rx_from_cx(2), 4);
+    assert_eq!(row.cx_from_rx(2), 1);
+}
+
+#[test]
+fn test_control_characters() {
+    // Control characters are never written to the terminal as-is
+    let row = Row::new("a\x01\x1b[1m\x7f\u{85}b", 8);
+    let rendered: String = row.render_chars().map(|(_, c, _)| c).collect();
+    assert_eq!(rendered, "a^A^[[1m^?<85>b");
+    let sources: Vec<_> = row.render_chars().map(|(x, _, _)| x).collect();
+    assert_eq!(sources, vec![0, 1, 1, 2, 2, 3, 4, 5, 6, 6, 7, 7, 7, 7, 8]);
+    // Cursor moves over a notation as one character
+    assert_eq!(row.rx_from_cx(2), 3);
+    assert_eq!(row.rx_from_cx(8), 14);
+    assert_eq!(row.cx_from_rx(12), 7);
+
+    let path = env::temp_dir().join(format!("kiro-test-control-{}", process::id()));
+    fs::write(&path, "a\x01b\x07c\x7f\n").unwrap();
+    let mut output = vec![];
+    let input = DummyInputs(vec![]);
+    let mut editor = Editor::open(
+        input,
+        &mut output,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    drop(editor);
+    let output = String::from_utf8(output).unwrap();
+    assert!(!output.contains(['\x01', '\x07', '\x7f']), "{:?}", output);
+    assert!(
+        output.contains("^A") && output.contains("^G"),
+        "{:?}",
+        output
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_grapheme_clusters() {
+    // Accent, ZWJ sequence, flag and emoji presentation are single units
+    let row = Row::new(
+        "ae\u{301}b\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f466}\u{1f1ef}\u{1f1f5}\u{263a}\u{fe0f}c",
+        8,
+    );
+    let widths: Vec<_> = row.render_chars().map(|(_, _, w)| w).collect();
+    assert_eq!(widths, vec![1, 1, 0, 1, 2, 0, 0, 0, 0, 2, 0, 2, 0, 1]);
+    assert_eq!(row.rx_from_cx(11), 7);
+    assert_eq!(row.cx_from_rx(4), 4);
+    assert_eq!(row.cx_from_rx(5), 9);
+    assert_eq!(row.next_boundary(1), 3);
+    assert_eq!(row.next_boundary(4), 9);
+    assert_eq!(row.next_boundary(9), 11);
+    assert_eq!(row.prev_boundary(9), 4);
+    assert_eq!(row.prev_boundary(3), 1);
+
+    let path = env::temp_dir().join(format!("kiro-test-grapheme-{}", process::id()));
+    fs::write(&path, "ae\u{301}b\n\u{1f468}\u{200d}\u{1f469}x\n").unwrap();
+    let mut input = vec![sp(KeySeq::RightKey); 2];
+    input.push(ctrl('h')); // Delete 'e' with the accent
+    input.push(ctrl('n'));
+    input.push(ctrl('a'));
+    input.push(ctrl('d')); // Delete the whole family
+    input.push(ctrl('s'));
+    input.push(ctrl('q'));
+    let mut output = vec![];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        &mut output,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    drop(editor);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "ab\nx\n");
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.contains("\u{1f468}\u{200d}\u{1f469}x"),
+        "{:?}",
+        output
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_ambiguous_width() {
+    // The terminal reports the cursor after the probe at column 2, so '○' is narrow
+    let path = env::temp_dir().join(format!("kiro-test-ambiguous-{}", process::id()));
+    fs::write(&path, "\u{25cb}\u{25cb}x\n").unwrap();
+    let input = vec![sp(KeySeq::Cursor(1, 2)), ctrl('e')];
+    let mut output = vec![];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        &mut output,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    drop(editor);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("\x1b[H\u{25cb}\x1b[6n"), "{:?}", output);
+    assert!(output.contains("\x1b[1;4H"), "{:?}", output);
+
+    // Explicit width does not wait for the report
+    let input = vec![sp(KeySeq::Cursor(1, 2)), ctrl('e')];
+    let mut output = vec![];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        &mut output,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    let config = Config::parse("[editor]\nambiguous_width = \"wide\"").unwrap();
+    editor.set_options(config.editor);
+    editor.edit().unwrap();
+    drop(editor);
+    let output = String::from_utf8(output).unwrap();
+    assert!(!output.contains("\x1b[6n"), "{:?}", output);
+    assert!(output.contains("\x1b[1;6H"), "{:?}", output);
+    fs::remove_file(&path).unwrap();
+}
+
+// Reader returning chunks one by one. An empty chunk is a read timeout
+struct Chunks(Vec<&'static [u8]>);
+
+impl io::Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        let chunk = self.0.remove(0);
+        let len = cmp::min(chunk.len(), buf.len());
+        buf[..len].copy_from_slice(&chunk[..len]);
+        if len < chunk.len() {
+            self.0.insert(0, &chunk[len..]);
+        }
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_ime_input() {
+    // Character committed by input method is not dropped even if its bytes are split by timeouts
+    let reader = Chunks(vec![b"\xe6", b"", b"", b"\x97\xa5\xe6\x9c", b"", b"\xac"]);
+    let keys: Vec<_> = InputSequences::new(reader)
+        .take(2)
+        .map(|s| s.unwrap().key)
+        .collect();
+    assert_eq!(keys, vec![KeySeq::Utf8Key('日'), KeySeq::Utf8Key('本')]);
+
+    // Cursor stays at the insertion point of a prompt longer than the screen
+    let mut input = vec![ctrl('s')];
+    input.extend(vec![sp(KeySeq::Utf8Key('日')); 40]);
+    input.push(ctrl('g'));
+    let mut output = vec![];
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        &mut output,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    drop(editor);
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("\x1b[24;79H"), "{:?}", output);
+}
+
+#[test]
+fn test_recover_after_panic() {
+    let path = env::temp_dir().join(format!("kiro-test-recover-{}", process::id()));
+    fs::write(&path, "abc\n").unwrap();
+    let input = DummyInputs(keys("x"));
+    let mut editor =
+        Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+    editor.edit().unwrap();
+    let messages = editor.recover();
+    let mut recovered = path.clone().into_os_string();
+    recovered.push(".recovered");
+    assert_eq!(fs::read_to_string(&recovered).unwrap(), "xabc\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "abc\n");
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].ends_with(".recovered"), "{:?}", messages);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&recovered).unwrap();
+}
+
+#[test]
+fn test_row_single_char_edits() {
+    // Indices patched by each edit must be the same as indices built from the whole line
+    let mut row = Row::new("ab", 8);
+    let edits: &[(bool, usize, char)] = &[
+        (true, 1, 'x'),
+        (true, 0, '良'),
+        (true, 3, '🦀'),
+        (true, 10, 'い'),
+        (false, 1, ' '),
+        (false, 0, ' '),
+        (false, 1, ' '),
+        (false, 2, ' '),
+        (true, 2, 'a'),
+    ];
+    for &(insert, at, c) in edits {
+        if insert {
+            row.insert_char(at, c);
+        } else {
+            row.delete_char(at);
+        }
+        let built = Row::new(row.buffer(), 8);
+        assert_eq!(row.len(), built.len(), "{:?}", row.buffer());
+        for x in 0..=row.len() {
+            assert_eq!(&row[..x], &built[..x], "{:?}", row.buffer());
+        }
+    }
+    assert_eq!(row.buffer(), "xba");
+}
+
+#[test]
+fn test_row_out_of_range() {
+    let mut row = Row::new("a良", 8);
+    assert_eq!(row.char_at_checked(1), Some('良'));
+    assert_eq!(row.char_at_checked(2), None);
+    assert_eq!(row.char_at_checked(5), None);
+    row.remove(1, 10);
+    assert_eq!(row.buffer(), "a");
+    row.remove(3, 10);
+    assert_eq!(row.buffer(), "a");
+}
+
+#[test]
+fn test_detect_indent() {
+    let cases = [
+        ("fn f() {\n  if x {\n    y();\n  }\n}\n", "  "), // Default of Rust is 4 spaces
+        ("fn f() {\n\tif x {\n\t\ty();\n\t}\n}\n", "\t"),
+        ("/*\n * comment\n */\n", "    "), // Alignment by one space is ignored
+    ];
+    let path = env::temp_dir().join(format!("kiro-test-detect-indent-{}.rs", process::id()));
+    for (content, indent) in &cases {
+        fs::write(&path, content).unwrap();
+        let input = vec![ctrl('i'), ctrl('s'), ctrl('q')];
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(saved, format!("{}{}", indent, content), "{:?}", content);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_trailing_spaces() {
+    let mut buf = TextBuffer::new();
+    buf.insert_text("fn f() {  \n\tx; \t\n}");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    hl.update(buf.rows(), buf.rows().len());
+    let trailing = |line: &Vec<Highlight>| {
+        line.iter()
+            .filter(|h| **h == Highlight::TrailingSpace)
+            .count()
+    };
+    let counts: Vec<_> = hl.lines.iter().map(trailing).collect();
+    assert_eq!(counts, vec![2, 2, 0]); // Space and tab after 'x;'
+
+    let mut hl = Highlighting::new(Language::Markdown, buf.rows());
+    hl.update(buf.rows(), buf.rows().len());
+    assert!(hl.lines.iter().all(|l| trailing(l) == 0));
+
+    buf.strip_trailing_spaces();
+    let lines: Vec<_> = buf.lines().collect();
+    assert_eq!(lines, vec!["fn f() {", "\tx;", "}"]);
+    assert_eq!((buf.cx(), buf.cy()), (1, 2));
+}
+
+#[test]
+fn test_file_encodings() {
+    let cases: Vec<(&str, Vec<u8>)> = vec![
+        ("utf-8-bom", b"\xef\xbb\xbfa\xc3\xa9\n".to_vec()),
+        ("utf-16le", b"\xff\xfea\x00\xe9\x00\n\x00".to_vec()),
+        ("utf-16be", b"\xfe\xff\x00a\x00\xe9\x00\n".to_vec()),
+        ("latin1", b"a\xe9\n".to_vec()),
+    ];
+    let path = env::temp_dir().join(format!("kiro-test-encoding-{}", process::id()));
+    for (name, bytes) in cases {
+        fs::write(&path, &bytes).unwrap();
+        let input = vec![ctrl('e'), key('x'), ctrl('s'), ctrl('q')];
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        assert_eq!(
+            editor.lines().collect::<Vec<_>>(),
+            vec!["a\u{e9}x"],
+            "{}",
+            name
+        );
+
+        // Saved in the same encoding
+        let encoding = Encoding::from_name(name).unwrap();
+        let mut expected = encoding.bom().to_vec();
+        expected.extend(encoding.encode("a\u{e9}x\n").unwrap());
+        assert_eq!(fs::read(&path).unwrap(), expected, "{}", name);
+    }
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(Encoding::Latin1.encode("\u{3042}"), Err('\u{3042}'));
+}
+
+#[test]
+fn test_shift_jis() {
+    let sjis = Encoding::ShiftJis;
+    // "日本語ｶﾅ\n" with half-width katakana
+    let bytes = b"\x93\xfa\x96\x7b\x8c\xea\xb6\xc5\\\n";
+    assert_eq!(Encoding::detect(bytes), sjis);
+    assert_eq!(sjis.decode(bytes), "日本語ｶﾅ\\\n");
+    assert_eq!(sjis.encode("日本語ｶﾅ\\\n").unwrap(), bytes.to_vec());
+
+    // Latin-1 and ASCII are not taken as Shift_JIS though accented letters are valid in it
+    for text in &[
+        "caf\u{e9}\n",
+        "K\u{e4}se\n",
+        "\u{a9} 2020\n",
+        "\u{e9}l\u{e8}ve, fa\u{e7}ade\n",
+        "\u{c4}rger \u{fc}ber \u{d6}l\n",
+        "\u{c7}a co\u{fb}te 5 \u{a4}\n",
+    ] {
+        let bytes = Encoding::Latin1.encode(text).unwrap();
+        assert_eq!(Encoding::detect(&bytes), Encoding::Latin1, "{:?}", text);
+    }
+    let text = "// これは日本語です\n";
+    assert_eq!(Encoding::detect(&sjis.encode(text).unwrap()), sjis);
+    assert_eq!(Encoding::detect(b"abc"), Encoding::Utf8);
+    assert_eq!(sjis.decode(b"\x82\n\x82"), "\u{fffd}\n\u{fffd}");
+    assert_eq!(sjis.encode("a\u{e9}"), Err('\u{e9}'));
+
+    // All double byte characters round-trip
+    let mut all = Vec::new();
+    for lead in (0x81..=0x9f).chain(0xe0..=0xef) {
+        for trail in (0x40..=0x7e).chain(0x80..=0xfc) {
+            all.extend_from_slice(&[lead, trail]);
+        }
+    }
+    let text: String = sjis
+        .decode(&all)
+        .chars()
+        .filter(|c| *c != '\u{fffd}')
+        .collect();
+    assert!(text.chars().count() > 7000);
+    assert_eq!(sjis.decode(&sjis.encode(&text).unwrap()), text);
+
+    // Opened and saved as Shift_JIS
+    let path = env::temp_dir().join(format!("kiro-test-sjis-{}", process::id()));
+    fs::write(&path, bytes).unwrap();
+    let mut input = vec![ctrl('e')];
+    input.extend(keys("ab"));
+    input.push(ctrl('s'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["日本語ｶﾅ\\ab"]);
+    let mut expected = bytes[..bytes.len() - 1].to_vec();
+    expected.extend_from_slice(b"ab\n");
+    assert_eq!(fs::read(&path).unwrap(), expected);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_hex_view() {
+    let path = env::temp_dir().join(format!("kiro-test-hexview-{}", process::id()));
+    let bytes: Vec<u8> = (0..20).collect();
+    fs::write(&path, &bytes).unwrap();
+    let mut input = keys("abc");
+    input.push(ctrl('k'));
+    input.push(alt('c'));
+    input.extend(keys("go-to-offset\r0x11\r"));
+    input.push(ctrl('s'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "00000000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|",
+            "00000010  10 11 12 13                                       |....|",
+        ]
+    );
+    assert_eq!(
+        editor.screen().message_text(),
+        "Could not save: buffer is read-only"
+    );
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(hexview::position_of(0x11), (13, 1));
+    assert_eq!(hexview::offset_at(13, 1), 0x11);
+    assert_eq!(hexview::offset_at(36, 0), 8); // Right half
+    assert_eq!(hexview::offset_at(62, 0), 1); // ASCII column
+}
+
+#[test]
+fn test_atomic_save_with_backup() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("kiro-test-save-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("script.sh");
+    fs::write(&path, "abc\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let input = vec![key('x'), ctrl('s'), ctrl('q')];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.set_options(Config::parse("[editor]\nbackup = true").unwrap().editor);
+    editor.edit().unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "xabc\n");
+    assert_eq!(fs::read_to_string(dir.join("script.sh~")).unwrap(), "abc\n");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+    // Temporary file was renamed
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(Config::parse("[editor]\nbackup = 1").is_err());
+}
+
+#[test]
+fn test_error_message_with_context() {
+    let dir = env::temp_dir().join(format!("kiro-test-error-{}", process::id()));
+    let path = dir.join("missing").join("file.txt");
+    let mut input = keys("a");
+    input.push(ctrl('s'));
+    input.extend(keys("b"));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    // The editor keeps running after the error
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["ab"]);
+    assert_eq!(
+        editor.screen().message_text(),
+        format!("No such file or directory saving {}", path.display()),
+    );
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_swap_file_recovery() {
+    let dir = env::temp_dir().join(format!("kiro-test-swap-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("test.txt");
+    let swap = dir.join(".test.txt.kiro-swap");
+
+    for (choice, expected) in [('r', "xabc\n"), ('d', "abc\n")] {
+        fs::write(&path, "abc\n").unwrap();
+
+        // Swap file left by crashed editor
+        let mut buf = TextBuffer::open(&path).unwrap();
+        buf.insert_char('x');
+        buf.write_swap().unwrap();
+        assert_eq!(fs::read_to_string(&swap).unwrap(), "xabc\n");
+        drop(buf);
+
+        let input = vec![key(choice), ctrl('s'), ctrl('q')];
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected, "{}", choice);
+        assert!(!swap.exists(), "{}", choice);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(Config::parse("[editor]\nswap_interval = -1").is_err());
+}
+
+#[test]
+fn test_prompt_line_editing() {
+    use crate::prompt::PromptInput;
+
+    let mut input = PromptInput::default();
+    let edit = |input: &mut PromptInput, seqs: Vec<InputSeq>| {
+        for seq in seqs {
+            input.edit(&seq);
+        }
+        (input.text().to_string(), input.cursor())
+    };
+    let mut seqs = keys("foo b");
+    seqs.push(sp(KeySeq::Utf8Key('ä')));
+    seqs.push(key('r'));
+    seqs.push(alt('b'));
+    seqs.extend(keys("x "));
+    assert_eq!(edit(&mut input, seqs), ("foo x bär".to_string(), 6));
+    let seqs = vec![ctrl('w'), ctrl('w'), sp(KeySeq::RightKey), ctrl('k')];
+    assert_eq!(edit(&mut input, seqs), ("b".to_string(), 1));
+    input.set("a-b c");
+    let seqs = vec![ctrl('a'), alt('f'), alt('d'), sp(KeySeq::DeleteKey)];
+    assert_eq!(edit(&mut input, seqs), ("ac".to_string(), 1));
+    assert_eq!(input.edit(&sp(KeySeq::LeftKey)), Some(false));
+    assert_eq!(input.edit(&ctrl('u')), Some(false));
+    assert_eq!(input.edit(&ctrl('d')), Some(true));
+    assert_eq!(input.edit(&ctrl('x')), None);
+
+    // Cursor keys in the search prompt edit the query instead of moving to next match
+    let mut input = keys("abc baz");
+    input.push(ctrl('m'));
+    input.extend(keys("bar"));
+    input.push(alt('<'));
+    input.push(ctrl('g'));
+    input.extend(keys("bz"));
+    input.push(sp(KeySeq::LeftKey));
+    input.push(key('a'));
+    input.push(ctrl('m'));
+    input.push(key('X'));
+    input.push(ctrl('g'));
+    input.extend(keys("xx bar"));
+    input.push(sp(KeySeq::HomeKey));
+    input.push(alt('d'));
+    input.push(ctrl('d'));
+    input.push(ctrl('m'));
+    input.push(key('Y'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["abc Xbaz", "Ybar"]);
+}
+
+#[test]
+fn test_open_path_completion() {
+    use crate::prompt;
+
+    assert_eq!(prompt::completion("a", &["abc", "abd", "b"]), "ab");
+    assert_eq!(prompt::completion("ab", &["abc"]), "abc");
+    assert_eq!(prompt::completion("x", &["abc"]), "x");
+    assert_eq!(prompt::completion("", &["あい", "あう"]), "あ");
+
+    let dir = env::temp_dir().join(format!("kiro-test-complete-{}", process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("hello.txt"), "hello\n").unwrap();
+    fs::write(dir.join("subway.txt"), "").unwrap();
+    fs::write(dir.join(".hidden"), "").unwrap();
+
+    let base = format!("{}/", dir.display());
+    assert_eq!(
+        prompt::path_candidates(&base),
+        vec![format!("{}sub/", base), format!("{}subway.txt", base)],
+    );
+    assert_eq!(prompt::path_candidates(&format!("{}.", base)).len(), 1);
+
+    // "s" is completed to "sub" and then to "sub/hello.txt" by giving "/"
+    let mut input = vec![ctrl('o')];
+    input.extend(base.chars().map(key));
+    input.extend(vec![key('s'), ctrl('i'), key('/'), ctrl('i'), key('\r')]);
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["hello"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_directory_listing() {
+    let dir = env::temp_dir().join(format!("kiro-test-dired-{}", process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("inner.txt"), "inner\n").unwrap();
+    fs::write(dir.join("a.txt"), "a\n").unwrap();
+
+    let buf = TextBuffer::open(&dir).unwrap();
+    assert!(buf.readonly());
+    assert_eq!(
+        buf.lines().collect::<Vec<_>>(),
+        vec!["../", "sub/", "a.txt"]
+    );
+
+    let down = || InputSeq::new(KeySeq::DownKey);
+    for (input, expected) in [
+        // Descend into sub/ and open inner.txt
+        (vec![down(), key('\r'), down(), key('\r')], vec!["inner"]),
+        // Go back to parent and the cursor is on sub/
+        (
+            vec![down(), key('\r'), key('\r'), key('\r')],
+            vec!["../", "inner.txt"],
+        ),
+        // Editing listing is not allowed
+        (vec![key('x'), ctrl('d')], vec!["../", "sub/", "a.txt"]),
+    ] {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&dir],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        assert_eq!(editor.lines().collect::<Vec<_>>(), expected);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_fuzzy_file_finder() {
+    use crate::finder;
+
+    let dir = env::temp_dir().join(format!("kiro-test-finder-{}", process::id()));
+    for d in ["src/sub", "target/debug", "docs/api", ".git"] {
+        fs::create_dir_all(dir.join(d)).unwrap();
+    }
+    let gitignore = "# comment\n/target\n*.o\n!keep.o\ndocs/**/*.html\n";
+    fs::write(dir.join(".gitignore"), gitignore).unwrap();
+    fs::write(dir.join("src/sub/.gitignore"), "gen_*\n").unwrap();
+    for f in [
+        "README.md",
+        "src/main.rs",
+        "src/main.o",
+        "src/keep.o",
+        "src/sub/gen_foo.rs",
+        "src/sub/mod.rs",
+        "target/debug/main",
+        "docs/api/index.html",
+        "docs/index.html",
+        ".git/HEAD",
+    ] {
+        fs::write(dir.join(f), "").unwrap();
+    }
+
+    let files = finder::walk(&dir);
+    let expected = vec![
+        ".gitignore",
+        "README.md",
+        "src/keep.o",
+        "src/main.rs",
+        "src/sub/.gitignore",
+        "src/sub/mod.rs",
+    ];
+    assert_eq!(files, expected);
+    let mut picker = Picker::new(files);
+    picker.filter("smr");
+    let expected = vec!["src/main.rs".to_string(), "src/sub/mod.rs".to_string()];
+    assert_eq!(picker.page(10), (expected, 0));
+    picker.filter("");
+    assert_eq!(picker.page(2).0.len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Tests run at root of this repository
+    let input = vec![
+        ctrl('t'),
+        key('f'),
+        key('i'),
+        key('n'),
+        key('d'),
+        key('e'),
+        key('r'),
+        key('\r'),
+    ];
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    let first = editor.lines().next().unwrap();
+    assert!(first.starts_with("// Fuzzy file finder"), "{:?}", first);
+}
+
+#[test]
+fn test_go_to_line() {
+    use crate::jump;
+
+    assert_eq!(jump::parse_line("120", 0, 200), Some(119));
+    assert_eq!(jump::parse_line("0", 10, 200), Some(0));
+    assert_eq!(jump::parse_line("+20", 10, 200), Some(30));
+    assert_eq!(jump::parse_line("-5", 10, 200), Some(5));
+    assert_eq!(jump::parse_line("-50", 10, 200), Some(0));
+    assert_eq!(jump::parse_line("50%", 10, 200), Some(99));
+    assert_eq!(jump::parse_line("100%", 10, 200), Some(199));
+    assert_eq!(jump::parse_line("1000", 10, 200), Some(199));
+    assert_eq!(jump::parse_line("10", 0, 0), Some(0));
+    assert_eq!(jump::parse_line("abc", 0, 200), None);
+    assert_eq!(jump::parse_line("+", 0, 200), None);
+
+    // Jumped line is at center of screen. Text area is 22 lines in 80x24 window
+    let this_file = file!();
+    let mut input = vec![alt('g')];
+    input.extend("500\r".chars().map(key));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[this_file],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.screen().rowoff, 499 - 11);
+}
+
+#[test]
+fn test_jump_list() {
+    let mut input = vec![alt('g')];
+    input.extend("100\r".chars().map(key));
+    input.push(alt('g'));
+    input.extend("300\r".chars().map(key));
+    // Back to line 100, line 1 and then forward to line 100
+    input.extend(vec![
+        alt(','),
+        alt(','),
+        alt('.'),
+        key('x'),
+        alt('.'),
+        key('y'),
+    ]);
+    input.extend(vec![alt('>'), alt(','), key('z')]);
+
+    let this_file = file!();
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[this_file],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    let lines: Vec<_> = editor.lines().collect();
+    assert!(lines[99].starts_with('x'), "{:?}", lines[99]);
+    assert!(lines[299].starts_with("yz"), "{:?}", lines[299]);
+}
+
+#[test]
+fn test_bookmarks() {
+    let dir = env::temp_dir().join(format!("kiro-test-marks-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("test.txt");
+    fs::write(&path, "1\n2\n3\n4\n5\n").unwrap();
+    let state = dir.join("state");
+
+    let down = || InputSeq::new(KeySeq::DownKey);
+    // Mark moves to the next line when a line is inserted above it
+    let input = vec![
+        down(),
+        down(),
+        alt('m'),
+        key('a'),
+        alt('<'),
+        key('\r'),
+        alt('j'),
+        key('a'),
+        key('x'),
+    ];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.set_state_dir(Some(state.clone()));
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        vec!["", "1", "2", "x3", "4", "5"]
+    );
+
+    let saved = fs::read_to_string(state.join("marks")).unwrap();
+    let key_path = fs::canonicalize(&path).unwrap();
+    assert_eq!(saved, format!("a\t0\t3\t{}\n", key_path.display()));
+
+    // Marks are restored in next session
+    let input = vec![alt('j'), key('a'), key('y'), alt('j'), key('b')];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.set_state_dir(Some(state));
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().nth(3), Some("y4"));
+    assert_eq!(editor.screen().message_text(), "Mark 'b' is not set");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bracket_matching() {
+    use crate::highlight::BracketPair;
+
+    let mut buf = TextBuffer::new();
+    buf.insert_text("fn f(a: &str) {\n    let s = \"(}\";\n    g(a[0]);\n}\n(");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    hl.update(buf.rows(), buf.rows().len());
+    let rows = buf.rows();
+    assert_eq!(
+        hl.find_bracket_pair(rows, 4, 0),
+        Some(BracketPair::Matched(12, 0))
+    );
+    // Brackets in the string literal are ignored
+    assert_eq!(
+        hl.find_bracket_pair(rows, 14, 0),
+        Some(BracketPair::Matched(0, 3))
+    );
+    assert_eq!(
+        hl.find_bracket_pair(rows, 0, 3),
+        Some(BracketPair::Matched(14, 0))
+    );
+    assert_eq!(
+        hl.find_bracket_pair(rows, 9, 2),
+        Some(BracketPair::Matched(7, 2))
+    );
+    assert_eq!(
+        hl.find_bracket_pair(rows, 0, 4),
+        Some(BracketPair::Unmatched)
+    );
+    assert_eq!(hl.find_bracket_pair(rows, 13, 1), None);
+    assert_eq!(hl.find_bracket_pair(rows, 0, 0), None);
+
+    // Bracket before the cursor is also matched
+    let mut input = keys("a(b[c]d)");
+    input.push(alt(']'));
+    input.push(key('x'));
+    input.push(alt(']'));
+    input.push(key('y'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ax(b[c]dy)"]);
+}
+
+#[test]
+fn test_toggle_comment() {
+    let mut buf = TextBuffer::new();
+    buf.insert_text("fn f() {\n    let a = 1;\n\n  // let b = 2;\n}");
+    buf.set_lang(Language::Rust);
+
+    // Leaders are aligned to the least indented line. Blank line is left as-is
+    buf.set_cursor(6, 1);
+    buf.set_anchor();
+    buf.set_cursor(1, 3);
+    assert!(buf.toggle_comment());
+    let lines: Vec<_> = buf.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "fn f() {",
+            "  //   let a = 1;",
+            "",
+            "  // // let b = 2;",
+            "}"
+        ]
+    );
+    assert_eq!(buf.selection(), Some(((9, 1), (1, 3))));
+
+    // All lines are commented out so comments are removed
+    assert!(buf.toggle_comment());
+    let lines: Vec<_> = buf.lines().collect();
+    assert_eq!(
+        lines,
+        vec!["fn f() {", "    let a = 1;", "", "  // let b = 2;", "}"]
+    );
+
+    buf.clear_anchor();
+    buf.set_cursor(0, 3);
+    assert!(buf.toggle_comment());
+    assert_eq!(buf.lines().nth(3), Some("  let b = 2;"));
+
+    buf.set_lang(Language::Plain);
+    assert!(!buf.toggle_comment());
+}
+
+#[test]
+fn test_duplicate_and_move_lines() {
+    let alt_up = || {
+        let mut seq = sp(KeySeq::UpKey);
+        seq.alt = true;
+        seq
+    };
+    let mut input = keys("a\rb\rc");
+    input.push(ctrl('p'));
+    input.push(alt('d')); // The cursor moves to the copy of "b"
+    input.push(key('x'));
+    input.push(alt_up());
+    input.push(alt_up());
+    input.push(alt_up()); // Nothing happens at the top of file
+    input.push(key('y'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        vec!["bxy", "a", "b", "c"]
+    );
+
+    // Selected lines are moved together and the selection is kept
+    let mut buf = TextBuffer::new();
+    buf.insert_text("1\n2\n3\n4");
+    buf.set_cursor(0, 0);
+    buf.set_anchor();
+    buf.set_cursor(0, 2);
+    buf.move_lines(CursorDir::Down);
+    buf.move_lines(CursorDir::Down);
+    assert_eq!(buf.lines().collect::<Vec<_>>(), vec!["3", "4", "1", "2"]);
+    assert_eq!(buf.selection(), Some(((0, 2), (0, 4))));
+    buf.duplicate_lines();
+    assert_eq!(
+        buf.lines().collect::<Vec<_>>(),
+        vec!["3", "4", "1", "2", "1", "2"]
+    );
+    assert_eq!(buf.selection(), Some(((0, 4), (0, 6))));
+}
+
+#[test]
+fn test_multiple_cursors() {
+    // Cursors are added at the same offset in the next occurrences of the word. Occurrences in
+    // other words like 'foobar' are skipped
+    let mut input = keys("foo(foo);\rfoobar = foo;");
+    input.push(alt('<'));
+    input.push(ctrl('f'));
+    input.push(alt('a'));
+    input.push(alt('a'));
+    input.push(alt('a')); // Wraps around to the first one already having the cursor
+    input.push(ctrl('e'));
+    input.extend(keys("xy"));
+    input.push(ctrl('h'));
+    // Splitting lines moves cursors after the split
+    input.push(ctrl('a'));
+    input.push(ctrl('m'));
+    input.push(key('z'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        vec!["", "zfoo(foo);x", "", "zfoobar = foo;x"]
+    );
+    assert_eq!(
+        editor.screen().message_text(),
+        "No more occurrence of 'foo'"
+    );
+}
+
+#[test]
+fn test_rectangle_selection() {
+    let mut input = keys("a1b\rc2d\re3");
+    input.push(alt('<'));
+    input.push(ctrl('f'));
+    input.push(alt(' '));
+    input.push(ctrl('n'));
+    input.push(ctrl('n'));
+    input.push(ctrl('f'));
+    input.push(ctrl('w')); // Cut "1", "2" and "3"
+    input.extend(keys("XY")); // Inserted at the same column in all lines
+    input.push(ctrl('h'));
+    input.push(ctrl(' '));
+    input.push(ctrl('e'));
+    input.push(ctrl('v')); // Paste the cut rectangle. Lines are added and padded
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        vec!["aXb", "cXd", "eX1", "  2", "  3"]
+    );
+}
+
+#[test]
+fn test_repeat_edit() {
+    let mut input = keys("ab\rcd");
+    input.push(alt('<'));
+    input.push(alt('z')); // Typed text including new line is inserted again
+    input.push(ctrl('k'));
+    input.push(ctrl('p'));
+    input.push(ctrl('a'));
+    input.push(alt('z')); // Repeat delete-to-end
+    input.push(ctrl('z'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["ab", "cd", "cd"]);
+    assert_eq!(editor.screen().message_text(), "Undo delete-to-end");
+
+    let input = vec![alt('z')];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.screen().message_text(), "No edit to repeat");
+}
+
+#[test]
+fn test_vi_mode() {
+    let esc = || InputSeq::new(KeySeq::Key(0x1b));
+    let mut input = keys("ifoo bar baz");
+    input.push(esc());
+    input.extend(keys("0dw")); // Operator composed with motion
+    input.extend(keys("cwqux"));
+    input.push(esc());
+    input.extend(keys("onext"));
+    input.push(esc());
+    input.extend(keys("ggyyGp")); // Copy the first line and paste it before the last line
+    input.extend(keys("0vld")); // Visual selection includes the character at cursor
+    input.extend(keys("gg2x"));
+    input.extend(keys("jiX"));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.set_options(Config::parse("[editor]\nvi = true").unwrap().editor);
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        vec!["x baz", "Xqux baz", "xt"],
+    );
+    assert_eq!(editor.screen().message_text(), "-- INSERT --");
+}
+
+#[test]
+fn test_resize_event() {
+    let input = vec![sp(KeySeq::Resize(40, 12)), key('a')];
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    // Resize event is not handled as key input
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["a"]);
+    assert_eq!(editor.screen().cols(), 40);
+    assert_eq!(editor.screen().rows(), 10); // Status bar and message bar are not included
+}
+
+#[test]
+fn test_handle_input_without_blocking() {
+    let mut editor = Editor::new(
+        DummyInputs(vec![]),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.start().unwrap();
+    for seq in keys("hi") {
+        assert!(!editor.handle_input(seq).unwrap());
+    }
+    assert!(!editor.handle_input(ctrl('q')).unwrap()); // Buffer is modified
+    assert!(editor.handle_input(ctrl('q')).unwrap());
+    editor.finish();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["hi"]);
+}
+
+#[test]
+fn test_buffer_from_reader() {
+    let input: &[u8] = b"#!/usr/bin/env python3\r\nprint(1)\r\n";
+    let buf = TextBuffer::from_reader(input).unwrap();
+    assert_eq!(
+        buf.lines().collect::<Vec<_>>(),
+        vec!["#!/usr/bin/env python3", "print(1)"],
+    );
+    assert_eq!(buf.lang(), Language::Python);
+    assert!(!buf.has_file()); // File name is asked on saving
+    assert!(buf.modified());
+}
+
+#[test]
+fn test_command_line_args() {
+    use crate::cli::{parse_args, Args, Cli, Jump};
+
+    let parse = |args: &[&str]| parse_args("kiro", args.iter().map(|s| s.to_string()));
+    assert_eq!(parse(&["--version"]), Ok(Cli::Version));
+    assert!(parse(&["+abc"]).is_err());
+    assert_eq!(
+        parse(&["-R", "+", "a.txt", "-"]),
+        Ok(Cli::Edit(Args {
+            files: vec!["a.txt".to_string(), "-".to_string()],
+            jump: Some(Jump::LastLine),
+            readonly: true,
+            view: false,
+            ..Default::default()
+        })),
+    );
+    assert!(parse(&["--remote"]).is_err()); // No file
+    assert!(matches!(
+        parse(&["--remote-wait", "--socket", "/tmp/s", "a.txt:3"]),
+        Ok(Cli::Edit(Args {
+            remote: true,
+            wait: true,
+            socket: Some(_),
+            ..
+        }))
+    ));
+
+    let path = env::temp_dir().join(format!("kiro-test-cli-{}", process::id()));
+    fs::write(&path, "foo\nbaz bar\nbar\n").unwrap();
+
+    let input = DummyInputs(vec![key('X'), ctrl('q'), ctrl('q')]);
+    let mut editor =
+        Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+    editor.jump(&Jump::Pattern("ba+r".to_string()));
+    editor.edit().unwrap();
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["foo", "baz Xbar", "bar"]);
+
+    let input = DummyInputs(vec![key('X'), ctrl('q')]);
+    let mut editor =
+        Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+    editor.set_readonly();
+    editor.jump(&Jump::Line(3));
+    editor.edit().unwrap();
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["foo", "baz bar", "bar"]);
+    assert_eq!(editor.screen().message_text(), "Buffer is read-only");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_json() {
+    let src = r#" {"a": [1, -2.5e1, "x\"\u00e9\ud83d\ude00\n"], "b": null, "c": true, "d": {}} "#;
+    let json = Json::parse(src).unwrap();
+    assert_eq!(
+        json.get("a"),
+        Some(&Json::Array(vec![
+            Json::Number(1.0),
+            Json::Number(-25.0),
+            Json::String("x\"é😀\n".to_string()),
+        ]))
+    );
+    assert_eq!(json.get("c").and_then(Json::as_bool), Some(true));
+    assert_eq!(json.get("e"), None);
+    let written = json.to_string();
+    assert_eq!(
+        written,
+        r#"{"a":[1,-25,"x\"é😀\n"],"b":null,"c":true,"d":{}}"#
+    );
+    assert_eq!(Json::parse(&written), Ok(json));
+    for invalid in &[
+        "",
+        "[1,]",
+        "{\"a\" 1}",
+        "\"abc",
+        "tru",
+        "1 2",
+        "\"\\ud800\"",
+    ] {
+        assert!(Json::parse(invalid).is_err(), "{:?}", invalid);
+    }
+}
+
+// Key input which only times out until the flag is set. Then the input ends and the editor stops
+#[cfg(unix)]
+struct TicksUntil(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+#[cfg(unix)]
+impl Iterator for TicksUntil {
+    type Item = io::Result<InputSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.load(std::sync::atomic::Ordering::SeqCst) {
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        Some(Ok(InputSeq::new(KeySeq::Unidentified)))
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remote() {
+    use crate::remote::{self, Request};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    assert_eq!(
+        remote::split_location("src/a.rs:12"),
+        ("src/a.rs", Some(12))
+    );
+    assert_eq!(remote::split_location("a:b"), ("a:b", None));
+    assert_eq!(remote::split_location(":3"), (":3", None));
+
+    let dir = env::temp_dir();
+    let socket = dir.join(format!("kiro-test-remote-{}.sock", process::id()));
+    let first = dir.join(format!("kiro-test-remote-{}-1.txt", process::id()));
+    let second = dir.join(format!("kiro-test-remote-{}-2.txt", process::id()));
+    fs::write(&first, "a\nb\nc").unwrap();
+    fs::write(&second, "hello").unwrap();
+    assert_eq!(remote::send(&socket, &Request::Text(None)).unwrap(), None);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let mut editor = Editor::new(
+        TicksUntil(done.clone()),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.listen(&socket).unwrap();
+    assert!(Editor::new(
+        Script::new().into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default()
+    )
+    .unwrap()
+    .listen(&socket)
+    .is_err()); // Already listening
+
+    let client = {
+        let (socket, first, second) = (socket.clone(), first.clone(), second.clone());
+        thread::spawn(move || {
+            let send = |req: Request| remote::send(&socket, &req).unwrap().unwrap();
+            let path = first.to_string_lossy().into_owned();
+            let open = Request::Open {
+                files: vec![(path, Some(2))],
+                wait: false,
+            };
+            assert_eq!(send(open), Ok(None));
+            assert_eq!(send(Request::Text(None)), Ok(Some("a\nb\nc".to_string())));
+            let unknown = send(Request::Run("no-such-command".to_string()));
+            assert_eq!(
+                unknown,
+                Err("Unknown command 'no-such-command'".to_string())
+            );
+            assert!(send(Request::Text(Some("no-such-buffer".to_string()))).is_err());
+
+            let mut stream = UnixStream::connect(&socket).unwrap();
+            writeln!(stream, r#"{{"command":"open","files":[]}}"#).unwrap();
+            writeln!(stream, r#"{{"command":"text"}}"#).unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let line = lines.next().unwrap().unwrap();
+            assert!(
+                line.starts_with(r#"{"ok":false,"error":"Invalid request: "#),
+                "{}",
+                line
+            );
+            let line = lines.next().unwrap().unwrap();
+            assert_eq!(line, r#"{"ok":true,"text":"a\nb\nc"}"#);
+
+            // The client waiting for the file returns after it is saved
+            let waiting = {
+                let (socket, second) = (socket.clone(), second.clone());
+                thread::spawn(move || {
+                    let file = second.to_string_lossy().into_owned();
+                    remote::open_files(&socket, &[file], true).unwrap()
+                })
+            };
+            while send(Request::Text(None)) != Ok(Some("hello".to_string())) {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert!(!waiting.is_finished());
+            assert_eq!(send(Request::Run("save".to_string())), Ok(None));
+            assert!(waiting.join().unwrap());
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+    editor.edit().unwrap();
+    client.join().unwrap();
+    drop(editor);
+    assert!(!socket.exists());
+    fs::remove_file(&first).unwrap();
+    fs::remove_file(&second).unwrap();
+}
+
+#[test]
+fn test_view_mode() {
+    use crate::cli::{parse_args, Cli};
+
+    let parsed = parse_args("kiro", vec!["--view".to_string(), "-".to_string()]);
+    assert!(matches!(parsed, Ok(Cli::Edit(args)) if args.view && !args.readonly));
+
+    let path = env::temp_dir().join(format!("kiro-test-view-{}", process::id()));
+    let text: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+    fs::write(&path, text).unwrap();
+    let run = |input: Vec<InputSeq>| {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.set_view_mode();
+        editor.edit().unwrap();
+        editor
+    };
+
+    let editor = run(vec![key(' '), key(' '), key('b')]);
+    assert!(editor.screen().rowoff > 0);
+    let editor = run(vec![key('G')]);
+    let rows = editor.screen().rows();
+    assert_eq!(editor.screen().rowoff, 100 - rows + 1); // Cursor is at the end of buffer
+    let editor = run(vec![key('G'), key('g')]);
+    assert_eq!(editor.screen().rowoff, 0);
+
+    // Search moves the cursor to the match. Edits are rejected
+    let mut input = vec![key('/')];
+    input.extend(keys("line 50"));
+    input.push(ctrl('m'));
+    input.push(ctrl('d'));
+    let editor = run(input);
+    assert!(editor.screen().rowoff > 0);
+    assert_eq!(editor.screen().message_text(), "Buffer is read-only");
+    assert_eq!(editor.lines().nth(50), Some("line 50"));
+
+    // 'q' quits without the following inputs
+    let editor = run(vec![key('q'), key('G')]);
+    assert_eq!(editor.screen().rowoff, 0);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_suspend() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let input = DummyInputs(vec![alt('s'), ctrl('q')]);
+    let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(
+        editor.screen().message_text(),
+        "Suspend is not supported on this terminal"
+    );
+
+    let count = Rc::new(Cell::new(0));
+    let input = DummyInputs(vec![alt('s'), key('a'), alt('s'), ctrl('q'), ctrl('q')]);
+    let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
+    let suspended = count.clone();
+    editor.set_suspender(Box::new(move || {
+        suspended.set(suspended.get() + 1);
+        Ok(())
+    }));
+    editor.edit().unwrap();
+    assert_eq!(count.get(), 2);
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["a"]);
+}
+
+#[test]
+fn test_shell_command() {
+    let mut input = keys("c\rb\ra");
+    input.push(alt('<'));
+    input.push(ctrl(' '));
+    input.push(alt('>'));
+    input.push(alt('|'));
+    input.extend(keys("sort\r"));
+    input.push(alt('!'));
+    input.extend(keys("echo foo\r"));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["a", "b", "c", "foo"]);
+
+    let err = shell::run("echo oops >&2; exit 1", Some("")).unwrap_err();
+    assert_eq!(err.to_string(), "'echo oops >&2; exit 1' failed: oops");
+}
+
+#[test]
+fn test_compile_and_jump_to_error() {
+    let output = "error[E0425]: cannot find value `x`\n  --> src/main.rs:3:5\n\
+                  foo.c:10:2: warning: unused\nFinished at 12:30:00\nC:\\a.go:7\n";
+    let diags: Vec<_> = compile::parse(output)
+        .into_iter()
+        .map(|d| (d.file, d.line, d.col, d.message))
+        .collect();
+    assert_eq!(
+        diags,
+        vec![
+            (
+                "src/main.rs".to_string(),
+                3,
+                5,
+                "error[E0425]: cannot find value `x`".to_string()
+            ),
+            (
+                "foo.c".to_string(),
+                10,
+                2,
+                "foo.c:10:2: warning: unused".to_string()
+            ),
+            ("C:\\a.go".to_string(), 7, 1, "C:\\a.go:7".to_string()),
+        ],
+    );
+
+    let path = env::temp_dir().join(format!("kiro-test-compile-{}.txt", process::id()));
+    fs::write(&path, "abc\ndefgh\n").unwrap();
+    let mut input = vec![alt('k')];
+    input.extend(keys(&format!(
+        "echo {}:2:3: error; exit 1\r",
+        path.display()
+    )));
+    input.push(alt('l'));
+    input.push(key('X'));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["abc", "deXfgh"]);
+}
+
+#[test]
+fn test_quick_open() {
+    for (text, expected) in &[
+        ("  --> src/main.rs:3:5", Some(("src/main.rs", 3, 5))),
+        ("foo.c:10:2: error: oops", Some(("foo.c", 10, 2))),
+        (
+            "Traceback (most recent call last):\n  File \"a/b.py\", line 12, in <module>",
+            Some(("a/b.py", 12, 1)),
+        ),
+        ("at (lib/x.js:7:9)", Some(("lib/x.js", 7, 9))),
+        ("Finished at 12:30:00", None),
+    ] {
+        let found = compile::find_location(text);
+        let found = found.as_ref().map(|(f, l, c)| (f.as_str(), *l, *c));
+        assert_eq!(found, *expected, "{:?}", text);
+    }
+
+    let path = env::temp_dir().join(format!("kiro-test-quick-open-{}.txt", process::id()));
+    fs::write(&path, "abc\ndefgh\n").unwrap();
+    // Location in the current line is opened with empty input
+    let mut input = keys(&format!("  File \"{}\", line 2, in f", path.display()));
+    input.push(alt('c'));
+    input.extend(keys("quick-open\r\r"));
+    input.push(key('X'));
+    input.push(alt('c'));
+    input.extend(keys(&format!(
+        "quick-open\rerror: {}:1:3: bad\r",
+        path.display()
+    )));
+    input.push(key('Y'));
+    input.push(alt('c'));
+    input.extend(keys("quick-open\rno location\r"));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["abYc", "Xdefgh"]);
+    assert_eq!(
+        editor.screen().message_text(),
+        "No location such as 'file:line' in 'no location'"
+    );
+}
+
+#[test]
+fn test_vcs_diff_marks() {
+    use crate::vcs::{Mark, VcsDiff};
+
+    let base = ["a", "b", "c", "d", "e", "f"];
+    let mut diff = VcsDiff::new(base.iter().map(|s| s.to_string()).collect());
+    let lines = ["a", "new", "b", "C", "d", "f"];
+    assert_eq!(diff.update(lines.iter().copied()), Some(1));
+    let marks: Vec<_> = (0..lines.len()).map(|y| diff.mark_at(y)).collect();
+    assert_eq!(
+        marks,
+        vec![
+            None,
+            Some(Mark::Added),
+            None,
+            Some(Mark::Modified),
+            None,
+            Some(Mark::Deleted), // 'e' was deleted before 'f'
+        ],
+    );
+
+    // Marks are cleared when the lines are back to HEAD
+    assert_eq!(diff.update(base.iter().copied()), Some(1));
+    assert!((0..base.len()).all(|y| diff.mark_at(y).is_none()));
+    assert_eq!(diff.update(base.iter().copied()), None);
+
+    // Deleted at end of buffer is marked on the last line
+    assert_eq!(diff.update(base[..4].iter().copied()), Some(3));
+    assert_eq!(diff.mark_at(3), Some(Mark::Deleted));
+}
+
+#[test]
+fn test_blame_and_show_commit() {
+    let dir = env::temp_dir().join(format!("kiro-test-blame-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("file.txt");
+    fs::write(&path, "abc\ndef\n").unwrap();
+    let git = |args: &[&str]| {
+        process::Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "Alice")
+            .env("GIT_AUTHOR_EMAIL", "alice@example.com")
+            .env("GIT_AUTHOR_DATE", "1700000000 +0900")
+            .env("GIT_COMMITTER_NAME", "Alice")
+            .env("GIT_COMMITTER_EMAIL", "alice@example.com")
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    };
+    if !git(&["init", "-q"]) {
+        fs::remove_dir_all(&dir).unwrap();
+        return; // git is not available
+    }
+    assert!(git(&["add", "file.txt"]));
+    assert!(git(&["commit", "-q", "-m", "Add file"]));
+
+    // Lines are blamed with unsaved contents of the buffer
+    let contents = "new\nabc\ndef\n";
+    assert!(!vcs::blame(&path, 1, contents).unwrap().is_committed());
+    let blame = vcs::blame(&path, 3, contents).unwrap();
+    assert!(blame.is_committed());
+    assert_eq!(
+        (
+            blame.author.as_str(),
+            blame.date.as_str(),
+            blame.summary.as_str()
+        ),
+        ("Alice", "2023-11-15", "Add file"),
+    );
+
+    let files = [path.to_str().unwrap()];
+    let mut input = vec![sp(KeySeq::DownKey), alt('i')];
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &files,
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    let msg = editor.screen().message_text().to_string();
+    assert!(msg.ends_with(" Alice 2023-11-15 Add file"), "{:?}", msg);
+
+    let mut input = vec![alt('c')];
+    input.extend(keys("show-commit\r"));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &files,
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert!(lines[0].starts_with("commit "), "{:?}", lines);
+    assert!(lines.contains(&"    Add file"), "{:?}", lines);
+    assert!(lines.contains(&"+abc"), "{:?}", lines);
+}
+
+#[test]
+fn test_completion_popup() {
+    let mut input = keys("hello help\rhe");
+    input.push(sp(KeySeq::DownKey)); // Select 'help'
+    input.push(ctrl('i'));
+    input.extend(keys(" xhe"));
+    input.push(key('\x1b')); // Dismiss
+    input.push(sp(KeySeq::DownKey)); // Move cursor after the popup is closed
+    input.extend(keys("\rhel"));
+    input.push(ctrl('i')); // 'help' in the nearer line comes first
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["hello help", "help xhe", "help"]);
+}
+
+#[test]
+fn test_complete_command() {
+    let dir = env::temp_dir().join(format!("kiro-test-complete-cmd-{}", process::id()));
+    fs::create_dir_all(dir.join("alps")).unwrap();
+    fs::write(dir.join("alpha.txt"), "").unwrap();
+
+    let mut input = keys("help\rhello\rhel");
+    input.push(alt('/')); // 'hello' in the nearer line comes first
+    input.push(alt('/'));
+    input.push(alt('/')); // Back to 'hel'
+    input.push(alt('/'));
+    input.extend(keys(&format!("\r{}/al", dir.display())));
+    input.push(alt('/'));
+    input.push(alt('/'));
+    input.extend(keys("\rxyz"));
+    input.push(alt('/')); // No candidate
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    let lines: Vec<_> = editor.lines().collect();
+    let path = format!("{}/alps/", dir.display());
+    assert_eq!(lines, vec!["help", "hello", "hello", &path, "xyz"]);
+
+    // Ctrl-N completes in insert mode of Vi
+    let mut input = keys("ihello he");
+    input.push(ctrl('n'));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[] as &[&str],
+    )
+    .unwrap();
+    editor.set_options(Config::parse("[editor]\nvi = true").unwrap().editor);
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec!["hello hello"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_abbreviation() {
+    let config = "[editor.abbrev]\nteh = \"the\"\nsopl = 'println!(\"{}\", );'";
+    let mut input = keys("teh ");
+    input.push(ctrl('z')); // Undo only the expansion
+    input.extend(keys("\rsopl\rteh"));
+    input.push(alt('\'')); // Next '.' does not expand the abbreviation
+    input.extend(keys(". teh,"));
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.set_options(Config::parse(config).unwrap().editor);
+    editor.edit().unwrap();
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["teh ", "println!(\"{}\", );", "teh. the,"]);
+
+    for invalid in &[
+        "[editor]\nabbrev = 1",
+        "[editor]\nabbrev = { teh = 1 }",
+        "[editor]\nabbrev = { \"a-b\" = \"x\" }",
+        "[editor]\nabbrev = { ab = \"x\\ny\" }",
+    ] {
+        assert!(Config::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_spell_check() {
+    use crate::spell::Dictionary;
+    use std::rc::Rc;
+
+    let aff = "SFX S Y 2\nSFX S 0 s [^y]\nSFX S y ies [^aeiou]y\nPFX U Y 1\nPFX U 0 un .\n";
+    let dic = "4\nword/S\nthe\nstory/SU\nhello\n";
+    let dict = Dictionary::parse(dic, aff);
+    for word in &["words", "stories", "unstories", "The", "HELLO"] {
+        assert!(dict.check(word), "{}", word);
+    }
+    assert!(!dict.check("storys"));
+    assert_eq!(dict.suggest("teh"), vec!["the"]);
+    assert_eq!(dict.suggest("Wrod"), vec!["Word"]);
+
+    // Only comments and strings are checked in code. Identifiers and paths are not checked
+    let mut buf = TextBuffer::new();
+    buf.insert_text("// teh word_x fooBar src/wrod.rs\nlet wrod = \"storys\";");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    hl.set_dictionary(Some(&Rc::new(dict)));
+    hl.update(buf.rows(), buf.rows().len());
+    let misspelled: Vec<Vec<_>> = hl
+        .lines
+        .iter()
+        .map(|l| {
+            l.iter()
+                .enumerate()
+                .filter(|(_, h)| **h == Highlight::Misspelled)
+                .map(|(x, _)| x)
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        misspelled,
+        vec![vec![3, 4, 5], vec![12, 13, 14, 15, 16, 17]]
+    );
+
+    let path = env::temp_dir().join(format!("kiro-test-spell-{}.dic", process::id()));
+    fs::write(&path, dic).unwrap();
+    fs::write(path.with_extension("aff"), aff).unwrap();
+    let mut input = keys("teh");
+    input.extend(vec![alt('w'), alt('w'), alt('w')]); // Back to the original word and the first
+    input.push(ctrl('q'));
+    input.push(ctrl('q'));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    let config = format!(
+        "[editor]\nspell = true\ndictionary = {:?}",
+        path.display().to_string()
+    );
+    editor.set_options(Config::parse(&config).unwrap().editor);
+    editor.edit().unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(path.with_extension("aff")).unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["the"]);
+}
+
+#[test]
+fn test_format_buffer() {
+    // Only changed lines are replaced and the cursor stays at the same line
+    let mut buf = TextBuffer::new();
+    buf.insert_text("a\nb\nc\nd");
+    buf.set_cursor(1, 2);
+    buf.finish_edit(None);
+    assert!(buf.replace_lines(&["a", "x", "b", "c", "D"]));
+    buf.finish_edit(None);
+    let lines: Vec<_> = buf.lines().collect();
+    assert_eq!(lines, vec!["a", "x", "b", "c", "D"]);
+    assert_eq!((buf.cx(), buf.cy()), (1, 3));
+    assert!(!buf.replace_lines(&["a", "x", "b", "c", "D"]));
+    buf.undo();
+    let lines: Vec<_> = buf.lines().collect();
+    assert_eq!(lines, vec!["a", "b", "c", "d"]); // Formatting is undone at once
+
+    // Buffer is formatted with the command in config on saving
+    let path = env::temp_dir().join(format!("kiro-test-format-{}.txt", process::id()));
+    fs::write(&path, "abc\n").unwrap();
+    let input = DummyInputs(vec![key('d'), ctrl('s'), ctrl('q')]);
+    let mut editor =
+        Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+    let config = "[editor]\nformat = { plain = \"tr a-z A-Z\" }\nformat_on_save = true";
+    editor.set_options(Config::parse(config).unwrap().editor);
+    editor.edit().unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(saved, "DABC\n");
+    assert!(editor.screen().message_text().contains("written"));
+}
+
+#[test]
+fn test_background_task_events() {
+    use crate::event::{Event, Events, TaskResult};
+
+    let mut events = Events::new(DummyInputs(vec![
+        key('a'),
+        InputSeq::new(KeySeq::Unidentified),
+    ]));
+    events.spawn(|| TaskResult::Compile("echo".to_string(), Ok(("done".to_string(), true))));
+    let mut received = vec![];
+    while let Some(event) = events.next_event() {
+        received.push(match event.unwrap() {
+            Event::Input(seq) if seq == key('a') => "input",
+            Event::Tick => "tick",
+            Event::Task(TaskResult::Compile(_, Ok((out, true)))) if out == "done" => "task",
+            _ => "unexpected",
+        });
+    }
+    // The task result is received before or between inputs, or after the input stream ends
+    assert_eq!(received.iter().filter(|e| **e == "task").count(), 1);
+    received.retain(|e| *e != "task");
+    assert_eq!(received, vec!["input", "tick"]);
+    assert!(events.wait_task().is_none());
+}
+
+#[test]
+fn test_diff_rendering() {
+    use crate::grid::Grid;
+
+    // Output of the diff makes a terminal showing the previous frame show the next frame
+    let draw = |grid: &Grid, seq: &str| {
+        let mut next = grid.clone();
+        next.apply(seq.as_bytes());
+        next
+    };
+    let update = |terminal: &Grid, prev: Option<&Grid>, next: &Grid| {
+        let mut out = vec![];
+        next.write_diff(prev, &mut out).unwrap();
+        assert_eq!(
+            &draw(terminal, &String::from_utf8(out.clone()).unwrap()),
+            next
+        );
+        String::from_utf8(out).unwrap()
+    };
+
+    let empty = Grid::new(20, 3);
+    let first = draw(
+        &empty,
+        "\x1b[1Hhello world\x1b[K\x1b[2H\x1b[91mred\x1b[39;0m\x1b[K\x1b[3H\x1b[7mstatus\x1b[39;0m",
+    );
+    // Terminal contents are unknown at first so everything is written
+    let terminal = draw(&Grid::new(20, 3), "\x1b[1Hgarbage");
+    let out = update(&terminal, None, &first);
+    assert!(
+        out.contains("hello world") && out.contains("red"),
+        "{:?}",
+        out
+    );
+
+    // Only the changed cell is written
+    let second = draw(
+        &first,
+        "\x1b[1Hhallo world\x1b[K\x1b[2H\x1b[91mred\x1b[39;0m\x1b[K",
+    );
+    let out = update(&first, Some(&first), &second);
+    assert!(!out.contains("world") && !out.contains("red"), "{:?}", out);
+    assert!(out.contains('a'), "{:?}", out);
+
+    // Double-width characters and erasing the rest of line
+    let third = draw(&second, "\x1b[1Hあいう\x1b[K\x1b[2;2Hx");
+    let out = update(&second, Some(&second), &third);
+    assert!(
+        out.contains("あいう") && out.contains("\x1b[K"),
+        "{:?}",
+        out
+    );
+    let fourth = draw(&third, "\x1b[1;2Hz");
+    update(&third, Some(&third), &fourth);
+
+    assert_eq!(update(&fourth, Some(&fourth), &fourth), "");
+}
+
+#[test]
+fn test_scroll_region() {
+    use crate::grid::Grid;
+
+    let draw = |lines: &[&str]| {
+        let mut grid = Grid::new(10, 4);
+        for (y, line) in lines.iter().enumerate() {
+            grid.apply(format!("\x1b[{}H{}\x1b[K", y + 1, line).as_bytes());
+        }
+        grid
+    };
+
+    // Lines in the region are moved and the line scrolled in is blank. The last line is not moved
+    let mut grid = draw(&["a", "b", "c", "status"]);
+    let mut out = vec![];
+    grid.scroll(3, 1, &mut out).unwrap();
+    assert_eq!(grid, draw(&["b", "c", "", "status"]));
+    let out = String::from_utf8(out).unwrap();
+    assert!(
+        out.contains("\x1b[1;3r") && out.contains("\x1bD"),
+        "{:?}",
+        out
+    );
+
+    let mut out = vec![];
+    grid.scroll(3, -2, &mut out).unwrap();
+    assert_eq!(grid, draw(&["", "", "b", "status"]));
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("\x1bM").count(), 2, "{:?}", out);
+}
+
+#[test]
+fn test_large_file_streaming() {
+    let path = env::temp_dir().join(format!("kiro-test-large-{}", process::id()));
+    let pad = "x".repeat(90);
+    let total = stream::THRESHOLD as usize / 100 + 1000;
+    let text: String = (0..total)
+        .map(|i| format!("{:07} {}\r\n", i, pad))
+        .collect();
+    fs::write(&path, &text).unwrap();
+
+    // Only the head is read on opening and the buffer is read-only while loading
+    let mut buf = TextBuffer::open(&path).unwrap();
+    let head = buf.rows().len();
+    assert!(0 < head && head < total);
+    assert!(buf.readonly());
+    assert_eq!(buf.line_ending(), "CRLF");
+    assert!(matches!(buf.loaded_percent(), Some(p) if p < 10));
+    assert_eq!(
+        buf.rows()[head - 1].buffer(),
+        format!("{:07} {}", head - 1, pad)
+    );
+
+    // Chunk not following the loaded lines is ignored
+    let offset = buf.stream_offset().unwrap();
+    let stale = stream::read_chunk(&path, 0, stream::CHUNK_SIZE, 8).unwrap();
+    buf.append_chunk(stale);
+    assert_eq!(buf.rows().len(), head);
+    while let Some(offset) = buf.stream_offset() {
+        let chunk = stream::read_chunk(&path, offset, stream::CHUNK_SIZE, 8).unwrap();
+        buf.append_chunk(chunk);
+    }
+    assert!(offset > 0);
+    assert_eq!(buf.rows().len(), total);
+    assert!(!buf.readonly());
+
+    // Editor loads the rest in background
+    let mut editor = Editor::open(
+        DummyInputs(vec![]),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().count(), total);
+    assert_eq!(
+        editor.lines().last().unwrap(),
+        format!("{:07} {}", total - 1, pad)
+    );
+    let msg = editor.screen().message_text();
+    assert!(
+        msg.starts_with(&format!("Loaded {} lines", total)),
+        "{:?}",
+        msg
+    );
+
+    // Large file opened with memory map is also loaded lazily
+    let mut buf = TextBuffer::open_mapped(&path).unwrap();
+    assert!(buf.readonly() && buf.rows()[0].is_mapped());
+    while let Some(offset) = buf.stream_offset() {
+        let map = buf.mapping().unwrap().clone();
+        buf.append_chunk(stream::map_chunk(&map, offset, stream::CHUNK_SIZE, 8).unwrap());
+    }
+    assert_eq!(buf.rows().len(), total);
+    assert_eq!(
+        buf.rows()[total - 1].buffer(),
+        format!("{:07} {}", total - 1, pad)
+    );
+
+    // Large file which is not UTF-8 at its head is decoded entirely instead of being streamed
+    let mut bytes = b"caf\xe9\n".to_vec();
+    bytes.extend(text.as_bytes());
+    fs::write(&path, &bytes).unwrap();
+    let buf = TextBuffer::open(&path).unwrap();
+    assert_eq!(buf.stream_offset(), None);
+    assert_eq!(buf.encoding(), Encoding::Latin1);
+    assert_eq!(buf.rows()[0].buffer(), "caf\u{e9}");
+    assert!(!buf.readonly());
+
+    // Invalid UTF-8 after the head keeps the buffer read-only after it is loaded
+    let mut bytes = text.into_bytes();
+    bytes.extend(b"caf\xe9\n");
+    fs::write(&path, &bytes).unwrap();
+    let mut buf = TextBuffer::open(&path).unwrap();
+    while let Some(offset) = buf.stream_offset() {
+        let chunk = stream::read_chunk(&path, offset, stream::CHUNK_SIZE, 8).unwrap();
+        buf.append_chunk(chunk);
+    }
+    assert!(buf.lossy() && buf.readonly());
+    assert!(buf.save(false).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mmap_open() {
+    let dir = env::temp_dir().join(format!("kiro-test-mmap-{}", process::id()));
+    let path = dir.join("file.txt");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(&path, "aaa\r\nbbb\r\ncaf\u{e9}\r\n").unwrap();
+
+    // Rows refer to the map until the buffer is edited. Lines which are not ASCII are copied
+    let mut buf = TextBuffer::open_mapped(&path).unwrap();
+    assert_eq!(
+        buf.lines().collect::<Vec<_>>(),
+        vec!["aaa", "bbb", "caf\u{e9}"]
+    );
+    assert_eq!(buf.line_ending(), "CRLF");
+    let mapped: Vec<_> = buf.rows().iter().map(|r| r.is_mapped()).collect();
+    assert_eq!(mapped, vec![true, true, false]);
+    buf.set_cursor(0, 1);
+    buf.insert_char('X');
+    assert_eq!(buf.rows()[1].buffer(), "Xbbb");
+    assert!(buf.mapping().is_none() && buf.rows().iter().all(|r| !r.is_mapped()));
+
+    // The file is overwritten in place while the editor waits for input
+    let run = |input: Vec<InputSeq>, contents: &'static str, after: Vec<InputSeq>| {
+        fs::write(&path, "aaa\nbbb\nccc\n").unwrap();
+        let mut seqs = vec![ctrl('o')];
+        seqs.extend(keys(path.to_str().unwrap()));
+        seqs.push(ctrl('m'));
+        seqs.extend(input);
+        let mut seqs = seqs.into_iter();
+        let mut after = after.into_iter();
+        let mut changed = false;
+        let input = std::iter::from_fn(|| match seqs.next() {
+            Some(seq) => Some(Ok(seq)),
+            None if !changed => {
+                changed = true;
+                fs::write(&path, contents).unwrap();
+                Some(Ok(sp(KeySeq::Unidentified)))
+            }
+            None => after.next().map(Ok),
+        });
+        let mut editor = Editor::new(input, Discard, WINDOW_SIZE, Clipboard::default()).unwrap();
+        editor.set_options(Config::parse("[editor]\nmmap = true").unwrap().editor);
+        editor.edit().unwrap();
+        let lines: Vec<_> = editor.lines().map(String::from).collect();
+        (lines, editor.screen().message_text().to_string())
+    };
+
+    // Buffer without unsaved changes is loaded again
+    let (lines, msg) = run(vec![], "new\n", vec![]);
+    assert_eq!(lines, vec!["new"]);
+    assert!(msg.ends_with("was changed on disk. Reloaded"), "{:?}", msg);
+
+    // Lines of modified buffer were copied on the first edit. Saving it is refused once
+    let (lines, msg) = run(vec![key('X')], "zzz\n", vec![ctrl('s')]);
+    assert_eq!(lines, vec!["Xaaa", "bbb", "ccc"]);
+    assert!(msg.ends_with("Save again to overwrite it"), "{:?}", msg);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "zzz\n");
+    let (_, msg) = run(vec![key('X')], "zzz\n", vec![ctrl('s'), ctrl('s')]);
+    assert!(msg.contains("bytes written"), "{:?}", msg);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "Xaaa\nbbb\nccc\n");
+
+    // Long lines are copied. A range of mapped line overwritten with non-ASCII bytes reads as
+    // spaces of the same length
+    use std::io::{Seek, SeekFrom};
+    fs::write(&path, format!("{}\nabc\n", "x".repeat(2000))).unwrap();
+    let buf = TextBuffer::open_mapped(&path).unwrap();
+    let mapped: Vec<_> = buf.rows().iter().map(|r| r.is_mapped()).collect();
+    assert_eq!(mapped, vec![false, true]);
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(2001)).unwrap();
+    file.write_all(b"\xe9").unwrap();
+    let row = &buf.rows()[1];
+    assert_eq!((row.len(), row.buffer(), &row[1..]), (3, "   ", "bc"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_mmap_truncated() {
+    let path = env::temp_dir().join(format!("kiro-test-mmap-truncated-{}", process::id()));
+    let text: String = (0..10000).map(|i| format!("line {}\n", i)).collect();
+    fs::write(&path, &text).unwrap();
+    let buf = TextBuffer::open_mapped(&path).unwrap();
+    assert!(buf.rows().iter().all(|r| r.is_mapped()));
+    assert!(!buf.mapping().unwrap().changed());
+
+    // Other process truncates the file. Rendering rows does not kill the editor by SIGBUS and the
+    // map is detected as changed so that the file is loaded again
+    File::create(&path).unwrap();
+    let rendered: usize = buf.rows().iter().map(|r| r.render_chars().count()).sum();
+    assert_eq!(rendered, (text.len() - 10000) * 2); // Zero bytes are rendered as '^@'
+    assert!(buf.mapping().unwrap().changed());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_follow_file() {
+    let path = env::temp_dir().join(format!("kiro-test-follow-{}", process::id()));
+    let tick = || sp(KeySeq::Unidentified);
+    // Each step writes text to the file before the key input. true truncates the file
+    let run = |steps: Vec<(Option<(bool, &str)>, InputSeq)>| {
+        let text: String = (0..30).map(|i| format!("{}\n", i)).collect();
+        fs::write(&path, text).unwrap();
+        let mut input = vec![alt('c')];
+        input.extend(keys("follow"));
+        input.push(ctrl('m'));
+        let mut steps = input
+            .into_iter()
+            .map(|seq| (None, seq))
+            .chain(steps)
+            .collect::<Vec<_>>()
+            .into_iter();
+        let input = std::iter::from_fn(|| {
+            let (write, seq) = steps.next()?;
+            if let Some((truncate, text)) = write {
+                let mut opts = fs::OpenOptions::new();
+                let mut file = if truncate {
+                    opts.write(true).truncate(true).open(&path)
+                } else {
+                    opts.append(true).open(&path)
+                }
+                .unwrap();
+                file.write_all(text.as_bytes()).unwrap();
+            }
+            Some(Ok(seq))
+        });
+        let mut editor =
+            Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+        editor.edit().unwrap();
+        let lines: Vec<_> = editor.lines().map(String::from).collect();
+        (lines, editor.screen().rowoff)
+    };
+    let expected = |n: usize| (0..n).map(|i| i.to_string()).collect::<Vec<_>>();
+
+    // Screen follows appended lines. A line without newline is not read yet
+    let (lines, rowoff) = run(vec![(Some((false, "30\n31\n32\n33\n34\n35")), tick())]);
+    assert_eq!(lines, expected(35));
+    assert_eq!(rowoff, 35 - 22 + 1);
+
+    // Moving the cursor up pauses following
+    let mut steps = vec![(Some((false, "30\n31\n32\n33\n34\n35")), tick())];
+    steps.extend((0..10).map(|_| (None, ctrl('p'))));
+    steps.push((Some((false, "\n36\n37\n38\n")), tick()));
+    let (lines, rowoff) = run(steps.clone());
+    assert_eq!(lines, expected(39));
+    assert_eq!(rowoff, 35 - 22 + 1);
+
+    // Moving back to the bottom resumes it
+    steps.push((None, alt('>')));
+    steps.push((Some((false, "39\n")), tick()));
+    let (lines, rowoff) = run(steps);
+    assert_eq!(lines, expected(40));
+    assert_eq!(rowoff, 40 - 22 + 1);
+
+    // Truncated file is loaded again and is still followed
+    let (lines, _) = run(vec![
+        (Some((true, "rotated\n")), tick()),
+        (Some((false, "new\n")), tick()),
+    ]);
+    assert_eq!(lines, vec!["rotated", "new"]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_auto_revert() {
+    let path = env::temp_dir().join(format!("kiro-test-revert-{}", process::id()));
+    // Keys are typed before the file is changed by other process
+    let run = |config: &str, typed: Vec<InputSeq>| {
+        fs::write(&path, "a\n").unwrap();
+        let mut typed = typed.into_iter();
+        let mut changed = false;
+        let input = std::iter::from_fn(|| {
+            if let Some(seq) = typed.next() {
+                return Some(Ok(seq));
+            }
+            if changed {
+                return None;
+            }
+            changed = true;
+            fs::write(&path, "b\nc\n").unwrap();
+            // Wait until the watcher polls the file
+            std::thread::sleep(Duration::from_millis(600));
+            Some(Ok(sp(KeySeq::Unidentified)))
+        });
+        let mut editor =
+            Editor::open(input, Discard, WINDOW_SIZE, Clipboard::default(), &[&path]).unwrap();
+        editor.set_options(Config::parse(config).unwrap().editor);
+        editor.edit().unwrap();
+        let lines: Vec<_> = editor.lines().map(String::from).collect();
+        let msg = editor.screen().message_text().to_string();
+        (lines, msg)
+    };
+
+    let (lines, msg) = run("[editor]\nauto_revert = true", vec![]);
+    assert_eq!(lines, vec!["b", "c"]);
+    assert!(msg.starts_with("Reverted "), "{:?}", msg);
+
+    let (lines, msg) = run("[editor]\nauto_revert = true", vec![key('x')]);
+    assert_eq!(lines, vec!["xa"]);
+    assert!(msg.ends_with("has unsaved changes"), "{:?}", msg);
+
+    let (lines, msg) = run("[editor]", vec![]);
+    assert_eq!(lines, vec!["a"]);
+    assert!(msg.ends_with("was changed on disk"), "{:?}", msg);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_scrolloff() {
+    let this_file = file!();
+    let run = |scrolloff: usize, input: Vec<InputSeq>| {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[this_file],
+        )
+        .unwrap();
+        let config = format!("[editor]\nscrolloff = {}", scrolloff);
+        editor.set_options(Config::parse(&config).unwrap().editor);
+        editor.edit().unwrap();
+        editor.screen().rowoff
+    };
+    let command = |name: &str| {
+        let mut input = vec![alt('c')];
+        input.extend(keys(name));
+        input.push(ctrl('m'));
+        input
+    };
+    let down = |n: usize| (0..n).map(|_| ctrl('n')).collect::<Vec<_>>();
+
+    // Text area is 22 lines. 3 lines are kept below the cursor at line 20
+    assert_eq!(run(0, down(20)), 0);
+    assert_eq!(run(3, down(20)), 2);
+    // And above the cursor moving up
+    let mut input = down(40);
+    input.extend((0..20).map(|_| ctrl('p')));
+    assert_eq!(run(3, input), 17);
+    // Margin is at most half of the screen
+    assert_eq!(run(100, down(20)), 20 + 10 + 1 - 22);
+
+    for (name, rowoff) in [
+        ("center-line", 9),
+        ("line-to-top", 17),
+        ("line-to-bottom", 2),
+    ] {
+        let mut input = down(20);
+        input.extend(command(name));
+        assert_eq!(run(3, input), rowoff, "{}", name);
+    }
+    let mut input = down(20);
+    input.extend(command("line-to-top"));
+    assert_eq!(run(0, input), 20);
+
+    // 'zt' in Vi normal mode
+    let input = keys("20jzt");
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[this_file],
+    )
+    .unwrap();
+    let config = "[editor]\nvi = true\nscrolloff = 3";
+    editor.set_options(Config::parse(config).unwrap().editor);
+    editor.edit().unwrap();
+    assert_eq!(editor.screen().rowoff, 17);
+}
+
+#[test]
+fn test_folding() {
+    use crate::fold::Folds;
+
+    let mut folds = Folds::default();
+    folds.close(2, 5);
+    folds.close(3, 4); // Nested
+    folds.close(8, 9);
+    assert!(folds.is_hidden(4) && !folds.is_hidden(2) && !folds.is_hidden(6));
+    assert_eq!(folds.count(0, 10), 6); // 0, 1, 2, 6, 7, 8
+    assert_eq!(folds.next_line(2), 6);
+    assert_eq!(folds.prev_line(6), 2);
+    assert_eq!(folds.visible_line(4), 2);
+    assert_eq!(folds.down(0, 4, 100), 7);
+    assert_eq!(folds.up(9, 3), 6);
+    assert!(folds.reveal(4)); // Both folds hiding the line are opened
+    assert_eq!(folds.next_line(2), 3);
+    assert!(!folds.open(3));
+    assert!(folds.open(8));
+    assert!(folds.is_empty());
+
+    let text = "fn main() {\n    if a {\n        b();\n    } else {\n        c();\n    }\n}\n\
+                def f():\n    x = 1\n\n    y = 2\nz = 3\n";
+    let path = env::temp_dir().join(format!("kiro-test-folding-{}.txt", process::id()));
+    fs::write(&path, text).unwrap();
+    let run = |input: Vec<InputSeq>| {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            vec![],
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        let lines: Vec<_> = editor.lines().map(String::from).collect();
+        let rendered = editor.screen().rendered_lines();
+        (
+            lines,
+            rendered[..6].to_vec(),
+            editor.screen().rendered_cursor(),
+        )
+    };
+
+    // Folds by brackets and by indentation. Cursor moves skip hidden lines
+    let mut input = vec![alt('q'), ctrl('n'), alt('q'), ctrl('n'), ctrl('p')];
+    input.push(ctrl('p'));
+    let (_, rendered, cursor) = run(input);
+    assert_eq!(
+        rendered,
+        [
+            "fn main() { ⋯ 6 lines",
+            "def f(): ⋯ 3 lines",
+            "z = 3",
+            "~",
+            "~",
+            "~"
+        ]
+    );
+    assert_eq!(cursor, (0, 0));
+
+    // Search opens folds hiding the match. Other nested folds are kept
+    let mut input = vec![alt('{'), ctrl('g')];
+    input.extend(keys("c()\r"));
+    let (_, rendered, cursor) = run(input);
+    assert_eq!(
+        rendered,
+        [
+            "fn main() {",
+            "    if a { ⋯ 1 line",
+            "    } else {",
+            "        c();",
+            "    }",
+            "}"
+        ]
+    );
+    assert_eq!(cursor, (8, 3));
+
+    // Folds follow lines inserted above and are opened by edits in them
+    let mut input = vec![ctrl('n'), ctrl('n'), ctrl('n'), ctrl('n')];
+    input.push(alt('q')); // Fold 'else' block at its inner line
+    input.extend(vec![alt('<'), ctrl('m'), ctrl('n'), ctrl('n'), ctrl('n')]);
+    let (lines, rendered, cursor) = run(input.clone());
+    assert_eq!(lines[0], "");
+    assert_eq!(
+        rendered,
+        [
+            "",
+            "fn main() {",
+            "    if a {",
+            "        b();",
+            "    } else { ⋯ 2 lines",
+            "}"
+        ]
+    );
+    assert_eq!(cursor, (0, 4));
+    input.extend(vec![ctrl('e'), ctrl('d')]); // Join the hidden line
+    let (lines, rendered, _) = run(input);
+    assert_eq!(lines[4], "    } else {        c();");
+    assert_eq!(rendered[4..], ["    } else {        c();", "    }"]);
+
+    // Scrolling counts only visible lines
+    let mut input = vec![alt('{')];
+    input.extend((0..100).map(|_| ctrl('n')));
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[file!()],
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.screen().rendered_cursor().1, 21);
+    let rendered = editor.screen().rendered_lines();
+    assert!(rendered[..22]
+        .iter()
+        .filter(|l| l.trim_start().starts_with("fn "))
+        .all(|l| l.contains('⋯')));
+
+    // Vi mode
+    let input = keys("jzcggzMzRzojza");
+    let mut editor = Editor::open(
+        DummyInputs(input),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+        &[&path],
+    )
+    .unwrap();
+    editor.set_options(Config::parse("[editor]\nvi = true").unwrap().editor);
+    editor.edit().unwrap();
+    let rendered = editor.screen().rendered_lines();
+    assert_eq!(
+        rendered[..3],
+        ["fn main() {", "    if a { ⋯ 1 line", "    } else {"]
+    );
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_horizontal_scroll() {
+    let run = |sidescroll: usize, input: Vec<InputSeq>| {
+        let mut output = vec![];
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            &mut output,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        let config = format!("[editor]\nsidescroll = {}", sidescroll);
+        editor.set_options(Config::parse(&config).unwrap().editor);
+        editor.edit().unwrap();
+        let coloff = editor.screen().coloff;
+        let line = editor.lines().next().unwrap().to_string();
+        drop(editor);
+        (coloff, line, String::from_utf8(output).unwrap())
+    };
+    let alt_end = || {
+        let mut seq = sp(KeySeq::EndKey);
+        seq.alt = true;
+        seq
+    };
+    let long_line = |keys: &[InputSeq]| {
+        let mut input: Vec<_> = (0..100).map(|_| key('a')).collect();
+        input.push(ctrl('a'));
+        input.extend_from_slice(keys);
+        input
+    };
+
+    // The last column shows a marker while the line continues out of screen
+    let (coloff, _, output) = run(0, long_line(&[]));
+    assert_eq!(coloff, 0);
+    assert!(output.contains("a>"), "{:?}", output);
+
+    // Cursor moves to the last character before the marker
+    let (coloff, _, _) = run(0, long_line(&[alt_end()]));
+    assert_eq!(coloff, 0);
+    let (_, line, _) = run(0, long_line(&[alt_end(), key('x')]));
+    assert_eq!(line.find('x'), Some(78));
+
+    // Moving onto the marker scrolls by half of the screen, or by the step
+    let (coloff, _, output) = run(0, long_line(&[alt_end(), ctrl('f')]));
+    assert_eq!(coloff, 40);
+    assert!(output.contains('<'), "{:?}", output);
+    let (coloff, _, _) = run(8, long_line(&[alt_end(), ctrl('f')]));
+    assert_eq!(coloff, 8);
+    let (coloff, _, _) = run(8, long_line(&[ctrl('e')]));
+    assert_eq!(coloff, 100 + 1 + 8 - 80);
+
+    // Moving onto the marker at the first column scrolls back
+    let mut input = long_line(&[ctrl('e')]);
+    input.extend((0..71).map(|_| ctrl('b')));
+    let (coloff, _, _) = run(8, input);
+    assert_eq!(coloff, 29 - 8);
+}
+
+#[test]
+fn test_invisibles() {
+    let path = env::temp_dir().join(format!("kiro-test-invisibles-{}", process::id()));
+    fs::write(&path, "a\tb\u{a0}c d  \n\t\n").unwrap();
+    // Text written to the terminal without escape sequences
+    let run = |config: &str, input: Vec<InputSeq>| {
+        let mut output = vec![];
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            &mut output,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.set_options(Config::parse(config).unwrap().editor);
+        editor.edit().unwrap();
+        let lines: Vec<_> = editor.lines().map(String::from).collect();
+        let msg = editor.screen().message_text().to_string();
+        drop(editor);
+        let mut text = String::new();
+        let output = String::from_utf8(output).unwrap();
+        let mut chars = output.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.find(|c| c.is_ascii_alphabetic());
+            } else {
+                text.push(c);
+            }
+        }
+        (lines, msg, text)
+    };
+
+    let (_, _, text) = run("[editor]", vec![]);
+    assert!(!text.contains(['›', '⋅', '␣']), "{:?}", text);
+
+    // Only the first cell of tab shows the glyph. Spaces between words are not shown
+    let (lines, _, text) = run("[editor]\ninvisibles = true", vec![]);
+    assert!(text.contains("a›      b␣c d⋅⋅"), "{:?}", text);
+    assert!(text.contains("›       ~"), "{:?}", text);
+    assert_eq!(lines, vec!["a\tb\u{a0}c d  ", "\t"]);
+
+    let mut input = vec![alt('c')];
+    input.extend(keys("toggle-invisibles"));
+    input.push(ctrl('m'));
+    let (_, msg, text) = run("[editor]", input);
+    assert_eq!(msg, "Invisible characters shown");
+    assert!(text.contains('›'), "{:?}", text);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_rainbow_brackets() {
+    use Highlight::{Comment, Rainbow};
+    let mut buf = TextBuffer::new();
+    buf.insert_text("f(a[0], \"(\") /* ( */\n{\n    g(')') }\n}");
+    let mut hl = Highlighting::new(Language::Rust, buf.rows());
+    hl.update(buf.rows(), buf.rows().len());
+    assert!(!hl.lines.iter().flatten().any(|h| matches!(h, Rainbow(_))));
+
+    hl.set_rainbow(true);
+    assert!(hl.needs_update);
+    hl.update(buf.rows(), buf.rows().len());
+    let brackets = |y: usize| -> Vec<_> {
+        hl.lines[y]
+            .iter()
+            .enumerate()
+            .filter_map(|(x, h)| match h {
+                Rainbow(d) => Some((x, *d)),
+                _ => None,
+            })
+            .collect()
+    };
+    // Brackets in strings, characters and comments are not counted
+    assert_eq!(brackets(0), vec![(1, 0), (3, 1), (5, 1), (11, 0)]);
+    assert_eq!(hl.lines[0][9], Highlight::String);
+    assert_eq!(hl.lines[0][17], Comment);
+    // Depth continues across lines and the unmatched closing bracket is not colored
+    assert_eq!(brackets(1), vec![(0, 0)]);
+    assert_eq!(brackets(2), vec![(5, 1), (9, 1), (11, 0)]);
+    assert_eq!(brackets(3), vec![]);
+    assert!(Rainbow(0).color() != Rainbow(1).color());
+
+    let mut input = vec![alt('c')];
+    input.extend(keys("toggle-rainbow-brackets\r"));
+    let mut editor = Editor::new(
+        DummyInputs(input),
+        Discard,
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.screen().message_text(), "Rainbow brackets enabled");
+    assert!(
+        Config::parse("[editor]\nrainbow_brackets = true")
+            .unwrap()
+            .editor
+            .rainbow_brackets
+    );
+}
+
+#[test]
+fn test_cursor_line_and_ruler() {
+    let run = |config: &str| {
+        let mut input = keys("fn f() {}\rworld");
+        input.push(sp(KeySeq::UpKey));
+        let mut editor = Editor::new(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+        )
+        .unwrap();
+        editor.set_options(Config::parse(config).unwrap().editor);
+        editor.edit().unwrap();
+        let screen = editor.screen();
+        let bg = |x, y| screen.rendered_background(x, y);
+        let default = bg(0, 2);
+        assert_eq!(bg(0, 1), default);
+        (bg(0, 0), bg(20, 0), bg(10, 0), bg(10, 1), bg(10, 2))
+    };
+
+    let (cursor, rest, ruler0, ruler1, empty) = run("[editor]");
+    assert_eq!([cursor, rest, ruler0, ruler1], [empty; 4]);
+
+    // Background of the cursor line continues after the end of line. The ruler is drawn on padding
+    // of lines but not on empty rows after the end of buffer
+    let (cursor, rest, ruler0, ruler1, empty) =
+        run("[editor]\ncursor_line = true\nruler = [10, 100]");
+    assert_ne!(cursor, empty);
+    assert_eq!(rest, cursor);
+    assert_ne!(ruler0, cursor);
+    assert_ne!(ruler0, empty);
+    assert_eq!(ruler1, ruler0);
+    assert_eq!(empty, run("[editor]").4);
+
+    let options = Config::parse("[editor]\nruler = 80").unwrap().editor;
+    assert_eq!(options.ruler, vec![80]);
+    assert!(Config::parse("[editor]\nruler = [80, \"x\"]").is_err());
+    assert!(Config::parse("[editor]\nruler = 0").is_err());
+}
+
+#[test]
+fn test_indent_guides() {
+    let dir = env::temp_dir().join(format!("kiro-test-indent-guides-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let text = "fn f() {\n    if x {\n        y();\n\n    }\n}\n";
+    let run = |name: &str, config: &str, input: Vec<InputSeq>| {
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.set_options(Config::parse(config).unwrap().editor);
+        editor.edit().unwrap();
+        let screen = editor.screen();
+        let cells = [
+            (0, 0),
+            (0, 1),
+            (1, 1),
+            (4, 1),
+            (0, 2),
+            (4, 2),
+            (8, 2),
+            (0, 3),
+            (4, 3),
+        ];
+        let bgs: Vec<_> = cells
+            .iter()
+            .map(|&(x, y)| screen.rendered_background(x, y))
+            .collect();
+        (bgs, screen.message_text().to_string())
+    };
+
+    let (bgs, _) = run("a.rs", "[editor]\nindent_guides = true", vec![]);
+    let (default, guide) = (bgs[0], bgs[1]);
+    assert_ne!(guide, default);
+    // Guides are at each level in indentation. The blank line continues the guide of the block
+    let expected = [
+        default, guide, default, default, guide, guide, default, guide, default,
+    ];
+    assert_eq!(bgs, expected);
+
+    let (bgs, _) = run("a.txt", "[editor]\nindent_guides = true", vec![]);
+    assert!(bgs.iter().all(|bg| *bg == default), "{:?}", bgs);
+
+    let mut input = vec![alt('c')];
+    input.extend(keys("toggle-indent-guides\r"));
+    let (bgs, msg) = run("a.rs", "[editor]", input);
+    assert_eq!(msg, "Indent guides shown");
+    assert_eq!(bgs, expected);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scrollbar() {
+    let path = env::temp_dir().join(format!("kiro-test-scrollbar-{}", process::id()));
+    let mut text = format!("{}\n", "x".repeat(100));
+    for i in 1..220 {
+        text.push_str(&format!("line {}\n", i));
+    }
+    text = text.replace("line 150\n", "line 150 needle\n");
+    fs::write(&path, text).unwrap();
+    let run = |config: &str, input: Vec<InputSeq>| {
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.set_options(Config::parse(config).unwrap().editor);
+        editor.edit().unwrap();
+        let screen = editor.screen();
+        let bar: Vec<_> = (0..screen.rows())
+            .map(|y| screen.rendered_background(screen.cols() - 1, y))
+            .collect();
+        (screen.rendered_lines(), bar)
+    };
+
+    let (lines, bar) = run("[editor]", vec![]);
+    assert_eq!(lines[0], "x".repeat(79) + ">");
+    assert!(bar.iter().all(|bg| *bg == bar[0]));
+
+    // 22 rows show 220 lines so that each row of the scrollbar stands for 10 lines
+    let (lines, bar) = run("[editor]\nscrollbar = true", vec![]);
+    assert_eq!(lines[0], "x".repeat(78) + ">");
+    let (thumb, track) = (bar[0], bar[3]);
+    assert_ne!(thumb, track);
+    assert_eq!(&bar[..3], &[thumb; 3]);
+    assert!(bar[3..].iter().all(|bg| *bg == track));
+
+    // Clicking the scrollbar jumps to the relative position and centers the line
+    let click = sp(KeySeq::MouseClick(12, 80, 0));
+    let (lines, bar) = run("[editor]\nscrollbar = true", vec![click]);
+    assert_eq!(lines[11], "line 110");
+    // Lines 99..121 are on the screen
+    assert_eq!(bar[8], track);
+    assert_eq!(&bar[9..13], &[thumb; 4]);
+    assert_eq!(bar[13], track);
+
+    // Matches of search are marked
+    let mut input = vec![alt('c')];
+    input.extend(keys("search\rneedle"));
+    let (lines, _) = run("[editor]\nscrollbar = true", input);
+    let marks: Vec<_> = lines[..22]
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| l.chars().nth(79) == Some('*'))
+        .map(|(y, _)| y)
+        .collect();
+    assert_eq!(marks, vec![15]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_headless_editing() {
+    // LEFT, BACKSPACE, ENTER, UP, Ctrl-E and DOWN are sent as a terminal sends them
+    let input: &[u8] = b"abc\x1b[D\x7fX\r\x1b[A\x05!\x1b[Bdef";
+    let mut editor = Editor::with_io(input, vec![]).unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["aX!", "cdef"]);
+    let rendered = editor.screen().rendered_lines();
+    assert_eq!(rendered.len(), 24);
+    assert_eq!(rendered[..3], ["aX!", "cdef", "~"]);
+    assert_eq!(editor.screen().rendered_cursor(), (4, 1));
+}
+
+#[test]
+fn test_search_next_with_empty_query() {
+    // '{', Ctrl-A, Ctrl-D, Ctrl-G, Ctrl-L, Ctrl-N, 'x', Enter and Ctrl-K. Moving to the next match
+    // with empty query must not put the cursor past the end of line
+    let input: &[u8] = b"{\x01\x04\x07\x0c\x0ex\r\x0b";
+    let mut editor = Editor::with_io(input, vec![]).unwrap();
+    editor.edit().unwrap();
+    assert_eq!(editor.lines().collect::<Vec<_>>(), vec![""]);
+    assert_eq!(editor.screen().rendered_cursor(), (0, 0));
+}
+
+#[test]
+fn test_headless_search() {
+    let script = Script::new()
+        .keys("foo\rbar\rbaz bar\r")
+        .alt('<')
+        .ctrl('g')
+        .keys("bar")
+        .ctrl('n')
+        .keys("\r")
+        .keys("!");
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let rendered = editor.screen().rendered_lines();
+    assert_eq!(rendered[..5], ["foo", "bar", "baz !bar", "", "~"]);
+    assert_eq!(editor.screen().rendered_cursor(), (5, 2));
+}
+
+#[test]
+fn test_headless_scrolling() {
+    let mut script = Script::new();
+    for i in 0..100 {
+        script = script.keys(&format!("line {}\r", i));
+    }
+    let script = script
+        .alt('<')
+        .key(KeySeq::PageDownKey)
+        .key(KeySeq::PageDownKey);
+    let mut editor = Editor::new(
+        script.clone().into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let rendered = editor.screen().rendered_lines();
+    assert_eq!(rendered[0], "line 44");
+    assert_eq!(rendered[21], "line 65");
+    assert!(rendered[22].ends_with("66:1 65%"), "{:?}", rendered[22]);
+    assert_eq!(editor.screen().rendered_cursor().1, 21);
+
+    // The view scrolls to keep the cursor visible when the screen shrinks
+    let script = script.resize(40, 10).keys("!");
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+    let rendered = editor.screen().rendered_lines();
+    assert_eq!(rendered.len(), 10);
+    assert_eq!(rendered[0], "line 58");
+    assert_eq!(rendered[7], "!line 65");
+    assert_eq!(editor.screen().rendered_cursor(), (1, 7));
+}
+
+#[test]
+fn test_headless_cursor_after_last_line() {
+    // Searching and selecting at the line after the last line must not index out of rows
+    let script = Script::new()
+        .keys("foo bar")
+        .key(KeySeq::DownKey)
+        .ctrl('g')
+        .keys("bar")
+        .keys("\r")
+        .keys("!")
+        .key(KeySeq::DownKey)
+        .ctrl(' ')
+        .key(KeySeq::UpKey);
+    let mut editor = Editor::new(
+        script.into_iter(),
+        vec![],
+        WINDOW_SIZE,
+        Clipboard::default(),
+    )
+    .unwrap();
+    editor.edit().unwrap();
+
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(lines, vec!["foo !bar"]);
+}
+
+#[test]
+fn test_diff_mode() {
+    let dir = env::temp_dir().join(format!("kiro-test-diff-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (old, new) = (dir.join("old.txt"), dir.join("new.txt"));
+    fs::write(&old, "a\nb\nc\nd\ne\n").unwrap();
+    fs::write(&new, "a\nB\nc\nd\ne\nf\n").unwrap();
+    let run = |input: Vec<InputSeq>| {
+        let files = [&old, &new];
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &files,
+        )
+        .unwrap();
+        editor.start_diff();
+        editor.edit().unwrap();
+        editor
+    };
+
+    // The old file is on the left and the new file is on the right of the separator
+    let editor = run(vec![]);
+    let screen = editor.screen();
+    let lines = screen.rendered_lines();
+    assert_eq!(lines[0], format!("{:39}|{}", "a", "a"));
+    assert_eq!(lines[1], format!("{:39}|{}", "b", "B"));
+    assert_eq!(lines[5], format!("{:39}|{}", "~", "f"));
+    assert_eq!(screen.message_text(), "2 hunks");
+    let normal = screen.rendered_background(0, 0);
+    let changed = screen.rendered_background(0, 1);
+    let added = screen.rendered_background(40, 5);
+    assert_ne!(changed, normal);
+    assert_ne!(added, normal);
+    assert_ne!(added, changed);
+    assert_eq!(screen.rendered_background(40, 1), changed);
+    assert_eq!(screen.rendered_background(0, 5), normal);
+
+    // Jump between hunks
+    let editor = run(vec![alt(')')]);
+    assert_eq!(editor.screen().rendered_cursor(), (0, 1));
+    assert_eq!(editor.screen().message_text(), "Hunk 1/2");
+    let editor = run(vec![alt(')'), alt(')'), alt(')')]);
+    assert_eq!(editor.screen().rendered_cursor(), (0, 5));
+    assert_eq!(editor.screen().message_text(), "No more hunks");
+    let editor = run(vec![alt('>'), alt('(')]);
+    assert_eq!(editor.screen().rendered_cursor(), (0, 1));
+
+    // Copy the hunk at cursor to the right and switch to the right side at the same line
+    let editor = run(vec![alt(')'), alt('='), ctrl('x')]);
+    assert_eq!(
+        editor.lines().collect::<Vec<_>>(),
+        ["a", "b", "c", "d", "e", "f"]
+    );
+    assert_eq!(editor.screen().rendered_cursor(), (40, 1));
+    let lines = editor.screen().rendered_lines();
+    assert_eq!(lines[1], format!("{:39}|{}", "b", "b"));
+    assert_eq!(
+        editor.screen().rendered_background(0, 1),
+        editor.screen().rendered_background(0, 0),
+    );
+
+    // The other side scrolls to the lines corresponding to the top of the screen
+    let text: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+    fs::write(&old, &text).unwrap();
+    fs::write(&new, format!("x\ny\n{}", text)).unwrap();
+    let editor = run(vec![alt('>')]);
+    let lines = editor.screen().rendered_lines();
+    let (left, right) = lines[0].split_once('|').unwrap();
+    assert_ne!(left.trim(), "1");
+    assert_eq!(left.trim(), right);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_merge_conflicts() {
+    let path = env::temp_dir().join(format!("kiro-test-conflict-{}.txt", process::id()));
+    let text = [
+        "top",
+        "<<<<<<< HEAD",
+        "ours",
+        "=======",
+        "theirs",
+        ">>>>>>> branch",
+        "middle",
+        "<<<<<<< HEAD",
+        "ours 2",
+        "||||||| base",
+        "base 2",
+        "=======",
+        "theirs 2",
+        ">>>>>>> branch",
+        "bottom",
+    ];
+    fs::write(&path, text.join("\n")).unwrap();
+    let run = |commands: &[&str]| {
+        let mut input = vec![];
+        for cmd in commands {
+            input.push(alt('c'));
+            input.extend(keys(cmd));
+            input.push(key('\r'));
+        }
+        let mut editor = Editor::open(
+            DummyInputs(input),
+            Discard,
+            WINDOW_SIZE,
+            Clipboard::default(),
+            &[&path],
+        )
+        .unwrap();
+        editor.edit().unwrap();
+        editor
+    };
+
+    let editor = run(&[]);
+    let screen = editor.screen();
+    assert_eq!(screen.message_text(), "2 merge conflicts");
+    let bg = |y| screen.rendered_background(0, y);
+    let (normal, marker, ours, theirs) = (bg(0), bg(1), bg(2), bg(4));
+    assert_ne!(marker, normal);
+    assert_ne!(ours, normal);
+    assert_ne!(theirs, normal);
+    assert_ne!(ours, theirs);
+    assert_eq!(bg(3), marker);
+    assert_eq!(bg(6), normal);
+    assert_eq!(bg(8), ours);
+    assert_eq!(bg(10), marker); // Common ancestor
+    assert_eq!(bg(12), theirs);
+
+    let editor = run(&["next-conflict", "next-conflict"]);
+    assert_eq!(editor.screen().rendered_cursor(), (0, 7));
+    assert_eq!(editor.screen().message_text(), "Conflict 2/2");
+
+    let editor = run(&["next-conflict", "keep-theirs"]);
+    assert_eq!(editor.screen().message_text(), "1 conflict left");
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(&lines[..3], ["top", "theirs", "middle"]);
+
+    let editor = run(&["bottom-of-file", "previous-conflict", "keep-both"]);
+    assert_eq!(editor.screen().message_text(), "1 conflict left");
+    let lines: Vec<_> = editor.lines().collect();
+    assert_eq!(&lines[7..], ["ours 2", "theirs 2", "bottom"]);
+
+    let editor = run(&["keep-ours"]);
+    assert_eq!(
+        editor.screen().message_text(),
+        "No merge conflict at cursor"
+    );
+
+    fs::remove_file(&path).unwrap();
+}
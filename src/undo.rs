@@ -0,0 +1,227 @@
+use crate::clock::Instant;
+use crate::gap_buffer::GapBuffer;
+use crate::keymap::Command;
+use crate::row::Row;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Max number of changes kept in history. Older changes are forgotten
+const MAX_ENTRIES: usize = 1000;
+// Typing characters successively within this duration is undone at once
+const COALESCE_DURATION: Duration = Duration::from_millis(1000);
+
+// Minimal unit of modification to rows of text buffer. All edit operations in TextBuffer are
+// represented as sequence of this diff so that they can be reverted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditDiff {
+    InsertChar(usize, usize, char), // (x, y, inserted char)
+    DeleteChar(usize, usize, char), // (x, y, deleted char)
+    Insert(usize, usize, String),   // (x, y, inserted text)
+    Remove(usize, usize, String),   // (x, y, removed text)
+    Append(usize, String),          // (y, appended text)
+    Truncate(usize, String),        // (y, truncated text)
+    InsertLine(usize, String),      // (y, inserted line)
+    DeleteLine(usize, String),      // (y, deleted line)
+}
+
+impl EditDiff {
+    pub fn inverse(&self) -> EditDiff {
+        use EditDiff::*;
+        match self.clone() {
+            InsertChar(x, y, c) => DeleteChar(x, y, c),
+            DeleteChar(x, y, c) => InsertChar(x, y, c),
+            Insert(x, y, s) => Remove(x, y, s),
+            Remove(x, y, s) => Insert(x, y, s),
+            Append(y, s) => Truncate(y, s),
+            Truncate(y, s) => Append(y, s),
+            InsertLine(y, s) => DeleteLine(y, s),
+            DeleteLine(y, s) => InsertLine(y, s),
+        }
+    }
+
+    // Line which is modified by this diff. Lines after this line need to be rendered again
+    pub fn line(&self) -> usize {
+        use EditDiff::*;
+        match *self {
+            InsertChar(_, y, _)
+            | DeleteChar(_, y, _)
+            | Insert(_, y, _)
+            | Remove(_, y, _)
+            | Append(y, _)
+            | Truncate(y, _)
+            | InsertLine(y, _)
+            | DeleteLine(y, _) => y,
+        }
+    }
+
+    // New line is rendered with the tab width
+    pub fn apply(&self, rows: &mut GapBuffer<Row>, tab_width: usize) {
+        use EditDiff::*;
+        match self {
+            InsertChar(x, y, c) => rows[*y].insert_char(*x, *c),
+            DeleteChar(x, y, _) => rows[*y].delete_char(*x),
+            Insert(x, y, s) => rows[*y].insert_str(*x, s),
+            Remove(x, y, s) => rows[*y].remove(*x, x + s.chars().count()),
+            Append(y, s) => rows[*y].append(s),
+            Truncate(y, s) => {
+                let at = rows[*y].len() - s.chars().count();
+                rows[*y].truncate(at);
+            }
+            InsertLine(y, s) => rows.insert(*y, Row::new(s.as_str(), tab_width)),
+            DeleteLine(y, _) => {
+                rows.remove(*y);
+            }
+        }
+    }
+}
+
+// Edit operation by key inputs which made a change. Editor remembers the last one to repeat it at
+// another position
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditCommand {
+    Insert(String), // Typed text. New line is represented as "\n"
+    Run(Command),   // Command which edits text such as delete-word
+}
+
+impl EditCommand {
+    // Successive typing is merged into one insertion. Returns false when it cannot be merged
+    pub fn merge(&mut self, next: &EditCommand) -> bool {
+        match (self, next) {
+            (EditCommand::Insert(text), EditCommand::Insert(next)) => {
+                text.push_str(next);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            EditCommand::Insert(text) => format!("insert {:?}", text),
+            EditCommand::Run(cmd) => cmd.name().to_string(),
+        }
+    }
+}
+
+// One undoable change. It consists of diffs caused by one key input (or successive typing)
+struct Change {
+    diffs: Vec<EditDiff>,
+    command: Option<EditCommand>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+    updated_at: Instant,
+}
+
+impl Change {
+    fn is_typing(&self) -> bool {
+        self.diffs
+            .iter()
+            .all(|d| matches!(d, EditDiff::InsertChar(..)))
+    }
+
+    fn can_coalesce(&self, next: &Change) -> bool {
+        // Note that the first typing in a line may insert a new line before inserting a char
+        matches!(self.diffs.last(), Some(EditDiff::InsertChar(..)))
+            && next.is_typing()
+            && self.cursor_after == next.cursor_before
+            && next.updated_at.duration_since(self.updated_at) < COALESCE_DURATION
+    }
+}
+
+// (first modified line, cursor position, command of the change) after undo or redo
+pub type Undone = (usize, (usize, usize), Option<EditCommand>);
+
+#[derive(Default)]
+pub struct History {
+    // Changes which can be undone. Changes after `index` were undone and can be redone
+    changes: VecDeque<Change>,
+    index: usize,
+    // Change made by current key input which is not finished yet
+    ongoing: Option<Change>,
+}
+
+impl History {
+    pub fn push(&mut self, diff: EditDiff, cursor: (usize, usize)) {
+        self.ongoing
+            .get_or_insert_with(|| Change {
+                diffs: vec![],
+                command: None,
+                cursor_before: cursor,
+                cursor_after: cursor,
+                updated_at: Instant::now(),
+            })
+            .diffs
+            .push(diff);
+    }
+
+    // Finish current change and make it one undo unit. This is called per key input. `command` is
+    // the operation which made the change
+    pub fn finish_ongoing_edit(&mut self, cursor: (usize, usize), command: Option<EditCommand>) {
+        let mut change = if let Some(c) = self.ongoing.take() {
+            c
+        } else {
+            return;
+        };
+        change.cursor_after = cursor;
+        change.command = command;
+        change.updated_at = Instant::now();
+
+        // Changes undone so far are no longer redoable
+        self.changes.truncate(self.index);
+
+        if let Some(last) = self.changes.back_mut() {
+            if last.can_coalesce(&change) {
+                last.diffs.append(&mut change.diffs);
+                let merged = match (&mut last.command, &change.command) {
+                    (Some(cmd), Some(next)) => cmd.merge(next),
+                    _ => false,
+                };
+                if !merged {
+                    last.command = change.command;
+                }
+                last.cursor_after = change.cursor_after;
+                last.updated_at = change.updated_at;
+                return;
+            }
+        }
+
+        if self.changes.len() == MAX_ENTRIES {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+        self.index = self.changes.len();
+    }
+
+    // Returns the first modified line, cursor position after undo and the command of the change
+    // `apply` applies each diff to rows of text buffer
+    pub fn undo<F: FnMut(&EditDiff)>(&mut self, mut apply: F) -> Option<Undone> {
+        if self.index == 0 {
+            return None;
+        }
+        self.index -= 1;
+        let change = &self.changes[self.index];
+        let mut dirty = usize::MAX;
+        for diff in change.diffs.iter().rev() {
+            let diff = diff.inverse();
+            apply(&diff);
+            dirty = dirty.min(diff.line());
+        }
+        Some((dirty, change.cursor_before, change.command.clone()))
+    }
+
+    // Returns the first modified line, cursor position after redo and the command of the change
+    // `apply` applies each diff to rows of text buffer
+    pub fn redo<F: FnMut(&EditDiff)>(&mut self, mut apply: F) -> Option<Undone> {
+        if self.index == self.changes.len() {
+            return None;
+        }
+        let change = &self.changes[self.index];
+        self.index += 1;
+        let mut dirty = usize::MAX;
+        for diff in change.diffs.iter() {
+            apply(diff);
+            dirty = dirty.min(diff.line());
+        }
+        Some((dirty, change.cursor_after, change.command.clone()))
+    }
+}
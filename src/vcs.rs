@@ -0,0 +1,230 @@
+// Changes of a text buffer from the file in HEAD of git repository. The contents in HEAD are read
+// by `git show` once on opening the file and on saving it. Lines of the buffer are compared with
+// them using line-based diff by Myers' O(ND) algorithm on each edit. Changed lines are marked in
+// gutter of the screen:
+//
+//   +  # Added lines
+//   ~  # Modified lines (added and deleted at the same place)
+//   -  # Lines were deleted before this line (or after the last line)
+//
+// `git blame` of a line is run with the current contents of the buffer so that line numbers are not
+// shifted by unsaved edits.
+
+use crate::diff::{self, Op};
+use crate::error::{Error, Result};
+use crate::shell;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mark {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Mark {
+    pub fn symbol(self) -> char {
+        match self {
+            Mark::Added => '+',
+            Mark::Modified => '~',
+            Mark::Deleted => '-',
+        }
+    }
+}
+
+// Marks of each line of `new`
+fn diff_marks(old: &[&str], new: &[&str]) -> Vec<Option<Mark>> {
+    let mut marks = vec![None; new.len()];
+
+    // Group consecutive inserts and deletes into hunks
+    let mut y = 0;
+    let mut ops = diff::line_ops(old, new).into_iter().peekable();
+    while let Some(op) = ops.next() {
+        if op == Op::Equal {
+            y += 1;
+            continue;
+        }
+        let (start, mut deleted) = (y, false);
+        let mut current = Some(op);
+        while let Some(op) = current.filter(|op| *op != Op::Equal) {
+            match op {
+                Op::Insert => y += 1,
+                _ => deleted = true,
+            }
+            current = ops.next_if(|op| *op != Op::Equal);
+        }
+        if start == y {
+            // Only deleted. Mark the next line or the last line when deleted at end of buffer
+            let at = start.min(marks.len().saturating_sub(1));
+            if let Some(m) = marks.get_mut(at) {
+                m.get_or_insert(Mark::Deleted);
+            }
+        } else {
+            let mark = if deleted { Mark::Modified } else { Mark::Added };
+            for m in &mut marks[start..y] {
+                *m = Some(mark);
+            }
+        }
+    }
+    marks
+}
+
+// `git -C <dir>` command for the file and its name in the directory
+fn git_at(path: &Path) -> Option<(Command, &str)> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+    let name = path.file_name()?.to_str()?;
+    let mut git = Command::new("git");
+    git.arg("-C").arg(dir.unwrap_or_else(|| Path::new(".")));
+    Some((git, name))
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Blame {
+    pub commit: String,
+    pub author: String,
+    pub date: String, // YYYY-MM-DD in the author's time zone
+    pub summary: String,
+}
+
+impl Blame {
+    // git blame shows all-zero hash for lines which are not committed yet
+    pub fn is_committed(&self) -> bool {
+        !self.commit.bytes().all(|b| b == b'0')
+    }
+
+    pub fn short_commit(&self) -> &str {
+        &self.commit[..self.commit.len().min(8)]
+    }
+}
+
+// Convert days since 1970-01-01 to (year, month, day)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Format UNIX time with the time zone such as '+0900' as YYYY-MM-DD
+fn format_date(time: i64, tz: &str) -> String {
+    let offset = match (tz.get(..1), tz.get(1..3), tz.get(3..5)) {
+        (Some(sign), Some(h), Some(m)) => {
+            let secs = h.parse::<i64>().unwrap_or(0) * 3600 + m.parse::<i64>().unwrap_or(0) * 60;
+            if sign == "-" {
+                -secs
+            } else {
+                secs
+            }
+        }
+        _ => 0,
+    };
+    let (y, m, d) = civil_from_days((time + offset).div_euclid(86400));
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+// Parse output of `git blame --porcelain` for one line
+fn parse_blame(output: &str) -> Option<Blame> {
+    let mut lines = output.lines();
+    let commit = lines.next()?.split(' ').next()?.to_string();
+    let (mut author, mut time, mut tz, mut summary) = ("", 0, "+0000", "");
+    for line in lines.take_while(|l| !l.starts_with('\t')) {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "author" => author = value,
+            "author-time" => time = value.parse().unwrap_or(0),
+            "author-tz" => tz = value,
+            "summary" => summary = value,
+            _ => {}
+        }
+    }
+    Some(Blame {
+        commit,
+        author: author.to_string(),
+        date: format_date(time, tz),
+        summary: summary.to_string(),
+    })
+}
+
+// Blame the 1-based line of the file. `contents` is the current text of the buffer
+pub fn blame(path: &Path, line: usize, contents: &str) -> Result<Blame> {
+    let (mut git, name) =
+        git_at(path).ok_or_else(|| Error::other("Not a file in git repository"))?;
+    git.arg("blame")
+        .arg("--porcelain")
+        .arg("-L")
+        .arg(format!("{},{}", line, line))
+        .arg("--contents")
+        .arg("-")
+        .arg("--")
+        .arg(name);
+    let output = shell::run_program(git, "git blame", Some(contents))?;
+    parse_blame(&output).ok_or_else(|| Error::other("Unexpected output of git blame"))
+}
+
+// Log message and diff of the commit which the file belongs to
+pub fn show_commit(path: &Path, commit: &str) -> Result<String> {
+    let (mut git, _) = git_at(path).ok_or_else(|| Error::other("Not a file in git repository"))?;
+    git.arg("show").arg("--no-color").arg(commit);
+    shell::run_program(git, "git show", None)
+}
+
+pub struct VcsDiff {
+    base: Vec<String>, // Lines of the file in HEAD
+    marks: Vec<Option<Mark>>,
+}
+
+impl VcsDiff {
+    pub fn new(base: Vec<String>) -> Self {
+        VcsDiff {
+            base,
+            marks: vec![],
+        }
+    }
+
+    // None when the file is not tracked by git or git is not available
+    pub fn load(path: &Path) -> Option<Self> {
+        let (mut git, name) = git_at(path)?;
+        let output = git
+            .arg("show")
+            .arg(format!("HEAD:./{}", name))
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        let base = if text.is_empty() {
+            vec![]
+        } else {
+            text.split('\n')
+                .map(|l| l.strip_suffix('\r').unwrap_or(l).to_string())
+                .collect()
+        };
+        Some(Self::new(base))
+    }
+
+    // Compare lines with HEAD. Returns the first line whose mark was changed
+    pub fn update<'a, I: Iterator<Item = &'a str>>(&mut self, lines: I) -> Option<usize> {
+        let base: Vec<_> = self.base.iter().map(String::as_str).collect();
+        let lines: Vec<_> = lines.collect();
+        let marks = diff_marks(&base, &lines);
+        let changed = (0..marks.len().max(self.marks.len()))
+            .find(|i| marks.get(*i).copied().flatten() != self.marks.get(*i).copied().flatten());
+        self.marks = marks;
+        changed
+    }
+
+    pub fn mark_at(&self, y: usize) -> Option<Mark> {
+        self.marks.get(y).copied().flatten()
+    }
+}
@@ -0,0 +1,274 @@
+// Modal editing like Vi. This is a layer in front of command dispatch of editor. Keys in normal and
+// visual modes are translated into editor commands. Keys in insert mode are passed to the modeless
+// key mappings as-is. Supported keys are:
+//
+//   h j k l w b e 0 $ gg G  # Motions. Count like '3w' repeats them
+//   g$                      # Move to the last character on the screen line
+//   d y c                   # Operators composed with motions like 'dw'. 'dd' operates on lines
+//   i a I A o O             # Enter insert mode
+//   v                       # Enter visual mode. Operators apply to the selected text
+//   x p u /                 # Delete character, paste at cursor, undo, search
+//   zz zt zb                # Scroll the cursor line to center, top or bottom of screen
+//   zc zo za zM zR          # Close, open or toggle the fold at cursor. Close or open all folds
+//   ESC                     # Back to normal mode
+//   Ctrl-N                  # Complete the word before the cursor in insert mode. Repeat to cycle
+//   Ctrl-V                  # Insert the next character without expanding abbreviation
+//
+// Keys with Ctrl or Alt are also passed to the modeless key mappings in all modes.
+
+use crate::input::{InputSeq, KeySeq};
+use crate::keymap::Command;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Change,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Action {
+    Pass,              // Key is handled by the modeless key mappings
+    Run(Vec<Command>), // Run the commands in order
+    // Run `before`, start selection, run `motion` and apply the operator to the selected text
+    Operate {
+        op: Operator,
+        before: Vec<Command>,
+        motion: Vec<Command>,
+    },
+    OperateSelection(Operator), // Apply the operator to the selected text in visual mode
+    StartSelection,
+    EndSelection,
+    LeaveInsert, // Move cursor onto the last inserted character
+    Consumed,    // Key is consumed by mode change or waits for following keys
+    NotMapped,
+}
+
+#[derive(Default)]
+struct Pending {
+    count: usize,
+    op: Option<(Operator, char)>,
+    prefix: Option<char>, // 'g' or 'z' was typed and waits for the next key like 'gg' or 'zz'
+}
+
+pub struct Vi {
+    mode: Mode,
+    pending: Pending,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Vi {
+            mode: Mode::Normal,
+            pending: Pending::default(),
+        }
+    }
+}
+
+fn motion(c: char) -> Option<Command> {
+    use Command::*;
+    let cmd = match c {
+        'h' => CursorLeft,
+        'j' => CursorDown,
+        'k' => CursorUp,
+        'l' => CursorRight,
+        'w' => NextWord,
+        'b' => PreviousWord,
+        'e' => EndOfWord,
+        '0' => HeadOfLine,
+        '$' => EndOfLine,
+        'G' => BottomOfFile,
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+fn operator(c: char) -> Option<Operator> {
+    match c {
+        'd' => Some(Operator::Delete),
+        'y' => Some(Operator::Yank),
+        'c' => Some(Operator::Change),
+        _ => None,
+    }
+}
+
+impl Vi {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn translate(&mut self, seq: &InputSeq) -> Action {
+        let is_esc = seq.key == KeySeq::Key(0x1b) && !seq.alt;
+        if is_esc {
+            let prev = self.mode;
+            self.mode = Mode::Normal;
+            self.pending = Pending::default();
+            return match prev {
+                Mode::Insert => Action::LeaveInsert,
+                Mode::Visual => Action::EndSelection,
+                Mode::Normal => Action::Consumed,
+            };
+        }
+        if self.mode == Mode::Insert && seq.ctrl && !seq.alt {
+            match seq.key {
+                KeySeq::Key(b'n') => return Action::Run(vec![Command::Complete]),
+                KeySeq::Key(b'v') => return Action::Run(vec![Command::LiteralInsert]),
+                _ => {}
+            }
+        }
+        if self.mode == Mode::Insert || seq.ctrl || seq.alt {
+            return Action::Pass;
+        }
+        let c = match seq.key {
+            KeySeq::Key(b) if !b.is_ascii_control() => b as char,
+            KeySeq::Key(b'\r') => 'j', // Enter moves to next line like '+'
+            KeySeq::Key(_) | KeySeq::Utf8Key(_) => return self.not_mapped(),
+            _ => return Action::Pass, // Cursor keys, mouse events, ...
+        };
+        self.translate_char(c)
+    }
+
+    fn not_mapped(&mut self) -> Action {
+        self.pending = Pending::default();
+        Action::NotMapped
+    }
+
+    fn translate_char(&mut self, c: char) -> Action {
+        use Command::*;
+
+        // '0' is a motion when no count is typed
+        if let Some(d) = c.to_digit(10).filter(|d| *d > 0 || self.pending.count > 0) {
+            self.pending.count = self
+                .pending
+                .count
+                .saturating_mul(10)
+                .saturating_add(d as usize);
+            return Action::Consumed;
+        }
+        let prefix = self.pending.prefix.take();
+        let count = std::mem::take(&mut self.pending.count).max(1);
+        let repeat = |cmds: &[Command]| -> Vec<Command> {
+            cmds.iter()
+                .copied()
+                .cycle()
+                .take(cmds.len() * count)
+                .collect()
+        };
+        let cmd = match (prefix, c) {
+            (Some('g'), 'g') => Some(TopOfFile),
+            (Some('g'), '$') => Some(EndOfScreenLine),
+            (Some('z'), _) if self.pending.op.is_none() => {
+                let cmd = match c {
+                    'z' => CenterLine,
+                    't' => LineToTop,
+                    'b' => LineToBottom,
+                    'c' => Fold,
+                    'o' => Unfold,
+                    'a' => ToggleFold,
+                    'M' => FoldAll,
+                    'R' => UnfoldAll,
+                    _ => return self.not_mapped(),
+                };
+                return Action::Run(vec![cmd]);
+            }
+            (Some(_), _) => return self.not_mapped(),
+            (None, 'g' | 'z') => {
+                self.pending.prefix = Some(c);
+                self.pending.count = count;
+                return Action::Consumed;
+            }
+            _ => motion(c),
+        };
+
+        if let Some((op, key)) = self.pending.op.take() {
+            if c == key {
+                // Operator typed twice like 'dd' operates on lines
+                let mut motion = repeat(&[CursorDown]);
+                if op == Operator::Change {
+                    // Keep the last newline so that the changed lines are replaced with one line
+                    motion.pop();
+                    motion.push(EndOfLine);
+                } else {
+                    motion.push(HeadOfLine);
+                }
+                return self.operate(op, vec![HeadOfLine], motion);
+            }
+            return match cmd {
+                // 'cw' changes until end of word like 'ce'
+                Some(NextWord) if op == Operator::Change => {
+                    self.operate(op, vec![], repeat(&[EndOfWord]))
+                }
+                Some(cmd) => self.operate(op, vec![], repeat(&[cmd])),
+                None => self.not_mapped(),
+            };
+        }
+
+        if let Some(cmd) = cmd {
+            // 'e' moves onto the last character of word and 'G' moves to the last line in Vi
+            match cmd {
+                EndOfWord => return Action::Run(repeat(&[EndOfWord, CursorLeft])),
+                BottomOfFile => return Action::Run(vec![BottomOfFile, CursorUp]),
+                _ => {}
+            }
+            return Action::Run(repeat(&[cmd]));
+        }
+
+        if let Some(op) = operator(c) {
+            if self.mode == Mode::Visual {
+                self.mode = if op == Operator::Change {
+                    Mode::Insert
+                } else {
+                    Mode::Normal
+                };
+                return Action::OperateSelection(op);
+            }
+            self.pending.op = Some((op, c));
+            self.pending.count = count;
+            return Action::Consumed;
+        }
+
+        let (cmds, insert) = match c {
+            'i' => (vec![], true),
+            'a' => (vec![CursorRight], true),
+            'I' => (vec![HeadOfLine], true),
+            'A' => (vec![EndOfLine], true),
+            'o' => (vec![EndOfLine, NewLine], true),
+            'O' => (vec![HeadOfLine, NewLine, CursorUp], true),
+            'x' if self.mode == Mode::Visual => {
+                self.mode = Mode::Normal;
+                return Action::OperateSelection(Operator::Delete);
+            }
+            'x' => (repeat(&[DeleteNextChar]), false),
+            'p' => (repeat(&[Paste]), false),
+            'u' => (repeat(&[Undo]), false),
+            '/' => (vec![Search], false),
+            'v' if self.mode == Mode::Visual => {
+                self.mode = Mode::Normal;
+                return Action::EndSelection;
+            }
+            'v' => {
+                self.mode = Mode::Visual;
+                return Action::StartSelection;
+            }
+            _ => return self.not_mapped(),
+        };
+        if insert {
+            self.mode = Mode::Insert;
+        }
+        Action::Run(cmds)
+    }
+
+    fn operate(&mut self, op: Operator, before: Vec<Command>, motion: Vec<Command>) -> Action {
+        if op == Operator::Change {
+            self.mode = Mode::Insert;
+        }
+        Action::Operate { op, before, motion }
+    }
+}
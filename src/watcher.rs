@@ -0,0 +1,66 @@
+// Watcher of files opened in buffers. Changes by other processes are detected by polling size and
+// modification time of the files since no crate for file system notifications is available. The
+// event loop polls the watcher at most every `POLL_INTERVAL` while no key is pressed, and each
+// change is notified once as `Event::FileChanged`.
+//
+// The editor updates the state of a file after saving it so that its own writes are not notified.
+
+use crate::clock::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Size and modification time of a file. None when the file does not exist
+type Stamp = Option<(u64, Option<SystemTime>)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let meta = path.metadata().ok()?;
+    Some((meta.len(), meta.modified().ok()))
+}
+
+pub struct Watcher {
+    files: Vec<(PathBuf, Stamp)>,
+    polled_at: Instant,
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Watcher {
+            files: vec![],
+            polled_at: Instant::now(),
+        }
+    }
+}
+
+impl Watcher {
+    // Start watching the file or forget its changes until now
+    pub fn watch(&mut self, path: &Path) {
+        let current = stamp(path);
+        match self.files.iter_mut().find(|(p, _)| p == path) {
+            Some((_, s)) => *s = current,
+            None => self.files.push((path.to_path_buf(), current)),
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.retain(|(p, _)| p != path);
+    }
+
+    // Files changed since the previous poll. It returns nothing until the interval passes
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.files.is_empty() || self.polled_at.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.polled_at = Instant::now();
+        let mut changed = vec![];
+        for (path, prev) in self.files.iter_mut() {
+            let current = stamp(path);
+            if current != *prev {
+                *prev = current;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,169 @@
+// Frontend of Kiro for browsers. JavaScript sends bytes typed in xterm.js terminal to the editor and
+// writes escape sequences output by the editor to the terminal. Exported functions are:
+//
+//   kiro_start(cols, rows)    # Open an empty buffer and paint the first screen
+//   kiro_input_buffer(len)    # Returns a pointer where JavaScript writes `len` bytes of key input
+//   kiro_input(len)           # Handle the key input in the buffer. Returns 1 when the editor quits
+//   kiro_resize(cols, rows)   # Notify new size of the terminal
+//   kiro_output()             # Returns a pointer to bytes output since the previous call
+//   kiro_output_len()         # Returns the length of the bytes returned by `kiro_output`
+//
+// The editor cannot block to wait for next key input in browser. Prompts such as text search read
+// keys following in the same input and they are canceled when no more key is available.
+
+use kiro_editor::{Clipboard, Editor, InputSeq, InputSequences, KeySeq, Result};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Key inputs decoded from bytes sent by JavaScript. Editor reads them until it is empty
+#[derive(Clone, Default)]
+struct Queue(Rc<RefCell<VecDeque<InputSeq>>>);
+
+impl Iterator for Queue {
+    type Item = io::Result<InputSeq>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.borrow_mut().pop_front().map(Ok)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Frontend {
+    editor: Editor<Queue, Output>,
+    queue: Queue,
+    output: Output,
+    input: Vec<u8>,
+    flushed: Vec<u8>, // Output passed to JavaScript. It must live until next `kiro_output` call
+}
+
+impl Frontend {
+    fn new(cols: usize, rows: usize) -> Result<Frontend> {
+        let (queue, output) = (Queue::default(), Output::default());
+        let mut editor = Editor::new(
+            queue.clone(),
+            output.clone(),
+            Some((cols, rows)),
+            Clipboard::default(),
+        )?;
+        editor.start()?;
+        Ok(Frontend {
+            editor,
+            queue,
+            output,
+            input: vec![],
+            flushed: vec![],
+        })
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let mut seqs = InputSequences::new(bytes);
+        let mut queue = self.queue.0.borrow_mut();
+        while !seqs.reader().is_empty() {
+            match seqs.next() {
+                Some(Ok(seq)) => queue.push_back(seq),
+                _ => break,
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<bool> {
+        loop {
+            let seq = self.queue.0.borrow_mut().pop_front();
+            let seq = match seq {
+                Some(seq) => seq,
+                None => return Ok(false),
+            };
+            if self.editor.handle_input(seq)? {
+                self.editor.finish();
+                return Ok(true);
+            }
+        }
+    }
+
+    fn report(&mut self, result: Result<bool>) -> u32 {
+        match result {
+            Ok(quit) => quit as u32,
+            Err(err) => {
+                write!(self.output, "\r\nError: {}\r\n", err).ok();
+                1
+            }
+        }
+    }
+}
+
+thread_local! {
+    static FRONTEND: RefCell<Option<Frontend>> = const { RefCell::new(None) };
+}
+
+fn with_frontend<T: Default>(f: impl FnOnce(&mut Frontend) -> T) -> T {
+    FRONTEND.with(|fe| fe.borrow_mut().as_mut().map(f).unwrap_or_default())
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_start(cols: usize, rows: usize) -> u32 {
+    match Frontend::new(cols, rows) {
+        Ok(fe) => {
+            FRONTEND.with(|f| *f.borrow_mut() = Some(fe));
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_input_buffer(len: usize) -> *mut u8 {
+    with_frontend(|fe| {
+        fe.input.resize(len, 0);
+        fe.input.as_mut_ptr()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_input(len: usize) -> u32 {
+    with_frontend(|fe| {
+        let input = std::mem::take(&mut fe.input);
+        fe.push(&input[..len.min(input.len())]);
+        let result = fe.run();
+        fe.report(result)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_resize(cols: usize, rows: usize) -> u32 {
+    with_frontend(|fe| {
+        fe.queue
+            .0
+            .borrow_mut()
+            .push_back(InputSeq::new(KeySeq::Resize(cols, rows)));
+        let result = fe.run();
+        fe.report(result)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_output() -> *const u8 {
+    with_frontend(|fe| {
+        fe.flushed = std::mem::take(&mut *fe.output.0.borrow_mut());
+        fe.flushed.as_ptr()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn kiro_output_len() -> usize {
+    with_frontend(|fe| fe.flushed.len())
+}